@@ -0,0 +1,210 @@
+//! Bounded log of recently committed changes.
+//!
+//! With `QuickStepConfig::with_activity_log`, every commit logs the puts and deletes it made:
+//! the key and the kind of each write, the transaction and the commit time. The committing
+//! transaction writes them as one record in the reserved keyspace, see `reserved`, right
+//! before its commit marker, so the entries commit, roll back, recover and reach followers
+//! with the writes they describe, and scans never see them. `QuickStep::recent_changes(since)`
+//! reads back the entries committed at or after `since`, oldest first. Aborted transactions
+//! log nothing, and merges log as puts.
+//!
+//! Only the newest `capacity` entries are read back. A commit also deletes the oldest
+//! records once the entries after them reach the capacity without them. A record is keyed
+//! by `| committed_at: u64 BE | txn_id: u64 BE |` and holds `| op: u8 | key len: u32 | key |`
+//! entries.
+//!
+//! A record that cannot be written, because the leaf holding the log stays locked past the
+//! lock-wait policy or the write fails, does not hold back the commit: the transaction
+//! commits without its entries and `HealthEvent::ActivityLogSkipped` reports it.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::{
+    error::QSError,
+    reserved,
+    watch::{ChangeEvent, ChangeKind},
+    Store,
+};
+
+/// Name of the log in the reserved keyspace
+const SPACE: &[u8] = b"activity/";
+const OP_PUT: u8 = 0;
+const OP_DELETE: u8 = 1;
+
+/// One committed write in the activity log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityEntry {
+    pub key: Vec<u8>,
+    pub op: ActivityOp,
+    pub txn_id: u64,
+    /// Commit time, in milliseconds since the Unix epoch
+    pub committed_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityOp {
+    Put,
+    Delete,
+}
+
+pub struct ActivityLog {
+    capacity: usize,
+    state: Mutex<ActivityState>,
+}
+
+/// The committed records, to pick the ones a commit deletes
+#[derive(Default)]
+struct ActivityState {
+    /// Key and entry count of each record, oldest first
+    records: VecDeque<(Vec<u8>, usize)>,
+    /// Entries in `records`
+    entries: usize,
+}
+
+impl ActivityLog {
+    pub fn new(capacity: usize) -> ActivityLog {
+        ActivityLog {
+            capacity,
+            state: Mutex::new(ActivityState::default()),
+        }
+    }
+
+    /// Most entries the log keeps
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Entries in the committed records, including those past the capacity not deleted yet
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .expect("activity log mutex poisoned")
+            .entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The key and value of the record logging `changes` of transaction `txn_id`, committed
+    /// at `committed_at`, `None` if there are none
+    pub(crate) fn record(
+        txn_id: u64,
+        committed_at: u64,
+        changes: &[ChangeEvent],
+    ) -> Option<(Vec<u8>, Vec<u8>)> {
+        if changes.is_empty() {
+            return None;
+        }
+        let mut suffix = Vec::with_capacity(16);
+        suffix.extend_from_slice(&committed_at.to_be_bytes());
+        suffix.extend_from_slice(&txn_id.to_be_bytes());
+        let mut value = Vec::new();
+        for change in changes {
+            value.push(match change.kind {
+                ChangeKind::Put(_) => OP_PUT,
+                ChangeKind::Delete => OP_DELETE,
+            });
+            value.extend_from_slice(&(change.key.len() as u32).to_le_bytes());
+            value.extend_from_slice(&change.key);
+        }
+        Some((reserved::key(SPACE, &suffix), value))
+    }
+
+    /// Take the oldest records a commit adding `adding` entries deletes, leaving at least
+    /// `capacity` entries without them
+    pub(crate) fn take_expired(&self, adding: usize) -> Vec<Vec<u8>> {
+        let mut state = self.state.lock().expect("activity log mutex poisoned");
+        let mut expired = Vec::new();
+        while let Some((_, count)) = state.records.front() {
+            if state.entries - count + adding < self.capacity {
+                break;
+            }
+            let (key, count) = state.records.pop_front().expect("front checked");
+            state.entries -= count;
+            expired.push(key);
+        }
+        expired
+    }
+
+    /// Count a record once its transaction has committed
+    pub(crate) fn committed(&self, key: Vec<u8>, entries: usize) {
+        let mut state = self.state.lock().expect("activity log mutex poisoned");
+        state.entries += entries;
+        state.records.push_back((key, entries));
+    }
+
+    /// Forget every record, the tree they were in has been replaced
+    pub(crate) fn clear(&self) {
+        *self.state.lock().expect("activity log mutex poisoned") = ActivityState::default();
+    }
+}
+
+impl Store {
+    /// Count the records the log holds, once recovery has replayed the WAL
+    pub(crate) fn load_activity_log(&self) -> Result<(), QSError> {
+        let Some(log) = &self.activity_log else {
+            return Ok(());
+        };
+        log.clear();
+        for (suffix, value) in self.reserved_records(SPACE, &[])? {
+            let entries = decode_entries(&suffix, &value).len();
+            log.committed(reserved::key(SPACE, &suffix), entries);
+        }
+        Ok(())
+    }
+
+    /// The newest `capacity` entries committed at or after `since`, oldest first
+    pub(crate) fn activity_since(&self, since: u64) -> Result<Vec<ActivityEntry>, QSError> {
+        let Some(log) = &self.activity_log else {
+            return Ok(Vec::new());
+        };
+        let mut entries = Vec::new();
+        for (suffix, value) in self.reserved_records(SPACE, &since.to_be_bytes())? {
+            entries.extend(decode_entries(&suffix, &value));
+        }
+        let past_capacity = entries.len().saturating_sub(log.capacity);
+        entries.drain(..past_capacity);
+        Ok(entries)
+    }
+}
+
+/// The entries of the record with key suffix `suffix`, as many as are whole
+fn decode_entries(suffix: &[u8], value: &[u8]) -> Vec<ActivityEntry> {
+    let (Some(committed_at), Some(txn_id)) = (
+        suffix.get(..8).and_then(|bytes| bytes.try_into().ok()),
+        suffix.get(8..16).and_then(|bytes| bytes.try_into().ok()),
+    ) else {
+        return Vec::new();
+    };
+    let (committed_at, txn_id) = (u64::from_be_bytes(committed_at), u64::from_be_bytes(txn_id));
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while let Some((entry, next)) = decode_entry(value, pos, txn_id, committed_at) {
+        entries.push(entry);
+        pos = next;
+    }
+    entries
+}
+
+fn decode_entry(
+    bytes: &[u8],
+    pos: usize,
+    txn_id: u64,
+    committed_at: u64,
+) -> Option<(ActivityEntry, usize)> {
+    let op = match *bytes.get(pos)? {
+        OP_PUT => ActivityOp::Put,
+        OP_DELETE => ActivityOp::Delete,
+        _ => return None,
+    };
+    let len = u32::from_le_bytes(bytes.get(pos + 1..pos + 5)?.try_into().ok()?) as usize;
+    let key = bytes.get(pos + 5..pos + 5 + len)?;
+    let entry = ActivityEntry {
+        key: key.to_vec(),
+        op,
+        txn_id,
+        committed_at,
+    };
+    Some((entry, pos + 5 + len))
+}
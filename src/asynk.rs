@@ -0,0 +1,266 @@
+//! Futures facade for async services.
+//!
+//! `AsyncQuickStep` opens the store and shares it with a pool of worker threads, handing
+//! them jobs; each call returns a future a worker completes, so page reads and WAL syncs
+//! block the workers instead of the executor. The futures only rely on their waker and run
+//! on any executor. Jobs start in submission order but run concurrently, up to one per
+//! worker, and coordinate through the store's page locks as transactions on threads of
+//! their own would; the writes of one `AsyncTx` are applied in order in one transaction.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{error::QSError, QuickStep, QuickStepConfig};
+
+type Job = Box<dyn FnOnce(&QuickStep, &AtomicBool) + Send>;
+
+/// Worker threads `AsyncQuickStep::open` starts
+pub const DEFAULT_ASYNC_WORKERS: usize = 4;
+
+pub struct AsyncQuickStep {
+    jobs: Option<Sender<Job>>,
+    /// Share the store, which the last of them to stop closes
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl AsyncQuickStep {
+    /// Open the store, recovering it on the calling thread, with `DEFAULT_ASYNC_WORKERS`
+    /// workers. Fails as `QuickStep::open` does
+    pub fn open(config: QuickStepConfig) -> Result<AsyncQuickStep, QSError> {
+        AsyncQuickStep::open_with_workers(config, DEFAULT_ASYNC_WORKERS)
+    }
+
+    /// As `open`, with `workers` worker threads, at least 1
+    pub fn open_with_workers(
+        config: QuickStepConfig,
+        workers: usize,
+    ) -> Result<AsyncQuickStep, QSError> {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let db = Arc::new(QuickStep::open(config)?);
+        let receiver = Arc::new(Mutex::new(receiver));
+        // a job that panics stops the pool, as its store may be left part way through a write
+        let stopped = Arc::new(AtomicBool::new(false));
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let db = Arc::clone(&db);
+                let receiver = Arc::clone(&receiver);
+                let stopped = Arc::clone(&stopped);
+                thread::Builder::new()
+                    .name("quickstep-async".into())
+                    .spawn(move || work(&db, &receiver, &stopped))
+                    .expect("failed to spawn quickstep worker")
+            })
+            .collect();
+        Ok(AsyncQuickStep {
+            jobs: Some(jobs),
+            workers,
+        })
+    }
+
+    /// Run `f` against the store on a worker thread
+    pub fn run<T, F>(&self, f: F) -> QuickStepFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&QuickStep) -> Result<T, QSError> + Send + 'static,
+    {
+        let slot = Arc::new(Mutex::new(Slot {
+            result: None,
+            waker: None,
+        }));
+        let completer = Completer {
+            slot: Arc::clone(&slot),
+            result: None,
+        };
+        let job: Job = Box::new(move |db, stopped| {
+            let mut completer = completer;
+            // dropped first, so the pool has stopped by the time the future fails
+            let _stop = StopOnPanic(stopped);
+            completer.result = Some(f(db));
+        });
+        // if the workers are gone the job is dropped here, failing the future
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(job);
+        }
+        QuickStepFuture { slot }
+    }
+
+    pub fn get(&self, key: &[u8]) -> QuickStepFuture<Option<Vec<u8>>> {
+        let key = key.to_vec();
+        self.run(move |db| db.get(&key))
+    }
+
+    /// Put `key` in a transaction of its own
+    pub fn put(&self, key: &[u8], val: &[u8]) -> QuickStepFuture<()> {
+        let mut tx = self.tx();
+        tx.put(key, val);
+        tx.commit()
+    }
+
+    /// Delete `key` in a transaction of its own, resolving to whether it was present
+    pub fn delete(&self, key: &[u8]) -> QuickStepFuture<bool> {
+        let key = key.to_vec();
+        self.run(move |db| db.delete(&key))
+    }
+
+    /// Buffer writes to apply in one transaction on commit
+    pub fn tx(&self) -> AsyncTx<'_> {
+        AsyncTx {
+            db: self,
+            ops: Vec::new(),
+        }
+    }
+}
+
+/// Run jobs until the channel closes or a job panics; once stopped, the jobs left are
+/// dropped, failing their futures
+fn work(db: &QuickStep, receiver: &Mutex<Receiver<Job>>, stopped: &AtomicBool) {
+    loop {
+        // the lock is only held while waiting, the job runs without it
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = job else {
+            return;
+        };
+        if !stopped.load(Ordering::Acquire) {
+            job(db, stopped);
+        }
+    }
+}
+
+/// Stops the pool if the job it was made for panics
+struct StopOnPanic<'a>(&'a AtomicBool);
+
+impl Drop for StopOnPanic<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for AsyncQuickStep {
+    fn drop(&mut self) {
+        // closing the channel lets the workers finish queued jobs, then the last one to stop
+        // closes the store
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+enum TxOp {
+    Put(Vec<u8>, Vec<u8>),
+    PutWithTtl(Vec<u8>, Vec<u8>, Duration),
+    Merge(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Writes buffered for one transaction, nothing reaches the store before `commit`
+pub struct AsyncTx<'a> {
+    db: &'a AsyncQuickStep,
+    ops: Vec<TxOp>,
+}
+
+impl AsyncTx<'_> {
+    pub fn put(&mut self, key: &[u8], val: &[u8]) {
+        self.ops.push(TxOp::Put(key.to_vec(), val.to_vec()));
+    }
+
+    pub fn put_with_ttl(&mut self, key: &[u8], val: &[u8], ttl: Duration) {
+        self.ops
+            .push(TxOp::PutWithTtl(key.to_vec(), val.to_vec(), ttl));
+    }
+
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) {
+        self.ops.push(TxOp::Merge(key.to_vec(), operand.to_vec()));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.ops.push(TxOp::Delete(key.to_vec()));
+    }
+
+    /// Apply the writes in order and commit; the first failing write aborts the transaction
+    /// and fails the future with its error
+    pub fn commit(self) -> QuickStepFuture<()> {
+        let ops = self.ops;
+        self.db.run(move |db| {
+            let mut tx = db.tx();
+            for op in ops {
+                match op {
+                    TxOp::Put(key, val) => tx.put(&key, &val)?,
+                    TxOp::PutWithTtl(key, val, ttl) => tx.put_with_ttl(&key, &val, ttl)?,
+                    TxOp::Merge(key, operand) => tx.merge(&key, &operand)?,
+                    TxOp::Delete(key) => {
+                        tx.delete(&key)?;
+                    }
+                }
+            }
+            tx.commit();
+            Ok(())
+        })
+    }
+}
+
+struct Slot<T> {
+    result: Option<Result<T, QSError>>,
+    waker: Option<Waker>,
+}
+
+/// Fills the slot when dropped, with `AsyncWorkerStopped` if the job never ran to the end
+struct Completer<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+    result: Option<Result<T, QSError>>,
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let result = self
+            .result
+            .take()
+            .unwrap_or(Err(QSError::AsyncWorkerStopped));
+        let waker = {
+            let mut slot = match self.slot.lock() {
+                Ok(slot) => slot,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            slot.result = Some(result);
+            slot.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Resolves once the worker has run the job
+pub struct QuickStepFuture<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Future for QuickStepFuture<T> {
+    type Output = Result<T, QSError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().expect("async slot poisoned");
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
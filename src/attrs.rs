@@ -0,0 +1,94 @@
+//! Key expiries and soft-delete flags, carried in the records they belong to.
+//!
+//! Leaf records have no spare bits for either, so a value with an expiry or a soft-delete
+//! flag is stored behind a header: a marker, the expiry in milliseconds since the unix
+//! epoch (0 for none) and a flags byte, followed by the value as it would be stored
+//! otherwise, an overflow or blob pointer included. The header is part of the stored value,
+//! so the WAL logs it with the write, recovery replays it, followers and two-phase commit
+//! apply it and a rollback restores the one before, like any value bytes. A value that
+//! happens to start with the marker is stored behind an empty header, so every stored value
+//! reads back one way.
+//!
+//! Reads hide the keys whose header says they have expired or are soft-deleted. Changing
+//! the flag of a key rewrites its record, value included, as a put would. A third flag says
+//! the value behind the header holds merge operands not folded yet, see `merge`.
+
+use std::borrow::Cow;
+
+/// Opens a header, unlikely at the start of a value
+const MARKER: [u8; 8] = *b"\xF5QSATTR\x01";
+/// Length of the header in front of a value that carries one
+pub const HEADER_LEN: usize = MARKER.len() + 8 + 1;
+const FLAG_SOFT_DELETED: u8 = 1;
+const FLAG_PENDING_MERGE: u8 = 2;
+
+/// What a record's header says about its key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordAttrs {
+    /// When the key expires, in milliseconds since the unix epoch
+    pub expires_at: Option<u64>,
+    /// The value is kept but hidden by `QuickStepTx::soft_delete`
+    pub soft_deleted: bool,
+    /// The value is a key's value with merge operands still to fold into it, see `merge`
+    pub pending_merge: bool,
+}
+
+impl RecordAttrs {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Whether reads leave the key out
+    pub fn hides(&self, now: u64) -> bool {
+        self.soft_deleted || self.is_expired(now)
+    }
+}
+
+/// The header of a stored value and the value behind it
+pub fn split(stored: &[u8]) -> (RecordAttrs, &[u8]) {
+    if stored.len() < HEADER_LEN || stored[..MARKER.len()] != MARKER {
+        return (RecordAttrs::default(), stored);
+    }
+    let expires_at = u64::from_le_bytes(stored[MARKER.len()..HEADER_LEN - 1].try_into().unwrap());
+    let flags = stored[HEADER_LEN - 1];
+    let attrs = RecordAttrs {
+        expires_at: (expires_at != 0).then_some(expires_at),
+        soft_deleted: flags & FLAG_SOFT_DELETED != 0,
+        pending_merge: flags & FLAG_PENDING_MERGE != 0,
+    };
+    (attrs, &stored[HEADER_LEN..])
+}
+
+/// The value to store for `value` with `attrs`, behind a header only if it needs one
+pub fn wrap(attrs: RecordAttrs, value: &[u8]) -> Cow<'_, [u8]> {
+    if !needs_header(attrs, value) {
+        return Cow::Borrowed(value);
+    }
+    let mut stored = Vec::with_capacity(HEADER_LEN + value.len());
+    stored.extend_from_slice(&MARKER);
+    // an expiry of 0 would read back as none
+    let expires_at = attrs.expires_at.map_or(0, |at| at.max(1));
+    stored.extend_from_slice(&expires_at.to_le_bytes());
+    let mut flags = 0;
+    if attrs.soft_deleted {
+        flags |= FLAG_SOFT_DELETED;
+    }
+    if attrs.pending_merge {
+        flags |= FLAG_PENDING_MERGE;
+    }
+    stored.push(flags);
+    stored.extend_from_slice(value);
+    Cow::Owned(stored)
+}
+
+/// Bytes the header adds when `value` is stored with `attrs`
+pub fn header_len(attrs: RecordAttrs, value: &[u8]) -> usize {
+    match needs_header(attrs, value) {
+        true => HEADER_LEN,
+        false => 0,
+    }
+}
+
+fn needs_header(attrs: RecordAttrs, value: &[u8]) -> bool {
+    attrs != RecordAttrs::default() || value.starts_with(&MARKER)
+}
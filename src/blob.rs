@@ -0,0 +1,431 @@
+//! Very long values kept in blob files beside the data file, WiscKey style.
+//!
+//! With `QuickStepConfig::with_blob_store`, a value of at least the configured threshold is
+//! appended to the active blob file instead of a chain of overflow pages, and its leaf
+//! record holds a `BlobPointer`: a marker, the file's number, the value's offset and length
+//! and its CRC-32, as long as an `OverflowPointer`. Leaves stay as dense as with overflow
+//! chains, while the values leave the data file altogether and read back in one read.
+//! Files are named after the data file, `quickstep.blob-000001` and on; the active file is
+//! sealed once it passes its configured size, and each open starts a new one. An append is
+//! synced before the put pointing at it is logged.
+//!
+//! Each file is a run of `| crc: u32 | key len: u32 | value len: u32 | key | value |`
+//! entries, the CRC covering the value, so a file can be read back without the leaves. A
+//! value overwritten, deleted or written by a transaction that aborted stays in its file
+//! until `QuickStep::gc_blobs` collects it: for every sealed file, the entries the leaves
+//! still point at are counted and, if enough of the file is garbage, appended again to the
+//! active file, the records repointed in a transaction of their own, and the file removed
+//! once that commit is synced. Repointing keeps a key's expiry and soft-delete flag and is
+//! not told to watchers. A file one of whose keys' leaves a transaction holds is left for a
+//! later pass, and nothing is collected while transactions are in doubt, as their logged
+//! writes may point into any file.
+//!
+//! `replace_all` writes long values to overflow chains, its new leaves are not in the tree
+//! a collection looks through. Followers write blob files of their own from the values
+//! shipped to them, and do not collect them.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::{attrs, error::QSError, overflow::POINTER_LEN, storage::Storage, utils::crc32, Store};
+
+/// Opens a stored blob pointer, unlikely at the start of a short value
+const POINTER_MARKER: [u8; 8] = *b"\xF5QSBLOB\x01";
+/// Bytes of an entry before its key
+const ENTRY_HEADER_LEN: u64 = 12;
+/// Size of the files values are moved to by a collection when no blob store is configured
+const DEFAULT_FILE_BYTES: u64 = 64 << 20;
+
+/// Which values go to blob files and how large a file grows before it is sealed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobStore {
+    /// Values at least this long are appended to a blob file, at least 1
+    pub threshold: usize,
+    /// Bytes an active file holds before the next append starts a new one
+    pub file_bytes: u64,
+}
+
+impl BlobStore {
+    pub fn new(threshold: usize, file_bytes: u64) -> BlobStore {
+        BlobStore {
+            threshold: threshold.max(1),
+            file_bytes,
+        }
+    }
+}
+
+/// Where a value stored in a blob file is, and how to tell it read back whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobPointer {
+    pub file: u32,
+    /// Offset of the value in its file
+    pub offset: u64,
+    pub len: u32,
+    /// CRC-32 of the value
+    pub crc: u32,
+}
+
+impl BlobPointer {
+    /// The pointer a stored value holds, behind its header if it has one, `None` for any
+    /// other value
+    pub fn decode(stored: &[u8]) -> Option<BlobPointer> {
+        let stored = attrs::split(stored).1;
+        if stored.len() != POINTER_LEN || stored[..8] != POINTER_MARKER {
+            return None;
+        }
+        Some(BlobPointer {
+            file: u32::from_le_bytes(stored[8..12].try_into().unwrap()),
+            offset: u64::from_le_bytes(stored[12..20].try_into().unwrap()),
+            len: u32::from_le_bytes(stored[20..24].try_into().unwrap()),
+            crc: u32::from_le_bytes(stored[24..28].try_into().unwrap()),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; POINTER_LEN] {
+        let mut stored = [0u8; POINTER_LEN];
+        stored[..8].copy_from_slice(&POINTER_MARKER);
+        stored[8..12].copy_from_slice(&self.file.to_le_bytes());
+        stored[12..20].copy_from_slice(&self.offset.to_le_bytes());
+        stored[20..24].copy_from_slice(&self.len.to_le_bytes());
+        stored[24..28].copy_from_slice(&self.crc.to_le_bytes());
+        stored
+    }
+
+    fn corrupt(&self) -> QSError {
+        QSError::BlobCorrupt {
+            file: self.file,
+            offset: self.offset,
+        }
+    }
+}
+
+/// What a blob collection did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlobGcStats {
+    /// Sealed files looked through
+    pub files_scanned: u64,
+    pub files_removed: u64,
+    /// Files past the garbage ratio left for a later pass, a transaction held one of their
+    /// keys' leaves
+    pub files_skipped: u64,
+    /// Live values appended again to the active file
+    pub values_moved: u64,
+    /// Bytes of the removed files, less those moved
+    pub bytes_reclaimed: u64,
+}
+
+/// The blob files of a store
+pub struct BlobLog {
+    /// Data file the blob files are named after, `None` in memory
+    data_path: Option<PathBuf>,
+    /// Which values are appended, `None` to only read the files already there
+    config: Option<BlobStore>,
+    files: RwLock<BTreeMap<u32, Arc<Storage>>>,
+    /// The file appends go to and its length, `None` until the first append after opening
+    /// or sealing
+    active: Mutex<Option<(u32, u64)>>,
+}
+
+impl BlobLog {
+    /// Open the blob files next to `data_path`, appending to new ones if `config` is given
+    pub fn open(data_path: &Path, config: Option<BlobStore>) -> io::Result<BlobLog> {
+        Self::open_files(data_path, config, Storage::open)
+    }
+
+    /// Open the blob files next to `data_path` for reading only
+    pub fn open_read_only(data_path: &Path) -> io::Result<BlobLog> {
+        Self::open_files(data_path, None, Storage::open_read_only)
+    }
+
+    /// Keep the blob files in memory, they are lost once the log is dropped
+    pub fn in_memory(config: Option<BlobStore>) -> BlobLog {
+        BlobLog {
+            data_path: None,
+            config,
+            files: RwLock::new(BTreeMap::new()),
+            active: Mutex::new(None),
+        }
+    }
+
+    fn open_files(
+        data_path: &Path,
+        config: Option<BlobStore>,
+        open: fn(&Path) -> io::Result<Storage>,
+    ) -> io::Result<BlobLog> {
+        let mut files = BTreeMap::new();
+        for file in list_files(data_path)? {
+            files.insert(file, Arc::new(open(&file_path(data_path, file))?));
+        }
+        Ok(BlobLog {
+            data_path: Some(data_path.to_path_buf()),
+            config,
+            files: RwLock::new(files),
+            active: Mutex::new(None),
+        })
+    }
+
+    /// Whether `value` is long enough to be appended to a blob file
+    pub fn takes(&self, value: &[u8]) -> bool {
+        self.config
+            .is_some_and(|config| value.len() >= config.threshold)
+    }
+
+    /// Append `value`, stored under `key`, to the active file and sync it
+    pub fn append(&self, key: &[u8], value: &[u8]) -> Result<BlobPointer, QSError> {
+        let config = self.config.unwrap_or(BlobStore::new(1, DEFAULT_FILE_BYTES));
+        let mut active = self.active.lock().expect("blob log mutex poisoned");
+        let (file, offset) = match *active {
+            Some((file, len)) if len < config.file_bytes => (file, len),
+            _ => (self.create_file()?, 0),
+        };
+        let crc = crc32(value);
+        let mut entry = Vec::with_capacity(ENTRY_HEADER_LEN as usize + key.len() + value.len());
+        entry.extend_from_slice(&crc.to_le_bytes());
+        entry.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        entry.extend_from_slice(key);
+        entry.extend_from_slice(value);
+        let storage = self
+            .storage(file)
+            .ok_or(QSError::BlobCorrupt { file, offset })?;
+        storage.write_all_at(&entry, offset)?;
+        storage.sync_data()?;
+        *active = Some((file, offset + entry.len() as u64));
+        Ok(BlobPointer {
+            file,
+            offset: offset + ENTRY_HEADER_LEN + key.len() as u64,
+            len: value.len() as u32,
+            crc,
+        })
+    }
+
+    /// Read a value back, failing with `BlobCorrupt` if its file is gone or it does not
+    /// match its pointer
+    pub fn read(&self, pointer: BlobPointer) -> Result<Vec<u8>, QSError> {
+        let storage = self.storage(pointer.file).ok_or(pointer.corrupt())?;
+        let mut value = vec![0u8; pointer.len as usize];
+        storage
+            .read_exact_at(&mut value, pointer.offset)
+            .map_err(|_| pointer.corrupt())?;
+        match crc32(&value) == pointer.crc {
+            true => Ok(value),
+            false => Err(pointer.corrupt()),
+        }
+    }
+
+    /// End the active file, the next append starts a new one
+    pub fn seal(&self) {
+        *self.active.lock().expect("blob log mutex poisoned") = None;
+    }
+
+    /// Every file but the active one, oldest first
+    pub fn sealed_files(&self) -> Vec<u32> {
+        let active = self.active.lock().expect("blob log mutex poisoned");
+        let files = self.files.read().expect("blob log lock poisoned");
+        files
+            .keys()
+            .copied()
+            .filter(|file| active.is_none_or(|(active, _)| *file != active))
+            .collect()
+    }
+
+    /// Bytes held by blob files, including entries nothing points at
+    pub fn bytes(&self) -> u64 {
+        let files = self.files.read().expect("blob log lock poisoned");
+        files.values().filter_map(|file| file.len().ok()).sum()
+    }
+
+    /// The key and pointer of every whole entry of `file`, in the order they were appended
+    pub fn entries(&self, file: u32) -> Result<Vec<(Vec<u8>, BlobPointer)>, QSError> {
+        let storage = self
+            .storage(file)
+            .ok_or(QSError::BlobCorrupt { file, offset: 0 })?;
+        let len = storage.len()?;
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        let mut header = [0u8; ENTRY_HEADER_LEN as usize];
+        // a crash mid-append leaves a torn entry, nothing points at it or past it
+        while offset + ENTRY_HEADER_LEN <= len {
+            storage.read_exact_at(&mut header, offset)?;
+            let crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            let key_len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+            let value_len = u32::from_le_bytes(header[8..12].try_into().unwrap());
+            let value_offset = offset + ENTRY_HEADER_LEN + key_len;
+            if value_offset + value_len as u64 > len {
+                break;
+            }
+            let mut key = vec![0u8; key_len as usize];
+            storage.read_exact_at(&mut key, offset + ENTRY_HEADER_LEN)?;
+            let pointer = BlobPointer {
+                file,
+                offset: value_offset,
+                len: value_len,
+                crc,
+            };
+            entries.push((key, pointer));
+            offset = value_offset + value_len as u64;
+        }
+        Ok(entries)
+    }
+
+    /// Bytes `file` holds, 0 if it is gone
+    pub fn file_len(&self, file: u32) -> u64 {
+        self.storage(file)
+            .and_then(|storage| storage.len().ok())
+            .unwrap_or(0)
+    }
+
+    /// Drop `file` and delete it from disk
+    pub fn remove(&self, file: u32) -> io::Result<()> {
+        let removed = self
+            .files
+            .write()
+            .expect("blob log lock poisoned")
+            .remove(&file);
+        match (&self.data_path, removed) {
+            (Some(data_path), Some(_)) => fs::remove_file(file_path(data_path, file)),
+            _ => Ok(()),
+        }
+    }
+
+    fn storage(&self, file: u32) -> Option<Arc<Storage>> {
+        let files = self.files.read().expect("blob log lock poisoned");
+        files.get(&file).cloned()
+    }
+
+    /// Start the file after the newest one
+    fn create_file(&self) -> io::Result<u32> {
+        let mut files = self.files.write().expect("blob log lock poisoned");
+        let file = files.keys().next_back().map_or(1, |newest| newest + 1);
+        let storage = match &self.data_path {
+            Some(data_path) => Storage::open(&file_path(data_path, file))?,
+            None => Storage::memory(),
+        };
+        files.insert(file, Arc::new(storage));
+        Ok(file)
+    }
+}
+
+/// Bytes the entry behind `pointer` takes in its file
+pub(crate) fn entry_len(key: &[u8], pointer: &BlobPointer) -> u64 {
+    ENTRY_HEADER_LEN + key.len() as u64 + pointer.len as u64
+}
+
+fn file_path(data_path: &Path, file: u32) -> PathBuf {
+    data_path.with_extension(format!("blob-{file:06}"))
+}
+
+/// Numbers of the blob files next to `data_path`
+fn list_files(data_path: &Path) -> io::Result<Vec<u32>> {
+    let dir = match data_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(stem) = data_path.file_stem().and_then(|stem| stem.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{stem}.blob-");
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        let file = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|number| number.parse::<u32>().ok());
+        files.extend(file);
+    }
+    Ok(files)
+}
+
+/// Move the live values out of every sealed file whose garbage makes up at least
+/// `min_garbage_ratio` of it, then remove the file, see the module docs
+pub(crate) fn collect(db: &Store, min_garbage_ratio: f64) -> Result<BlobGcStats, QSError> {
+    if db.follower.is_some() {
+        return Err(QSError::FollowerReadOnly);
+    }
+    db.check_writable()?;
+    let mut stats = BlobGcStats::default();
+    let Some(log) = db.io_engine.blob_log() else {
+        return Ok(stats);
+    };
+    if !db.in_doubt_txns().is_empty() {
+        return Ok(stats);
+    }
+    log.seal();
+    for file in log.sealed_files() {
+        stats.files_scanned += 1;
+        let entries = log.entries(file)?;
+        let file_bytes = log.file_len(file);
+        let mut live_bytes = 0;
+        for (key, pointer) in &entries {
+            let live = match db.stored_value(key) {
+                Ok(stored) => stored.as_deref().and_then(BlobPointer::decode) == Some(*pointer),
+                // held by a writer, count it live until the move looks under its lock
+                Err(QSError::PageLockFail) => true,
+                Err(err) => return Err(err),
+            };
+            if live {
+                live_bytes += entry_len(key, pointer);
+            }
+        }
+        let garbage = 1.0 - live_bytes as f64 / file_bytes.max(1) as f64;
+        if garbage < min_garbage_ratio {
+            continue;
+        }
+        let (moved, moved_bytes) = match move_live(db, log, &entries) {
+            Ok(moved) => moved,
+            Err(QSError::WriteConflict { .. } | QSError::PageLockFail) => {
+                stats.files_skipped += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        // no recovery may bring back a record pointing into the file once it is gone
+        db.wal.sync()?;
+        log.remove(file)?;
+        stats.files_removed += 1;
+        stats.values_moved += moved;
+        stats.bytes_reclaimed += file_bytes.saturating_sub(moved_bytes);
+    }
+    Ok(stats)
+}
+
+/// Append again each of `entries` a record still points at and repoint the record, all in
+/// one transaction. Each key's leaf is write locked even when nothing points at its entry,
+/// so no transaction still running can roll a record back to one
+fn move_live(
+    db: &Store,
+    log: &BlobLog,
+    entries: &[(Vec<u8>, BlobPointer)],
+) -> Result<(u64, u64), QSError> {
+    let mut tx = db.tx();
+    let (mut moved, mut moved_bytes) = (0, 0);
+    for (key, pointer) in entries {
+        let relocated = tx.relocate_value(key, &pointer.encode(), || {
+            let value = log.read(*pointer)?;
+            Ok(log.append(key, &value)?.encode().to_vec())
+        });
+        match relocated {
+            Ok(true) => {
+                moved += 1;
+                moved_bytes += entry_len(key, pointer);
+            }
+            Ok(false) => {}
+            Err(err) => {
+                tx.abort();
+                return Err(err);
+            }
+        }
+    }
+    tx.commit();
+    Ok((moved, moved_bytes))
+}
@@ -1,29 +1,60 @@
 use std::{
     alloc::{alloc, Layout},
+    collections::HashSet,
     marker::PhantomData,
     mem::size_of,
     num::NonZeroU16,
     ptr::NonNull,
-    sync::atomic::{AtomicU32, AtomicU64, Ordering},
-    u32, u64,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
 };
 
 use crate::{
+    epoch::Epochs,
     error::QSError,
     map_table::PageId,
     utils::{extract_u32, extract_u48, store_u32, store_u48},
     SPIN_RETRIES,
 };
 
+/// Restarts an OLC retry loop spins through before it starts yielding the thread
+const RESTART_SPINS: usize = 64;
+/// Restarts after which a retry loop sleeps between attempts rather than yielding
+const RESTART_YIELDS: usize = 1024;
+
+/// Pause before the `attempt`th try of an OLC retry loop. A writer can hold an inner node
+/// while it writes a leaf out, so after a few spins the loop yields and then sleeps rather
+/// than burning its retries before the writer is done
+fn restart_pause(attempt: usize) {
+    match attempt {
+        0 => {}
+        attempt if attempt < RESTART_SPINS => std::hint::spin_loop(),
+        attempt if attempt < RESTART_YIELDS => thread::yield_now(),
+        _ => thread::sleep(Duration::from_micros(50)),
+    }
+}
+
 /// Max length of key in bytes
-const MAX_KEY_LENGTH: usize = 64;
+pub(crate) const MAX_KEY_LENGTH: usize = 64;
+
+/// Node index of an empty free list, and the mask of the index in its head
+const FREE_LIST_EMPTY: u64 = u32::MAX as u64;
 
-// TODO: prevent race condition when freeing nodes
+/// Inner nodes taken out of the tree are marked obsolete, so optimistic readers holding them
+/// restart, and retired: they join the free list once no traversal that could have reached
+/// them is still running, see `epoch`. A reused node keeps counting up its version, so a
+/// guard taken on its previous life can never be upgraded
 pub struct BPTree {
     /// The buffer containing all nodes, allocated at initialisation
     slab: NonNull<BPNode>,
-    /// The number of nodes we have capacity for in the above buffer
-    cap: u32,
+    /// The number of nodes handed out from the above buffer before it counts as full
+    cap: AtomicU32,
+    /// The number of nodes the buffer is allocated for, `cap` grows up to it, see `grow`
+    max_cap: u32,
     /// The root node and level of the root
     /// If the level is 0 then its a 48bit pageid
     /// otherwise its a 32bit BP Tree index
@@ -33,15 +64,47 @@ pub struct BPTree {
     root_vlock: AtomicU64,
     /// index of next free node in the buffer
     next_free: AtomicU32,
-    /// start of node free list, u32::MAX if empty
-    free_list: AtomicU32,
+    /// start of node free list, u32::MAX if empty, in the low half; the high half counts
+    /// pops so a pop racing another cannot succeed on a stale head.
+    /// Each free node holds the next one in `lowest`
+    free_list: AtomicU64,
+    /// Epochs pinned by traversals
+    epochs: Epochs,
+    /// Nodes taken out of the tree with the epoch they were retired in, oldest first
+    retired: Mutex<Vec<(u64, BPNodeId)>>,
+}
+
+// SAFETY: nodes in the slab are read optimistically and validated against their version
+// lock, and only written under it, see `InnerReadGuard` and `InnerWriteGuard`
+unsafe impl Send for BPTree {}
+unsafe impl Sync for BPTree {}
+
+/// Inner node usage of a tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InnerNodeStats {
+    /// Nodes the tree has room for
+    pub capacity: u32,
+    /// Nodes ever handed out, the high-water mark of the buffer
+    pub allocated: u32,
+    /// Nodes on the free list, ready to reuse
+    pub free: u32,
+    /// Nodes out of the tree waiting for readers that may still see them
+    pub retired: u32,
 }
 
 impl BPTree {
     pub fn new(inner_node_upper_bound: u32) -> BPTree {
-        let memory_req = inner_node_upper_bound * 4096;
+        BPTree::with_max_capacity(inner_node_upper_bound, inner_node_upper_bound)
+    }
+
+    /// A tree with room for `inner_node_upper_bound` nodes that `grow` can take up to
+    /// `max_inner_nodes`. The buffer is allocated at the maximum up front, the memory of
+    /// nodes never handed out is not touched
+    pub fn with_max_capacity(inner_node_upper_bound: u32, max_inner_nodes: u32) -> BPTree {
+        let max_cap = max_inner_nodes.max(inner_node_upper_bound);
+        let memory_req = max_cap as usize * 4096;
 
-        let layout = Layout::from_size_align(memory_req as usize, 4096).expect("todo");
+        let layout = Layout::from_size_align(memory_req, 4096).expect("todo");
 
         let slab_ptr = unsafe { alloc(layout) as *mut BPNode };
 
@@ -54,11 +117,14 @@ impl BPTree {
 
         BPTree {
             slab,
-            cap: inner_node_upper_bound,
+            cap: AtomicU32::new(inner_node_upper_bound),
+            max_cap,
             root: AtomicU64::new(0),
             root_vlock: AtomicU64::new(0),
             next_free: AtomicU32::new(1),
-            free_list: AtomicU32::new(u32::MAX),
+            free_list: AtomicU64::new(FREE_LIST_EMPTY),
+            epochs: Epochs::new(),
+            retired: Mutex::new(Vec::new()),
         }
     }
 
@@ -67,30 +133,170 @@ impl BPTree {
     }
 
     fn alloc_inner_node(&self) -> Result<BPNodeId, QSError> {
+        self.reclaim_retired();
+        if let Some(node_id) = self.pop_free_node() {
+            let node_ptr = unsafe { self.slab.as_ptr().add(node_id.0 as usize) };
+            // SAFETY: the node left the tree two epochs ago, nothing reads it any more
+            unsafe { (*node_ptr).recycle() };
+            return Ok(node_id);
+        }
+
+        // a failed allocation does not take a slot
+        let idx = self
+            .next_free
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |idx| {
+                (idx < self.cap.load(Ordering::Acquire)).then_some(idx + 1)
+            })
+            .map_err(|_| QSError::TreeFull)?;
+
+        // SAFETY: idx < cap, slab points to a buffer of cap nodes
+        let node_ptr = unsafe { self.slab.as_ptr().add(idx as usize) };
+        // SAFETY: we have exclusive ownership of this slot because idx is unique
+        unsafe {
+            node_ptr.write(BPNode::blank());
+        }
+        Ok(BPNodeId(idx))
+    }
+
+    /// Take a node that was unlinked from the tree under `guard`: readers holding it restart,
+    /// and it is reused once they are done
+    pub fn retire_inner(&self, mut guard: InnerWriteGuard<'_>) {
+        let node_id = guard.node_id();
+        guard.obsolete = true;
+        drop(guard);
+        let epoch = self.epochs.current();
+        self.retired
+            .lock()
+            .expect("retired node list poisoned")
+            .push((epoch, node_id));
+    }
+
+    /// Move retired nodes no reader can see any more to the free list
+    fn reclaim_retired(&self) {
+        let mut retired = self.retired.lock().expect("retired node list poisoned");
+        if retired.is_empty() {
+            return;
+        }
+        // two epochs must pass, try both steps now rather than waiting for later calls
+        self.epochs.try_advance();
+        let epoch = self.epochs.try_advance();
+        let ready = retired
+            .iter()
+            .take_while(|(retired_in, _)| retired_in + 2 <= epoch)
+            .count();
+        for (_, node_id) in retired.drain(..ready) {
+            self.push_free_node(node_id);
+        }
+    }
+
+    /// Give back a node that was allocated but never linked into the tree, so no reader can
+    /// hold it and it skips retirement
+    fn free_unused_node(&self, node_id: BPNodeId) {
+        // SAFETY: the node was handed out by `alloc_inner_node` and nothing else refers to it
+        let node = unsafe { &*self.slab.as_ptr().add(node_id.0 as usize) };
+        // obsolete, as a retired node would be, so reusing it moves its version on
+        node.vlock.fetch_or(1, Ordering::Release);
+        self.push_free_node(node_id);
+    }
+
+    fn push_free_node(&self, node_id: BPNodeId) {
+        let node_ptr = unsafe { self.slab.as_ptr().add(node_id.0 as usize) };
+        let mut head = self.free_list.load(Ordering::Acquire);
         loop {
-            let idx = self.next_free.fetch_add(1, Ordering::AcqRel);
-            if idx >= self.cap {
-                return Err(QSError::TreeFull);
+            // SAFETY: the node is out of the tree and no reader can see it
+            unsafe { (*node_ptr).lowest = head & FREE_LIST_EMPTY };
+            let next = (head & !FREE_LIST_EMPTY) | node_id.0 as u64;
+            match self.free_list.compare_exchange_weak(
+                head,
+                next,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
             }
+        }
+    }
 
-            // SAFETY: idx < cap, slab points to a buffer of cap nodes
+    fn pop_free_node(&self) -> Option<BPNodeId> {
+        let mut head = self.free_list.load(Ordering::Acquire);
+        loop {
+            let idx = head & FREE_LIST_EMPTY;
+            if idx == FREE_LIST_EMPTY {
+                return None;
+            }
+            // another pop may hand this node out before we swap it off the head; the read
+            // is then stale, and the count in the head fails the swap
             let node_ptr = unsafe { self.slab.as_ptr().add(idx as usize) };
-            // SAFETY: we have exclusive ownership of this slot because idx is unique
-            unsafe {
-                node_ptr.write(BPNode::blank());
+            let next = unsafe { (*node_ptr).lowest } & FREE_LIST_EMPTY;
+            let pops = (head >> 32).wrapping_add(1);
+            match self.free_list.compare_exchange_weak(
+                head,
+                (pops << 32) | next,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(BPNodeId(idx as u32)),
+                Err(current) => head = current,
             }
-            return Ok(BPNodeId(idx));
         }
     }
 
+    pub fn inner_node_stats(&self) -> InnerNodeStats {
+        let retired = self
+            .retired
+            .lock()
+            .expect("retired node list poisoned")
+            .len() as u32;
+        let mut free = 0;
+        let mut idx = self.free_list.load(Ordering::Acquire) & FREE_LIST_EMPTY;
+        while idx != FREE_LIST_EMPTY {
+            free += 1;
+            idx = unsafe { (*self.slab.as_ptr().add(idx as usize)).lowest } & FREE_LIST_EMPTY;
+        }
+        InnerNodeStats {
+            capacity: self.cap.load(Ordering::Acquire).saturating_sub(1),
+            allocated: self.next_free.load(Ordering::Acquire) - 1,
+            free,
+            retired,
+        }
+    }
+
+    /// Take `count` nodes for a split up front, so running out of room fails it before
+    /// anything was changed. `TreeFull` if there are not that many left
+    pub fn reserve(&self, count: usize) -> Result<InnerReservation<'_>, QSError> {
+        let mut reservation = InnerReservation {
+            tree: self,
+            nodes: Vec::with_capacity(count),
+        };
+        for _ in 0..count {
+            // on failure, dropping the reservation gives back the nodes taken so far
+            reservation.nodes.push(self.alloc_inner_node()?);
+        }
+        Ok(reservation)
+    }
+
+    /// Double the nodes the tree has room for, up to the maximum it was created with.
+    /// Returns the new capacity, `None` if it is already at the maximum
+    pub fn grow(&self) -> Option<u32> {
+        let grown = self
+            .cap
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |cap| {
+                (cap < self.max_cap).then(|| cap.saturating_mul(2).min(self.max_cap))
+            })
+            .ok()?;
+        Some(grown.saturating_mul(2).min(self.max_cap) - 1)
+    }
+
     pub fn promote_leaf_root(
         &self,
+        reservation: &mut InnerReservation<'_>,
         root_lock: &mut RootWriteLock<'_>,
         left_child: PageId,
         right_child: PageId,
         pivot_key: &[u8],
     ) -> Result<(), QSError> {
-        let node_id = self.alloc_inner_node()?;
+        let node_id = reservation.take()?;
 
         unsafe {
             let node_ptr = self.slab.as_ptr().add(node_id.0 as usize);
@@ -103,6 +309,7 @@ impl BPTree {
 
     pub fn promote_inner_root(
         &self,
+        reservation: &mut InnerReservation<'_>,
         root_lock: &mut RootWriteLock<'_>,
         left_child: BPNodeId,
         right_child: BPNodeId,
@@ -110,7 +317,7 @@ impl BPTree {
         child_level: u16,
     ) -> Result<(), QSError> {
         let new_level = child_level + 1;
-        let node_id = self.alloc_inner_node()?;
+        let node_id = reservation.take()?;
 
         unsafe {
             let node_ptr = self.slab.as_ptr().add(node_id.0 as usize);
@@ -135,9 +342,12 @@ impl BPTree {
             .remove_entry_for_merge(level, survivor, removed)
     }
 
+    /// Make `child`, the only child left in the root under `old_root`, the root and retire
+    /// the old one
     pub fn demote_root_after_merge(
         &self,
         root_lock: &mut RootWriteLock<'_>,
+        old_root: InnerWriteGuard<'_>,
         child: ChildPointer,
         parent_level: u16,
     ) -> Result<(), QSError> {
@@ -145,9 +355,151 @@ impl BPTree {
             1 => root_lock.set_leaf(child.as_leaf()),
             _ => root_lock.set_inner(child.as_inner(), parent_level - 1),
         }
+        self.retire_inner(old_root);
         Ok(())
     }
 
+    /// Build inner nodes over `leaves`, each with the pivot it starts at in key order (the
+    /// first pivot is unused), off to the side of the tree: nothing reaches them until
+    /// `swap_root`. Nodes are filled until they could overflow, leaving room for one pivot
+    pub fn build_detached(&self, leaves: &[(Vec<u8>, PageId)]) -> Result<DetachedTree, QSError> {
+        let mut children: Vec<(Vec<u8>, ChildPointer)> = leaves
+            .iter()
+            .map(|(pivot, page)| (pivot.clone(), ChildPointer::Leaf(*page)))
+            .collect();
+        let mut nodes = Vec::new();
+        let mut level = 0;
+        while children.len() > 1 {
+            level += 1;
+            match self.build_detached_level(level, &children, &mut nodes) {
+                Ok(parents) => children = parents,
+                Err(err) => {
+                    self.retire_detached(nodes);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(DetachedTree {
+            root: children[0].1,
+            level,
+            nodes,
+        })
+    }
+
+    fn build_detached_level(
+        &self,
+        level: u16,
+        children: &[(Vec<u8>, ChildPointer)],
+        nodes: &mut Vec<BPNodeId>,
+    ) -> Result<Vec<(Vec<u8>, ChildPointer)>, QSError> {
+        let mut parents: Vec<(Vec<u8>, ChildPointer)> = Vec::new();
+        let level_start = nodes.len();
+        let mut current: Option<BPNodeId> = None;
+        for (pivot, child) in children {
+            // SAFETY: the node is detached, only this build can reach it
+            let open = current.map(|node_id| unsafe { self.detached_node(node_id) });
+            match open {
+                Some(node) if !node.can_overflow(level) => {
+                    node.append_entry_for_level(level, pivot, *child)?;
+                }
+                _ => {
+                    let node_id = self.alloc_inner_node()?;
+                    nodes.push(node_id);
+                    // SAFETY: as above
+                    unsafe { self.detached_node(node_id) }.reset_for_level(level, *child);
+                    parents.push((pivot.clone(), ChildPointer::Inner(node_id)));
+                    current = Some(node_id);
+                }
+            }
+        }
+        // filled with whole pivots, each node keeps the prefix they share only once
+        for &node_id in &nodes[level_start..] {
+            // SAFETY: as above
+            unsafe { self.detached_node(node_id) }.compact(level)?;
+        }
+        Ok(parents)
+    }
+
+    /// # Safety
+    /// The node must be one `build_detached` allocated and not yet linked into the tree
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn detached_node(&self, node_id: BPNodeId) -> &mut BPNode {
+        &mut *self.slab.as_ptr().add(node_id.0 as usize)
+    }
+
+    /// Give back the nodes of a detached tree that will never be linked in
+    pub fn discard_detached(&self, tree: DetachedTree) {
+        self.retire_detached(tree.nodes);
+    }
+
+    fn retire_detached(&self, nodes: Vec<BPNodeId>) {
+        for node_id in nodes {
+            let guard = self
+                .write_inner(node_id)
+                .unwrap_or_else(|_| panic!("detached node {node_id:?} is locked"));
+            self.retire_inner(guard);
+        }
+    }
+
+    /// Make `tree` the tree, retiring every inner node of the one it replaces. The caller
+    /// holds the write lock of every leaf, so no split or merge is changing the old nodes;
+    /// optimistic readers in them restart on the new root. `log` makes the swap durable once
+    /// every old node is locked, and the swap only happens if it succeeds. A failed swap
+    /// discards `tree`
+    pub fn swap_root(
+        &self,
+        tree: DetachedTree,
+        log: impl FnOnce() -> Result<(), QSError>,
+    ) -> Result<(), QSError> {
+        let locked = (0..SPIN_RETRIES).find_map(|attempt| {
+            restart_pause(attempt);
+            let root_lock = self.read_root().ok()?.upgrade().ok()?;
+            let old_nodes = self.lock_subtree(root_lock.get_root()).ok()?;
+            Some((root_lock, old_nodes))
+        });
+        let Some((mut root_lock, old_nodes)) = locked else {
+            self.discard_detached(tree);
+            return Err(QSError::OLCRetriesExceeded);
+        };
+        if let Err(err) = log() {
+            drop((old_nodes, root_lock));
+            self.discard_detached(tree);
+            return Err(err);
+        }
+        match tree.level {
+            0 => root_lock.set_leaf(tree.root.as_leaf()),
+            level => root_lock.set_inner(tree.root.as_inner(), level),
+        }
+        for guard in old_nodes {
+            self.retire_inner(guard);
+        }
+        Ok(())
+    }
+
+    /// Write lock every inner node under `root`
+    fn lock_subtree(&self, root: BPRootInfo) -> Result<Vec<InnerWriteGuard<'_>>, BPRestart> {
+        let BPRootInfo::Inner { level, node } = root else {
+            return Ok(Vec::new());
+        };
+        let mut locked = vec![(level.get(), self.write_inner(node)?)];
+        let mut idx = 0;
+        while idx < locked.len() {
+            let (level, guard) = &locked[idx];
+            let level = *level;
+            if level > 1 {
+                let node = guard.as_ref();
+                let children: Vec<_> = std::iter::once(node.lowest_child_for_level(level))
+                    .chain((0..node.count).map(|idx| node.get_child_for_level(idx, level)))
+                    .collect();
+                for child in children {
+                    locked.push((level - 1, self.write_inner(child.as_inner())?));
+                }
+            }
+            idx += 1;
+        }
+        Ok(locked.into_iter().map(|(_, guard)| guard).collect())
+    }
+
     pub fn read_root(&self) -> Result<RootReadLock<'_>, BPRestart> {
         let version = self.root_vlock.load(Ordering::Acquire);
         if is_locked_or_obsolete(version) {
@@ -159,6 +511,7 @@ impl BPTree {
         })
     }
 
+    /// # Safety
     /// Must have checked the root lock
     pub unsafe fn get_root(&self) -> BPRootInfo {
         let info = self.root.load(Ordering::Acquire);
@@ -195,7 +548,8 @@ impl BPTree {
     }
 
     pub fn read_traverse_leaf(&self, key: &[u8]) -> Result<ReadRes<'_>, QSError> {
-        for _ in 0..SPIN_RETRIES {
+        for attempt in 0..SPIN_RETRIES {
+            restart_pause(attempt);
             if let Ok(leaf) = self.try_read_traverse_leaf(key) {
                 return Ok(leaf);
             }
@@ -204,6 +558,9 @@ impl BPTree {
     }
 
     fn try_read_traverse_leaf(&self, key: &[u8]) -> Result<ReadRes<'_>, BPRestart> {
+        // the lock points outlive the pin, but they are only ever upgraded, which fails on a
+        // retired or reused node
+        let _pin = self.epochs.pin();
         let root_guard = self.read_root()?;
 
         let mut underflow_point = WriteLockPoint::Root;
@@ -259,13 +616,60 @@ impl BPTree {
         let leaf_cand = unsafe { parent_guard.as_ref().search_for_leaf(key) };
 
         parent_guard.unlock_or_restart()?;
-        return Ok(ReadRes {
+        Ok(ReadRes {
             page: leaf_cand,
             overflow_point,
             underflow_point,
             lower_fence_key: None,
             upper_fence_key: None,
-        });
+        })
+    }
+
+    /// The leaf `key` belongs to, with the pivot the next leaf starts at, `None` past the last
+    /// leaf. Lets a caller step through the leaves of a range without reading them
+    pub fn read_leaf_and_successor(&self, key: &[u8]) -> Result<LeafAndSuccessor, QSError> {
+        for attempt in 0..SPIN_RETRIES {
+            restart_pause(attempt);
+            if let Ok(res) = self.try_read_leaf_and_successor(key) {
+                return Ok(res);
+            }
+        }
+        Err(QSError::OLCRetriesExceeded)
+    }
+
+    fn try_read_leaf_and_successor(&self, key: &[u8]) -> Result<LeafAndSuccessor, BPRestart> {
+        let _pin = self.epochs.pin();
+        let root_guard = self.read_root()?;
+
+        // SAFETY: we checked its not locked or obsolete
+        let (mut level, node) = match unsafe { self.get_root() } {
+            BPRootInfo::Leaf(page) => return Ok((page, None)),
+            BPRootInfo::Inner { level, node } => (level.get(), node),
+        };
+        let mut guard = self.read_inner(node)?;
+        root_guard.unlock_or_restart()?;
+
+        // the nearest pivot above `key` on the way down bounds the leaf from above
+        let mut successor = None;
+        loop {
+            if let Some(pivot) = guard.as_ref().pivot_after(key) {
+                successor = Some(Box::from(pivot));
+            }
+            if level == 1 {
+                break;
+            }
+            // SAFETY: level of the node > 1
+            let child = unsafe { guard.as_ref().search_for_inner(key) };
+            let child_guard = self.read_inner(child)?;
+            guard.unlock_or_restart()?;
+            guard = child_guard;
+            level -= 1;
+        }
+
+        // SAFETY: level of the node = 1
+        let leaf = unsafe { guard.as_ref().search_for_leaf(key) };
+        guard.unlock_or_restart()?;
+        Ok((leaf, successor))
     }
 
     pub fn write_lock<'a>(
@@ -279,7 +683,8 @@ impl BPTree {
             return Ok(l);
         }
 
-        for _ in 0..SPIN_RETRIES {
+        for attempt in 0..SPIN_RETRIES {
+            restart_pause(attempt);
             let Ok(res) = self.try_read_traverse_leaf(key) else {
                 continue;
             };
@@ -356,6 +761,7 @@ impl BPTree {
 
     pub(crate) fn split_inner_node(
         &self,
+        reservation: &mut InnerReservation<'_>,
         guard: &mut InnerWriteGuard<'_>,
         level: u16,
         left_child: ChildPointer,
@@ -363,12 +769,7 @@ impl BPTree {
         right_child: ChildPointer,
     ) -> Result<InnerSplitPropagation, QSError> {
         let lowest_child = guard.as_ref().lowest_child_for_level(level);
-        let mut entries = Vec::with_capacity(guard.as_ref().count as usize + 1);
-        for idx in 0..guard.as_ref().count {
-            let key = guard.as_ref().get_key(idx).to_vec();
-            let child = guard.as_ref().get_child_for_level(idx, level);
-            entries.push((key, child));
-        }
+        let mut entries = guard.as_ref().entries(level);
 
         let insert_idx = if left_child == lowest_child {
             0
@@ -381,7 +782,18 @@ impl BPTree {
         };
         entries.insert(insert_idx, (pivot_key.to_vec(), right_child));
 
-        let promote_idx = entries.len() / 2;
+        // the middle, unless a pivot without the others' prefix leaves a half too long to
+        // fit: then the nearest point that splits them into halves that do
+        let middle = entries.len() / 2;
+        let promote_idx = (0..entries.len())
+            .flat_map(|offset| [middle.checked_sub(offset), Some(middle + offset)])
+            .flatten()
+            .find(|&idx| {
+                idx < entries.len()
+                    && fits(level, &entries[..idx])
+                    && fits(level, &entries[idx + 1..])
+            })
+            .ok_or(QSError::NodeFull)?;
         let promote_entry = entries.remove(promote_idx);
         let promote_key = promote_entry.0.clone();
         let right_lowest = promote_entry.1;
@@ -389,20 +801,12 @@ impl BPTree {
         let right_entries = entries.split_off(promote_idx);
         let left_entries = entries;
 
-        let right_node_id = self.alloc_inner_node()?;
+        let right_node_id = reservation.take()?;
         let right_node_ptr = unsafe { self.slab.as_ptr().add(right_node_id.0 as usize) };
         let right_node = unsafe { &mut *right_node_ptr };
 
-        let left_node = guard.as_mut();
-        left_node.reset_for_level(level, lowest_child);
-        for (key, child) in left_entries {
-            left_node.append_entry_for_level(level, &key, child)?;
-        }
-
-        right_node.reset_for_level(level, right_lowest);
-        for (key, child) in right_entries {
-            right_node.append_entry_for_level(level, &key, child)?;
-        }
+        guard.as_mut().rebuild(level, lowest_child, &left_entries)?;
+        right_node.rebuild(level, right_lowest, &right_entries)?;
 
         Ok(InnerSplitPropagation {
             pivot_key: promote_key,
@@ -411,6 +815,7 @@ impl BPTree {
     }
 
     pub fn debug_root_leaf_parent(&self) -> Option<DebugLeafParent> {
+        let _pin = self.epochs.pin();
         let root_guard = self.read_root().ok()?;
         let info = root_guard.get_root();
         let snapshot = match info {
@@ -421,7 +826,7 @@ impl BPTree {
                 let mut children = Vec::with_capacity(node_ref.count as usize + 1);
                 children.push(PageId(node_ref.lowest));
                 for idx in 0..node_ref.count {
-                    pivots.push(node_ref.get_key(idx).to_vec());
+                    pivots.push(node_ref.get_key(idx));
                     children.push(node_ref.get_leaf_child(idx));
                 }
                 inner_guard.unlock_or_restart().ok()?;
@@ -433,6 +838,73 @@ impl BPTree {
         snapshot
     }
 
+    /// Copy out every inner node reachable from the root. A child naming a node that was
+    /// never handed out, or one already copied, is listed but not followed
+    pub fn snapshot_inner(&self) -> Result<TreeSnapshot, QSError> {
+        for attempt in 0..SPIN_RETRIES {
+            restart_pause(attempt);
+            if let Ok(snapshot) = self.try_snapshot_inner() {
+                return Ok(snapshot);
+            }
+        }
+        Err(QSError::OLCRetriesExceeded)
+    }
+
+    fn try_snapshot_inner(&self) -> Result<TreeSnapshot, BPRestart> {
+        let _pin = self.epochs.pin();
+        let root_guard = self.read_root()?;
+        let (level, node) = match root_guard.get_root() {
+            BPRootInfo::Leaf(page) => {
+                root_guard.unlock_or_restart()?;
+                return Ok(TreeSnapshot {
+                    root: ChildPointer::Leaf(page),
+                    nodes: Vec::new(),
+                });
+            }
+            BPRootInfo::Inner { level, node } => (level.get(), node),
+        };
+
+        let allocated = self.next_free.load(Ordering::Acquire);
+        let mut nodes = Vec::new();
+        let mut copied = HashSet::new();
+        let mut stack = vec![(node, level)];
+        while let Some((node, level)) = stack.pop() {
+            if node.0 == 0 || node.0 >= allocated || !copied.insert(node) {
+                continue;
+            }
+            let guard = self.read_inner(node)?;
+            let node_ref = guard.as_ref();
+            let mut pivots = Vec::with_capacity(node_ref.count as usize);
+            let mut children = Vec::with_capacity(node_ref.count as usize + 1);
+            children.push(node_ref.lowest_child_for_level(level));
+            for idx in 0..node_ref.count {
+                pivots.push(node_ref.get_key(idx));
+                children.push(node_ref.get_child_for_level(idx, level));
+            }
+            guard.unlock_or_restart()?;
+            if level > 1 {
+                // pushed right to left so the leftmost child is copied next
+                stack.extend(
+                    children
+                        .iter()
+                        .rev()
+                        .map(|child| (child.as_inner(), level - 1)),
+                );
+            }
+            nodes.push(InnerSnapshot {
+                node,
+                level,
+                pivots,
+                children,
+            });
+        }
+        root_guard.unlock_or_restart()?;
+        Ok(TreeSnapshot {
+            root: ChildPointer::Inner(node),
+            nodes,
+        })
+    }
+
     pub fn root_level(&self) -> u16 {
         let Ok(root_guard) = self.read_root() else {
             return 0;
@@ -535,10 +1007,36 @@ fn update_lock_points<'a>(
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct BPNodeId(u32);
 
+impl BPNodeId {
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
+/// The pivots and children of one inner node, see `BPTree::snapshot_inner`
+#[derive(Debug, Clone)]
+pub struct InnerSnapshot {
+    pub node: BPNodeId,
+    pub level: u16,
+    pub pivots: Vec<Vec<u8>>,
+    /// The lowest child, then the child after each pivot
+    pub children: Vec<ChildPointer>,
+}
+
+/// The inner nodes reachable from the root, each once, parents before their children
+#[derive(Debug, Clone)]
+pub struct TreeSnapshot {
+    pub root: ChildPointer,
+    pub nodes: Vec<InnerSnapshot>,
+}
+
+/// A leaf and the pivot its right neighbour starts at, if it has one
+pub type LeafAndSuccessor = (PageId, Option<Box<[u8]>>);
+
 pub struct ReadRes<'a> {
     /// Page where the target would be located
     pub page: PageId,
@@ -588,6 +1086,47 @@ pub struct WriteLockBundle<'a> {
     pub chain: Vec<LockedInner<'a>>,
 }
 
+impl WriteLockBundle<'_> {
+    /// Most nodes a leaf split under these locks allocates: one for each full node it
+    /// splits from the bottom up, and a new root if the split reaches the root
+    pub fn split_nodes_needed(&self) -> usize {
+        let splits = self
+            .chain
+            .iter()
+            .rev()
+            .take_while(|locked| locked.guard.as_ref().can_overflow(locked.level))
+            .count();
+        let new_root = splits == self.chain.len() && self.root_lock.is_some();
+        splits + new_root as usize
+    }
+}
+
+/// Nodes set aside by `BPTree::reserve`, those left unused go back to the free list once
+/// it is dropped
+pub struct InnerReservation<'a> {
+    tree: &'a BPTree,
+    nodes: Vec<BPNodeId>,
+}
+
+impl InnerReservation<'_> {
+    fn take(&mut self) -> Result<BPNodeId, QSError> {
+        // the reservation covers the worst case, running past it is a counting bug
+        debug_assert!(
+            !self.nodes.is_empty(),
+            "split took more nodes than it reserved"
+        );
+        self.nodes.pop().ok_or(QSError::TreeFull)
+    }
+}
+
+impl Drop for InnerReservation<'_> {
+    fn drop(&mut self) {
+        for node_id in self.nodes.drain(..) {
+            self.tree.free_unused_node(node_id);
+        }
+    }
+}
+
 pub struct InnerSplitPropagation {
     pub pivot_key: Vec<u8>,
     pub right_node: BPNodeId,
@@ -598,6 +1137,21 @@ pub struct LockedInner<'a> {
     pub guard: InnerWriteGuard<'a>,
 }
 
+/// Inner nodes built by `BPTree::build_detached`, not yet linked into the tree
+pub struct DetachedTree {
+    /// A leaf when there is a single one, at level 0
+    root: ChildPointer,
+    level: u16,
+    nodes: Vec<BPNodeId>,
+}
+
+impl DetachedTree {
+    /// Inner nodes the tree is built from
+    pub fn inner_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
 pub enum BPRootInfo {
     Leaf(PageId),
     Inner {
@@ -622,6 +1176,7 @@ pub struct InnerReadGuard<'a> {
 }
 
 impl<'a> InnerReadGuard<'a> {
+    #[allow(clippy::should_implement_trait)]
     pub fn as_ref(&self) -> &BPNode {
         unsafe { self.node.as_ref() }
         // &self.node
@@ -638,6 +1193,7 @@ impl<'a> InnerReadGuard<'a> {
             Ok(_) => Ok(InnerWriteGuard {
                 node: unsafe { &mut *self.node.as_ptr() },
                 node_id: self.node_id,
+                obsolete: false,
             }),
             Err(_v) => Err(BPRestart),
         }
@@ -660,13 +1216,17 @@ impl<'a> InnerReadGuard<'a> {
 pub struct InnerWriteGuard<'a> {
     node: &'a mut BPNode,
     node_id: BPNodeId,
+    /// Unlock as obsolete, the node has left the tree
+    obsolete: bool,
 }
 
 impl<'a> InnerWriteGuard<'a> {
+    #[allow(clippy::should_implement_trait)]
     pub fn as_ref(&self) -> &BPNode {
-        &self.node
+        self.node
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn as_mut(&mut self) -> &mut BPNode {
         self.node
     }
@@ -689,22 +1249,29 @@ impl<'a> InnerWriteGuard<'a> {
 
 impl<'a> Drop for InnerWriteGuard<'a> {
     fn drop(&mut self) {
-        self.node.vlock.fetch_add(0b10, Ordering::Release);
+        // adding 0b11 clears the lock bit and sets the obsolete one
+        let unlock = if self.obsolete { 0b11 } else { 0b10 };
+        self.node.vlock.fetch_add(unlock, Ordering::Release);
     }
 }
 
-/// | vlock | count | alloc idx |lowest child | KVMeta ...   ... Full keys |
-///    8B      4B          4B           8B             8B   ...
+/// | vlock | count | alloc idx | prefix len | lowest child | KVMeta ...   ... Key suffixes | Prefix |
+///    8B      4B         2B          2B            8B             8B   ...
 ///                                             4072B
 // NOTE: this is inefficient use of memory, but I want to keep everything word aligned
 // so this is easier, but more information can easily be squeesed in, (at least 32 bit)
+//
+// The pivots of a node share the prefix stored once at the end of the rest buffer, each
+// entry holds only the rest of its key
 #[repr(C)]
 pub struct BPNode {
     vlock: AtomicU64,
     count: u32,
     /// index of the last allocated byte in the rest buffer
     /// a la a stack pointer
-    alloc_idx: u32,
+    alloc_idx: u16,
+    /// length of the prefix every pivot starts with
+    prefix_len: u16,
     // all 1s for None
     lowest: u64,
     rest: [u8; INLINE_BUFFER_LEN],
@@ -718,59 +1285,48 @@ const _: () = assert!(size_of::<BPNode>() == 4096);
 impl BPNode {
     fn reset_leaf_parent(&mut self, lowest_child: PageId) {
         self.count = 0;
-        self.alloc_idx = INLINE_BUFFER_LEN as u32 - 1;
+        self.alloc_idx = INLINE_BUFFER_LEN as u16 - 1;
+        self.prefix_len = 0;
         self.lowest = lowest_child.0;
     }
 
     fn reset_inner_parent(&mut self, lowest_child: BPNodeId) {
         self.count = 0;
-        self.alloc_idx = INLINE_BUFFER_LEN as u32 - 1;
+        self.alloc_idx = INLINE_BUFFER_LEN as u16 - 1;
+        self.prefix_len = 0;
         self.lowest = lowest_child.0 as u64;
     }
 
-    fn append_leaf_entry(&mut self, key: &[u8], child: PageId) -> Result<(), QSError> {
-        if key.len() > MAX_KEY_LENGTH {
-            return Err(QSError::KeyTooLarge);
-        }
-
-        let needed = key.len() + LEAF_CHILD_BYTES;
-        let meta_cost = size_of::<BPKVMeta>();
-        if self.space_left() < needed + meta_cost {
-            return Err(QSError::NodeFull);
-        }
-
-        self.alloc_idx = self
-            .alloc_idx
-            .checked_sub(needed as u32)
-            .ok_or(QSError::NodeFull)?;
-
-        let key_start = self.alloc_idx as usize + 1;
-        let child_start = key_start + key.len();
-
-        self.rest[key_start..key_start + key.len()].copy_from_slice(key);
-        let child_bytes = store_u48(child.0);
-        self.rest[child_start..child_start + LEAF_CHILD_BYTES].copy_from_slice(&child_bytes);
-
-        let meta = BPKVMeta {
-            start_offset: key_start as u16,
-            key_len: key.len() as u16,
-        };
-
-        let meta_ptr = self.rest.as_mut_ptr() as *mut BPKVMeta;
-        unsafe {
-            meta_ptr.add(self.count as usize).write(meta);
-        }
+    /// Store `prefix` once for the pivots appended after it, on a node without pivots
+    fn set_prefix(&mut self, prefix: &[u8]) {
+        debug_assert_eq!(self.count, 0, "prefix set under existing pivots");
+        let start = INLINE_BUFFER_LEN - prefix.len();
+        self.rest[start..].copy_from_slice(prefix);
+        self.prefix_len = prefix.len() as u16;
+        self.alloc_idx = start as u16 - 1;
+    }
 
-        self.count += 1;
-        Ok(())
+    /// The bytes every pivot of the node starts with
+    fn prefix(&self) -> &[u8] {
+        // clamped for optimistic readers, which may see a torn length before they restart
+        &self.rest[INLINE_BUFFER_LEN - (self.prefix_len as usize).min(INLINE_BUFFER_LEN)..]
     }
 
-    fn append_inner_entry(&mut self, key: &[u8], child: BPNodeId) -> Result<(), QSError> {
+    /// Append a pivot starting with the node's prefix, storing the rest of it and the
+    /// encoded child after it
+    fn append_entry(&mut self, key: &[u8], child: &[u8]) -> Result<(), QSError> {
         if key.len() > MAX_KEY_LENGTH {
             return Err(QSError::KeyTooLarge);
         }
+        debug_assert!(
+            key.starts_with(self.prefix()),
+            "pivot outside the node's prefix"
+        );
+        let suffix = &key[self.prefix_len as usize..];
+        // the first bytes live in the metadata as the lookahead, only the rest in the heap
+        let tail = &suffix[suffix.len().min(LOOKAHEAD_LEN)..];
 
-        let needed = key.len() + 4;
+        let needed = tail.len() + child.len();
         let meta_cost = size_of::<BPKVMeta>();
         if self.space_left() < needed + meta_cost {
             return Err(QSError::NodeFull);
@@ -778,19 +1334,19 @@ impl BPNode {
 
         self.alloc_idx = self
             .alloc_idx
-            .checked_sub(needed as u32)
+            .checked_sub(needed as u16)
             .ok_or(QSError::NodeFull)?;
 
         let key_start = self.alloc_idx as usize + 1;
-        let child_start = key_start + key.len();
+        let child_start = key_start + tail.len();
 
-        self.rest[key_start..key_start + key.len()].copy_from_slice(key);
-        let child_bytes = store_u32(child.0 as u64);
-        self.rest[child_start..child_start + 4].copy_from_slice(&child_bytes);
+        self.rest[key_start..child_start].copy_from_slice(tail);
+        self.rest[child_start..child_start + child.len()].copy_from_slice(child);
 
         let meta = BPKVMeta {
             start_offset: key_start as u16,
-            key_len: key.len() as u16,
+            key_len: suffix.len() as u16,
+            lookahead: get_lookahead(suffix),
         };
 
         let meta_ptr = self.rest.as_mut_ptr() as *mut BPKVMeta;
@@ -802,12 +1358,27 @@ impl BPNode {
         Ok(())
     }
 
+    fn append_leaf_entry(&mut self, key: &[u8], child: PageId) -> Result<(), QSError> {
+        self.append_entry(key, &store_u48(child.0))
+    }
+
+    fn append_inner_entry(&mut self, key: &[u8], child: BPNodeId) -> Result<(), QSError> {
+        self.append_entry(key, &store_u32(child.0 as u64))
+    }
+
+    /// Append a pivot after every other in the node. One without the node's prefix has the
+    /// node written again with the prefix it shares with the others
     fn append_entry_for_level(
         &mut self,
         level: u16,
         key: &[u8],
         child: ChildPointer,
     ) -> Result<(), QSError> {
+        if !key.starts_with(self.prefix()) {
+            let mut entries = self.entries(level);
+            entries.push((key.to_vec(), child));
+            return self.rebuild(level, self.lowest_child_for_level(level), &entries);
+        }
         match level {
             1 => self.append_leaf_entry(key, child.as_leaf()),
             _ => self.append_inner_entry(key, child.as_inner()),
@@ -821,18 +1392,54 @@ impl BPNode {
         }
     }
 
+    /// Every pivot of the node, whole, with the child after it
+    fn entries(&self, level: u16) -> Vec<(Vec<u8>, ChildPointer)> {
+        (0..self.count)
+            .map(|idx| (self.get_key(idx), self.get_child_for_level(idx, level)))
+            .collect()
+    }
+
+    /// Empty the node and fill it with `entries` after `lowest_child`, storing the prefix
+    /// their pivots share once. Fails with `QSError::NodeFull`, leaving the node as it was,
+    /// if they do not fit
+    fn rebuild(
+        &mut self,
+        level: u16,
+        lowest_child: ChildPointer,
+        entries: &[(Vec<u8>, ChildPointer)],
+    ) -> Result<(), QSError> {
+        if entries.iter().any(|(key, _)| key.len() > MAX_KEY_LENGTH) {
+            return Err(QSError::KeyTooLarge);
+        }
+        if !fits(level, entries) {
+            return Err(QSError::NodeFull);
+        }
+        self.reset_for_level(level, lowest_child);
+        self.set_prefix(shared_prefix(entries));
+        for (key, child) in entries {
+            self.append_entry_for_level(level, key, *child)?;
+        }
+        Ok(())
+    }
+
+    /// Store the node again with the longest prefix its pivots share
+    fn compact(&mut self, level: u16) -> Result<(), QSError> {
+        let entries = self.entries(level);
+        self.rebuild(level, self.lowest_child_for_level(level), &entries)
+    }
+
     fn get_leaf_child(&self, idx: u32) -> PageId {
         let meta = self.get_meta(idx);
-        let child_offset = meta.start_offset as usize + meta.key_len as usize;
-        let child_ptr = unsafe { self.rest.as_ptr().add(child_offset) };
-        PageId(extract_u48(child_ptr))
+        let child_offset = meta.child_offset();
+        // SAFETY: every entry stores its 6 byte child right after the key
+        PageId(unsafe { extract_u48(self.rest.as_ptr().add(child_offset)) })
     }
 
     fn get_inner_child(&self, idx: u32) -> BPNodeId {
         let meta = self.get_meta(idx);
-        let child_offset = meta.start_offset as usize + meta.key_len as usize;
-        let child_ptr = unsafe { self.rest.as_ptr().add(child_offset) };
-        BPNodeId(extract_u32(child_ptr))
+        let child_offset = meta.child_offset();
+        // SAFETY: every entry stores its 4 byte child right after the key
+        BPNodeId(unsafe { extract_u32(self.rest.as_ptr().add(child_offset)) })
     }
 
     fn lowest_child_for_level(&self, level: u16) -> ChildPointer {
@@ -871,12 +1478,7 @@ impl BPNode {
         right_child: ChildPointer,
     ) -> Result<(), QSError> {
         let lowest_child = self.lowest_child_for_level(level);
-        let mut entries = Vec::with_capacity(self.count as usize + 1);
-        for idx in 0..self.count {
-            let key = self.get_key(idx).to_vec();
-            let child = self.get_child_for_level(idx, level);
-            entries.push((key, child));
-        }
+        let mut entries = self.entries(level);
 
         let insert_idx = if left_child == lowest_child {
             0
@@ -890,12 +1492,8 @@ impl BPNode {
 
         entries.insert(insert_idx, (pivot_key.to_vec(), right_child));
 
-        self.reset_for_level(level, lowest_child);
-        for (key, child) in entries {
-            self.append_entry_for_level(level, &key, child)?;
-        }
-
-        Ok(())
+        // a full node is left whole for `split_inner_node` to read
+        self.rebuild(level, lowest_child, &entries)
     }
 
     fn remove_entry_for_merge(
@@ -909,7 +1507,7 @@ impl BPNode {
 
         children.push(self.lowest_child_for_level(level));
         for idx in 0..self.count {
-            pivots.push(self.get_key(idx).to_vec());
+            pivots.push(self.get_key(idx));
             children.push(self.get_child_for_level(idx, level));
         }
 
@@ -929,25 +1527,37 @@ impl BPNode {
         children.remove(removed_idx);
         pivots.remove(removed_idx - 1);
 
-        let new_lowest = children[0];
-        self.reset_for_level(level, new_lowest);
-        for (idx, pivot) in pivots.iter().enumerate() {
-            let child = children[idx + 1];
-            self.append_entry_for_level(level, pivot, child)?;
-        }
+        let entries: Vec<_> = pivots
+            .into_iter()
+            .zip(children[1..].iter().copied())
+            .collect();
+        self.rebuild(level, children[0], &entries)?;
 
-        if pivots.is_empty() {
+        if entries.is_empty() {
             Ok(Some(children[0]))
         } else {
             Ok(None)
         }
     }
 
+    /// Empty a node taken off the free list. Its version moves on from the one it was
+    /// retired with, so guards from its previous life stay stale
+    fn recycle(&mut self) {
+        let version = self.vlock.load(Ordering::Acquire);
+        debug_assert!(is_obsolete(version), "reused a node still in the tree");
+        self.vlock.store((version | 0b11) + 1, Ordering::Release);
+        self.count = 0;
+        self.alloc_idx = INLINE_BUFFER_LEN as u16 - 1;
+        self.prefix_len = 0;
+        self.lowest = u64::MAX;
+    }
+
     fn blank() -> BPNode {
         BPNode {
             vlock: AtomicU64::new(0),
             count: 0,
-            alloc_idx: INLINE_BUFFER_LEN as u32 - 1,
+            alloc_idx: INLINE_BUFFER_LEN as u16 - 1,
+            prefix_len: 0,
             lowest: u64::MAX,
             rest: [0; INLINE_BUFFER_LEN],
         }
@@ -962,50 +1572,19 @@ impl BPNode {
         if pivot_key.len() > MAX_KEY_LENGTH {
             return Err(QSError::KeyTooLarge);
         }
-
-        self.count = 0;
-        self.alloc_idx = INLINE_BUFFER_LEN as u32 - 1;
-        self.lowest = left_child.0;
-
-        let total = pivot_key.len() + LEAF_CHILD_BYTES;
-        if total + size_of::<BPKVMeta>() > INLINE_BUFFER_LEN {
-            return Err(QSError::KeyTooLarge);
-        }
-
-        self.alloc_idx = self
-            .alloc_idx
-            .checked_sub(total as u32)
-            .ok_or(QSError::KeyTooLarge)?;
-
-        let key_start = self.alloc_idx as usize + 1;
-        let child_start = key_start + pivot_key.len();
-
-        self.rest[key_start..key_start + pivot_key.len()].copy_from_slice(pivot_key);
-        let child_bytes = store_u48(right_child.0);
-        self.rest[child_start..child_start + LEAF_CHILD_BYTES].copy_from_slice(&child_bytes);
-
-        let meta = BPKVMeta {
-            start_offset: key_start as u16,
-            key_len: pivot_key.len() as u16,
-        };
-
-        let meta_ptr = self.rest.as_mut_ptr() as *mut BPKVMeta;
-        unsafe {
-            meta_ptr.write(meta);
-        }
-
-        self.count = 1;
-
-        Ok(())
+        self.reset_leaf_parent(left_child);
+        self.append_leaf_entry(pivot_key, right_child)
     }
 
+    #[allow(dead_code)]
     unsafe fn init(mem: NonNull<[u8; 4096]>) {
         let node_ptr = mem.as_ptr() as *mut BPNode;
 
         node_ptr.write(BPNode {
             vlock: AtomicU64::new(0),
             count: 0,
-            alloc_idx: INLINE_BUFFER_LEN as u32 - 1,
+            alloc_idx: INLINE_BUFFER_LEN as u16 - 1,
+            prefix_len: 0,
             lowest: u64::MAX,
             rest: [0; INLINE_BUFFER_LEN],
         });
@@ -1015,7 +1594,8 @@ impl BPNode {
     pub fn space_left(&self) -> usize {
         let kv_meta_size = size_of::<BPKVMeta>() * self.count as usize;
 
-        self.alloc_idx as usize - kv_meta_size + 1
+        // a node filled exactly has its last entry's metadata end just past `alloc_idx`
+        self.alloc_idx as usize + 1 - kv_meta_size
     }
 
     /// The node can overflow when a key is added to it
@@ -1026,56 +1606,93 @@ impl BPNode {
             // If pointing to inner nodes then 32bit (4B)
             _ => 4,
         };
+        // a pivot without the node's prefix has the prefix written back into every entry
+        let unshared = self.prefix_len as usize * self.count as usize;
         // If we have more space than the metadata, child, and max key then we can't overflow
-        self.space_left() < size_of::<BPKVMeta>() + MAX_KEY_LENGTH + child_size
+        self.space_left()
+            < size_of::<BPKVMeta>() + MAX_KEY_LENGTH - LOOKAHEAD_LEN + child_size + unshared
     }
 
     /// The node will be underfull if a key is removed
     pub fn will_underflow(&self) -> bool {
-        // This is just a heuristic, experimentation needed
-        self.space_left() <= INLINE_BUFFER_LEN / 2
+        // Inner nodes are never merged yet, so only losing the last pivot needs the parent
+        // locked: a root left with one child is replaced by it
+        self.count <= 1
     }
 
-    /// SAFETY: This method should only be called on nodes with height > 1
+    /// The only child of a node left without pivots
+    pub fn sole_child(&self, level: u16) -> Option<ChildPointer> {
+        (self.count == 0).then(|| self.lowest_child_for_level(level))
+    }
+
+    /// # Safety
+    /// This method should only be called on nodes with height > 1
     pub unsafe fn search_for_inner(&self, key: &[u8]) -> BPNodeId {
+        // a node left with only its lowest child (e.g. after a merge) has no pivots to read
+        if self.count == 0 {
+            return BPNodeId(self.lowest as u32);
+        }
         let idx = self.binary_search(key);
-        let pivot_key = self.get_key(idx);
-        if key < pivot_key {
+        if self.cmp_pivot(idx, key).is_gt() {
             BPNodeId(self.lowest as u32)
         } else {
             let m = self.get_meta(idx);
-            let child_offset = m.start_offset as usize + m.key_len as usize;
+            let child_offset = m.child_offset();
             let child_ptr = self.rest.as_ptr().add(child_offset);
             let child = extract_u32(child_ptr);
             BPNodeId(child)
         }
     }
 
-    // SAFETY: This method should only be called on nodes with height = 1
+    /// # Safety
+    /// This method should only be called on nodes with height = 1
     pub unsafe fn search_for_leaf(&self, key: &[u8]) -> PageId {
+        // a node left with only its lowest child (e.g. after a merge) has no pivots to read
+        if self.count == 0 {
+            return PageId(self.lowest);
+        }
         let idx = self.binary_search(key);
-        let pivot_key = self.get_key(idx);
-        if key < pivot_key {
+        if self.cmp_pivot(idx, key).is_gt() {
             PageId(self.lowest)
         } else {
             let m = self.get_meta(idx);
-            let child_offset = m.start_offset as usize + m.key_len as usize;
+            let child_offset = m.child_offset();
             let child_ptr = self.rest.as_ptr().add(child_offset);
             let child = extract_u48(child_ptr);
             PageId(child)
         }
     }
 
+    /// The smallest pivot greater than `key`, where the child after `key`'s starts
+    fn pivot_after(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if self.count == 0 {
+            return None;
+        }
+        let idx = self.binary_search(key);
+        if self.cmp_pivot(idx, key).is_gt() {
+            return Some(self.get_key(idx));
+        }
+        (idx + 1 < self.count).then(|| self.get_key(idx + 1))
+    }
+
     // find the index of the largest key smaller than or equal to the target
     #[inline]
     fn binary_search(&self, key: &[u8]) -> u32 {
+        let prefix = self.prefix();
+        let Some(rest) = key.strip_prefix(prefix) else {
+            // `key` leaves the prefix first, so it is below or above every pivot
+            return match prefix.cmp(key) {
+                std::cmp::Ordering::Greater => 0,
+                _ => self.count.saturating_sub(1),
+            };
+        };
+        let probe = get_lookahead(rest);
         let mut low = 0;
         let mut high = self.count;
 
         while low < high {
             let mid = low + (high - low) / 2;
-            let mid_key = self.get_key(mid);
-            if mid_key <= key {
+            if self.cmp_suffix(mid, rest, probe).is_le() {
                 low = mid + 1;
             } else {
                 high = mid;
@@ -1090,11 +1707,45 @@ impl BPNode {
         unsafe { start_ptr.add(idx as usize).read() }
     }
 
-    fn get_key(&self, idx: u32) -> &[u8] {
+    /// The part of pivot `idx` past the node's prefix and its lookahead
+    fn get_tail(&self, idx: u32) -> &[u8] {
         let meta = self.get_meta(idx);
         let start = meta.start_offset as usize;
-        let end = start + meta.key_len as usize;
-        &self.rest[start..end]
+        &self.rest[start..meta.child_offset()]
+    }
+
+    /// Pivot `idx`, whole
+    fn get_key(&self, idx: u32) -> Vec<u8> {
+        let meta = self.get_meta(idx);
+        let head = meta.lookahead.to_be_bytes();
+        [self.prefix(), &head[..meta.head_len()], self.get_tail(idx)].concat()
+    }
+
+    /// How pivot `idx` orders against `key`, without putting the pivot back together
+    fn cmp_pivot(&self, idx: u32, key: &[u8]) -> std::cmp::Ordering {
+        let prefix = self.prefix();
+        match key.strip_prefix(prefix) {
+            Some(rest) => self.cmp_suffix(idx, rest, get_lookahead(rest)),
+            // `key` leaves the prefix first, so it orders against every pivot the same way
+            None => prefix.cmp(key),
+        }
+    }
+
+    /// How the suffix of pivot `idx` orders against `rest`, whose lookahead is `probe`. Only
+    /// a tie in the lookahead bytes reads the rest of the suffix from the key heap
+    fn cmp_suffix(&self, idx: u32, rest: &[u8], probe: u32) -> std::cmp::Ordering {
+        let meta = self.get_meta(idx);
+        match meta.lookahead.cmp(&probe) {
+            // the padded bytes tie, so the shorter head is a prefix of the longer one and
+            // ends its key first
+            std::cmp::Ordering::Equal => {
+                let head = rest.len().min(LOOKAHEAD_LEN);
+                meta.head_len()
+                    .cmp(&head)
+                    .then_with(|| self.get_tail(idx).cmp(&rest[head..]))
+            }
+            ordering => ordering,
+        }
     }
 }
 
@@ -1160,6 +1811,7 @@ impl BPNode {
 //         return version;
 //     }
 
+#[allow(dead_code)]
 fn set_locked_bit(version: u64) -> u64 {
     version + 2
 }
@@ -1173,12 +1825,73 @@ fn is_locked_or_obsolete(version: u64) -> bool {
 }
 // }
 
-// TODO: add lookahead bytes
+/// The longest prefix every pivot of `entries` starts with
+fn shared_prefix(entries: &[(Vec<u8>, ChildPointer)]) -> &[u8] {
+    let Some(((first, _), rest)) = entries.split_first() else {
+        return &[];
+    };
+    let len = rest.iter().fold(first.len(), |len, (key, _)| {
+        first[..len]
+            .iter()
+            .zip(key)
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+    &first[..len]
+}
+
+/// Whether `entries` fit a node at `level` once their shared prefix is stored only once
+fn fits(level: u16, entries: &[(Vec<u8>, ChildPointer)]) -> bool {
+    let child_size = match level {
+        1 => LEAF_CHILD_BYTES,
+        _ => 4,
+    };
+    let prefix = shared_prefix(entries).len();
+    let stored: usize = entries
+        .iter()
+        .map(|(key, _)| {
+            size_of::<BPKVMeta>() + (key.len() - prefix).saturating_sub(LOOKAHEAD_LEN) + child_size
+        })
+        .sum();
+    prefix + stored <= INLINE_BUFFER_LEN
+}
+
+/// Bytes of a pivot's suffix its metadata holds
+const LOOKAHEAD_LEN: usize = 4;
+
+/// The first 4 bytes of a key suffix, zero padded and big endian so lookaheads that differ
+/// order the same way as their suffixes; equal ones may still come from different suffixes
+#[inline]
+fn get_lookahead(suffix: &[u8]) -> u32 {
+    let mut bytes = [0; LOOKAHEAD_LEN];
+    let len = suffix.len().min(LOOKAHEAD_LEN);
+    bytes[..len].copy_from_slice(&suffix[..len]);
+    u32::from_be_bytes(bytes)
+}
+
+/// The lookahead takes the suffix's first bytes out of the key heap, so an entry with a
+/// suffix of at least `LOOKAHEAD_LEN` bytes takes as much room as before it had one, and
+/// fanout only drops for shorter suffixes
 #[repr(C)]
 struct BPKVMeta {
-    /// offset from the start of the rest buffer, of the start of the key
+    /// offset from the start of the rest buffer, of the suffix's bytes past the lookahead
     start_offset: u16,
+    /// length of the whole suffix
     key_len: u16,
+    /// the first bytes of the key suffix, so most comparisons stay in the metadata array
+    lookahead: u32,
+}
+
+impl BPKVMeta {
+    /// Bytes of the suffix held in `lookahead` rather than the key heap
+    fn head_len(&self) -> usize {
+        (self.key_len as usize).min(LOOKAHEAD_LEN)
+    }
+
+    /// Offset of the child, stored right after the rest of the suffix
+    fn child_offset(&self) -> usize {
+        self.start_offset as usize + self.key_len as usize - self.head_len()
+    }
 }
 
 pub struct BPRestart;
@@ -1,38 +1,97 @@
 use std::{
-    array,
+    collections::HashMap,
     marker::PhantomData,
     ptr::NonNull,
-    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
-    usize,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
 };
 
 use crate::{
     debug,
     error::QSError,
+    events::{Events, StructuralEvent},
     io_engine::IoEngine,
-    map_table::MapTable,
+    lock_manager::{GuardWrapper, LockManager},
+    map_table::{MapTable, PageId, PageWriteGuard},
     page_op::flush_dirty_entries,
-    types::{NodeMeta, NodeRef, NodeSize},
+    types::{EvictionClaim, NodeMeta, NodeRef, NodeSize, SlotState},
     wal::WalManager,
     SPIN_RETRIES,
 };
 
+/// ```text
 ///         head     2nd chance        tail
 ///          |          |                |
 ///    +----------------------------------------------------+
 ///    |     [  ][][  ][    ][][  ][][][]                   |
 ///    +----------------------------------------------------+
+/// ```
+///
+/// Mini-pages are allocated at the tail and evicted from the head, the oldest first; a page
+/// referenced since the last pass gets a second chance. Evicting the page at the head moves
+/// the head past it, so the tail can wrap around and reuse the space. A page evicted from
+/// further along goes to the free list of its size instead.
 pub struct MiniPageBuffer {
     buffer: NonNull<u64>,
+    /// owns the allocation `buffer` points into
+    #[allow(dead_code)]
     backing: Box<[u64]>,
     /// number of words in buffer, must be a power of 2
     buff_size: usize,
-    /// u64::MAX represents None
-    free_lists: [AtomicUsize; 7],
+    /// head of each size's freelist, usize::MAX represents None. Each free slot holds the
+    /// next one in the word after its meta
+    free_lists: Mutex<[usize; 7]>,
     /// start of the oldest node not yet fully freed
     head: AtomicUsize,
     /// start of unmanaged memory
     tail: AtomicUsize,
+    /// slots referenced by `PinnedValue`s, these are never evicted or rewritten
+    pins: Mutex<HashMap<usize, SlotPin>>,
+    /// held to move the head or wrap the tail
+    ring: Mutex<Ring>,
+    /// Told of each slot handed out and each page evicted, see `events`
+    events: Events,
+    /// Gets the mini-pages answered, and those that went on to the disk leaf
+    lookup_hits: AtomicU64,
+    lookup_misses: AtomicU64,
+}
+
+struct Ring {
+    /// Where the tail was when it wrapped to the start, the head jumps to the start when it
+    /// gets there. The words before it too few for the allocation were carved into free
+    /// slots, the few past it, less than the smallest slot, hold no page
+    wrapped_at: Option<usize>,
+}
+
+impl Ring {
+    /// Words allocated from `head` to `tail`
+    fn words(&self, head: usize, tail: usize) -> usize {
+        match self.wrapped_at {
+            Some(wrapped_at) => wrapped_at - head + tail,
+            None => tail - head,
+        }
+    }
+
+    /// Start of the page after the `words` long one at `index`
+    fn next(&self, index: usize, words: usize) -> usize {
+        match index + words {
+            next if self.wrapped_at == Some(next) => 0,
+            next => next,
+        }
+    }
+}
+
+// SAFETY: slots are handed out through the locked free lists and head/tail, the map table's
+// page locks arbitrate access to the mini-page in each slot
+unsafe impl Send for MiniPageBuffer {}
+unsafe impl Sync for MiniPageBuffer {}
+
+struct SlotPin {
+    count: usize,
+    /// the page moved to another slot, free this one once the last pin goes
+    retired: bool,
 }
 
 impl MiniPageBuffer {
@@ -47,7 +106,7 @@ impl MiniPageBuffer {
             .checked_shl(cache_size_lg as u32)
             .expect("cache size overflowed usize");
         assert!(
-            total_bytes % 8 == 0,
+            total_bytes.is_multiple_of(8),
             "cache size must be aligned to 64-bit words"
         );
 
@@ -65,27 +124,50 @@ impl MiniPageBuffer {
             buffer,
             backing,
             buff_size,
-            free_lists: array::from_fn(|_| AtomicUsize::new(usize::MAX)),
+            free_lists: Mutex::new([usize::MAX; 7]),
             head: AtomicUsize::new(0),
             tail: AtomicUsize::new(0),
+            pins: Mutex::new(HashMap::new()),
+            ring: Mutex::new(Ring { wrapped_at: None }),
+            events: Events::default(),
+            lookup_hits: AtomicU64::new(0),
+            lookup_misses: AtomicU64::new(0),
         }
     }
 
-    const fn wrap(&self, index: usize) -> usize {
-        index & (self.buff_size - 1)
+    /// Tell `events` of each slot handed out and each page evicted
+    pub(crate) fn with_events(mut self, events: Events) -> MiniPageBuffer {
+        self.events = events;
+        self
     }
 }
 
 impl MiniPageBuffer {
     pub fn alloc(&self, size: NodeSize) -> Option<usize> {
+        let slot = self.alloc_slot(size)?;
+        self.events.emit(|| StructuralEvent::MiniPageAllocated {
+            slot,
+            bytes: size.size_in_bytes(),
+        });
+        Some(slot)
+    }
+
+    fn alloc_slot(&self, size: NodeSize) -> Option<usize> {
         if let Some(page) = self.pop_freelist(size) {
             return Some(page);
         }
+        self.alloc_tail(size).or_else(|| self.split_free(size))
+    }
 
+    /// Take `size` from the tail of the ring, `None` once it has caught up with the head
+    fn alloc_tail(&self, size: NodeSize) -> Option<usize> {
         let req_size = size.size_in_words();
         let mut tail = self.tail.load(Ordering::Acquire);
         for _ in 0..SPIN_RETRIES {
             let head = self.head.load(Ordering::Acquire);
+            if head == tail && self.is_full() {
+                return None;
+            }
 
             match head <= tail {
                 // barrier is end of buffer
@@ -109,18 +191,25 @@ impl MiniPageBuffer {
                             }
                         }
                         false => {
-                            if head > req_size {
-                                match self.tail.compare_exchange_weak(
+                            // the head only moves under the ring lock, so it holds still while
+                            // the wrap is recorded
+                            let mut ring = self.ring.lock().expect("ring lock poisoned");
+                            let head = self.head.load(Ordering::Acquire);
+                            // fails if another allocation moved or wrapped the tail first
+                            if head >= req_size {
+                                match self.tail.compare_exchange(
                                     tail,
                                     0,
                                     Ordering::AcqRel,
                                     Ordering::Acquire,
                                 ) {
-                                    Ok(_) => tail = 0,
-                                    Err(t) => {
-                                        tail = t;
-                                        continue;
+                                    Ok(_) => {
+                                        // SAFETY: the words past the old tail are ours now
+                                        ring.wrapped_at =
+                                            Some(unsafe { self.carve_remainder(tail) });
+                                        tail = 0;
                                     }
+                                    Err(t) => tail = t,
                                 }
                                 continue;
                             } else {
@@ -156,56 +245,184 @@ impl MiniPageBuffer {
         None
     }
 
+    /// Take `size` from the front of the smallest larger free slot, handing the rest back to
+    /// the freelists. Slots only go back on the freelist of their own size, so without this
+    /// a full ring whose head is held can have free words but no slot of the size asked for
+    fn split_free(&self, size: NodeSize) -> Option<usize> {
+        let mut free_lists = self.lock_free_lists();
+        let larger = NodeSize::ALL[size.index() + 1..]
+            .iter()
+            .copied()
+            .find(|larger| free_lists[larger.index()] != usize::MAX)?;
+        let start = free_lists[larger.index()];
+        free_lists[larger.index()] = self.free_link(start).load(Ordering::Relaxed) as usize;
+        // SAFETY: the slot left the freelist, nothing else references its words. The rest
+        // halves down to `size`, each half a slot with its own header
+        unsafe {
+            NodeMeta::init_unused(self.get_meta_ptr(start), size);
+            let mut index = start + size.size_in_words();
+            for rest in &NodeSize::ALL[size.index()..larger.index()] {
+                NodeMeta::init_unused(self.get_meta_ptr(index), *rest);
+                self.free_link(index)
+                    .store(free_lists[rest.index()] as u64, Ordering::Relaxed);
+                free_lists[rest.index()] = index;
+                (*self.get_meta_ptr(index)).set_free_listed(true);
+                index += rest.size_in_words();
+            }
+        }
+        Some(start)
+    }
+
+    /// Hand the words from `start` to the end of the buffer, too few for the allocation that
+    /// wrapped the tail, to the freelists as slots of the largest sizes that fit. They stay
+    /// in the ring, so the head passes them like any other freed slot. Returns where the
+    /// carved slots end, the point the ring wraps at
+    ///
+    /// # Safety
+    /// Nothing else may reference the words from `start` on
+    unsafe fn carve_remainder(&self, start: usize) -> usize {
+        let mut index = start;
+        while let Some(&size) = NodeSize::ALL
+            .iter()
+            .rev()
+            .find(|size| size.size_in_words() <= self.buff_size - index)
+        {
+            NodeMeta::init_unused(self.get_meta_ptr(index), size);
+            self.push_free(index, size);
+            index += size.size_in_words();
+        }
+        index
+    }
+
+    /// Full leaves the buffer has room for
+    pub fn leaf_capacity(&self) -> usize {
+        self.buff_size / NodeSize::LeafPage.size_in_words()
+    }
+
+    /// With the head and the tail together the buffer is either empty or, once the tail
+    /// has wrapped and caught up, full
+    fn is_full(&self) -> bool {
+        let ring = self.ring.lock().expect("ring lock poisoned");
+        ring.wrapped_at.is_some()
+            && self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    fn lock_free_lists(&self) -> MutexGuard<'_, [usize; 7]> {
+        self.free_lists.lock().expect("free lists poisoned")
+    }
+
+    /// The word after the meta of a free slot, holding the next slot of its freelist
+    fn free_link(&self, index: usize) -> &AtomicU64 {
+        // SAFETY: a free slot is at least a header long and nothing else writes it
+        unsafe { &*(self.buffer.add(index + 1).as_ptr() as *const AtomicU64) }
+    }
+
     fn pop_freelist(&self, size: NodeSize) -> Option<usize> {
-        let free_list_head = &self.free_lists[size.index()];
-        let mut head_index = free_list_head.load(Ordering::Acquire);
-        for _ in 0..SPIN_RETRIES {
-            // No items in free list
-            if head_index == usize::MAX {
-                return None;
+        let mut free_lists = self.lock_free_lists();
+        let head_index = free_lists[size.index()];
+        // No items in free list
+        if head_index == usize::MAX {
+            return None;
+        }
+        free_lists[size.index()] = self.free_link(head_index).load(Ordering::Relaxed) as usize;
+        // SAFETY: the slot left the freelist, nothing else references it
+        unsafe { (*self.get_meta_ptr(head_index)).set_free_listed(false) };
+        Some(head_index)
+    }
+
+    /// Take the slot at `index` off the freelist of `size`, false if it is not on it. The
+    /// list decides, not the header: a slot just taken from the tail has no header yet
+    fn unlink_free(&self, index: usize, size: NodeSize) -> bool {
+        let mut free_lists = self.lock_free_lists();
+        let next = self.free_link(index).load(Ordering::Relaxed) as usize;
+        let mut cursor = free_lists[size.index()];
+        if cursor == index {
+            free_lists[size.index()] = next;
+        } else {
+            loop {
+                if cursor == usize::MAX {
+                    return false;
+                }
+                let link = self.free_link(cursor);
+                if link.load(Ordering::Relaxed) as usize == index {
+                    link.store(next as u64, Ordering::Relaxed);
+                    break;
+                }
+                cursor = link.load(Ordering::Relaxed) as usize;
             }
+        }
+        // SAFETY: the slot left the freelist, nothing else references it
+        unsafe { (*self.get_meta_ptr(index)).set_free_listed(false) };
+        true
+    }
 
-            // next pointer should be stored in the word after the meta
-            // let next = self.buffer[head_index + 1];
-            let next = unsafe { &*(self.buffer.add(head_index + 1).as_ptr() as *const AtomicU64) }
-                .load(Ordering::Relaxed);
-
-            match free_list_head.compare_exchange_weak(
-                head_index,
-                next as usize,
-                Ordering::AcqRel,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => return Some(head_index as usize),
-                Err(h) => head_index = h,
+    /// Move the head past the `words` long page at `head`, returns the new head
+    fn advance_head(&self, ring: &mut Ring, head: usize, words: usize) -> usize {
+        let mut next = head + words;
+        if ring.wrapped_at == Some(next) {
+            next = 0;
+            ring.wrapped_at = None;
+        }
+        self.head.store(next, Ordering::Release);
+        debug::record_head_advance();
+        next
+    }
+
+    /// Move the head past free-listed slots, which would otherwise hold it back until they
+    /// are handed out again. Returns the new head
+    fn skip_free_at_head(&self, ring: &mut Ring, mut head: usize) -> usize {
+        while ring.words(head, self.tail.load(Ordering::Acquire)) > 0 {
+            // SAFETY: every page from the head to the tail has a header
+            let meta = unsafe { &*self.get_meta_ptr(head) };
+            if meta.state() != SlotState::FreeListed {
+                break;
+            }
+            let size = meta.size();
+            if !self.unlink_free(head, size) {
+                break;
             }
-            std::hint::spin_loop();
+            head = self.advance_head(ring, head, size.size_in_words());
         }
-        None
+        head
     }
 
-    pub fn evict(
+    /// Evict the next cold mini-page at the head of the buffer. Pages locked by other
+    /// transactions are skipped; pages held by `owner` (the calling transaction) can be
+    /// evicted through its own guards as long as they are not currently in use.
+    pub fn evict<'a>(
         &self,
-        map_table: &MapTable,
+        map_table: &'a MapTable,
         io_engine: &IoEngine,
         wal: &WalManager,
+        mut owner: Option<&mut LockManager<'a>>,
     ) -> Result<(), QSError> {
-        // scan through items in the last chance zone
-        // for each:
-        // de mark ref bit,
-
-        // TODO: deal with race condition where I read the head pointer, but someone else advances the head pointer and allocates a different node
-        let mut eviction_cand = self.head.load(Ordering::Relaxed);
+        // one eviction at a time: the scan walks the headers from the head to the tail, which
+        // only holds while nobody else moves the head
+        let (mut ring, head) = self.lock_ring();
+        let skipped = self.skip_free_at_head(&mut ring, head);
+        if skipped != head {
+            // the words before the new head are free for the tail
+            return Ok(());
+        }
+        let tail = self.tail.load(Ordering::Acquire);
+        let ring_words = ring.words(head, tail);
+        // the page after `index`, back at the head once the scan reaches the tail
+        let step = |index: usize, words: usize| match ring.next(index, words) {
+            next if next == tail => head,
+            next => next,
+        };
 
+        let mut eviction_cand = head;
         let mut scanned = 0usize;
 
-        while scanned < self.buff_size {
+        // two passes: the first may only clear hot bits
+        while scanned < 2 * ring_words {
             let meta_ptr = unsafe { self.get_meta_ptr(eviction_cand) };
             let meta = unsafe { &mut *meta_ptr };
             let chunk_words = meta.size().size_in_words();
 
             if !meta.is_live() {
-                eviction_cand = self.wrap(eviction_cand + chunk_words);
+                eviction_cand = step(eviction_cand, chunk_words);
                 scanned += chunk_words;
                 continue;
             }
@@ -213,53 +430,70 @@ impl MiniPageBuffer {
             if meta.is_hot() {
                 meta.clear_hot();
                 debug::record_second_chance();
-                eviction_cand = self.wrap(eviction_cand + chunk_words);
+                eviction_cand = step(eviction_cand, chunk_words);
+                scanned += chunk_words;
+                continue;
+            }
+
+            if self.is_pinned(eviction_cand) {
+                eviction_cand = step(eviction_cand, chunk_words);
                 scanned += chunk_words;
                 continue;
             }
 
             let page_id = meta.page_id();
-            let mut guard = match map_table.write_page_entry(page_id) {
-                Ok(g) => g,
-                Err(_) => {
-                    eviction_cand = self.wrap(eviction_cand + chunk_words);
-                    scanned += chunk_words;
-                    continue;
+            let mut local_guard;
+            let guard: &mut PageWriteGuard<'a> = match map_table.try_write_page_entry(page_id) {
+                Ok(g) => {
+                    local_guard = g;
+                    &mut local_guard
                 }
+                Err(_) => match owner
+                    .as_deref_mut()
+                    .and_then(|locks| locks.owned_write_guard(page_id))
+                {
+                    Some(page_guard) => {
+                        // the cached disk copy goes stale once the page is flushed
+                        page_guard.leaf = None;
+                        match &mut page_guard.guard_inner {
+                            GuardWrapper::Write(g) => g,
+                            GuardWrapper::Read(_) => unreachable!("owned guard is a write guard"),
+                        }
+                    }
+                    None => {
+                        eviction_cand = step(eviction_cand, chunk_words);
+                        scanned += chunk_words;
+                        continue;
+                    }
+                },
             };
 
             let mini_page_index = match guard.node() {
                 NodeRef::MiniPage(idx) => idx,
                 NodeRef::Leaf(_) => {
-                    eviction_cand = self.wrap(eviction_cand + chunk_words);
+                    eviction_cand = step(eviction_cand, chunk_words);
                     scanned += chunk_words;
                     continue;
                 }
             };
 
             if mini_page_index.index != eviction_cand {
-                eviction_cand = mini_page_index.index;
+                // stale header: the page now lives in another slot
+                eviction_cand = step(eviction_cand, chunk_words);
+                scanned += chunk_words;
                 continue;
             }
 
-            if meta.mark_for_eviction().is_err() {
-                eviction_cand = self.wrap(eviction_cand + chunk_words);
+            let Some(claim) = meta.mark_for_eviction() else {
+                eviction_cand = step(eviction_cand, chunk_words);
                 scanned += chunk_words;
                 continue;
-            }
-
-            flush_dirty_entries(meta, io_engine);
-
-            let disk_addr = meta.leaf();
-            wal.checkpoint_page(page_id)
-                .expect("failed to checkpoint WAL during eviction");
-            guard.set_leaf(disk_addr);
-            meta.set_live(false);
-            meta.clear_eviction();
-            meta.set_record_count(0);
+            };
 
-            let next_head = self.wrap(eviction_cand + chunk_words);
-            self.head.store(next_head, Ordering::Release);
+            // SAFETY: we hold the page's write lock
+            unsafe {
+                self.evict_claimed(&mut ring, head, eviction_cand, claim, guard, io_engine, wal)?;
+            }
             debug::record_eviction();
             return Ok(());
         }
@@ -267,48 +501,264 @@ impl MiniPageBuffer {
         Err(QSError::CacheExhausted)
     }
 
-    /// Deallocate a mini-page, this mini-page must be unused, ie. not appear in the mapping table
+    /// Flush the mini-page of `page_id` and give its slot back now, rather than waiting for
+    /// it to reach the head. False if the page is not cached, is locked or has pinned values
+    pub fn demote(
+        &self,
+        map_table: &MapTable,
+        io_engine: &IoEngine,
+        wal: &WalManager,
+        page_id: PageId,
+    ) -> Result<bool, QSError> {
+        let (mut ring, head) = self.lock_ring();
+        let Ok(mut guard) = map_table.try_write_page_entry(page_id) else {
+            return Ok(false);
+        };
+        let index = match guard.node() {
+            NodeRef::MiniPage(idx) => idx.index,
+            NodeRef::Leaf(_) => return Ok(false),
+        };
+        if self.is_pinned(index) {
+            return Ok(false);
+        }
+        // SAFETY: we hold the page's write lock
+        let Some(claim) = (unsafe { (*self.get_meta_ptr(index)).mark_for_eviction() }) else {
+            return Ok(false);
+        };
+        // SAFETY: as above
+        unsafe { self.evict_claimed(&mut ring, head, index, claim, &mut guard, io_engine, wal)? };
+        Ok(true)
+    }
+
+    /// Live mini-pages between the head and the tail
+    pub fn live_pages(&self) -> usize {
+        let (ring, head) = self.lock_ring();
+        let tail = self.tail.load(Ordering::Acquire);
+        let ring_words = ring.words(head, tail);
+        let mut index = head;
+        let mut scanned = 0;
+        let mut live = 0;
+        while scanned < ring_words {
+            // SAFETY: every page from the head to the tail has a header
+            let meta = unsafe { &*self.get_meta_ptr(index) };
+            let words = meta.size().size_in_words();
+            live += meta.is_live() as usize;
+            scanned += words;
+            index = ring.next(index, words);
+        }
+        live
+    }
+
+    /// Slots between the head and the tail waiting on a freelist
+    pub fn free_listed_slots(&self) -> usize {
+        let (ring, head) = self.lock_ring();
+        let tail = self.tail.load(Ordering::Acquire);
+        let ring_words = ring.words(head, tail);
+        let mut index = head;
+        let mut scanned = 0;
+        let mut free = 0;
+        while scanned < ring_words {
+            // SAFETY: every page from the head to the tail has a header
+            let meta = unsafe { &*self.get_meta_ptr(index) };
+            let words = meta.size().size_in_words();
+            free += meta.is_free_listed() as usize;
+            scanned += words;
+            index = ring.next(index, words);
+        }
+        free
+    }
+
+    /// The ring lock with the head, moved back to the start if the tail wrapped behind it
+    fn lock_ring(&self) -> (MutexGuard<'_, Ring>, usize) {
+        let mut ring = self.ring.lock().expect("ring lock poisoned");
+        let mut head = self.head.load(Ordering::Acquire);
+        if ring.wrapped_at == Some(head) {
+            head = 0;
+            ring.wrapped_at = None;
+            self.head.store(head, Ordering::Release);
+        }
+        (ring, head)
+    }
+
+    /// Write the mini-page at `index` back to disk, point its page at the disk leaf and free
+    /// the slot: past the head if it is the oldest page, to its freelist otherwise
+    ///
+    /// # Safety
+    /// `guard` must be the write lock of the page at `index`, which `claim` was taken on
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn evict_claimed(
+        &self,
+        ring: &mut Ring,
+        head: usize,
+        index: usize,
+        claim: EvictionClaim,
+        guard: &mut PageWriteGuard<'_>,
+        io_engine: &IoEngine,
+        wal: &WalManager,
+    ) -> Result<(), QSError> {
+        let meta = &mut *self.get_meta_ptr(index);
+        wal.sync().expect("failed to sync WAL during eviction");
+        if let Err(err) = flush_dirty_entries(meta, io_engine, wal.durable_lsn()) {
+            // the disk leaf the entries merge into is unreadable, keep the page cached
+            meta.abort_eviction(claim);
+            return Err(err);
+        }
+
+        let disk_addr = meta.leaf();
+        wal.checkpoint_page(guard.page)
+            .expect("failed to checkpoint WAL during eviction");
+        guard.set_leaf(disk_addr);
+        meta.finish_eviction(claim);
+        meta.clear_hot();
+        self.events.emit(|| StructuralEvent::MiniPageEvicted {
+            page: guard.page.as_u64(),
+            slot: index,
+        });
+
+        if index == head {
+            // the oldest page is gone, the tail may now reuse its words
+            let chunk_words = meta.size().size_in_words();
+            let next = self.advance_head(ring, head, chunk_words);
+            self.skip_free_at_head(ring, next);
+        } else {
+            // further along the head can't move past the live pages before it; the slot goes
+            // to its freelist, never behind the head where it could be handed out twice
+            self.dealloc(MiniPageIndex::new(index));
+        }
+        Ok(())
+    }
+
+    /// Deallocate a mini-page
+    ///
+    /// # Safety
+    /// The mini-page must be unused, ie. not appear in the mapping table
     pub unsafe fn dealloc(&self, node: MiniPageIndex) {
         let (size, slot) = {
             let meta = self.get_meta_mut(node);
+            // an evicting page belongs to its evictor, a free-listed one was freed already
+            if let Err(state) = meta.transition(SlotState::Live, SlotState::Unused) {
+                assert_eq!(
+                    state,
+                    SlotState::Unused,
+                    "mini-page deallocated while {state:?}"
+                );
+            }
             let node_size = meta.size();
-            meta.set_live(false);
-            meta.clear_eviction();
             meta.clear_hot();
             meta.set_record_count(0);
             (node_size, node.index)
         };
 
-        let free_head = &self.free_lists[size.index()];
-        let next_cell = &*(self.buffer.as_ptr().add(slot + 1) as *const AtomicU64);
-        let mut head = free_head.load(Ordering::Acquire);
-        loop {
-            next_cell.store(head as u64, Ordering::Release);
-            match free_head.compare_exchange_weak(
-                head,
-                slot,
-                Ordering::AcqRel,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => break,
-                Err(actual) => {
-                    head = actual;
-                }
+        self.push_free(slot, size);
+    }
+
+    /// Put the free slot at `index` on the freelist of `size`
+    fn push_free(&self, index: usize, size: NodeSize) {
+        let mut free_lists = self.lock_free_lists();
+        self.free_link(index)
+            .store(free_lists[size.index()] as u64, Ordering::Relaxed);
+        free_lists[size.index()] = index;
+        // set under the lock, so a slot with the bit is always on its list
+        // SAFETY: the slot is free, nothing else references its header
+        unsafe { (*self.get_meta_ptr(index)).set_free_listed(true) };
+    }
+
+    /// Keep the mini-page at `index` in place until a matching `unpin`
+    pub(crate) fn pin(&self, index: usize) {
+        let mut pins = self.pins.lock().expect("pin table poisoned");
+        pins.entry(index)
+            .or_insert(SlotPin {
+                count: 0,
+                retired: false,
+            })
+            .count += 1;
+    }
+
+    /// Release a pin, freeing the slot if it was retired and this was the last pin
+    pub(crate) fn unpin(&self, index: usize) {
+        let mut pins = self.pins.lock().expect("pin table poisoned");
+        let pin = pins
+            .get_mut(&index)
+            .expect("unpin of a slot that is not pinned");
+        pin.count -= 1;
+        if pin.count == 0 {
+            let retired = pin.retired;
+            pins.remove(&index);
+            if retired {
+                // SAFETY: a retired slot has been replaced in the mapping table
+                unsafe { self.dealloc(MiniPageIndex::new(index)) };
             }
         }
     }
 
+    pub fn is_pinned(&self, index: usize) -> bool {
+        self.pins
+            .lock()
+            .expect("pin table poisoned")
+            .contains_key(&index)
+    }
+
+    /// Count a get the mini-page of its leaf answered, or one it did not
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        let counter = match hit {
+            true => &self.lookup_hits,
+            false => &self.lookup_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Gets answered from a mini-page and gets that read the disk leaf, since open
+    pub fn lookups(&self) -> (u64, u64) {
+        (
+            self.lookup_hits.load(Ordering::Relaxed),
+            self.lookup_misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Number of mini-page slots currently pinned
+    pub fn pinned_slots(&self) -> usize {
+        self.pins.lock().expect("pin table poisoned").len()
+    }
+
+    /// Give up a slot whose page was copied elsewhere: it is freed now, or when its last
+    /// pin is released
+    ///
+    /// # Safety
+    /// The slot must no longer appear in the mapping table
+    pub(crate) unsafe fn retire(&self, node: MiniPageIndex) {
+        let mut pins = self.pins.lock().expect("pin table poisoned");
+        match pins.get_mut(&node.index) {
+            Some(pin) => pin.retired = true,
+            None => {
+                drop(pins);
+                self.dealloc(node);
+            }
+        }
+    }
+
+    /// Whether `ptr` points into the mini-page starting at `index`
+    pub(crate) fn slot_contains(&self, index: usize, ptr: *const u8) -> bool {
+        // SAFETY: index is the start of a mini-page, we only read its header
+        let size = unsafe { (*self.get_meta_ptr(index)).size().size_in_bytes() };
+        let start = unsafe { self.get_meta_ptr(index) } as *const u8;
+        ptr >= start && ptr < start.wrapping_add(size)
+    }
+
+    /// # Safety
+    /// `index` must be the start of a mini-page inside the buffer
     pub unsafe fn get_meta_ptr(&self, index: usize) -> *mut NodeMeta {
         unsafe { self.buffer.add(index).as_ptr() as *mut NodeMeta }
     }
 
-    /// SAFETY: caller must guarentee that a mutable reference does not exist eg. hold a lock
+    /// # Safety
+    /// Caller must guarentee that a mutable reference does not exist eg. hold a lock
     pub unsafe fn get_meta_ref<'g>(&self, node: MiniPageIndex<'g>) -> &'g NodeMeta {
         // SAFETY: MiniPageIndex was created as an index to the metadata of a valid NodeMeta
         unsafe { &*self.get_meta_ptr(node.index) }
     }
 
-    /// SAFETY: caller must guarentee that no other references exist
+    /// # Safety
+    /// Caller must guarentee that no other references exist
     pub unsafe fn get_meta_mut<'g>(&self, node: MiniPageIndex<'g>) -> &'g mut NodeMeta {
         // SAFETY: MiniPageIndex was created as an index to the metadata of a valid NodeMeta
         unsafe { &mut *self.get_meta_ptr(node.index) }
@@ -0,0 +1,139 @@
+//! Choosing which leaves a global checkpoint flushes.
+
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{map_table::PageId, wal::WalPageSummary};
+
+/// Shortest wait between the checkpoint thread's looks at the WAL
+pub const MIN_CHECKPOINT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How a global checkpoint ranks the leaves with records in the WAL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CheckpointPolicy {
+    /// Most WAL bytes first
+    #[default]
+    Largest,
+    /// Oldest first record first, so no leaf pins the head of the log for long
+    Oldest,
+    /// Cycle through the leaves by page id, picking up after the last one flushed
+    RoundRobin,
+    /// Highest share of dirty records in the mini-page first, so a flush rewrites the leaf
+    /// for as many changes as possible
+    DirtyRatio,
+}
+
+/// What `QuickStep::checkpoint` did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CheckpointStats {
+    /// Leaves written back and dropped from the WAL
+    pub pages_flushed: usize,
+    /// Leaves with WAL records a transaction held past the lock-wait policy, left as they were
+    pub pages_skipped: usize,
+    /// How much smaller the WAL is
+    pub wal_bytes_reclaimed: u64,
+}
+
+/// When leaves are checkpointed: the WAL thresholds writes and the checkpoint thread check,
+/// and how often the thread checks them. Shared with the thread, so changes apply to a
+/// running store at its next check
+#[derive(Debug)]
+pub(crate) struct CheckpointTriggers {
+    leaf_records: AtomicUsize,
+    global_records: AtomicUsize,
+    global_bytes: AtomicUsize,
+    interval_nanos: AtomicU64,
+}
+
+impl CheckpointTriggers {
+    pub(crate) fn new(thresholds: (usize, usize, usize), interval: Duration) -> CheckpointTriggers {
+        let triggers = CheckpointTriggers {
+            leaf_records: AtomicUsize::new(0),
+            global_records: AtomicUsize::new(0),
+            global_bytes: AtomicUsize::new(0),
+            interval_nanos: AtomicU64::new(0),
+        };
+        triggers.set_thresholds(thresholds.0, thresholds.1, thresholds.2);
+        triggers.set_interval(interval);
+        triggers
+    }
+
+    pub(crate) fn set_thresholds(&self, leaf_records: usize, global_records: usize, bytes: usize) {
+        self.leaf_records.store(leaf_records, Ordering::Relaxed);
+        self.global_records.store(global_records, Ordering::Relaxed);
+        self.global_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Records of one leaf, records of the whole log and bytes of the whole log
+    pub(crate) fn thresholds(&self) -> (usize, usize, usize) {
+        (
+            self.leaf_records.load(Ordering::Relaxed),
+            self.global_records.load(Ordering::Relaxed),
+            self.global_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    pub(crate) fn leaf_records(&self) -> usize {
+        self.leaf_records.load(Ordering::Relaxed)
+    }
+
+    /// Clamped to `MIN_CHECKPOINT_INTERVAL`, so the thread never spins
+    pub(crate) fn set_interval(&self, interval: Duration) {
+        let nanos = interval.max(MIN_CHECKPOINT_INTERVAL).as_nanos();
+        self.interval_nanos
+            .store(nanos.min(u64::MAX as u128) as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        Duration::from_nanos(self.interval_nanos.load(Ordering::Relaxed))
+    }
+}
+
+/// A leaf eligible for checkpointing and what the policies rank it by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckpointCandidate {
+    pub summary: WalPageSummary,
+    /// Share of dirty records in the leaf's mini-page, 0.0 when unknown
+    pub dirty_ratio: f64,
+}
+
+impl CheckpointPolicy {
+    /// Up to `batch` pages to checkpoint, best first. `last` is the page the round-robin
+    /// cursor stopped at.
+    pub fn select(
+        self,
+        mut candidates: Vec<CheckpointCandidate>,
+        last: Option<PageId>,
+        batch: usize,
+    ) -> Vec<PageId> {
+        match self {
+            CheckpointPolicy::Largest => {
+                candidates.sort_by_key(|c| std::cmp::Reverse(c.summary.bytes));
+            }
+            CheckpointPolicy::Oldest => {
+                candidates.sort_by_key(|c| c.summary.first_record);
+            }
+            CheckpointPolicy::RoundRobin => {
+                candidates.sort_by_key(|c| c.summary.page_id.0);
+                if let Some(last) = last {
+                    let start = candidates.partition_point(|c| c.summary.page_id.0 <= last.0);
+                    candidates.rotate_left(start);
+                }
+            }
+            CheckpointPolicy::DirtyRatio => {
+                candidates.sort_by(|a, b| {
+                    b.dirty_ratio
+                        .total_cmp(&a.dirty_ratio)
+                        .then(b.summary.bytes.cmp(&a.summary.bytes))
+                });
+            }
+        }
+        candidates
+            .into_iter()
+            .take(batch)
+            .map(|c| c.summary.page_id)
+            .collect()
+    }
+}
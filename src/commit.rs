@@ -0,0 +1,43 @@
+//! Commits acknowledged before they are durable.
+//!
+//! `QuickStepTx::commit_async` logs the commit marker and releases the transaction's locks
+//! as `commit` does, but returns before the marker is synced, whatever the sync policy. The
+//! `CommitHandle` it returns waits for the sync covering the marker; handles waited on
+//! together share one sync, as concurrent commits do. A caller can reply to other requests
+//! meanwhile, and a crash before the sync loses the commit. The handle can be sent to
+//! another thread to wait there.
+
+use std::sync::Arc;
+
+use crate::{error::QSError, wal::WalManager};
+
+/// A commit whose marker is logged, maybe not yet synced
+#[must_use = "a commit is only known to be durable once its handle is waited on"]
+pub struct CommitHandle {
+    wal: Arc<WalManager>,
+    /// Sequence number of the commit marker, 0 if the transaction had nothing to commit
+    lsn: u64,
+}
+
+impl CommitHandle {
+    pub(crate) fn new(wal: Arc<WalManager>, lsn: u64) -> CommitHandle {
+        CommitHandle { wal, lsn }
+    }
+
+    /// Sequence number of the commit marker, 0 for a transaction already rolled back, as
+    /// past its timeout
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    /// Whether the commit marker is synced already
+    pub fn is_durable(&self) -> bool {
+        self.wal.durable_lsn() >= self.lsn
+    }
+
+    /// Wait until the commit marker is synced, leading the sync if none is running
+    pub fn wait(self) -> Result<(), QSError> {
+        self.wal.wait_durable(self.lsn)?;
+        Ok(())
+    }
+}
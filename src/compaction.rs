@@ -0,0 +1,154 @@
+//! Online compaction of the data file.
+//!
+//! Merges give disk pages back to the free list, but the file keeps its peak size: a freed
+//! page in the middle is only reused by a later split, and one at the end is never given
+//! back. A compaction pass moves the leaves stored furthest into the file to the lowest
+//! free pages, logging each move as a structure record so recovery finds the leaf at its
+//! new page, then truncates the run of free pages the moves left at the end of the file.
+//!
+//! A move copies the disk leaf under the page's write lock; a cached mini-page keeps its
+//! records and is pointed at the new page. Leaves locked by a transaction are skipped. With
+//! auto compaction configured, a background thread runs a pass of up to a batch of moves
+//! whenever enough pages are free, so no write waits on one. Hash-index stores and
+//! compressed files are not compacted.
+
+use std::{cmp::Reverse, time::Duration};
+
+use crate::{
+    error::QSError, events::StructuralEvent, iter::LOWER_SENTINEL, map_table::PageId,
+    types::NodeRef, wal::WalSmo, Store,
+};
+
+/// How often to look for free pages and how many make a pass worth running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoCompaction {
+    pub interval: Duration,
+    /// Free pages the data file must hold before a pass is run, at least 1
+    pub min_free_pages: u64,
+}
+
+impl AutoCompaction {
+    pub fn new(interval: Duration, min_free_pages: u64) -> AutoCompaction {
+        AutoCompaction {
+            interval,
+            min_free_pages: min_free_pages.max(1),
+        }
+    }
+}
+
+/// What a compaction pass did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Leaves moved to a lower page
+    pub pages_moved: u64,
+    /// Free pages cut off the end of the file
+    pub pages_trimmed: u64,
+    /// Bytes the data file shrank by
+    pub bytes_reclaimed: u64,
+}
+
+/// Move up to `max_moves` leaves, furthest into the file first, to lower free pages, then
+/// truncate the free pages left at the end of the file
+pub(crate) fn run(db: &Store, max_moves: usize) -> Result<CompactionStats, QSError> {
+    let mut stats = CompactionStats::default();
+    if db.hash_buckets.is_some() || db.io_engine.is_compressed() {
+        return Ok(stats);
+    }
+
+    let mut leaves = leaves_by_addr(db)?;
+    leaves.sort_by_key(|(_, _, addr)| Reverse(*addr));
+    for (page, lower, addr) in leaves {
+        if stats.pages_moved as usize >= max_moves {
+            break;
+        }
+        match relocate_leaf(db, page, &lower, addr)? {
+            Relocation::Moved => stats.pages_moved += 1,
+            Relocation::Skipped => {}
+            Relocation::NoFreePage => break,
+        }
+    }
+
+    let pages_before = db.io_engine.pages_used();
+    stats.bytes_reclaimed = db.io_engine.trim_free_tail();
+    stats.pages_trimmed = pages_before - db.io_engine.pages_used();
+    Ok(stats)
+}
+
+enum Relocation {
+    Moved,
+    /// The leaf is locked, gone, or moved since it was listed
+    Skipped,
+    /// No free page sits below the leaf, nor below any leaf listed after it
+    NoFreePage,
+}
+
+/// Every leaf with its lower fence and disk page, in key order. Leaves that can't be read
+/// without waiting are left out
+fn leaves_by_addr(db: &Store) -> Result<Vec<(PageId, Vec<u8>, u64)>, QSError> {
+    let mut leaves = Vec::new();
+    let mut lower = LOWER_SENTINEL.to_vec();
+    loop {
+        let (page, successor) = db.inner_nodes.read_leaf_and_successor(&lower)?;
+        if let Ok(guard) = db.map_table.try_read_page_entry(page) {
+            let addr = match guard.node() {
+                NodeRef::Leaf(addr) => addr,
+                // SAFETY: we hold a read lock on the page
+                NodeRef::MiniPage(index) => unsafe { db.cache.get_meta_ref(index) }.leaf(),
+            };
+            leaves.push((page, lower.clone(), addr));
+        }
+        match successor {
+            Some(pivot) => lower = pivot.into_vec(),
+            None => return Ok(leaves),
+        }
+    }
+}
+
+/// Move the leaf of `page` from `addr` to the lowest free page below it
+fn relocate_leaf(db: &Store, page: PageId, lower: &[u8], addr: u64) -> Result<Relocation, QSError> {
+    if db.lock_owners.owner(page).is_some() {
+        return Ok(Relocation::Skipped);
+    }
+    let Ok(mut guard) = db.map_table.try_write_page_entry(page) else {
+        return Ok(Relocation::Skipped);
+    };
+    // a merge may have removed the leaf, or another pass moved it, since it was listed
+    let current = match guard.node() {
+        NodeRef::Leaf(addr) => addr,
+        // SAFETY: we hold the page's write lock
+        NodeRef::MiniPage(index) => unsafe { db.cache.get_meta_ref(index) }.leaf(),
+    };
+    if current != addr || db.inner_nodes.read_traverse_leaf(lower)?.page != page {
+        return Ok(Relocation::Skipped);
+    }
+    let Some(to) = db.io_engine.take_free_page_below(addr) else {
+        return Ok(Relocation::NoFreePage);
+    };
+
+    db.io_engine.copy_page(page, addr, to)?;
+    db.wal
+        .append_smo(
+            WalSmo::Relocate {
+                page: page.as_u64(),
+                addr: to,
+            },
+            &[],
+        )
+        .expect("failed to log leaf relocation");
+    db.events.emit(|| StructuralEvent::LeafRelocated {
+        page: page.as_u64(),
+        from: addr,
+        to,
+    });
+    match guard.node() {
+        NodeRef::Leaf(_) => guard.set_leaf(to),
+        NodeRef::MiniPage(index) => {
+            // SAFETY: we hold the page's write lock
+            let meta = unsafe { db.cache.get_meta_mut(index) };
+            meta.set_disk_addr(to);
+        }
+    }
+    drop(guard);
+    db.io_engine.free_page(addr);
+    Ok(Relocation::Moved)
+}
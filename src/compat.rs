@@ -0,0 +1,250 @@
+//! A sled-shaped API over quickstep, to ease migrating code written against sled.
+//!
+//! Trees are keyspaces of the one store: each tree's keys are stored behind a prefix of the
+//! tree name's length and bytes, so trees never see each other's keys. Every single-key call
+//! runs in a transaction of its own and is durable when it returns; `flush` checkpoints the
+//! WAL into the data file. `Db` dereferences to its default tree, as in sled.
+
+use std::{ops::Deref, path::Path, rc::Rc};
+
+use crate::{error::QSError, iter::RangeIter, QuickStep, QuickStepConfig, QuickStepTx};
+
+const DEFAULT_TREE: &[u8] = b"__sled__default";
+/// Tree holding the names of the trees opened so far
+const REGISTRY_TREE: &[u8] = b"__quickstep__trees";
+
+const DEFAULT_INNER_NODES: u32 = 1 << 10;
+const DEFAULT_LEAVES: u64 = 1 << 16;
+const DEFAULT_CACHE_SIZE_LG: usize = 24;
+
+/// Open a store at `path` with sizes suited to a small embedded database
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Db, QSError> {
+    Db::open_with(QuickStepConfig::new(
+        path.as_ref(),
+        DEFAULT_INNER_NODES,
+        DEFAULT_LEAVES,
+        DEFAULT_CACHE_SIZE_LG,
+    ))
+}
+
+pub struct Db {
+    inner: Rc<QuickStep>,
+    default: Tree,
+}
+
+impl Db {
+    pub fn open_with(config: QuickStepConfig) -> Result<Db, QSError> {
+        let inner = Rc::new(QuickStep::new(config));
+        let default = Tree::new(&inner, DEFAULT_TREE);
+        Ok(Db { inner, default })
+    }
+
+    /// The tree called `name`, created empty on first use
+    pub fn open_tree<N: AsRef<[u8]>>(&self, name: N) -> Result<Tree, QSError> {
+        let name = name.as_ref();
+        let registry = Tree::new(&self.inner, REGISTRY_TREE);
+        if name != DEFAULT_TREE && !registry.contains_key(name)? {
+            registry.insert(name, b"")?;
+        }
+        Ok(Tree::new(&self.inner, name))
+    }
+
+    /// Delete every key of the tree called `name`, returns whether it existed
+    pub fn drop_tree<N: AsRef<[u8]>>(&self, name: N) -> Result<bool, QSError> {
+        let name = name.as_ref();
+        let registry = Tree::new(&self.inner, REGISTRY_TREE);
+        let existed = name == DEFAULT_TREE || registry.contains_key(name)?;
+        Tree::new(&self.inner, name).clear()?;
+        registry.remove(name)?;
+        Ok(existed)
+    }
+
+    /// Names of the default tree and every tree opened and not dropped since
+    pub fn tree_names(&self) -> Result<Vec<Vec<u8>>, QSError> {
+        let mut names = vec![DEFAULT_TREE.to_vec()];
+        for entry in Tree::new(&self.inner, REGISTRY_TREE).iter() {
+            names.push(entry?.0);
+        }
+        Ok(names)
+    }
+
+    /// The underlying store, for what the sled shape does not cover
+    pub fn store(&self) -> &QuickStep {
+        &self.inner
+    }
+}
+
+impl Deref for Db {
+    type Target = Tree;
+
+    fn deref(&self) -> &Tree {
+        &self.default
+    }
+}
+
+/// A keyspace of the store; clones share it
+#[derive(Clone)]
+pub struct Tree {
+    db: Rc<QuickStep>,
+    /// `| name len: u16 BE | name |` in front of every key of the tree
+    prefix: Vec<u8>,
+}
+
+impl Tree {
+    fn new(db: &Rc<QuickStep>, name: &[u8]) -> Tree {
+        let len = u16::try_from(name.len()).expect("tree name too long");
+        let mut prefix = Vec::with_capacity(2 + name.len());
+        prefix.extend_from_slice(&len.to_be_bytes());
+        prefix.extend_from_slice(name);
+        Tree {
+            db: Rc::clone(db),
+            prefix,
+        }
+    }
+
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        [self.prefix.as_slice(), key].concat()
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.prefix[2..]
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, QSError> {
+        self.db.get(&self.key(key.as_ref()))
+    }
+
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> Result<bool, QSError> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Set `key` to `value`, returns the value it replaced
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, QSError>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.transaction(|tx| tx.insert(key.as_ref(), value.as_ref()))
+    }
+
+    /// Delete `key`, returns the value it held
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, QSError> {
+        self.transaction(|tx| tx.remove(key.as_ref()))
+    }
+
+    /// Run `f` in one transaction, committed if it returns `Ok` and aborted otherwise
+    pub fn transaction<T, F>(&self, f: F) -> Result<T, QSError>
+    where
+        F: FnOnce(&mut TransactionalTree<'_, '_>) -> Result<T, QSError>,
+    {
+        let mut tx = TransactionalTree {
+            tree: self,
+            tx: self.db.tx(),
+        };
+        let result = f(&mut tx)?;
+        tx.tx.commit();
+        Ok(result)
+    }
+
+    /// Key and value pairs of the tree in key order
+    pub fn iter(&self) -> Iter<'_> {
+        let upper = prefix_successor(&self.prefix);
+        Iter {
+            inner: self.db.range_iter(&self.prefix, &upper),
+            prefix_len: self.prefix.len(),
+        }
+    }
+
+    /// Pairs whose key starts with `prefix`
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Iter<'_> {
+        let lower = self.key(prefix.as_ref());
+        let upper = prefix_successor(&lower);
+        Iter {
+            inner: self.db.range_iter(&lower, &upper),
+            prefix_len: self.prefix.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().filter(Result::is_ok).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Delete every key of the tree
+    pub fn clear(&self) -> Result<(), QSError> {
+        let keys = self
+            .iter()
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.transaction(|tx| {
+            for key in &keys {
+                tx.remove(key)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Checkpoint the WAL into the data file, returns the WAL bytes freed. Writes are
+    /// already durable once they return, this only shortens recovery
+    pub fn flush(&self) -> Result<usize, QSError> {
+        self.db.checkpoint_leaves()
+    }
+}
+
+/// A tree as seen from inside `Tree::transaction`
+pub struct TransactionalTree<'t, 'db> {
+    tree: &'t Tree,
+    tx: QuickStepTx<'db>,
+}
+
+impl TransactionalTree<'_, '_> {
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, QSError> {
+        let key = self.tree.key(key);
+        Ok(self.tx.get(&key)?.map(<[u8]>::to_vec))
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, QSError> {
+        let key = self.tree.key(key);
+        let previous = self.tx.get(&key)?.map(<[u8]>::to_vec);
+        self.tx.put(&key, value)?;
+        Ok(previous)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, QSError> {
+        let key = self.tree.key(key);
+        let previous = self.tx.get(&key)?.map(<[u8]>::to_vec);
+        self.tx.delete(&key)?;
+        Ok(previous)
+    }
+}
+
+/// Pairs of one tree, with the tree's prefix taken off the keys
+pub struct Iter<'db> {
+    inner: RangeIter<'db>,
+    prefix_len: usize,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>), QSError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next()?;
+        Some(entry.map(|(mut key, value)| (key.split_off(self.prefix_len), value)))
+    }
+}
+
+/// Smallest key greater than every key starting with `prefix`
+fn prefix_successor(prefix: &[u8]) -> Vec<u8> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < u8::MAX {
+            upper.push(last + 1);
+            return upper;
+        }
+    }
+    // only reached for all-0xFF prefixes, which tree prefixes never are
+    vec![u8::MAX]
+}
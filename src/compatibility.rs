@@ -0,0 +1,188 @@
+//! Checking whether this build can open a database, without opening it.
+//!
+//! `QuickStep::compatibility` reads the superblock at the head of the data file and the
+//! manifest at the head of the WAL, the two headers that carry a format version, and says
+//! whether opening would work, whether it would rewrite a header in an older format, and
+//! what stands in the way if it would not. Nothing is written, and tags are not checked: a
+//! file under a metadata key reads the same as one without.
+//!
+//! The superblock also records the crate version that last wrote it. A file last written by
+//! a newer crate opens as long as its formats are ones this build knows; the report notes
+//! it, since a downgrade may lose features the newer crate used.
+
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read},
+    path::PathBuf,
+};
+
+use crate::{
+    io_engine::PAGE_SIZE,
+    superblock::{
+        CrateVersion, Superblock, FLAG_COMPRESSED, SUPERBLOCK_LEN, SUPERBLOCK_VERSION,
+        SUPERBLOCK_VERSION_V1,
+    },
+    wal::{self, MANIFEST_VERSION, MANIFEST_VERSION_UNTAGGED},
+};
+
+/// What `QuickStep::compatibility` found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatReport {
+    pub data_path: PathBuf,
+    pub wal_path: PathBuf,
+    /// Superblock format of the data file, `None` if there is no formatted data file yet
+    pub data_format_version: Option<u32>,
+    /// Crate that last wrote the superblock, `None` for files from before it was recorded
+    pub written_by: Option<CrateVersion>,
+    /// Manifest format of the WAL, `None` if there is no WAL or it has no manifest
+    pub wal_format_version: Option<u32>,
+    /// The crate doing the check
+    pub crate_version: CrateVersion,
+    /// Opening with this build would succeed, given the right metadata key
+    pub can_open: bool,
+    /// Opening would rewrite a header written in an older format
+    pub needs_upgrade: bool,
+    /// Everything worth knowing before opening, blocking or not
+    pub issues: Vec<CompatIssue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompatIssue {
+    /// The data file does not start with a quickstep superblock. Blocks opening
+    NotQuickstep,
+    /// The superblock is in a format this build does not know. Blocks opening
+    UnsupportedDataFormat(u32),
+    /// The data file uses pages of another size. Blocks opening
+    PageSize(u32),
+    /// The superblock sets flags this build does not know. Blocks opening
+    UnknownFlags(u32),
+    /// The WAL manifest is in a format this build does not know; opening would replace
+    /// the log and lose its records. Blocks opening
+    UnsupportedWalFormat(u32),
+    /// The WAL does not start with a manifest; opening would replace the log. Blocks
+    /// opening
+    UnrecognizedWal,
+    /// The superblock predates the recorded crate version, opening rewrites it
+    OldDataFormat(u32),
+    /// The WAL manifest predates manifest tags, opening rewrites it
+    OldWalFormat(u32),
+    /// The data file was last written by a newer crate
+    WrittenByNewerCrate(CrateVersion),
+}
+
+impl CompatIssue {
+    /// Whether the issue stops this build from opening the database
+    pub fn blocks_open(&self) -> bool {
+        !matches!(
+            self,
+            CompatIssue::OldDataFormat(_)
+                | CompatIssue::OldWalFormat(_)
+                | CompatIssue::WrittenByNewerCrate(_)
+        )
+    }
+}
+
+/// Inspect the data file at `data_path` and the WAL at `wal_path`; either may be missing
+pub(crate) fn inspect(data_path: PathBuf, wal_path: PathBuf) -> io::Result<CompatReport> {
+    let mut report = CompatReport {
+        data_path,
+        wal_path,
+        data_format_version: None,
+        written_by: None,
+        wal_format_version: None,
+        crate_version: CrateVersion::current(),
+        can_open: true,
+        needs_upgrade: false,
+        issues: Vec::new(),
+    };
+    inspect_data_file(&mut report)?;
+    inspect_wal(&mut report)?;
+    report.can_open = !report.issues.iter().any(CompatIssue::blocks_open);
+    report.needs_upgrade = report.issues.iter().any(|issue| {
+        matches!(
+            issue,
+            CompatIssue::OldDataFormat(_) | CompatIssue::OldWalFormat(_)
+        )
+    });
+    Ok(report)
+}
+
+fn inspect_data_file(report: &mut CompatReport) -> io::Result<()> {
+    let mut file = match File::open(&report.data_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let mut block = [0u8; SUPERBLOCK_LEN];
+    let mut read = 0;
+    while read < block.len() {
+        match file.read(&mut block[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    let superblock = match Superblock::decode_unverified(&block) {
+        Ok(Some(superblock)) => superblock,
+        Ok(None) => return Ok(()),
+        Err(err) if err.kind() == ErrorKind::InvalidData => {
+            report.issues.push(CompatIssue::NotQuickstep);
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+
+    report.data_format_version = Some(superblock.version);
+    report.written_by = superblock.written_by;
+    match superblock.version {
+        SUPERBLOCK_VERSION => {}
+        SUPERBLOCK_VERSION_V1 => report
+            .issues
+            .push(CompatIssue::OldDataFormat(superblock.version)),
+        version => {
+            // the other fields may mean something else in an unknown format
+            report
+                .issues
+                .push(CompatIssue::UnsupportedDataFormat(version));
+            return Ok(());
+        }
+    }
+    if superblock.page_size as u64 != PAGE_SIZE {
+        report
+            .issues
+            .push(CompatIssue::PageSize(superblock.page_size));
+    }
+    if superblock.flags & !FLAG_COMPRESSED != 0 {
+        report
+            .issues
+            .push(CompatIssue::UnknownFlags(superblock.flags));
+    }
+    if let Some(written_by) = superblock.written_by {
+        if written_by > report.crate_version {
+            report
+                .issues
+                .push(CompatIssue::WrittenByNewerCrate(written_by));
+        }
+    }
+    Ok(())
+}
+
+fn inspect_wal(report: &mut CompatReport) -> io::Result<()> {
+    if !report.wal_path.exists() {
+        return Ok(());
+    }
+    let version = wal::manifest_version(&report.wal_path)?;
+    report.wal_format_version = version;
+    match version {
+        Some(MANIFEST_VERSION) => {}
+        Some(MANIFEST_VERSION_UNTAGGED) => report
+            .issues
+            .push(CompatIssue::OldWalFormat(MANIFEST_VERSION_UNTAGGED)),
+        Some(version) => report
+            .issues
+            .push(CompatIssue::UnsupportedWalFormat(version)),
+        // a log shorter than a manifest holds nothing, opening writes a fresh one
+        None if fs::metadata(&report.wal_path)?.len() < wal::MANIFEST_LEN => {}
+        None => report.issues.push(CompatIssue::UnrecognizedWal),
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Byte-oriented LZ compression of page images.
+//!
+//! The block format follows LZ4's: a run of sequences, each a token byte whose high nibble
+//! is the literal count and low nibble the match length less `MIN_MATCH`, extended by 255
+//! bytes while a nibble is 15, then the literals, then a 2 byte little-endian offset back to
+//! the match. The last sequence carries literals only. Matches may overlap their own output,
+//! so the free space of a leaf, and keys sharing long prefixes, come out as a few bytes.
+
+/// Shortest match worth a sequence
+const MIN_MATCH: usize = 4;
+/// Farthest back a match may start
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+/// Compress `input` into a block `decompress` restores
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 4);
+    // last position + 1 each 4 byte hash was seen at, 0 for none
+    let mut table = [0usize; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH <= input.len() {
+        let word = u32::from_le_bytes(input[pos..pos + MIN_MATCH].try_into().unwrap());
+        let slot = (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+        let candidate = table[slot].checked_sub(1);
+        table[slot] = pos + 1;
+        let Some(start) = candidate.filter(|start| {
+            pos - start <= MAX_OFFSET
+                && input[*start..*start + MIN_MATCH] == input[pos..pos + MIN_MATCH]
+        }) else {
+            pos += 1;
+            continue;
+        };
+        let mut len = MIN_MATCH;
+        while pos + len < input.len() && input[start + len] == input[pos + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &input[anchor..pos], Some((pos - start, len)));
+        pos += len;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+/// Restore a block into `out`, which it must fill exactly. `None` for a malformed block
+pub fn decompress(block: &[u8], out: &mut [u8]) -> Option<()> {
+    let mut pos = 0;
+    let mut written = 0;
+    loop {
+        let token = *block.get(pos)?;
+        pos += 1;
+        let literals = read_len(block, &mut pos, (token >> 4) as usize)?;
+        out.get_mut(written..written + literals)?
+            .copy_from_slice(block.get(pos..pos + literals)?);
+        pos += literals;
+        written += literals;
+        if pos == block.len() {
+            return (written == out.len()).then_some(());
+        }
+
+        let offset = u16::from_le_bytes(block.get(pos..pos + 2)?.try_into().ok()?) as usize;
+        pos += 2;
+        let len = read_len(block, &mut pos, (token & 0xF) as usize)? + MIN_MATCH;
+        let start = written.checked_sub(offset).filter(|_| offset > 0)?;
+        if written + len > out.len() {
+            return None;
+        }
+        // byte at a time, a match may overlap the bytes it is producing
+        for idx in 0..len {
+            out[written + idx] = out[start + idx];
+        }
+        written += len;
+    }
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    write_len(out, literals.len());
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_len(out, match_len);
+    }
+}
+
+/// The bytes past a nibble of 15
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    if len < 15 {
+        return;
+    }
+    let mut rest = len - 15;
+    while rest >= 255 {
+        out.push(255);
+        rest -= 255;
+    }
+    out.push(rest as u8);
+}
+
+fn read_len(block: &[u8], pos: &mut usize, nibble: usize) -> Option<usize> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *block.get(*pos)?;
+            *pos += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
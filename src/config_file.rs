@@ -0,0 +1,546 @@
+//! Loading a `QuickStepConfig` from a file, see `QuickStepConfig::from_file`.
+//!
+//! The file is either flat TOML, one `key = value` per line with `#` comments, or a flat JSON
+//! object, told apart by whether it starts with `{`. Values are unsigned integers, quoted
+//! strings or booleans; durations are given in milliseconds under keys ending in `_ms`.
+//!
+//! | key | value |
+//! |-----|-------|
+//! | `path` | data directory, relative to the file's own directory; required unless `in_memory` |
+//! | `in_memory` | bool |
+//! | `inner_node_upper_bound`, `leaf_upper_bound`, `cache_size_lg` | required, as for `new` |
+//! | `wal_leaf_threshold`, `wal_global_record_threshold`, `wal_global_byte_threshold` | int |
+//! | `max_wal_bytes` | int |
+//! | `wal_limit_policy` | `"throttle"` or `"fail"` |
+//! | `sync_policy` | `"per_commit"`, `"never"` or `"every"` with `sync_interval_ms` |
+//! | `wal_tail_retention`, `activity_log` | int |
+//! | `wal_audit_interval_ms`, `ttl_sweep_interval_ms`, `txn_timeout_ms` | int |
+//! | `idle_maintenance_after_ms` with `idle_maintenance_leaf_writes_per_sec` | int |
+//! | `auto_compaction_interval_ms` with `auto_compaction_min_free_pages` | int |
+//! | `blob_threshold` with `blob_file_bytes` | int |
+//! | `checkpoint_policy` | `"largest"`, `"oldest"`, `"round_robin"` or `"dirty_ratio"` |
+//! | `checkpoint_batch`, `checkpoint_interval_ms`, `leaf_cache_pages`, `max_db_bytes` | int |
+//! | `lock_spins`, `lock_timeout_ms` | int |
+//! | `inner_tree_max_nodes` | int, grow the inner tree up to it instead of failing when full |
+//! | `split_point` | `"even_count"`, `"even_bytes"` or `"ratio"` with `split_left_percent` |
+//! | `append_split_percent` | int, 0 to split appends at `split_point` too |
+//! | `max_key_len`, `max_value_len`, `hash_buckets` | int |
+//! | `read_repair`, `read_only`, `page_compression` | bool |
+//! | `metadata_key` | string |
+//!
+//! Keys not in the table, given twice or with the wrong kind of value fail the load, so a
+//! typo is not silently ignored. The merge operator, event listener, metrics exporter,
+//! keyspace limits and IO latency are code and stay with the builders.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    checkpoint::CheckpointPolicy, error::QSError, health::InnerTreeFullPolicy,
+    health::WalLimitPolicy, page_op::SplitPoint, wal::SyncPolicy, QuickStepConfig,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Int(u64),
+    Str(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "a number",
+            Value::Str(_) => "a string",
+            Value::Bool(_) => "a boolean",
+        }
+    }
+}
+
+/// A problem with the file, on `line` or 0 for the file as a whole
+struct ConfigError {
+    line: usize,
+    reason: String,
+}
+
+fn error(line: usize, reason: impl Into<String>) -> ConfigError {
+    ConfigError {
+        line,
+        reason: reason.into(),
+    }
+}
+
+/// Read the config at `path`
+pub(crate) fn load(path: &Path) -> Result<QuickStepConfig, QSError> {
+    let text = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    parse(&text)
+        .and_then(|entries| build(entries, base))
+        .map_err(|err| QSError::InvalidConfig {
+            path: path.to_path_buf(),
+            line: err.line,
+            reason: err.reason,
+        })
+}
+
+fn parse(text: &str) -> Result<Vec<(String, Value, usize)>, ConfigError> {
+    let mut scanner = Scanner::new(text);
+    scanner.skip_whitespace();
+    if scanner.peek() == Some('{') {
+        scanner.parse_json()
+    } else {
+        scanner.parse_toml()
+    }
+}
+
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Scanner {
+    fn new(text: &str) -> Scanner {
+        Scanner {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// Spaces within a line
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\r')) {
+            self.bump();
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, want: char) -> Result<(), ConfigError> {
+        match self.bump() {
+            Some(c) if c == want => Ok(()),
+            Some(c) => Err(error(self.line, format!("expected `{want}`, found `{c}`"))),
+            None => Err(error(
+                self.line,
+                format!("expected `{want}`, found the end"),
+            )),
+        }
+    }
+
+    /// A bare key or value: letters, digits and `_`, `-`, `.`, `+`
+    fn bare(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '+')) {
+                break;
+            }
+            token.push(c);
+            self.bump();
+        }
+        token
+    }
+
+    fn string(&mut self) -> Result<String, ConfigError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(out),
+                Some('\\') => match self.bump() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some(c) => return Err(error(self.line, format!("unknown escape `\\{c}`"))),
+                    None => return Err(error(self.line, "unterminated string")),
+                },
+                Some('\n') | None => return Err(error(self.line, "unterminated string")),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ConfigError> {
+        if self.peek() == Some('"') {
+            return self.string().map(Value::Str);
+        }
+        let token = self.bare();
+        match token.as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => token
+                .replace('_', "")
+                .parse::<u64>()
+                .map(Value::Int)
+                .map_err(|_| {
+                    let found = if token.is_empty() {
+                        self.peek()
+                            .map_or("the end".to_string(), |c| format!("`{c}`"))
+                    } else {
+                        format!("`{token}`")
+                    };
+                    error(
+                        self.line,
+                        format!("expected a number, string or boolean, found {found}"),
+                    )
+                }),
+        }
+    }
+
+    fn parse_toml(&mut self) -> Result<Vec<(String, Value, usize)>, ConfigError> {
+        let mut entries = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let line = self.line;
+            let key = match self.peek() {
+                None => return Ok(entries),
+                Some('#') => {
+                    self.skip_line();
+                    continue;
+                }
+                Some('[') => return Err(error(line, "tables are not supported, keys are flat")),
+                Some('"') => self.string()?,
+                Some(_) => self.bare(),
+            };
+            if key.is_empty() {
+                return Err(error(line, "expected a key"));
+            }
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            self.skip_spaces();
+            match self.peek() {
+                None | Some('\n' | '#') => self.skip_line(),
+                Some(c) => return Err(error(line, format!("unexpected `{c}` after the value"))),
+            }
+            entries.push((key, value, line));
+        }
+    }
+
+    fn parse_json(&mut self) -> Result<Vec<(String, Value, usize)>, ConfigError> {
+        let mut entries = Vec::new();
+        self.expect('{')?;
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.bump();
+        } else {
+            loop {
+                self.skip_whitespace();
+                let line = self.line;
+                if self.peek() != Some('"') {
+                    return Err(error(line, "expected a quoted key"));
+                }
+                let key = self.string()?;
+                self.skip_whitespace();
+                self.expect(':')?;
+                self.skip_whitespace();
+                let value = self.value()?;
+                entries.push((key, value, line));
+                self.skip_whitespace();
+                match self.bump() {
+                    Some(',') => continue,
+                    Some('}') => break,
+                    Some(c) => {
+                        return Err(error(
+                            self.line,
+                            format!("expected `,` or `}}`, found `{c}`"),
+                        ))
+                    }
+                    None => return Err(error(self.line, "expected `}`, found the end")),
+                }
+            }
+        }
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return Err(error(self.line, "unexpected text after the closing `}`"));
+        }
+        Ok(entries)
+    }
+}
+
+/// The parsed entries, taken out by key as the config is built
+struct Fields {
+    entries: BTreeMap<String, (Value, usize)>,
+}
+
+impl Fields {
+    fn new(entries: Vec<(String, Value, usize)>) -> Result<Fields, ConfigError> {
+        let mut map = BTreeMap::new();
+        for (key, value, line) in entries {
+            if map.contains_key(&key) {
+                return Err(error(line, format!("`{key}` is given more than once")));
+            }
+            map.insert(key, (value, line));
+        }
+        Ok(Fields { entries: map })
+    }
+
+    fn int<T: TryFrom<u64>>(&mut self, key: &str) -> Result<Option<T>, ConfigError> {
+        match self.entries.remove(key) {
+            None => Ok(None),
+            Some((Value::Int(n), line)) => T::try_from(n)
+                .map(Some)
+                .map_err(|_| error(line, format!("`{key}` is out of range"))),
+            Some((value, line)) => Err(mismatch(key, "a number", &value, line)),
+        }
+    }
+
+    fn required_int<T: TryFrom<u64>>(&mut self, key: &str) -> Result<T, ConfigError> {
+        self.int(key)?
+            .ok_or_else(|| error(0, format!("`{key}` is required")))
+    }
+
+    fn millis(&mut self, key: &str) -> Result<Option<Duration>, ConfigError> {
+        Ok(self.int(key)?.map(Duration::from_millis))
+    }
+
+    fn string(&mut self, key: &str) -> Result<Option<(String, usize)>, ConfigError> {
+        match self.entries.remove(key) {
+            None => Ok(None),
+            Some((Value::Str(s), line)) => Ok(Some((s, line))),
+            Some((value, line)) => Err(mismatch(key, "a string", &value, line)),
+        }
+    }
+
+    fn bool(&mut self, key: &str) -> Result<bool, ConfigError> {
+        match self.entries.remove(key) {
+            None => Ok(false),
+            Some((Value::Bool(b), _)) => Ok(b),
+            Some((value, line)) => Err(mismatch(key, "a boolean", &value, line)),
+        }
+    }
+
+    /// Two keys that are only meaningful together
+    fn pair<A, B>(
+        &mut self,
+        a: (&str, Option<A>),
+        b: (&str, Option<B>),
+    ) -> Result<Option<(A, B)>, ConfigError> {
+        match (a.1, b.1) {
+            (Some(a), Some(b)) => Ok(Some((a, b))),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(error(0, format!("`{}` needs `{}`", a.0, b.0))),
+            (None, Some(_)) => Err(error(0, format!("`{}` needs `{}`", b.0, a.0))),
+        }
+    }
+}
+
+fn mismatch(key: &str, want: &str, found: &Value, line: usize) -> ConfigError {
+    error(
+        line,
+        format!("`{key}` should be {want}, not {}", found.kind()),
+    )
+}
+
+fn build(
+    entries: Vec<(String, Value, usize)>,
+    base: &Path,
+) -> Result<QuickStepConfig, ConfigError> {
+    let mut fields = Fields::new(entries)?;
+    let in_memory = fields.bool("in_memory")?;
+    let inner_node_upper_bound = fields.required_int("inner_node_upper_bound")?;
+    let leaf_upper_bound = fields.required_int("leaf_upper_bound")?;
+    let cache_size_lg = fields.required_int("cache_size_lg")?;
+    let path = fields.string("path")?;
+    let mut config = match (in_memory, path) {
+        (true, None) => {
+            QuickStepConfig::in_memory(inner_node_upper_bound, leaf_upper_bound, cache_size_lg)
+        }
+        (true, Some((_, line))) => return Err(error(line, "`path` is unused when `in_memory`")),
+        (false, Some((path, _))) => QuickStepConfig::new(
+            base.join(PathBuf::from(path)),
+            inner_node_upper_bound,
+            leaf_upper_bound,
+            cache_size_lg,
+        ),
+        (false, None) => return Err(error(0, "`path` is required unless `in_memory`")),
+    };
+
+    let (leaf, record, bytes) = config.wal_thresholds();
+    config = config.with_wal_thresholds(
+        fields.int("wal_leaf_threshold")?.unwrap_or(leaf),
+        fields.int("wal_global_record_threshold")?.unwrap_or(record),
+        fields.int("wal_global_byte_threshold")?.unwrap_or(bytes),
+    );
+    if let Some(bytes) = fields.int("max_wal_bytes")? {
+        config = config.with_max_wal_size(bytes);
+    }
+    if let Some((policy, line)) = fields.string("wal_limit_policy")? {
+        config = config.with_wal_limit_policy(match policy.as_str() {
+            "throttle" => WalLimitPolicy::Throttle,
+            "fail" => WalLimitPolicy::Fail,
+            _ => return Err(unknown_choice("wal_limit_policy", &policy, line)),
+        });
+    }
+    let sync_interval = fields.millis("sync_interval_ms")?;
+    if let Some((policy, line)) = fields.string("sync_policy")? {
+        config = config.with_sync_policy(match (policy.as_str(), sync_interval) {
+            ("every", Some(interval)) => SyncPolicy::Every(interval),
+            ("every", None) => return Err(error(line, "`every` needs `sync_interval_ms`")),
+            ("per_commit", None) => SyncPolicy::PerCommit,
+            ("never", None) => SyncPolicy::Never,
+            ("per_commit" | "never", Some(_)) => {
+                return Err(error(line, "`sync_interval_ms` only applies to `every`"))
+            }
+            _ => return Err(unknown_choice("sync_policy", &policy, line)),
+        });
+    } else if sync_interval.is_some() {
+        return Err(error(
+            0,
+            "`sync_interval_ms` needs `sync_policy = \"every\"`",
+        ));
+    }
+    if let Some(records) = fields.int("wal_tail_retention")? {
+        config = config.with_wal_tail_retention(records);
+    }
+    if let Some(interval) = fields.millis("wal_audit_interval_ms")? {
+        config = config.with_wal_audit(interval);
+    }
+    if let Some(capacity) = fields.int("activity_log")? {
+        config = config.with_activity_log(capacity);
+    }
+
+    if let Some(interval) = fields.millis("ttl_sweep_interval_ms")? {
+        config = config.with_ttl_sweep_interval(interval);
+    }
+    if let Some(timeout) = fields.millis("txn_timeout_ms")? {
+        config = config.with_txn_timeout(timeout);
+    }
+    let idle_after = fields.millis("idle_maintenance_after_ms")?;
+    let leaf_writes = fields.int("idle_maintenance_leaf_writes_per_sec")?;
+    if let Some((idle_after, leaf_writes)) = fields.pair(
+        ("idle_maintenance_after_ms", idle_after),
+        ("idle_maintenance_leaf_writes_per_sec", leaf_writes),
+    )? {
+        config = config.with_idle_maintenance(idle_after, leaf_writes);
+    }
+    let interval = fields.millis("auto_compaction_interval_ms")?;
+    let min_free_pages = fields.int("auto_compaction_min_free_pages")?;
+    if let Some((interval, min_free_pages)) = fields.pair(
+        ("auto_compaction_interval_ms", interval),
+        ("auto_compaction_min_free_pages", min_free_pages),
+    )? {
+        config = config.with_auto_compaction(interval, min_free_pages);
+    }
+    let threshold = fields.int("blob_threshold")?;
+    let file_bytes = fields.int("blob_file_bytes")?;
+    if let Some((threshold, file_bytes)) = fields.pair(
+        ("blob_threshold", threshold),
+        ("blob_file_bytes", file_bytes),
+    )? {
+        config = config.with_blob_store(threshold, file_bytes);
+    }
+
+    if let Some((policy, line)) = fields.string("checkpoint_policy")? {
+        config = config.with_checkpoint_policy(match policy.as_str() {
+            "largest" => CheckpointPolicy::Largest,
+            "oldest" => CheckpointPolicy::Oldest,
+            "round_robin" => CheckpointPolicy::RoundRobin,
+            "dirty_ratio" => CheckpointPolicy::DirtyRatio,
+            _ => return Err(unknown_choice("checkpoint_policy", &policy, line)),
+        });
+    }
+    if let Some(pages) = fields.int("checkpoint_batch")? {
+        config = config.with_checkpoint_batch(pages);
+    }
+    if let Some(interval) = fields.millis("checkpoint_interval_ms")? {
+        config = config.with_checkpoint_interval(interval);
+    }
+    if let Some(pages) = fields.int("leaf_cache_pages")? {
+        config = config.with_leaf_cache_pages(pages);
+    }
+    if let Some(bytes) = fields.int("max_db_bytes")? {
+        config = config.with_max_db_size(bytes);
+    }
+    if let Some(spins) = fields.int("lock_spins")? {
+        config = config.with_lock_spins(spins);
+    }
+    if let Some(timeout) = fields.millis("lock_timeout_ms")? {
+        config = config.with_lock_timeout(timeout);
+    }
+    if let Some(max_inner_nodes) = fields.int("inner_tree_max_nodes")? {
+        config = config.with_inner_tree_full_policy(InnerTreeFullPolicy::Grow { max_inner_nodes });
+    }
+    let left_percent = fields.int("split_left_percent")?;
+    if let Some((point, line)) = fields.string("split_point")? {
+        config = config.with_split_point(match (point.as_str(), left_percent) {
+            ("ratio", Some(left_percent)) => SplitPoint::Ratio { left_percent },
+            ("ratio", None) => return Err(error(line, "`ratio` needs `split_left_percent`")),
+            ("even_count", None) => SplitPoint::EvenCount,
+            ("even_bytes", None) => SplitPoint::EvenBytes,
+            ("even_count" | "even_bytes", Some(_)) => {
+                return Err(error(line, "`split_left_percent` only applies to `ratio`"))
+            }
+            _ => return Err(unknown_choice("split_point", &point, line)),
+        });
+    } else if left_percent.is_some() {
+        return Err(error(
+            0,
+            "`split_left_percent` needs `split_point = \"ratio\"`",
+        ));
+    }
+    if let Some(percent) = fields.int::<u8>("append_split_percent")? {
+        config = config.with_append_split((percent > 0).then_some(percent));
+    }
+    if let Some(len) = fields.int("max_key_len")? {
+        config = config.with_max_key_len(len);
+    }
+    if let Some(len) = fields.int("max_value_len")? {
+        config = config.with_max_value_len(len);
+    }
+    if let Some(buckets) = fields.int("hash_buckets")? {
+        config = config.with_hash_index(buckets);
+    }
+    if fields.bool("read_repair")? {
+        config = config.with_read_repair();
+    }
+    if fields.bool("read_only")? {
+        config = config.with_read_only();
+    }
+    if fields.bool("page_compression")? {
+        config = config.with_page_compression();
+    }
+    if let Some((key, _)) = fields.string("metadata_key")? {
+        config = config.with_metadata_key(key.as_bytes());
+    }
+
+    match fields.entries.into_iter().next() {
+        Some((key, (_, line))) => Err(error(line, format!("unknown key `{key}`"))),
+        None => Ok(config),
+    }
+}
+
+fn unknown_choice(key: &str, found: &str, line: usize) -> ConfigError {
+    error(line, format!("`{found}` is not a `{key}`"))
+}
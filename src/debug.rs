@@ -1,8 +1,21 @@
 #![allow(dead_code)]
 
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Mutex,
+use std::{
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    btree::{ChildPointer, TreeSnapshot},
+    events::hex,
+    io_engine::PAGE_SIZE,
+    map_table::PageId,
+    types::{NodeMeta, NodeRef},
+    Store,
 };
 
 #[derive(Clone, Debug)]
@@ -27,6 +40,9 @@ static EVICTION_REQUESTS: AtomicU64 = AtomicU64::new(0);
 static SPLIT_EVENTS: Mutex<Vec<SplitEvent>> = Mutex::new(Vec::new());
 static MERGE_EVENTS: Mutex<Vec<MergeEvent>> = Mutex::new(Vec::new());
 static SECOND_CHANCE_PASSES: AtomicU64 = AtomicU64::new(0);
+static HEAD_ADVANCES: AtomicU64 = AtomicU64::new(0);
+static MINI_PAGE_GROWTHS: AtomicU64 = AtomicU64::new(0);
+static CACHED_READS: AtomicU64 = AtomicU64::new(0);
 
 pub fn record_split_event(
     left_page: u64,
@@ -55,6 +71,18 @@ pub fn record_second_chance() {
     SECOND_CHANCE_PASSES.fetch_add(1, Ordering::Relaxed);
 }
 
+pub fn record_head_advance() {
+    HEAD_ADVANCES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_mini_page_growth() {
+    MINI_PAGE_GROWTHS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_cached_read() {
+    CACHED_READS.fetch_add(1, Ordering::Relaxed);
+}
+
 pub fn record_merge_event(survivor_page: u64, removed_page: u64, merged_count: usize) {
     MERGE_REQUESTS.fetch_add(1, Ordering::Relaxed);
     if let Ok(mut guard) = MERGE_EVENTS.lock() {
@@ -71,6 +99,9 @@ pub fn reset_debug_counters() {
     MERGE_REQUESTS.store(0, Ordering::Relaxed);
     EVICTION_REQUESTS.store(0, Ordering::Relaxed);
     SECOND_CHANCE_PASSES.store(0, Ordering::Relaxed);
+    HEAD_ADVANCES.store(0, Ordering::Relaxed);
+    MINI_PAGE_GROWTHS.store(0, Ordering::Relaxed);
+    CACHED_READS.store(0, Ordering::Relaxed);
     let mut guard = match SPLIT_EVENTS.lock() {
         Ok(guard) => guard,
         Err(poisoned) => poisoned.into_inner(),
@@ -102,6 +133,21 @@ pub fn second_chance_passes() -> u64 {
     SECOND_CHANCE_PASSES.load(Ordering::Relaxed)
 }
 
+/// Evictions that freed the oldest mini-page and moved the buffer's head past it
+pub fn head_advances() -> u64 {
+    HEAD_ADVANCES.load(Ordering::Relaxed)
+}
+
+/// Mini-pages moved into a bigger slot after running out of room
+pub fn mini_page_growths() -> u64 {
+    MINI_PAGE_GROWTHS.load(Ordering::Relaxed)
+}
+
+/// Disk records a read copied into the page's mini-page
+pub fn cached_reads() -> u64 {
+    CACHED_READS.load(Ordering::Relaxed)
+}
+
 pub fn merge_requests() -> u64 {
     MERGE_REQUESTS.load(Ordering::Relaxed)
 }
@@ -112,3 +158,297 @@ pub fn merge_events() -> Vec<MergeEvent> {
         Err(poison) => poison.into_inner().clone(),
     }
 }
+
+/// The shape of a store's tree, see `dump_tree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeDump {
+    /// `None` for a hash-index store, whose buckets have no inner nodes
+    pub root: Option<DumpChild>,
+    /// Parents before their children, each node's children left to right
+    pub inner_nodes: Vec<InnerDump>,
+    /// In key order, or bucket order for a hash-index store
+    pub leaves: Vec<LeafDump>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpChild {
+    Inner(u32),
+    Leaf(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InnerDump {
+    pub id: u32,
+    /// 1 for the parents of leaves
+    pub level: u16,
+    pub pivots: Vec<Vec<u8>>,
+    /// The lowest child, then the child after each pivot
+    pub children: Vec<DumpChild>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafDump {
+    pub page_id: u64,
+    pub disk_addr: u64,
+    pub lower_fence: Vec<u8>,
+    pub upper_fence: Vec<u8>,
+    /// Live records on disk, fences and tombstones aside
+    pub records: usize,
+    /// Bytes the records on disk take, their metadata included
+    pub record_bytes: usize,
+    /// Size of the leaf's mini-page, `None` if it is not cached
+    pub cached_bytes: Option<usize>,
+    /// Live records in the mini-page
+    pub cached_records: usize,
+    /// Why the leaf could not be read, the fields above are then empty
+    pub error: Option<String>,
+}
+
+impl LeafDump {
+    /// Share of the disk page the records fill
+    pub fn occupancy(&self) -> f64 {
+        self.record_bytes as f64 / PAGE_SIZE as f64
+    }
+}
+
+/// Copy out the inner nodes and read every leaf they reach, for a picture of the tree. A
+/// leaf that cannot be read is listed with the error. Leaves are read one at a time, so a
+/// dump taken while writers are active may mix the tree before and after a change
+pub fn dump_tree(db: &Store) -> TreeDump {
+    if let Some(buckets) = db.hash_index_buckets() {
+        return TreeDump {
+            root: None,
+            inner_nodes: Vec::new(),
+            leaves: (0..buckets).map(|bucket| dump_leaf(db, bucket)).collect(),
+        };
+    }
+
+    let snapshot = loop {
+        // the copy only fails while writers keep restructuring the nodes it reads
+        match db.inner_nodes.snapshot_inner() {
+            Ok(snapshot) => break snapshot,
+            Err(_) => thread::yield_now(),
+        }
+    };
+    let TreeSnapshot { root, nodes } = snapshot;
+    let inner_nodes: Vec<InnerDump> = nodes
+        .into_iter()
+        .map(|node| InnerDump {
+            id: node.node.as_u32(),
+            level: node.level,
+            pivots: node.pivots,
+            children: node.children.into_iter().map(dump_child).collect(),
+        })
+        .collect();
+    let mut pages = Vec::new();
+    collect_leaves(&inner_nodes, dump_child(root), &mut pages);
+    TreeDump {
+        root: Some(dump_child(root)),
+        leaves: pages.into_iter().map(|page| dump_leaf(db, page)).collect(),
+        inner_nodes,
+    }
+}
+
+fn dump_child(child: ChildPointer) -> DumpChild {
+    match child {
+        ChildPointer::Inner(node) => DumpChild::Inner(node.as_u32()),
+        ChildPointer::Leaf(page) => DumpChild::Leaf(page.as_u64()),
+    }
+}
+
+/// The leaves under `child` left to right
+fn collect_leaves(nodes: &[InnerDump], child: DumpChild, pages: &mut Vec<u64>) {
+    match child {
+        DumpChild::Leaf(page) => pages.push(page),
+        DumpChild::Inner(id) => {
+            if let Some(node) = nodes.iter().find(|node| node.id == id) {
+                for child in &node.children {
+                    collect_leaves(nodes, *child, pages);
+                }
+            }
+        }
+    }
+}
+
+fn dump_leaf(db: &Store, page: u64) -> LeafDump {
+    let mut dump = LeafDump {
+        page_id: page,
+        disk_addr: 0,
+        lower_fence: Vec::new(),
+        upper_fence: Vec::new(),
+        records: 0,
+        record_bytes: 0,
+        cached_bytes: None,
+        cached_records: 0,
+        error: None,
+    };
+    if let Err(err) = read_leaf_into(db, PageId::from_u64(page), &mut dump) {
+        dump.error = Some(format!("{err:?}"));
+    }
+    dump
+}
+
+fn read_leaf_into(
+    db: &Store,
+    page_id: PageId,
+    dump: &mut LeafDump,
+) -> Result<(), crate::error::QSError> {
+    let guard = db.map_table.read_page_entry(page_id)?;
+    dump.disk_addr = match guard.node() {
+        NodeRef::Leaf(addr) => addr,
+        NodeRef::MiniPage(index) => {
+            // SAFETY: we hold a read lock on the page
+            let meta = unsafe { db.cache.get_meta_ref(index) };
+            dump.cached_bytes = Some(meta.size().size_in_bytes());
+            dump.cached_records = live_records(meta);
+            meta.leaf()
+        }
+    };
+    let leaf = db.io_engine.get_page(dump.disk_addr)?;
+    let meta = leaf.as_ref();
+    if meta.record_count() >= 2 {
+        (dump.lower_fence, dump.upper_fence) = meta.fence_bounds();
+    }
+    dump.records = live_records(meta);
+    dump.record_bytes = meta.record_bytes();
+    Ok(())
+}
+
+fn live_records(meta: &NodeMeta) -> usize {
+    meta.entries().filter(|entry| !entry.meta.fence()).count()
+}
+
+impl TreeDump {
+    /// The dump as one JSON object, without a trailing newline. Keys and pivots are written
+    /// as hex
+    pub fn to_json(&self) -> String {
+        let child = |child: &DumpChild| match child {
+            DumpChild::Inner(id) => format!(r#"{{"inner":{id}}}"#),
+            DumpChild::Leaf(page) => format!(r#"{{"leaf":{page}}}"#),
+        };
+        let mut out = String::from("{\"root\":");
+        match &self.root {
+            Some(root) => out.push_str(&child(root)),
+            None => out.push_str("null"),
+        }
+        out.push_str(",\"inner_nodes\":[");
+        for (idx, node) in self.inner_nodes.iter().enumerate() {
+            let pivots: Vec<String> = node
+                .pivots
+                .iter()
+                .map(|p| format!("\"{}\"", hex(p)))
+                .collect();
+            let children: Vec<String> = node.children.iter().map(child).collect();
+            let _ = write!(
+                out,
+                r#"{}{{"id":{},"level":{},"pivots":[{}],"children":[{}]}}"#,
+                if idx == 0 { "" } else { "," },
+                node.id,
+                node.level,
+                pivots.join(","),
+                children.join(",")
+            );
+        }
+        out.push_str("],\"leaves\":[");
+        for (idx, leaf) in self.leaves.iter().enumerate() {
+            let cached_bytes = leaf
+                .cached_bytes
+                .map_or_else(|| "null".to_string(), |bytes| bytes.to_string());
+            let error = leaf.error.as_ref().map_or_else(
+                || "null".to_string(),
+                |err| format!("\"{}\"", escape_json(err)),
+            );
+            let _ = write!(
+                out,
+                r#"{}{{"page_id":{},"disk_addr":{},"lower_fence":"{}","upper_fence":"{}","#,
+                if idx == 0 { "" } else { "," },
+                leaf.page_id,
+                leaf.disk_addr,
+                hex(&leaf.lower_fence),
+                hex(&leaf.upper_fence),
+            );
+            let _ = write!(
+                out,
+                r#""records":{},"record_bytes":{},"occupancy":{:.3},"#,
+                leaf.records,
+                leaf.record_bytes,
+                leaf.occupancy(),
+            );
+            let _ = write!(
+                out,
+                r#""cached_bytes":{},"cached_records":{},"error":{}}}"#,
+                cached_bytes, leaf.cached_records, error
+            );
+        }
+        out.push_str("]}");
+        out
+    }
+
+    /// The dump as a Graphviz digraph, inner nodes labelled with their pivots and leaves
+    /// with their page, address, records and occupancy. Printable keys are shown as they
+    /// are, others as hex
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph quickstep {\n    node [shape=box];\n");
+        for node in &self.inner_nodes {
+            let pivots: Vec<String> = node.pivots.iter().map(|p| display_key(p)).collect();
+            let _ = writeln!(
+                out,
+                "    inner{} [label=\"inner {} (level {})\\n{}\"];",
+                node.id,
+                node.id,
+                node.level,
+                escape_dot(&pivots.join(" | "))
+            );
+            for child in &node.children {
+                let _ = writeln!(out, "    inner{} -> {};", node.id, dot_id(*child));
+            }
+        }
+        for leaf in &self.leaves {
+            let detail = match &leaf.error {
+                Some(err) => escape_dot(err),
+                None => format!(
+                    "addr {}\\n{} records, {:.0}% full{}",
+                    leaf.disk_addr,
+                    leaf.records,
+                    leaf.occupancy() * 100.0,
+                    leaf.cached_bytes
+                        .map_or_else(String::new, |bytes| format!("\\ncached in {bytes} bytes"))
+                ),
+            };
+            let _ = writeln!(
+                out,
+                "    leaf{} [label=\"leaf {}\\n{}\", style=rounded];",
+                leaf.page_id, leaf.page_id, detail
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn dot_id(child: DumpChild) -> String {
+    match child {
+        DumpChild::Inner(id) => format!("inner{id}"),
+        DumpChild::Leaf(page) => format!("leaf{page}"),
+    }
+}
+
+fn display_key(key: &[u8]) -> String {
+    match key
+        .iter()
+        .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+    {
+        true => String::from_utf8_lossy(key).into_owned(),
+        false => format!("0x{}", hex(key)),
+    }
+}
+
+fn escape_json(text: &str) -> String {
+    text.replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', r"\\").replace('"', "\\\"")
+}
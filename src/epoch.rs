@@ -0,0 +1,83 @@
+//! Epochs telling when no reader can still be looking at a retired inner node.
+//!
+//! Readers walk the inner nodes without locks and only validate versions afterwards, so a
+//! node taken out of the tree may still be read by a traversal that reached it just before.
+//! Each traversal pins the current epoch for as long as it reads nodes. A node retired in
+//! epoch `e` is safe to reuse once the global epoch reaches `e + 2`: the epoch only moves
+//! from `e + 1` once no reader is pinned in `e`, and readers pinned later started after the
+//! node was unlinked.
+//!
+//! Readers are counted per epoch in three slots, the current epoch, the one before it, and
+//! the one after, which is empty until the epoch moves into it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct Epochs {
+    global: AtomicU64,
+    /// Readers pinned in each epoch, by epoch modulo 3
+    pinned: [AtomicU64; 3],
+}
+
+impl Epochs {
+    pub fn new() -> Epochs {
+        Epochs::default()
+    }
+
+    pub fn current(&self) -> u64 {
+        self.global.load(Ordering::SeqCst)
+    }
+
+    /// Pin the current epoch until the guard drops
+    pub fn pin(&self) -> EpochGuard<'_> {
+        loop {
+            let epoch = self.global.load(Ordering::SeqCst);
+            let slot = &self.pinned[(epoch % 3) as usize];
+            slot.fetch_add(1, Ordering::SeqCst);
+            // the epoch may have moved on after it was read; counting a reader in an old
+            // epoch's slot would let the epoch run ahead of it
+            if self.global.load(Ordering::SeqCst) == epoch {
+                return EpochGuard {
+                    epochs: self,
+                    epoch,
+                };
+            }
+            slot.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Move to the next epoch if no reader is pinned in the previous one, returns the
+    /// epoch now current
+    pub fn try_advance(&self) -> u64 {
+        let epoch = self.global.load(Ordering::SeqCst);
+        let previous = &self.pinned[((epoch + 2) % 3) as usize];
+        if previous.load(Ordering::SeqCst) != 0 {
+            return epoch;
+        }
+        match self
+            .global
+            .compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => epoch + 1,
+            Err(current) => current,
+        }
+    }
+}
+
+/// A reader's pin on an epoch
+pub struct EpochGuard<'a> {
+    epochs: &'a Epochs,
+    epoch: u64,
+}
+
+impl EpochGuard<'_> {
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+}
+
+impl Drop for EpochGuard<'_> {
+    fn drop(&mut self) {
+        self.epochs.pinned[(self.epoch % 3) as usize].fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 #[derive(Debug)]
 pub enum QSError {
+    /// Growing the database would pass a format or configured capacity limit
+    CapacityExceeded(CapacityLimit),
     /// There was a failure to acquire a page lock
     PageLockFail,
     /// The number of retries on inner nodes was exceeded
@@ -14,8 +18,87 @@ pub enum QSError {
     NodeFull,
     /// Parent node did not contain expected child pointer
     ParentChildMissing,
-    /// Inner node slab exhausted
+    /// Inner node slab exhausted: a leaf split could not get the inner nodes it needs, and
+    /// its write failed before the leaf was changed, see `health::InnerTreeFullPolicy`
     TreeFull,
-    /// Pivot key exceeded internal node storage limits
+    /// Pivot key exceeded internal node storage limits. A write whose key is over the limit
+    /// fails with `KeyTooLong` instead
     KeyTooLarge,
+    /// Another transaction holds the leaf this write needs, or has scanned a range holding
+    /// the key in serializable mode; the transaction should be aborted and retried. The
+    /// holders are available from `QuickStepTx::conflicting_txns`
+    WriteConflict { key: Vec<u8> },
+    /// `QuickStepTx::merge` was called without a merge operator in the config
+    NoMergeOperator,
+    /// The `AsyncQuickStep` worker thread stopped before running the job, after a panic in
+    /// an earlier job or while the store was being dropped
+    AsyncWorkerStopped,
+    /// The WAL is at its configured maximum size and checkpointing could not shrink it
+    WalFull {
+        wal_bytes: usize,
+        max_wal_bytes: usize,
+    },
+    /// The hash-index bucket the key hashes to is full, buckets never split
+    HashBucketFull { bucket: u64 },
+    /// Range scans need the ordered tree, a hash-index store keeps no key order
+    OrderedScanUnsupported,
+    /// The disk leaf at `addr` failed its checksum: it was torn or corrupted on disk
+    PageCorrupt { addr: u64 },
+    /// The overflow chain starting at `addr` does not hold the value its pointer describes,
+    /// see `overflow`
+    OverflowCorrupt { addr: u64 },
+    /// The blob file `file` is gone or does not hold at `offset` the value its pointer
+    /// describes, see `blob`
+    BlobCorrupt { file: u32, offset: u64 },
+    /// A written key is longer than the limit for its keyspace, see `limits`
+    KeyTooLong { len: usize, max: usize },
+    /// A written value, or the result of a merge, is longer than the limit for its keyspace
+    ValueTooLong { len: usize, max: usize },
+    /// A write named a key in the keyspace the store keeps for itself, see `reserved`
+    ReservedKey { key: Vec<u8> },
+    /// The transaction was idle past the configured timeout and has been rolled back, see
+    /// `txn_timeout`
+    TxnExpired { txn_id: u64 },
+    /// The data file or the WAL could not be written or synced
+    Io(std::io::Error),
+    /// The store is a follower, it only changes through `QuickStep::apply_wal_batch`
+    FollowerReadOnly,
+    /// The store was opened with `QuickStepConfig::with_read_only`, nothing can be written
+    ReadOnly,
+    /// Shipped WAL records were applied to a store not opened as a follower
+    NotFollower,
+    /// `QuickStep::resolve_in_doubt` was given a transaction recovery did not leave in doubt,
+    /// or one already resolved
+    NotInDoubt { txn_id: u64 },
+    /// The WAL cannot be read back: its manifest failed authentication or is not one this
+    /// build understands. A torn tail is not corrupt, opening drops it
+    WalCorrupt { reason: String },
+    /// The data file is shorter than its metadata page, it was cut short or is not a
+    /// quickstep file
+    DataFileTooSmall { len: u64, min: u64 },
+    /// Another handle, in this process or another, holds the lock on the data file at `path`,
+    /// see `QuickStepConfig::with_read_only` to read it anyway
+    AlreadyLocked { path: PathBuf },
+    /// The config file at `path` is not one `QuickStepConfig::from_file` understands: `line`
+    /// holds the problem, or is 0 for one with the file as a whole, such as a missing key
+    InvalidConfig {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+}
+
+impl From<std::io::Error> for QSError {
+    fn from(err: std::io::Error) -> QSError {
+        QSError::Io(err)
+    }
+}
+
+/// The limit a rejected allocation ran into, with its maximum
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapacityLimit {
+    /// Number of data pages the disk file may hold
+    DiskPages(u64),
+    /// Number of leaf page ids the mapping table may hand out
+    PageIds(u64),
 }
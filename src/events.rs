@@ -0,0 +1,184 @@
+//! Allocation and structural events, for tests and operational tooling.
+//!
+//! With `QuickStepConfig::with_event_listener`, the store tells the listener of every disk
+//! page it allocates or frees, every mini-page slot it hands out or evicts, every leaf
+//! split, merge, relocation and whole-tree replacement, every checkpoint and every rewrite
+//! that shrinks the WAL, as it happens. Unlike the `debug` counters, which are process-wide,
+//! listeners belong to one store and work in release builds. Events carry page ids, disk
+//! addresses and buffer slots but no timestamps, so two runs of the same single-threaded
+//! workload give the same stream.
+//!
+//! `StructuralEvent::to_json` writes an event as one line of JSON, and `EventRecorder`
+//! appends every event it hears to a file that way. Recording a concurrency test under two
+//! versions of the crate and diffing the files shows where their behaviour parted.
+//!
+//! Listeners are called on the thread that made the change, often with page locks held, so
+//! they must not call back into the store.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Something the store allocated, freed or restructured
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StructuralEvent {
+    /// A disk page was handed out, `reused` if it came off the free list
+    DiskPageAllocated { addr: u64, reused: bool },
+    /// A disk page was zeroed and given back
+    DiskPageFreed { addr: u64 },
+    /// A mini-page slot of `bytes` was handed out at `slot`
+    MiniPageAllocated { slot: usize, bytes: usize },
+    /// The mini-page of `page` at `slot` was written back and left the buffer
+    MiniPageEvicted { page: u64, slot: usize },
+    /// `right`, written to `right_addr`, split off `left` at `pivot`
+    LeafSplit {
+        left: u64,
+        right: u64,
+        right_addr: u64,
+        pivot: Vec<u8>,
+    },
+    /// `removed` merged into its left neighbour `survivor`
+    LeafMerge { survivor: u64, removed: u64 },
+    /// `page` moved from the disk page `from` to `to`
+    LeafRelocated { page: u64, from: u64, to: u64 },
+    /// The whole tree was replaced by one of `leaves` leaves
+    TreeReplaced { leaves: u64, replaced_leaves: u64 },
+    /// The WAL records of `pages`, now on disk, were dropped from the log
+    LeavesCheckpointed { pages: Vec<u64> },
+    /// A checkpoint or clear rewrote the WAL from `bytes_before` down to `bytes_after`
+    WalTruncated { bytes_before: u64, bytes_after: u64 },
+}
+
+impl StructuralEvent {
+    /// The event as one line of JSON, without the newline. Keys are written as hex
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let _ = match self {
+            StructuralEvent::DiskPageAllocated { addr, reused } => write!(
+                out,
+                r#"{{"event":"disk_page_allocated","addr":{addr},"reused":{reused}}}"#
+            ),
+            StructuralEvent::DiskPageFreed { addr } => {
+                write!(out, r#"{{"event":"disk_page_freed","addr":{addr}}}"#)
+            }
+            StructuralEvent::MiniPageAllocated { slot, bytes } => write!(
+                out,
+                r#"{{"event":"mini_page_allocated","slot":{slot},"bytes":{bytes}}}"#
+            ),
+            StructuralEvent::MiniPageEvicted { page, slot } => write!(
+                out,
+                r#"{{"event":"mini_page_evicted","page":{page},"slot":{slot}}}"#
+            ),
+            StructuralEvent::LeafSplit {
+                left,
+                right,
+                right_addr,
+                pivot,
+            } => write!(
+                out,
+                r#"{{"event":"leaf_split","left":{left},"right":{right},"right_addr":{right_addr},"pivot":"{}"}}"#,
+                hex(pivot)
+            ),
+            StructuralEvent::LeafMerge { survivor, removed } => write!(
+                out,
+                r#"{{"event":"leaf_merge","survivor":{survivor},"removed":{removed}}}"#
+            ),
+            StructuralEvent::LeafRelocated { page, from, to } => write!(
+                out,
+                r#"{{"event":"leaf_relocated","page":{page},"from":{from},"to":{to}}}"#
+            ),
+            StructuralEvent::TreeReplaced {
+                leaves,
+                replaced_leaves,
+            } => write!(
+                out,
+                r#"{{"event":"tree_replaced","leaves":{leaves},"replaced_leaves":{replaced_leaves}}}"#
+            ),
+            StructuralEvent::LeavesCheckpointed { pages } => {
+                let pages: Vec<String> = pages.iter().map(u64::to_string).collect();
+                write!(
+                    out,
+                    r#"{{"event":"leaves_checkpointed","pages":[{}]}}"#,
+                    pages.join(",")
+                )
+            }
+            StructuralEvent::WalTruncated {
+                bytes_before,
+                bytes_after,
+            } => write!(
+                out,
+                r#"{{"event":"wal_truncated","bytes_before":{bytes_before},"bytes_after":{bytes_after}}}"#
+            ),
+        };
+        out
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+/// Told of every `StructuralEvent` of the store it is registered with
+pub trait EventListener: Send + Sync {
+    fn on_event(&self, event: &StructuralEvent);
+}
+
+impl<F> EventListener for F
+where
+    F: Fn(&StructuralEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &StructuralEvent) {
+        self(event)
+    }
+}
+
+/// Writes every event it hears to a file as JSON lines
+pub struct EventRecorder {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl EventRecorder {
+    /// Record to a new file at `path`, replacing any there
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<EventRecorder> {
+        Ok(EventRecorder {
+            out: Mutex::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// Write out the events recorded so far; dropping the recorder does too
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.lock().expect("event recorder poisoned").flush()
+    }
+}
+
+impl EventListener for EventRecorder {
+    fn on_event(&self, event: &StructuralEvent) {
+        let mut out = self.out.lock().expect("event recorder poisoned");
+        // a test that can't record has nothing to diff, but the store carries on
+        let _ = writeln!(out, "{}", event.to_json());
+    }
+}
+
+/// The listener of a store, if it has one
+#[derive(Clone, Default)]
+pub(crate) struct Events(Option<Arc<dyn EventListener>>);
+
+impl Events {
+    pub(crate) fn new(listener: Option<Arc<dyn EventListener>>) -> Events {
+        Events(listener)
+    }
+
+    /// Tell the listener of the event `event` builds, which only runs if there is one
+    pub(crate) fn emit(&self, event: impl FnOnce() -> StructuralEvent) {
+        if let Some(listener) = &self.0 {
+            listener.on_event(&event());
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Named hooks between the I/O steps of the store, to test what survives a crash at each of
+//! them. Built only with the `failpoints` feature; without it the hooks compile to nothing.
+//!
+//! A hook does nothing until a test arms it with `enable` or `enable_nth`. Armed, it fails
+//! the step it sits in front of with an injected error, panics, or crashes: it panics and
+//! every later write through a `storage::Storage` (the data file, the WAL and the sidecar
+//! logs) is discarded, as if the process had died at the hook. A test then drops the store,
+//! calls `reset` and opens it again to see what recovery makes of the files. Sidecar
+//! compactions, which write a temporary file and rename it, are not held back.
+//!
+//! Hooks are process-wide, so tests that arm them must not run alongside each other. Hooks
+//! never fire on a thread that is already panicking, nor once a crash has fired.
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+/// Before records are queued for the WAL, by `append_record` and `append_batch`
+pub const WAL_APPEND: &str = "wal::append_record";
+/// Before queued records are written to the WAL file, by commits, syncs and reads of the log
+pub const WAL_WRITE: &str = "wal::write_pending";
+/// Before the WAL is rewritten without the records of checkpointed leaves, which are
+/// already on disk
+pub const WAL_CHECKPOINT: &str = "wal::checkpoint_page";
+/// Before a leaf image is written to the data file. The write cannot report an error, so
+/// `FailAction::Error` panics here as a failed write would
+pub const WRITE_PAGE: &str = "io_engine::write_page";
+/// After the right half of a split is on disk, before the split is logged
+pub const SPLIT_RIGHT_WRITTEN: &str = "split::right_written";
+/// After a split is logged, before the narrowed left half is written
+pub const SPLIT_LOGGED: &str = "split::logged";
+/// After the survivor of a merge is on disk, before the merge is logged
+pub const MERGE_SURVIVOR_WRITTEN: &str = "merge::survivor_written";
+/// After a merge is logged, before the removed leaf's disk page is freed
+pub const MERGE_LOGGED: &str = "merge::logged";
+
+/// What an armed hook does when it is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailAction {
+    /// The step fails with an `io::Error` of kind `Other`. A split or merge that fails part
+    /// way is not rolled back in memory, so reopen the store after one
+    Error,
+    Panic,
+    /// Panic, and discard every later write until `reset`
+    Crash,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Armed {
+    action: FailAction,
+    /// Hits to let through before firing, `None` to fire on every hit
+    skip: Option<u64>,
+}
+
+#[derive(Default)]
+struct Registry {
+    armed: HashMap<String, Armed>,
+    hits: HashMap<String, u64>,
+}
+
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+fn with_registry<T>(f: impl FnOnce(&mut Registry) -> T) -> T {
+    let mut guard = match REGISTRY.lock() {
+        Ok(guard) => guard,
+        Err(poison) => poison.into_inner(),
+    };
+    f(guard.get_or_insert_with(Registry::default))
+}
+
+/// Arm `name` to take `action` on every hit until it is disabled
+pub fn enable(name: &str, action: FailAction) {
+    with_registry(|registry| {
+        registry
+            .armed
+            .insert(name.to_string(), Armed { action, skip: None })
+    });
+}
+
+/// Arm `name` to take `action` once, on its `nth` hit from now, 1 being the next
+pub fn enable_nth(name: &str, nth: u64, action: FailAction) {
+    let skip = Some(nth.saturating_sub(1));
+    with_registry(|registry| {
+        registry
+            .armed
+            .insert(name.to_string(), Armed { action, skip })
+    });
+}
+
+pub fn disable(name: &str) {
+    with_registry(|registry| registry.armed.remove(name));
+}
+
+/// Disarm every hook, zero the hit counts and end a crash, so writes reach the files again
+pub fn reset() {
+    with_registry(|registry| *registry = Registry::default());
+    CRASHED.store(false, Ordering::Release);
+}
+
+/// Times `name` was reached since the last `reset`, armed or not
+pub fn hits(name: &str) -> u64 {
+    with_registry(|registry| registry.hits.get(name).copied().unwrap_or(0))
+}
+
+/// Whether a crash has fired, writes are then discarded
+pub fn crashed() -> bool {
+    CRASHED.load(Ordering::Acquire)
+}
+
+/// Count a hit of `name` and take its action if it is armed to fire now
+pub(crate) fn hit(name: &str) -> io::Result<()> {
+    if crashed() || thread::panicking() {
+        return Ok(());
+    }
+    let action = with_registry(|registry| {
+        *registry.hits.entry(name.to_string()).or_default() += 1;
+        let armed = registry.armed.get_mut(name)?;
+        match &mut armed.skip {
+            None => Some(armed.action),
+            Some(0) => {
+                let action = armed.action;
+                registry.armed.remove(name);
+                Some(action)
+            }
+            Some(skip) => {
+                *skip -= 1;
+                None
+            }
+        }
+    });
+    match action {
+        None => Ok(()),
+        Some(FailAction::Error) => Err(io::Error::other(format!("failpoint {name}"))),
+        Some(FailAction::Panic) => panic!("failpoint {name}"),
+        Some(FailAction::Crash) => {
+            CRASHED.store(true, Ordering::Release);
+            panic!("failpoint {name} crashed the store")
+        }
+    }
+}
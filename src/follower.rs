@@ -0,0 +1,109 @@
+//! Follower stores that apply a WAL stream shipped from another store.
+//!
+//! `QuickStep::open_follower(config)` opens a store that refuses local writes with
+//! `QSError::FollowerReadOnly`, and only changes through `QuickStep::apply_wal_batch`,
+//! which takes records as `QuickStep::tail_wal` streams them from the primary. Records are
+//! held per transaction until its commit marker arrives and are then replayed by key, as
+//! recovery does, in a transaction of the follower's own: it takes the page locks any
+//! writer would, so readers of the follower see each shipped transaction whole, and it is
+//! logged to the follower's WAL, so the follower recovers like any store. Undo records are
+//! not needed, and an abort marker drops what was held for its transaction. Structure
+//! records are skipped: the follower splits and merges its leaves on its own. Values the
+//! primary keeps in overflow pages or blob files arrive whole, see `QuickStep::tail_wal`,
+//! and are stored as the follower's own config says.
+//!
+//! Replaying a record sets its key to the value the primary logged, merges included, so
+//! applying a stretch of the stream twice leaves the same state as once. A follower is
+//! seeded with a copy of the primary's data directory taken after `QuickStep::checkpoint`
+//! and fed from a sequence number no later than the one durable when the checkpoint began;
+//! if a batch fails part way, resending it is safe. Key expiries and soft-delete flags are
+//! in the header of the values logged, see `attrs`, and are kept as shipped.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{
+    error::QSError,
+    wal::{WalEntryKind, WalOp, WalRecord, WalTxnMarker, STRUCTURE_PAGE_ID},
+    Store,
+};
+
+/// What applying a batch of shipped records did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApplyStats {
+    /// Records in the batch
+    pub records: u64,
+    /// Transactions whose commit marker was in the batch, replayed on the follower
+    pub txns_applied: u64,
+    /// Transactions whose abort marker was in the batch, dropped
+    pub txns_discarded: u64,
+}
+
+/// The keys a shipped transaction wrote and what it wrote to them, in log order
+type ShippedOps = Vec<(Vec<u8>, WalOp)>;
+
+/// Records of shipped transactions whose commit or abort has not arrived yet
+#[derive(Default)]
+pub(crate) struct Follower {
+    pending: Mutex<HashMap<u64, ShippedOps>>,
+}
+
+/// Apply `records`, in the order the primary logged them, to the follower `db`
+pub(crate) fn apply(
+    db: &Store,
+    follower: &Follower,
+    records: impl IntoIterator<Item = WalRecord>,
+) -> Result<ApplyStats, QSError> {
+    // one batch at a time, so shipped transactions commit in the primary's order
+    let mut pending = follower.pending.lock().expect("follower mutex poisoned");
+    let mut stats = ApplyStats::default();
+    for record in records {
+        stats.records += 1;
+        match record.op {
+            WalOp::TxnMarker(WalTxnMarker::Begin | WalTxnMarker::Prepare) | WalOp::Smo(_) => {}
+            WalOp::TxnMarker(WalTxnMarker::Abort) => {
+                pending.remove(&record.txn_id);
+                stats.txns_discarded += 1;
+            }
+            WalOp::TxnMarker(WalTxnMarker::Commit) => {
+                let ops = pending.remove(&record.txn_id).unwrap_or_default();
+                if let Err(err) = replay_txn(db, &ops) {
+                    // kept for the batch to be sent again, the ops replayed twice end the same
+                    pending.insert(record.txn_id, ops);
+                    return Err(err);
+                }
+                stats.txns_applied += 1;
+            }
+            op => {
+                if record.page_id == STRUCTURE_PAGE_ID || matches!(record.kind, WalEntryKind::Undo)
+                {
+                    continue;
+                }
+                pending
+                    .entry(record.txn_id)
+                    .or_default()
+                    .push((record.key, op));
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Replay a transaction's redo records, as shipped or as resolved after recovery, in a new
+/// transaction of `db`
+pub(crate) fn replay_txn(db: &Store, ops: &[(Vec<u8>, WalOp)]) -> Result<(), QSError> {
+    let mut tx = db.tx();
+    tx.replica = true;
+    for (key, op) in ops {
+        let res = match op {
+            WalOp::Put { value } | WalOp::Merge { value, .. } => tx.put_stored(key, value),
+            WalOp::Tombstone => tx.delete(key).map(|_| ()),
+            WalOp::TxnMarker(_) | WalOp::Smo(_) => Ok(()),
+        };
+        if let Err(err) = res {
+            tx.abort();
+            return Err(err);
+        }
+    }
+    tx.commit();
+    Ok(())
+}
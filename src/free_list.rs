@@ -0,0 +1,128 @@
+//! Disk pages given back by leaf merges, kept in the data file's metadata page.
+//!
+//! | magic: "QSFL" | count: u32 | addrs: count * u64 | tag: 16 bytes |
+//!
+//! The list sits at `FREE_LIST_OFFSET`, past the superblock, and the tag authenticates the
+//! bytes before it, see `auth`. An all-zero region is an empty list, as in files from before
+//! the free list. The region holds `FREE_LIST_CAPACITY` addresses; a page freed while it is
+//! full is left unused.
+//!
+//! A freed page is zeroed before it joins the list, and taking a page off the list is synced
+//! before the page is handed out, so a crash can leak a free page but never hands out one
+//! that is in use.
+
+use std::io;
+
+use crate::{
+    auth::{MetadataAuth, TAG_LEN},
+    io_engine::PAGE_SIZE,
+};
+
+pub const FREE_LIST_MAGIC: [u8; 4] = *b"QSFL";
+/// Where the list starts in the metadata page
+pub const FREE_LIST_OFFSET: u64 = 512;
+const HEADER_LEN: usize = 8;
+/// Bytes the list may take
+pub const FREE_LIST_LEN: usize = PAGE_SIZE as usize - FREE_LIST_OFFSET as usize;
+/// Most addresses the list holds
+pub const FREE_LIST_CAPACITY: usize = (FREE_LIST_LEN - HEADER_LEN - TAG_LEN) / 8;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FreeList {
+    /// Taken from the back
+    addrs: Vec<u64>,
+}
+
+impl FreeList {
+    /// Add `addr`, false if the list is full
+    pub fn push(&mut self, addr: u64) -> bool {
+        debug_assert!(!self.addrs.contains(&addr), "page {addr} freed twice");
+        if self.addrs.len() >= FREE_LIST_CAPACITY {
+            return false;
+        }
+        self.addrs.push(addr);
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u64> {
+        self.addrs.pop()
+    }
+
+    /// Take the lowest address below `limit`, so compaction fills the start of the file
+    pub fn take_lowest_below(&mut self, limit: u64) -> Option<u64> {
+        let (idx, _) = self
+            .addrs
+            .iter()
+            .enumerate()
+            .filter(|(_, addr)| **addr < limit)
+            .min_by_key(|(_, addr)| **addr)?;
+        Some(self.addrs.swap_remove(idx))
+    }
+
+    /// Drop `addr` from the list, false if it is not on it
+    pub fn remove(&mut self, addr: u64) -> bool {
+        match self.addrs.iter().position(|listed| *listed == addr) {
+            Some(idx) => {
+                self.addrs.swap_remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.addrs.contains(&addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    /// The whole region, zero past the tag
+    pub fn encode(&self, auth: &MetadataAuth) -> Vec<u8> {
+        let mut buf = vec![0u8; FREE_LIST_LEN];
+        buf[0..4].copy_from_slice(&FREE_LIST_MAGIC);
+        buf[4..8].copy_from_slice(&(self.addrs.len() as u32).to_le_bytes());
+        let body_len = HEADER_LEN + self.addrs.len() * 8;
+        for (idx, addr) in self.addrs.iter().enumerate() {
+            let at = HEADER_LEN + idx * 8;
+            buf[at..at + 8].copy_from_slice(&addr.to_le_bytes());
+        }
+        let tag = auth.tag(&buf[..body_len]);
+        buf[body_len..body_len + TAG_LEN].copy_from_slice(&tag);
+        buf
+    }
+
+    /// An empty list for an all-zero region, `InvalidData` for anything else that is not a
+    /// free list whose tag checks out under `auth`
+    pub fn decode(bytes: &[u8], auth: &MetadataAuth) -> io::Result<FreeList> {
+        let bytes = &bytes[..FREE_LIST_LEN];
+        if bytes.iter().all(|b| *b == 0) {
+            return Ok(FreeList::default());
+        }
+        if bytes[0..4] != FREE_LIST_MAGIC {
+            return Err(invalid("metadata page has no free list"));
+        }
+        let count = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        if count > FREE_LIST_CAPACITY {
+            return Err(invalid("free list longer than its region"));
+        }
+        let body_len = HEADER_LEN + count * 8;
+        if !auth.verify(&bytes[..body_len], &bytes[body_len..body_len + TAG_LEN]) {
+            return Err(invalid("free list failed authentication"));
+        }
+        let addrs = bytes[HEADER_LEN..body_len]
+            .chunks_exact(8)
+            .map(|addr| u64::from_le_bytes(addr.try_into().unwrap()))
+            .collect();
+        Ok(FreeList { addrs })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,28 @@
+//! Hash-partitioned leaves for keyspaces that only see point lookups.
+//!
+//! With `QuickStepConfig::with_hash_index(buckets)` the store is laid out as a fixed number
+//! of leaf pages, one per bucket, and a key's bucket is picked by hashing it, so reads and
+//! writes go straight to the mapping table without traversing inner nodes. Buckets are
+//! ordinary leaves: they are cached as mini-pages, evicted, logged and replayed like any
+//! other. They never split, a write to a full bucket fails with `HashBucketFull`, and as
+//! keys are scattered across buckets range scans fail with `OrderedScanUnsupported`.
+//!
+//! The bucket count is recorded in the superblock, a data file opened with another count
+//! (or without a hash index) panics.
+
+use crate::map_table::PageId;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a, fixed so bucket assignments stay the same across builds
+pub fn hash_key(key: &[u8]) -> u64 {
+    key.iter().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// The leaf page holding `key` in a store with `buckets` buckets
+pub fn bucket_for(key: &[u8], buckets: u64) -> PageId {
+    PageId::from_u64(hash_key(key) % buckets)
+}
@@ -0,0 +1,147 @@
+//! WAL size limit, inner tree capacity and health events.
+//!
+//! With a maximum WAL size configured, a write that finds the log at the limit first
+//! checkpoints leaves on the log's behalf, so writers slow to the pace checkpointing can
+//! sustain. If that frees nothing, the write goes ahead under `WalLimitPolicy::Throttle` and
+//! fails with `QSError::WalFull` under `WalLimitPolicy::Fail`. Subscribers are told each time
+//! the pressure level changes, on its way up and on its way back down.
+//!
+//! A leaf split sets aside the inner nodes it may need before it moves any records. When
+//! there are not enough left, `InnerTreeFullPolicy::Grow` raises the capacity of the inner
+//! node buffer and tries again, and otherwise the write fails with `QSError::TreeFull`,
+//! leaving the leaf as it was. Subscribers are told of each refused split and each growth.
+//! Leaf merges only give back a root, so they are no way to make room.
+//!
+//! With a WAL audit configured, a background thread reads the log file back at an interval
+//! and compares its manifest, length and per-page record counts with what the store
+//! believes it wrote. Opening would trim or rewrite a disagreeing file without a word, so
+//! subscribers are told of each mismatch when it is first found instead.
+
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    mpsc::{self, Receiver, Sender},
+    Mutex,
+};
+
+use crate::wal::WalMismatch;
+
+/// Share of the maximum past which the log counts as approaching it, in percent
+const ELEVATED_PERCENT: usize = 80;
+
+/// What a write does once the WAL is at its limit and checkpointing frees nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalLimitPolicy {
+    /// Go ahead, having paid for the checkpoint attempt
+    #[default]
+    Throttle,
+    /// Fail with `QSError::WalFull`
+    Fail,
+}
+
+/// What a leaf split does when the inner tree has no room for the nodes it may need
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InnerTreeFullPolicy {
+    /// Fail the write with `QSError::TreeFull`
+    #[default]
+    Fail,
+    /// Double the inner nodes the tree has room for, up to `max_inner_nodes`, then fail
+    Grow { max_inner_nodes: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WalPressure {
+    Normal,
+    /// Past 80% of the maximum
+    Elevated,
+    AtLimit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthEvent {
+    /// The WAL moved to a new pressure level
+    WalPressure {
+        pressure: WalPressure,
+        wal_bytes: usize,
+        max_wal_bytes: usize,
+    },
+    /// A split needed `needed` inner nodes the tree did not have and its write failed
+    InnerTreeFull { capacity: u32, needed: usize },
+    /// The inner tree grew to room for `capacity` nodes
+    InnerTreeGrown { capacity: u32 },
+    /// A WAL audit found the file disagreeing with the log's own accounting
+    WalMismatch(WalMismatch),
+    /// Transaction `txn_id` committed without its activity log entries, which could not be
+    /// written, see `activity_log`
+    ActivityLogSkipped { txn_id: u64, reason: String },
+    /// A pass of the background thread `task` failed, the next pass tries again
+    BackgroundPassFailed { task: &'static str, reason: String },
+}
+
+pub struct WalLimit {
+    /// 0 leaves the log unbounded
+    max_bytes: usize,
+    policy: WalLimitPolicy,
+    /// Last reported `WalPressure`
+    level: AtomicU8,
+    subscribers: Mutex<Vec<Sender<HealthEvent>>>,
+}
+
+impl WalLimit {
+    pub fn new(max_bytes: Option<usize>, policy: WalLimitPolicy) -> WalLimit {
+        WalLimit {
+            max_bytes: max_bytes.unwrap_or(0),
+            policy,
+            level: AtomicU8::new(WalPressure::Normal as u8),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn max_bytes(&self) -> Option<usize> {
+        (self.max_bytes > 0).then_some(self.max_bytes)
+    }
+
+    pub fn policy(&self) -> WalLimitPolicy {
+        self.policy
+    }
+
+    pub fn pressure(&self, wal_bytes: usize) -> WalPressure {
+        if self.max_bytes == 0 {
+            WalPressure::Normal
+        } else if wal_bytes >= self.max_bytes {
+            WalPressure::AtLimit
+        } else if wal_bytes.saturating_mul(100) >= self.max_bytes.saturating_mul(ELEVATED_PERCENT) {
+            WalPressure::Elevated
+        } else {
+            WalPressure::Normal
+        }
+    }
+
+    /// Pressure at `wal_bytes`, telling subscribers if it differs from the last report
+    pub fn observe(&self, wal_bytes: usize) -> WalPressure {
+        let pressure = self.pressure(wal_bytes);
+        let previous = self.level.swap(pressure as u8, Ordering::AcqRel);
+        if previous != pressure as u8 {
+            self.publish(HealthEvent::WalPressure {
+                pressure,
+                wal_bytes,
+                max_wal_bytes: self.max_bytes,
+            });
+        }
+        pressure
+    }
+
+    /// Receive an event each time the health of the store changes
+    pub fn subscribe(&self) -> Receiver<HealthEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .expect("health mutex poisoned")
+            .push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: HealthEvent) {
+        let mut subscribers = self.subscribers.lock().expect("health mutex poisoned");
+        subscribers.retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
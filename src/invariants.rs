@@ -0,0 +1,231 @@
+//! Structural checks of the leaf layer, for test suites that want to assert the tree is
+//! healthy after a workload.
+//!
+//! `check_leaf` reads one leaf under its read lock and checks its own records: the record
+//! count agrees with the page, the page names the leaf, the fences are ordered, keys are
+//! strictly increasing and every key lies within the fences. A
+//! mini-page that caches part of a leaf is checked along with the disk leaf under it.
+//! `check_all` checks every leaf, walking the ordered tree by its pivots so that each leaf's
+//! fences must also match the pivots routing to it, or every bucket of a hash-index store.
+//! Leaves are read one at a time, so run the checks while no writers are active.
+//! `QuickStep::verify` runs the same leaf checks from a walk of the inner nodes, see `verify`.
+
+use std::mem::size_of;
+
+use crate::{
+    error::QSError,
+    hash_index,
+    iter::{LOWER_SENTINEL, UPPER_SENTINEL},
+    map_table::PageId,
+    types::{KVMeta, NodeMeta, NodeRef, NodeSize},
+    Store,
+};
+
+/// The first broken invariant a check ran into
+#[derive(Debug)]
+pub enum InvariantViolation {
+    /// The leaf could not be read
+    Read { page_id: PageId, err: QSError },
+    /// The record count does not agree with the page: fewer than the two fences, or the
+    /// metadata of that many records or a record itself running past the page
+    RecordCountMismatch { page_id: PageId, record_count: u16 },
+    /// The page the leaf's entry points at names another leaf
+    WrongPageId { page_id: PageId, found: PageId },
+    /// The lower fence is not below the upper fence
+    FencesOutOfOrder {
+        page_id: PageId,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+    },
+    /// `key` does not sort after the key stored before it
+    KeysOutOfOrder {
+        page_id: PageId,
+        prev: Vec<u8>,
+        key: Vec<u8>,
+    },
+    /// `key` lies outside the leaf's fences
+    KeyOutsideFences {
+        page_id: PageId,
+        key: Vec<u8>,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+    },
+    /// A fence differs from the pivot the inner nodes route by, or the sentinel at either end
+    /// of the key space
+    PivotMismatch {
+        page_id: PageId,
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+    /// A hash-index key is stored in a bucket it does not hash to
+    WrongBucket { page_id: PageId, key: Vec<u8> },
+}
+
+/// Check the records and fences of one leaf
+pub fn check_leaf(db: &Store, page_id: PageId) -> Result<(), InvariantViolation> {
+    read_checked_leaf(db, page_id).map(|_| ())
+}
+
+/// Check every leaf, returns how many were checked
+pub fn check_all(db: &Store) -> Result<usize, InvariantViolation> {
+    if let Some(buckets) = db.hash_index_buckets() {
+        for bucket in 0..buckets {
+            let page_id = PageId::from_u64(bucket);
+            let leaf = read_checked_leaf(db, page_id)?;
+            if let Some(key) = leaf
+                .keys
+                .into_iter()
+                .find(|key| hash_index::bucket_for(key, buckets) != page_id)
+            {
+                return Err(InvariantViolation::WrongBucket { page_id, key });
+            }
+        }
+        return Ok(buckets as usize);
+    }
+
+    let mut checked = 0;
+    let mut lower = LOWER_SENTINEL.to_vec();
+    loop {
+        let (page_id, successor) =
+            db.inner_nodes
+                .read_leaf_and_successor(&lower)
+                .map_err(|err| InvariantViolation::Read {
+                    page_id: PageId::from_u64(0),
+                    err,
+                })?;
+        let leaf = read_checked_leaf(db, page_id)?;
+        checked += 1;
+        expect_fence(page_id, &lower, &leaf.lower)?;
+        match successor {
+            Some(pivot) => {
+                expect_fence(page_id, &pivot, &leaf.upper)?;
+                lower = pivot.into_vec();
+            }
+            None => {
+                expect_fence(page_id, UPPER_SENTINEL, &leaf.upper)?;
+                return Ok(checked);
+            }
+        }
+    }
+}
+
+/// Fences and user keys of a leaf that passed `check_leaf`
+pub(crate) struct CheckedLeaf {
+    pub(crate) lower: Vec<u8>,
+    pub(crate) upper: Vec<u8>,
+    /// Tombstones included, a key cached in a mini-page and on disk is here twice
+    pub(crate) keys: Vec<Vec<u8>>,
+}
+
+pub(crate) fn read_checked_leaf(
+    db: &Store,
+    page_id: PageId,
+) -> Result<CheckedLeaf, InvariantViolation> {
+    let read = |err| InvariantViolation::Read { page_id, err };
+    let guard = db.map_table.read_page_entry(page_id).map_err(read)?;
+    match guard.node() {
+        NodeRef::Leaf(addr) => {
+            let leaf = db.io_engine.get_page(addr).map_err(read)?;
+            check_node(page_id, leaf.as_ref())
+        }
+        NodeRef::MiniPage(index) => {
+            // SAFETY: we hold a read lock on the page
+            let meta = unsafe { db.cache.get_meta_ref(index) };
+            let mut checked = check_node(page_id, meta)?;
+            if !matches!(meta.size(), NodeSize::LeafPage) {
+                let leaf = db.io_engine.get_page(meta.leaf()).map_err(read)?;
+                checked
+                    .keys
+                    .extend(check_node(page_id, leaf.as_ref())?.keys);
+            }
+            Ok(checked)
+        }
+    }
+}
+
+/// Check one leaf image, tombstones included, against its own fences
+pub(crate) fn check_node(
+    page_id: PageId,
+    meta: &NodeMeta,
+) -> Result<CheckedLeaf, InvariantViolation> {
+    check_record_count(page_id, meta)?;
+    if meta.page_id() != page_id {
+        return Err(InvariantViolation::WrongPageId {
+            page_id,
+            found: meta.page_id(),
+        });
+    }
+    let (lower, upper) = meta.fence_bounds();
+    let unbounded = lower.as_slice() == LOWER_SENTINEL || upper.as_slice() == UPPER_SENTINEL;
+    if lower >= upper && !unbounded {
+        return Err(InvariantViolation::FencesOutOfOrder {
+            page_id,
+            lower,
+            upper,
+        });
+    }
+
+    let prefix = meta.get_node_prefix();
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    for i in 0..meta.record_count() as usize {
+        let kv = meta.get_kv_meta(i);
+        if kv.fence() {
+            continue;
+        }
+        let mut key = prefix.to_vec();
+        key.extend_from_slice(meta.get_stored_key_from_meta(kv));
+        if let Some(prev) = keys.last().filter(|prev| **prev >= key) {
+            return Err(InvariantViolation::KeysOutOfOrder {
+                page_id,
+                prev: prev.clone(),
+                key,
+            });
+        }
+        let above_lower = lower.as_slice() == LOWER_SENTINEL || key >= lower;
+        let below_upper = upper.as_slice() == UPPER_SENTINEL || key < upper;
+        if !(above_lower && below_upper) {
+            return Err(InvariantViolation::KeyOutsideFences {
+                page_id,
+                key,
+                lower,
+                upper,
+            });
+        }
+        keys.push(key);
+    }
+    Ok(CheckedLeaf { lower, upper, keys })
+}
+
+/// The record metadata and every record lie inside the page, before the records are read
+fn check_record_count(page_id: PageId, meta: &NodeMeta) -> Result<(), InvariantViolation> {
+    let record_count = meta.record_count();
+    let page_bytes = meta.size().size_in_bytes();
+    let records_start = size_of::<NodeMeta>() + record_count as usize * size_of::<KVMeta>();
+    let in_page = |idx| {
+        let kv = meta.get_kv_meta(idx);
+        let end = kv.offset() + (kv.key_size() + kv.val_size()) as usize;
+        kv.offset() >= records_start && end <= page_bytes
+    };
+    if record_count >= 2 && records_start <= page_bytes && (0..record_count as usize).all(in_page) {
+        return Ok(());
+    }
+    Err(InvariantViolation::RecordCountMismatch {
+        page_id,
+        record_count,
+    })
+}
+
+pub(crate) fn expect_fence(
+    page_id: PageId,
+    expected: &[u8],
+    found: &[u8],
+) -> Result<(), InvariantViolation> {
+    if expected == found {
+        return Ok(());
+    }
+    Err(InvariantViolation::PivotMismatch {
+        page_id,
+        expected: expected.to_vec(),
+        found: found.to_vec(),
+    })
+}
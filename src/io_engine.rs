@@ -1,78 +1,773 @@
+use std::path::{Path, PathBuf};
+
 use std::{
-    fs::{self, File, OpenOptions},
-    os::unix::fs::FileExt,
-    path::Path,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    auth::MetadataAuth,
+    blob::{BlobLog, BlobPointer},
+    compress::{compress, decompress},
+    error::{CapacityLimit, QSError},
+    events::{Events, StructuralEvent},
+    free_list::{FreeList, FREE_LIST_LEN, FREE_LIST_OFFSET},
+    latency::LatencyInjector,
+    leaf_cache::{LeafCache, LeafCacheStats, DEFAULT_LEAF_CACHE_PAGES},
+    map_table::PageId,
+    merge::MergeOperator,
+    overflow::{self, OverflowPointer},
+    page_map::PageMap,
+    read_repair::ReadRepairs,
+    storage::Storage,
+    superblock::{CrateVersion, Superblock, FLAG_COMPRESSED, SUPERBLOCK_LEN, SUPERBLOCK_VERSION},
+    txn_ids::{self, TXN_IDS_LEN, TXN_IDS_OFFSET},
+    types::{page_from_disk_order, page_to_disk_order, NodeMeta},
+    utils::crc32,
 };
 
-use std::sync::atomic::{AtomicU64, Ordering};
+pub const PAGE_SIZE: u64 = 4096;
+/// Disk addresses are stored in 48 bit fields (`NodeMeta`, `PageEntry`)
+pub const MAX_DISK_PAGES: u64 = 1 << 48;
+/// Largest file the format can address: the metadata page plus every data page
+pub const MAX_DB_BYTES: u64 = (MAX_DISK_PAGES + 1) * PAGE_SIZE;
+
+/// Pages stored by a compressed data file and the bytes they take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageCompressionStats {
+    pub pages: u64,
+    /// Compressed images of the current pages
+    pub stored_bytes: u64,
+    /// Space taken in the file, including block capacity and blocks left behind by rewrites
+    pub block_bytes: u64,
+}
 
-use crate::types::NodeMeta;
+impl PageCompressionStats {
+    /// Stored bytes per byte of uncompressed page, 0.0 with no pages
+    pub fn ratio(&self) -> f64 {
+        match self.pages {
+            0 => 0.0,
+            pages => self.stored_bytes as f64 / (pages * PAGE_SIZE) as f64,
+        }
+    }
+}
 
 pub struct IoEngine {
-    file: File,
+    file: Storage,
     next_addr: AtomicU64,
+    /// Addresses at or past this are never handed out
+    max_pages: u64,
+    /// Recently read leaves, so repeated reads of a cold leaf skip the file
+    leaf_cache: LeafCache,
+    /// Bytes written to the data file since open
+    bytes_written: AtomicU64,
+    /// Page images read from the data file since open
+    pages_read: AtomicU64,
+    superblock: Superblock,
+    /// The superblock was written by this open, the file had none
+    formatted: bool,
+    /// Where the page map of a compressed file is kept, `None` in memory
+    page_map_path: Option<PathBuf>,
+    /// Blocks of a file storing compressed pages, see `page_map`
+    page_map: Option<PageMap>,
+    /// Pages freed by merges, handed out before the file grows, see `free_list`
+    free_list: Mutex<FreeList>,
+    /// Key the free list is tagged under
+    auth: MetadataAuth,
+    /// Transaction ids reserved when the file was opened, see `txn_ids`
+    txn_id_mark: u64,
+    /// Leaves the WAL is ahead of, repaired as they are read, see `read_repair`
+    read_repairs: ReadRepairs,
+    /// Told of each page allocated and freed, see `events`
+    events: Events,
+    /// Files holding values too long even for overflow pages, see `blob`
+    blob_log: Option<BlobLog>,
+    /// Folds the merge operands records still hold, see `merge`
+    merge_operator: Option<Arc<dyn MergeOperator>>,
 }
 
 impl IoEngine {
     pub fn open(path: &Path) -> std::io::Result<IoEngine> {
-        if let Some(parent) = path.parent() {
-            if !parent.as_os_str().is_empty() {
-                fs::create_dir_all(parent)?;
-            }
-        }
+        Self::open_with_max_pages(path, MAX_DISK_PAGES)
+    }
+
+    /// Open the data file, refusing to grow it past `max_pages` data pages
+    pub fn open_with_max_pages(path: &Path, max_pages: u64) -> std::io::Result<IoEngine> {
+        Self::open_with_auth(path, max_pages, &MetadataAuth::default())
+    }
+
+    /// Like `open_with_max_pages`, failing with `InvalidData` if the superblock does not
+    /// authenticate under `auth`. A new file gets a superblock tagged under it. The file is
+    /// locked while the engine is open, another open of it fails with `WouldBlock`, see
+    /// `FileLocked`
+    pub fn open_with_auth(
+        path: &Path,
+        max_pages: u64,
+        auth: &MetadataAuth,
+    ) -> std::io::Result<IoEngine> {
+        let file = Storage::open(path)?;
+        file.try_lock()?;
+        Self::with_storage(file, max_pages, auth, Some(path.with_extension("pagemap")))
+    }
 
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
+    /// Open an existing data file for reading without taking its lock, to inspect a store
+    /// another process has open. Nothing can be written through it, and a page the other
+    /// process is writing may read back torn
+    pub fn open_read_only(path: &Path, auth: &MetadataAuth) -> std::io::Result<IoEngine> {
+        Self::with_storage(
+            Storage::open_read_only(path)?,
+            MAX_DISK_PAGES,
+            auth,
+            Some(path.with_extension("pagemap")),
+        )
+    }
+
+    /// Keep the pages in memory instead of a file, they are lost once the engine is dropped
+    pub fn in_memory(max_pages: u64, auth: &MetadataAuth) -> std::io::Result<IoEngine> {
+        Self::with_storage(Storage::memory(), max_pages, auth, None)
+    }
 
+    fn with_storage(
+        file: Storage,
+        max_pages: u64,
+        auth: &MetadataAuth,
+        page_map_path: Option<PathBuf>,
+    ) -> std::io::Result<IoEngine> {
         // Ensure at least metadata page + first data page exist
         let min_len = 2 * 4096;
-        let mut current_len = file.metadata()?.len();
+        let mut current_len = file.len()?;
         if current_len < min_len {
-            file.set_len(min_len as u64)?;
-            current_len = min_len as u64;
+            file.set_len(min_len)?;
+            current_len = min_len;
         }
 
-        let next_addr = (current_len / 4096).saturating_sub(1);
+        let mut superblock = [0u8; SUPERBLOCK_LEN];
+        file.read_exact_at(&mut superblock, 0)?;
+        let (superblock, formatted) = match Superblock::decode(&superblock, auth)? {
+            Some(superblock) if superblock.version != SUPERBLOCK_VERSION => {
+                // written before the crate version was recorded, upgrade it in place
+                let upgraded = Superblock {
+                    version: SUPERBLOCK_VERSION,
+                    written_by: Some(CrateVersion::current()),
+                    ..superblock
+                };
+                file.write_all_at(&upgraded.encode(auth), 0)?;
+                file.sync_data()?;
+                (upgraded, false)
+            }
+            Some(superblock) => (superblock, false),
+            None => {
+                file.write_all_at(&Superblock::new().encode(auth), 0)?;
+                file.sync_data()?;
+                (Superblock::new(), true)
+            }
+        };
+
+        let mut free_list = vec![0u8; FREE_LIST_LEN];
+        file.read_exact_at(&mut free_list, FREE_LIST_OFFSET)?;
+        let free_list = FreeList::decode(&free_list, auth)?;
+        let mut txn_id_mark = [0u8; TXN_IDS_LEN];
+        file.read_exact_at(&mut txn_id_mark, TXN_IDS_OFFSET)?;
+        let txn_id_mark = txn_ids::decode(&txn_id_mark, auth)?;
+
+        let page_map = match superblock.compressed() {
+            true => Some(open_page_map(page_map_path.as_deref())?),
+            false => None,
+        };
+        // pages handed out but never written leave no trace in the file, only in the mark
+        let next_addr = match &page_map {
+            Some(page_map) => page_map.addr_end().max(1),
+            None => (current_len / 4096).saturating_sub(1),
+        }
+        .max(superblock.next_addr);
 
         Ok(IoEngine {
             file,
             next_addr: AtomicU64::new(next_addr),
+            max_pages: max_pages.min(MAX_DISK_PAGES),
+            leaf_cache: LeafCache::new(DEFAULT_LEAF_CACHE_PAGES),
+            bytes_written: AtomicU64::new(0),
+            pages_read: AtomicU64::new(0),
+            superblock,
+            formatted,
+            page_map_path,
+            page_map,
+            free_list: Mutex::new(free_list),
+            auth: auth.clone(),
+            txn_id_mark,
+            read_repairs: ReadRepairs::default(),
+            events: Events::default(),
+            blob_log: None,
+            merge_operator: None,
         })
     }
 
-    /// Get the page of the given address
-    pub fn get_page(&self, page_addr: u64) -> DiskLeaf {
-        let mut out: Box<[u8; 4096]> = Box::new([0u8; 4096]);
+    /// Store pages as compressed blocks, or as plain 4K pages. A file keeps the setting it
+    /// was created with: claiming the other fails with `InvalidData`
+    pub fn claim_compression(
+        &mut self,
+        compressed: bool,
+        auth: &MetadataAuth,
+    ) -> std::io::Result<()> {
+        if self.superblock.compressed() == compressed {
+            return Ok(());
+        }
+        if !self.formatted {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "data file was created with a different page compression setting",
+            ));
+        }
+        self.superblock.flags ^= FLAG_COMPRESSED;
+        self.write_superblock(auth)?;
+        self.file.sync_data()?;
+        self.page_map = match compressed {
+            true => Some(open_page_map(self.page_map_path.as_deref())?),
+            false => None,
+        };
+        Ok(())
+    }
 
-        let offset = calc_offset(page_addr);
+    pub fn is_compressed(&self) -> bool {
+        self.page_map.is_some()
+    }
 
-        self.file
-            .read_exact_at(out.as_mut_slice(), offset)
-            .expect("todo");
+    /// How well the stored pages compress, `None` for uncompressed files
+    pub fn compression_stats(&self) -> Option<PageCompressionStats> {
+        self.page_map.as_ref().map(|page_map| PageCompressionStats {
+            pages: page_map.len() as u64,
+            stored_bytes: page_map.stored_bytes(),
+            block_bytes: page_map.block_bytes(),
+        })
+    }
+
+    /// Leaf pages of a hash-index store, 0 for the ordered tree
+    pub fn hash_buckets(&self) -> u64 {
+        self.superblock.hash_buckets
+    }
+
+    /// Lay the file out as a hash index of `buckets` leaves, or the ordered tree for 0. A file
+    /// keeps the layout it was created with: claiming another fails with `InvalidData`
+    pub fn claim_layout(&mut self, buckets: u64, auth: &MetadataAuth) -> std::io::Result<()> {
+        if buckets > self.max_pages {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "more hash buckets than the data file may hold pages",
+            ));
+        }
+        if self.superblock.hash_buckets == buckets {
+            return Ok(());
+        }
+        if !self.formatted {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "data file was created with a different hash index layout",
+            ));
+        }
+        self.superblock.hash_buckets = buckets;
+        // the bucket leaves sit at the first addresses
+        self.next_addr.fetch_max(buckets, Ordering::AcqRel);
+        self.write_superblock(auth)?;
+        let len = calc_offset(buckets);
+        if self.file.len()? < len {
+            self.file.set_len(len)?;
+        }
+        self.file.sync_data()?;
+        Ok(())
+    }
 
-        DiskLeaf { inner: out }
+    /// Write the superblock with the allocator's current high-water mark
+    fn write_superblock(&self, auth: &MetadataAuth) -> std::io::Result<()> {
+        let superblock = Superblock {
+            next_addr: self.next_addr.load(Ordering::Acquire),
+            ..self.superblock
+        };
+        self.file.write_all_at(&superblock.encode(auth), 0)
+    }
+
+    /// Keep up to `pages` recently read leaves in memory, 0 disables the cache
+    pub fn with_leaf_cache(mut self, pages: usize) -> IoEngine {
+        self.leaf_cache = LeafCache::new(pages);
+        self
+    }
+
+    /// Tell `events` of each page allocated and freed
+    pub(crate) fn with_events(mut self, events: Events) -> IoEngine {
+        self.events = events;
+        self
+    }
+
+    /// Follow blob pointers into `blob_log`, and let writers append to it
+    pub fn with_blob_log(mut self, blob_log: BlobLog) -> IoEngine {
+        self.blob_log = Some(blob_log);
+        self
+    }
+
+    pub fn blob_log(&self) -> Option<&BlobLog> {
+        self.blob_log.as_ref()
+    }
+
+    /// Fold the operands of pending merges through `operator` as they are read, flushed and
+    /// replayed
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> IoEngine {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    pub fn merge_operator(&self) -> Option<&dyn MergeOperator> {
+        self.merge_operator.as_deref()
+    }
+
+    /// Hold up the data file's reads, writes and syncs by `injector`'s latency, see
+    /// `latency`
+    pub fn with_io_latency(mut self, injector: Arc<LatencyInjector>) -> IoEngine {
+        let file = mem::replace(&mut self.file, Storage::memory());
+        self.file = file.delayed(injector);
+        self
+    }
+
+    /// Get the page of the given address, failing with `PageCorrupt` if its checksum does not
+    /// match
+    pub fn get_page(&self, page_addr: u64) -> Result<DiskLeaf, QSError> {
+        let read = || self.read_image(page_addr).map(|inner| DiskLeaf { inner });
+        if let Some(repaired) = self
+            .read_repairs
+            .get(page_addr, read, self.merge_operator())
+        {
+            return repaired;
+        }
+        if let Some(leaf) = self.leaf_cache.get(page_addr) {
+            return Ok(leaf);
+        }
+
+        let out = self.read_image(page_addr)?;
+        let leaf = DiskLeaf { inner: out };
+        self.leaf_cache.insert(page_addr, &leaf);
+        Ok(leaf)
+    }
+
+    /// Write the leaf at `page_addr` back if it is waiting for read repair, repairing it
+    /// first if it was never read
+    pub(crate) fn write_back_repair(&self, page_addr: u64) -> Result<(), QSError> {
+        let read = || self.read_image(page_addr).map(|inner| DiskLeaf { inner });
+        if let Some(repaired) = self
+            .read_repairs
+            .get(page_addr, read, self.merge_operator())
+        {
+            self.write_page(page_addr, &repaired?);
+        }
+        Ok(())
     }
 
     /// Write the page of the given address
     pub fn write_page(&self, page_addr: u64, leaf: &DiskLeaf) {
+        #[cfg(feature = "failpoints")]
+        crate::failpoints::hit(crate::failpoints::WRITE_PAGE).expect("failed to write page");
+        self.write_image(page_addr, leaf);
+        self.leaf_cache.write(page_addr, leaf);
+        self.read_repairs.retire(page_addr);
+    }
+
+    /// Write `value` to a chain of new overflow pages and sync them, see `overflow`. On
+    /// failure the pages taken so far are freed
+    pub fn write_overflow(&self, value: &[u8]) -> Result<OverflowPointer, QSError> {
+        let pointer = OverflowPointer {
+            head: 0,
+            len: value.len() as u64,
+            crc: crc32(value),
+        };
+        let mut addrs = Vec::with_capacity(pointer.pages() as usize);
+        for _ in 0..pointer.pages() {
+            match self.get_new_addr() {
+                Ok(addr) => addrs.push(addr),
+                Err(err) => {
+                    addrs.into_iter().for_each(|addr| self.free_page(addr));
+                    return Err(err);
+                }
+            }
+        }
+        for (idx, payload) in value.chunks(overflow::PAGE_PAYLOAD).enumerate() {
+            let page = overflow::write_page(addrs.get(idx + 1).copied(), payload);
+            self.write_image(addrs[idx], &page);
+        }
+        self.sync_data()?;
+        Ok(OverflowPointer {
+            head: addrs[0],
+            ..pointer
+        })
+    }
+
+    /// Read a value back from its overflow chain, failing with `OverflowCorrupt` if the
+    /// chain does not hold what the pointer describes. Bypasses the leaf cache
+    pub fn read_overflow(&self, pointer: OverflowPointer) -> Result<Vec<u8>, QSError> {
+        let corrupt = QSError::OverflowCorrupt { addr: pointer.head };
+        let mut value = Vec::with_capacity(pointer.len as usize);
+        let mut next = Some(pointer.head);
+        for _ in 0..pointer.pages() {
+            let Some(addr) = next else {
+                return Err(corrupt);
+            };
+            let page = DiskLeaf {
+                inner: self.read_image(addr)?,
+            };
+            let Some((after, payload)) = overflow::read_page(&page) else {
+                return Err(corrupt);
+            };
+            value.extend_from_slice(payload);
+            next = after;
+        }
+        if next.is_some() || value.len() as u64 != pointer.len || crc32(&value) != pointer.crc {
+            return Err(corrupt);
+        }
+        Ok(value)
+    }
+
+    /// Read a value back from its blob file, failing with `BlobCorrupt` if the engine has no
+    /// blob log or the file does not hold what the pointer describes
+    pub fn read_blob(&self, pointer: BlobPointer) -> Result<Vec<u8>, QSError> {
+        match &self.blob_log {
+            Some(blob_log) => blob_log.read(pointer),
+            None => Err(QSError::BlobCorrupt {
+                file: pointer.file,
+                offset: pointer.offset,
+            }),
+        }
+    }
+
+    /// Free the pages of an overflow chain nothing points at anymore. Stops at a page that
+    /// is not part of a chain, leaving the rest allocated
+    pub fn free_overflow(&self, pointer: OverflowPointer) {
+        let mut next = Some(pointer.head);
+        for _ in 0..pointer.pages() {
+            let Some(addr) = next else {
+                return;
+            };
+            let Ok(inner) = self.read_image(addr) else {
+                return;
+            };
+            let Some((after, _)) = overflow::read_page(&DiskLeaf { inner }) else {
+                return;
+            };
+            self.free_page(addr);
+            next = after;
+        }
+    }
+
+    /// Seal `leaf` and write it to its place in the file, compressed if the file is
+    fn write_image(&self, page_addr: u64, leaf: &DiskLeaf) {
+        let mut image = leaf.inner.clone();
+        page_to_disk_order(&mut image);
+        seal_page(&mut image);
+        let written = match &self.page_map {
+            Some(page_map) => {
+                let block = compress(image.as_slice());
+                // a page that does not compress is stored as it is
+                let block = match block.len() < image.len() {
+                    true => block.as_slice(),
+                    false => image.as_slice(),
+                };
+                let slot = page_map.place(page_addr, block.len() as u32);
+                self.file
+                    .write_all_at(block, PAGE_SIZE + slot.offset)
+                    .expect("todo");
+                page_map
+                    .commit(page_addr, slot)
+                    .expect("failed to record compressed page block");
+                block.len() as u64
+            }
+            None => {
+                self.file
+                    .write_all_at(image.as_slice(), calc_offset(page_addr))
+                    .expect("todo");
+                PAGE_SIZE
+            }
+        };
+        self.bytes_written.fetch_add(written, Ordering::Relaxed);
+    }
+
+    /// Whether `prefetch_page` would read the page: the leaf cache is on and lacks it
+    pub fn should_prefetch(&self, page_addr: u64) -> bool {
+        self.leaf_cache.capacity() > 0 && !self.leaf_cache.contains(page_addr)
+    }
+
+    /// Read the page into the leaf cache ahead of use. Holds no page lock, so the image
+    /// is dropped if a write may have overtaken the read
+    pub fn prefetch_page(&self, page_addr: u64) -> bool {
+        if !self.should_prefetch(page_addr) {
+            return false;
+        }
+        let stamp = self.leaf_cache.write_stamp();
+        let Ok(out) = self.read_image(page_addr) else {
+            return false;
+        };
+        self.leaf_cache
+            .fill(page_addr, &DiskLeaf { inner: out }, stamp)
+    }
+
+    /// Read and check the page image at `page_addr`, decompressing it from its block in a
+    /// compressed file. A page never written reads as zeroes
+    fn read_image(&self, page_addr: u64) -> Result<Box<[u8; 4096]>, QSError> {
+        self.pages_read.fetch_add(1, Ordering::Relaxed);
+        let mut out: Box<[u8; 4096]> = Box::new([0u8; 4096]);
+        match &self.page_map {
+            Some(page_map) => {
+                if let Some(slot) = page_map.get(page_addr) {
+                    let mut block = vec![0u8; slot.len as usize];
+                    self.file
+                        .read_exact_at(&mut block, PAGE_SIZE + slot.offset)
+                        .expect("todo");
+                    match block.len() == out.len() {
+                        true => out.copy_from_slice(&block),
+                        false => decompress(&block, out.as_mut_slice())
+                            .ok_or(QSError::PageCorrupt { addr: page_addr })?,
+                    }
+                }
+            }
+            None => {
+                self.file
+                    .read_exact_at(out.as_mut_slice(), calc_offset(page_addr))
+                    .expect("todo");
+            }
+        }
+        unseal_page(&mut out, page_addr)?;
+        page_from_disk_order(&mut out);
+        Ok(out)
+    }
+
+    /// An address for a new leaf: a freed page if there is one, else one past the last.
+    /// Either way the metadata page is synced before the address is handed out, so a
+    /// reopened file never hands it out again
+    pub fn get_new_addr(&self) -> Result<u64, QSError> {
+        // the free list lock also keeps the high-water marks reaching the disk in order
+        let mut free_list = self.free_list.lock().expect("free list poisoned");
+        if let Some(addr) = free_list.pop() {
+            self.write_free_list(&free_list)
+                .and_then(|_| self.file.sync_data())
+                .expect("failed to record reused page");
+            self.events
+                .emit(|| StructuralEvent::DiskPageAllocated { addr, reused: true });
+            return Ok(addr);
+        }
+        let addr = self
+            .next_addr
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+                (next < self.max_pages).then_some(next + 1)
+            })
+            .map_err(|_| QSError::CapacityExceeded(CapacityLimit::DiskPages(self.max_pages)))?;
+        self.write_superblock(&self.auth)
+            .and_then(|_| self.file.sync_data())
+            .expect("failed to record page allocation");
+        self.events.emit(|| StructuralEvent::DiskPageAllocated {
+            addr,
+            reused: false,
+        });
+        Ok(addr)
+    }
+
+    /// Give back the page at `page_addr`, which nothing may reference anymore. It is zeroed
+    /// and handed out again by `get_new_addr`; if the free list is full it stays unused
+    pub fn free_page(&self, page_addr: u64) {
+        self.write_page(page_addr, &DiskLeaf::zeroed());
+        // the zeroes reach the disk before the list does, so a reused page never shows the
+        // records of the leaf that was freed
+        self.sync_data().expect("failed to sync freed page");
+        let mut free_list = self.free_list.lock().expect("free list poisoned");
+        if free_list.push(page_addr) {
+            self.write_free_list(&free_list)
+                .expect("failed to record freed page");
+        }
+        self.events
+            .emit(|| StructuralEvent::DiskPageFreed { addr: page_addr });
+    }
+
+    /// Take the lowest free page below `page_addr` off the free list, synced like
+    /// `get_new_addr`. `None` if no free page sits below it
+    pub fn take_free_page_below(&self, page_addr: u64) -> Option<u64> {
+        let mut free_list = self.free_list.lock().expect("free list poisoned");
+        let addr = free_list.take_lowest_below(page_addr)?;
+        self.write_free_list(&free_list)
+            .and_then(|_| self.file.sync_data())
+            .expect("failed to record reused page");
+        self.events
+            .emit(|| StructuralEvent::DiskPageAllocated { addr, reused: true });
+        Some(addr)
+    }
+
+    /// Copy the leaf of `page_id` from the page at `from` to the page at `to` and sync it
+    pub fn copy_page(&self, page_id: PageId, from: u64, to: u64) -> Result<(), QSError> {
+        let mut leaf = self.get_page(from)?;
+        leaf.as_mut().set_identity(page_id, to);
+        self.write_page(to, &leaf);
+        self.sync_data().expect("failed to sync copied page");
+        Ok(())
+    }
+
+    /// Sync the pages written so far, so log records that depend on them may follow
+    pub fn sync_pages(&self) {
+        self.sync_data().expect("failed to sync written pages");
+    }
+
+    /// Like `sync_pages`, handing back a failed sync. A compressed file's page map is synced
+    /// along with it, or a crash could lose where the synced blocks are
+    pub fn sync_data(&self) -> std::io::Result<()> {
+        self.file.sync_data()?;
+        match &self.page_map {
+            Some(page_map) => page_map.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Give the free pages at the end of the file back to the file system, lowering the
+    /// high-water mark past them. Returns the bytes the file shrank by; a compressed file
+    /// keeps its blocks and is left alone
+    pub fn trim_free_tail(&self) -> u64 {
+        if self.page_map.is_some() {
+            return 0;
+        }
+        let mut free_list = self.free_list.lock().expect("free list poisoned");
+        let mut next = self.next_addr.load(Ordering::Acquire);
+        // page 0 holds the root leaf of a new file and is never trimmed
+        while next > 1 && free_list.remove(next - 1) {
+            next -= 1;
+        }
+        if next == self.next_addr.load(Ordering::Acquire) {
+            return 0;
+        }
+        // the list loses the pages before the mark drops past them, so a crash in between
+        // only leaks them
+        self.write_free_list(&free_list)
+            .and_then(|_| self.file.sync_data())
+            .expect("failed to record trimmed pages");
+        self.next_addr.store(next, Ordering::Release);
+        self.write_superblock(&self.auth)
+            .and_then(|_| self.file.sync_data())
+            .expect("failed to record trimmed pages");
+        let len = self.file.len().expect("failed to read data file length");
+        if len <= calc_offset(next) {
+            return 0;
+        }
+        self.file
+            .set_len(calc_offset(next))
+            .expect("failed to truncate data file");
+        len - calc_offset(next)
+    }
+
+    fn write_free_list(&self, free_list: &FreeList) -> std::io::Result<()> {
+        self.file
+            .write_all_at(&free_list.encode(&self.auth), FREE_LIST_OFFSET)
+    }
+
+    /// Transaction ids below this were reserved by an earlier open, see `txn_ids`
+    pub fn txn_id_mark(&self) -> u64 {
+        self.txn_id_mark
+    }
+
+    /// Reserve the transaction ids below `mark`, synced before it returns
+    pub fn write_txn_id_mark(&self, mark: u64) -> std::io::Result<()> {
         self.file
-            .write_at(leaf.inner.as_slice(), calc_offset(page_addr))
-            .expect("todo");
+            .write_all_at(&txn_ids::encode(mark, &self.auth), TXN_IDS_OFFSET)?;
+        self.file.sync_data()
+    }
+
+    /// Pages handed out so far, including the root leaf and pages since freed
+    pub fn pages_used(&self) -> u64 {
+        self.next_addr.load(Ordering::Acquire)
+    }
+
+    /// Freed pages waiting to be handed out again
+    /// Whether `page_addr` is on the free list, waiting to be reused
+    pub fn is_free_page(&self, page_addr: u64) -> bool {
+        self.free_list
+            .lock()
+            .expect("free list poisoned")
+            .contains(page_addr)
+    }
+
+    pub fn free_pages(&self) -> u64 {
+        self.free_list.lock().expect("free list poisoned").len() as u64
+    }
+
+    pub fn max_pages(&self) -> u64 {
+        self.max_pages
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Page images read from the data file, reads the leaf cache answered are not counted
+    pub fn pages_read(&self) -> u64 {
+        self.pages_read.load(Ordering::Relaxed)
+    }
+
+    pub fn read_repairs(&self) -> &ReadRepairs {
+        &self.read_repairs
+    }
+
+    pub fn leaf_cache_stats(&self) -> LeafCacheStats {
+        self.leaf_cache.stats()
     }
+}
+
+/// Marks the address field of a page image as holding a checksum, in its top 16 bits
+const CHECKSUM_TAG: u64 = 0xC5C5;
+const ADDR_SHIFT: u32 = 16;
+const ADDR_MASK: u64 = !0 << ADDR_SHIFT;
 
-    pub fn get_new_addr(&self) -> u64 {
-        self.next_addr.fetch_add(1, Ordering::AcqRel)
+/// A page image's header repeats the page's own disk address, which is known from where
+/// the page is read. On disk that field carries `CHECKSUM_TAG` and a CRC-32 of the image
+/// with the field zeroed instead
+fn seal_page(image: &mut [u8; 4096]) {
+    let word = header_word(image) & !ADDR_MASK;
+    set_header_word(image, word);
+    let crc = crc32(image.as_slice()) as u64;
+    set_header_word(image, word | ((CHECKSUM_TAG << 32 | crc) << ADDR_SHIFT));
+}
+
+/// Check a page image read from `page_addr` and put its address back. Images that were never
+/// written (all zeroes) or written before checksums (holding their address) are accepted
+fn unseal_page(image: &mut [u8; 4096], page_addr: u64) -> Result<(), QSError> {
+    let word = header_word(image);
+    let field = word >> ADDR_SHIFT;
+    if field == page_addr || image.iter().all(|b| *b == 0) {
+        return Ok(());
+    }
+    let flags = word & !ADDR_MASK;
+    set_header_word(image, flags);
+    if field >> 32 != CHECKSUM_TAG || crc32(image.as_slice()) as u64 != field & 0xFFFF_FFFF {
+        return Err(QSError::PageCorrupt { addr: page_addr });
+    }
+    set_header_word(image, flags | (page_addr << ADDR_SHIFT));
+    Ok(())
+}
+
+/// The first header word of an image in disk order
+fn header_word(image: &[u8; 4096]) -> u64 {
+    u64::from_le_bytes(image[..8].try_into().unwrap())
+}
+
+fn set_header_word(image: &mut [u8; 4096], word: u64) {
+    image[..8].copy_from_slice(&word.to_le_bytes());
+}
+
+fn open_page_map(path: Option<&Path>) -> std::io::Result<PageMap> {
+    match path {
+        Some(path) => PageMap::open(path),
+        None => PageMap::in_memory(),
     }
 }
 
 fn calc_offset(page_addr: u64) -> u64 {
     // add one for a metadata page
-    let offset = (page_addr + 1) * 4096;
-    offset
+    (page_addr + 1) * 4096
 }
+#[derive(Clone)]
+/// A leaf page image, laid out as `NodeMeta` reads it in memory. Its integers are put in
+/// their little-endian disk order as it is written and back as it is read, see
+/// `types::page_to_disk_order`
 pub struct DiskLeaf {
     inner: Box<[u8; 4096]>,
 }
@@ -84,10 +779,20 @@ impl DiskLeaf {
         }
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        self.inner.as_slice()
+    }
+
+    pub(crate) fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self.inner.as_mut_slice()
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn as_ref(&self) -> &NodeMeta {
         unsafe { &*(self.inner.as_ptr() as *const NodeMeta) }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn as_mut(&mut self) -> &mut NodeMeta {
         unsafe { &mut *(self.inner.as_ptr() as *mut NodeMeta) }
     }
@@ -0,0 +1,323 @@
+//! Range cursors over the leaves of a `QuickStep`.
+//!
+//! Consistency model: a cursor reads one leaf at a time, as an atomic snapshot taken under the
+//! leaf's read lock, and holds no locks between leaves. It does not follow sibling pointers,
+//! instead every hop re-traverses the tree with the key it resumes from and checks that the
+//! leaf it lands on still covers that key according to the leaf's own fences; a split or merge
+//! that moved the range in between sends it back to traverse again. Because each leaf's
+//! records are clipped to `[resume, upper fence)` and the cursor then resumes at that fence,
+//! keys come out strictly increasing and every key present for the whole scan is returned
+//! exactly once, however the leaves split or merge underneath it. Keys written or deleted
+//! during the scan are seen if the write lands before the cursor reaches their leaf. Keys
+//! past their expiry are skipped, and so are soft-deleted keys unless the cursor asks for them.
+//!
+//! `QuickStepTx::range_scan` walks the leaves the same way inside a transaction, but reads
+//! them through its locks, so it sees the transaction's own writes and keeps each leaf read
+//! locked until the transaction ends. A serializable transaction also locks the scanned range,
+//! widened to the fences of the first and last leaf, in the shared range lock table: other
+//! transactions writing a key in it fail with `WriteConflict` rather than add a phantom.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{
+    attrs,
+    buffer::MiniPageBuffer,
+    error::QSError,
+    io_engine::IoEngine,
+    lock_manager::GuardWrapper,
+    merge, reserved,
+    ttl::now_millis,
+    types::{KVRecordType, NodeMeta, NodeRef, NodeSize},
+    QuickStepTx, Store, SPIN_RETRIES,
+};
+
+/// Fence keys the root leaf starts with, they stand for unbounded ends
+pub(crate) const LOWER_SENTINEL: &[u8] = &[0x00];
+pub(crate) const UPPER_SENTINEL: &[u8] = &[0xFF];
+
+/// Iterator over the records with `lower <= key < upper`, see the module docs for what it
+/// guarantees under concurrent writes
+pub struct RangeIter<'db> {
+    db: &'db Store,
+    /// Smallest key not yet returned or skipped, `None` once the range is exhausted
+    resume: Option<Vec<u8>>,
+    upper: Vec<u8>,
+    batch: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// Leaves visited so far, including revisits after a failed revalidation
+    leaf_visits: usize,
+    revalidations: usize,
+    include_soft_deleted: bool,
+}
+
+/// Records and fences of one leaf, read under its lock
+pub(crate) struct LeafView {
+    pub(crate) lower: Vec<u8>,
+    pub(crate) upper: Vec<u8>,
+    pub(crate) records: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl<'db> RangeIter<'db> {
+    pub(crate) fn new(db: &'db Store, lower: &[u8], upper: &[u8]) -> RangeIter<'db> {
+        RangeIter {
+            db,
+            resume: (lower < upper).then(|| lower.to_vec()),
+            upper: upper.to_vec(),
+            batch: VecDeque::new(),
+            leaf_visits: 0,
+            revalidations: 0,
+            include_soft_deleted: false,
+        }
+    }
+
+    /// Also return soft-deleted keys, whose values are still stored
+    pub fn include_soft_deleted(mut self) -> RangeIter<'db> {
+        self.include_soft_deleted = true;
+        self
+    }
+
+    /// Number of leaf reads that had to be retried because the leaf no longer covered the
+    /// resume key
+    pub fn revalidations(&self) -> usize {
+        self.revalidations
+    }
+
+    pub fn leaf_visits(&self) -> usize {
+        self.leaf_visits
+    }
+
+    /// Read the leaf covering the resume key into `batch` and move past it
+    fn advance(&mut self, resume: Vec<u8>) -> Result<(), QSError> {
+        if self.db.hash_buckets.is_some() {
+            return Err(QSError::OrderedScanUnsupported);
+        }
+        for _ in 0..SPIN_RETRIES {
+            let page = self.db.inner_nodes.read_traverse_leaf(&resume)?.page;
+            let view = {
+                let guard = self.db.map_table.read_page_entry(page)?;
+                // a leaf merged away, or split, before the lock was taken is no longer where
+                // the key leads; both need its write lock, so the route holds from here on
+                if self.db.inner_nodes.read_traverse_leaf(&resume)?.page != page {
+                    self.revalidations += 1;
+                    continue;
+                }
+                self.leaf_visits += 1;
+                read_leaf(
+                    &self.db.cache,
+                    &self.db.io_engine,
+                    guard.node(),
+                    now_millis(),
+                    self.include_soft_deleted,
+                )?
+            };
+
+            if !view.covers(&resume) {
+                // a split or merge moved the key since we traversed
+                self.revalidations += 1;
+                continue;
+            }
+
+            let end = match view.upper.as_slice() {
+                UPPER_SENTINEL => self.upper.as_slice(),
+                fence => fence.min(self.upper.as_slice()),
+            };
+            self.batch.extend(
+                view.records
+                    .range(resume.clone()..end.to_vec())
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            );
+            self.resume = (end < self.upper.as_slice()).then(|| end.to_vec());
+            return Ok(());
+        }
+        Err(QSError::OLCRetriesExceeded)
+    }
+}
+
+impl Iterator for RangeIter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>), QSError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.batch.pop_front() {
+                return Some(Ok(record));
+            }
+            let resume = self.resume.take()?;
+            if let Err(err) = self.advance(resume) {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+impl<'db> QuickStepTx<'db> {
+    /// Key/value pairs with `lower <= key < upper` in key order, as this transaction sees
+    /// them. The leaves read stay read locked until the transaction ends, as with `get`; a
+    /// serializable transaction also locks the range against other writers, see `iter`
+    #[allow(clippy::type_complexity)]
+    pub fn range_scan(
+        &mut self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, QSError> {
+        let _op = self.enter()?;
+        if self.db.hash_buckets.is_some() {
+            return Err(QSError::OrderedScanUnsupported);
+        }
+        if lower >= upper {
+            return Ok(Vec::new());
+        }
+        // locked before reading, so a writer either lands before the scan reads its leaf or
+        // sees the lock
+        if self.serializable {
+            self.lock_manager.lock_range(lower, Some(upper));
+        }
+
+        let mut records = Vec::new();
+        let mut resume = lower.to_vec();
+        let mut first_lower = None;
+        loop {
+            let view = self.read_leaf_covering(&resume)?;
+            let scan_lower = first_lower.get_or_insert_with(|| view.lower.clone());
+            let end = match view.upper.as_slice() {
+                UPPER_SENTINEL => upper,
+                fence => fence.min(upper),
+            };
+            records.extend(
+                view.records
+                    .range(resume.clone()..end.to_vec())
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            );
+            if end == upper {
+                if self.serializable {
+                    // next-key locking: cover the gaps out to the fences of the leaves read
+                    let lock_lower = match scan_lower.as_slice() {
+                        LOWER_SENTINEL => &[][..],
+                        fence => fence,
+                    };
+                    let lock_upper =
+                        (view.upper.as_slice() != UPPER_SENTINEL).then_some(view.upper.as_slice());
+                    self.lock_manager.lock_range(lock_lower, lock_upper);
+                }
+                return Ok(records);
+            }
+            resume = end.to_vec();
+        }
+    }
+
+    /// Read lock, through this transaction, the leaf covering `key` and read it
+    fn read_leaf_covering(&mut self, key: &[u8]) -> Result<LeafView, QSError> {
+        for _ in 0..SPIN_RETRIES {
+            let page = self.db.inner_nodes.read_traverse_leaf(key)?.page;
+            let guard = self
+                .lock_manager
+                .get_or_acquire_read_lock(&self.db.map_table, page)?;
+            // see `RangeIter::advance`
+            if self.db.inner_nodes.read_traverse_leaf(key)?.page != page {
+                continue;
+            }
+            let node = match &guard.guard_inner {
+                GuardWrapper::Write(g) => g.node(),
+                GuardWrapper::Read(g) => g.node(),
+            };
+            let view = read_leaf(
+                &self.db.cache,
+                &self.db.io_engine,
+                node,
+                now_millis(),
+                false,
+            )?;
+            if view.covers(key) {
+                return Ok(view);
+            }
+        }
+        Err(QSError::OLCRetriesExceeded)
+    }
+}
+
+impl LeafView {
+    pub(crate) fn covers(&self, key: &[u8]) -> bool {
+        let above_lower = self.lower.as_slice() == LOWER_SENTINEL || key >= self.lower.as_slice();
+        let below_upper = self.upper.as_slice() == UPPER_SENTINEL || key < self.upper.as_slice();
+        above_lower && below_upper
+    }
+}
+
+/// The records of a leaf a read sees at `now`, with the values kept in overflow pages or
+/// blob files read back and pending merge operands folded. Reserved keys and keys their header hides are left out,
+/// soft-deleted ones only unless `include_soft_deleted`
+pub(crate) fn read_leaf(
+    cache: &MiniPageBuffer,
+    io: &IoEngine,
+    node: NodeRef<'_>,
+    now: u64,
+    include_soft_deleted: bool,
+) -> Result<LeafView, QSError> {
+    let mut view = read_leaf_stored(cache, io, node)?;
+    let mut records = BTreeMap::new();
+    for (key, stored) in view.records {
+        if reserved::is_reserved(&key) {
+            continue;
+        }
+        let attrs = attrs::split(&stored).0;
+        if attrs.is_expired(now) || (attrs.soft_deleted && !include_soft_deleted) {
+            continue;
+        }
+        let value = merge::resolve(io, &key, &stored)?.into_owned();
+        records.insert(key, value);
+    }
+    view.records = records;
+    Ok(view)
+}
+
+/// Everything stored in a leaf as its records hold it: a mini-page smaller than a full leaf
+/// only caches part of it, so its entries are laid over the disk records, with tombstones
+/// hiding them
+pub(crate) fn read_leaf_stored(
+    cache: &MiniPageBuffer,
+    io: &IoEngine,
+    node: NodeRef<'_>,
+) -> Result<LeafView, QSError> {
+    match node {
+        NodeRef::Leaf(addr) => {
+            let leaf = io.get_page(addr)?;
+            Ok(leaf_view(leaf.as_ref(), BTreeMap::new()))
+        }
+        NodeRef::MiniPage(index) => {
+            // SAFETY: the caller holds a read lock on the page
+            let meta = unsafe { cache.get_meta_ref(index) };
+            let base = match meta.size() {
+                NodeSize::LeafPage => BTreeMap::new(),
+                _ => leaf_view(io.get_page(meta.leaf())?.as_ref(), BTreeMap::new()).records,
+            };
+            Ok(leaf_view(meta, base))
+        }
+    }
+}
+
+fn leaf_view(meta: &NodeMeta, mut records: BTreeMap<Vec<u8>, Vec<u8>>) -> LeafView {
+    let (lower, upper) = meta.fence_bounds();
+    let prefix = meta.get_node_prefix();
+    for i in 0..meta.record_count() as usize {
+        let kv = meta.get_kv_meta(i);
+        if kv.fence() {
+            continue;
+        }
+        let suffix = meta.get_stored_key_from_meta(kv);
+        let mut key = Vec::with_capacity(prefix.len() + suffix.len());
+        key.extend_from_slice(prefix);
+        key.extend_from_slice(suffix);
+        match kv.typ() {
+            KVRecordType::Insert | KVRecordType::Cache => {
+                records.insert(key, meta.get_val_from_meta(kv).to_vec());
+            }
+            KVRecordType::Tombstone => {
+                records.remove(&key);
+            }
+            KVRecordType::Phantom => {}
+        }
+    }
+    LeafView {
+        lower,
+        upper,
+        records,
+    }
+}
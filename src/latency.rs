@@ -0,0 +1,111 @@
+//! Latency injected into the data file and WAL, to try a workload on a slow disk.
+//!
+//! With `QuickStepConfig::with_io_latency`, every positional read or write, cursor read or
+//! write and sync of the data file and the WAL sleeps for the configured time plus a random
+//! jitter of up to `jitter` before it runs. The operation itself still runs and succeeds as
+//! it would have: nothing is dropped or corrupted. Reads served by the leaf cache never
+//! reach the file and are not delayed.
+//!
+//! The delays are counted, see `LatencyStats`, so a benchmark can tell how much of its time
+//! was spent waiting on the injected latency.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// How long each kind of file operation is held up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoLatency {
+    pub read: Duration,
+    pub write: Duration,
+    pub sync: Duration,
+    /// Most extra time added to each delay, drawn uniformly
+    pub jitter: Duration,
+}
+
+impl IoLatency {
+    pub fn new(read: Duration, write: Duration, sync: Duration) -> IoLatency {
+        IoLatency {
+            read,
+            write,
+            sync,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> IoLatency {
+        self.jitter = jitter;
+        self
+    }
+}
+
+/// Operations delayed and the time spent sleeping since open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub syncs: u64,
+    pub injected: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum IoKind {
+    Read,
+    Write,
+    Sync,
+}
+
+/// The delays shared by every store of a database
+#[derive(Debug, Default)]
+pub struct LatencyInjector {
+    latency: IoLatency,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    syncs: AtomicU64,
+    injected_nanos: AtomicU64,
+}
+
+impl LatencyInjector {
+    pub fn new(latency: IoLatency) -> LatencyInjector {
+        LatencyInjector {
+            latency,
+            ..LatencyInjector::default()
+        }
+    }
+
+    pub fn latency(&self) -> IoLatency {
+        self.latency
+    }
+
+    /// Sleep for an operation of `kind`
+    pub(crate) fn delay(&self, kind: IoKind) {
+        let (base, count) = match kind {
+            IoKind::Read => (self.latency.read, &self.reads),
+            IoKind::Write => (self.latency.write, &self.writes),
+            IoKind::Sync => (self.latency.sync, &self.syncs),
+        };
+        let jitter = match self.latency.jitter.as_nanos() as u64 {
+            0 => Duration::ZERO,
+            max => Duration::from_nanos(fastrand::u64(0..=max)),
+        };
+        let delay = base + jitter;
+        count.fetch_add(1, Ordering::Relaxed);
+        if delay.is_zero() {
+            return;
+        }
+        thread::sleep(delay);
+        self.injected_nanos
+            .fetch_add(delay.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        LatencyStats {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            syncs: self.syncs.load(Ordering::Relaxed),
+            injected: Duration::from_nanos(self.injected_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::io_engine::DiskLeaf;
+
+/// Pages kept by default, 256 KiB of leaf images
+pub const DEFAULT_LEAF_CACHE_PAGES: usize = 64;
+
+/// Small LRU of recently read disk leaves, keyed by disk address. It sits below the
+/// mini-page buffer so cold leaves read again shortly after don't go back to the file.
+/// Writes go through `write`, so a cached image is never older than the file.
+pub struct LeafCache {
+    /// Most pages held at once, 0 disables the cache
+    capacity: usize,
+    state: Mutex<LruState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct LruState {
+    /// addr -> (image, last use)
+    pages: HashMap<u64, (DiskLeaf, u64)>,
+    /// last use -> addr, oldest first
+    recency: BTreeMap<u64, u64>,
+    clock: u64,
+    /// Number of `write`s, lets a fill read outside the lock tell if it raced one
+    writes: u64,
+}
+
+impl LruState {
+    fn touch(&mut self, addr: u64) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some((_, used)) = self.pages.get_mut(&addr) {
+            self.recency.remove(used);
+            *used = clock;
+            self.recency.insert(clock, addr);
+        }
+    }
+
+    fn insert(&mut self, capacity: usize, addr: u64, leaf: &DiskLeaf) {
+        if let Some((cached, _)) = self.pages.get_mut(&addr) {
+            cached.clone_from(leaf);
+            self.touch(addr);
+            return;
+        }
+
+        if self.pages.len() >= capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.pages.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        let clock = self.clock;
+        self.pages.insert(addr, (leaf.clone(), clock));
+        self.recency.insert(clock, addr);
+    }
+}
+
+/// Hit rate and occupancy of the leaf cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafCacheStats {
+    pub capacity: usize,
+    pub pages: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl LeafCacheStats {
+    /// Fraction of lookups served from the cache, 0 before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl LeafCache {
+    pub fn new(capacity: usize) -> LeafCache {
+        LeafCache {
+            capacity,
+            state: Mutex::new(LruState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Copy of the cached image at `addr`, counted as a hit or a miss
+    pub fn get(&self, addr: u64) -> Option<DiskLeaf> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut state = self.state.lock().expect("leaf cache poisoned");
+        let found = state.pages.get(&addr).map(|(leaf, _)| leaf.clone());
+        match found {
+            Some(leaf) => {
+                state.touch(addr);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(leaf)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Cache `leaf` as the current image at `addr`, evicting the least recently used page
+    /// when full
+    pub fn insert(&self, addr: u64, leaf: &DiskLeaf) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().expect("leaf cache poisoned");
+        state.insert(self.capacity, addr, leaf);
+    }
+
+    /// Cache `leaf` as just written to `addr`
+    pub fn write(&self, addr: u64, leaf: &DiskLeaf) {
+        let mut state = self.state.lock().expect("leaf cache poisoned");
+        state.writes += 1;
+        if self.capacity > 0 {
+            state.insert(self.capacity, addr, leaf);
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Whether `addr` is cached, without counting a lookup
+    pub fn contains(&self, addr: u64) -> bool {
+        let state = self.state.lock().expect("leaf cache poisoned");
+        state.pages.contains_key(&addr)
+    }
+
+    /// Stamp to pass to `fill`, taken before reading the page
+    pub fn write_stamp(&self) -> u64 {
+        self.state.lock().expect("leaf cache poisoned").writes
+    }
+
+    /// Cache an image read without the page's lock, unless the page is already cached or a
+    /// write since `stamp` may have made the image stale
+    pub fn fill(&self, addr: u64, leaf: &DiskLeaf, stamp: u64) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        let mut state = self.state.lock().expect("leaf cache poisoned");
+        if state.writes != stamp || state.pages.contains_key(&addr) {
+            return false;
+        }
+        state.insert(self.capacity, addr, leaf);
+        true
+    }
+
+    pub fn stats(&self) -> LeafCacheStats {
+        let pages = self.state.lock().expect("leaf cache poisoned").pages.len();
+        LeafCacheStats {
+            capacity: self.capacity,
+            pages,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
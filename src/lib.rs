@@ -7,67 +7,232 @@
 //! [design documentation](../design/).
 
 use std::{
-    collections::{BTreeMap, HashMap},
-    env,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fs, io, mem,
+    ops::Deref,
     path::{Path, PathBuf},
     ptr,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc,
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, Weak,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    btree::{BPTree, ChildPointer, DebugLeafParent, OpType, WriteLockBundle},
+    activity_log::{ActivityEntry, ActivityLog},
+    attrs::RecordAttrs,
+    auth::MetadataAuth,
+    blob::{BlobGcStats, BlobLog, BlobPointer, BlobStore},
+    btree::{
+        BPTree, ChildPointer, DebugLeafParent, InnerNodeStats, InnerReservation, LockedInner,
+        OpType, WriteLockBundle,
+    },
     buffer::{MiniPageBuffer, MiniPageIndex},
+    checkpoint::{
+        CheckpointCandidate, CheckpointPolicy, CheckpointStats, CheckpointTriggers,
+        MIN_CHECKPOINT_INTERVAL,
+    },
+    commit::CommitHandle,
+    compaction::{AutoCompaction, CompactionStats},
+    compatibility::CompatReport,
     error::QSError,
-    io_engine::IoEngine,
-    lock_manager::{LockManager, WriteGuardWrapper},
-    map_table::{MapTable, PageId},
-    page_op::{LeafMergePlan, LeafSplitOutcome, LeafSplitPlan, TryPutResult},
-    types::{NodeMeta, NodeRef, NodeSize},
-    wal::{WalEntryKind, WalManager, WalOp, WalRecord, WalTxnMarker, TXN_META_PAGE_ID},
+    events::{EventListener, Events, StructuralEvent},
+    follower::{ApplyStats, Follower},
+    health::{HealthEvent, InnerTreeFullPolicy, WalLimit, WalLimitPolicy, WalPressure},
+    io_engine::{IoEngine, PageCompressionStats, MAX_DB_BYTES, MAX_DISK_PAGES, PAGE_SIZE},
+    iter::{read_leaf_stored, RangeIter, LOWER_SENTINEL},
+    latency::{IoLatency, LatencyInjector, LatencyStats},
+    leaf_cache::{LeafCacheStats, DEFAULT_LEAF_CACHE_PAGES},
+    limits::{KeyspaceLimits, SizeLimits},
+    lock_manager::{GuardWrapper, LockManager, LockOwners, PageGuard, WriteGuardWrapper},
+    maintenance::{ActivityClock, IdleMaintenance, LeafWriteBudget, MaintenanceCtx},
+    map_table::{LockWaitPolicy, MapTable, PageId, MAX_PAGE_IDS},
+    merge::MergeOperator,
+    metrics::{MetricFamily, MetricsExporter},
+    migrate::{MigrationStats, DEFAULT_MIGRATION_BATCH},
+    overflow::OverflowPointer,
+    overlay::Overlay,
+    page_op::{
+        flush_dirty_entries, LeafMergePlan, LeafSplitOutcome, LeafSplitPlan, SplitPoint,
+        SplitPolicy, TryPutResult,
+    },
+    periodic::{Periodic, Signal},
+    read_repair::LeafRepair,
+    replace::ReplaceHandle,
+    salvage::SalvageReport,
+    stats::{Stats, StatsSnapshot},
+    storage::FileLocked,
+    ttl::{now_millis, TtlIndex},
+    two_phase::{InDoubt, PreparedTx},
+    txn_ids::TxnIds,
+    txn_timeout::{TxnOp, TxnRegistry, TxnSlot},
+    types::{KVMeta, NodeMeta, NodeRef, NodeSize},
+    value::{EntryInfo, PinnedValue, ValueRef},
+    verify::VerifyReport,
+    wal::{
+        SyncPolicy, WalEntryKind, WalManager, WalMismatch, WalOp, WalRecord, WalSmo, WalStats,
+        WalStatsLevel, WalTailGap, WalTxnMarker, STRUCTURE_PAGE_ID, TXN_META_PAGE_ID,
+    },
+    watch::{ChangeEvent, ChangeKind, Watchers},
 };
 
+pub mod activity_log;
+pub mod asynk;
+pub mod attrs;
+pub mod auth;
+pub mod blob;
 pub mod btree;
 pub mod buffer;
+pub mod checkpoint;
+pub mod commit;
+pub mod compaction;
+pub mod compat;
+pub mod compatibility;
+pub mod compress;
+pub mod config_file;
 pub mod debug;
+pub mod epoch;
 pub mod error;
+pub mod events;
+#[cfg(feature = "failpoints")]
+pub mod failpoints;
+pub mod follower;
+pub mod free_list;
+pub mod hash_index;
+pub mod health;
+pub mod invariants;
 pub mod io_engine;
+pub mod iter;
+pub mod latency;
+pub mod leaf_cache;
+pub mod limits;
 pub mod lock_manager;
+pub mod maintenance;
 pub mod map_table;
+pub mod merge;
+pub mod metrics;
+pub mod migrate;
 pub mod node;
+pub mod overflow;
+pub mod overlay;
+pub mod page_map;
 pub mod page_op;
+pub mod periodic;
+pub mod profile;
 pub mod rand;
+pub mod read_repair;
+pub mod replace;
+pub mod reserved;
+pub mod salvage;
+pub mod sample;
+pub mod sidecar;
+pub mod stats;
+pub mod storage;
+pub mod superblock;
+pub mod ttl;
+pub mod two_phase;
+pub mod txn_ids;
+pub mod txn_timeout;
 pub mod types;
 pub mod utils;
+pub mod value;
+pub mod verify;
 pub mod wal;
+pub mod watch;
 
-pub const SPIN_RETRIES: usize = 2 ^ 12;
+pub const SPIN_RETRIES: usize = 1 << 12;
 
 const _: () = assert!(std::mem::size_of::<usize>() == std::mem::size_of::<u64>());
 
-/// Represents the overall Bf-tree
+/// Represents the overall Bf-tree. Closing the handle, by dropping it or with `close`,
+/// stops the background threads and flushes the store
 pub struct QuickStep {
+    store: Arc<Store>,
+    /// Cleared by `debug_close_without_flush`, the drop then only syncs the WAL
+    flush_on_drop: bool,
+}
+
+/// The store behind a `QuickStep` handle, shared with its background threads
+pub struct Store {
     /// The inner nodes of the Tree, stores no values, but references to leaves
     inner_nodes: BPTree,
     /// The mini-page cache
-    cache: MiniPageBuffer,
+    cache: Arc<MiniPageBuffer>,
     /// The interface for all file io operation
-    io_engine: IoEngine,
+    io_engine: Arc<IoEngine>,
+    /// Disk addresses `QuickStepTx::prefetch` asked to have read ahead
+    prefetch_sender: Option<Sender<u64>>,
+    prefetch_thread: Option<thread::JoinHandle<()>>,
     /// The map from page ids to their location, either in the mini-page buffer or on disk
-    map_table: MapTable,
+    map_table: Arc<MapTable>,
+    /// Number of hash buckets keys are found by instead of the inner nodes, see `hash_index`
+    hash_buckets: Option<u64>,
     /// Write-ahead log for tombstones/deletes
     wal: Arc<WalManager>,
-    wal_leaf_checkpoint_threshold: usize,
-    wal_global_record_threshold: usize,
-    wal_global_byte_threshold: usize,
+    /// WAL thresholds and checkpoint thread interval, adjustable while the store runs
+    checkpoint_triggers: Arc<CheckpointTriggers>,
     wal_checkpoint_requested: Arc<AtomicBool>,
-    wal_checkpoint_stop: Arc<AtomicBool>,
-    wal_checkpoint_thread: Option<thread::JoinHandle<()>>,
-    next_txn_id: AtomicU64,
+    /// Wakes the checkpoint thread once its interval changes
+    checkpoint_signal: Arc<Signal>,
+    /// Which leaves a global checkpoint flushes, and how many at a time
+    checkpoint_policy: CheckpointPolicy,
+    checkpoint_batch: usize,
+    /// Last page a global checkpoint flushed, where round-robin picks up
+    checkpoint_cursor: Arc<AtomicU64>,
+    /// Keys written with a TTL, for the sweeper, see `ttl`
+    ttl: Arc<TtlIndex>,
+    /// How often the sweeper looks for expired keys, started once a key has a TTL
+    ttl_sweep_interval: Duration,
+    /// When the last foreground operation started, for idle maintenance
+    activity: Arc<ActivityClock>,
+    idle_leaf_writes: Arc<AtomicU64>,
+    /// Open transactions that expire once idle, see `txn_timeout`
+    txn_registry: Option<Arc<TxnRegistry>>,
+    /// Recent committed changes, see `activity_log`
+    activity_log: Option<ActivityLog>,
+    /// Leaves the WAL is ahead of are repaired as they are read, see `read_repair`
+    read_repair: bool,
+    /// Maximum WAL size and the health subscribers told as it is approached
+    wal_limit: Arc<WalLimit>,
+    /// Told of each allocation and structural change, see `events`
+    events: Events,
+    /// What a split does when the inner tree is out of nodes, see `health`
+    inner_tree_full_policy: InnerTreeFullPolicy,
+    /// Where a full leaf is split, see `page_op::SplitPolicy`
+    split_policy: SplitPolicy,
+    /// Subscribers to committed changes
+    watchers: Watchers,
+    /// Kept past the ids earlier opens handed out, see `txn_ids`
+    txn_ids: TxnIds,
+    /// Which transaction holds each write-locked page
+    lock_owners: LockOwners,
+    /// Key and value bytes users have put or deleted since open
+    logical_bytes_written: AtomicU64,
+    /// What replaying the WAL on open did
+    recovery_report: RecoveryReport,
+    /// Largest keys and values writes may store, per keyspace
+    size_limits: KeyspaceLimits,
+    /// Delays injected into the data file and WAL, see `latency`
+    latency_injector: Option<Arc<LatencyInjector>>,
+    /// Set by `open_follower`, local writes are refused, see `follower`
+    follower: Option<Follower>,
+    /// Opened with `QuickStepConfig::with_read_only`, the files are never written
+    read_only: bool,
+    /// Prepared transactions recovery left in doubt, see `two_phase`
+    in_doubt: InDoubt,
+    /// Operation counts and latencies, see `stats`
+    stats: Arc<Stats>,
+    /// The background threads, taken when the handle closes
+    workers: Mutex<Option<Vec<Periodic>>>,
+    /// Threads building a tree for `replace_all`, joined when the handle closes
+    replacements: Mutex<Vec<thread::JoinHandle<()>>>,
+    ttl_sweeper_started: AtomicBool,
+    /// For threads started after open
+    this: Weak<Store>,
 }
 
 impl<'db> Drop for QuickStepTx<'db> {
@@ -79,9 +244,26 @@ impl<'db> Drop for QuickStepTx<'db> {
 }
 
 const AUTO_MERGE_MIN_ENTRIES: usize = 3;
+/// Smallest buffer, in full leaves, that promotes cold leaves into small mini-pages; a
+/// growing mini-page leaves its old slot behind, which a buffer of a few leaves cannot spare
+pub(crate) const SMALL_MINI_PAGE_MIN_LEAVES: usize = 4;
 const DEFAULT_WAL_LEAF_CHECKPOINT_THRESHOLD: usize = 32;
 const DEFAULT_WAL_GLOBAL_RECORD_THRESHOLD: usize = 1024;
 const DEFAULT_WAL_GLOBAL_BYTE_THRESHOLD: usize = 512 * 1024;
+const DEFAULT_CHECKPOINT_BATCH: usize = 1;
+/// How often the checkpoint thread looks at the WAL's size, unless configured otherwise
+const CHECKPOINT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Longest the checkpoint thread waits after a pass found every leaf locked
+const CHECKPOINT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+/// How often idle maintenance looks again while the store stays idle
+const IDLE_MAINTENANCE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const DEFAULT_TTL_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+/// Most expired keys a pass of the TTL sweeper deletes
+const TTL_SWEEP_BATCH: usize = 64;
+/// Most leaves a pass of auto compaction moves
+const COMPACTION_BATCH: usize = 16;
+/// Most repaired leaves a write writes back, see `read_repair`
+const READ_REPAIR_BATCH: usize = 16;
 const ENV_WAL_LEAF_THRESHOLD: &str = "QUICKSTEP_WAL_LEAF_THRESHOLD";
 const ENV_WAL_GLOBAL_RECORD_THRESHOLD: &str = "QUICKSTEP_WAL_GLOBAL_RECORD_THRESHOLD";
 const ENV_WAL_GLOBAL_BYTE_THRESHOLD: &str = "QUICKSTEP_WAL_GLOBAL_BYTE_THRESHOLD";
@@ -94,6 +276,8 @@ pub struct DebugLeafSnapshot {
     pub page_id: PageId,
     pub disk_addr: u64,
     pub keys: Vec<Vec<u8>>,
+    /// Bytes of the leaf's mini-page, `None` if it is not cached
+    pub cached_bytes: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -104,6 +288,101 @@ pub struct DebugLeafFences {
     pub upper: Vec<u8>,
 }
 
+/// Format limits next to the configured ones and current usage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityStats {
+    /// Data pages the 48 bit disk addresses can reach
+    pub format_max_disk_pages: u64,
+    /// Leaf page ids the 48 bit page id field can hold
+    pub format_max_page_ids: u64,
+    /// Largest data file the format can describe
+    pub format_max_bytes: u64,
+    /// Data pages allowed by `QuickStepConfig::with_max_db_size`
+    pub max_disk_pages: u64,
+    /// Leaf page ids allowed by the mapping table size (`leaf_upper_bound`)
+    pub max_page_ids: u64,
+    /// Data file size allowed by `QuickStepConfig::with_max_db_size`
+    pub max_bytes: u64,
+    pub disk_pages_used: u64,
+    /// Pages freed by leaf merges that new leaves will reuse, counted in `disk_pages_used`
+    pub disk_pages_free: u64,
+    pub page_ids_used: u64,
+}
+
+/// Bytes written to the WAL and the data file against the bytes users asked to write, all
+/// counted since open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteAmplificationStats {
+    /// Key and value bytes of successful puts, key bytes of successful deletes
+    pub logical_bytes: u64,
+    /// Log bytes appended plus those rewritten by checkpoints
+    pub wal_bytes: u64,
+    /// Page images written back to the data file
+    pub data_bytes: u64,
+}
+
+impl WriteAmplificationStats {
+    pub fn physical_bytes(&self) -> u64 {
+        self.wal_bytes + self.data_bytes
+    }
+
+    /// Physical bytes per logical byte, 0 before anything was written
+    pub fn ratio(&self) -> f64 {
+        Self::per_logical(self.physical_bytes(), self.logical_bytes)
+    }
+
+    pub fn wal_ratio(&self) -> f64 {
+        Self::per_logical(self.wal_bytes, self.logical_bytes)
+    }
+
+    pub fn data_ratio(&self) -> f64 {
+        Self::per_logical(self.data_bytes, self.logical_bytes)
+    }
+
+    fn per_logical(bytes: u64, logical: u64) -> f64 {
+        match logical {
+            0 => 0.0,
+            logical => bytes as f64 / logical as f64,
+        }
+    }
+}
+
+/// What opening the database recovered from the WAL, see `QuickStep::last_recovery_report`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Length of the WAL file read on open
+    pub wal_bytes_scanned: u64,
+    /// Leaf records applied to the leaves, redo records of committed transactions and undo
+    /// records of the rest
+    pub records_replayed: usize,
+    /// Leaf records the leaves were already stamped as holding when they were last written,
+    /// see `NodeMeta::page_lsn`
+    pub records_skipped: usize,
+    /// Transactions in the log without a commit marker, whose changes were undone or dropped
+    pub txns_rolled_back: usize,
+    /// Prepared transactions neither committed nor aborted, left out of the leaves until
+    /// resolved, see `QuickStep::resolve_in_doubt`
+    pub txns_in_doubt: Vec<u64>,
+    /// Leaves rewritten from the log
+    pub pages_rebuilt: usize,
+    /// Leaves left for read repair rather than rewritten, see `read_repair`
+    pub pages_deferred: usize,
+    /// Leaf splits and merges replayed to rebuild the tree above the leaves
+    pub structure_changes: usize,
+    /// Leaves found by scanning the data file, when the log held no structure records but
+    /// the file more than one leaf
+    pub leaves_scanned: usize,
+    /// Overflow chains written by transactions cut short, given back once their puts were
+    /// undone, see `overflow`
+    pub overflow_chains_freed: usize,
+    /// Records cut off with a torn or corrupt tail of the log
+    pub corrupt_records_skipped: usize,
+    /// Bytes of that tail
+    pub corrupt_bytes_skipped: u64,
+    /// Time from opening the WAL to the end of replay
+    pub elapsed: Duration,
+}
+
 #[derive(Debug)]
 pub struct DebugWalStats {
     pub total_records: usize,
@@ -119,6 +398,10 @@ pub struct QuickStepConfig {
     /// Upper bounds on number of inner nodes
     /// This value should be tested but expected to be less than 1% of overall space
     inner_node_upper_bound: u32,
+    /// What a split does once the inner nodes run out
+    inner_tree_full_policy: InnerTreeFullPolicy,
+    /// Where a full leaf is split
+    split_policy: SplitPolicy,
     /// Upper bound on the number of leaves that will need to be in the Mapping table
     leaf_upper_bound: u64,
     /// log base 2 of the cache size
@@ -128,6 +411,58 @@ pub struct QuickStepConfig {
     wal_leaf_checkpoint_threshold: usize,
     wal_global_record_threshold: usize,
     wal_global_byte_threshold: usize,
+    /// How contended page locks are waited for before failing
+    lock_wait: LockWaitPolicy,
+    /// Largest the data file may grow, in bytes
+    max_db_bytes: u64,
+    /// Recently read disk leaves kept in the io engine's read cache
+    leaf_cache_pages: usize,
+    checkpoint_policy: CheckpointPolicy,
+    checkpoint_batch: usize,
+    /// How often the checkpoint thread checks the WAL against the thresholds
+    checkpoint_interval: Duration,
+    /// How often the sweeper looks for expired keys
+    ttl_sweep_interval: Duration,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Largest the WAL may grow before writes are throttled, in bytes
+    max_wal_bytes: Option<usize>,
+    wal_limit_policy: WalLimitPolicy,
+    /// When commits wait for the WAL to be synced
+    sync_policy: SyncPolicy,
+    /// Flush leaves and trim the WAL in the background while no foreground ops run
+    idle_maintenance: Option<IdleMaintenance>,
+    /// Move leaves toward the start of the data file and truncate it once pages are freed
+    auto_compaction: Option<AutoCompaction>,
+    /// Append values past a threshold to blob files instead of overflow pages
+    blob_store: Option<BlobStore>,
+    /// Largest keys and values writes may store, per keyspace
+    size_limits: KeyspaceLimits,
+    /// Key the superblock and WAL manifest are authenticated under
+    metadata_auth: MetadataAuth,
+    /// Keep the data pages, WAL and TTL index in memory, `path` is unused
+    in_memory: bool,
+    /// Read the files without locking or writing them, see `with_read_only`
+    read_only: bool,
+    /// Lay the leaves out as this many hash buckets instead of the ordered tree
+    hash_buckets: Option<u64>,
+    /// Store leaves as compressed blocks in the data file
+    page_compression: bool,
+    /// Delay the data file's and WAL's operations by this much, to try a slow disk
+    io_latency: Option<IoLatency>,
+    /// Most entries the activity log of committed changes keeps, `None` for no log
+    activity_log: Option<usize>,
+    /// Abort transactions idle for this long, `None` to keep them open
+    txn_timeout: Option<Duration>,
+    /// Repair leaves the WAL is ahead of as they are read instead of on open
+    read_repair: bool,
+    /// Read the WAL back this often and check it against what it counts, `None` never
+    wal_audit_interval: Option<Duration>,
+    /// Told of each allocation and structural change, see `events`
+    event_listener: Option<Arc<dyn EventListener>>,
+    /// Newest WAL records kept in memory for shipping to replicas, 0 for none
+    wal_tail_retention: usize,
+    /// Handed the store's metrics at this interval, see `metrics`
+    metrics_exporter: Option<(Duration, Arc<dyn MetricsExporter>)>,
 }
 
 impl QuickStepConfig {
@@ -140,14 +475,73 @@ impl QuickStepConfig {
         QuickStepConfig {
             path: path.into(),
             inner_node_upper_bound,
+            inner_tree_full_policy: InnerTreeFullPolicy::default(),
+            split_policy: SplitPolicy::default(),
             leaf_upper_bound,
             cache_size_lg,
             wal_leaf_checkpoint_threshold: DEFAULT_WAL_LEAF_CHECKPOINT_THRESHOLD,
             wal_global_record_threshold: DEFAULT_WAL_GLOBAL_RECORD_THRESHOLD,
             wal_global_byte_threshold: DEFAULT_WAL_GLOBAL_BYTE_THRESHOLD,
+            lock_wait: LockWaitPolicy::default(),
+            max_db_bytes: MAX_DB_BYTES,
+            leaf_cache_pages: DEFAULT_LEAF_CACHE_PAGES,
+            checkpoint_policy: CheckpointPolicy::default(),
+            checkpoint_batch: DEFAULT_CHECKPOINT_BATCH,
+            checkpoint_interval: CHECKPOINT_POLL_INTERVAL,
+            ttl_sweep_interval: DEFAULT_TTL_SWEEP_INTERVAL,
+            merge_operator: None,
+            max_wal_bytes: None,
+            wal_limit_policy: WalLimitPolicy::default(),
+            sync_policy: SyncPolicy::default(),
+            idle_maintenance: None,
+            auto_compaction: None,
+            blob_store: None,
+            size_limits: KeyspaceLimits::default(),
+            metadata_auth: MetadataAuth::default(),
+            in_memory: false,
+            read_only: false,
+            hash_buckets: None,
+            page_compression: false,
+            io_latency: None,
+            activity_log: None,
+            txn_timeout: None,
+            read_repair: false,
+            wal_audit_interval: None,
+            event_listener: None,
+            wal_tail_retention: 0,
+            metrics_exporter: None,
+        }
+    }
+
+    /// Config for an ephemeral database whose data pages, WAL and TTL index live in memory;
+    /// everything is lost once it is dropped
+    pub fn in_memory(
+        inner_node_upper_bound: u32,
+        leaf_upper_bound: u64,
+        cache_size_lg: usize,
+    ) -> QuickStepConfig {
+        QuickStepConfig {
+            in_memory: true,
+            ..QuickStepConfig::new(
+                PathBuf::new(),
+                inner_node_upper_bound,
+                leaf_upper_bound,
+                cache_size_lg,
+            )
         }
     }
 
+    /// Config read from the TOML or JSON file at `path`, covering every tunable that is not
+    /// code; see `config_file` for the keys. Fails with `QSError::InvalidConfig` for a file
+    /// that does not parse or holds unknown keys, and `QSError::Io` for one that cannot be read
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<QuickStepConfig, QSError> {
+        config_file::load(path.as_ref())
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
+    }
+
     pub fn with_env_overrides(mut self) -> QuickStepConfig {
         if let Some(val) = read_env_usize(ENV_WAL_LEAF_THRESHOLD) {
             self.wal_leaf_checkpoint_threshold = val;
@@ -201,330 +595,1786 @@ impl QuickStepConfig {
         self
     }
 
-    pub fn wal_thresholds(&self) -> (usize, usize, usize) {
-        (
-            self.wal_leaf_checkpoint_threshold,
-            self.wal_global_record_threshold,
-            self.wal_global_byte_threshold,
-        )
+    /// Spin this many times on a contended page lock before parking
+    pub fn with_lock_spins(mut self, spins: usize) -> QuickStepConfig {
+        self.lock_wait.spins = spins;
+        self
     }
-}
 
-impl QuickStep {
-    pub fn new(mut config: QuickStepConfig) -> QuickStep {
-        config = config
-            .with_env_overrides()
-            .with_cli_overrides(env::args().skip(1));
+    /// Park for at most `timeout` once spinning on a contended page lock stops, after which
+    /// the acquisition fails
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> QuickStepConfig {
+        self.lock_wait.timeout = timeout;
+        self
+    }
 
-        let QuickStepConfig {
-            path,
-            inner_node_upper_bound,
-            leaf_upper_bound,
-            cache_size_lg,
-            wal_leaf_checkpoint_threshold,
-            wal_global_record_threshold,
-            wal_global_byte_threshold,
-        } = config;
+    pub fn lock_wait(&self) -> LockWaitPolicy {
+        self.lock_wait
+    }
 
-        let data_path = resolve_data_path(&path);
+    /// Cap the data file at `bytes` (clamped to the format limit), including its metadata
+    /// page; writes that need a new page past it fail with `QSError::CapacityExceeded`
+    pub fn with_max_db_size(mut self, bytes: u64) -> QuickStepConfig {
+        self.max_db_bytes = bytes.min(MAX_DB_BYTES);
+        self
+    }
 
-        let io_engine =
-            IoEngine::open(&data_path).expect("failed to open quickstep data file for writing");
-        let wal_path = wal_path_for(&data_path);
-        let wal = Arc::new(
-            WalManager::open(&wal_path).expect("failed to open quickstep write-ahead log file"),
-        );
-        let cache = MiniPageBuffer::new(cache_size_lg);
-        let wal_checkpoint_requested = Arc::new(AtomicBool::new(false));
-        let wal_checkpoint_stop = Arc::new(AtomicBool::new(false));
-        let wal_checkpoint_thread = {
-            let wal_clone = Arc::clone(&wal);
-            let stop_clone = Arc::clone(&wal_checkpoint_stop);
-            let flag_clone = Arc::clone(&wal_checkpoint_requested);
-            let record_thresh = wal_global_record_threshold;
-            let byte_thresh = wal_global_byte_threshold;
-            Some(thread::spawn(move || {
-                while !stop_clone.load(Ordering::Relaxed) {
-                    if wal_clone.total_records() >= record_thresh
-                        || wal_clone.total_bytes() >= byte_thresh
-                    {
-                        flag_clone.store(true, Ordering::Release);
-                    }
-                    thread::sleep(Duration::from_millis(50));
-                }
-            }))
-        };
+    pub fn max_db_size(&self) -> u64 {
+        self.max_db_bytes
+    }
 
-        let mut quickstep = QuickStep {
-            inner_nodes: BPTree::new(inner_node_upper_bound),
-            cache,
-            io_engine,
-            map_table: MapTable::new(leaf_upper_bound),
-            wal,
-            wal_leaf_checkpoint_threshold,
-            wal_global_record_threshold,
-            wal_global_byte_threshold,
-            wal_checkpoint_requested,
-            wal_checkpoint_stop,
-            wal_checkpoint_thread,
-            next_txn_id: AtomicU64::new(1),
-        };
+    /// Keep up to `pages` recently read disk leaves in memory, 0 disables the cache
+    pub fn with_leaf_cache_pages(mut self, pages: usize) -> QuickStepConfig {
+        self.leaf_cache_pages = pages;
+        self
+    }
 
-        quickstep.ensure_root_leaf_on_disk();
-        quickstep.replay_wal();
+    pub fn leaf_cache_pages(&self) -> usize {
+        self.leaf_cache_pages
+    }
 
-        // initialise root leaf (page 0 for now)
-        let root_page = quickstep.map_table.init_leaf_entry(0);
-        quickstep.inner_nodes.set_leaf_root(root_page);
+    /// Rank the leaves a global checkpoint flushes by `policy`
+    pub fn with_checkpoint_policy(mut self, policy: CheckpointPolicy) -> QuickStepConfig {
+        self.checkpoint_policy = policy;
+        self
+    }
 
-        quickstep
+    pub fn checkpoint_policy(&self) -> CheckpointPolicy {
+        self.checkpoint_policy
     }
 
-    /// Create a new transaction for isolated operations
-    pub fn tx(&self) -> QuickStepTx<'_> {
-        let txn_id = self.next_txn_id.fetch_add(1, Ordering::Relaxed);
-        self.wal
-            .append_txn_marker(WalTxnMarker::Begin, WalEntryKind::Redo, txn_id)
-            .expect("failed to record txn begin");
-        // coordination is done via the locks so it can just hold a reference to the db
-        QuickStepTx {
-            db: self,
-            lock_manager: LockManager::new(),
-            txn_id,
-            wal_entry_kind: WalEntryKind::Redo,
-            undo_log: Vec::new(),
-            state: TxState::Active,
-        }
+    /// Flush up to `pages` leaves (at least 1) each time a global checkpoint triggers
+    pub fn with_checkpoint_batch(mut self, pages: usize) -> QuickStepConfig {
+        self.checkpoint_batch = pages.max(1);
+        self
     }
-}
 
-impl Drop for QuickStep {
-    fn drop(&mut self) {
-        self.wal_checkpoint_stop.store(true, Ordering::Release);
-        if let Some(handle) = self.wal_checkpoint_thread.take() {
-            let _ = handle.join();
-        }
+    pub fn checkpoint_batch(&self) -> usize {
+        self.checkpoint_batch
     }
-}
 
-impl QuickStep {
-    fn ensure_root_leaf_on_disk(&self) {
-        let mut leaf = self.io_engine.get_page(0);
-        {
-            let meta = leaf.as_mut();
-            if meta.record_count() >= 2 {
-                return;
-            }
-            meta.format_leaf(PageId(0), NodeSize::LeafPage, 0);
-        }
-        self.io_engine.write_page(0, &leaf);
+    /// Have the checkpoint thread check the WAL against the global thresholds every
+    /// `interval`, at least `checkpoint::MIN_CHECKPOINT_INTERVAL`
+    pub fn with_checkpoint_interval(mut self, interval: Duration) -> QuickStepConfig {
+        self.checkpoint_interval = interval.max(MIN_CHECKPOINT_INTERVAL);
+        self
     }
 
-    /// Test helper to inspect the root after splits; not intended for production use.
-    pub fn debug_root_leaf_parent(&self) -> Option<DebugLeafParent> {
-        self.inner_nodes.debug_root_leaf_parent()
+    pub fn checkpoint_interval(&self) -> Duration {
+        self.checkpoint_interval
     }
 
-    pub fn debug_root_level(&self) -> u16 {
-        self.inner_nodes.root_level()
+    /// Have the sweeper thread look for expired keys and delete them every `interval`. The
+    /// thread only starts once a key is written with a TTL
+    pub fn with_ttl_sweep_interval(mut self, interval: Duration) -> QuickStepConfig {
+        self.ttl_sweep_interval = interval;
+        self
     }
 
-    /// Test helper: materialises the user keys stored in the specified leaf page.
-    /// This acquires a transient read lock on the map table entry and copies the keys,
-    /// so it is safe to drop immediately after use in tests.
-    pub fn debug_leaf_snapshot(&self, page_id: PageId) -> Result<DebugLeafSnapshot, QSError> {
-        let guard = self.map_table.read_page_entry(page_id)?;
-        let snapshot = match guard.node() {
-            NodeRef::MiniPage(index) => {
-                let meta = unsafe { self.cache.get_meta_ref(index) };
-                DebugLeafSnapshot {
-                    page_id,
-                    disk_addr: meta.leaf(),
-                    keys: collect_user_keys(meta),
-                }
-            }
-            NodeRef::Leaf(disk_addr) => {
-                let disk_leaf = self.io_engine.get_page(disk_addr);
-                let meta = disk_leaf.as_ref();
-                DebugLeafSnapshot {
-                    page_id,
-                    disk_addr,
-                    keys: collect_user_keys(meta),
-                }
-            }
-        };
-        Ok(snapshot)
+    pub fn ttl_sweep_interval(&self) -> Duration {
+        self.ttl_sweep_interval
     }
 
-    /// Returns all key/value pairs with `lower <= key < upper`, sorted by key.
-    pub fn range_scan(
-        &self,
-        lower: &[u8],
-        upper: &[u8],
-    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, QSError> {
-        if upper <= lower {
-            return Ok(Vec::new());
-        }
-        let mut results = Vec::new();
-        for slot in 0..self.map_table.capacity() {
-            let page_id = PageId(slot as u64);
-            if !self.map_table.has_entry(page_id) {
-                continue;
-            }
-            let guard = self.map_table.read_page_entry(page_id)?;
-            match guard.node() {
-                NodeRef::MiniPage(index) => {
-                    let meta = unsafe { self.cache.get_meta_ref(index) };
-                    results.extend(records_between(meta, lower, upper));
-                }
-                NodeRef::Leaf(addr) => {
-                    let leaf = self.io_engine.get_page(addr);
-                    let meta = leaf.as_ref();
-                    results.extend(records_between(meta, lower, upper));
-                }
-            }
-        }
-        results.sort_by(|a, b| a.0.cmp(&b.0));
-        Ok(results)
+    /// Register the operator `QuickStepTx::merge` resolves operands with
+    pub fn with_merge_operator<M: MergeOperator + 'static>(
+        mut self,
+        operator: M,
+    ) -> QuickStepConfig {
+        self.merge_operator = Some(Arc::new(operator));
+        self
     }
 
-    pub fn debug_leaf_fences(&self, page_id: PageId) -> Result<DebugLeafFences, QSError> {
-        let guard = self.map_table.read_page_entry(page_id)?;
-        let (disk_addr, lower, upper) = match guard.node() {
-            NodeRef::MiniPage(index) => {
-                let meta = unsafe { self.cache.get_meta_ref(index) };
-                let (lower, upper) = collect_fence_keys(meta);
-                (meta.leaf(), lower, upper)
-            }
-            NodeRef::Leaf(disk_addr) => {
-                let disk_leaf = self.io_engine.get_page(disk_addr);
-                let meta = disk_leaf.as_ref();
-                let (lower, upper) = collect_fence_keys(meta);
-                (disk_addr, lower, upper)
-            }
-        };
+    pub fn has_merge_operator(&self) -> bool {
+        self.merge_operator.is_some()
+    }
 
-        Ok(DebugLeafFences {
-            page_id,
-            disk_addr,
-            lower,
-            upper,
-        })
+    /// Throttle writes once the WAL reaches `bytes`, see `health` for what throttling does
+    pub fn with_max_wal_size(mut self, bytes: usize) -> QuickStepConfig {
+        self.max_wal_bytes = Some(bytes.max(1));
+        self
     }
 
-    pub fn debug_wal_stats(&self, page_id: Option<PageId>) -> DebugWalStats {
-        let (leaf_records, leaf_bytes) = page_id
-            .and_then(|pid| self.wal.leaf_stats(pid))
-            .map(|(records, bytes)| (Some(records), Some(bytes)))
-            .unwrap_or((None, None));
+    pub fn max_wal_size(&self) -> Option<usize> {
+        self.max_wal_bytes
+    }
 
-        DebugWalStats {
-            total_records: self.wal.total_records(),
-            total_bytes: self.wal.total_bytes(),
-            leaf_records,
-            leaf_bytes,
-        }
+    /// What a write does when the WAL is at its limit and checkpointing frees nothing
+    pub fn with_wal_limit_policy(mut self, policy: WalLimitPolicy) -> QuickStepConfig {
+        self.wal_limit_policy = policy;
+        self
     }
 
-    fn replay_wal(&self) {
-        let mut grouped = self.wal.records_grouped();
-        if grouped.is_empty() {
-            return;
-        }
+    pub fn wal_limit_policy(&self) -> WalLimitPolicy {
+        self.wal_limit_policy
+    }
 
-        let txn_meta = grouped.remove(&TXN_META_PAGE_ID).unwrap_or_default();
-        let statuses = self.txn_statuses(&txn_meta);
+    /// When commits wait for the WAL to reach disk, trading durability of recent commits
+    /// for throughput, see `SyncPolicy`
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> QuickStepConfig {
+        self.sync_policy = policy;
+        self
+    }
 
-        for (page_key, records) in grouped.into_iter() {
-            let page_id = PageId(page_key);
-            if page_key as usize >= self.map_table.capacity() {
-                continue;
-            }
-            if !self.map_table.has_entry(page_id) {
-                continue;
-            }
-            let mut lower: Option<Vec<u8>> = None;
-            let mut upper: Option<Vec<u8>> = None;
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
 
-            let guard = self
-                .map_table
-                .read_page_entry(page_id)
-                .expect("WAL replay requires mapped page");
-            let node_ref = guard.node();
-            let disk_addr = match node_ref {
-                NodeRef::Leaf(addr) => addr,
-                NodeRef::MiniPage(idx) => unsafe { self.cache.get_meta_ref(idx) }.leaf(),
-            };
+    /// Once no foreground operation has started for `idle_after`, flush leaves with WAL
+    /// records and trim the WAL, writing at most `leaf_writes_per_sec` leaves a second, see
+    /// `maintenance`
+    pub fn with_idle_maintenance(
+        mut self,
+        idle_after: Duration,
+        leaf_writes_per_sec: u32,
+    ) -> QuickStepConfig {
+        self.idle_maintenance = Some(IdleMaintenance::new(idle_after, leaf_writes_per_sec));
+        self
+    }
 
-            let mut disk_leaf = self.io_engine.get_page(disk_addr);
-            let base_meta = disk_leaf.as_ref();
-            let (base_lower, base_upper) = collect_fence_keys(base_meta);
-            let mut entries: BTreeMap<Vec<u8>, Vec<u8>> =
-                collect_user_records(base_meta).into_iter().collect();
-
-            for record in records {
-                let WalRecord {
-                    page_id: _,
-                    key,
-                    lower_fence: record_lower,
-                    upper_fence: record_upper,
-                    kind,
-                    txn_id,
-                    op,
-                    ..
-                } = record;
-                if matches!(op, WalOp::TxnMarker(_)) {
-                    continue;
-                }
-                let committed = matches!(statuses.get(&txn_id), Some(TxStatus::Committed));
-                let apply = match kind {
-                    WalEntryKind::Redo => committed,
-                    WalEntryKind::Undo => !committed,
-                };
-                if !apply {
-                    continue;
-                }
-                lower = Some(record_lower);
-                upper = Some(record_upper);
-                apply_wal_op(&mut entries, key, op);
-            }
+    pub fn idle_maintenance(&self) -> Option<IdleMaintenance> {
+        self.idle_maintenance
+    }
 
-            if entries.is_empty() {
-                continue;
-            }
+    /// Log the key, kind, transaction and time of each committed put and delete, keeping
+    /// the newest `capacity` entries, see `activity_log`
+    pub fn with_activity_log(mut self, capacity: usize) -> QuickStepConfig {
+        self.activity_log = Some(capacity);
+        self
+    }
 
-            let (lower_fence, upper_fence) = match (lower, upper) {
-                (Some(l), Some(u)) => (l, u),
-                _ => (base_lower, base_upper),
-            };
+    pub fn activity_log(&self) -> Option<usize> {
+        self.activity_log
+    }
 
-            {
-                let leaf = &mut disk_leaf;
-                {
-                    let meta = leaf.as_mut();
-                    meta.reset_user_entries_with_fences(&lower_fence, &upper_fence);
-                    meta.replay_entries(
-                        entries
-                            .iter()
-                            .map(|(key, value)| (key.as_slice(), value.as_slice())),
-                    )
-                    .expect("disk leaf should accept WAL replay");
-                }
-                self.io_engine.write_page(disk_addr, &disk_leaf);
-            }
+    /// Roll back and release transactions that go `timeout` without an operation, such as
+    /// ones leaked without being dropped, see `txn_timeout`
+    pub fn with_txn_timeout(mut self, timeout: Duration) -> QuickStepConfig {
+        self.txn_timeout = Some(timeout);
+        self
+    }
+
+    pub fn txn_timeout(&self) -> Option<Duration> {
+        self.txn_timeout
+    }
+
+    /// Open without rewriting the leaves the WAL is ahead of: each is repaired from the log
+    /// when first read and written back by a later write, see `read_repair`
+    pub fn with_read_repair(mut self) -> QuickStepConfig {
+        self.read_repair = true;
+        self
+    }
+
+    pub fn read_repair(&self) -> bool {
+        self.read_repair
+    }
+
+    /// Open the store without taking its lock, to read one another handle or process has
+    /// open. The data file and WAL are read as they are at open, the WAL's records repaired
+    /// into leaves as they are read, and nothing is written back: writes, checkpoints and
+    /// compaction fail with `QSError::ReadOnly` and no background thread runs. Changes the
+    /// other handle makes after the open are not seen, and a page it is writing meanwhile
+    /// may fail its checksum
+    pub fn with_read_only(mut self) -> QuickStepConfig {
+        self.read_only = true;
+        self
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Every `interval`, read the WAL file back and report where it disagrees with the
+    /// manifest and record counts the store keeps as `HealthEvent::WalMismatch`, see
+    /// `WalManager::audit`
+    pub fn with_wal_audit(mut self, interval: Duration) -> QuickStepConfig {
+        self.wal_audit_interval = Some(interval);
+        self
+    }
+
+    pub fn wal_audit_interval(&self) -> Option<Duration> {
+        self.wal_audit_interval
+    }
+
+    /// Tell `listener` of every page allocated or freed, mini-page evicted and leaf split,
+    /// merged or moved, see `events`
+    pub fn with_event_listener(mut self, listener: Arc<dyn EventListener>) -> QuickStepConfig {
+        self.event_listener = Some(listener);
+        self
+    }
+
+    pub fn has_event_listener(&self) -> bool {
+        self.event_listener.is_some()
+    }
+
+    /// Hand `exporter` the store's metrics every `interval` from a background thread, for
+    /// pushing them to a collector, see `metrics`
+    pub fn with_metrics_exporter(
+        mut self,
+        interval: Duration,
+        exporter: Arc<dyn MetricsExporter>,
+    ) -> QuickStepConfig {
+        self.metrics_exporter = Some((interval, exporter));
+        self
+    }
+
+    pub fn has_metrics_exporter(&self) -> bool {
+        self.metrics_exporter.is_some()
+    }
+
+    /// Keep the newest `records` WAL records in memory for `QuickStep::tail_wal`, so a
+    /// process can stream them to a replica, see `WalManager::tail`
+    pub fn with_wal_tail_retention(mut self, records: usize) -> QuickStepConfig {
+        self.wal_tail_retention = records;
+        self
+    }
+
+    pub fn wal_tail_retention(&self) -> usize {
+        self.wal_tail_retention
+    }
+
+    /// What a leaf split does when the inner tree has no room for the nodes it needs, see
+    /// `health`
+    pub fn with_inner_tree_full_policy(mut self, policy: InnerTreeFullPolicy) -> QuickStepConfig {
+        self.inner_tree_full_policy = policy;
+        self
+    }
+
+    pub fn inner_tree_full_policy(&self) -> InnerTreeFullPolicy {
+        self.inner_tree_full_policy
+    }
+
+    /// Where a split divides a full leaf when the insert splitting it is not an append, see
+    /// `page_op::SplitPoint`. A `Ratio` is clamped to between 1 and 99
+    pub fn with_split_point(mut self, point: SplitPoint) -> QuickStepConfig {
+        self.split_policy.point = match point {
+            SplitPoint::Ratio { left_percent } => SplitPoint::Ratio {
+                left_percent: left_percent.clamp(1, 99),
+            },
+            point => point,
+        };
+        self
+    }
+
+    /// Share of the entries a split keeps on the left when the insert splitting the leaf sorts
+    /// after every key in it, clamped to between 1 and 99; `None` splits appends at the split
+    /// point too. Defaults to `page_op::SEQUENTIAL_SPLIT_PERCENT`
+    pub fn with_append_split(mut self, left_percent: Option<u8>) -> QuickStepConfig {
+        self.split_policy.append_left_percent = left_percent.map(|percent| percent.clamp(1, 99));
+        self
+    }
+
+    pub fn split_policy(&self) -> SplitPolicy {
+        self.split_policy
+    }
+
+    /// Every `interval`, check whether the data file holds `min_free_pages` free pages; if
+    /// so a background thread moves leaves into them and truncates the file, see
+    /// `compaction`
+    pub fn with_auto_compaction(
+        mut self,
+        interval: Duration,
+        min_free_pages: u64,
+    ) -> QuickStepConfig {
+        self.auto_compaction = Some(AutoCompaction::new(interval, min_free_pages));
+        self
+    }
+
+    pub fn auto_compaction(&self) -> Option<AutoCompaction> {
+        self.auto_compaction
+    }
+
+    /// Append values of at least `threshold` bytes to blob files of up to about `file_bytes`
+    /// beside the data file, their records only point at them; `QuickStep::gc_blobs` takes
+    /// back the space of values no longer pointed at. See `blob`
+    pub fn with_blob_store(mut self, threshold: usize, file_bytes: u64) -> QuickStepConfig {
+        self.blob_store = Some(BlobStore::new(threshold, file_bytes));
+        self
+    }
+
+    pub fn blob_store(&self) -> Option<BlobStore> {
+        self.blob_store
+    }
+
+    /// Reject writes of keys longer than `len` bytes, clamped to `limits::MAX_KEY_LEN`
+    pub fn with_max_key_len(mut self, len: usize) -> QuickStepConfig {
+        let limits = self.size_limits.default_limits();
+        self.size_limits
+            .set_default(SizeLimits::new(len, limits.max_value_len));
+        self
+    }
+
+    /// Reject writes of values longer than `len` bytes, clamped to `limits::MAX_VALUE_LEN`
+    pub fn with_max_value_len(mut self, len: usize) -> QuickStepConfig {
+        let limits = self.size_limits.default_limits();
+        self.size_limits
+            .set_default(SizeLimits::new(limits.max_key_len, len));
+        self
+    }
+
+    /// Hold keys starting with `prefix` to `limits` instead of the defaults; the longest
+    /// matching prefix decides
+    pub fn with_keyspace_limits(mut self, prefix: &[u8], limits: SizeLimits) -> QuickStepConfig {
+        self.size_limits.set_override(
+            prefix,
+            SizeLimits::new(limits.max_key_len, limits.max_value_len),
+        );
+        self
+    }
+
+    /// The limits writes to `key` are held to
+    pub fn size_limits(&self, key: &[u8]) -> SizeLimits {
+        self.size_limits.for_key(key)
+    }
+
+    /// Find keys by hash in `buckets` fixed leaves rather than through the inner nodes, for
+    /// keyspaces that never scan, see `hash_index`
+    pub fn with_hash_index(mut self, buckets: u64) -> QuickStepConfig {
+        self.hash_buckets = Some(buckets.max(1));
+        self
+    }
+
+    pub fn hash_index_buckets(&self) -> Option<u64> {
+        self.hash_buckets
+    }
+
+    /// Compress leaves before they are written to the data file, see `page_map`. A file
+    /// keeps the setting it was created with, opening it with the other one panics
+    pub fn with_page_compression(mut self) -> QuickStepConfig {
+        self.page_compression = true;
+        self
+    }
+
+    pub fn page_compression(&self) -> bool {
+        self.page_compression
+    }
+
+    /// Sleep before every read, write and sync of the data file and WAL, to see how the
+    /// store behaves on a slow disk, see `latency`
+    pub fn with_io_latency(mut self, latency: IoLatency) -> QuickStepConfig {
+        self.io_latency = Some(latency);
+        self
+    }
+
+    pub fn io_latency(&self) -> Option<IoLatency> {
+        self.io_latency
+    }
+
+    /// Authenticate the superblock and WAL manifest under `key`; opening files written under
+    /// another key, or tampered with, fails, see `QuickStep::open`
+    pub fn with_metadata_key(mut self, key: &[u8]) -> QuickStepConfig {
+        self.metadata_auth = MetadataAuth::new(key);
+        self
+    }
+
+    pub fn wal_thresholds(&self) -> (usize, usize, usize) {
+        (
+            self.wal_leaf_checkpoint_threshold,
+            self.wal_global_record_threshold,
+            self.wal_global_byte_threshold,
+        )
+    }
+}
+
+impl QuickStep {
+    /// Open the store as `open` does, panicking if it cannot be opened
+    pub fn new(config: QuickStepConfig) -> QuickStep {
+        QuickStep::open(config)
+            .unwrap_or_else(|err| panic!("failed to open quickstep store: {err:?}"))
+    }
+
+    /// Open the store, creating it if there is none, and recover it from its WAL. Fails with
+    /// `QSError::AlreadyLocked` while another handle has it open, `QSError::WalCorrupt` for a log
+    /// that cannot be read back, `QSError::DataFileTooSmall` for a data file cut short, and
+    /// `QSError::Io` for a file that cannot be opened or one that fails its checks
+    pub fn open(config: QuickStepConfig) -> Result<QuickStep, QSError> {
+        QuickStep::open_as(config, None)
+    }
+
+    fn open_as(
+        mut config: QuickStepConfig,
+        follower: Option<Follower>,
+    ) -> Result<QuickStep, QSError> {
+        config = config
+            .with_env_overrides()
+            .with_cli_overrides(env::args().skip(1));
+
+        let QuickStepConfig {
+            path,
+            inner_node_upper_bound,
+            inner_tree_full_policy,
+            split_policy,
+            leaf_upper_bound,
+            cache_size_lg,
+            wal_leaf_checkpoint_threshold,
+            wal_global_record_threshold,
+            wal_global_byte_threshold,
+            lock_wait,
+            max_db_bytes,
+            leaf_cache_pages,
+            checkpoint_policy,
+            checkpoint_batch,
+            checkpoint_interval,
+            ttl_sweep_interval,
+            merge_operator,
+            max_wal_bytes,
+            wal_limit_policy,
+            sync_policy,
+            idle_maintenance,
+            auto_compaction,
+            blob_store,
+            size_limits,
+            metadata_auth,
+            in_memory,
+            read_only,
+            hash_buckets,
+            page_compression,
+            io_latency,
+            activity_log,
+            txn_timeout,
+            read_repair,
+            wal_audit_interval,
+            event_listener,
+            wal_tail_retention,
+            metrics_exporter,
+        } = config;
+
+        if read_only && in_memory {
+            return Err(QSError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an in-memory store has no files to read",
+            )));
+        }
+        let data_path = resolve_data_path(&path);
+        if !in_memory {
+            check_data_file_len(&data_path)?;
+        }
+        let events = Events::new(event_listener);
+        // leaves the WAL is ahead of can't be written back, they are repaired as they are read
+        let read_repair = read_repair || read_only;
+
+        // the first page of the file holds metadata, data pages follow
+        let max_disk_pages = (max_db_bytes / PAGE_SIZE).saturating_sub(1);
+        let mut io_engine = if in_memory {
+            IoEngine::in_memory(max_disk_pages, &metadata_auth)
+        } else if read_only {
+            IoEngine::open_read_only(&data_path, &metadata_auth)
+        } else {
+            IoEngine::open_with_auth(&data_path, max_disk_pages, &metadata_auth)
+        }
+        .map_err(|err| match FileLocked::is(&err) {
+            true => QSError::AlreadyLocked {
+                path: data_path.clone(),
+            },
+            false => QSError::Io(err),
+        })?
+        .with_leaf_cache(leaf_cache_pages)
+        .with_events(events.clone())
+        .with_blob_log(if in_memory {
+            BlobLog::in_memory(blob_store)
+        } else if read_only {
+            BlobLog::open_read_only(&data_path)?
+        } else {
+            BlobLog::open(&data_path, blob_store)?
+        });
+        let latency_injector = io_latency.map(|latency| Arc::new(LatencyInjector::new(latency)));
+        if let Some(injector) = &latency_injector {
+            io_engine = io_engine.with_io_latency(Arc::clone(injector));
+        }
+        if let Some(operator) = merge_operator {
+            io_engine = io_engine.with_merge_operator(operator);
+        }
+        io_engine.claim_compression(page_compression, &metadata_auth)?;
+        io_engine.claim_layout(hash_buckets.unwrap_or(0), &metadata_auth)?;
+        let io_engine = Arc::new(io_engine);
+        let (prefetch_sender, prefetch_receiver) = mpsc::channel::<u64>();
+        let prefetch_thread = {
+            let io_clone = Arc::clone(&io_engine);
+            Some(thread::spawn(move || {
+                // ends once the db drops its sender
+                for disk_addr in prefetch_receiver {
+                    io_clone.prefetch_page(disk_addr);
+                }
+            }))
+        };
+        let wal_path = wal_path_for(&data_path);
+        let recovery_started = Instant::now();
+        let mut wal = if in_memory {
+            WalManager::in_memory(metadata_auth)
+        } else if read_only {
+            WalManager::memory_copy_of(&wal_path, metadata_auth)
+        } else {
+            WalManager::open_with_auth(&wal_path, metadata_auth)
+        }
+        .map_err(wal_open_error)?
+        .with_sync_policy(sync_policy)
+        .with_tail_retention(wal_tail_retention)
+        .with_events(events.clone());
+        if let Some(injector) = &latency_injector {
+            wal = wal.with_io_latency(Arc::clone(injector));
+        }
+        let wal = Arc::new(wal);
+        // the rest of the store's files are opened before any background thread starts
+        let activity_log = activity_log.map(ActivityLog::new);
+        let cache = Arc::new(MiniPageBuffer::new(cache_size_lg).with_events(events.clone()));
+        let map_table = Arc::new(MapTable::with_lock_wait(leaf_upper_bound, lock_wait));
+        let checkpoint_triggers = Arc::new(CheckpointTriggers::new(
+            (
+                wal_leaf_checkpoint_threshold,
+                wal_global_record_threshold,
+                wal_global_byte_threshold,
+            ),
+            checkpoint_interval,
+        ));
+        let txn_ids = match read_only {
+            true => TxnIds::unrecorded(io_engine.txn_id_mark()),
+            false => TxnIds::new(io_engine.txn_id_mark()),
+        };
+        let mut store = Store {
+            inner_nodes: match inner_tree_full_policy {
+                InnerTreeFullPolicy::Fail => BPTree::new(inner_node_upper_bound),
+                InnerTreeFullPolicy::Grow { max_inner_nodes } => {
+                    BPTree::with_max_capacity(inner_node_upper_bound, max_inner_nodes)
+                }
+            },
+            cache,
+            io_engine,
+            prefetch_sender: Some(prefetch_sender),
+            prefetch_thread,
+            map_table,
+            hash_buckets,
+            wal,
+            checkpoint_triggers,
+            wal_checkpoint_requested: Arc::new(AtomicBool::new(false)),
+            checkpoint_signal: Arc::default(),
+            checkpoint_policy,
+            checkpoint_batch,
+            checkpoint_cursor: Arc::new(AtomicU64::new(u64::MAX)),
+            ttl: Arc::new(TtlIndex::new()),
+            ttl_sweep_interval,
+            activity: Arc::new(ActivityClock::new()),
+            idle_leaf_writes: Arc::new(AtomicU64::new(0)),
+            txn_registry: txn_timeout.map(|timeout| Arc::new(TxnRegistry::new(timeout))),
+            activity_log,
+            read_repair,
+            wal_limit: Arc::new(WalLimit::new(max_wal_bytes, wal_limit_policy)),
+            events,
+            inner_tree_full_policy,
+            split_policy,
+            watchers: Watchers::new(),
+            txn_ids,
+            lock_owners: LockOwners::new(),
+            logical_bytes_written: AtomicU64::new(0),
+            recovery_report: RecoveryReport::default(),
+            size_limits,
+            latency_injector,
+            follower,
+            read_only,
+            in_doubt: InDoubt::default(),
+            stats: Arc::new(Stats::default()),
+            workers: Mutex::new(Some(Vec::new())),
+            replacements: Mutex::new(Vec::new()),
+            ttl_sweeper_started: AtomicBool::new(false),
+            this: Weak::new(),
+        };
+        // on failure the files are left as they were for another attempt, nothing is flushed
+        store.recover(recovery_started)?;
+        store.index_expiries()?;
+        store.load_activity_log()?;
+        let store = Arc::new_cyclic(|this| {
+            store.this = this.clone();
+            store
+        });
+        // every background thread writes, or only matters once something is written
+        if read_only {
+            return Ok(QuickStep {
+                store,
+                flush_on_drop: false,
+            });
+        }
+
+        // the background threads only start once recovery is done; each one holds the store
+        // until the handle stops it
+        let mut workers = Vec::new();
+        if let Some(idle) = idle_maintenance {
+            let store = Arc::clone(&store);
+            let mut budget = LeafWriteBudget::new(idle.leaf_writes_per_sec);
+            workers.push(Periodic::spawn(
+                "idle-maintenance",
+                idle.idle_after,
+                move || {
+                    let idle_for = store.activity.idle_for();
+                    if idle_for < idle.idle_after {
+                        return idle.idle_after - idle_for;
+                    }
+                    let available = budget.available();
+                    if available > 0 {
+                        let ctx = store.maintenance_ctx();
+                        match maintenance::run_pass(&ctx, idle.idle_after, available) {
+                            Ok(written) => {
+                                budget.spend(written);
+                                store
+                                    .idle_leaf_writes
+                                    .fetch_add(written as u64, Ordering::Relaxed);
+                            }
+                            Err(err) => {
+                                store.wal_limit.publish(HealthEvent::BackgroundPassFailed {
+                                    task: "idle-maintenance",
+                                    reason: format!("{err:?}"),
+                                })
+                            }
+                        }
+                    }
+                    IDLE_MAINTENANCE_POLL_INTERVAL
+                },
+            ));
+        }
+        workers.push({
+            let store = Arc::clone(&store);
+            // `None` waits the interval, read afresh so a change applies at once
+            let mut backoff = None;
+            Periodic::spawn_with(
+                Arc::clone(&store.checkpoint_signal),
+                "checkpoint",
+                store.checkpoint_triggers.interval(),
+                move || {
+                    let interval = store.checkpoint_triggers.interval();
+                    let wait = backoff.unwrap_or(interval);
+                    let (_, record_thresh, byte_thresh) = store.checkpoint_triggers.thresholds();
+                    let due = store.wal_checkpoint_requested.load(Ordering::Acquire)
+                        || match (store.wal.total_records(), store.wal.total_bytes()) {
+                            (Ok(records), Ok(bytes)) => {
+                                records >= record_thresh || bytes >= byte_thresh
+                            }
+                            // the queued records could not be written, the checkpoint's own
+                            // sync fails the same way and backs off
+                            _ => true,
+                        };
+                    let longer = (wait * 2).clamp(interval, CHECKPOINT_MAX_BACKOFF.max(interval));
+                    backoff = match due {
+                        false => None,
+                        true => match store.run_global_checkpoint() {
+                            // go again at once in case the WAL is still over
+                            Ok(checkpointed) if checkpointed > 0 => {
+                                store
+                                    .wal_checkpoint_requested
+                                    .store(false, Ordering::Release);
+                                Some(Duration::ZERO)
+                            }
+                            // only structure records are left, nothing to do till the next
+                            // write
+                            Ok(_)
+                                if store
+                                    .wal
+                                    .page_summaries()
+                                    .is_ok_and(|summaries| summaries.is_empty()) =>
+                            {
+                                store
+                                    .wal_checkpoint_requested
+                                    .store(false, Ordering::Release);
+                                None
+                            }
+                            // every pick was locked, leave the writers be for a while
+                            Ok(_) => Some(longer),
+                            Err(err) => {
+                                store.wal_limit.publish(HealthEvent::BackgroundPassFailed {
+                                    task: "checkpoint",
+                                    reason: format!("{err:?}"),
+                                });
+                                Some(longer)
+                            }
+                        },
+                    };
+                    backoff.unwrap_or(interval)
+                },
+            )
+        });
+        // a follower's data file only changes as the primary's does
+        if let Some(auto) = auto_compaction.filter(|_| store.follower.is_none()) {
+            let store = Arc::clone(&store);
+            workers.push(Periodic::spawn("compaction", auto.interval, move || {
+                if store.io_engine.free_pages() < auto.min_free_pages {
+                    return auto.interval;
+                }
+                // a leaf that can't be read is left for the foreground to report
+                match compaction::run(&store, COMPACTION_BATCH) {
+                    // more may be left after a full batch, go again at once
+                    Ok(stats) if stats.pages_moved as usize == COMPACTION_BATCH => Duration::ZERO,
+                    _ => auto.interval,
+                }
+            }));
+        }
+        if let Some(timeout) = txn_timeout {
+            let store = Arc::clone(&store);
+            let interval = timeout / 4;
+            workers.push(Periodic::spawn("txn-expiry", interval, move || {
+                let registry = store.txn_registry.as_ref().expect("txn timeout configured");
+                if registry.any_idle(now_millis()) {
+                    store.expire_idle_txns();
+                }
+                interval
+            }));
+        }
+        if let Some(interval) = wal_audit_interval {
+            let store = Arc::clone(&store);
+            let mut reported = Vec::new();
+            workers.push(Periodic::spawn("wal-audit", interval, move || {
+                // a read that fails is tried again next time
+                if let Ok(mismatches) = store.wal.audit() {
+                    // a mismatch is reported once, until it clears
+                    for mismatch in &mismatches {
+                        if !reported.contains(mismatch) {
+                            store
+                                .wal_limit
+                                .publish(HealthEvent::WalMismatch(mismatch.clone()));
+                        }
+                    }
+                    reported = mismatches;
+                }
+                interval
+            }));
+        }
+        if let SyncPolicy::Every(interval) = sync_policy {
+            let store = Arc::clone(&store);
+            workers.push(Periodic::spawn("wal-sync", interval, move || {
+                store.wal.sync().expect("failed to sync quickstep WAL");
+                interval
+            }));
+        }
+        if let Some((interval, exporter)) = metrics_exporter {
+            let store = Arc::clone(&store);
+            workers.push(Periodic::spawn("metrics", interval, move || {
+                // a WAL that can't be written is skipped, the next export tries again
+                if let Ok(metrics) =
+                    metrics::collect(&store.stats, &store.cache, &store.io_engine, &store.wal)
+                {
+                    exporter.export(&metrics);
+                }
+                interval
+            }));
+        }
+        *store.workers.lock().expect("workers mutex poisoned") = Some(workers);
+        if store.ttl.next_expiry().is_some() {
+            store.start_ttl_sweeper();
+        }
+        Ok(QuickStep {
+            store,
+            flush_on_drop: true,
+        })
+    }
+
+    /// Copy what the data file of the store `config` describes still holds into a new store
+    /// at `output_path`, created with the same settings, skipping the pages that fail their
+    /// checksum or checks, see `salvage`. For a store that no longer opens: the damaged one
+    /// is only read. Fails with `QSError::Io` if `output_path` already holds a store
+    pub fn salvage<P: AsRef<Path>>(
+        config: QuickStepConfig,
+        output_path: P,
+    ) -> Result<SalvageReport, QSError> {
+        salvage::salvage(config, output_path.as_ref())
+    }
+
+    /// Open a follower of another store, seeded from a copy of its data directory: local
+    /// writes fail with `QSError::FollowerReadOnly` and the store only changes through
+    /// `apply_wal_batch`, see `follower`
+    pub fn open_follower(config: QuickStepConfig) -> QuickStep {
+        QuickStep::open_as(config, Some(Follower::default()))
+            .unwrap_or_else(|err| panic!("failed to open quickstep store: {err:?}"))
+    }
+
+    /// Whether this build can open the database at `path`, read from the headers of its
+    /// data file and WAL without opening it, see `compatibility`
+    pub fn compatibility<P: AsRef<Path>>(path: P) -> std::io::Result<CompatReport> {
+        let data_path = resolve_data_path(path.as_ref());
+        let wal_path = wal_path_for(&data_path);
+        compatibility::inspect(data_path, wal_path)
+    }
+}
+
+impl Deref for QuickStep {
+    type Target = Store;
+
+    fn deref(&self) -> &Store {
+        &self.store
+    }
+}
+
+impl Drop for QuickStep {
+    fn drop(&mut self) {
+        self.store.stop_workers();
+        // a clean close keeps every commit, whatever the sync policy, and spares the next
+        // open the replay; a drop while panicking leaves it all to the WAL
+        if self.flush_on_drop && !thread::panicking() {
+            let _ = self.store.flush_leaves(false);
+        } else {
+            let _ = self.store.wal.sync();
+        }
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        // the prefetch thread ends once its sender is gone
+        self.prefetch_sender.take();
+        if let Some(handle) = self.prefetch_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl QuickStep {
+    /// Flush every leaf the WAL holds records for to the data file, trim the WAL and sync
+    /// both files, then close the store. The map table is rebuilt from the WAL's structure
+    /// records on open and the disk allocator is persisted as it changes, so nothing else
+    /// is left to write. Unlike dropping the store, which does the same but ignores
+    /// failures, the first failure is returned; the WAL then still has whatever was not
+    /// flushed. Leaves held by transactions leaked without being dropped are not flushed
+    pub fn close(self) -> Result<(), QSError> {
+        self.store.stop_workers();
+        if self.store.read_only {
+            return Ok(());
+        }
+        self.store.flush_leaves(false).map(|_| ())
+    }
+
+    /// Replace every entry of the store with `entries`, a later duplicate key winning. The
+    /// new tree is built in the background beside the live one, which keeps serving reads
+    /// and writes, and swapped in at the end; writes committed meanwhile are replaced too.
+    /// The returned handle waits for the swap. See `replace`
+    pub fn replace_all<I, K, V>(&self, entries: I) -> ReplaceHandle
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_vec(), value.as_ref().to_vec()))
+            .collect();
+        replace::spawn(&self.store, entries)
+    }
+
+    /// Close with every commit in the synced WAL but no leaf written back, as a crash
+    /// after the last commit would, so the next open replays the log
+    pub fn debug_close_without_flush(mut self) {
+        self.flush_on_drop = false;
+    }
+}
+
+impl Store {
+    /// Format a new file's first leaves, grow the tree back and replay the WAL
+    fn recover(&mut self, recovery_started: Instant) -> Result<(), QSError> {
+        // a read-only open only reads a store that was created before
+        if !self.read_only {
+            self.ensure_leaves_on_disk(self.hash_buckets.unwrap_or(1))?;
+        }
+
+        // initialise root leaf (page 0) and grow the tree back from the structure records,
+        // so replay can resolve the map-table entries of the leaves
+        let mut structure_changes = 0;
+        let mut leaves_scanned = 0;
+        match self.hash_buckets {
+            Some(buckets) => self.map_table.init_leaf_entries(buckets),
+            None => {
+                let root_page = self.map_table.init_leaf_entry(0);
+                self.inner_nodes.set_leaf_root(root_page);
+                structure_changes = self.restore_structure();
+                if structure_changes == 0 {
+                    leaves_scanned = self.log_structure_from_disk()?;
+                    structure_changes = self.restore_structure();
+                }
+            }
+        }
+
+        let open_stats = self.wal.open_stats();
+        let mut report = self.replay_wal()?;
+        report.structure_changes = structure_changes;
+        report.leaves_scanned = leaves_scanned;
+        report.wal_bytes_scanned = open_stats.bytes_scanned;
+        report.corrupt_records_skipped = open_stats.records_discarded;
+        report.corrupt_bytes_skipped = open_stats.bytes_discarded;
+        report.elapsed = recovery_started.elapsed();
+        self.in_doubt = InDoubt::new(&report.txns_in_doubt);
+        self.recovery_report = report;
+        Ok(())
+    }
+
+    /// Whether the store was opened with `open_follower`
+    pub fn is_follower(&self) -> bool {
+        self.follower.is_some()
+    }
+
+    /// Whether the store was opened with `QuickStepConfig::with_read_only`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fails with `QSError::ReadOnly` on a store opened read-only
+    pub(crate) fn check_writable(&self) -> Result<(), QSError> {
+        match self.read_only {
+            true => Err(QSError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    /// Apply records shipped from the primary's `tail_wal`, in the order it returned them.
+    /// Each transaction is replayed under page locks once its commit marker arrives. Fails
+    /// with `QSError::NotFollower` on a store not opened with `open_follower`; a batch
+    /// that fails part way can be sent again
+    pub fn apply_wal_batch<I>(&self, records: I) -> Result<ApplyStats, QSError>
+    where
+        I: IntoIterator<Item = WalRecord>,
+    {
+        let follower = self.follower.as_ref().ok_or(QSError::NotFollower)?;
+        self.activity.touch();
+        follower::apply(self, follower, records)
+    }
+
+    /// Create a new transaction for isolated operations
+    pub fn tx(&self) -> QuickStepTx<'_> {
+        self.activity.touch();
+        self.new_tx(false)
+    }
+
+    /// Create a transaction whose `range_scan`s lock the ranges they read, so no other
+    /// transaction can insert or delete a key in them until it ends. Writers into a locked
+    /// range fail with `QSError::WriteConflict` naming this transaction
+    pub fn serializable_tx(&self) -> QuickStepTx<'_> {
+        self.activity.touch();
+        self.new_tx(true)
+    }
+
+    /// A transaction that does not count as foreground activity, for background threads
+    fn new_tx(&self, serializable: bool) -> QuickStepTx<'_> {
+        let txn_id = self.txn_ids.next(&self.io_engine);
+        self.wal
+            .append_txn_marker(WalTxnMarker::Begin, WalEntryKind::Redo, txn_id)
+            .expect("failed to record txn begin");
+        let mut lock_manager = LockManager::for_txn(txn_id, &self.lock_owners);
+        let expiry = self.txn_registry.as_ref().map(|registry| {
+            let slot = registry.register(txn_id);
+            lock_manager.publish_held(Arc::clone(&slot.held));
+            slot
+        });
+        // coordination is done via the locks so it can just hold a reference to the db
+        QuickStepTx {
+            db: self,
+            lock_manager,
+            txn_id,
+            wal_entry_kind: WalEntryKind::Redo,
+            undo_log: Vec::new(),
+            state: TxState::Active,
+            ttl_changes: Vec::new(),
+            changes: Vec::new(),
+            serializable,
+            expiry,
+            replica: false,
+            activity_record: None,
+            overflow_written: Vec::new(),
+            overflow_replaced: Vec::new(),
+            value_buf: Vec::new(),
+        }
+    }
+
+    /// Roll back and release the transactions idle for longer than the configured timeout,
+    /// returns how many. A background thread does this once it finds one, see `txn_timeout`
+    pub fn expire_idle_txns(&self) -> usize {
+        self.txn_registry
+            .as_ref()
+            .map_or(0, |registry| txn_timeout::expire_idle(self, registry))
+    }
+
+    /// Transactions expired for being idle since open
+    pub fn expired_txns(&self) -> u64 {
+        self.txn_registry
+            .as_ref()
+            .map_or(0, |registry| registry.expired())
+    }
+
+    /// Stop the background threads, in the order they started; none start after this
+    fn stop_workers(&self) {
+        // taken before joining, a thread may be waiting for the lock to start another
+        let workers = self.workers.lock().expect("workers mutex poisoned").take();
+        drop(workers);
+        let replacements = mem::take(
+            &mut *self
+                .replacements
+                .lock()
+                .expect("replacements mutex poisoned"),
+        );
+        for thread in replacements {
+            let _ = thread.join();
+        }
+    }
+
+    /// Start the thread deleting expired keys, once the first key has a TTL. Each pass
+    /// deletes a batch of them in a transaction of its own, skipping leaves other
+    /// transactions are writing; a follower leaves them to the primary
+    fn start_ttl_sweeper(&self) {
+        if self.follower.is_some()
+            || self.read_only
+            || self.ttl_sweeper_started.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+        let mut workers = self.workers.lock().expect("workers mutex poisoned");
+        let (Some(workers), Some(store)) = (workers.as_mut(), self.this.upgrade()) else {
+            return;
+        };
+        let interval = self.ttl_sweep_interval;
+        workers.push(Periodic::spawn("ttl-sweep", interval, move || {
+            if store
+                .ttl
+                .next_expiry()
+                .is_none_or(|expires_at| expires_at > now_millis())
+            {
+                return interval;
+            }
+            let mut tx = store.new_tx(false);
+            match tx.sweep_expired(TTL_SWEEP_BATCH) {
+                Ok(swept) => {
+                    tx.commit();
+                    // there may be more after a full batch, go again at once
+                    match swept {
+                        TTL_SWEEP_BATCH => Duration::ZERO,
+                        _ => interval,
+                    }
+                }
+                // a leaf that can't be read is left for the foreground to report
+                Err(_) => {
+                    tx.abort();
+                    interval
+                }
+            }
+        }));
+    }
+
+    /// Components a maintenance pass works on
+    fn maintenance_ctx(&self) -> MaintenanceCtx<'_> {
+        MaintenanceCtx {
+            map_table: &self.map_table,
+            cache: &self.cache,
+            io_engine: &self.io_engine,
+            wal: &self.wal,
+            clock: &self.activity,
+        }
+    }
+
+    /// Write every leaf with WAL records back, sync the data file, then drop their records
+    /// and the markers of finished transactions and sync the WAL. Each leaf stays locked
+    /// until its records are gone; with `wait` a locked leaf is waited for as the lock-wait
+    /// policy allows, else it is skipped at once
+    fn flush_leaves(&self, wait: bool) -> Result<CheckpointStats, QSError> {
+        let before = self.wal.total_bytes()?;
+        self.wal.sync()?;
+        let mut stats = CheckpointStats::default();
+        let mut guards = Vec::new();
+        for summary in self.wal.page_summaries()? {
+            let guard = match wait {
+                true => self.map_table.write_page_entry(summary.page_id),
+                false => self.map_table.try_write_page_entry(summary.page_id),
+            };
+            let Ok(guard) = guard else {
+                stats.pages_skipped += 1;
+                continue;
+            };
+            match guard.node() {
+                NodeRef::MiniPage(index) => {
+                    // SAFETY: we hold the page's write lock
+                    let meta = unsafe { self.cache.get_meta_mut(index) };
+                    flush_dirty_entries(meta, &self.io_engine, self.wal.durable_lsn())?;
+                }
+                NodeRef::Leaf(addr) => self.io_engine.write_back_repair(addr)?,
+            }
+            guards.push(guard);
+        }
+        self.io_engine.sync_data()?;
+        let flushed: Vec<PageId> = guards.iter().map(|guard| guard.page).collect();
+        self.wal.checkpoint_pages(&flushed)?;
+        drop(guards);
+        self.wal.checkpoint_txn_markers()?;
+        self.wal.sync()?;
+        stats.pages_flushed = flushed.len();
+        stats.wal_bytes_reclaimed = before.saturating_sub(self.wal.total_bytes()?) as u64;
+        Ok(stats)
+    }
+}
+
+impl Store {
+    /// Format the first `count` leaves (the root, or the hash buckets) of a new file
+    fn ensure_leaves_on_disk(&self, count: u64) -> Result<(), QSError> {
+        for addr in 0..count {
+            let mut leaf = self.io_engine.get_page(addr)?;
+            {
+                let meta = leaf.as_mut();
+                if meta.record_count() >= 2 {
+                    continue;
+                }
+                meta.format_leaf(PageId(addr), NodeSize::LeafPage, addr);
+            }
+            self.io_engine.write_page(addr, &leaf);
+        }
+        Ok(())
+    }
+
+    /// The leaf `key` belongs in: its hash bucket, or the leaf the inner nodes lead to
+    fn leaf_for_key(&self, key: &[u8]) -> Result<PageId, QSError> {
+        match self.hash_buckets {
+            Some(buckets) => Ok(hash_index::bucket_for(key, buckets)),
+            None => Ok(self.inner_nodes.read_traverse_leaf(key)?.page),
+        }
+    }
+
+    /// Buckets of a hash-index store, `None` for the ordered tree
+    pub fn hash_index_buckets(&self) -> Option<u64> {
+        self.hash_buckets
+    }
+
+    /// Test helper to inspect the root after splits; not intended for production use.
+    pub fn debug_root_leaf_parent(&self) -> Option<DebugLeafParent> {
+        self.inner_nodes.debug_root_leaf_parent()
+    }
+
+    pub fn debug_root_level(&self) -> u16 {
+        self.inner_nodes.root_level()
+    }
+
+    /// Inner nodes in use, free and waiting to be reclaimed
+    pub fn inner_node_stats(&self) -> InnerNodeStats {
+        self.inner_nodes.inner_node_stats()
+    }
+
+    /// Leaves opened with read repair whose disk copy the WAL is still ahead of, repaired
+    /// or not, see `read_repair`
+    pub fn pending_read_repairs(&self) -> usize {
+        self.io_engine.read_repairs().outstanding()
+    }
+
+    /// Write back every leaf read repair has fixed up now rather than over the next writes,
+    /// returns how many were written. Leaves other transactions are writing are left
+    pub fn flush_read_repairs(&self) -> Result<usize, QSError> {
+        self.check_writable()?;
+        let mut tx = self.tx();
+        let rewritten = tx.rewrite_repaired(usize::MAX)?;
+        tx.commit();
+        Ok(rewritten)
+    }
+
+    /// Set aside the inner nodes a leaf split under `lock_bundle` may need, growing the tree
+    /// first if the policy allows. A refused split is reported to health subscribers
+    fn reserve_split_nodes(
+        &self,
+        lock_bundle: &WriteLockBundle<'_>,
+    ) -> Result<InnerReservation<'_>, QSError> {
+        let needed = lock_bundle.split_nodes_needed();
+        loop {
+            match self.inner_nodes.reserve(needed) {
+                Err(QSError::TreeFull) => {}
+                reserved => return reserved,
+            }
+            let grown = match self.inner_tree_full_policy {
+                InnerTreeFullPolicy::Grow { .. } => self.inner_nodes.grow(),
+                InnerTreeFullPolicy::Fail => None,
+            };
+            let event = match grown {
+                Some(capacity) => HealthEvent::InnerTreeGrown { capacity },
+                None => HealthEvent::InnerTreeFull {
+                    capacity: self.inner_nodes.inner_node_stats().capacity,
+                    needed,
+                },
+            };
+            self.wal_limit.publish(event);
+            if grown.is_none() {
+                return Err(QSError::TreeFull);
+            }
+        }
+    }
+
+    /// Test helper: materialises the user keys stored in the specified leaf page.
+    /// This acquires a transient read lock on the map table entry and copies the keys,
+    /// so it is safe to drop immediately after use in tests.
+    pub fn debug_leaf_snapshot(&self, page_id: PageId) -> Result<DebugLeafSnapshot, QSError> {
+        let guard = self.map_table.read_page_entry(page_id)?;
+        let snapshot = match guard.node() {
+            NodeRef::MiniPage(index) => {
+                let meta = unsafe { self.cache.get_meta_ref(index) };
+                let keys = match meta.size() {
+                    NodeSize::LeafPage => collect_user_keys(meta),
+                    // a smaller mini-page only holds part of the leaf
+                    _ => read_leaf_stored(&self.cache, &self.io_engine, guard.node())?
+                        .records
+                        .into_keys()
+                        .collect(),
+                };
+                DebugLeafSnapshot {
+                    page_id,
+                    disk_addr: meta.leaf(),
+                    keys,
+                    cached_bytes: Some(meta.size().size_in_bytes()),
+                }
+            }
+            NodeRef::Leaf(disk_addr) => {
+                let disk_leaf = self.io_engine.get_page(disk_addr)?;
+                let meta = disk_leaf.as_ref();
+                DebugLeafSnapshot {
+                    page_id,
+                    disk_addr,
+                    keys: collect_user_keys(meta),
+                    cached_bytes: None,
+                }
+            }
+        };
+        Ok(snapshot)
+    }
+
+    /// An in-memory layer of writes over this store, read before it and merged down or
+    /// dropped as a whole, see `overlay`
+    pub fn overlay(&self) -> Overlay<'_> {
+        Overlay::new(self)
+    }
+
+    /// Iterate the key/value pairs with `lower <= key < upper` in key order, reading a leaf at
+    /// a time. Stays consistent across concurrent splits and merges, see `iter`
+    pub fn range_iter(&self, lower: &[u8], upper: &[u8]) -> RangeIter<'_> {
+        self.activity.touch();
+        RangeIter::new(self, lower, upper)
+    }
+
+    /// Returns all key/value pairs with `lower <= key < upper`, sorted by key, collected from
+    /// `range_iter`.
+    #[allow(clippy::type_complexity)]
+    pub fn range_scan(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, QSError> {
+        self.range_iter(lower, upper).collect()
+    }
+
+    pub fn debug_leaf_fences(&self, page_id: PageId) -> Result<DebugLeafFences, QSError> {
+        let guard = self.map_table.read_page_entry(page_id)?;
+        let (disk_addr, lower, upper) = match guard.node() {
+            NodeRef::MiniPage(index) => {
+                let meta = unsafe { self.cache.get_meta_ref(index) };
+                let (lower, upper) = collect_fence_keys(meta);
+                (meta.leaf(), lower, upper)
+            }
+            NodeRef::Leaf(disk_addr) => {
+                let disk_leaf = self.io_engine.get_page(disk_addr)?;
+                let meta = disk_leaf.as_ref();
+                let (lower, upper) = collect_fence_keys(meta);
+                (disk_addr, lower, upper)
+            }
+        };
+
+        Ok(DebugLeafFences {
+            page_id,
+            disk_addr,
+            lower,
+            upper,
+        })
+    }
+
+    pub fn capacity_stats(&self) -> CapacityStats {
+        let max_disk_pages = self.io_engine.max_pages();
+        CapacityStats {
+            format_max_disk_pages: MAX_DISK_PAGES,
+            format_max_page_ids: MAX_PAGE_IDS,
+            format_max_bytes: MAX_DB_BYTES,
+            max_disk_pages,
+            max_page_ids: self.map_table.capacity() as u64,
+            max_bytes: (max_disk_pages + 1) * PAGE_SIZE,
+            disk_pages_used: self.io_engine.pages_used(),
+            disk_pages_free: self.io_engine.free_pages(),
+            page_ids_used: self.map_table.pages_used(),
+        }
+    }
+
+    /// Operations held up by the latency set with `QuickStepConfig::with_io_latency` and
+    /// the time spent waiting on it, `None` without one
+    pub fn io_latency_stats(&self) -> Option<LatencyStats> {
+        self.latency_injector
+            .as_ref()
+            .map(|injector| injector.stats())
+    }
+
+    /// The largest key and value a write to `key` may store, so inputs can be checked up
+    /// front; writes past them fail with `QSError::KeyTooLong` or `QSError::ValueTooLong`
+    pub fn size_limits(&self, key: &[u8]) -> SizeLimits {
+        self.size_limits.for_key(key)
+    }
+
+    /// What opening this database recovered from the WAL left by the last run
+    pub fn last_recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Prepared transactions left in doubt by the last run and not resolved yet, see
+    /// `two_phase`
+    pub fn in_doubt_txns(&self) -> Vec<u64> {
+        self.in_doubt.txns()
+    }
+
+    /// Commit or abort a transaction left in doubt, as its coordinator decided. Fails with
+    /// `QSError::NotInDoubt` for one that is not, or is already resolved
+    pub fn resolve_in_doubt(&self, txn_id: u64, commit: bool) -> Result<(), QSError> {
+        self.check_writable()?;
+        self.activity.touch();
+        two_phase::resolve(self, txn_id, commit)
+    }
+
+    /// Syncs the WAL has issued to make commits durable, see `SyncPolicy`
+    pub fn wal_syncs(&self) -> u64 {
+        self.wal.syncs()
+    }
+
+    /// How well leaves compress in the data file, `None` unless it compresses them
+    pub fn page_compression_stats(&self) -> Option<PageCompressionStats> {
+        self.io_engine.compression_stats()
+    }
+
+    /// Move leaves from the end of the data file into free pages nearer its start, then
+    /// truncate the free pages left at the end. Leaves locked by a transaction stay where
+    /// they are; see `compaction`
+    pub fn compact(&self) -> Result<CompactionStats, QSError> {
+        self.check_writable()?;
+        compaction::run(self, usize::MAX)
+    }
+
+    /// Move the values still pointed at out of every sealed blob file whose garbage makes up
+    /// at least `min_garbage_ratio` of it, between 0.0 and 1.0, then delete the file. Files
+    /// a transaction holds a key of are left for a later call; see `blob`
+    pub fn gc_blobs(&self, min_garbage_ratio: f64) -> Result<BlobGcStats, QSError> {
+        blob::collect(self, min_garbage_ratio)
+    }
+
+    /// Bytes held by blob files, including values no longer pointed at
+    pub fn blob_bytes(&self) -> u64 {
+        self.io_engine.blob_log().map_or(0, BlobLog::bytes)
+    }
+
+    /// Re-encode `lower <= key < upper` through `f` in batches of `DEFAULT_MIGRATION_BATCH`
+    /// entries, each in its own transaction. `Some((key, value))` replaces an entry and `None`
+    /// keeps it. A migration of the same range that was interrupted resumes after its last
+    /// committed batch, so `f` should keep entries it has already re-encoded; see `migrate`
+    pub fn migrate_range<F, K, V>(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+        f: F,
+    ) -> Result<MigrationStats, QSError>
+    where
+        F: FnMut(&[u8], &[u8]) -> Option<(K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.migrate_range_in_batches(lower, upper, DEFAULT_MIGRATION_BATCH, f)
+    }
+
+    /// `migrate_range` with `batch` entries per transaction
+    pub fn migrate_range_in_batches<F, K, V>(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+        batch: usize,
+        f: F,
+    ) -> Result<MigrationStats, QSError>
+    where
+        F: FnMut(&[u8], &[u8]) -> Option<(K, V)>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        migrate::run(self, lower, upper, batch, f)
+    }
+
+    /// Leaves idle maintenance has written since open
+    pub fn idle_leaf_writes(&self) -> u64 {
+        self.idle_leaf_writes.load(Ordering::Relaxed)
+    }
+
+    /// Operation counts and latencies, cache hits, page reads, WAL bytes, checkpoints,
+    /// splits and merges since open, see `stats`
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot(&self.cache, &self.io_engine, &self.wal)
+    }
+
+    /// The statistics of `stats` and the WAL and data file sizes as metric families, see
+    /// `metrics`
+    pub fn metrics(&self) -> Result<Vec<MetricFamily>, QSError> {
+        metrics::collect(&self.stats, &self.cache, &self.io_engine, &self.wal)
+    }
+
+    /// `metrics` in the Prometheus text exposition format, for a scrape endpoint to serve
+    pub fn metrics_text(&self) -> Result<String, QSError> {
+        Ok(metrics::to_text(&self.metrics()?))
+    }
+
+    pub fn write_amplification(&self) -> WriteAmplificationStats {
+        WriteAmplificationStats {
+            logical_bytes: self.logical_bytes_written.load(Ordering::Relaxed),
+            wal_bytes: self.wal.bytes_written(),
+            data_bytes: self.io_engine.bytes_written(),
+        }
+    }
+
+    fn record_logical_write(&self, bytes: usize) {
+        self.logical_bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Mini-page slots held in place by live `PinnedValue`s
+    pub fn debug_pinned_slots(&self) -> usize {
+        self.cache.pinned_slots()
+    }
+
+    pub fn leaf_cache_stats(&self) -> LeafCacheStats {
+        self.io_engine.leaf_cache_stats()
+    }
+
+    /// Write the leaf `page` back to disk and free its mini-page, the opposite of
+    /// `QuickStepTx::prefetch`, once a batch job is done with cold data. Returns whether the
+    /// leaf was dropped from the cache: a leaf already on disk, locked by a transaction or
+    /// held by a `PinnedValue` stays as it is
+    pub fn demote(&self, page: PageId) -> Result<bool, QSError> {
+        self.check_writable()?;
+        self.cache
+            .demote(&self.map_table, &self.io_engine, &self.wal, page)
+    }
+
+    /// `demote` every leaf overlapping `lower <= key < upper`, found from the inner nodes
+    /// alone. Returns how many leaves left the cache
+    pub fn demote_range(&self, lower: &[u8], upper: &[u8]) -> Result<usize, QSError> {
+        if self.hash_buckets.is_some() {
+            return Err(QSError::OrderedScanUnsupported);
+        }
+        let mut demoted = 0;
+        let mut cursor = lower.to_vec();
+        while cursor.as_slice() < upper {
+            let (page, successor) = self.inner_nodes.read_leaf_and_successor(&cursor)?;
+            demoted += self.demote(page)? as usize;
+            match successor {
+                Some(next) => cursor = next.into_vec(),
+                None => break,
+            }
+        }
+        Ok(demoted)
+    }
+
+    /// Mini-pages cached in the buffer
+    pub fn cached_leaves(&self) -> usize {
+        self.cache.live_pages()
+    }
+
+    /// Mini-page slots freed ahead of the ring head and waiting to be reused
+    pub fn free_listed_slots(&self) -> usize {
+        self.cache.free_listed_slots()
+    }
+
+    /// Queue the disk leaf behind `page` for the prefetch thread, unless reading `key` (or the
+    /// whole leaf, without one) would not touch the file
+    fn prefetch_leaf(&self, page: PageId, key: Option<&[u8]>) -> bool {
+        let Ok(guard) = self.map_table.try_read_page_entry(page) else {
+            return false;
+        };
+        let disk_addr = match guard.node() {
+            NodeRef::Leaf(disk_addr) => disk_addr,
+            NodeRef::MiniPage(index) => {
+                // SAFETY: we hold a read lock on the page
+                let meta = unsafe { self.cache.get_meta_ref(index) };
+                let cached = match key {
+                    _ if matches!(meta.size(), NodeSize::LeafPage) => true,
+                    Some(key) => key
+                        .strip_prefix(meta.get_node_prefix())
+                        .is_some_and(|suffix| meta.binary_search(suffix).is_ok()),
+                    None => false,
+                };
+                if cached {
+                    return false;
+                }
+                meta.leaf()
+            }
+        };
+        drop(guard);
+        if !self.io_engine.should_prefetch(disk_addr) {
+            return false;
+        }
+        self.prefetch_sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(disk_addr).is_ok())
+    }
+
+    /// What the WAL holds, in total and, as far as `level` asks, per leaf and per transaction.
+    /// The counts are exact; see `WalStats`
+    pub fn wal_stats(&self, level: WalStatsLevel) -> Result<WalStats, QSError> {
+        Ok(self.wal.stats(level)?)
+    }
+
+    pub fn debug_wal_stats(&self, page_id: Option<PageId>) -> Result<DebugWalStats, QSError> {
+        let leaf = match page_id {
+            Some(pid) => self.wal.leaf_stats(pid)?,
+            None => None,
+        };
+        let (leaf_records, leaf_bytes) = leaf
+            .map(|(records, bytes)| (Some(records), Some(bytes)))
+            .unwrap_or((None, None));
+
+        Ok(DebugWalStats {
+            total_records: self.wal.total_records()?,
+            total_bytes: self.wal.total_bytes()?,
+            leaf_records,
+            leaf_bytes,
+        })
+    }
+
+    /// Redo the logged splits, merges and relocations on the bare root leaf, returning how
+    /// many there were. A split's right leaf and a relocated leaf were written before their
+    /// records, so they only need mapping
+    fn restore_structure(&self) -> usize {
+        let records = self
+            .wal
+            .records_grouped()
+            .remove(&STRUCTURE_PAGE_ID)
+            .unwrap_or_default();
+        for record in records.iter() {
+            let WalOp::Smo(smo) = record.op else {
+                continue;
+            };
+            let key = record.key.as_slice();
+            let traverse = || {
+                self.inner_nodes
+                    .read_traverse_leaf(key)
+                    .expect("structure replay needs the tree it was logged against")
+            };
+            match smo {
+                WalSmo::Split {
+                    left,
+                    right,
+                    right_addr,
+                } => {
+                    let traversed = traverse();
+                    self.map_table.install_leaf_entry(PageId(right), right_addr);
+                    let mut lock_bundle = self
+                        .inner_nodes
+                        .write_lock(traversed.overflow_point, OpType::Split, key)
+                        .expect("structure replay runs alone");
+                    let mut reservation = self
+                        .reserve_split_nodes(&lock_bundle)
+                        .expect("no room in the inner tree to replay a leaf split");
+                    QuickStepTx::insert_into_parents_after_leaf_split(
+                        self,
+                        &mut reservation,
+                        &mut lock_bundle,
+                        PageId(left),
+                        key,
+                        PageId(right),
+                    )
+                    .expect("failed to replay a leaf split");
+                }
+                WalSmo::Merge { survivor, removed } => {
+                    let traversed = traverse();
+                    let mut lock_bundle = self
+                        .inner_nodes
+                        .write_lock(traversed.underflow_point, OpType::Merge, key)
+                        .expect("structure replay runs alone");
+                    QuickStepTx::remove_parent_after_merge(
+                        self,
+                        &mut lock_bundle,
+                        PageId(survivor),
+                        PageId(removed),
+                    )
+                    .expect("failed to replay a leaf merge");
+                }
+                WalSmo::Relocate { page, addr } => {
+                    self.map_table.install_leaf_entry(PageId(page), addr);
+                }
+            }
+        }
+        records.len()
+    }
+
+    /// Log the tree the leaves on disk describe, for a file whose log has lost its structure
+    /// records: each leaf holds its page id and fences, so the leaves sorted by lower fence
+    /// are the splits that made them. Returns how many leaves were found, 0 if the file
+    /// holds just the root leaf or its leaves do not start with page 0.
+    ///
+    /// Freed pages are zeroed and skipped. A leaf overlapping the one before it is the right
+    /// half of a split that was never logged, whose records the left half still holds
+    fn log_structure_from_disk(&self) -> Result<usize, QSError> {
+        let mut leaves = Vec::new();
+        for addr in 0..self.io_engine.pages_used() {
+            let Ok(leaf) = self.io_engine.get_page(addr) else {
+                continue;
+            };
+            let meta = leaf.as_ref();
+            if meta.record_count() < 2 {
+                continue;
+            }
+            let (lower, upper) = collect_fence_keys(meta);
+            leaves.push((lower, upper, meta.page_id(), addr));
+        }
+        leaves.sort_by(|a, b| (&a.0, a.3).cmp(&(&b.0, b.3)));
+        match leaves.first() {
+            Some((lower, _, page, _)) if lower == LOWER_SENTINEL && *page == PageId(0) => {}
+            _ => return Ok(0),
+        }
+        if leaves.len() == 1 && leaves[0].3 == 0 {
+            return Ok(0);
+        }
+
+        let (_, mut prev_upper, mut prev_page, root_addr) = leaves[0].clone();
+        if root_addr != 0 {
+            let relocate = WalSmo::Relocate {
+                page: 0,
+                addr: root_addr,
+            };
+            self.wal.append_smo(relocate, &[])?;
+        }
+        let mut scanned = 1;
+        for (lower, upper, page, addr) in leaves.into_iter().skip(1) {
+            if lower < prev_upper {
+                continue;
+            }
+            let split = WalSmo::Split {
+                left: prev_page.as_u64(),
+                right: page.as_u64(),
+                right_addr: addr,
+            };
+            self.wal.append_smo(split, &lower)?;
+            (prev_upper, prev_page) = (upper, page);
+            scanned += 1;
+        }
+        Ok(scanned)
+    }
+
+    /// Apply the WAL to the leaves and clear all but its structure records, reporting what
+    /// was replayed
+    fn replay_wal(&self) -> Result<RecoveryReport, QSError> {
+        let mut report = RecoveryReport::default();
+        let mut grouped = self.wal.records_grouped();
+        grouped.remove(&STRUCTURE_PAGE_ID);
+        if grouped.is_empty() {
+            return Ok(report);
+        }
+
+        let txn_meta = grouped.remove(&TXN_META_PAGE_ID).unwrap_or_default();
+        let statuses = self.txn_statuses(&txn_meta);
+        let aborted_at: HashMap<u64, u64> = txn_meta
+            .iter()
+            .filter(|record| matches!(record.op, WalOp::TxnMarker(WalTxnMarker::Abort)))
+            .map(|record| (record.txn_id, record.lsn))
+            .collect();
+        let next_lsn = self.wal.open_stats().next_lsn;
+        let mut rolled_back: HashSet<u64> = txn_meta
+            .iter()
+            .map(|record| record.txn_id)
+            .chain(grouped.values().flatten().map(|record| record.txn_id))
+            .collect();
+        rolled_back.retain(|txn_id| {
+            !matches!(
+                statuses.get(txn_id),
+                Some(TxStatus::Committed | TxStatus::Prepared)
+            )
+        });
+        report.txns_rolled_back = rolled_back.len();
+        // out of the leaves like the rolled back ones, but their records stay in the log
+        report.txns_in_doubt = statuses
+            .iter()
+            .filter(|(_, status)| matches!(status, TxStatus::Prepared))
+            .map(|(txn_id, _)| *txn_id)
+            .collect();
+        report.txns_in_doubt.sort_unstable();
+        // transactions numbered after the ones left in the log keep their markers apart
+        let last_txn = rolled_back.iter().chain(statuses.keys()).max().copied();
+        self.txn_ids.skip_past(last_txn.unwrap_or(0));
+        // only a transaction cut short leaves no marker; an aborted one freed its own chains
+        // once its marker was synced
+        let leaked: Vec<OverflowPointer> = grouped
+            .values()
+            .flatten()
+            .filter(|record| {
+                matches!(record.kind, WalEntryKind::Redo) && !statuses.contains_key(&record.txn_id)
+            })
+            .filter_map(|record| match &record.op {
+                WalOp::Put { value } | WalOp::Merge { value, .. } => OverflowPointer::decode(value),
+                _ => None,
+            })
+            .collect();
+
+        for (page_key, records) in grouped.into_iter() {
+            let page_id = PageId(page_key);
+            if page_key as usize >= self.map_table.capacity() {
+                continue;
+            }
+            if !self.map_table.has_entry(page_id) {
+                continue;
+            }
+            let guard = self
+                .map_table
+                .read_page_entry(page_id)
+                .expect("WAL replay requires mapped page");
+            let node_ref = guard.node();
+            let disk_addr = match node_ref {
+                NodeRef::Leaf(addr) => addr,
+                NodeRef::MiniPage(idx) => unsafe { self.cache.get_meta_ref(idx) }.leaf(),
+            };
+
+            let mut repair = LeafRepair::from_records(
+                records,
+                |txn_id| matches!(statuses.get(&txn_id), Some(TxStatus::Committed)),
+                |txn_id| aborted_at.get(&txn_id).copied(),
+                next_lsn,
+            );
+            if self.read_repair {
+                report.records_replayed += repair.len();
+                // the leaf is repaired when it is first read, the log keeps its records
+                // until then
+                self.io_engine.read_repairs().stage(disk_addr, repair);
+                report.pages_deferred += 1;
+                continue;
+            }
+
+            let mut disk_leaf = self.io_engine.get_page(disk_addr)?;
+            report.records_skipped += repair.skip_reflected(disk_leaf.as_ref());
+            report.records_replayed += repair.len();
+            if repair.is_current(disk_leaf.as_ref()) {
+                continue;
+            }
+            let Some(repaired) = repair.replay(disk_leaf.as_ref(), self.io_engine.merge_operator())
+            else {
+                continue;
+            };
+            repaired.write_into(disk_leaf.as_mut());
+            self.io_engine.write_page(disk_addr, &disk_leaf);
+            report.pages_rebuilt += 1;
 
             if let NodeRef::MiniPage(idx) = node_ref {
                 let meta = unsafe { self.cache.get_meta_mut(idx) };
-                meta.reset_user_entries_with_fences(&lower_fence, &upper_fence);
-                meta.replay_entries(
-                    entries
-                        .iter()
-                        .map(|(key, value)| (key.as_slice(), value.as_slice())),
-                )
-                .expect("cached leaf should accept WAL replay");
+                repaired.write_into(meta);
+            }
+        }
+        if report.pages_deferred > 0 {
+            // the records stay in the log; the rolled back transactions are finished, their
+            // records can go with their leaves' checkpoints
+            for txn_id in rolled_back {
+                if !statuses.contains_key(&txn_id) {
+                    self.wal
+                        .append_txn_marker(WalTxnMarker::Abort, WalEntryKind::Redo, txn_id)?;
+                }
             }
+            self.wal.sync()?;
+        } else {
+            self.wal.clear_leaf_records()?;
+        }
+        // the log no longer shows their transactions unfinished, so no later open frees the
+        // chains again once their pages are reused; a read-only open leaves them to the next
+        // open that can write
+        if self.read_only {
+            return Ok(report);
+        }
+        report.overflow_chains_freed = leaked.len();
+        for chain in leaked {
+            self.io_engine.free_overflow(chain);
         }
-        self.wal.clear().expect("failed to clear WAL after replay");
+        Ok(report)
     }
 
     fn txn_statuses(&self, txn_meta: &[WalRecord]) -> HashMap<u64, TxStatus> {
@@ -538,6 +2388,9 @@ impl QuickStep {
                     WalTxnMarker::Abort => {
                         statuses.insert(record.txn_id, TxStatus::Aborted);
                     }
+                    WalTxnMarker::Prepare => {
+                        statuses.entry(record.txn_id).or_insert(TxStatus::Prepared);
+                    }
                     WalTxnMarker::Begin => {}
                 }
             }
@@ -545,18 +2398,43 @@ impl QuickStep {
         statuses
     }
 
-    pub fn debug_wal_record_count(&self) -> usize {
-        self.wal.total_records()
+    pub fn debug_wal_record_count(&self) -> Result<usize, QSError> {
+        Ok(self.wal.total_records()?)
     }
 }
 
 pub struct QuickStepTx<'db> {
-    db: &'db QuickStep,
+    db: &'db Store,
     lock_manager: LockManager<'db>,
     txn_id: u64,
     wal_entry_kind: WalEntryKind,
     undo_log: Vec<UndoAction>,
     state: TxState,
+    /// Expiries this transaction's writes set or cleared, applied to the sweeper's index
+    /// on commit
+    ttl_changes: Vec<(Vec<u8>, Option<u64>)>,
+    /// Writes to publish to subscribers on commit, only kept while someone is subscribed
+    changes: Vec<ChangeEvent>,
+    /// Scans lock the key ranges they read, see `QuickStep::serializable_tx`
+    serializable: bool,
+    /// Shared with the registry when transactions expire once idle, the undo log is kept
+    /// there instead of in `undo_log`
+    expiry: Option<Arc<TxnSlot>>,
+    /// Writes records as another store logged them: shipped records on a follower, where it
+    /// is the only transaction allowed to write, or salvaged ones. It may write reserved
+    /// keys and logs no activity of its own
+    replica: bool,
+    /// Key and entry count of the record this transaction wrote to the activity log
+    activity_record: Option<(Vec<u8>, usize)>,
+    /// Overflow chains this transaction wrote, freed if it aborts, see `overflow`. Kept in
+    /// `expiry` instead when the transaction can expire
+    overflow_written: Vec<OverflowPointer>,
+    /// Overflow chains of the values this transaction overwrote or deleted, freed once its
+    /// commit is synced
+    overflow_replaced: Vec<OverflowPointer>,
+    /// The last value `get` read from an overflow chain or blob file, or copied out of the
+    /// leaf when the transaction can expire, borrowed by what it returned
+    value_buf: Vec<u8>,
     // changes for rollback
 }
 
@@ -580,47 +2458,454 @@ enum UndoAction {
     },
 }
 
+/// What a write stores: a plain value, an operand to resolve against the current one, or
+/// the current value with its soft-delete flag changed
+enum RecordValue<'a> {
+    Put {
+        value: &'a [u8],
+        /// Expiry and flag the record is given, see `attrs`
+        attrs: RecordAttrs,
+    },
+    /// The key's expiry is kept unless it has passed
+    Merge {
+        operator: &'a dyn MergeOperator,
+        operand: &'a [u8],
+    },
+    SoftDelete(bool),
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 enum TxStatus {
     Committed,
     Aborted,
+    /// Prepared and waiting to be resolved, see `two_phase`
+    Prepared,
 }
 
 impl<'db> QuickStepTx<'db> {
     /// Get a value
     pub fn get<'tx>(&'tx mut self, key: &[u8]) -> Result<Option<&'tx [u8]>, QSError> {
-        let page = self.db.inner_nodes.read_traverse_leaf(key)?.page;
+        self.lookup(key, true)
+    }
+
+    /// Get a value without copying it out of the leaf, it stays borrowed under this
+    /// transaction's read lock on the page. Unlike `get`, a value read from the disk leaf is
+    /// not copied into the mini-page, so it borrows from the disk leaf image the page guard
+    /// holds; a value in overflow pages is still read into a buffer, as is every value when
+    /// transactions expire, see `QuickStepConfig::with_txn_timeout`
+    pub fn get_ref<'tx>(&'tx mut self, key: &[u8]) -> Result<Option<ValueRef<'tx>>, QSError> {
+        Ok(self.lookup(key, false)?.map(ValueRef::new))
+    }
+
+    /// `admit` lets a value read from the disk leaf be cached in the mini-page
+    fn lookup<'tx>(&'tx mut self, key: &[u8], admit: bool) -> Result<Option<&'tx [u8]>, QSError> {
+        let _op = self.enter()?;
+        let started = Instant::now();
+        let page = self.db.leaf_for_key(key)?;
 
         let page_guard = self
             .lock_manager
             .get_or_acquire_read_lock(&self.db.map_table, page)?;
 
-        let res = page_guard.get(&self.db.cache, &self.db.io_engine, key)?;
+        let res = match admit {
+            true => page_guard.get(&self.db.cache, &self.db.io_engine, key)?,
+            false => page_guard.get_in_place(&self.db.cache, &self.db.io_engine, key)?,
+        };
+        let now = now_millis();
+        let res = res.filter(|stored| !attrs::split(stored).0.hides(now));
+        let res = match res.and_then(|stored| merge::fetch(&self.db.io_engine, key, stored)) {
+            Some(value) => {
+                self.value_buf = value?;
+                Some(self.value_buf.as_slice())
+            }
+            // an expired transaction's locks are released under it, so it can't keep a
+            // borrow of the leaf
+            None if self.expiry.is_some() => match res {
+                Some(stored) => {
+                    self.value_buf.clear();
+                    self.value_buf.extend_from_slice(attrs::split(stored).1);
+                    Some(self.value_buf.as_slice())
+                }
+                None => None,
+            },
+            None => res.map(|stored| attrs::split(stored).1),
+        };
+        self.db.stats.gets.record(started.elapsed());
 
         Ok(res)
     }
 
-    /// Insert or update a value
-    pub fn put<'tx>(&'tx mut self, key: &[u8], val: &[u8]) -> Result<(), QSError> {
-        let res = self.db.inner_nodes.read_traverse_leaf(key)?;
+    /// Get a value that stays readable after this transaction ends, see `PinnedValue`
+    pub fn get_pinned(&mut self, key: &[u8]) -> Result<Option<PinnedValue<'db>>, QSError> {
+        let _op = self.enter()?;
+        let page = self.db.leaf_for_key(key)?;
+
+        let page_guard = self
+            .lock_manager
+            .get_or_acquire_read_lock(&self.db.map_table, page)?;
+
+        page_guard.get_pinned(&self.db.cache, &self.db.io_engine, key, now_millis())
+    }
 
-        let mut page_guard = self
+    /// Size and whereabouts of the value stored for `key` without reading it out, `None` if
+    /// the key is absent or expired. Soft-deleted keys and unflushed tombstones are reported
+    /// with their flag set. May read the leaf from disk to find the record, as `get` would
+    pub fn entry_info(&mut self, key: &[u8]) -> Result<Option<EntryInfo>, QSError> {
+        let _op = self.enter()?;
+        let page = self.db.leaf_for_key(key)?;
+
+        let page_guard = self
             .lock_manager
-            .get_upgrade_or_acquire_write_lock(&self.db.map_table, res.page)?;
+            .get_or_acquire_read_lock(&self.db.map_table, page)?;
+
+        page_guard.entry_info(&self.db.cache, &self.db.io_engine, key, now_millis())
+    }
+
+    /// Start reading the leaves holding `keys` into the leaf cache in the background, so the
+    /// gets that follow skip the file. Returns how many leaves were queued: leaves already
+    /// cached, held in the mini-page buffer or locked by a writer are skipped. Does nothing
+    /// when the leaf cache is disabled
+    pub fn prefetch<I>(&self, keys: I) -> Result<usize, QSError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut queued = 0;
+        for key in keys {
+            let key = key.as_ref();
+            let page = self.db.leaf_for_key(key)?;
+            queued += self.db.prefetch_leaf(page, Some(key)) as usize;
+        }
+        Ok(queued)
+    }
+
+    /// `prefetch` for every leaf overlapping `lower <= key < upper`, found from the inner
+    /// nodes alone, ahead of a `range_iter` over the same bounds
+    pub fn prefetch_range(&self, lower: &[u8], upper: &[u8]) -> Result<usize, QSError> {
+        if self.db.hash_buckets.is_some() {
+            return Err(QSError::OrderedScanUnsupported);
+        }
+        let mut queued = 0;
+        let mut cursor = lower.to_vec();
+        while cursor.as_slice() < upper {
+            let (page, successor) = self.db.inner_nodes.read_leaf_and_successor(&cursor)?;
+            queued += self.db.prefetch_leaf(page, None) as usize;
+            match successor {
+                Some(next) => cursor = next.into_vec(),
+                None => break,
+            }
+        }
+        Ok(queued)
+    }
+
+    /// The id this transaction is logged under
+    pub fn txn_id(&self) -> u64 {
+        self.txn_id
+    }
+
+    /// Transactions whose locks made a write in this transaction fail with
+    /// `QSError::WriteConflict`
+    pub fn conflicting_txns(&self) -> &[u64] {
+        self.lock_manager.conflicts()
+    }
+
+    /// Insert or update a value, clearing any expiry the key had
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> Result<(), QSError> {
+        self.put_with_attrs(key, val, RecordAttrs::default())
+    }
+
+    /// Combine `operand` with the key's current value through the registered merge operator,
+    /// without a separate read. An expiry on the key is kept unless it has already passed
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<(), QSError> {
+        let _op = self.enter()?;
+        self.check_sizes(key, None)?;
+        self.enforce_wal_limit()?;
+        let db = self.db;
+        let operator = db
+            .io_engine
+            .merge_operator()
+            .ok_or(QSError::NoMergeOperator)?;
+        self.put_record(key, RecordValue::Merge { operator, operand })?;
+        self.maybe_rewrite_repaired()
+    }
+
+    /// Insert or update a value that expires `ttl` from now. The expiry takes effect when the
+    /// transaction commits
+    pub fn put_with_ttl(&mut self, key: &[u8], val: &[u8], ttl: Duration) -> Result<(), QSError> {
+        let attrs = RecordAttrs {
+            expires_at: Some(now_millis().saturating_add(ttl.as_millis() as u64)),
+            ..RecordAttrs::default()
+        };
+        self.put_with_attrs(key, val, attrs)
+    }
+
+    /// Write a value as it was stored elsewhere, in a shipped WAL record or a salvaged leaf,
+    /// keeping the expiry and flag of its header. A pointer in it must have been read back
+    /// already, see `overflow::fetch_stored`; a long value is stored as this store's config
+    /// says
+    pub(crate) fn put_stored(&mut self, key: &[u8], stored: &[u8]) -> Result<(), QSError> {
+        let (attrs, value) = attrs::split(stored);
+        self.put_with_attrs(key, value, attrs)
+    }
+
+    fn put_with_attrs(
+        &mut self,
+        key: &[u8],
+        val: &[u8],
+        attrs: RecordAttrs,
+    ) -> Result<(), QSError> {
+        let _op = self.enter()?;
+        self.check_sizes(key, Some(val))?;
+        let started = Instant::now();
+        self.enforce_wal_limit()?;
+        self.put_record(key, RecordValue::Put { value: val, attrs })?;
+        self.db.stats.puts.record(started.elapsed());
+        self.maybe_rewrite_repaired()
+    }
+
+    /// Write `value` to `key` in the reserved keyspace, see `reserved`
+    pub(crate) fn put_reserved(&mut self, key: &[u8], value: &[u8]) -> Result<(), QSError> {
+        let _op = self.enter()?;
+        self.enforce_wal_limit()?;
+        let attrs = RecordAttrs::default();
+        self.put_record(key, RecordValue::Put { value, attrs })?;
+        Ok(())
+    }
+
+    /// Delete `key` from the reserved keyspace, if it is there
+    pub(crate) fn delete_reserved(&mut self, key: &[u8]) -> Result<(), QSError> {
+        let _op = self.enter()?;
+        self.delete_record(key)?;
+        Ok(())
+    }
+
+    /// Hide a key from reads and scans without removing its value, returns whether it was
+    /// visible. `undelete` brings it back; a put or delete of the key clears the flag. The
+    /// flag is written to the key's record, which takes effect when the transaction commits
+    pub fn soft_delete(&mut self, key: &[u8]) -> Result<bool, QSError> {
+        let _op = self.enter()?;
+        self.check_not_reserved(key)?;
+        self.put_record(key, RecordValue::SoftDelete(true))
+    }
+
+    /// Make a soft-deleted key visible again with the value it had, returns whether it was
+    /// soft-deleted
+    pub fn undelete(&mut self, key: &[u8]) -> Result<bool, QSError> {
+        let _op = self.enter()?;
+        self.check_not_reserved(key)?;
+        self.put_record(key, RecordValue::SoftDelete(false))
+    }
+
+    /// Hold a write to its keyspace's limits before it waits on the WAL or touches a leaf,
+    /// see `limits`. A merge's value is checked once its operand has been applied
+    fn check_sizes(&self, key: &[u8], value: Option<&[u8]>) -> Result<(), QSError> {
+        self.check_not_reserved(key)?;
+        let limits = self.db.size_limits.for_key(key);
+        limits.check_key(key)?;
+        value.map_or(Ok(()), |value| limits.check_value(value))
+    }
+
+    /// Only the store writes the reserved keyspace, see `reserved`
+    fn check_not_reserved(&self, key: &[u8]) -> Result<(), QSError> {
+        if reserved::is_reserved(key) && !self.replica {
+            return Err(QSError::ReservedKey { key: key.to_vec() });
+        }
+        Ok(())
+    }
+
+    /// Write `value` to `key`'s record, returns whether it did: a soft-delete flag is only
+    /// changed on a record that has a value and is not already in that state
+    fn put_record(&mut self, key: &[u8], value: RecordValue<'_>) -> Result<bool, QSError> {
+        let limits = self.db.size_limits.for_key(key);
+        let mut page_guard = self.write_lock_for_key(key)?;
+
+        // kept for the undo log, so it is copied out of the leaf
+        let undo_value = Self::with_existing_value(self.db, &mut page_guard, key, |value| {
+            value.map(<[u8]>::to_vec)
+        })?;
+        let now = now_millis();
+        let current = undo_value.as_deref().map(attrs::split);
+        let current_attrs = current.map(|(attrs, _)| attrs).unwrap_or_default();
+
+        // an operand is stored with the value it goes into and folded once that is read,
+        // unless the record can't hold it, see `merge`
+        let owned;
+        let mut keeps_blob = false;
+        let (val, attrs, operand) = match value {
+            RecordValue::Put { value, attrs } => (value, attrs, None),
+            RecordValue::Merge { operator, operand } => {
+                // an expired or soft-deleted value is gone as far as the operand is concerned
+                let visible = undo_value
+                    .as_deref()
+                    .filter(|stored| !attrs::split(stored).0.hides(now));
+                let mut attrs = RecordAttrs {
+                    expires_at: current_attrs.expires_at.filter(|at| *at > now),
+                    ..RecordAttrs::default()
+                };
+                // a tighter limit than the format's is checked on the folded value
+                match merge::push_operand(visible, operand)
+                    .filter(|_| limits.max_value_len >= limits::MAX_VALUE_LEN)
+                    .filter(|merged| !overflow::needs_chain(merged, attrs::HEADER_LEN))
+                {
+                    Some(merged) => {
+                        owned = merged;
+                        attrs.pending_merge = true;
+                    }
+                    None => {
+                        let existing = match visible {
+                            Some(stored) => Some(merge::resolve(&self.db.io_engine, key, stored)?),
+                            None => None,
+                        };
+                        owned = operator.merge(key, existing.as_deref(), operand);
+                        limits.check_value(&owned)?;
+                    }
+                }
+                (owned.as_slice(), attrs, Some(operand))
+            }
+            RecordValue::SoftDelete(deleted) => {
+                let Some((_, existing)) = current else {
+                    return Ok(false);
+                };
+                let changes = match deleted {
+                    true => !current_attrs.hides(now),
+                    false => current_attrs.soft_deleted,
+                };
+                if !changes {
+                    return Ok(false);
+                }
+                let attrs = RecordAttrs {
+                    soft_deleted: deleted,
+                    ..current_attrs
+                };
+                // a blob file is collected once no record points at it, so the record can
+                // keep pointing at it; an overflow chain goes with the write that replaces
+                // it, so the value gets a chain of its own
+                if BlobPointer::decode(existing).is_some() {
+                    keeps_blob = true;
+                    (existing, attrs, None)
+                } else {
+                    let stored = undo_value.as_deref().unwrap_or_default();
+                    owned = merge::resolve(&self.db.io_engine, key, stored)?.into_owned();
+                    let attrs = RecordAttrs {
+                        pending_merge: false,
+                        ..attrs
+                    };
+                    (owned.as_slice(), attrs, None)
+                }
+            }
+        };
+
+        // a long value goes to a blob file or overflow pages first, the record only points
+        // at them
+        let pointer;
+        let inner = match self.db.io_engine.blob_log().filter(|log| log.takes(val)) {
+            _ if keeps_blob || attrs.pending_merge => val,
+            Some(log) => {
+                pointer = log.append(key, val)?.encode();
+                pointer.as_slice()
+            }
+            None if overflow::needs_chain(val, attrs::header_len(attrs, val)) => {
+                let chain = self.db.io_engine.write_overflow(val)?;
+                self.push_overflow_written(chain);
+                pointer = chain.encode();
+                pointer.as_slice()
+            }
+            None => val,
+        };
+        let stored = attrs::wrap(attrs, inner);
+        let stored = stored.as_ref();
 
-        let undo_value = Self::existing_value(self.db, &mut page_guard, key);
+        loop {
+            match Self::try_put_with_promotion(
+                self.db,
+                &mut self.lock_manager,
+                &mut page_guard,
+                key,
+                stored,
+            )? {
+                TryPutResult::Success => {
+                    self.append_wal_put(&mut page_guard, key, stored, operand, undo_value.clone())?;
+                    if let Some(replaced) = undo_value.as_deref().and_then(OverflowPointer::decode)
+                    {
+                        self.overflow_replaced.push(replaced);
+                    }
+                    if attrs.expires_at != current_attrs.expires_at {
+                        self.ttl_changes.push((key.to_vec(), attrs.expires_at));
+                    }
+                    match value {
+                        RecordValue::SoftDelete(true) => {
+                            self.record_change(key, || ChangeKind::Delete);
+                        }
+                        RecordValue::SoftDelete(false) => {
+                            if self.tracks_changes() {
+                                let value = merge::resolve(&self.db.io_engine, key, stored)?;
+                                self.record_change(key, || ChangeKind::Put(value.into_owned()));
+                            }
+                        }
+                        RecordValue::Put { .. } | RecordValue::Merge { .. } => {
+                            let logical = operand.map_or(val.len(), <[u8]>::len);
+                            self.db.record_logical_write(key.len() + logical);
+                            if !attrs.pending_merge {
+                                self.record_change(key, || ChangeKind::Put(val.to_vec()));
+                            } else if self.tracks_changes() {
+                                let value = merge::resolve(&self.db.io_engine, key, stored)?;
+                                self.record_change(key, || ChangeKind::Put(value.into_owned()));
+                            }
+                        }
+                    }
+                    return Ok(true);
+                }
+                TryPutResult::NeedsSplit => {
+                    page_guard = self.split_current_leaf(page_guard, key, stored.len())?;
+                }
+                TryPutResult::NeedsPromotion(_) | TryPutResult::NeedsGrowth(_) => {
+                    unreachable!("promotion and growth handled before returning")
+                }
+            }
+        }
+    }
 
+    /// Point `key`'s record at the value `to` returns if it still holds `from`, keeping the
+    /// key's expiry and soft-delete flag and telling no watcher: a value moved by
+    /// `QuickStep::gc_blobs`. Write locks the key's leaf either way. Returns whether it did
+    pub(crate) fn relocate_value<F>(
+        &mut self,
+        key: &[u8],
+        from: &[u8],
+        to: F,
+    ) -> Result<bool, QSError>
+    where
+        F: FnOnce() -> Result<Vec<u8>, QSError>,
+    {
+        let _op = self.enter()?;
+        let mut page_guard = self.write_lock_for_key(key)?;
+        let undo_value = Self::with_existing_value(self.db, &mut page_guard, key, |value| {
+            value
+                .filter(|stored| attrs::split(stored).1 == from)
+                .map(<[u8]>::to_vec)
+        })?;
+        let Some(current) = &undo_value else {
+            return Ok(false);
+        };
+        let stored = attrs::wrap(attrs::split(current).0, &to()?).into_owned();
         loop {
-            match Self::try_put_with_promotion(self.db, &mut page_guard, key, val)? {
+            match Self::try_put_with_promotion(
+                self.db,
+                &mut self.lock_manager,
+                &mut page_guard,
+                key,
+                &stored,
+            )? {
                 TryPutResult::Success => {
-                    self.append_wal_put(&mut page_guard, key, val, undo_value.clone())?;
-                    self.maybe_global_checkpoint()?;
-                    return Ok(());
+                    self.append_wal_put(&mut page_guard, key, &stored, None, undo_value)?;
+                    return Ok(true);
                 }
                 TryPutResult::NeedsSplit => {
-                    page_guard = self.split_current_leaf(page_guard, key)?;
+                    page_guard = self.split_current_leaf(page_guard, key, stored.len())?;
+                }
+                TryPutResult::NeedsPromotion(_) | TryPutResult::NeedsGrowth(_) => {
+                    unreachable!("promotion and growth handled before returning")
                 }
-                TryPutResult::NeedsPromotion(_) => unreachable!("promotion handled before returning"),
             }
         }
     }
@@ -630,34 +2915,168 @@ impl<'db> QuickStepTx<'db> {
     }
 
     pub fn commit(mut self) {
-        self.commit_in_place();
+        self.commit_in_place(true);
+    }
+
+    /// Commit without waiting for the commit marker to be synced; the handle waits for it,
+    /// see `commit`. Watchers hear of the commit before it is durable
+    pub fn commit_async(mut self) -> CommitHandle {
+        let lsn = self.commit_in_place(false);
+        CommitHandle::new(Arc::clone(&self.db.wal), lsn)
     }
 
-    fn commit_in_place(&mut self) {
+    /// Log that the transaction is ready to commit and sync it, whatever the sync policy:
+    /// the first phase of a two-phase commit. The returned transaction keeps its locks until
+    /// it commits or aborts, see `two_phase`
+    pub fn prepare(mut self) -> Result<PreparedTx<'db>, QSError> {
+        let txn_id = self.txn_id;
+        let Ok(op) = self.enter() else {
+            self.disown_expired();
+            return Err(QSError::TxnExpired { txn_id });
+        };
         if self.state != TxState::Active {
-            return;
+            return Err(QSError::TxnExpired { txn_id });
         }
+        self.log_activity();
         self.db
             .wal
-            .append_txn_marker(WalTxnMarker::Commit, self.wal_entry_kind, self.txn_id)
-            .expect("failed to record txn commit");
+            .append_txn_marker(WalTxnMarker::Prepare, self.wal_entry_kind, txn_id)?;
+        self.db.wal.sync()?;
+        // only the coordinator ends it from here
+        self.finish_expiry();
+        drop(op);
+        Ok(PreparedTx::new(self))
+    }
+
+    /// Log the commit, waiting for the sync the sync policy asks for if `durable`. Otherwise
+    /// returns the commit marker's sequence number for the caller to wait on, 0 when there
+    /// is none to wait on
+    fn commit_in_place(&mut self, durable: bool) -> u64 {
+        if self.state != TxState::Active {
+            return 0;
+        }
+        let Ok(_op) = self.enter() else {
+            self.disown_expired();
+            return 0;
+        };
+        self.log_activity();
+        let lsn = if durable {
+            self.db
+                .wal
+                .append_txn_marker(WalTxnMarker::Commit, self.wal_entry_kind, self.txn_id)
+                .map(|()| 0)
+        } else {
+            self.db
+                .wal
+                .append_commit_nowait(self.wal_entry_kind, self.txn_id)
+        }
+        .expect("failed to record txn commit");
+        // a replaced chain is freed once no recovery can bring back a record pointing at it
+        if !self.overflow_replaced.is_empty() {
+            self.db.wal.sync().expect("failed to sync txn commit");
+            for pointer in self.overflow_replaced.drain(..) {
+                self.db.io_engine.free_overflow(pointer);
+            }
+        }
+        self.take_overflow_written();
+        // the leaves' records were pinned while the transaction ran, checkpoint them now
+        // it has committed, before the locks go. One that fails is left to a later write
+        for page_id in self.lock_manager.write_locked_pages() {
+            if let Ok(mut guard) = self
+                .lock_manager
+                .get_upgrade_or_acquire_write_lock(&self.db.map_table, page_id)
+            {
+                let _ = Self::maybe_checkpoint_leaf(
+                    self.db,
+                    &mut self.lock_manager,
+                    &mut guard,
+                    page_id,
+                );
+            }
+        }
+        // the expiries are in the records already, the index only tells the sweeper
+        let mut expiring = false;
+        for (key, expires_at) in self.ttl_changes.drain(..) {
+            expiring |= expires_at.is_some();
+            self.db.ttl.set(&key, expires_at);
+        }
+        if expiring {
+            self.db.start_ttl_sweeper();
+        }
+        if let (Some(log), Some((key, entries))) =
+            (&self.db.activity_log, self.activity_record.take())
+        {
+            log.committed(key, entries);
+        }
+        // under the default sync policy the commit marker is synced, so subscribers only hear
+        // about durable changes, unless the commit is asynchronous
+        self.db.watchers.publish(&self.changes);
+        self.changes.clear();
         self.undo_log.clear();
+        if let Some(slot) = &self.expiry {
+            slot.undo_log
+                .lock()
+                .expect("undo log mutex poisoned")
+                .clear();
+        }
+        self.finish_expiry();
         self.state = TxState::Committed;
+        lsn
     }
 
     fn abort_in_place(&mut self) {
         if self.state != TxState::Active {
             return;
         }
+        let Ok(_op) = self.enter() else {
+            self.disown_expired();
+            return;
+        };
         self.apply_undo_actions()
             .expect("failed to roll back transaction");
         self.db
             .wal
             .append_txn_marker(WalTxnMarker::Abort, self.wal_entry_kind, self.txn_id)
             .expect("failed to record txn abort");
+        // the undone records no longer point at the chains this transaction wrote. They are
+        // freed once the marker is synced, so recovery does not free them again
+        let written = self.take_overflow_written();
+        if !written.is_empty() {
+            self.db.wal.sync().expect("failed to sync txn abort");
+            for pointer in written {
+                self.db.io_engine.free_overflow(pointer);
+            }
+        }
+        self.overflow_replaced.clear();
+        self.undo_log.clear();
+        self.ttl_changes.clear();
+        self.changes.clear();
+        self.activity_record = None;
+        self.finish_expiry();
+        self.state = TxState::Aborted;
+    }
+
+    /// Hold off expiry until the returned op drops, fails once this transaction has expired
+    fn enter(&self) -> Result<Option<TxnOp>, QSError> {
+        self.expiry.as_ref().map_or(Ok(None), |slot| slot.enter())
+    }
+
+    /// Leave the locks of an expired transaction to the one that took them over
+    fn disown_expired(&mut self) {
+        self.lock_manager.disown();
         self.undo_log.clear();
+        self.overflow_written.clear();
+        self.overflow_replaced.clear();
+        self.ttl_changes.clear();
+        self.changes.clear();
         self.state = TxState::Aborted;
     }
+
+    fn finish_expiry(&self) {
+        if let (Some(_), Some(registry)) = (&self.expiry, &self.db.txn_registry) {
+            registry.finish(self.txn_id);
+        }
+    }
 }
 
 fn resolve_data_path(path: &Path) -> PathBuf {
@@ -668,6 +3087,32 @@ fn resolve_data_path(path: &Path) -> PathBuf {
     }
 }
 
+/// A data file already there must at least hold its metadata page, an empty one is new
+fn check_data_file_len(data_path: &Path) -> Result<(), QSError> {
+    let len = match fs::metadata(data_path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if len > 0 && len < PAGE_SIZE {
+        return Err(QSError::DataFileTooSmall {
+            len,
+            min: PAGE_SIZE,
+        });
+    }
+    Ok(())
+}
+
+/// A log whose manifest cannot be read back is corrupt, anything else failed to open
+fn wal_open_error(err: io::Error) -> QSError {
+    match err.kind() {
+        io::ErrorKind::InvalidData => QSError::WalCorrupt {
+            reason: err.to_string(),
+        },
+        _ => QSError::Io(err),
+    }
+}
+
 fn wal_path_for(data_path: &Path) -> PathBuf {
     let mut wal_path = data_path.to_path_buf();
     wal_path.set_extension("wal");
@@ -701,14 +3146,16 @@ where
 
 impl<'db> QuickStepTx<'db> {
     fn plan_leaf_split(
-        db: &'db QuickStep,
+        db: &'db Store,
         page_guard: &mut WriteGuardWrapper<'db>,
+        pending_key: &[u8],
+        pending_len: usize,
     ) -> LeafSplitPlan {
         let write_guard = page_guard.get_write_guard();
         match write_guard.node() {
             NodeRef::MiniPage(idx) => {
                 let node_meta = unsafe { db.cache.get_meta_ref(idx) };
-                LeafSplitPlan::from_node(node_meta)
+                LeafSplitPlan::from_node(node_meta, pending_key, pending_len, &db.split_policy)
             }
             NodeRef::Leaf(_) => unreachable!("leaf splits only apply to cached mini-pages"),
         }
@@ -718,16 +3165,23 @@ impl<'db> QuickStepTx<'db> {
         &mut self,
         mut left_guard: WriteGuardWrapper<'db>,
         key: &[u8],
+        value_len: usize,
     ) -> Result<WriteGuardWrapper<'db>, QSError> {
+        if self.db.hash_buckets.is_some() {
+            return Err(QSError::HashBucketFull {
+                bucket: left_guard.page_id().as_u64(),
+            });
+        }
         let (mut lock_bundle, page_id) = self.lock_bundle_for_split(key)?;
         debug_assert_eq!(
             page_id,
             left_guard.page_id(),
             "split lock bundle must reference active leaf"
         );
+        let mut reservation = self.db.reserve_split_nodes(&lock_bundle)?;
 
         let mut right_guard = self.new_mini_page(NodeSize::LeafPage, None)?;
-        let split_plan = Self::plan_leaf_split(self.db, &mut left_guard);
+        let split_plan = Self::plan_leaf_split(self.db, &mut left_guard, key, value_len);
 
         let split_outcome =
             Self::apply_leaf_split(self.db, &mut left_guard, &mut right_guard, &split_plan)?;
@@ -740,12 +3194,20 @@ impl<'db> QuickStepTx<'db> {
             split_outcome.right_count,
         );
 
-        self.insert_into_parents_after_leaf_split(
+        Self::insert_into_parents_after_leaf_split(
+            self.db,
+            &mut reservation,
             &mut lock_bundle,
             left_guard.page_id(),
             &split_outcome.pivot_key,
             right_guard.page_id(),
         )?;
+        Self::log_leaf_split(
+            self.db,
+            &mut left_guard,
+            &mut right_guard,
+            &split_outcome.pivot_key,
+        )?;
 
         let pivot_key = split_outcome.pivot_key.clone();
         if key >= pivot_key.as_slice() {
@@ -757,10 +3219,39 @@ impl<'db> QuickStepTx<'db> {
         }
     }
 
-    fn lock_bundle_for_split(
-        &self,
-        key: &[u8],
-    ) -> Result<(WriteLockBundle<'db>, PageId), QSError> {
+    /// Write lock the leaf holding `key`, reporting a lock held elsewhere, or a range
+    /// another transaction has scanned, as a conflict on it
+    /// Write lock the leaf `key` belongs to. A split or merge can move the key between the
+    /// traversal and the lock, and both need the leaf's write lock, so the tree is traversed
+    /// again once it is held: if it still leads to the same leaf, the key stays there
+    fn write_lock_for_key(&mut self, key: &[u8]) -> Result<WriteGuardWrapper<'db>, QSError> {
+        if self.db.follower.is_some() && !self.replica {
+            return Err(QSError::FollowerReadOnly);
+        }
+        self.db.check_writable()?;
+        // scans never return reserved keys, so no scan needs them kept out of its range
+        if !reserved::is_reserved(key) {
+            self.lock_manager.check_range(key)?;
+        }
+        let mut page = self.db.leaf_for_key(key)?;
+        for _ in 0..SPIN_RETRIES {
+            let guard = self
+                .lock_manager
+                .get_upgrade_or_acquire_write_lock(&self.db.map_table, page)
+                .map_err(|err| match err {
+                    QSError::PageLockFail => QSError::WriteConflict { key: key.to_vec() },
+                    other => other,
+                })?;
+            let current = self.db.leaf_for_key(key)?;
+            if current == page {
+                return Ok(guard);
+            }
+            page = current;
+        }
+        Err(QSError::OLCRetriesExceeded)
+    }
+
+    fn lock_bundle_for_split(&self, key: &[u8]) -> Result<(WriteLockBundle<'db>, PageId), QSError> {
         let res = self.db.inner_nodes.read_traverse_leaf(key)?;
         let bundle = self
             .db
@@ -774,61 +3265,57 @@ impl<'db> QuickStepTx<'db> {
         guard: &mut WriteGuardWrapper<'db>,
         key: &[u8],
         val: &[u8],
+        operand: Option<&[u8]>,
         undo_value: Option<Vec<u8>>,
     ) -> Result<(), QSError> {
         let page_id = guard.page_id();
-        let (_disk_addr, lower_fence, upper_fence) = Self::leaf_snapshot(self.db, guard);
-        self.db
-            .wal
-            .append_put(
+        let (_disk_addr, lower_fence, upper_fence) = Self::leaf_snapshot(self.db, guard)?;
+        let redo = match operand {
+            Some(operand) => WalOp::Merge {
+                operand: operand.to_vec(),
+                value: val.to_vec(),
+            },
+            None => WalOp::Put {
+                value: val.to_vec(),
+            },
+        };
+        let undo = match undo_value.as_ref() {
+            Some(prev) => WalOp::Put {
+                value: prev.clone(),
+            },
+            None => WalOp::Tombstone,
+        };
+        let record = |kind, op| {
+            WalRecord::leaf(
                 page_id,
                 key,
-                val,
                 &lower_fence,
                 &upper_fence,
-                self.wal_entry_kind,
+                kind,
                 self.txn_id,
+                op,
+            )
+        };
+        self.db
+            .wal
+            .append_with_undo(
+                record(self.wal_entry_kind, redo),
+                Some(record(WalEntryKind::Undo, undo)),
             )
             .expect("failed to record put in WAL");
-        if let Some(prev) = undo_value.as_ref() {
-            self.db
-                .wal
-                .append_put(
-                    page_id,
-                    key,
-                    prev,
-                    &lower_fence,
-                    &upper_fence,
-                    WalEntryKind::Undo,
-                    self.txn_id,
-                )
-                .expect("failed to record undo put in WAL");
-        } else {
-            self.db
-                .wal
-                .append_tombstone(
-                    page_id,
-                    key,
-                    &lower_fence,
-                    &upper_fence,
-                    WalEntryKind::Undo,
-                    self.txn_id,
-                )
-                .expect("failed to record undo tombstone in WAL");
-        }
         self.log_put_undo(page_id, key, undo_value);
-        Self::maybe_checkpoint_leaf(self.db, guard, page_id)?;
+        Self::maybe_checkpoint_leaf(self.db, &mut self.lock_manager, guard, page_id)?;
         Ok(())
     }
 
     fn log_put_undo(&mut self, page_id: PageId, key: &[u8], undo_value: Option<Vec<u8>>) {
         match undo_value {
-            Some(value) => self.undo_log.push(UndoAction::Restore {
+            Some(value) => self.push_undo(UndoAction::Restore {
                 page_id,
                 key: key.to_vec(),
                 value,
             }),
-            None => self.undo_log.push(UndoAction::Remove {
+            None => self.push_undo(UndoAction::Remove {
                 page_id,
                 key: key.to_vec(),
             }),
@@ -837,7 +3324,7 @@ impl<'db> QuickStepTx<'db> {
 
     fn log_delete_undo(&mut self, page_id: PageId, key: &[u8], value: Option<Vec<u8>>) {
         if let Some(value) = value {
-            self.undo_log.push(UndoAction::Restore {
+            self.push_undo(UndoAction::Restore {
                 page_id,
                 key: key.to_vec(),
                 value,
@@ -845,8 +3332,49 @@ impl<'db> QuickStepTx<'db> {
         }
     }
 
+    fn push_undo(&mut self, action: UndoAction) {
+        match &self.expiry {
+            Some(slot) => slot
+                .undo_log
+                .lock()
+                .expect("undo log mutex poisoned")
+                .push(action),
+            None => self.undo_log.push(action),
+        }
+    }
+
+    fn pop_undo(&mut self) -> Option<UndoAction> {
+        match &self.expiry {
+            Some(slot) => slot.undo_log.lock().expect("undo log mutex poisoned").pop(),
+            None => self.undo_log.pop(),
+        }
+    }
+
+    fn push_overflow_written(&mut self, chain: OverflowPointer) {
+        match &self.expiry {
+            Some(slot) => slot
+                .overflow_written
+                .lock()
+                .expect("overflow chains mutex poisoned")
+                .push(chain),
+            None => self.overflow_written.push(chain),
+        }
+    }
+
+    fn take_overflow_written(&mut self) -> Vec<OverflowPointer> {
+        match &self.expiry {
+            Some(slot) => mem::take(
+                &mut *slot
+                    .overflow_written
+                    .lock()
+                    .expect("overflow chains mutex poisoned"),
+            ),
+            None => mem::take(&mut self.overflow_written),
+        }
+    }
+
     fn apply_undo_actions(&mut self) -> Result<(), QSError> {
-        while let Some(action) = self.undo_log.pop() {
+        while let Some(action) = self.pop_undo() {
             self.apply_undo_action(action)?;
         }
         Ok(())
@@ -859,7 +3387,7 @@ impl<'db> QuickStepTx<'db> {
         let mut guard = self
             .lock_manager
             .get_upgrade_or_acquire_write_lock(&self.db.map_table, page_id)?;
-        Self::ensure_mini_page(self.db, &mut guard)?;
+        Self::ensure_full_mini_page(self.db, &mut self.lock_manager, &mut guard)?;
         let index = match guard.get_write_guard().node() {
             NodeRef::MiniPage(idx) => idx,
             NodeRef::Leaf(_) => unreachable!("mini page expected after promotion"),
@@ -879,86 +3407,135 @@ impl<'db> QuickStepTx<'db> {
     }
 
     fn leaf_snapshot(
-        db: &'db QuickStep,
+        db: &'db Store,
         guard: &mut WriteGuardWrapper<'db>,
-    ) -> (u64, Vec<u8>, Vec<u8>) {
+    ) -> Result<(u64, Vec<u8>, Vec<u8>), QSError> {
         match guard.get_write_guard().node() {
             NodeRef::MiniPage(idx) => {
                 let meta = unsafe { db.cache.get_meta_ref(idx) };
                 let (lower, upper) = meta.fence_bounds();
-                (meta.leaf(), lower, upper)
+                Ok((meta.leaf(), lower, upper))
             }
             NodeRef::Leaf(addr) => {
-                let leaf = db.io_engine.get_page(addr);
+                let leaf = db.io_engine.get_page(addr)?;
                 let meta = leaf.as_ref();
                 let (lower, upper) = collect_fence_keys(meta);
-                (addr, lower, upper)
+                Ok((addr, lower, upper))
             }
         }
     }
 
-    fn existing_value(
-        db: &'db QuickStep,
+    /// Hand `read` the value `key` holds in the write-locked leaf, borrowed from the
+    /// mini-page or the disk leaf, so a caller that keeps it decides whether to copy it
+    fn with_existing_value<R>(
+        db: &'db Store,
         guard: &mut WriteGuardWrapper<'db>,
         key: &[u8],
-    ) -> Option<Vec<u8>> {
+        read: impl FnOnce(Option<&[u8]>) -> R,
+    ) -> Result<R, QSError> {
         match guard.get_write_guard().node() {
             NodeRef::MiniPage(idx) => {
                 let meta = unsafe { db.cache.get_meta_ref(idx) };
-                meta.get(key).map(|value| value.to_vec())
+                match meta.find(key) {
+                    Some(value) => Ok(read(value)),
+                    None if matches!(meta.size(), NodeSize::LeafPage) => Ok(read(None)),
+                    // a smaller mini-page only holds part of the leaf
+                    None => {
+                        let leaf = db.io_engine.get_page(meta.leaf())?;
+                        Ok(read(leaf.as_ref().get(key)))
+                    }
+                }
             }
             NodeRef::Leaf(addr) => {
-                let leaf = db.io_engine.get_page(addr);
-                leaf.as_ref().get(key).map(|value| value.to_vec())
+                let leaf = db.io_engine.get_page(addr)?;
+                Ok(read(leaf.as_ref().get(key)))
             }
         }
     }
 
     fn maybe_checkpoint_leaf(
-        db: &'db QuickStep,
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
         guard: &mut WriteGuardWrapper<'db>,
         page_id: PageId,
     ) -> Result<(), QSError> {
         if !db
             .wal
-            .should_checkpoint_page(page_id, db.wal_leaf_checkpoint_threshold)
+            .should_checkpoint_page(page_id, db.checkpoint_triggers.leaf_records())
         {
             return Ok(());
         }
-        Self::ensure_mini_page(db, guard)?;
-        guard.merge_to_disk(&db.cache, &db.io_engine);
+        Self::ensure_mini_page(db, locks, guard)?;
+        guard.merge_to_disk(&db.cache, &db.io_engine, &db.wal)?;
         db.wal
             .checkpoint_page(page_id)
             .expect("failed to checkpoint WAL for leaf");
         Ok(())
     }
 
+    /// Checkpoint a batch of leaves here and now if one was requested or the WAL is past
+    /// its global thresholds. Writes leave this to the checkpoint thread, only a writer held
+    /// back by the WAL limit or an explicit flush waits for it
     fn maybe_global_checkpoint(&mut self) -> Result<(), QSError> {
         let requested = self.db.wal_checkpoint_requested.load(Ordering::Acquire);
-        let candidate = self
+        let (_, record_thresh, byte_thresh) = self.db.checkpoint_triggers.thresholds();
+        if !requested
+            && !self
+                .db
+                .wal
+                .exceeds_global_thresholds(record_thresh, byte_thresh)
+        {
+            return Ok(());
+        }
+
+        let policy = self.db.checkpoint_policy;
+        let candidates = self
             .db
             .wal
-            .global_checkpoint_candidate(
-                self.db.wal_global_record_threshold,
-                self.db.wal_global_byte_threshold,
-            )
-            .or_else(|| {
-                if requested {
-                    self.db.wal.global_checkpoint_candidate(0, 0)
-                } else {
-                    None
-                }
-            });
-        if let Some(page_id) = candidate {
-            let mut guard = self
+            .page_summaries()?
+            .into_iter()
+            .map(|summary| CheckpointCandidate {
+                summary,
+                dirty_ratio: match policy {
+                    CheckpointPolicy::DirtyRatio => self.leaf_dirty_ratio(summary.page_id),
+                    _ => 0.0,
+                },
+            })
+            .collect();
+        let last = match self.db.checkpoint_cursor.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            page => Some(PageId(page)),
+        };
+        let targets = policy.select(candidates, last, self.db.checkpoint_batch);
+
+        let mut checkpointed = false;
+        for page_id in targets {
+            // the checkpoint is opportunistic, a leaf another transaction is writing is left
+            // for a later attempt rather than failing this write
+            let holder = self.db.lock_owners.owner(page_id);
+            if holder.is_some_and(|holder| holder != self.txn_id) {
+                continue;
+            }
+            let mut guard = match self
                 .lock_manager
-                .get_upgrade_or_acquire_write_lock(&self.db.map_table, page_id)?;
-            Self::ensure_mini_page(self.db, &mut guard)?;
-            guard.merge_to_disk(&self.db.cache, &self.db.io_engine);
+                .get_upgrade_or_acquire_write_lock(&self.db.map_table, page_id)
+            {
+                Ok(guard) => guard,
+                Err(QSError::PageLockFail) => continue,
+                Err(err) => return Err(err),
+            };
+            Self::ensure_mini_page(self.db, &mut self.lock_manager, &mut guard)?;
+            guard.merge_to_disk(&self.db.cache, &self.db.io_engine, &self.db.wal)?;
             self.db
                 .wal
                 .checkpoint_page(page_id)
                 .expect("failed to checkpoint WAL for candidate leaf");
+            self.db
+                .checkpoint_cursor
+                .store(page_id.0, Ordering::Relaxed);
+            checkpointed = true;
+        }
+        if checkpointed {
             self.db
                 .wal_checkpoint_requested
                 .store(false, Ordering::Release);
@@ -966,8 +3543,64 @@ impl<'db> QuickStepTx<'db> {
         Ok(())
     }
 
+    /// Hold a write back while the WAL is at its limit: checkpoint leaves until it drops
+    /// below, then either go ahead or fail with `WalFull` if nothing more could be freed
+    fn enforce_wal_limit(&mut self) -> Result<(), QSError> {
+        let Some(max_wal_bytes) = self.db.wal_limit.max_bytes() else {
+            return Ok(());
+        };
+        let mut wal_bytes = self.db.wal.total_bytes()?;
+        while self.db.wal_limit.observe(wal_bytes) == WalPressure::AtLimit {
+            self.db
+                .wal_checkpoint_requested
+                .store(true, Ordering::Release);
+            self.maybe_global_checkpoint()?;
+            if self.db.wal.page_summaries()?.is_empty() {
+                self.db
+                    .wal
+                    .checkpoint_txn_markers()
+                    .expect("failed to checkpoint WAL transaction markers");
+            }
+            let after = self.db.wal.total_bytes()?;
+            if after >= wal_bytes {
+                return match self.db.wal_limit.policy() {
+                    WalLimitPolicy::Throttle => Ok(()),
+                    WalLimitPolicy::Fail => Err(QSError::WalFull {
+                        wal_bytes: after,
+                        max_wal_bytes,
+                    }),
+                };
+            }
+            wal_bytes = after;
+        }
+        Ok(())
+    }
+
+    /// Dirty share of a leaf's mini-page, read without waiting; 0.0 for a leaf only on disk
+    /// or one that is busy
+    fn leaf_dirty_ratio(&mut self, page_id: PageId) -> f64 {
+        let ratio = |node: NodeRef<'_>| match node {
+            // SAFETY: the caller holds a lock on the page
+            NodeRef::MiniPage(index) => unsafe { self.db.cache.get_meta_ref(index) }.dirty_ratio(),
+            NodeRef::Leaf(_) => 0.0,
+        };
+        if let Some(guard) = self.lock_manager.owned_write_guard(page_id) {
+            if let GuardWrapper::Write(write) = &guard.guard_inner {
+                return ratio(write.node());
+            }
+        }
+        match self.db.map_table.try_read_page_entry(page_id) {
+            Ok(guard) => ratio(guard.node()),
+            Err(_) => 0.0,
+        }
+    }
+
+    /// Split the left mini-page into itself and `right_guard`'s slot. Both halves are built
+    /// in scratch buffers first and only copied over the slots, which cannot fail, once the
+    /// whole split has been built, so a failed or panicking split leaves both leaves as
+    /// they were
     fn apply_leaf_split(
-        db: &'db QuickStep,
+        db: &'db Store,
         left_guard: &mut WriteGuardWrapper<'db>,
         right_guard: &mut WriteGuardWrapper<'db>,
         plan: &LeafSplitPlan,
@@ -983,50 +3616,171 @@ impl<'db> QuickStepTx<'db> {
         };
 
         let copy_bytes = unsafe { db.cache.get_meta_ref(left_index).size().size_in_bytes() };
+        // the right half starts as a copy of the left, so capture its identity first
+        let (right_page_id, right_disk_addr) = unsafe {
+            let right_meta = db.cache.get_meta_ref(right_index);
+            (right_meta.page_id(), right_meta.leaf())
+        };
 
+        // u64 words keep the scratch pages aligned like the buffer's slots
+        let mut scratch = [vec![0u64; copy_bytes / 8], vec![0u64; copy_bytes / 8]];
         unsafe {
             let src = db.cache.get_meta_ptr(left_index.index) as *const u8;
-            let dst = db.cache.get_meta_ptr(right_index.index) as *mut u8;
-            ptr::copy_nonoverlapping(src, dst, copy_bytes);
+            for page in scratch.iter_mut() {
+                ptr::copy_nonoverlapping(src, page.as_mut_ptr() as *mut u8, copy_bytes);
+            }
         }
+        let [left_scratch, right_scratch] = &mut scratch;
+        let left_meta = unsafe { &mut *(left_scratch.as_mut_ptr() as *mut NodeMeta) };
+        let right_meta = unsafe { &mut *(right_scratch.as_mut_ptr() as *mut NodeMeta) };
 
-        let left_meta = unsafe { db.cache.get_meta_mut(left_index) };
-        let right_meta = unsafe { db.cache.get_meta_mut(right_index) };
-        let right_page_id = right_meta.page_id();
-        let right_disk_addr = right_meta.leaf();
+        let outcome = plan
+            .apply(left_meta, right_meta)
+            .map_err(|_| QSError::SplitFailed)?;
+        right_meta.set_identity(right_page_id, right_disk_addr);
 
-        plan.apply(left_meta, right_meta)
-            .map_err(|_| QSError::SplitFailed)
-            .map(|outcome| {
-                right_meta.set_identity(right_page_id, right_disk_addr);
-                outcome
-            })
+        // SAFETY: we hold the write locks of both pages, so no reader sees a slot mid-copy
+        unsafe {
+            for (page, index) in [(&scratch[0], left_index), (&scratch[1], right_index)] {
+                let dst = db.cache.get_meta_ptr(index.index) as *mut u8;
+                ptr::copy_nonoverlapping(page.as_ptr() as *const u8, dst, copy_bytes);
+            }
+        }
+        Ok(outcome)
     }
     fn try_put_with_promotion(
-        db: &'db QuickStep,
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
         page_guard: &mut WriteGuardWrapper<'db>,
         key: &[u8],
         val: &[u8],
     ) -> Result<TryPutResult, QSError> {
+        Self::unshare_pinned(db, locks, page_guard)?;
         let attempt = page_guard.try_put(&db.cache, key, val);
         match attempt {
             TryPutResult::NeedsPromotion(addr) => {
-                Self::promote_leaf_to_mini_page(db, page_guard, addr)?;
-                Self::try_put_with_promotion(db, page_guard, key, val)
+                Self::promote_leaf_for_put(db, locks, page_guard, addr, key, val)?;
+                Self::try_put_with_promotion(db, locks, page_guard, key, val)
+            }
+            TryPutResult::NeedsGrowth(size) => {
+                Self::grow_mini_page(db, locks, page_guard, size)?;
+                Self::try_put_with_promotion(db, locks, page_guard, key, val)
             }
             other => Ok(other),
         }
     }
 
+    /// Cache a disk leaf for the put of `key` in the smallest mini-page that holds its fences
+    /// and the record. The page grows as puts fill it, see `grow_mini_page`. A record too big
+    /// for the smaller sizes, or one the disk leaf has no room for, gets a full copy of the
+    /// leaf instead
+    fn promote_leaf_for_put(
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
+        page_guard: &mut WriteGuardWrapper<'db>,
+        disk_addr: u64,
+        key: &[u8],
+        val: &[u8],
+    ) -> Result<(), QSError> {
+        let disk_leaf = db.io_engine.get_page(disk_addr)?;
+        let (lower, upper) = disk_leaf.as_ref().fence_bounds();
+        let disk_room = disk_leaf.as_ref().room_for_records();
+        let record = size_of::<KVMeta>() + key.len() + val.len();
+        let fenced = size_of::<NodeMeta>() + 2 * size_of::<KVMeta>() + lower.len() + upper.len();
+        let size = match NodeSize::from_byte_num(fenced + record) {
+            Some(size)
+                if !matches!(size, NodeSize::LeafPage)
+                    && record <= disk_room
+                    && db.cache.leaf_capacity() >= SMALL_MINI_PAGE_MIN_LEAVES =>
+            {
+                size
+            }
+            _ => return Self::promote_leaf_to_mini_page(db, locks, page_guard, disk_addr),
+        };
+        let cache_index = Self::alloc_mini_page(db, locks, size)?;
+
+        unsafe {
+            let mini_index = MiniPageIndex::new(cache_index);
+            let write_guard = page_guard.get_write_guard();
+            let page_id = write_guard.page;
+            write_guard.set_mini_page(mini_index);
+
+            let node_meta = db.cache.get_meta_mut(mini_index);
+            node_meta.reset_header(page_id, size, disk_addr);
+            node_meta.reset_user_entries_with_fences(&lower, &upper);
+            node_meta.set_disk_room(disk_room);
+            node_meta.mark_hot();
+        }
+        Ok(())
+    }
+
+    /// Copy a mini-page that ran out of room into a new one of `size` and free the old slot.
+    /// A full-size mini-page holds the whole leaf, so growing into one lays the records over
+    /// a copy of the disk leaf; from there the page splits when it fills
+    fn grow_mini_page(
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
+        page_guard: &mut WriteGuardWrapper<'db>,
+        size: NodeSize,
+    ) -> Result<(), QSError> {
+        let old_index = match page_guard.get_write_guard().node() {
+            NodeRef::MiniPage(idx) => idx.index,
+            NodeRef::Leaf(_) => unreachable!("only cached mini-pages grow"),
+        };
+        let page_id = page_guard.page_id();
+        // SAFETY: we hold the page's write lock
+        let (disk_addr, disk_room) = {
+            let old_meta = unsafe { db.cache.get_meta_ref(MiniPageIndex::new(old_index)) };
+            (old_meta.leaf(), old_meta.disk_room())
+        };
+        let disk_leaf = match size {
+            NodeSize::LeafPage => Some(db.io_engine.get_page(disk_addr)?),
+            _ => None,
+        };
+        let new_index = Self::alloc_mini_page(db, locks, size)?;
+
+        // SAFETY: the new slot is ours until it is published, the old one is write locked
+        unsafe {
+            let old_meta = db.cache.get_meta_ref(MiniPageIndex::new(old_index));
+            let new_meta = db.cache.get_meta_mut(MiniPageIndex::new(new_index));
+            match disk_leaf {
+                Some(disk_leaf) => {
+                    ptr::copy_nonoverlapping(
+                        disk_leaf.as_ref() as *const NodeMeta as *const u8,
+                        new_meta as *mut NodeMeta as *mut u8,
+                        size.size_in_bytes(),
+                    );
+                    new_meta.set_identity(page_id, disk_addr);
+                }
+                None => {
+                    let (lower, upper) = old_meta.fence_bounds();
+                    new_meta.reset_header(page_id, size, disk_addr);
+                    new_meta.reset_user_entries_with_fences(&lower, &upper);
+                    new_meta.set_disk_room(disk_room);
+                }
+            }
+            if old_meta.copy_records_into(new_meta).is_err() {
+                NodeMeta::init_unused(new_meta, size);
+                db.cache.dealloc(MiniPageIndex::new(new_index));
+                return Err(QSError::SplitFailed);
+            }
+            new_meta.mark_hot();
+            page_guard
+                .get_write_guard()
+                .set_mini_page(MiniPageIndex::new(new_index));
+            db.cache.retire(MiniPageIndex::new(old_index));
+        }
+        debug::record_mini_page_growth();
+        Ok(())
+    }
+
     fn promote_leaf_to_mini_page(
-        db: &'db QuickStep,
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
         page_guard: &mut WriteGuardWrapper<'db>,
         disk_addr: u64,
     ) -> Result<(), QSError> {
-        let cache_index = db
-            .cache
-            .alloc(NodeSize::LeafPage)
-            .ok_or(QSError::CacheExhausted)?;
+        let cache_index = Self::alloc_mini_page(db, locks, NodeSize::LeafPage)?;
 
         let disk_leaf = page_guard.load_leaf(&db.io_engine, disk_addr)?;
         let src_ptr = disk_leaf.as_ref() as *const NodeMeta as *const u8;
@@ -1053,35 +3807,109 @@ impl<'db> QuickStepTx<'db> {
     }
 
     fn ensure_mini_page(
-        db: &'db QuickStep,
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
         page_guard: &mut WriteGuardWrapper<'db>,
     ) -> Result<(), QSError> {
         loop {
             match page_guard.get_write_guard().node() {
-                NodeRef::MiniPage(_) => return Ok(()),
+                NodeRef::MiniPage(_) => return Self::unshare_pinned(db, locks, page_guard),
                 NodeRef::Leaf(addr) => {
-                    Self::promote_leaf_to_mini_page(db, page_guard, addr)?;
+                    Self::promote_leaf_to_mini_page(db, locks, page_guard, addr)?;
                 }
             }
         }
     }
 
+    /// Like `ensure_mini_page`, but grows a smaller mini-page into a full one, for changes
+    /// that need the whole leaf at hand
+    fn ensure_full_mini_page(
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
+        page_guard: &mut WriteGuardWrapper<'db>,
+    ) -> Result<(), QSError> {
+        Self::ensure_mini_page(db, locks, page_guard)?;
+        let full = match page_guard.get_write_guard().node() {
+            // SAFETY: we hold the page's write lock
+            NodeRef::MiniPage(idx) => {
+                matches!(
+                    unsafe { db.cache.get_meta_ref(idx) }.size(),
+                    NodeSize::LeafPage
+                )
+            }
+            NodeRef::Leaf(_) => unreachable!("mini page expected after promotion"),
+        };
+        if !full {
+            Self::grow_mini_page(db, locks, page_guard, NodeSize::LeafPage)?;
+        }
+        Ok(())
+    }
+
+    /// Move a write locked mini-page that `PinnedValue`s point into to a fresh slot, so
+    /// changes made through the guard leave the pinned bytes alone. The old slot is freed
+    /// with its last pin.
+    fn unshare_pinned(
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
+        page_guard: &mut WriteGuardWrapper<'db>,
+    ) -> Result<(), QSError> {
+        let old_index = match page_guard.get_write_guard().node() {
+            NodeRef::MiniPage(idx) if db.cache.is_pinned(idx.index) => idx.index,
+            _ => return Ok(()),
+        };
+        // new pins need a read lock, so none can appear while we hold the write lock
+        let size = unsafe { db.cache.get_meta_ref(MiniPageIndex::new(old_index)) }.size();
+        let new_index = Self::alloc_mini_page(db, locks, size)?;
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                db.cache.get_meta_ptr(old_index) as *const u8,
+                db.cache.get_meta_ptr(new_index) as *mut u8,
+                size.size_in_bytes(),
+            );
+            page_guard
+                .get_write_guard()
+                .set_mini_page(MiniPageIndex::new(new_index));
+            db.cache.retire(MiniPageIndex::new(old_index));
+        }
+        Ok(())
+    }
+
+    /// Allocate a mini-page, evicting cold pages (including idle ones this transaction
+    /// holds) until the buffer has room.
+    fn alloc_mini_page(
+        db: &'db Store,
+        locks: &mut LockManager<'db>,
+        size: NodeSize,
+    ) -> Result<usize, QSError> {
+        loop {
+            if let Some(idx) = db.cache.alloc(size) {
+                return Ok(idx);
+            }
+            db.cache
+                .evict(&db.map_table, &db.io_engine, &db.wal, Some(locks))?;
+        }
+    }
+
     fn new_mini_page(
         &mut self,
         size: NodeSize,
         disk_addr: Option<u64>,
     ) -> Result<WriteGuardWrapper<'db>, QSError> {
-        let new_mini_page = loop {
-            if let Some(idx) = self.db.cache.alloc(size) {
-                break idx;
+        let new_mini_page = Self::alloc_mini_page(self.db, &mut self.lock_manager, size)?;
+
+        let mut guard = match unsafe { NodeMeta::init(self, new_mini_page, size, disk_addr) } {
+            Ok(guard) => guard,
+            Err(err) => {
+                // SAFETY: the slot was never published in the mapping table
+                unsafe {
+                    NodeMeta::init_unused(self.db.cache.get_meta_ptr(new_mini_page), size);
+                    self.db.cache.dealloc(MiniPageIndex::new(new_mini_page));
+                }
+                return Err(err);
             }
-            self.db
-                .cache
-                .evict(&self.db.map_table, &self.db.io_engine, &self.db.wal)?;
         };
 
-        let mut guard = unsafe { NodeMeta::init(self, new_mini_page, size, disk_addr) };
-
         if let NodeRef::MiniPage(index) = guard.get_write_guard().node() {
             let meta = unsafe { self.db.cache.get_meta_mut(index) };
             meta.ensure_fence_keys();
@@ -1090,15 +3918,52 @@ impl<'db> QuickStepTx<'db> {
         Ok(guard)
     }
 
+    /// Make a split survive a crash. The right leaf is written first, unreachable until the
+    /// split is logged, then the narrowed left leaf
+    fn log_leaf_split(
+        db: &'db Store,
+        left_guard: &mut WriteGuardWrapper<'db>,
+        right_guard: &mut WriteGuardWrapper<'db>,
+        pivot_key: &[u8],
+    ) -> Result<(), QSError> {
+        let right_addr = match right_guard.get_write_guard().node() {
+            NodeRef::MiniPage(idx) => unsafe { db.cache.get_meta_ref(idx) }.leaf(),
+            NodeRef::Leaf(_) => unreachable!("split halves are cached mini-pages"),
+        };
+        right_guard.write_to_disk(&db.cache, &db.io_engine, &db.wal)?;
+        #[cfg(feature = "failpoints")]
+        failpoints::hit(failpoints::SPLIT_RIGHT_WRITTEN)?;
+        db.wal
+            .append_split(
+                left_guard.page_id(),
+                right_guard.page_id(),
+                right_addr,
+                pivot_key,
+            )
+            .expect("failed to log leaf split");
+        #[cfg(feature = "failpoints")]
+        failpoints::hit(failpoints::SPLIT_LOGGED)?;
+        db.stats.record_split();
+        db.events.emit(|| StructuralEvent::LeafSplit {
+            left: left_guard.page_id().as_u64(),
+            right: right_guard.page_id().as_u64(),
+            right_addr,
+            pivot: pivot_key.to_vec(),
+        });
+        left_guard.write_to_disk(&db.cache, &db.io_engine, &db.wal)
+    }
+
     fn insert_into_parents_after_leaf_split(
-        &mut self,
+        db: &'db Store,
+        reservation: &mut InnerReservation<'db>,
         lock_bundle: &mut WriteLockBundle<'db>,
         left_leaf: PageId,
         pivot_key: &[u8],
         right_leaf: PageId,
     ) -> Result<(), QSError> {
         if lock_bundle.chain.is_empty() {
-            return self.db.inner_nodes.promote_leaf_root(
+            return db.inner_nodes.promote_leaf_root(
+                reservation,
                 lock_bundle
                     .root_lock
                     .as_mut()
@@ -1121,7 +3986,8 @@ impl<'db> QuickStepTx<'db> {
         ) {
             Ok(()) => Ok(()),
             Err(QSError::NodeFull) => {
-                let split = self.db.inner_nodes.split_inner_node(
+                let split = db.inner_nodes.split_inner_node(
+                    reservation,
                     guard,
                     level,
                     ChildPointer::Leaf(left_leaf),
@@ -1136,14 +4002,15 @@ impl<'db> QuickStepTx<'db> {
                     child_level: level,
                 };
 
-                self.bubble_split_up(lock_bundle, parent_idx, pending)
+                Self::bubble_split_up(db, reservation, lock_bundle, parent_idx, pending)
             }
             Err(e) => Err(e),
         }
     }
 
     fn bubble_split_up(
-        &mut self,
+        db: &'db Store,
+        reservation: &mut InnerReservation<'db>,
         lock_bundle: &mut WriteLockBundle<'db>,
         mut idx: usize,
         mut pending: PendingParentSplit,
@@ -1160,7 +4027,8 @@ impl<'db> QuickStepTx<'db> {
             ) {
                 Ok(()) => return Ok(()),
                 Err(QSError::NodeFull) => {
-                    let split = self.db.inner_nodes.split_inner_node(
+                    let split = db.inner_nodes.split_inner_node(
+                        reservation,
                         guard,
                         level,
                         pending.left_child,
@@ -1183,7 +4051,8 @@ impl<'db> QuickStepTx<'db> {
             .root_lock
             .as_mut()
             .expect("root lock must exist for cascading split");
-        self.db.inner_nodes.promote_inner_root(
+        db.inner_nodes.promote_inner_root(
+            reservation,
             root_lock,
             pending.left_child.as_inner(),
             pending.right_child.as_inner(),
@@ -1197,9 +4066,10 @@ impl<'db> QuickStepTx<'db> {
         left_guard: &mut WriteGuardWrapper<'db>,
         right_guard: &mut WriteGuardWrapper<'db>,
         lock_bundle: &mut WriteLockBundle<'db>,
+        merge_key: &[u8],
     ) -> Result<(), QSError> {
-        Self::ensure_mini_page(self.db, left_guard)?;
-        Self::ensure_mini_page(self.db, right_guard)?;
+        Self::ensure_full_mini_page(self.db, &mut self.lock_manager, left_guard)?;
+        Self::ensure_full_mini_page(self.db, &mut self.lock_manager, right_guard)?;
 
         let left_index = match left_guard.get_write_guard().node() {
             NodeRef::MiniPage(idx) => idx,
@@ -1209,13 +4079,19 @@ impl<'db> QuickStepTx<'db> {
             NodeRef::MiniPage(idx) => idx,
             NodeRef::Leaf(_) => unreachable!("mini page expected after promotion"),
         };
+        let right_slot = right_index.index;
 
         let left_meta = unsafe { self.db.cache.get_meta_mut(left_index) };
         let right_meta = unsafe { self.db.cache.get_meta_mut(right_index) };
+        let removed_addr = right_meta.leaf();
         let plan = LeafMergePlan::from_nodes(left_meta, right_meta);
+        if !plan.fits() {
+            return Err(QSError::MergeFailed);
+        }
         let outcome = plan
             .apply(left_meta, right_meta)
             .map_err(|_| QSError::MergeFailed)?;
+        let (lower, upper) = collect_fence_keys(left_meta);
 
         debug::record_merge_event(
             left_guard.page_id().0,
@@ -1223,11 +4099,46 @@ impl<'db> QuickStepTx<'db> {
             outcome.merged_count,
         );
 
-        self.remove_parent_after_merge(lock_bundle, left_guard.page_id(), right_guard.page_id())
+        // the right leaf disappears from the tree, so its WAL group can no longer be
+        // replayed; persist the survivor and fold both groups into the merge record
+        left_guard.merge_to_disk(&self.db.cache, &self.db.io_engine, &self.db.wal)?;
+        #[cfg(feature = "failpoints")]
+        failpoints::hit(failpoints::MERGE_SURVIVOR_WRITTEN)?;
+        self.db
+            .wal
+            .append_leaf_merge(
+                left_guard.page_id(),
+                right_guard.page_id(),
+                &lower,
+                &upper,
+                merge_key,
+            )
+            .expect("failed to log leaf merge");
+        #[cfg(feature = "failpoints")]
+        failpoints::hit(failpoints::MERGE_LOGGED)?;
+        self.db.stats.record_merge();
+        self.db.events.emit(|| StructuralEvent::LeafMerge {
+            survivor: left_guard.page_id().as_u64(),
+            removed: right_guard.page_id().as_u64(),
+        });
+        // its records now live in the survivor, which is on disk
+        self.db.io_engine.free_page(removed_addr);
+        // nothing routes to the right page any more, so its slot can go to the freelist
+        // rather than sit in the ring until eviction reaches it
+        right_guard.get_write_guard().set_leaf(removed_addr);
+        // SAFETY: the page no longer points at the slot
+        unsafe { self.db.cache.retire(MiniPageIndex::new(right_slot)) };
+
+        Self::remove_parent_after_merge(
+            self.db,
+            lock_bundle,
+            left_guard.page_id(),
+            right_guard.page_id(),
+        )
     }
 
     fn remove_parent_after_merge(
-        &mut self,
+        db: &'db Store,
         lock_bundle: &mut WriteLockBundle<'db>,
         survivor: PageId,
         removed: PageId,
@@ -1239,54 +4150,25 @@ impl<'db> QuickStepTx<'db> {
         let parent_idx = lock_bundle.chain.len() - 1;
         let level = lock_bundle.chain[parent_idx].level;
         let guard = &mut lock_bundle.chain[parent_idx].guard;
-        let demote = self.db.inner_nodes.remove_child_after_merge(
+        db.inner_nodes.remove_child_after_merge(
             guard,
             level,
             ChildPointer::Leaf(survivor),
             ChildPointer::Leaf(removed),
         )?;
 
-        if let Some(mut child) = demote {
-            if parent_idx == 0 {
-                if let Some(ref mut root_lock) = lock_bundle.root_lock {
-                    self.db
-                        .inner_nodes
-                        .demote_root_after_merge(root_lock, child, level)?;
-                }
-                return Ok(());
-            }
-
-            lock_bundle.chain.pop();
-            let mut idx = parent_idx - 1;
-            loop {
-                let parent_level = lock_bundle.chain[idx].level;
-                let guard = &mut lock_bundle.chain[idx].guard;
-                let demotion = self.db.inner_nodes.remove_child_after_merge(
-                    guard,
-                    parent_level,
-                    child,
-                    ChildPointer::Inner(guard.node_id()),
-                )?;
-
-                if let Some(child_ptr) = demotion {
-                    if idx == 0 {
-                        if let Some(ref mut root_lock) = lock_bundle.root_lock {
-                            self.db.inner_nodes.demote_root_after_merge(
-                                root_lock,
-                                child_ptr,
-                                parent_level,
-                            )?;
-                        }
-                        break;
-                    } else {
-                        child = child_ptr;
-                        idx -= 1;
-                        continue;
-                    }
-                } else {
-                    break;
-                }
-            }
+        // a node below the root left with a single child keeps routing every key to it; a
+        // root left so gives way to its child, which may be down to one child as well
+        let Some(root_lock) = lock_bundle.root_lock.as_mut() else {
+            return Ok(());
+        };
+        while let Some(top) = lock_bundle.chain.first() {
+            let Some(child) = top.guard.as_ref().sole_child(top.level) else {
+                break;
+            };
+            let LockedInner { level, guard } = lock_bundle.chain.remove(0);
+            db.inner_nodes
+                .demote_root_after_merge(root_lock, guard, child, level)?;
         }
 
         Ok(())
@@ -1342,26 +4224,19 @@ fn collect_user_records(meta: &NodeMeta) -> Vec<(Vec<u8>, Vec<u8>)> {
         .collect()
 }
 
-fn records_between(meta: &NodeMeta, lower: &[u8], upper: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
-    collect_user_records(meta)
-        .into_iter()
-        .filter(|(key, _)| key.as_slice() >= lower && key.as_slice() < upper)
-        .collect()
-}
-
 fn apply_wal_op(entries: &mut BTreeMap<Vec<u8>, Vec<u8>>, key: Vec<u8>, op: WalOp) {
     match op {
-        WalOp::Put { value } => {
+        WalOp::Put { value } | WalOp::Merge { value, .. } => {
             entries.insert(key, value);
         }
         WalOp::Tombstone => {
             entries.remove(&key);
         }
-        WalOp::TxnMarker(_) => {}
+        WalOp::TxnMarker(_) | WalOp::Smo(_) => {}
     }
 }
 
-impl QuickStep {
+impl Store {
     pub fn debug_truncate_leaf(
         &self,
         page_id: PageId,
@@ -1381,6 +4256,40 @@ impl QuickStep {
         res
     }
 
+    /// Point lookup without a transaction: the page is read locked only for the lookup, and
+    /// no lock manager or WAL markers are set up. Fails with `PageLockFail` if a transaction
+    /// keeps the leaf write locked past the lock timeout
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, QSError> {
+        self.activity.touch();
+        let started = Instant::now();
+        let page = self.leaf_for_key(key)?;
+        let mut page_guard = PageGuard {
+            guard_inner: GuardWrapper::Read(self.map_table.read_page_entry(page)?),
+            leaf: None,
+        };
+        let now = now_millis();
+        let value = page_guard
+            .get(&self.cache, &self.io_engine, key)?
+            .filter(|stored| !attrs::split(stored).0.hides(now))
+            .map(|stored| merge::resolve(&self.io_engine, key, stored).map(Cow::into_owned))
+            .transpose()?;
+        self.stats.gets.record(started.elapsed());
+        Ok(value)
+    }
+
+    /// The value `key`'s record holds as stored, a pointer for a long value and its header
+    /// included, whether or not the key is expired or soft-deleted
+    pub(crate) fn stored_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, QSError> {
+        let page = self.leaf_for_key(key)?;
+        let mut page_guard = PageGuard {
+            guard_inner: GuardWrapper::Read(self.map_table.read_page_entry(page)?),
+            leaf: None,
+        };
+        Ok(page_guard
+            .get(&self.cache, &self.io_engine, key)?
+            .map(<[u8]>::to_vec))
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<bool, QSError> {
         let mut tx = self.tx();
         let res = tx.delete(key);
@@ -1388,6 +4297,109 @@ impl QuickStep {
         res
     }
 
+    pub fn soft_delete(&self, key: &[u8]) -> Result<bool, QSError> {
+        let mut tx = self.tx();
+        let res = tx.soft_delete(key);
+        tx.commit();
+        res
+    }
+
+    pub fn undelete(&self, key: &[u8]) -> Result<bool, QSError> {
+        let mut tx = self.tx();
+        let res = tx.undelete(key);
+        tx.commit();
+        res
+    }
+
+    /// Whether `key` is soft-deleted, its value is still stored
+    pub fn is_soft_deleted(&self, key: &[u8]) -> Result<bool, QSError> {
+        let stored = self.stored_value(key)?;
+        Ok(stored.is_some_and(|stored| attrs::split(&stored).0.soft_deleted))
+    }
+
+    /// Time left before `key` expires, `None` if it was written without a TTL
+    pub fn ttl(&self, key: &[u8]) -> Option<Duration> {
+        self.ttl
+            .expires_at(key)
+            .map(|expires_at| Duration::from_millis(expires_at.saturating_sub(now_millis())))
+    }
+
+    /// Receive the puts and deletes of committed transactions to keys starting with
+    /// `prefix`, see `watch`
+    pub fn subscribe(&self, prefix: &[u8]) -> Receiver<ChangeEvent> {
+        self.watchers.subscribe(prefix)
+    }
+
+    /// Puts and deletes committed at or after `since`, in milliseconds since the Unix epoch,
+    /// oldest first. Empty unless the activity log is configured, see `activity_log`
+    pub fn recent_changes(&self, since: u64) -> Result<Vec<ActivityEntry>, QSError> {
+        self.activity_since(since)
+    }
+
+    /// Receive an event each time the WAL moves between pressure levels, a split is refused
+    /// for want of inner nodes, the inner tree grows or a WAL audit finds a mismatch, see
+    /// `health`
+    pub fn health_events(&self) -> Receiver<HealthEvent> {
+        self.wal_limit.subscribe()
+    }
+
+    /// Read the WAL file back and list where it disagrees with what the store counts, as
+    /// the audit configured with `QuickStepConfig::with_wal_audit` does
+    pub fn audit_wal(&self) -> std::io::Result<Vec<WalMismatch>> {
+        self.wal.audit()
+    }
+
+    /// Walk the inner nodes and leaves and check the whole tree against its invariants and
+    /// the WAL, listing every problem found rather than stopping at the first, see `verify`.
+    /// Run it while no writers are active. An error means the check itself could not run
+    pub fn verify(&self) -> Result<VerifyReport, QSError> {
+        verify::verify(self)
+    }
+
+    /// Durable WAL records from the sequence number `from_lsn` on, for shipping to a
+    /// replica. Only the records kept with `QuickStepConfig::with_wal_tail_retention` are
+    /// available, see `WalManager::tail`. Values kept in overflow pages or blob files are
+    /// read back into the records; a put whose chain a later commit has already freed, or
+    /// whose blob file has been collected, is sent as an undo record, which a follower skips,
+    /// the later write follows it in the stream
+    pub fn tail_wal(&self, from_lsn: u64) -> Result<impl Iterator<Item = WalRecord>, WalTailGap> {
+        let records: Vec<_> = self
+            .wal
+            .tail(from_lsn)?
+            .map(|mut record| {
+                let redo = !matches!(record.kind, WalEntryKind::Undo);
+                if let WalOp::Put { value } | WalOp::Merge { value, .. } = &mut record.op {
+                    let read = overflow::fetch_stored(&self.io_engine, value).filter(|_| redo);
+                    match read {
+                        Some(Ok(read)) => *value = read,
+                        Some(Err(_)) => record.kind = WalEntryKind::Undo,
+                        None => {}
+                    }
+                }
+                record
+            })
+            .collect();
+        Ok(records.into_iter())
+    }
+
+    /// Receive the durable WAL sequence number each time more records become durable
+    pub fn wal_durable_events(&self) -> Receiver<u64> {
+        self.wal.subscribe_durable()
+    }
+
+    /// Number of keys written with a TTL that have not been swept yet
+    pub fn expiring_keys(&self) -> usize {
+        self.ttl.len()
+    }
+
+    /// Delete every expired key now rather than waiting for the sweeper, returns how many
+    pub fn purge_expired(&self) -> Result<usize, QSError> {
+        let mut tx = self.tx();
+        let res = tx.sweep_expired(usize::MAX);
+        tx.commit();
+        res
+    }
+
     pub fn debug_flush_leaf(&self, page_id: PageId) -> Result<(), QSError> {
         let mut tx = self.tx();
         let res = tx.debug_flush_leaf(page_id);
@@ -1398,6 +4410,82 @@ impl QuickStep {
     pub fn debug_flush_root_leaf(&self) -> Result<(), QSError> {
         self.debug_flush_leaf(PageId(0))
     }
+
+    /// Run one pass of the global checkpoint now, whatever the WAL's size: flush up to the
+    /// checkpoint batch of leaves picked by the checkpoint policy and drop their WAL records.
+    /// Returns how many leaves were flushed; leaves a transaction has locked are skipped
+    pub fn run_global_checkpoint(&self) -> Result<usize, QSError> {
+        self.check_writable()?;
+        maintenance::checkpoint_pass(
+            &self.maintenance_ctx(),
+            self.checkpoint_policy,
+            self.checkpoint_batch,
+            &self.checkpoint_cursor,
+        )
+    }
+
+    /// Flush every dirty leaf and drop its WAL records in one go, so that the data file
+    /// holds every commit made before the call and the WAL little more than the structure
+    /// records, as before taking a filesystem snapshot of the store. Each leaf is locked
+    /// from its flush until its records are dropped; one a transaction holds past the
+    /// lock-wait policy keeps its records and is counted as skipped. Transactions may carry
+    /// on meanwhile, their later commits go to the WAL as usual
+    pub fn checkpoint(&self) -> Result<CheckpointStats, QSError> {
+        self.check_writable()?;
+        self.flush_leaves(true)
+    }
+
+    /// Change the WAL thresholds of `QuickStepConfig::with_wal_thresholds` on the running
+    /// store: writes check the new ones from their next write, the checkpoint thread from
+    /// its next look at the WAL
+    pub fn set_wal_thresholds(
+        &self,
+        leaf_checkpoint: usize,
+        global_record: usize,
+        global_bytes: usize,
+    ) {
+        self.checkpoint_triggers
+            .set_thresholds(leaf_checkpoint, global_record, global_bytes);
+    }
+
+    /// The WAL thresholds in force, as `QuickStepConfig::wal_thresholds`
+    pub fn wal_thresholds(&self) -> (usize, usize, usize) {
+        self.checkpoint_triggers.thresholds()
+    }
+
+    /// Change how often the checkpoint thread looks at the WAL, as
+    /// `QuickStepConfig::with_checkpoint_interval`; a thread waiting out the old interval
+    /// looks at once and then every `interval`
+    pub fn set_checkpoint_interval(&self, interval: Duration) {
+        self.checkpoint_triggers.set_interval(interval);
+        self.checkpoint_signal.wake();
+    }
+
+    pub fn checkpoint_interval(&self) -> Duration {
+        self.checkpoint_triggers.interval()
+    }
+
+    /// Flush every leaf the WAL has records for and drop those records, returns how many
+    /// WAL bytes that freed. Leaves other transactions are writing keep their records
+    pub(crate) fn checkpoint_leaves(&self) -> Result<usize, QSError> {
+        let before = self.wal.total_bytes()?;
+        let mut tx = self.tx();
+        loop {
+            let remaining = self.wal.total_bytes()?;
+            self.wal_checkpoint_requested.store(true, Ordering::Release);
+            tx.maybe_global_checkpoint()?;
+            if self.wal.total_bytes()? >= remaining {
+                break;
+            }
+        }
+        self.wal_checkpoint_requested
+            .store(false, Ordering::Release);
+        tx.commit();
+        self.wal
+            .checkpoint_txn_markers()
+            .expect("failed to checkpoint WAL transaction markers");
+        Ok(before.saturating_sub(self.wal.total_bytes()?))
+    }
 }
 
 impl<'db> QuickStepTx<'db> {
@@ -1407,10 +4495,17 @@ impl<'db> QuickStepTx<'db> {
         keep: usize,
         auto_merge: bool,
     ) -> Result<(), QSError> {
+        let _op = self.enter()?;
         let mut guard = self
             .lock_manager
             .get_upgrade_or_acquire_write_lock(&self.db.map_table, page_id)?;
-        Self::ensure_mini_page(self.db, &mut guard)?;
+        // a page merged away points at its freed disk leaf, there is nothing left in it
+        if let NodeRef::Leaf(addr) = guard.get_write_guard().node() {
+            if self.db.io_engine.is_free_page(addr) {
+                return Ok(());
+            }
+        }
+        Self::ensure_full_mini_page(self.db, &mut self.lock_manager, &mut guard)?;
         let index = match guard.get_write_guard().node() {
             NodeRef::MiniPage(idx) => idx,
             NodeRef::Leaf(_) => unreachable!("mini page expected after promotion"),
@@ -1428,6 +4523,7 @@ impl<'db> QuickStepTx<'db> {
                 .map(|(key, value)| (key.as_slice(), value.as_slice())),
         )
         .map_err(|_| QSError::MergeFailed)?;
+        drop(guard);
 
         if auto_merge && records.len() <= AUTO_MERGE_MIN_ENTRIES {
             self.try_auto_merge(page_id)?;
@@ -1437,6 +4533,7 @@ impl<'db> QuickStepTx<'db> {
     }
 
     pub fn debug_merge_leaves(&mut self, left: PageId, right: PageId) -> Result<(), QSError> {
+        let _op = self.enter()?;
         let mut left_guard = self
             .lock_manager
             .get_upgrade_or_acquire_write_lock(&self.db.map_table, left)?;
@@ -1450,7 +4547,12 @@ impl<'db> QuickStepTx<'db> {
                 .inner_nodes
                 .write_lock(read_res.underflow_point, OpType::Merge, &merge_key);
         let mut lock_bundle = lock_bundle?;
-        self.merge_leaf_pages(&mut left_guard, &mut right_guard, &mut lock_bundle)
+        self.merge_leaf_pages(
+            &mut left_guard,
+            &mut right_guard,
+            &mut lock_bundle,
+            &merge_key,
+        )
     }
 
     fn try_auto_merge(&mut self, page_id: PageId) -> Result<(), QSError> {
@@ -1474,15 +4576,183 @@ impl<'db> QuickStepTx<'db> {
         let right_idx = neighbor_idx.max(idx);
         let left_child = snapshot.children[left_idx];
         let right_child = snapshot.children[right_idx];
-        self.debug_merge_leaves(left_child, right_child)
+        // a neighbour too full to take the records is left for a later delete
+        match self.debug_merge_leaves(left_child, right_child) {
+            Err(QSError::MergeFailed) => Ok(()),
+            res => res,
+        }
     }
 
-    pub fn delete<'tx>(&'tx mut self, key: &[u8]) -> Result<bool, QSError> {
-        let res = self.db.inner_nodes.read_traverse_leaf(key)?;
-        let mut page_guard = self
-            .lock_manager
-            .get_upgrade_or_acquire_write_lock(&self.db.map_table, res.page)?;
-        Self::ensure_mini_page(self.db, &mut page_guard)?;
+    /// Delete a key, returns whether it was present; an expired or soft-deleted key is
+    /// removed but reported absent
+    pub fn delete(&mut self, key: &[u8]) -> Result<bool, QSError> {
+        let _op = self.enter()?;
+        self.check_not_reserved(key)?;
+        self.enforce_wal_limit()?;
+        let started = Instant::now();
+        let removed = self.delete_record(key)?;
+        self.db.stats.deletes.record(started.elapsed());
+        self.maybe_rewrite_repaired()?;
+        Ok(removed.is_some_and(|attrs| !attrs.hides(now_millis())))
+    }
+
+    fn record_change(&mut self, key: &[u8], kind: impl FnOnce() -> ChangeKind) {
+        if self.tracks_changes() && !reserved::is_reserved(key) {
+            self.changes.push(ChangeEvent {
+                txn_id: self.txn_id,
+                key: key.to_vec(),
+                kind: kind(),
+            });
+        }
+    }
+
+    /// Write this transaction's changes to the activity log and delete the records past its
+    /// capacity, before the commit or prepare marker. A record that cannot be written is
+    /// reported instead of failing the commit, and one that cannot be deleted is left for
+    /// the next open to count again, see `activity_log`
+    fn log_activity(&mut self) {
+        let db = self.db;
+        let Some(log) = &db.activity_log else {
+            return;
+        };
+        if self.replica || self.activity_record.is_some() {
+            return;
+        }
+        let Some((key, value)) = ActivityLog::record(self.txn_id, now_millis(), &self.changes)
+        else {
+            return;
+        };
+        let entries = self.changes.len();
+        let written = self.put_record(
+            &key,
+            RecordValue::Put {
+                value: &value,
+                attrs: RecordAttrs::default(),
+            },
+        );
+        if let Err(err) = written {
+            db.wal_limit.publish(HealthEvent::ActivityLogSkipped {
+                txn_id: self.txn_id,
+                reason: format!("{err:?}"),
+            });
+            return;
+        }
+        self.activity_record = Some((key, entries));
+        for expired in log.take_expired(entries) {
+            let _ = self.delete_record(&expired);
+        }
+    }
+
+    /// Whether committed changes go anywhere, to a watcher or the activity log
+    fn tracks_changes(&self) -> bool {
+        self.db.watchers.is_watched() || self.db.activity_log.is_some()
+    }
+
+    /// Write back a batch of the leaves read repair fixed up, once one was repaired
+    fn maybe_rewrite_repaired(&mut self) -> Result<(), QSError> {
+        let repairs = self.db.io_engine.read_repairs();
+        if !repairs.take_rewrite_request() {
+            return Ok(());
+        }
+        self.rewrite_repaired(READ_REPAIR_BATCH)?;
+        if !repairs.repaired(1).is_empty() {
+            // more than a batch, or leaves that were busy: have the next write carry on
+            repairs.request_rewrite();
+        }
+        Ok(())
+    }
+
+    /// Write up to `limit` repaired leaves back to disk, returns how many were written. A
+    /// leaf only on disk then holds all its records and its WAL records are dropped; a
+    /// cached one keeps them until it is flushed. Leaves other transactions are writing are
+    /// left for a later pass
+    fn rewrite_repaired(&mut self, limit: usize) -> Result<usize, QSError> {
+        let mut rewritten = 0;
+        for addr in self.db.io_engine.read_repairs().repaired(limit) {
+            let Some(leaf) = self.db.io_engine.read_repairs().repaired_leaf(addr) else {
+                continue;
+            };
+            let page_id = leaf.as_ref().page_id();
+            let holder = self.db.lock_owners.owner(page_id);
+            if holder.is_some_and(|holder| holder != self.txn_id) {
+                continue;
+            }
+            let mut guard = match self
+                .lock_manager
+                .get_upgrade_or_acquire_write_lock(&self.db.map_table, page_id)
+            {
+                Ok(guard) => guard,
+                Err(QSError::PageLockFail) => continue,
+                Err(err) => return Err(err),
+            };
+            let only_on_disk = match guard.get_write_guard().node() {
+                NodeRef::Leaf(current) => current == addr,
+                // SAFETY: we hold the page's write lock
+                NodeRef::MiniPage(index) => {
+                    if unsafe { self.db.cache.get_meta_ref(index) }.leaf() != addr {
+                        continue;
+                    }
+                    false
+                }
+            };
+            // taken again under the lock, a flush since the listing has written it already
+            let Some(leaf) = self.db.io_engine.read_repairs().repaired_leaf(addr) else {
+                continue;
+            };
+            self.db.io_engine.write_page(addr, &leaf);
+            if only_on_disk {
+                self.db
+                    .wal
+                    .checkpoint_page(page_id)
+                    .expect("failed to checkpoint WAL for repaired leaf");
+            }
+            rewritten += 1;
+        }
+        Ok(rewritten)
+    }
+
+    /// Delete up to `limit` expired keys, returns how many were swept. Their tombstones are
+    /// dropped when the leaves are next flushed, split or merged. Keys on leaves other
+    /// transactions are writing are left for a later sweep
+    fn sweep_expired(&mut self, limit: usize) -> Result<usize, QSError> {
+        let now = now_millis();
+        let expired = self.db.ttl.expired(now, limit);
+        let mut swept = 0;
+        for key in &expired {
+            if self.ttl_changes.iter().any(|(staged, _)| staged == key) {
+                // rewritten by this transaction since it expired
+                continue;
+            }
+            let page = self.db.leaf_for_key(key)?;
+            let holder = self.db.lock_owners.owner(page);
+            if holder.is_some_and(|holder| holder != self.txn_id) {
+                continue;
+            }
+            // the record decides, a commit since the index was read may have renewed it
+            let attrs = self.write_lock_for_key(key).and_then(|mut guard| {
+                Self::with_existing_value(self.db, &mut guard, key, |stored| {
+                    stored.map(|stored| attrs::split(stored).0)
+                })
+            });
+            match attrs {
+                Ok(Some(attrs)) if attrs.is_expired(now) => {}
+                Ok(_) | Err(QSError::WriteConflict { .. } | QSError::PageLockFail) => continue,
+                Err(err) => return Err(err),
+            }
+            match self.delete_record(key) {
+                Ok(_) => {}
+                Err(QSError::WriteConflict { .. } | QSError::PageLockFail) => continue,
+                Err(err) => return Err(err),
+            }
+            swept += 1;
+        }
+        Ok(swept)
+    }
+
+    /// Delete `key`'s record, returns what its header said if there was one
+    fn delete_record(&mut self, key: &[u8]) -> Result<Option<RecordAttrs>, QSError> {
+        let mut page_guard = self.write_lock_for_key(key)?;
+        Self::ensure_full_mini_page(self.db, &mut self.lock_manager, &mut page_guard)?;
         let page_id = page_guard.page_id();
         let index = match page_guard.get_write_guard().node() {
             NodeRef::MiniPage(idx) => idx,
@@ -1494,55 +4764,65 @@ impl<'db> QuickStepTx<'db> {
             let meta = unsafe { self.db.cache.get_meta_mut(index) };
             deleted_value = meta.get(key).map(|value| value.to_vec());
             if deleted_value.is_none() {
-                return Ok(false);
+                return Ok(None);
             }
             let removed = meta.mark_tombstone(key);
             if !removed {
-                return Ok(false);
+                return Ok(None);
             }
             user_entries = meta.user_entry_count();
         }
-        let (_disk_addr, lower_fence, upper_fence) = Self::leaf_snapshot(self.db, &mut page_guard);
-        self.db
-            .wal
-            .append_tombstone(
+        let (_disk_addr, lower_fence, upper_fence) = Self::leaf_snapshot(self.db, &mut page_guard)?;
+        let record = |kind, op| {
+            WalRecord::leaf(
                 page_id,
                 key,
                 &lower_fence,
                 &upper_fence,
-                self.wal_entry_kind,
+                kind,
                 self.txn_id,
+                op,
+            )
+        };
+        let undo = deleted_value.as_ref().map(|prev| {
+            record(
+                WalEntryKind::Undo,
+                WalOp::Put {
+                    value: prev.clone(),
+                },
             )
+        });
+        self.db
+            .wal
+            .append_with_undo(record(self.wal_entry_kind, WalOp::Tombstone), undo)
             .expect("failed to record delete in WAL");
-        if let Some(prev) = deleted_value.as_ref() {
-            self.db
-                .wal
-                .append_put(
-                    page_id,
-                    key,
-                    prev,
-                    &lower_fence,
-                    &upper_fence,
-                    WalEntryKind::Undo,
-                    self.txn_id,
-                )
-                .expect("failed to record undo delete in WAL");
+        if let Some(replaced) = deleted_value.as_deref().and_then(OverflowPointer::decode) {
+            self.overflow_replaced.push(replaced);
+        }
+        let removed = deleted_value
+            .as_deref()
+            .map_or_else(RecordAttrs::default, |stored| attrs::split(stored).0);
+        if removed.expires_at.is_some() {
+            self.ttl_changes.push((key.to_vec(), None));
         }
         self.log_delete_undo(page_id, key, deleted_value);
-        Self::maybe_checkpoint_leaf(self.db, &mut page_guard, page_id)?;
-        self.maybe_global_checkpoint()?;
+        self.db.record_logical_write(key.len());
+        self.record_change(key, || ChangeKind::Delete);
+        Self::maybe_checkpoint_leaf(self.db, &mut self.lock_manager, &mut page_guard, page_id)?;
+        drop(page_guard);
         if user_entries <= AUTO_MERGE_MIN_ENTRIES {
             self.try_auto_merge(page_id)?;
         }
-        Ok(true)
+        Ok(Some(removed))
     }
 
     pub fn debug_flush_leaf(&mut self, page_id: PageId) -> Result<(), QSError> {
+        let _op = self.enter()?;
         let mut guard = self
             .lock_manager
             .get_upgrade_or_acquire_write_lock(&self.db.map_table, page_id)?;
-        Self::ensure_mini_page(self.db, &mut guard)?;
-        guard.merge_to_disk(&self.db.cache, &self.db.io_engine);
+        Self::ensure_mini_page(self.db, &mut self.lock_manager, &mut guard)?;
+        guard.merge_to_disk(&self.db.cache, &self.db.io_engine, &self.db.wal)?;
         self.db
             .wal
             .checkpoint_page(page_id)
@@ -1551,7 +4831,7 @@ impl<'db> QuickStepTx<'db> {
     }
 
     fn first_user_key(&mut self, guard: &mut WriteGuardWrapper<'db>) -> Result<Vec<u8>, QSError> {
-        Self::ensure_mini_page(self.db, guard)?;
+        Self::ensure_full_mini_page(self.db, &mut self.lock_manager, guard)?;
         let index = match guard.get_write_guard().node() {
             NodeRef::MiniPage(idx) => idx,
             NodeRef::Leaf(_) => unreachable!("mini page expected after promotion"),
@@ -0,0 +1,110 @@
+//! Key and value size limits.
+//!
+//! Writes are checked against the limits first thing, before they wait on a full WAL or
+//! touch a leaf, and fail with `QSError::KeyTooLong` or `QSError::ValueTooLong`, which carry
+//! the offending length and the limit. The format caps keys at what an inner
+//! node can hold as a pivot. Values up to what leaves two records to a page are stored in
+//! their leaf, so a full leaf can always split, and longer ones in overflow pages, see
+//! `overflow`, up to `MAX_VALUE_LEN`. Configured limits are clamped to these.
+//!
+//! A keyspace, the keys starting with some prefix, can be given limits of its own; the
+//! longest matching prefix decides.
+
+use std::mem::size_of;
+
+use crate::{
+    btree::MAX_KEY_LENGTH,
+    error::QSError,
+    io_engine::PAGE_SIZE,
+    types::{KVMeta, NodeMeta},
+};
+
+/// Longest key the format can store
+pub const MAX_KEY_LEN: usize = MAX_KEY_LENGTH;
+
+/// Longest value stored in its leaf record: two records of the longest key and such a value
+/// fit in a leaf along with its header and fences
+pub const MAX_INLINE_VALUE_LEN: usize =
+    (PAGE_SIZE as usize - size_of::<NodeMeta>() - 2 * (size_of::<KVMeta>() + MAX_KEY_LEN)) / 2
+        - size_of::<KVMeta>()
+        - MAX_KEY_LEN;
+
+/// Longest value the format can store, in a chain of overflow pages
+pub const MAX_VALUE_LEN: usize = 16 << 20;
+
+/// Largest key and value a write may store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub max_key_len: usize,
+    pub max_value_len: usize,
+}
+
+impl SizeLimits {
+    /// Limits clamped to the format's
+    pub fn new(max_key_len: usize, max_value_len: usize) -> SizeLimits {
+        SizeLimits {
+            max_key_len: max_key_len.min(MAX_KEY_LEN),
+            max_value_len: max_value_len.min(MAX_VALUE_LEN),
+        }
+    }
+
+    pub fn check_key(&self, key: &[u8]) -> Result<(), QSError> {
+        if key.len() > self.max_key_len {
+            return Err(QSError::KeyTooLong {
+                len: key.len(),
+                max: self.max_key_len,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn check_value(&self, value: &[u8]) -> Result<(), QSError> {
+        if value.len() > self.max_value_len {
+            return Err(QSError::ValueTooLong {
+                len: value.len(),
+                max: self.max_value_len,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for SizeLimits {
+    fn default() -> SizeLimits {
+        SizeLimits::new(MAX_KEY_LEN, MAX_VALUE_LEN)
+    }
+}
+
+/// Default limits and the keyspaces that override them
+#[derive(Debug, Clone, Default)]
+pub struct KeyspaceLimits {
+    default: SizeLimits,
+    /// Longest prefix first
+    overrides: Vec<(Vec<u8>, SizeLimits)>,
+}
+
+impl KeyspaceLimits {
+    pub fn set_default(&mut self, limits: SizeLimits) {
+        self.default = limits;
+    }
+
+    /// Apply `limits` to keys starting with `prefix`, replacing any set for it before
+    pub fn set_override(&mut self, prefix: &[u8], limits: SizeLimits) {
+        self.overrides.retain(|(existing, _)| existing != prefix);
+        self.overrides.push((prefix.to_vec(), limits));
+        self.overrides
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    }
+
+    pub fn default_limits(&self) -> SizeLimits {
+        self.default
+    }
+
+    /// The limits writes to `key` are held to
+    pub fn for_key(&self, key: &[u8]) -> SizeLimits {
+        self.overrides
+            .iter()
+            .find(|(prefix, _)| key.starts_with(prefix))
+            .map_or(self.default, |(_, limits)| *limits)
+    }
+}
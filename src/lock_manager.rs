@@ -1,4 +1,13 @@
-use std::{collections::HashMap, marker::PhantomData, mem, ptr::NonNull};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    mem,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use crate::{
     error::QSError,
@@ -9,6 +18,113 @@ use crate::{
 // TODO: optimise
 pub struct LockManager<'a> {
     locks: HashMap<u64, Box<LockSlot<'a>>>,
+    /// The owning transaction and the shared table its write locks are published in
+    owner: Option<(u64, &'a LockOwners)>,
+    /// Transactions that held a page or key range this transaction failed to write
+    conflicts: Vec<u64>,
+    /// This transaction has key ranges in `owner`'s table to release
+    holds_ranges: bool,
+    /// Where the pages this transaction locks are published, for transactions that can
+    /// expire
+    held: Option<Arc<HeldLocks>>,
+}
+
+/// The pages a transaction holds locked, and whether for writing, published so its locks
+/// can be taken over and released if the transaction is abandoned, see `txn_timeout`
+#[derive(Default)]
+pub struct HeldLocks(Mutex<HashMap<u64, bool>>);
+
+impl HeldLocks {
+    fn hold(&self, page: PageId, write: bool) {
+        let mut held = self.0.lock().expect("held locks mutex poisoned");
+        held.insert(page.0, write);
+    }
+
+    /// The pages held, with `true` for write locks
+    pub fn pages(&self) -> Vec<(PageId, bool)> {
+        let held = self.0.lock().expect("held locks mutex poisoned");
+        held.iter()
+            .map(|(page, write)| (PageId(*page), *write))
+            .collect()
+    }
+}
+
+/// Which transaction holds the write lock on each page, and the key ranges serializable
+/// scans have read, shared by all transactions so a failed write can be attributed to its
+/// holder
+#[derive(Default)]
+pub struct LockOwners {
+    owners: Mutex<HashMap<u64, u64>>,
+    ranges: Mutex<Vec<RangeLock>>,
+    /// Number of held ranges, lets writers skip the lock when there are none
+    ranges_held: AtomicUsize,
+}
+
+/// `lower <= key < upper` read by a serializable scan, no `upper` is unbounded
+struct RangeLock {
+    txn_id: u64,
+    lower: Vec<u8>,
+    upper: Option<Vec<u8>>,
+}
+
+impl RangeLock {
+    fn covers(&self, key: &[u8]) -> bool {
+        key >= self.lower.as_slice() && self.upper.as_deref().is_none_or(|upper| key < upper)
+    }
+}
+
+impl LockOwners {
+    pub fn new() -> LockOwners {
+        Self::default()
+    }
+
+    /// The transaction currently holding the write lock on `page`, if any
+    pub fn owner(&self, page: PageId) -> Option<u64> {
+        let owners = self.owners.lock().expect("lock owners mutex poisoned");
+        owners.get(&page.0).copied()
+    }
+
+    fn claim(&self, page: PageId, txn_id: u64) {
+        let mut owners = self.owners.lock().expect("lock owners mutex poisoned");
+        owners.insert(page.0, txn_id);
+    }
+
+    fn release<I: IntoIterator<Item = u64>>(&self, pages: I, txn_id: u64) {
+        let mut owners = self.owners.lock().expect("lock owners mutex poisoned");
+        for page in pages {
+            if owners.get(&page) == Some(&txn_id) {
+                owners.remove(&page);
+            }
+        }
+    }
+
+    /// A transaction other than `txn_id` whose scanned ranges cover `key`
+    pub fn range_holder(&self, key: &[u8], txn_id: u64) -> Option<u64> {
+        if self.ranges_held.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        let ranges = self.ranges.lock().expect("range locks mutex poisoned");
+        ranges
+            .iter()
+            .find(|range| range.txn_id != txn_id && range.covers(key))
+            .map(|range| range.txn_id)
+    }
+
+    fn lock_range(&self, txn_id: u64, lower: &[u8], upper: Option<&[u8]>) {
+        let mut ranges = self.ranges.lock().expect("range locks mutex poisoned");
+        ranges.push(RangeLock {
+            txn_id,
+            lower: lower.to_vec(),
+            upper: upper.map(<[u8]>::to_vec),
+        });
+        self.ranges_held.store(ranges.len(), Ordering::Release);
+    }
+
+    fn release_ranges(&self, txn_id: u64) {
+        let mut ranges = self.ranges.lock().expect("range locks mutex poisoned");
+        ranges.retain(|range| range.txn_id != txn_id);
+        self.ranges_held.store(ranges.len(), Ordering::Release);
+    }
 }
 
 struct LockSlot<'a> {
@@ -27,15 +143,138 @@ impl<'a> LockSlot<'a> {
 
 // Locks need to be held for the length of the transaction
 
+impl<'a> Default for LockManager<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Drop for LockManager<'a> {
+    fn drop(&mut self) {
+        if let Some((txn_id, owners)) = self.owner {
+            owners.release(self.locks.keys().copied(), txn_id);
+            if self.holds_ranges {
+                owners.release_ranges(txn_id);
+            }
+        }
+    }
+}
+
 impl<'a> LockManager<'a> {
     pub fn new() -> LockManager<'a> {
         LockManager {
             locks: HashMap::new(),
+            owner: None,
+            conflicts: Vec::new(),
+            holds_ranges: false,
+            held: None,
+        }
+    }
+
+    /// Lock manager for transaction `txn_id`, publishing its write locks in `owners`
+    pub fn for_txn(txn_id: u64, owners: &'a LockOwners) -> LockManager<'a> {
+        LockManager {
+            locks: HashMap::new(),
+            owner: Some((txn_id, owners)),
+            conflicts: Vec::new(),
+            holds_ranges: false,
+            held: None,
+        }
+    }
+
+    /// Publish every page this lock manager locks in `held`
+    pub fn publish_held(&mut self, held: Arc<HeldLocks>) {
+        self.held = Some(held);
+    }
+
+    /// Take over the locks an abandoned transaction published in `held`, and the key ranges
+    /// it scanned, so they are released when this lock manager drops
+    ///
+    /// # Safety
+    /// The lock manager holding the locks must `disown` them, and not use them before
+    pub unsafe fn adopt_locks(&mut self, map_table: &'a MapTable, held: &HeldLocks) {
+        for (page, write) in held.pages() {
+            let guard_inner = if write {
+                GuardWrapper::Write(map_table.adopt_write_lock(page))
+            } else {
+                GuardWrapper::Read(map_table.adopt_read_lock(page))
+            };
+            self.locks.insert(
+                page.0,
+                Box::new(LockSlot::new(PageGuard {
+                    guard_inner,
+                    leaf: None,
+                })),
+            );
+        }
+        self.holds_ranges = true;
+    }
+
+    /// Forget every lock without releasing it, once `adopt_locks` has taken them over
+    pub fn disown(&mut self) {
+        for (_, slot) in self.locks.drain() {
+            let PageGuard { guard_inner, .. } = slot.guard;
+            mem::forget(guard_inner);
+        }
+        self.holds_ranges = false;
+    }
+
+    /// Ids of the transactions that blocked a write lock, in the order they were first seen
+    pub fn conflicts(&self) -> &[u64] {
+        &self.conflicts
+    }
+
+    /// Lock `lower <= key < upper` against other transactions' writes until this one ends,
+    /// no `upper` is unbounded. Only a lock manager for a transaction can hold ranges
+    pub fn lock_range(&mut self, lower: &[u8], upper: Option<&[u8]>) {
+        if let Some((txn_id, owners)) = self.owner {
+            owners.lock_range(txn_id, lower, upper);
+            self.holds_ranges = true;
+        }
+    }
+
+    /// Fail with `WriteConflict` if another transaction has scanned a range holding `key`,
+    /// recording it as a conflict
+    pub fn check_range(&mut self, key: &[u8]) -> Result<(), QSError> {
+        let Some((txn_id, owners)) = self.owner else {
+            return Ok(());
+        };
+        match owners.range_holder(key, txn_id) {
+            Some(holder) => {
+                if !self.conflicts.contains(&holder) {
+                    self.conflicts.push(holder);
+                }
+                Err(QSError::WriteConflict { key: key.to_vec() })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn claim(&self, page: PageId) {
+        if let Some((txn_id, owners)) = self.owner {
+            owners.claim(page, txn_id);
+        }
+        if let Some(held) = &self.held {
+            held.hold(page, true);
+        }
+    }
+
+    /// Attribute a failed write lock on `page` to the transaction holding it
+    fn record_conflict(&mut self, page: PageId) {
+        let Some((txn_id, owners)) = self.owner else {
+            return;
+        };
+        match owners.owner(page) {
+            Some(holder) if holder != txn_id && !self.conflicts.contains(&holder) => {
+                self.conflicts.push(holder);
+            }
+            _ => {}
         }
     }
 
     pub fn insert_write_lock(&mut self, guard: PageWriteGuard<'a>) -> WriteGuardWrapper<'a> {
         let id = guard.page.0;
+        self.claim(guard.page);
         self.locks.insert(
             id,
             Box::new(LockSlot::new(PageGuard {
@@ -58,16 +297,16 @@ impl<'a> LockManager<'a> {
         page: PageId,
     ) -> Result<&mut PageGuard<'a>, QSError> {
         // I tried entries, but couldn't get the lifetimes to work
-        if !self.locks.contains_key(&page.0) {
+        if let std::collections::hash_map::Entry::Vacant(e) = self.locks.entry(page.0) {
             let guard: PageReadGuard<'a> = mapping_table.read_page_entry(page)?;
 
-            self.locks.insert(
-                page.0,
-                Box::new(LockSlot::new(PageGuard {
-                    guard_inner: GuardWrapper::Read(guard),
-                    leaf: None,
-                })),
-            );
+            e.insert(Box::new(LockSlot::new(PageGuard {
+                guard_inner: GuardWrapper::Read(guard),
+                leaf: None,
+            })));
+            if let Some(held) = &self.held {
+                held.hold(page, false);
+            }
         }
 
         let slot = self
@@ -83,16 +322,21 @@ impl<'a> LockManager<'a> {
         mapping_table: &'a MapTable,
         page: PageId,
     ) -> Result<WriteGuardWrapper<'a>, QSError> {
-        if !self.locks.contains_key(&page.0) {
-            let guard = mapping_table.write_page_entry(page)?;
+        let mut acquired = false;
+        if let std::collections::hash_map::Entry::Vacant(e) = self.locks.entry(page.0) {
+            let guard = match mapping_table.write_page_entry(page) {
+                Ok(guard) => guard,
+                Err(err) => {
+                    self.record_conflict(page);
+                    return Err(err);
+                }
+            };
 
-            self.locks.insert(
-                page.0,
-                Box::new(LockSlot::new(PageGuard {
-                    guard_inner: GuardWrapper::Write(guard),
-                    leaf: None,
-                })),
-            );
+            e.insert(Box::new(LockSlot::new(PageGuard {
+                guard_inner: GuardWrapper::Write(guard),
+                leaf: None,
+            })));
+            acquired = true;
         }
 
         let slot = self
@@ -100,12 +344,45 @@ impl<'a> LockManager<'a> {
             .get_mut(&page.0)
             .expect("we just added it if it didn't exist");
 
-        slot.guard.ensure_write()?;
+        if !slot.guard.is_write() {
+            if let Err(err) = slot.guard.ensure_write() {
+                // readers aren't published, so an upgrade blocked only by them has no holder
+                self.record_conflict(page);
+                return Err(err);
+            }
+            acquired = true;
+        }
+
+        if acquired {
+            self.claim(page);
+        }
 
+        let slot = self.locks.get_mut(&page.0).expect("slot was checked above");
         Ok(WriteGuardWrapper::new(PageHandle::acquire(slot)))
     }
 }
 
+impl<'a> LockManager<'a> {
+    /// Write guard for a page this transaction already holds, as long as no handle to it is
+    /// currently out. Used by eviction to reclaim the transaction's own mini-pages.
+    pub fn owned_write_guard(&mut self, page: PageId) -> Option<&mut PageGuard<'a>> {
+        let slot = self.locks.get_mut(&page.0)?;
+        if slot.borrowed || !slot.guard.is_write() {
+            return None;
+        }
+        Some(&mut slot.guard)
+    }
+
+    /// Pages this transaction holds the write lock on
+    pub fn write_locked_pages(&self) -> Vec<PageId> {
+        self.locks
+            .iter()
+            .filter(|(_, slot)| slot.guard.is_write())
+            .map(|(page, _)| PageId(*page))
+            .collect()
+    }
+}
+
 pub enum GuardWrapper<'a> {
     Write(PageWriteGuard<'a>),
     Read(PageReadGuard<'a>),
@@ -207,7 +484,7 @@ impl<'a> PageGuard<'a> {
         let leaf = match self.leaf {
             Some(ref mut l) => l,
             None => {
-                let new_leaf = io.get_page(addr);
+                let new_leaf = io.get_page(addr)?;
                 self.leaf = Some(new_leaf);
                 self.leaf.as_mut().expect("just set leaf to Some")
             }
@@ -263,14 +540,50 @@ impl<'a> GuardWrapper<'a> {
                     unreachable!("We just wrote as a Write")
                 };
 
-                return Ok(TmpPageWrite::ReadOriginal {
+                Ok(TmpPageWrite::ReadOriginal {
                     guard,
                     original_location: wrapper_ptr,
-                });
+                })
             }
             Err((r, e)) => {
                 mem::forget(r);
-                return Err(e);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a> GuardWrapper<'a> {
+    /// Like `temp_upgrade`, but gives up at once rather than wait for other readers to leave
+    pub fn try_temp_upgrade<'tx>(&'tx mut self) -> Option<TmpPageWrite<'tx, 'a>> {
+        if let GuardWrapper::Write(ref mut w) = self {
+            return Some(TmpPageWrite::WriteOriginal(w));
+        }
+
+        let wrapper_ptr = self as *mut GuardWrapper<'a>;
+
+        let GuardWrapper::Read(read_ref) = self else {
+            unreachable!("We just checked for the write case")
+        };
+
+        let read_guard = unsafe { (read_ref as *const PageReadGuard<'a>).read() };
+        match read_guard.try_upgrade() {
+            Ok(w) => {
+                unsafe { wrapper_ptr.write(GuardWrapper::Write(w)) };
+
+                let GuardWrapper::Write(guard) = self else {
+                    unreachable!("We just wrote as a Write")
+                };
+
+                Some(TmpPageWrite::ReadOriginal {
+                    guard,
+                    original_location: wrapper_ptr,
+                })
+            }
+            Err(r) => {
+                // the original read guard is still in place
+                mem::forget(r);
+                None
             }
         }
     }
@@ -0,0 +1,230 @@
+//! Maintenance run while the store is idle.
+//!
+//! With idle maintenance configured, a background thread watches for foreground operations
+//! (transactions, point gets and range iterators). Once none has started for `idle_after`,
+//! it flushes the mini-pages of leaves with records in the WAL, largest first: the flush
+//! drops their tombstones, rewrites the disk leaf compactly and lets the leaf's WAL records
+//! go. When no leaf records are left, the markers of finished transactions are trimmed too.
+//!
+//! Leaf writes are paced by a token bucket of `leaf_writes_per_sec`, and the pass stops as
+//! soon as a foreground operation starts. Leaves locked by a transaction are skipped. A pass
+//! that fails, as on a WAL or data file error, is reported to health subscribers as
+//! `HealthEvent::BackgroundPassFailed` and the next pass tries again.
+//!
+//! The global checkpoint runs here too, on its own thread rather than inside whichever put
+//! or delete pushed the WAL past its thresholds, so an idle store checkpoints as well. Each
+//! pass flushes up to a batch of leaves picked by the checkpoint policy, taking their locks
+//! without waiting, and trims the transaction markers once no leaf records are left; a pass
+//! that finds every leaf busy, or fails, backs off before trying again; a failure is reported
+//! as `HealthEvent::BackgroundPassFailed` too. A leaf waiting for read repair
+//! is written back before its records go, whether or not it was read.
+
+use std::{
+    cmp::Reverse,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    buffer::MiniPageBuffer,
+    checkpoint::{CheckpointCandidate, CheckpointPolicy},
+    error::QSError,
+    io_engine::IoEngine,
+    map_table::{MapTable, PageId},
+    page_op::flush_dirty_entries,
+    ttl::now_millis,
+    types::NodeRef,
+    wal::WalManager,
+};
+
+/// When maintenance may run and how many leaves it may write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleMaintenance {
+    /// How long no foreground operation must have started before maintenance runs
+    pub idle_after: Duration,
+    /// Most leaves maintenance writes per second, at least 1
+    pub leaf_writes_per_sec: u32,
+}
+
+impl IdleMaintenance {
+    pub fn new(idle_after: Duration, leaf_writes_per_sec: u32) -> IdleMaintenance {
+        IdleMaintenance {
+            idle_after,
+            leaf_writes_per_sec: leaf_writes_per_sec.max(1),
+        }
+    }
+}
+
+/// Wall-clock time of the last foreground operation, in milliseconds
+pub(crate) struct ActivityClock {
+    last_op: AtomicU64,
+}
+
+impl ActivityClock {
+    pub(crate) fn new() -> ActivityClock {
+        ActivityClock {
+            last_op: AtomicU64::new(now_millis()),
+        }
+    }
+
+    pub(crate) fn touch(&self) {
+        self.last_op.store(now_millis(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn idle_for(&self) -> Duration {
+        Duration::from_millis(now_millis().saturating_sub(self.last_op.load(Ordering::Relaxed)))
+    }
+}
+
+/// Leaf writes maintenance has left, refilled at `leaf_writes_per_sec` up to one second's worth
+pub(crate) struct LeafWriteBudget {
+    per_sec: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl LeafWriteBudget {
+    pub(crate) fn new(leaf_writes_per_sec: u32) -> LeafWriteBudget {
+        LeafWriteBudget {
+            per_sec: leaf_writes_per_sec as f64,
+            tokens: 0.0,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn available(&mut self) -> usize {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.per_sec);
+        self.refilled_at = now;
+        self.tokens as usize
+    }
+
+    pub(crate) fn spend(&mut self, leaves: usize) {
+        self.tokens = (self.tokens - leaves as f64).max(0.0);
+    }
+}
+
+/// Components a maintenance pass works on, shared with the db
+pub(crate) struct MaintenanceCtx<'a> {
+    pub map_table: &'a MapTable,
+    pub cache: &'a MiniPageBuffer,
+    pub io_engine: &'a IoEngine,
+    pub wal: &'a WalManager,
+    pub clock: &'a ActivityClock,
+}
+
+/// Flush up to `max_leaves` leaves with WAL records, then trim the transaction markers once
+/// no leaf records are left. Returns how many leaves were written; stops early once a
+/// foreground operation starts
+pub(crate) fn run_pass(
+    ctx: &MaintenanceCtx,
+    idle_after: Duration,
+    max_leaves: usize,
+) -> Result<usize, QSError> {
+    let mut summaries = ctx.wal.page_summaries()?;
+    summaries.sort_by_key(|summary| Reverse(summary.bytes));
+
+    let mut written = 0;
+    for summary in summaries {
+        if written >= max_leaves || ctx.clock.idle_for() < idle_after {
+            return Ok(written);
+        }
+        if flush_leaf(ctx, summary.page_id)? {
+            written += 1;
+        }
+    }
+
+    if ctx.wal.page_summaries()?.is_empty() && ctx.clock.idle_for() >= idle_after {
+        ctx.wal.checkpoint_txn_markers()?;
+    }
+    Ok(written)
+}
+
+/// Flush the mini-page of `page_id` and drop its WAL records, false if the leaf is locked or
+/// not cached
+fn flush_leaf(ctx: &MaintenanceCtx, page_id: PageId) -> Result<bool, QSError> {
+    let Ok(guard) = ctx.map_table.try_write_page_entry(page_id) else {
+        return Ok(false);
+    };
+    let index = match guard.node() {
+        NodeRef::MiniPage(index) => index,
+        NodeRef::Leaf(_) => return Ok(false),
+    };
+    // SAFETY: we hold the page's write lock
+    let meta = unsafe { ctx.cache.get_meta_mut(index) };
+    ctx.wal.sync()?;
+    flush_dirty_entries(meta, ctx.io_engine, ctx.wal.durable_lsn())?;
+    ctx.wal.checkpoint_page(page_id)?;
+    Ok(true)
+}
+
+/// Checkpoint up to `batch` leaves picked by `policy`, then trim the transaction markers if
+/// no leaf records are left. Returns how many leaves were flushed; `cursor` holds the last
+/// page flushed, where round-robin picks up
+pub(crate) fn checkpoint_pass(
+    ctx: &MaintenanceCtx,
+    policy: CheckpointPolicy,
+    batch: usize,
+    cursor: &AtomicU64,
+) -> Result<usize, QSError> {
+    let candidates = ctx
+        .wal
+        .page_summaries()?
+        .into_iter()
+        .map(|summary| CheckpointCandidate {
+            summary,
+            dirty_ratio: match policy {
+                CheckpointPolicy::DirtyRatio => dirty_ratio(ctx, summary.page_id),
+                _ => 0.0,
+            },
+        })
+        .collect();
+    let last = match cursor.load(Ordering::Relaxed) {
+        u64::MAX => None,
+        page => Some(PageId(page)),
+    };
+
+    let mut checkpointed = 0;
+    for page_id in policy.select(candidates, last, batch) {
+        if checkpoint_leaf(ctx, page_id)? {
+            cursor.store(page_id.0, Ordering::Relaxed);
+            checkpointed += 1;
+        }
+    }
+    if ctx.wal.page_summaries()?.is_empty() {
+        ctx.wal.checkpoint_txn_markers()?;
+    }
+    Ok(checkpointed)
+}
+
+fn dirty_ratio(ctx: &MaintenanceCtx, page_id: PageId) -> f64 {
+    match ctx.map_table.try_read_page_entry(page_id) {
+        Ok(guard) => match guard.node() {
+            // SAFETY: we hold the page's read lock
+            NodeRef::MiniPage(index) => unsafe { ctx.cache.get_meta_ref(index) }.dirty_ratio(),
+            NodeRef::Leaf(_) => 0.0,
+        },
+        Err(_) => 0.0,
+    }
+}
+
+/// Write the leaf's dirty records back and drop its WAL records, false if it is locked
+fn checkpoint_leaf(ctx: &MaintenanceCtx, page_id: PageId) -> Result<bool, QSError> {
+    let Ok(guard) = ctx.map_table.try_write_page_entry(page_id) else {
+        return Ok(false);
+    };
+    match guard.node() {
+        NodeRef::MiniPage(index) => {
+            // SAFETY: we hold the page's write lock
+            let meta = unsafe { ctx.cache.get_meta_mut(index) };
+            ctx.wal.sync()?;
+            flush_dirty_entries(meta, ctx.io_engine, ctx.wal.durable_lsn())?;
+        }
+        // an evicted leaf was written back when it left the buffer, but one recovery left
+        // to read repair is still behind its records
+        NodeRef::Leaf(addr) => ctx.io_engine.write_back_repair(addr)?,
+    }
+    ctx.wal.checkpoint_page(page_id)?;
+    Ok(true)
+}
@@ -1,16 +1,22 @@
 use std::{
     alloc::{alloc_zeroed, Layout},
-    f64::consts::E,
-    iter::Map,
-    marker::PhantomData,
-    ptr::{self, NonNull},
+    ptr::NonNull,
     sync::{
-        atomic::{AtomicU64, AtomicUsize, Ordering},
-        RwLock,
+        atomic::{fence, AtomicU64, AtomicUsize, Ordering},
+        Condvar, Mutex,
     },
+    time::{Duration, Instant},
 };
 
-use crate::{buffer::MiniPageIndex, error::QSError, types::NodeRef, SPIN_RETRIES};
+use crate::{
+    buffer::MiniPageIndex,
+    error::{CapacityLimit, QSError},
+    types::NodeRef,
+    SPIN_RETRIES,
+};
+
+/// Page ids are stored in the 48 bits above the free-space field of `NodeMeta`
+pub const MAX_PAGE_IDS: u64 = 1 << 48;
 
 ///Needs to be initialised with at least one
 pub struct MapTable {
@@ -18,10 +24,170 @@ pub struct MapTable {
     /// first node in the free list,  usize::MAX if none
     next_free: AtomicUsize,
     cap: usize,
+    /// How long page lock acquisition waits before failing with `PageLockFail`
+    lock_wait: LockWaitPolicy,
+    /// Where waiters on contended entries sleep until a release wakes them
+    parking: ParkingLot,
+}
+
+// SAFETY: entries are only accessed through atomics, the lock protocol on each entry
+// arbitrates access to what it points at
+unsafe impl Send for MapTable {}
+unsafe impl Sync for MapTable {}
+
+/// How a contended page lock is waited for: spin for `spins` attempts, then park until the
+/// entry changes or `timeout` has passed since the spinning stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockWaitPolicy {
+    pub spins: usize,
+    pub timeout: Duration,
+}
+
+impl LockWaitPolicy {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(50);
+
+    pub fn new(spins: usize, timeout: Duration) -> LockWaitPolicy {
+        LockWaitPolicy { spins, timeout }
+    }
+
+    /// Spin only, never park: for callers that have other work to try on failure
+    pub fn spin_only(spins: usize) -> LockWaitPolicy {
+        LockWaitPolicy::new(spins, Duration::ZERO)
+    }
+}
+
+impl Default for LockWaitPolicy {
+    fn default() -> Self {
+        LockWaitPolicy::new(SPIN_RETRIES, Self::DEFAULT_TIMEOUT)
+    }
+}
+
+const PARKING_SHARDS: usize = 64;
+
+/// Futex-style waiting on entries: a waiter sleeps only while the entry still blocks it, and
+/// every release that could unblock someone wakes the entry's shard
+struct ParkingLot {
+    shards: Box<[ParkShard]>,
+}
+
+#[derive(Default)]
+struct ParkShard {
+    /// Threads parked (or about to park) in this shard, lets releases skip the mutex
+    waiters: AtomicUsize,
+    lock: Mutex<()>,
+    cond: Condvar,
+}
+
+impl ParkingLot {
+    fn new() -> ParkingLot {
+        ParkingLot {
+            shards: (0..PARKING_SHARDS).map(|_| ParkShard::default()).collect(),
+        }
+    }
+
+    fn shard(&self, page: PageId) -> &ParkShard {
+        &self.shards[page.0 as usize % PARKING_SHARDS]
+    }
+
+    /// Sleep until woken or `deadline`, unless `entry_ref` has stopped `blocked`
+    fn park(
+        &self,
+        page: PageId,
+        entry_ref: &AtomicU64,
+        blocked: impl Fn(PageEntry) -> bool,
+        deadline: Instant,
+    ) {
+        let shard = self.shard(page);
+        let guard = shard.lock.lock().expect("parking mutex poisoned");
+        shard.waiters.fetch_add(1, Ordering::SeqCst);
+        // pairs with the fence in `unpark`: either the release is visible here or the
+        // releaser sees our waiter count and notifies after we are waiting
+        if blocked(PageEntry(entry_ref.load(Ordering::SeqCst))) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let _ = shard
+                .cond
+                .wait_timeout(guard, timeout)
+                .expect("parking mutex poisoned");
+        }
+        shard.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wake everyone parked in `page`'s shard, called after an entry change that may let
+    /// them make progress
+    fn unpark(&self, page: PageId) {
+        let shard = self.shard(page);
+        fence(Ordering::SeqCst);
+        if shard.waiters.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let _guard = shard.lock.lock().expect("parking mutex poisoned");
+        shard.cond.notify_all();
+    }
+
+    fn parked(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.waiters.load(Ordering::SeqCst))
+            .sum()
+    }
+}
+
+/// State of a single lock acquisition under a `LockWaitPolicy`
+struct LockWait<'a> {
+    policy: LockWaitPolicy,
+    map_table: &'a MapTable,
+    page: PageId,
+    attempts: usize,
+    deadline: Option<Instant>,
+}
+
+impl<'a> LockWait<'a> {
+    fn new(map_table: &'a MapTable, page: PageId, policy: LockWaitPolicy) -> LockWait<'a> {
+        LockWait {
+            policy,
+            map_table,
+            page,
+            attempts: 0,
+            deadline: None,
+        }
+    }
+
+    /// Whether to make another attempt, pausing first unless this is the first one. Parking
+    /// lasts while `blocked` holds for the entry, which is reloaded into `entry` afterwards
+    fn retry(&mut self, entry: &mut PageEntry, blocked: impl Fn(PageEntry) -> bool) -> bool {
+        self.attempts += 1;
+        if self.attempts == 1 {
+            return true;
+        }
+        if self.attempts <= self.policy.spins {
+            std::hint::spin_loop();
+            return true;
+        }
+
+        let now = Instant::now();
+        let deadline = *self.deadline.get_or_insert(now + self.policy.timeout);
+        if now >= deadline {
+            return false;
+        }
+        let entry_ref = self.map_table.get_ref(self.page);
+        self.map_table
+            .parking
+            .park(self.page, entry_ref, blocked, deadline);
+        *entry = PageEntry(entry_ref.load(Ordering::Acquire));
+        true
+    }
 }
 
 impl MapTable {
     pub fn new(leaf_upper_bound: u64) -> MapTable {
+        Self::with_lock_wait(leaf_upper_bound, LockWaitPolicy::default())
+    }
+
+    pub fn with_lock_wait(leaf_upper_bound: u64, lock_wait: LockWaitPolicy) -> MapTable {
+        assert!(
+            leaf_upper_bound <= MAX_PAGE_IDS,
+            "leaf_upper_bound exceeds the {MAX_PAGE_IDS} page ids the format can store"
+        );
         let layout = Layout::array::<u64>(leaf_upper_bound as usize).expect("todo");
 
         let ptr = unsafe { alloc_zeroed(layout) };
@@ -35,6 +201,8 @@ impl MapTable {
             indirection_arr: arr,
             next_free: AtomicUsize::new(0),
             cap: leaf_upper_bound as usize,
+            lock_wait,
+            parking: ParkingLot::new(),
         }
     }
 }
@@ -56,16 +224,67 @@ impl MapTable {
         PageId(0)
     }
 
+    /// Map the first `count` page ids to the disk leaves at the same addresses, the buckets
+    /// of a hash index
+    pub fn init_leaf_entries(&self, count: u64) {
+        assert!(
+            count > 0 && count <= self.cap as u64,
+            "{count} hash buckets do not fit the {} page ids of the mapping table",
+            self.cap
+        );
+        for page in 0..count {
+            let entry = PageEntry::leaf(page);
+            unsafe {
+                let ptr = self.indirection_arr.as_ptr().add(page as usize);
+                ptr.write(AtomicU64::new(entry.to_repr()));
+            }
+        }
+        self.next_free.store(count as usize, Ordering::Release);
+    }
+
+    /// Map `page` to the disk leaf at `disk_addr` while the tree is rebuilt on open, keeping
+    /// the ids handed out after it past `page`
+    pub fn install_leaf_entry(&self, page: PageId, disk_addr: u64) {
+        assert!(
+            (page.0 as usize) < self.cap,
+            "page {} is past the {} page ids of the mapping table",
+            page.0,
+            self.cap
+        );
+        let entry = PageEntry::leaf(disk_addr);
+        unsafe {
+            let ptr = self.indirection_arr.as_ptr().add(page.0 as usize);
+            ptr.write(AtomicU64::new(entry.to_repr()));
+        }
+        self.next_free
+            .fetch_max(page.0 as usize + 1, Ordering::AcqRel);
+    }
+
     pub fn capacity(&self) -> usize {
         self.cap
     }
 
-    pub fn create_page_entry(&self, node: MiniPageIndex) -> PageWriteGuard<'_> {
-        let target_idx = self.next_free.fetch_add(1, Ordering::AcqRel);
+    pub fn lock_wait(&self) -> LockWaitPolicy {
+        self.lock_wait
+    }
 
-        if target_idx >= self.cap {
-            todo!("handle excessive pages")
-        }
+    /// Number of threads currently parked on contended page locks
+    pub fn parked_waiters(&self) -> usize {
+        self.parking.parked()
+    }
+
+    /// Page ids handed out so far
+    pub fn pages_used(&self) -> u64 {
+        self.next_free.load(Ordering::Acquire).min(self.cap) as u64
+    }
+
+    pub fn create_page_entry(&self, node: MiniPageIndex) -> Result<PageWriteGuard<'_>, QSError> {
+        let target_idx = self
+            .next_free
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+                (next < self.cap).then_some(next + 1)
+            })
+            .map_err(|_| QSError::CapacityExceeded(CapacityLimit::PageIds(self.cap as u64)))?;
 
         let val = PageEntry::new_write_locked(node);
 
@@ -73,23 +292,60 @@ impl MapTable {
         unsafe {
             self.indirection_arr
                 .offset(target_idx as isize)
-                .write(AtomicU64::new(val.clone().to_repr()));
+                .write(AtomicU64::new(val.to_repr()));
         }
 
-        PageWriteGuard {
+        Ok(PageWriteGuard {
             map_table: self,
             page: PageId(target_idx as u64),
             node: val,
+        })
+    }
+
+    /// Hand out a page id mapped, unlocked, to the disk leaf at `disk_addr`. Nothing routes
+    /// to it until the caller links it into the tree
+    pub fn create_leaf_entry(&self, disk_addr: u64) -> Result<PageId, QSError> {
+        let target_idx = self
+            .next_free
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+                (next < self.cap).then_some(next + 1)
+            })
+            .map_err(|_| QSError::CapacityExceeded(CapacityLimit::PageIds(self.cap as u64)))?;
+
+        let entry = PageEntry::leaf(disk_addr);
+        // SAFETY: as in `create_page_entry`, the id is ours until it is returned
+        unsafe {
+            self.indirection_arr
+                .offset(target_idx as isize)
+                .write(AtomicU64::new(entry.to_repr()));
         }
+        Ok(PageId(target_idx as u64))
     }
 
     pub fn read_page_entry(&self, page: PageId) -> Result<PageReadGuard<'_>, QSError> {
+        self.read_page_entry_with(page, self.lock_wait)
+    }
+
+    /// Read lock `page` if it is free within a short spin, without parking
+    pub fn try_read_page_entry(&self, page: PageId) -> Result<PageReadGuard<'_>, QSError> {
+        self.read_page_entry_with(page, LockWaitPolicy::spin_only(SPIN_RETRIES))
+    }
+
+    fn read_page_entry_with(
+        &self,
+        page: PageId,
+        policy: LockWaitPolicy,
+    ) -> Result<PageReadGuard<'_>, QSError> {
         let entry_ref = self.get_ref(page);
         let mut entry = PageEntry::from_repr(entry_ref.load(Ordering::Acquire));
 
-        for _ in 0..SPIN_RETRIES {
+        let mut wait = LockWait::new(self, page, policy);
+        while wait.retry(&mut entry, |e| {
+            e.pending_write() || e.state() >= WRITE_LOCK_STATE
+        }) {
+            // a waiting writer goes first, new readers queue behind it
             if entry.pending_write() {
-                std::hint::spin_loop();
+                entry = PageEntry(entry_ref.load(Ordering::Acquire));
                 continue;
             }
 
@@ -97,12 +353,11 @@ impl MapTable {
 
             if lock_state >= WRITE_LOCK_STATE {
                 // Write lock is currently held
-                std::hint::spin_loop();
                 entry = PageEntry(entry_ref.load(Ordering::Acquire));
             } else {
                 // Reader locked or unlocked
 
-                let new = entry.clone().set_state(lock_state + 1);
+                let new = entry.set_state(lock_state + 1);
 
                 match entry_ref.compare_exchange_weak(
                     entry.to_repr(),
@@ -126,26 +381,71 @@ impl MapTable {
     }
 
     pub fn has_entry(&self, page: PageId) -> bool {
-        if page.0 as usize >= self.cap {
-            return false;
-        }
-        let entry_ref = self.get_ref(page);
-        entry_ref.load(Ordering::Acquire) != 0
+        // an unlocked mini-page in slot 0 encodes as 0, so go by the allocated id range
+        (page.0 as usize) < self.next_free.load(Ordering::Acquire).min(self.cap)
     }
 
     // TODO: refactor to take read lock and upgrade
     pub fn write_page_entry(&self, page: PageId) -> Result<PageWriteGuard<'_>, QSError> {
+        self.write_page_entry_with(page, self.lock_wait)
+    }
+
+    /// Write lock `page` if it is free within a short spin, without parking
+    pub fn try_write_page_entry(&self, page: PageId) -> Result<PageWriteGuard<'_>, QSError> {
+        self.write_page_entry_with(page, LockWaitPolicy::spin_only(SPIN_RETRIES))
+    }
+
+    /// Take over a write lock on `page` held by a guard that is never used or dropped again
+    ///
+    /// # Safety
+    /// `page` must be write locked, and the guard holding the lock must be forgotten: the
+    /// lock is released when the returned guard drops
+    pub unsafe fn adopt_write_lock(&self, page: PageId) -> PageWriteGuard<'_> {
+        let entry = PageEntry(self.get_ref(page).load(Ordering::Acquire));
+        debug_assert_eq!(
+            entry.state(),
+            WRITE_LOCK_STATE,
+            "adopted page is not write locked"
+        );
+        PageWriteGuard {
+            map_table: self,
+            page,
+            node: entry,
+        }
+    }
+
+    /// Take over one of the read locks on `page`, as `adopt_write_lock`
+    ///
+    /// # Safety
+    /// `page` must be read locked, and the guard holding the lock must be forgotten
+    pub unsafe fn adopt_read_lock(&self, page: PageId) -> PageReadGuard<'_> {
+        let entry = PageEntry(self.get_ref(page).load(Ordering::Acquire));
+        debug_assert!(
+            entry.state() != 0 && entry.state() != WRITE_LOCK_STATE,
+            "adopted page is not read locked"
+        );
+        PageReadGuard {
+            map_table: self,
+            page,
+            node: entry,
+        }
+    }
+
+    fn write_page_entry_with(
+        &self,
+        page: PageId,
+        policy: LockWaitPolicy,
+    ) -> Result<PageWriteGuard<'_>, QSError> {
         let entry_ref = self.get_ref(page);
         let mut entry = PageEntry(entry_ref.load(Ordering::Acquire));
+        let mut set_pending = false;
 
-        for _ in 0..SPIN_RETRIES {
+        let mut wait = LockWait::new(self, page, policy);
+        while wait.retry(&mut entry, |e| e.state() != 0) {
             let lock_state = entry.state();
             match lock_state {
                 0 => {
-                    let new = entry
-                        .clone()
-                        .set_state(WRITE_LOCK_STATE)
-                        .set_pending_write(false);
+                    let new = entry.set_state(WRITE_LOCK_STATE).set_pending_write(false);
                     match entry_ref.compare_exchange_weak(
                         entry.to_repr(),
                         new.to_repr(),
@@ -164,25 +464,33 @@ impl MapTable {
                 }
                 _ => {
                     if !entry.pending_write() {
-                        let new = entry.clone().set_pending_write(true);
-                        let ev = entry_ref
-                            .compare_exchange_weak(
-                                entry.to_repr(),
-                                new.to_repr(),
-                                Ordering::Relaxed,
-                                Ordering::Relaxed,
-                            )
-                            .unwrap_or_else(|e| e);
-                        entry = PageEntry(ev);
+                        let new = entry.set_pending_write(true);
+                        match entry_ref.compare_exchange_weak(
+                            entry.to_repr(),
+                            new.to_repr(),
+                            Ordering::Relaxed,
+                            Ordering::Relaxed,
+                        ) {
+                            Ok(_) => {
+                                set_pending = true;
+                                entry = new;
+                            }
+                            Err(e) => entry = PageEntry(e),
+                        }
                         continue;
                     }
 
-                    std::hint::spin_loop();
                     entry = PageEntry(entry_ref.load(Ordering::Relaxed));
                 }
             }
         }
 
+        // give up our claim so readers aren't blocked by a writer that is no longer waiting
+        if set_pending {
+            entry_ref.fetch_and(!PENDING_WRITE_BIT, Ordering::Release);
+            self.parking.unpark(page);
+        }
+
         Err(QSError::PageLockFail)
     }
 
@@ -193,7 +501,7 @@ impl MapTable {
 }
 
 /// An id of a leaf page, representing an index into the mapping table
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct PageId(pub(crate) u64);
 
 impl PageId {
@@ -219,20 +527,50 @@ impl<'a> PageReadGuard<'a> {
 }
 
 impl<'a> PageReadGuard<'a> {
+    /// Upgrade to a write lock if no other reader holds the page and no writer is waiting
+    /// for it, without waiting
+    pub fn try_upgrade(self) -> Result<PageWriteGuard<'a>, PageReadGuard<'a>> {
+        let entry_ref = self.map_table.get_ref(self.page);
+        let entry = PageEntry(entry_ref.load(Ordering::Relaxed));
+        if entry.state() != 1 || entry.pending_write() {
+            return Err(self);
+        }
+        let new = entry.set_state(WRITE_LOCK_STATE);
+        match entry_ref.compare_exchange(
+            entry.to_repr(),
+            new.to_repr(),
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => {
+                let write = PageWriteGuard {
+                    map_table: self.map_table,
+                    page: self.page,
+                    node: self.node,
+                };
+                std::mem::forget(self);
+                Ok(write)
+            }
+            Err(_) => Err(self),
+        }
+    }
+
     pub fn upgrade(self) -> Result<PageWriteGuard<'a>, (PageReadGuard<'a>, QSError)> {
         let map_table = self.map_table;
         let page = self.page;
-        let node = self.node.clone();
+        let node = self.node;
 
         std::mem::forget(self);
 
         let entry_ref = map_table.get_ref(page);
         let mut entry = PageEntry(entry_ref.load(Ordering::Relaxed));
-        for _ in 0..SPIN_RETRIES {
+        let mut set_pending = false;
+        let mut wait = LockWait::new(map_table, page, map_table.lock_wait);
+        while wait.retry(&mut entry, |e| e.state() != 1) {
             match entry.state() {
                 // 1 means that we're the only reader, so we can upgrade to writer
                 1 => {
-                    let new = entry.clone().set_state(WRITE_LOCK_STATE);
+                    let new = entry.set_state(WRITE_LOCK_STATE).set_pending_write(false);
                     // not weak because we don't want someone else to intercept
                     match entry_ref.compare_exchange(
                         entry.to_repr(),
@@ -250,15 +588,32 @@ impl<'a> PageReadGuard<'a> {
                         Err(e) => entry = PageEntry(e),
                     }
                 }
-                // TODO: set writer waiting bit
+                // stop new readers joining while we wait for the others to leave
+                _ if !entry.pending_write() => {
+                    let new = entry.set_pending_write(true);
+                    match entry_ref.compare_exchange_weak(
+                        entry.to_repr(),
+                        new.to_repr(),
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => {
+                            set_pending = true;
+                            entry = new;
+                        }
+                        Err(e) => entry = PageEntry(e),
+                    }
+                }
                 _ => {
-                    std::hint::spin_loop();
                     entry = PageEntry(entry_ref.load(Ordering::Relaxed));
                 }
             }
         }
 
-        // TODO: try to unset writer waiting bit
+        if set_pending {
+            entry_ref.fetch_and(!PENDING_WRITE_BIT, Ordering::Release);
+            map_table.parking.unpark(page);
+        }
 
         let original_guard = PageReadGuard {
             map_table,
@@ -277,7 +632,7 @@ impl<'a> Drop for PageReadGuard<'a> {
 
         loop {
             let old_state = entry.state();
-            let new = entry.clone().set_state(old_state - 1);
+            let new = entry.set_state(old_state - 1);
             match entry_ref.compare_exchange_weak(
                 entry.to_repr(),
                 new.to_repr(),
@@ -288,6 +643,10 @@ impl<'a> Drop for PageReadGuard<'a> {
                 Err(e) => entry = PageEntry(e),
             }
         }
+        // the last reader leaving lets a waiting writer in, the second to last a waiting upgrade
+        if entry.state() <= 2 {
+            self.map_table.parking.unpark(self.page);
+        }
     }
 }
 
@@ -310,7 +669,8 @@ impl<'a> PageWriteGuard<'a> {
     }
 
     pub fn set_leaf(&mut self, disk_addr: u64) {
-        let entry = PageEntry::leaf(disk_addr);
+        // keep the entry write locked, the guard still owns it
+        let entry = PageEntry::leaf(disk_addr).set_state(WRITE_LOCK_STATE);
         let entry_ref = self.map_table.get_ref(self.page);
         entry_ref.store(entry.to_repr(), Ordering::Release);
         self.node = entry;
@@ -322,7 +682,7 @@ impl<'a> PageWriteGuard<'a> {
 impl<'a> PageWriteGuard<'a> {
     /// Cache the given key and value, without doing any resizing
     /// This should not invalidate any existing slices into the Node
-    pub fn cache_no_alloc(&mut self, key: &[u8], value: &[u8]) {
+    pub fn cache_no_alloc(&mut self, _key: &[u8], _value: &[u8]) {
         todo!()
     }
 }
@@ -332,7 +692,7 @@ impl<'a> PageWriteGuard<'a> {
     pub fn downgrade(self) -> PageReadGuard<'a> {
         let map_table = self.map_table;
         let page = self.page;
-        let node = self.node.clone();
+        let node = self.node;
 
         std::mem::forget(self);
 
@@ -343,6 +703,7 @@ impl<'a> PageWriteGuard<'a> {
         // Blind write is fine because we had write lock
         // the only concurrent modification could be setting writer pending
         entry_ref.store(entry.to_repr(), Ordering::Release);
+        map_table.parking.unpark(page);
 
         PageReadGuard {
             map_table,
@@ -358,7 +719,7 @@ impl<'a> Drop for PageWriteGuard<'a> {
         let mut entry = PageEntry(entry_ref.load(Ordering::Relaxed));
 
         loop {
-            let new = entry.clone().set_state(0);
+            let new = entry.set_state(0);
             match entry_ref.compare_exchange_weak(
                 entry.to_repr(),
                 new.to_repr(),
@@ -369,17 +730,19 @@ impl<'a> Drop for PageWriteGuard<'a> {
                 Err(e) => entry = PageEntry(e),
             }
         }
+        self.map_table.parking.unpark(self.page);
     }
 }
 
 /// | address | is_leaf | write pending | lock state
 ///     48b      1b           1b            14b
-// TODO: option to wait on two 32bit parts using futex
+/// Waiters park on the whole entry through the map table's `ParkingLot`
 #[derive(Clone, Copy)]
 #[repr(transparent)]
 pub struct PageEntry(u64);
 
 const WRITE_LOCK_STATE: u16 = (1 << 14) - 1;
+const PENDING_WRITE_BIT: u64 = 1 << 14;
 const _: () = assert!(WRITE_LOCK_STATE.count_ones() == 14);
 
 impl PageEntry {
@@ -427,11 +790,11 @@ impl PageEntry {
     }
 
     fn pending_write(&self) -> bool {
-        ((self.0 >> 14) & 1) == 1
+        self.0 & PENDING_WRITE_BIT != 0
     }
 
     fn set_pending_write(mut self, new: bool) -> PageEntry {
-        self.0 &= !(1 << 14);
+        self.0 &= !PENDING_WRITE_BIT;
         self.0 |= (new as u64) << 14;
         self
     }
@@ -0,0 +1,233 @@
+//! Read-modify-write through a registered merge operator.
+//!
+//! `QuickStepTx::merge` stores its operand in the key's record rather than combining it with
+//! the current value there and then, so accumulator workloads skip the get + put round trip
+//! and the operator only runs once the value is needed. The record keeps the value the key
+//! had and the operands merged since, behind a header flagging it as pending, see `attrs`:
+//! `| has base: u8 | base len: u32 | base | operand len: u32 | operand | ...`. Reads fold the
+//! operands into the value they hand out, and flushes and WAL replay fold them into the
+//! leaf they write, unless that would make the record longer.
+//!
+//! The WAL's merge record carries the operand with the record as stored, so replay stays
+//! idempotent however often the leaf was flushed in between, and followers fold it through
+//! their own operator. A merge folds at once when the value is in an overflow chain or a
+//! blob file, when the record already holds `MAX_PENDING_OPERANDS` operands, when it would
+//! no longer fit its leaf, or when the key's value limit is tighter than the format's, so
+//! that limit is checked on the result.
+
+use std::{borrow::Cow, collections::BTreeSet};
+
+use crate::{
+    attrs::{self, RecordAttrs},
+    error::QSError,
+    io_engine::IoEngine,
+    overflow,
+};
+
+/// Operands a record holds before the next merge into it folds them
+pub const MAX_PENDING_OPERANDS: usize = 16;
+
+/// Combines a key's current value with a merge operand
+pub trait MergeOperator: Send + Sync {
+    /// The key's new value; `existing` is `None` for an absent or expired key
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8>;
+}
+
+impl<F> MergeOperator for F
+where
+    F: Fn(&[u8], Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync,
+{
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+        self(key, existing, operand)
+    }
+}
+
+/// Counters: values and operands are little-endian `u64`s that are added, wrapping on
+/// overflow. Shorter inputs are zero extended
+#[derive(Debug, Clone, Copy, Default)]
+pub struct U64AddMerge;
+
+impl U64AddMerge {
+    pub fn decode(bytes: &[u8]) -> u64 {
+        let mut buf = [0u8; 8];
+        let len = bytes.len().min(8);
+        buf[..len].copy_from_slice(&bytes[..len]);
+        u64::from_le_bytes(buf)
+    }
+}
+
+impl MergeOperator for U64AddMerge {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+        let current = existing.map_or(0, U64AddMerge::decode);
+        current
+            .wrapping_add(U64AddMerge::decode(operand))
+            .to_le_bytes()
+            .to_vec()
+    }
+}
+
+/// Sets of byte strings: values and operands are `encode`d sets, merged by union
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SetUnionMerge;
+
+impl SetUnionMerge {
+    /// Sorted, deduplicated `| len: u32 | member |` sequence
+    pub fn encode<'a, I>(members: I) -> Vec<u8>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let members: BTreeSet<&[u8]> = members.into_iter().collect();
+        let mut buf = Vec::new();
+        for member in members {
+            buf.extend_from_slice(&(member.len() as u32).to_le_bytes());
+            buf.extend_from_slice(member);
+        }
+        buf
+    }
+
+    /// Members of an encoded set, a truncated trailing member is dropped
+    pub fn decode(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut members = Vec::new();
+        let mut pos = 0;
+        while let Some(len) = bytes.get(pos..pos + 4) {
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            let Some(member) = bytes.get(pos + 4..pos + 4 + len) else {
+                break;
+            };
+            members.push(member.to_vec());
+            pos += 4 + len;
+        }
+        members
+    }
+}
+
+impl MergeOperator for SetUnionMerge {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+        let mut members = existing.map(SetUnionMerge::decode).unwrap_or_default();
+        members.extend(SetUnionMerge::decode(operand));
+        SetUnionMerge::encode(members.iter().map(Vec::as_slice))
+    }
+}
+
+/// A key's value and the operands merged into it since, as a pending record holds them
+struct Pending<'a> {
+    base: Option<&'a [u8]>,
+    operands: Vec<&'a [u8]>,
+}
+
+impl<'a> Pending<'a> {
+    fn decode(bytes: &'a [u8]) -> Option<Pending<'a>> {
+        let has_base = *bytes.first()?;
+        let base_len = u32::from_le_bytes(bytes.get(1..5)?.try_into().ok()?) as usize;
+        let base = bytes.get(5..5 + base_len)?;
+        let mut operands = Vec::new();
+        let mut pos = 5 + base_len;
+        while pos < bytes.len() {
+            let len = u32::from_le_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+            operands.push(bytes.get(pos + 4..pos + 4 + len)?);
+            pos += 4 + len;
+        }
+        Some(Pending {
+            base: (has_base != 0).then_some(base),
+            operands,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let base = self.base.unwrap_or_default();
+        let mut bytes = Vec::with_capacity(
+            5 + base.len() + self.operands.iter().map(|op| 4 + op.len()).sum::<usize>(),
+        );
+        bytes.push(self.base.is_some() as u8);
+        bytes.extend_from_slice(&(base.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(base);
+        for operand in &self.operands {
+            bytes.extend_from_slice(&(operand.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(operand);
+        }
+        bytes
+    }
+
+    fn fold(&self, operator: &dyn MergeOperator, key: &[u8]) -> Vec<u8> {
+        let mut value = self.base.map(<[u8]>::to_vec);
+        for operand in &self.operands {
+            value = Some(operator.merge(key, value.as_deref(), operand));
+        }
+        value.unwrap_or_default()
+    }
+}
+
+/// The operands a stored value holds, `None` if it is not a pending merge
+fn pending(stored: &[u8]) -> Option<Pending<'_>> {
+    let (attrs, value) = attrs::split(stored);
+    attrs
+        .pending_merge
+        .then(|| Pending::decode(value))
+        .flatten()
+}
+
+/// What a pending record holds once `operand` is merged into `current`, the key's stored
+/// value if a read would see it; `None` if the merge has to fold at once
+pub(crate) fn push_operand(current: Option<&[u8]>, operand: &[u8]) -> Option<Vec<u8>> {
+    let mut merged = match current {
+        None => Pending {
+            base: None,
+            operands: Vec::new(),
+        },
+        Some(stored) => match pending(stored) {
+            Some(merged) if merged.operands.len() >= MAX_PENDING_OPERANDS => return None,
+            Some(merged) => merged,
+            None if overflow::is_pointer(stored) => return None,
+            None => Pending {
+                base: Some(attrs::split(stored).1),
+                operands: Vec::new(),
+            },
+        },
+    };
+    merged.operands.push(operand);
+    Some(merged.encode())
+}
+
+/// The value `stored` stands for if it is a pending merge or a pointer, folded through the
+/// store's operator or read from its chain or blob file; `None` if `stored` holds the
+/// value itself
+pub fn fetch(io: &IoEngine, key: &[u8], stored: &[u8]) -> Option<Result<Vec<u8>, QSError>> {
+    match pending(stored) {
+        Some(merged) => Some(
+            io.merge_operator()
+                .map(|operator| merged.fold(operator, key))
+                .ok_or(QSError::NoMergeOperator),
+        ),
+        None => overflow::fetch(io, stored),
+    }
+}
+
+/// The value `stored` stands for, without its header, its pending operands folded and read
+/// from its chain or blob file if it is a pointer
+pub fn resolve<'v>(io: &IoEngine, key: &[u8], stored: &'v [u8]) -> Result<Cow<'v, [u8]>, QSError> {
+    match fetch(io, key, stored) {
+        Some(value) => value.map(Cow::Owned),
+        None => Ok(Cow::Borrowed(attrs::split(stored).1)),
+    }
+}
+
+/// A pending merge folded into a plain value behind the same expiry, for a flush or replay
+/// to write; `None` if `stored` is not one, there is no operator, or the folded record
+/// would be longer or need an overflow chain
+pub(crate) fn fold_stored(
+    operator: Option<&dyn MergeOperator>,
+    key: &[u8],
+    stored: &[u8],
+) -> Option<Vec<u8>> {
+    let merged = pending(stored)?;
+    let value = merged.fold(operator?, key);
+    let attrs = RecordAttrs {
+        expires_at: attrs::split(stored).0.expires_at,
+        ..RecordAttrs::default()
+    };
+    if overflow::needs_chain(&value, attrs::header_len(attrs, &value)) {
+        return None;
+    }
+    let folded = attrs::wrap(attrs, &value).into_owned();
+    (folded.len() <= stored.len()).then_some(folded)
+}
@@ -0,0 +1,233 @@
+//! The store's statistics as metrics, in the Prometheus text exposition format.
+//!
+//! `QuickStep::metrics` gathers the counters of `stats` with a few gauges of the store's
+//! current size into `MetricFamily`s, and `QuickStep::metrics_text` renders them for a
+//! scrape endpoint to serve as they are. Operation latencies are summaries in seconds, with
+//! the 0.5 and 0.99 quantiles, their sum and count, labelled by `op`.
+//!
+//! For push-based collection, `QuickStepConfig::with_metrics_exporter` registers a
+//! `MetricsExporter` that a background thread hands the metrics to at a fixed interval.
+//! The exporter runs on that thread, outside any lock, and may take its time: the next
+//! export waits for it.
+
+use std::fmt::Write as _;
+
+use crate::{
+    buffer::MiniPageBuffer,
+    error::QSError,
+    io_engine::IoEngine,
+    stats::{OpStats, Stats},
+    wal::WalManager,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Only ever grows while the store is open
+    Counter,
+    /// Goes up and down
+    Gauge,
+    /// Quantiles, sum and count of observations
+    Summary,
+}
+
+impl MetricKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Summary => "summary",
+        }
+    }
+}
+
+/// One value of a family, `name` carries the `_sum` or `_count` suffix of a summary
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricSample {
+    pub name: String,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+/// Samples sharing a name, help text and type
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+    pub samples: Vec<MetricSample>,
+}
+
+impl MetricFamily {
+    fn single(name: &'static str, help: &'static str, kind: MetricKind, value: f64) -> Self {
+        MetricFamily {
+            name,
+            help,
+            kind,
+            samples: vec![MetricSample {
+                name: name.to_string(),
+                labels: Vec::new(),
+                value,
+            }],
+        }
+    }
+}
+
+/// Handed the store's metrics by the exporter thread, see `QuickStepConfig::with_metrics_exporter`
+pub trait MetricsExporter: Send + Sync {
+    fn export(&self, metrics: &[MetricFamily]);
+}
+
+impl<F> MetricsExporter for F
+where
+    F: Fn(&[MetricFamily]) + Send + Sync,
+{
+    fn export(&self, metrics: &[MetricFamily]) {
+        self(metrics)
+    }
+}
+
+/// The metrics of a store, from its counters and parts
+pub(crate) fn collect(
+    stats: &Stats,
+    cache: &MiniPageBuffer,
+    io_engine: &IoEngine,
+    wal: &WalManager,
+) -> Result<Vec<MetricFamily>, QSError> {
+    use MetricKind::{Counter, Gauge};
+
+    let snapshot = stats.snapshot(cache, io_engine, wal);
+    let mut latency = MetricFamily {
+        name: "quickstep_op_latency_seconds",
+        help: "Time taken by successful gets, puts and deletes",
+        kind: MetricKind::Summary,
+        samples: Vec::new(),
+    };
+    for (op, op_stats) in [
+        ("get", snapshot.gets),
+        ("put", snapshot.puts),
+        ("delete", snapshot.deletes),
+    ] {
+        latency
+            .samples
+            .extend(summary_samples(latency.name, op, op_stats));
+    }
+    Ok(vec![
+        latency,
+        MetricFamily::single(
+            "quickstep_cache_hits_total",
+            "Gets answered from the leaf's mini-page",
+            Counter,
+            snapshot.cache_hits as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_cache_misses_total",
+            "Gets that read the disk leaf",
+            Counter,
+            snapshot.cache_misses as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_disk_reads_total",
+            "Page images read from the data file",
+            Counter,
+            snapshot.disk_reads as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_wal_written_bytes_total",
+            "Bytes written to the WAL, checkpoint rewrites included",
+            Counter,
+            snapshot.wal_bytes as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_checkpoints_total",
+            "Checkpoints that dropped leaf records from the WAL",
+            Counter,
+            snapshot.checkpoints as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_leaf_splits_total",
+            "Leaf splits",
+            Counter,
+            snapshot.splits as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_leaf_merges_total",
+            "Leaf merges",
+            Counter,
+            snapshot.merges as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_wal_bytes",
+            "Current size of the WAL",
+            Gauge,
+            wal.total_bytes()? as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_wal_records",
+            "Records currently in the WAL",
+            Gauge,
+            wal.total_records()? as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_disk_pages_used",
+            "Data pages handed out, free ones included",
+            Gauge,
+            io_engine.pages_used() as f64,
+        ),
+        MetricFamily::single(
+            "quickstep_disk_pages_free",
+            "Data pages freed by merges and waiting for reuse",
+            Gauge,
+            io_engine.free_pages() as f64,
+        ),
+    ])
+}
+
+fn summary_samples(name: &str, op: &str, stats: OpStats) -> Vec<MetricSample> {
+    let sample = |suffix: &str, quantile: Option<&str>, value: f64| {
+        let mut labels = vec![("op", op.to_string())];
+        if let Some(quantile) = quantile {
+            labels.push(("quantile", quantile.to_string()));
+        }
+        MetricSample {
+            name: format!("{name}{suffix}"),
+            labels,
+            value,
+        }
+    };
+    vec![
+        sample("", Some("0.5"), stats.p50.as_secs_f64()),
+        sample("", Some("0.99"), stats.p99.as_secs_f64()),
+        sample("_sum", None, stats.total.as_secs_f64()),
+        sample("_count", None, stats.count as f64),
+    ]
+}
+
+/// `metrics` in the Prometheus text exposition format
+pub fn to_text(metrics: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in metrics {
+        let _ = writeln!(out, "# HELP {} {}", family.name, escape_help(family.help));
+        let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind.as_str());
+        for sample in &family.samples {
+            out.push_str(&sample.name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{name}=\"{}\"", escape_label(value)))
+                    .collect();
+                let _ = write!(out, "{{{}}}", labels.join(","));
+            }
+            let _ = writeln!(out, " {}", sample.value);
+        }
+    }
+    out
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', r"\\").replace('\n', r"\n")
+}
+
+fn escape_label(value: &str) -> String {
+    escape_help(value).replace('"', "\\\"")
+}
@@ -0,0 +1,135 @@
+//! Resumable re-encoding of a key range.
+//!
+//! `QuickStep::migrate_range(lower, upper, f)` walks `lower <= key < upper` in batches. Each
+//! batch runs in its own transaction, re-reading every key under its lock and passing the
+//! current value to `f`: `Some((key, value))` replaces the entry, deleting the old key when
+//! the new one differs, and `None` leaves it as it is. Other writers only ever wait on the
+//! batch in flight, so the range stays online while it is rewritten.
+//!
+//! Each batch records the key to resume from in the same transaction, as a record in the
+//! reserved keyspace, see `reserved`, so a migration of the same range that was
+//! interrupted, by an error, a panic, a crash or a restart, carries on from the last committed batch
+//! and never runs it again. The last batch drops the record. A rewritten key that sorts
+//! further into the range is visited a second time, so `f` should return `None` for
+//! entries already in the new encoding. Like any put, a rewritten entry loses its expiry.
+//!
+//! The record of a range is keyed by a hash of its bounds and holds `| lower len: u32 |
+//! lower | upper len: u32 | upper | resume len: u32 | resume |`; a record for other bounds
+//! is ignored.
+
+use crate::{error::QSError, hash_index::hash_key, reserved, Store};
+
+/// Entries rewritten per transaction by `QuickStep::migrate_range`
+pub const DEFAULT_MIGRATION_BATCH: usize = 256;
+
+/// Name of the progress records in the reserved keyspace
+const SPACE: &[u8] = b"migrate/";
+
+/// What a migration did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    /// Entries passed to the migration function
+    pub scanned: u64,
+    /// Entries the function replaced
+    pub rewritten: u64,
+    /// Transactions committed
+    pub batches: u64,
+    /// Whether the migration carried on from an earlier, interrupted one
+    pub resumed: bool,
+}
+
+/// Rewrite `lower <= key < upper` through `f`, `batch` entries per transaction, resuming an
+/// interrupted migration of the same range
+pub(crate) fn run<F, K, V>(
+    db: &Store,
+    lower: &[u8],
+    upper: &[u8],
+    batch: usize,
+    mut f: F,
+) -> Result<MigrationStats, QSError>
+where
+    F: FnMut(&[u8], &[u8]) -> Option<(K, V)>,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    let batch = batch.max(1);
+    let mut stats = MigrationStats::default();
+    let progress = progress_key(lower, upper);
+    let recorded = db.get(&progress)?;
+    let mut resume = match recorded.as_deref().and_then(decode_entry) {
+        Some((from, to, resume)) if from == lower && to == upper => {
+            stats.resumed = true;
+            resume.to_vec()
+        }
+        _ => lower.to_vec(),
+    };
+    loop {
+        let keys = db
+            .range_iter(&resume, upper)
+            .take(batch)
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(last) = keys.last() else {
+            break;
+        };
+        let mut tx = db.tx();
+        for key in &keys {
+            let Some(value) = tx.get(key)?.map(<[u8]>::to_vec) else {
+                continue;
+            };
+            stats.scanned += 1;
+            let Some((new_key, new_value)) = f(key, &value) else {
+                continue;
+            };
+            if new_key.as_ref() != key.as_slice() {
+                tx.delete(key)?;
+            }
+            tx.put(new_key.as_ref(), new_value.as_ref())?;
+            stats.rewritten += 1;
+        }
+        // the smallest key after the batch's last one
+        resume = last.clone();
+        resume.push(0);
+        let done = keys.len() < batch;
+        match done {
+            true => tx.delete_reserved(&progress)?,
+            false => tx.put_reserved(&progress, &encode_entry(lower, upper, &resume))?,
+        }
+        tx.commit();
+        stats.batches += 1;
+        if done {
+            return Ok(stats);
+        }
+    }
+    // the range ran out right after a full batch, or before the first
+    let mut tx = db.tx();
+    tx.delete_reserved(&progress)?;
+    tx.commit();
+    Ok(stats)
+}
+
+/// Key of the progress record of the migration of `lower..upper`
+fn progress_key(lower: &[u8], upper: &[u8]) -> Vec<u8> {
+    let bounds = encode_entry(lower, upper, &[]);
+    reserved::key(SPACE, &hash_key(&bounds).to_be_bytes())
+}
+
+fn encode_entry(lower: &[u8], upper: &[u8], resume: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for part in [lower, upper, resume] {
+        bytes.extend_from_slice(&(part.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(part);
+    }
+    bytes
+}
+
+fn decode_entry(bytes: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let mut parts = [&bytes[..0]; 3];
+    let mut pos = 0;
+    for part in parts.iter_mut() {
+        let len = u32::from_le_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        *part = bytes.get(pos + 4..pos + 4 + len)?;
+        pos += 4 + len;
+    }
+    Some((parts[0], parts[1], parts[2]))
+}
@@ -69,6 +69,13 @@ impl NodeMeta {
         }
     }
 
+    /// The record for `key` if the node has one: its value, or `None` for a tombstone
+    pub fn find(&self, key: &[u8]) -> Option<Option<&[u8]>> {
+        let suffix = key.strip_prefix(self.get_node_prefix())?;
+        let kv = self.get_kv_meta(self.binary_search(suffix).ok()?);
+        Some(kv.typ().exists().then(|| self.get_val_from_meta(kv)))
+    }
+
     // TODO: refactor with suffix implementation
     pub fn try_put(&mut self, key: &[u8], val: &[u8]) -> Result<(), InsufficientSpace> {
         debug_assert!(
@@ -82,12 +89,80 @@ impl NodeMeta {
         self.try_put_with_suffix(key_suffix, val)
     }
 
+    /// Bytes the non-fence records take, their metadata included
+    pub fn record_bytes(&self) -> usize {
+        (0..self.record_count() as usize)
+            .map(|i| self.get_kv_meta(i))
+            .filter(|kv| !kv.fence())
+            .map(|kv| size_of::<KVMeta>() + (kv.key_size() + kv.val_size()) as usize)
+            .sum()
+    }
+
+    /// Bytes left between the record metadata and the records
+    pub fn free_bytes(&self) -> usize {
+        let meta_end = size_of::<NodeMeta>() + self.record_count() as usize * size_of::<KVMeta>();
+        self.find_min_offset().saturating_sub(meta_end)
+    }
+
+    /// `free_bytes` of a disk leaf less what stamping it takes, see `page_lsn`: the room a
+    /// mini-page caching it has for records, so they still fit once they are flushed
+    pub fn room_for_records(&self) -> usize {
+        let stamp = size_of::<u64>().saturating_sub(self.get_kv_meta(0).val_size() as usize);
+        self.free_bytes().saturating_sub(stamp)
+    }
+
+    /// Insert a clean copy of a record read from the disk leaf, it is never written back
+    pub fn try_put_cached(&mut self, key: &[u8], val: &[u8]) -> Result<(), InsufficientSpace> {
+        self.try_put(key, val)?;
+        let suffix = &key[self.get_node_prefix().len()..];
+        if let Ok(idx) = self.binary_search(suffix) {
+            let cached = self.get_kv_meta(idx).set_record_type(KVRecordType::Cache);
+            self.set_kv_meta(idx, cached);
+        }
+        Ok(())
+    }
+
+    /// Copy the records into `dst`, a node with the same fences, keeping their types so
+    /// dirty records stay dirty and tombstones keep hiding the disk records they cover
+    pub fn copy_records_into(&self, dst: &mut NodeMeta) -> Result<(), InsufficientSpace> {
+        for i in 0..self.record_count() as usize {
+            let kv = self.get_kv_meta(i);
+            if kv.fence() || kv.typ() == KVRecordType::Phantom {
+                continue;
+            }
+            let suffix = self.get_stored_key_from_meta(kv);
+            dst.try_put_with_suffix(suffix, self.get_val_from_meta(kv))?;
+            if let Ok(idx) = dst.binary_search(suffix) {
+                let copied = dst.get_kv_meta(idx).set_record_type(kv.typ());
+                dst.set_kv_meta(idx, copied);
+            }
+        }
+        Ok(())
+    }
+
     pub fn user_entry_count(&self) -> usize {
         self.entries()
             .filter(|entry| entry.meta.typ().exists())
             .count()
     }
 
+    /// Share of the non-fence records, tombstones included, not yet on the disk leaf
+    pub fn dirty_ratio(&self) -> f64 {
+        let (mut records, mut dirty) = (0usize, 0usize);
+        for i in 0..self.record_count() as usize {
+            let kv = self.get_kv_meta(i);
+            if kv.fence() {
+                continue;
+            }
+            records += 1;
+            dirty += kv.is_dirty() as usize;
+        }
+        if records == 0 {
+            return 0.0;
+        }
+        dirty as f64 / records as f64
+    }
+
     pub fn mark_tombstone(&mut self, key: &[u8]) -> bool {
         let prefix = self.get_node_prefix();
         if !key.starts_with(prefix) {
@@ -164,31 +239,36 @@ impl NodeMeta {
         match self.binary_search(key_suffix) {
             // Value already exists, so update with kv meta in place
             Ok(idx) => {
-                let mut target_kv = self.get_kv_meta(idx);
+                // the new value is dirty, whether it replaces a clean copy or a tombstone
+                let mut target_kv = self.get_kv_meta(idx).set_record_type(KVRecordType::Insert);
                 match target_kv.val_size() as usize == val.len() {
                     true => {
                         // Don't need to change layout, just rewrite
+                        self.set_kv_meta(idx, target_kv);
                         let val_slice = self.get_val_mut_from_meta(target_kv);
                         val_slice.copy_from_slice(val);
                     }
                     false => {
                         // different length: shift other entries, then rewrite
 
-                        let alloc_ptr = unsafe { self.erase_kv_in_buffer(target_kv) };
-
+                        let old_size = (target_kv.key_size() + target_kv.val_size()) as usize;
                         let new_size = key_suffix.len() + val.len();
-                        let new_offset = alloc_ptr - new_size;
+                        let meta_end = size_of::<NodeMeta>()
+                            + self.record_count() as usize * size_of::<KVMeta>();
 
-                        // Add 1 to account for Node meta
-                        let meta_end = (self.record_count() as usize + 1) * size_of::<KVMeta>();
+                        // checked before erasing, a put that fails must leave the record as
+                        // it was for the split or growth that follows
+                        let new_offset = (self.find_min_offset() + old_size)
+                            .checked_sub(new_size)
+                            .filter(|offset| *offset >= meta_end)
+                            .ok_or(InsufficientSpace)?;
 
-                        if new_offset < meta_end {
-                            return Err(InsufficientSpace);
-                        }
+                        let alloc_ptr = unsafe { self.erase_kv_in_buffer(target_kv) };
+                        debug_assert_eq!(alloc_ptr - new_size, new_offset);
 
                         // update metadata
-                        let _ = target_kv.set_offset(new_offset as u16);
-                        let _ = target_kv.set_val_size(val.iter().len() as u16);
+                        target_kv.set_offset(new_offset as u16);
+                        target_kv.set_val_size(val.iter().len() as u16);
                         target_kv = target_kv.set_ref_bit(true);
                         self.set_kv_meta(idx, target_kv);
 
@@ -205,8 +285,9 @@ impl NodeMeta {
                 let min_offset = self.find_min_offset();
                 let new_offset = min_offset.checked_sub(size).ok_or(InsufficientSpace)?;
 
-                // add 1 for NodeMeta and one for new KVMeta
-                let meta_end = (self.record_count() as usize + 2) * size_of::<KVMeta>();
+                // the header, the existing KVMetas and one for the new record
+                let meta_end = size_of::<NodeMeta>()
+                    + (self.record_count() as usize + 1) * size_of::<KVMeta>();
 
                 if new_offset < meta_end {
                     return Err(InsufficientSpace);
@@ -264,7 +345,7 @@ impl NodeMeta {
         let kv_ref = self.get_kv_meta_ref(kv_index);
         let mut out = KVMeta(kv_ref.load(Ordering::Relaxed));
         if out.ref_bit() {
-            let new = out.clone().set_ref_bit(true);
+            let new = out.set_ref_bit(true);
             match kv_ref.compare_exchange(out.0, new.0, Ordering::Relaxed, Ordering::Relaxed) {
                 Ok(_) => out = new,
                 Err(act) => {
@@ -301,8 +382,7 @@ impl NodeMeta {
             .take_while(|(a, b)| a == b)
             .count();
 
-        let prefix = &low_fence_key[0..prefix_len];
-        prefix
+        &low_fence_key[0..prefix_len]
     }
 
     #[inline]
@@ -377,8 +457,8 @@ impl NodeMeta {
             if cur_offset < target_offset {
                 min_offset = min_offset.min(cur_offset);
                 let new_offset = cur_offset + len;
-                let _ = kv.set_offset(new_offset as u16);
-                let _ = kv.set_offset(new_offset as u16);
+                kv.set_offset(new_offset as u16);
+                kv.set_offset(new_offset as u16);
                 self.set_kv_meta(i, kv);
             }
         }
@@ -391,7 +471,8 @@ impl NodeMeta {
 
         let dst_ptr = base_ptr.add(min_offset + len);
 
-        copy(src_ptr, dst_ptr, len);
+        // everything between the lowest record and the erased one slides up by `len`
+        copy(src_ptr, dst_ptr, target_offset - min_offset);
 
         min_offset + len
     }
@@ -402,7 +483,7 @@ impl NodeMeta {
         (0..self.record_count())
             .map(|i| self.get_kv_meta(i as usize).offset())
             .min()
-            .expect("There should always be at least 2 fence keys") as usize
+            .expect("There should always be at least 2 fence keys")
     }
 
     /// Gets the key, not including the prefix
@@ -417,7 +498,7 @@ impl NodeMeta {
     }
 
     #[inline]
-    pub fn get_key_mut_from_meta(&self, kv: KVMeta) -> &mut [u8] {
+    pub fn get_key_mut_from_meta(&mut self, kv: KVMeta) -> &mut [u8] {
         let base_ptr = self.get_base_ptr() as *mut u8;
 
         let offset = kv.offset() as isize;
@@ -453,6 +534,43 @@ impl NodeMeta {
         self as *const NodeMeta as *const u8
     }
 
+    /// Sequence number of the newest WAL record the leaf reflects, kept as the value of its
+    /// lower fence; 0 for a leaf never stamped, whose records replay must all apply
+    pub fn page_lsn(&self) -> u64 {
+        let lower = self.get_kv_meta(0);
+        match self.get_val_from_meta(lower).try_into() {
+            Ok(lsn) if lower.fence() => u64::from_le_bytes(lsn),
+            _ => 0,
+        }
+    }
+
+    /// Stamp the leaf with `lsn`, see `page_lsn`. Fails, leaving the leaf as it was, if
+    /// there is no room for it
+    pub fn set_page_lsn(&mut self, lsn: u64) -> Result<(), InsufficientSpace> {
+        let mut lower = self.get_kv_meta(0);
+        debug_assert!(lower.fence(), "leaf missing its lower fence");
+        let val = lsn.to_le_bytes();
+        if lower.val_size() as usize == val.len() {
+            self.get_val_mut_from_meta(lower).copy_from_slice(&val);
+            return Ok(());
+        }
+        // moved down with room for the value, as a put that grows a record is
+        let key = self.get_stored_key_from_meta(lower).to_vec();
+        let old_size = (lower.key_size() + lower.val_size()) as usize;
+        let meta_end = size_of::<NodeMeta>() + self.record_count() as usize * size_of::<KVMeta>();
+        let new_offset = (self.find_min_offset() + old_size)
+            .checked_sub(key.len() + val.len())
+            .filter(|offset| *offset >= meta_end)
+            .ok_or(InsufficientSpace)?;
+        unsafe { self.erase_kv_in_buffer(lower) };
+        lower.set_offset(new_offset as u16);
+        lower.set_val_size(val.len() as u16);
+        self.set_kv_meta(0, lower);
+        self.get_key_mut_from_meta(lower).copy_from_slice(&key);
+        self.get_val_mut_from_meta(lower).copy_from_slice(&val);
+        Ok(())
+    }
+
     pub fn fence_bounds(&self) -> (Vec<u8>, Vec<u8>) {
         let lower_meta = self.get_kv_meta(0);
         let upper_meta = self.get_kv_meta(self.record_count() as usize - 1);
@@ -485,11 +603,11 @@ impl NodeMeta {
         self.set_record_count(2);
 
         let mut lower_meta = KVMeta::new(lower.len(), 0, 0, KVRecordType::Cache, true, true, 0);
-        let _ = lower_meta.set_offset(lower_offset);
+        lower_meta.set_offset(lower_offset);
         self.set_kv_meta(0, lower_meta);
 
         let mut upper_meta = KVMeta::new(upper.len(), 0, 0, KVRecordType::Cache, true, true, 0);
-        let _ = upper_meta.set_offset(upper_offset);
+        upper_meta.set_offset(upper_offset);
         self.set_kv_meta(1, upper_meta);
     }
 }
@@ -497,7 +615,7 @@ impl NodeMeta {
 #[inline]
 fn get_lookahead(key_suffix: &[u8]) -> u16 {
     // allow default if key is the prefix (not sure if this is possible), or only 1 byte longer
-    let b0 = key_suffix.get(0).copied().unwrap_or_default();
+    let b0 = key_suffix.first().copied().unwrap_or_default();
     let b1 = key_suffix.get(1).copied().unwrap_or_default();
     u16::from_be_bytes([b0, b1])
 }
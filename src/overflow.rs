@@ -0,0 +1,174 @@
+//! Values too long for a leaf, stored in chains of overflow pages.
+//!
+//! A value longer than `limits::MAX_INLINE_VALUE_LEN` is written to disk pages of its own,
+//! each holding the address of the next, and its leaf record holds an `OverflowPointer` to
+//! the first: a marker, the head page's address, the value's length and its CRC-32. Flushes,
+//! splits, merges, evictions and the WAL carry the pointer like any other value; the reads
+//! that hand values out (`get`, `get_pinned`, scans, samples and merge operands) follow it.
+//! A chain is written and synced before the put pointing at it is logged, so recovery only
+//! replays pointers to pages already on disk.
+//!
+//! Leaf records have no spare bits to flag a pointer, so a stored value is one when it is
+//! `POINTER_LEN` bytes long and starts with the marker, once any `attrs` header in front of
+//! it is taken off. A value that happens to look like
+//! that, or like a `blob::BlobPointer`, is given a chain too, so every stored value reads
+//! back one way. The reads follow blob pointers through the same functions, see `blob`.
+//!
+//! The chain of a value a transaction overwrites or deletes is freed once the commit is
+//! synced. The chains a transaction wrote are freed once its abort marker is synced,
+//! including the rollback of one expired while idle. The next open frees those of a
+//! transaction cut short by a crash, which left no marker, once its puts are undone.
+//! Compaction moves leaves only, so an overflow page near the end of the file holds the
+//! file's length in place.
+
+use std::borrow::Cow;
+
+use crate::{
+    attrs,
+    blob::BlobPointer,
+    error::QSError,
+    io_engine::{DiskLeaf, IoEngine, PAGE_SIZE},
+    limits::MAX_INLINE_VALUE_LEN,
+    wal::WalOp,
+};
+
+/// Length of a stored pointer to an overflow chain
+pub const POINTER_LEN: usize = 28;
+
+/// Opens a stored pointer, unlikely at the start of a short value
+const POINTER_MARKER: [u8; 8] = *b"\xF5QSOVFL\x01";
+
+/// Marks a page as part of an overflow chain, after the empty leaf header that keeps leaf
+/// readers from seeing records in it
+const PAGE_MARKER: u64 = 0x4F56_464C_5153_0001;
+const NEXT_OFFSET: usize = 24;
+const LEN_OFFSET: usize = 32;
+const PAYLOAD_OFFSET: usize = 40;
+/// Value bytes each overflow page holds
+pub const PAGE_PAYLOAD: usize = PAGE_SIZE as usize - PAYLOAD_OFFSET;
+const NO_NEXT: u64 = u64::MAX;
+
+/// Where a value stored in overflow pages is, and how to tell it read back whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowPointer {
+    /// Disk address of the chain's first page
+    pub head: u64,
+    pub len: u64,
+    /// CRC-32 of the value
+    pub crc: u32,
+}
+
+impl OverflowPointer {
+    /// The pointer a stored value holds, behind its header if it has one, `None` for a
+    /// value stored in its record
+    pub fn decode(stored: &[u8]) -> Option<OverflowPointer> {
+        let stored = attrs::split(stored).1;
+        if stored.len() != POINTER_LEN || stored[..8] != POINTER_MARKER {
+            return None;
+        }
+        Some(OverflowPointer {
+            head: u64::from_le_bytes(stored[8..16].try_into().unwrap()),
+            len: u64::from_le_bytes(stored[16..24].try_into().unwrap()),
+            crc: u32::from_le_bytes(stored[24..28].try_into().unwrap()),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; POINTER_LEN] {
+        let mut stored = [0u8; POINTER_LEN];
+        stored[..8].copy_from_slice(&POINTER_MARKER);
+        stored[8..16].copy_from_slice(&self.head.to_le_bytes());
+        stored[16..24].copy_from_slice(&self.len.to_le_bytes());
+        stored[24..28].copy_from_slice(&self.crc.to_le_bytes());
+        stored
+    }
+
+    /// Pages the chain is made of
+    pub fn pages(&self) -> u64 {
+        (self.len as usize).div_ceil(PAGE_PAYLOAD) as u64
+    }
+}
+
+/// Whether `value` has to be stored in an overflow chain rather than its record, behind a
+/// header of `header_len` bytes, see `attrs`
+pub fn needs_chain(value: &[u8], header_len: usize) -> bool {
+    value.len() + header_len > MAX_INLINE_VALUE_LEN || is_pointer(value)
+}
+
+/// Whether a stored value points at an overflow chain or a blob file
+pub fn is_pointer(stored: &[u8]) -> bool {
+    OverflowPointer::decode(stored).is_some() || BlobPointer::decode(stored).is_some()
+}
+
+/// Length of the value a stored value stands for
+pub fn value_len(stored: &[u8]) -> usize {
+    if let Some(pointer) = OverflowPointer::decode(stored) {
+        return pointer.len as usize;
+    }
+    BlobPointer::decode(stored).map_or(attrs::split(stored).1.len(), |pointer| pointer.len as usize)
+}
+
+/// The value a stored pointer stands for, read from its chain or blob file; `None` if
+/// `stored` holds the value itself
+pub fn fetch(io: &IoEngine, stored: &[u8]) -> Option<Result<Vec<u8>, QSError>> {
+    if let Some(pointer) = OverflowPointer::decode(stored) {
+        return Some(io.read_overflow(pointer));
+    }
+    BlobPointer::decode(stored).map(|pointer| io.read_blob(pointer))
+}
+
+/// The value a stored value stands for, without its header and read from its chain or
+/// blob file if it is a pointer
+pub fn resolve<'v>(io: &IoEngine, stored: &'v [u8]) -> Result<Cow<'v, [u8]>, QSError> {
+    match fetch(io, stored) {
+        Some(value) => value.map(Cow::Owned),
+        None => Ok(Cow::Borrowed(attrs::split(stored).1)),
+    }
+}
+
+/// A stored value with the value its pointer stands for in place of the pointer, behind
+/// the same header, to write it again elsewhere; `None` if it is not a pointer
+pub fn fetch_stored(io: &IoEngine, stored: &[u8]) -> Option<Result<Vec<u8>, QSError>> {
+    let read = fetch(io, stored)?;
+    Some(read.map(|read| attrs::wrap(attrs::split(stored).0, &read).into_owned()))
+}
+
+/// `op` with the value it logs read back if that is a pointer, to write it again elsewhere
+pub fn resolve_op(io: &IoEngine, mut op: WalOp) -> Result<WalOp, QSError> {
+    if let WalOp::Put { value } | WalOp::Merge { value, .. } = &mut op {
+        if let Some(read) = fetch_stored(io, value) {
+            *value = read?;
+        }
+    }
+    Ok(op)
+}
+
+/// An overflow page holding `payload`, followed by the page at `next`
+pub(crate) fn write_page(next: Option<u64>, payload: &[u8]) -> DiskLeaf {
+    debug_assert!(payload.len() <= PAGE_PAYLOAD);
+    let mut page = DiskLeaf::zeroed();
+    let image = page.as_bytes_mut();
+    image[16..NEXT_OFFSET].copy_from_slice(&PAGE_MARKER.to_le_bytes());
+    image[NEXT_OFFSET..LEN_OFFSET].copy_from_slice(&next.unwrap_or(NO_NEXT).to_le_bytes());
+    image[LEN_OFFSET..LEN_OFFSET + 4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    image[PAYLOAD_OFFSET..PAYLOAD_OFFSET + payload.len()].copy_from_slice(payload);
+    page
+}
+
+/// The payload of an overflow page and the address of the page after it, `None` if the
+/// image is not an overflow page
+pub(crate) fn read_page(page: &DiskLeaf) -> Option<(Option<u64>, &[u8])> {
+    if !is_overflow_page(page) {
+        return None;
+    }
+    let image = page.as_bytes();
+    let next = u64::from_le_bytes(image[NEXT_OFFSET..LEN_OFFSET].try_into().unwrap());
+    let len = u32::from_le_bytes(image[LEN_OFFSET..LEN_OFFSET + 4].try_into().unwrap()) as usize;
+    let payload = image.get(PAYLOAD_OFFSET..PAYLOAD_OFFSET + len)?;
+    Some(((next != NO_NEXT).then_some(next), payload))
+}
+
+/// Whether a page image belongs to an overflow chain rather than a leaf
+pub fn is_overflow_page(page: &DiskLeaf) -> bool {
+    page.as_ref().record_count() == 0
+        && page.as_bytes()[16..NEXT_OFFSET] == PAGE_MARKER.to_le_bytes()
+}
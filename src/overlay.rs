@@ -0,0 +1,136 @@
+//! In-memory layers of writes over a store.
+//!
+//! `QuickStep::overlay` opens a layer that takes writes without touching the store: puts
+//! and deletes land in an in-memory map that reads consult before the store, a delete
+//! standing as a marker that hides the store's key. The layer can be dropped, leaving the
+//! store as it was, or merged down, writing all of it in one transaction so the store sees
+//! every change or none. Useful for dry runs of a migration against real data and for tests
+//! that must not change the store they read.
+//!
+//! Reads of keys the layer has not written go to the store as it is at the time, so they
+//! see writes committed after the layer was opened; the layer is not a snapshot.
+
+use std::{
+    cmp::Ordering,
+    collections::{btree_map, BTreeMap},
+    iter::Peekable,
+    ops::Bound,
+};
+
+use crate::{error::QSError, iter::RangeIter, Store};
+
+/// Writes held over a store until they are merged down or dropped
+pub struct Overlay<'db> {
+    db: &'db Store,
+    /// `None` marks a delete
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'db> Overlay<'db> {
+    pub(crate) fn new(db: &'db Store) -> Overlay<'db> {
+        Overlay {
+            db,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// The layer's value for `key` if it wrote one, else the store's
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, QSError> {
+        match self.writes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.db.get(key),
+        }
+    }
+
+    /// Set `key` to `value` in the layer, held to the store's size limits
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), QSError> {
+        let limits = self.db.size_limits(key);
+        limits.check_key(key)?;
+        limits.check_value(value)?;
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        Ok(())
+    }
+
+    /// Hide `key` in the layer, returns whether it was visible
+    pub fn delete(&mut self, key: &[u8]) -> Result<bool, QSError> {
+        let existed = self.get(key)?.is_some();
+        self.writes.insert(key.to_vec(), None);
+        Ok(existed)
+    }
+
+    /// Pairs with `lower <= key < upper` in key order, the layer's writes over the store's
+    pub fn range_iter(&self, lower: &[u8], upper: &[u8]) -> OverlayIter<'_> {
+        let bounds = (
+            Bound::Included(lower.to_vec()),
+            Bound::Excluded(upper.to_vec()),
+        );
+        OverlayIter {
+            base: self.db.range_iter(lower, upper).peekable(),
+            writes: self.writes.range(bounds).peekable(),
+        }
+    }
+
+    /// Keys the layer has written or deleted
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Write the layer to the store in one transaction and empty it. If the transaction
+    /// fails, nothing reaches the store and the layer keeps its writes
+    pub fn merge_down(&mut self) -> Result<(), QSError> {
+        let mut tx = self.db.tx();
+        for (key, value) in self.writes.iter() {
+            let written = match value {
+                Some(value) => tx.put(key, value),
+                None => tx.delete(key).map(|_| ()),
+            };
+            if let Err(err) = written {
+                tx.abort();
+                return Err(err);
+            }
+        }
+        tx.commit();
+        self.writes.clear();
+        Ok(())
+    }
+
+    /// Drop the layer's writes, the store never sees them
+    pub fn discard(self) {}
+}
+
+/// Iterator of `Overlay::range_iter`
+pub struct OverlayIter<'o> {
+    base: Peekable<RangeIter<'o>>,
+    writes: Peekable<btree_map::Range<'o, Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl Iterator for OverlayIter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>), QSError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.base.peek(), self.writes.peek()) {
+                (Some(Err(_)), _) | (Some(Ok(_)), None) => return self.base.next(),
+                (None, None) => return None,
+                (None, Some(_)) => Ordering::Less,
+                (Some(Ok((base_key, _))), Some((key, _))) => key.as_slice().cmp(base_key),
+            };
+            match order {
+                Ordering::Greater => return self.base.next(),
+                // the layer's write shadows the store's pair
+                Ordering::Equal => {
+                    self.base.next();
+                }
+                Ordering::Less => {}
+            }
+            let (key, value) = self.writes.next()?;
+            if let Some(value) = value {
+                return Some(Ok((key.clone(), value.clone())));
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Where the compressed blocks of a compressed data file live.
+//!
+//! With page compression on, a leaf is still a 4K page in memory and is addressed by the
+//! same disk address, but the data file stores it as a variable-length compressed block.
+//! The page map is the indirection from address to block: a sidecar log next to the data
+//! file of `| addr: u64 | offset: u64 | len: u32 | cap: u32 | crc: u32 |` entries, the last
+//! entry for an address winning, rewritten once overridden entries outnumber live ones.
+//!
+//! A rewritten page goes back into its block when it fits the block's capacity and is
+//! appended past the last block otherwise; the space it leaves behind is not reused. The
+//! block is written before its entry, so a crash in between leaves the old block in place.
+
+use std::{collections::HashMap, io, path::Path, sync::Mutex};
+
+use crate::{sidecar::SidecarLog, utils::crc32};
+
+const ENTRY_LEN: usize = 28;
+/// Block capacities are rounded up to this, so a page that grows a little keeps its block
+const BLOCK_ALIGN: u64 = 64;
+/// Never rewrite the map for fewer overridden entries than this
+const MIN_COMPACT_GARBAGE: usize = 256;
+
+/// A page's block, `offset` counted from the start of the block area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockSlot {
+    pub offset: u64,
+    pub len: u32,
+    /// Bytes reserved for the block, it may be rewritten in place up to this
+    pub cap: u32,
+}
+
+pub struct PageMap {
+    state: Mutex<PageMapState>,
+}
+
+struct PageMapState {
+    log: SidecarLog,
+    slots: HashMap<u64, BlockSlot>,
+    /// End of the last block
+    end: u64,
+    /// Entries in the file that a later entry overrides
+    garbage: usize,
+}
+
+impl PageMap {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<PageMap> {
+        Self::with_log(SidecarLog::open(path.as_ref())?)
+    }
+
+    /// Keep the map in memory instead of a file, it is lost once the map is dropped
+    pub fn in_memory() -> io::Result<PageMap> {
+        Self::with_log(SidecarLog::in_memory())
+    }
+
+    fn with_log(mut log: SidecarLog) -> io::Result<PageMap> {
+        let mut entries = Vec::new();
+        log.replay(|bytes, pos| {
+            entries.push(decode_entry(bytes, pos)?);
+            Some(pos + ENTRY_LEN)
+        })?;
+        let mut state = PageMapState {
+            log,
+            slots: HashMap::new(),
+            end: 0,
+            garbage: 0,
+        };
+        for (addr, slot) in entries {
+            state.apply(addr, slot);
+        }
+        Ok(PageMap {
+            state: Mutex::new(state),
+        })
+    }
+
+    pub fn get(&self, addr: u64) -> Option<BlockSlot> {
+        let state = self.state.lock().expect("page map mutex poisoned");
+        state.slots.get(&addr).copied()
+    }
+
+    /// Where to write a `len` byte block for `addr`: its current block if it fits, else
+    /// fresh space past the last block. Record it with `commit` once written
+    pub fn place(&self, addr: u64, len: u32) -> BlockSlot {
+        let mut state = self.state.lock().expect("page map mutex poisoned");
+        match state.slots.get(&addr) {
+            Some(slot) if slot.cap >= len => BlockSlot { len, ..*slot },
+            _ => {
+                let cap = (len as u64).div_ceil(BLOCK_ALIGN) * BLOCK_ALIGN;
+                let offset = state.end;
+                state.end += cap;
+                BlockSlot {
+                    offset,
+                    len,
+                    cap: cap as u32,
+                }
+            }
+        }
+    }
+
+    /// Point `addr` at the block written to `slot`
+    pub fn commit(&self, addr: u64, slot: BlockSlot) -> io::Result<()> {
+        let mut state = self.state.lock().expect("page map mutex poisoned");
+        state.log.append(&encode_entry(addr, slot))?;
+        state.apply(addr, slot);
+        state.maybe_compact()
+    }
+
+    /// Wait for the entries committed so far to reach the disk, along with the blocks the
+    /// data file sync they go with wrote
+    pub fn sync(&self) -> io::Result<()> {
+        self.state
+            .lock()
+            .expect("page map mutex poisoned")
+            .log
+            .sync()
+    }
+
+    /// One past the highest address with a block, 0 for none
+    pub fn addr_end(&self) -> u64 {
+        let state = self.state.lock().expect("page map mutex poisoned");
+        state.slots.keys().max().map_or(0, |addr| addr + 1)
+    }
+
+    /// Bytes the blocks take up, capacity and abandoned blocks included
+    pub fn block_bytes(&self) -> u64 {
+        self.state.lock().expect("page map mutex poisoned").end
+    }
+
+    /// Bytes of compressed page images currently mapped
+    pub fn stored_bytes(&self) -> u64 {
+        let state = self.state.lock().expect("page map mutex poisoned");
+        state.slots.values().map(|slot| slot.len as u64).sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .expect("page map mutex poisoned")
+            .slots
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl PageMapState {
+    fn apply(&mut self, addr: u64, slot: BlockSlot) {
+        self.end = self.end.max(slot.offset + slot.cap as u64);
+        if self.slots.insert(addr, slot).is_some() {
+            self.garbage += 1;
+        }
+    }
+
+    fn maybe_compact(&mut self) -> io::Result<()> {
+        if self.garbage < MIN_COMPACT_GARBAGE.max(self.slots.len()) {
+            return Ok(());
+        }
+        let mut bytes = Vec::with_capacity(self.slots.len() * ENTRY_LEN);
+        for (addr, slot) in &self.slots {
+            bytes.extend_from_slice(&encode_entry(*addr, *slot));
+        }
+        self.log.rewrite(&bytes)?;
+        self.garbage = 0;
+        Ok(())
+    }
+}
+
+fn encode_entry(addr: u64, slot: BlockSlot) -> [u8; ENTRY_LEN] {
+    let mut buf = [0u8; ENTRY_LEN];
+    buf[0..8].copy_from_slice(&addr.to_le_bytes());
+    buf[8..16].copy_from_slice(&slot.offset.to_le_bytes());
+    buf[16..20].copy_from_slice(&slot.len.to_le_bytes());
+    buf[20..24].copy_from_slice(&slot.cap.to_le_bytes());
+    let crc = crc32(&buf[..24]);
+    buf[24..28].copy_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+fn decode_entry(bytes: &[u8], pos: usize) -> Option<(u64, BlockSlot)> {
+    let entry = bytes.get(pos..pos + ENTRY_LEN)?;
+    if crc32(&entry[..24]).to_le_bytes() != entry[24..28] {
+        return None;
+    }
+    let addr = u64::from_le_bytes(entry[0..8].try_into().ok()?);
+    let slot = BlockSlot {
+        offset: u64::from_le_bytes(entry[8..16].try_into().ok()?),
+        len: u32::from_le_bytes(entry[16..20].try_into().ok()?),
+        cap: u32::from_le_bytes(entry[20..24].try_into().ok()?),
+    };
+    Some((addr, slot))
+}
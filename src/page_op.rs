@@ -1,9 +1,89 @@
-use crate::buffer::MiniPageBuffer;
+use std::collections::BTreeMap;
+
+use crate::attrs;
+use crate::blob::BlobPointer;
+use crate::buffer::{MiniPageBuffer, MiniPageIndex};
+use crate::debug;
 use crate::error::QSError;
 use crate::io_engine::{DiskLeaf, IoEngine};
 use crate::lock_manager::{GuardWrapper, PageGuard, WriteGuardWrapper};
+use crate::map_table::PageId;
+use crate::merge;
 use crate::node::InsufficientSpace;
-use crate::types::{LeafEntry, NodeMeta, NodeRef};
+use crate::overflow::{self, OverflowPointer};
+use crate::rand::rand_for_cache;
+use crate::types::{KVMeta, KVRecordType, LeafEntry, NodeMeta, NodeRef, NodeSize};
+use crate::value::{EntryInfo, PinnedValue};
+use crate::wal::WalManager;
+use crate::SMALL_MINI_PAGE_MIN_LEAVES;
+
+/// Share of a leaf's entries kept on the left by default when an insert past its last key
+/// splits it
+pub const SEQUENTIAL_SPLIT_PERCENT: u8 = 90;
+
+/// Where a split divides a leaf, unless the insert splitting it is an append
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitPoint {
+    /// As many entries on each side
+    #[default]
+    EvenCount,
+    /// As close to half the leaf's key and value bytes on each side as whole entries allow,
+    /// for leaves whose values vary in length
+    EvenBytes,
+    /// `left_percent` of the entries on the left, between 1 and 99
+    Ratio { left_percent: u8 },
+}
+
+/// How a full leaf is split, see `QuickStepConfig::with_split_point`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitPolicy {
+    pub point: SplitPoint,
+    /// Share of the entries kept on the left when the insert splitting the leaf sorts after
+    /// every key in it, between 1 and 99; `None` splits appends at `point` too
+    pub append_left_percent: Option<u8>,
+}
+
+impl Default for SplitPolicy {
+    fn default() -> SplitPolicy {
+        SplitPolicy {
+            point: SplitPoint::default(),
+            append_left_percent: Some(SEQUENTIAL_SPLIT_PERCENT),
+        }
+    }
+}
+
+impl SplitPolicy {
+    /// Index of the first entry of the right half, given the stored length of every entry
+    /// as it will be once the pending one lands
+    fn split_index(&self, lens: &[usize], appending: bool) -> usize {
+        let total = lens.len();
+        // at least one entry on each side when there are two
+        let by_share = |percent: u8| {
+            (total * percent as usize / 100)
+                .max(1)
+                .min(total.saturating_sub(1))
+        };
+        match (self.append_left_percent, self.point) {
+            (Some(percent), _) if appending => by_share(percent),
+            (_, SplitPoint::EvenCount) => total / 2,
+            (_, SplitPoint::Ratio { left_percent }) => by_share(left_percent),
+            (_, SplitPoint::EvenBytes) => {
+                let half = lens.iter().sum::<usize>() / 2;
+                let mut left = 0;
+                let mut index = 0;
+                for len in lens {
+                    // stop at the cut nearer the middle
+                    if left + len / 2 >= half {
+                        break;
+                    }
+                    left += len;
+                    index += 1;
+                }
+                index.max(1).min(total.saturating_sub(1))
+            }
+        }
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -14,6 +94,9 @@ pub struct LeafSplitPlan {
     pub right_entries: Vec<LeafEntryOwned>,
     pub lower_fence: Vec<u8>,
     pub upper_fence: Vec<u8>,
+    /// the key whose insert triggered the split is new and lands on the left/right half
+    pub pending_left: bool,
+    pub pending_right: bool,
 }
 
 #[allow(dead_code)]
@@ -37,7 +120,16 @@ impl LeafEntryOwned {
 
 #[allow(dead_code)]
 impl LeafSplitPlan {
-    pub fn from_node(meta: &NodeMeta) -> LeafSplitPlan {
+    /// Plan a split of `meta` for the insert of `pending_key` with a stored value of
+    /// `pending_len` bytes, which triggered it. The pending entry is counted when picking the
+    /// split point so both halves have room for the follow-up insert, and it is included in
+    /// the reported counts. `policy` picks the split point, see `SplitPolicy`.
+    pub fn from_node(
+        meta: &NodeMeta,
+        pending_key: &[u8],
+        pending_len: usize,
+        policy: &SplitPolicy,
+    ) -> LeafSplitPlan {
         let prefix = meta.get_node_prefix();
         let mut prefix_buf = Vec::with_capacity(prefix.len());
         prefix_buf.extend_from_slice(prefix);
@@ -45,10 +137,11 @@ impl LeafSplitPlan {
         let mut live_entries = Vec::new();
 
         for entry in meta.entries() {
-            if entry.meta.fence() {
+            // tombstones are dropped: both halves are rewritten as complete leaf images
+            if entry.meta.fence() || !entry.meta.typ().exists() {
                 continue;
             }
-            live_entries.push(entry);
+            live_entries.push(LeafEntryOwned::from_entry(prefix, &entry));
         }
 
         assert!(
@@ -56,22 +149,42 @@ impl LeafSplitPlan {
             "Leaf must contain at least one non-fence entry for a split"
         );
 
-        let move_start = live_entries.len() / 2;
-        let pivot_entry = &live_entries[move_start];
-
-        let mut pivot_key = Vec::with_capacity(prefix.len() + pivot_entry.key_suffix.len());
-        pivot_key.extend_from_slice(prefix);
-        pivot_key.extend_from_slice(pivot_entry.key_suffix);
+        let pending_pos = live_entries.partition_point(|entry| entry.key.as_slice() < pending_key);
+        let pending_is_new = live_entries
+            .get(pending_pos)
+            .is_none_or(|entry| entry.key.as_slice() != pending_key);
 
-        let left_entries = live_entries[..move_start]
+        // split point over the entries as they will be once the pending key lands. A key
+        // past every other in the leaf is taken for an append of ascending keys, whose later
+        // inserts all go right: the left half is kept fuller rather than sit half empty
+        let appending = pending_is_new && pending_pos == live_entries.len();
+        let mut lens: Vec<usize> = live_entries
             .iter()
-            .map(|entry| LeafEntryOwned::from_entry(prefix, entry))
+            .map(|entry| entry.key.len() + entry.value.len())
             .collect();
+        match pending_is_new {
+            true => lens.insert(pending_pos, pending_key.len() + pending_len),
+            false => lens[pending_pos] = pending_key.len() + pending_len,
+        }
+        let mid = policy.split_index(&lens, appending);
+        let pending_left = pending_is_new && pending_pos < mid;
+        let pending_right = pending_is_new && !pending_left;
+        let move_start = mid - pending_left as usize;
+
+        // the pivot is the shortest key above the left half's last and at most the right
+        // half's first: inner nodes keep only as much of long keys as tells the halves apart
+        let key_at = |index: usize| match pending_is_new {
+            true if index == pending_pos => pending_key,
+            true if index > pending_pos => live_entries[index - 1].key.as_slice(),
+            _ => live_entries[index].key.as_slice(),
+        };
+        let pivot_key = match mid {
+            0 => key_at(0).to_vec(),
+            _ => separator(key_at(mid - 1), key_at(mid)),
+        };
 
-        let right_entries = live_entries[move_start..]
-            .iter()
-            .map(|entry| LeafEntryOwned::from_entry(prefix, entry))
-            .collect();
+        let right_entries = live_entries.split_off(move_start);
+        let left_entries = live_entries;
 
         let (lower_fence, upper_fence) = meta.fence_bounds();
 
@@ -82,6 +195,8 @@ impl LeafSplitPlan {
             right_entries,
             lower_fence,
             upper_fence,
+            pending_left,
+            pending_right,
         }
     }
 
@@ -106,12 +221,18 @@ impl LeafSplitPlan {
 
         Ok(LeafSplitOutcome {
             pivot_key: self.pivot_key.clone(),
-            left_count: self.left_entries.len(),
-            right_count: self.right_entries.len(),
+            left_count: self.left_entries.len() + self.pending_left as usize,
+            right_count: self.right_entries.len() + self.pending_right as usize,
         })
     }
 }
 
+/// The shortest key above `last` and at most `next`, keeping pivots and fences short
+pub(crate) fn separator(last: &[u8], next: &[u8]) -> Vec<u8> {
+    let common = last.iter().zip(next).take_while(|(a, b)| a == b).count();
+    next[..(common + 1).min(next.len())].to_vec()
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct LeafSplitOutcome {
@@ -152,6 +273,23 @@ impl LeafMergePlan {
         }
     }
 
+    /// Whether the merged records fit one leaf, stamp included. Keys are counted whole, a
+    /// prefix the merged leaf shares only leaves more room
+    pub fn fits(&self) -> bool {
+        let fences = 2 * size_of::<KVMeta>()
+            + self.survivor_lower.len()
+            + self.survivor_upper.len()
+            + size_of::<u64>();
+        let records: usize = self
+            .entries
+            .iter()
+            .map(|entry| size_of::<KVMeta>() + entry.key.len() + entry.value.len())
+            .sum();
+        size_of::<NodeMeta>() + fences + records <= NodeSize::LeafPage.size_in_bytes()
+    }
+
+    /// Rebuild `survivor` with the merged records; check `fits` first, a merge that runs out
+    /// of room leaves the survivor with only part of them
     pub fn apply(
         &self,
         survivor: &mut NodeMeta,
@@ -171,10 +309,58 @@ impl LeafMergePlan {
     }
 }
 
-pub fn flush_dirty_entries(node_meta: &mut NodeMeta, io_engine: &IoEngine) {
-    let mut disk_leaf: Option<DiskLeaf> = None;
+/// Write the dirty records back to the disk leaf, stamped with `page_lsn`: the WAL must be
+/// synced through every record of the page, and `page_lsn` be at least the newest of them,
+/// see `NodeMeta::page_lsn`
+pub fn flush_dirty_entries(
+    node_meta: &mut NodeMeta,
+    io_engine: &IoEngine,
+    page_lsn: u64,
+) -> Result<(), QSError> {
+    flush_entries(node_meta, io_engine, page_lsn, false)
+}
+
+/// Write a full-size mini-page to its disk slot whether or not it has changed since it was
+/// read, as a split does with both halves before logging it
+pub fn write_full_image(
+    node_meta: &mut NodeMeta,
+    io_engine: &IoEngine,
+    page_lsn: u64,
+) -> Result<(), QSError> {
+    debug_assert!(matches!(node_meta.size(), NodeSize::LeafPage));
+    flush_entries(node_meta, io_engine, page_lsn, true)
+}
+
+fn flush_entries(
+    node_meta: &mut NodeMeta,
+    io_engine: &IoEngine,
+    page_lsn: u64,
+    force: bool,
+) -> Result<(), QSError> {
     let leaf_addr = node_meta.leaf();
+    let prefix = node_meta.get_node_prefix().to_vec();
+    let (lower, upper) = node_meta.fence_bounds();
     let mut tombstones = Vec::new();
+    let mut dirty = false;
+
+    // a full-size mini-page is a complete image of the leaf, smaller ones only cache part of
+    // it, so merge with the disk records that still fall inside the (possibly split) fences.
+    // A full image may belong to a freshly split page whose disk slot was never written.
+    let full_image = matches!(node_meta.size(), NodeSize::LeafPage);
+    let mut disk_leaf = match full_image {
+        true => DiskLeaf::zeroed(),
+        false => io_engine.get_page(leaf_addr)?,
+    };
+    let mut records: BTreeMap<Vec<u8>, Vec<u8>> =
+        match !full_image && disk_leaf.as_ref().record_count() >= 2 {
+            true => leaf_records(disk_leaf.as_ref())
+                .into_iter()
+                .filter(|(key, _)| {
+                    key.as_slice() >= lower.as_slice() && key.as_slice() < upper.as_slice()
+                })
+                .collect(),
+            false => BTreeMap::new(),
+        };
 
     let cnt = node_meta.record_count() as usize;
     for i in 0..cnt {
@@ -184,108 +370,324 @@ pub fn flush_dirty_entries(node_meta: &mut NodeMeta, io_engine: &IoEngine) {
             continue;
         }
 
+        let suffix = node_meta.get_stored_key_from_meta(kv);
+        let mut key = Vec::with_capacity(prefix.len() + suffix.len());
+        key.extend_from_slice(&prefix);
+        key.extend_from_slice(suffix);
+
         match kv.typ() {
             crate::types::KVRecordType::Tombstone => {
-                let entry = disk_leaf.get_or_insert_with(|| io_engine.get_page(leaf_addr));
-                let prefix = node_meta.get_node_prefix();
-                let suffix = node_meta.get_stored_key_from_meta(kv);
-                let mut key = Vec::with_capacity(prefix.len() + suffix.len());
-                key.extend_from_slice(prefix);
-                key.extend_from_slice(suffix);
-                entry.as_mut().remove_key_physical(&key);
+                records.remove(&key);
                 tombstones.push(i);
+                dirty = true;
             }
             typ if typ.is_dirty() => {
-                let entry = disk_leaf.get_or_insert_with(|| io_engine.get_page(leaf_addr));
-                let key_suffix = node_meta.get_stored_key_from_meta(kv);
-                let val = node_meta.get_val_from_meta(kv);
-
-                entry
-                    .as_mut()
-                    .try_put_with_suffix(key_suffix, val)
-                    .expect("disk leaf should have room for cached entry");
+                records.insert(key, node_meta.get_val_from_meta(kv).to_vec());
+                dirty = true;
+            }
+            typ if full_image && typ.exists() => {
+                records.insert(key, node_meta.get_val_from_meta(kv).to_vec());
             }
             _ => {}
         }
     }
 
-    if let Some(dirty_leaf) = disk_leaf {
-        io_engine.write_page(leaf_addr, &dirty_leaf);
+    if dirty || force {
+        for (key, stored) in records.iter_mut() {
+            if let Some(folded) = merge::fold_stored(io_engine.merge_operator(), key, stored) {
+                *stored = folded;
+            }
+        }
+        // rebuild the disk image so its fences and prefix match the cached leaf
+        let meta = disk_leaf.as_mut();
+        meta.format_leaf(node_meta.page_id(), NodeSize::LeafPage, leaf_addr);
+        meta.reset_user_entries_with_fences(&lower, &upper);
+        meta.replay_entries(
+            records
+                .iter()
+                .map(|(key, value)| (key.as_slice(), value.as_slice())),
+        )
+        .expect("disk leaf should have room for cached entry");
+        // a leaf too full for the stamp replays every record the log holds for it
+        let _ = meta.set_page_lsn(page_lsn);
+        io_engine.write_page(leaf_addr, &disk_leaf);
     }
 
     for idx in tombstones.into_iter().rev() {
         node_meta.remove_entry_at(idx);
     }
+    Ok(())
+}
+
+/// What `entry_info` reports for a record holding `stored`, `None` once it has expired
+fn stored_entry_info(stored: &[u8], cached: bool, page_id: PageId, now: u64) -> Option<EntryInfo> {
+    let attrs = attrs::split(stored).0;
+    if attrs.is_expired(now) {
+        return None;
+    }
+    Some(EntryInfo {
+        value_len: overflow::value_len(stored),
+        cached,
+        overflow: OverflowPointer::decode(stored).is_some(),
+        blob: BlobPointer::decode(stored).is_some(),
+        tombstoned: false,
+        soft_deleted: attrs.soft_deleted,
+        merge_pending: attrs.pending_merge,
+        page_id,
+    })
+}
+
+fn leaf_records(meta: &NodeMeta) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let prefix = meta.get_node_prefix();
+    meta.entries()
+        .filter(|entry| !entry.meta.fence() && entry.meta.typ().exists())
+        .map(|entry| {
+            let mut key = Vec::with_capacity(prefix.len() + entry.key_suffix.len());
+            key.extend_from_slice(prefix);
+            key.extend_from_slice(entry.key_suffix);
+            (key, entry.value.to_vec())
+        })
+        .collect()
 }
 
 impl<'a> PageGuard<'a> {
-    pub fn get<'g>(
-        &'g mut self,
+    /// Look up `key` and pin its value so it can outlive this guard, `None` if its header
+    /// hides it at `now`
+    pub fn get_pinned<'db>(
+        &mut self,
+        cache: &'db MiniPageBuffer,
+        io: &IoEngine,
+        key: &[u8],
+        now: u64,
+    ) -> Result<Option<PinnedValue<'db>>, QSError> {
+        let mini_page = match &self.guard_inner {
+            GuardWrapper::Write(g) => g.node(),
+            GuardWrapper::Read(g) => g.node(),
+        };
+        let mini_page = match mini_page {
+            NodeRef::MiniPage(index) => Some(index.index),
+            NodeRef::Leaf(_) => None,
+        };
+
+        let Some(stored) = self.get(cache, io, key)? else {
+            return Ok(None);
+        };
+        let (attrs, value) = attrs::split(stored);
+        if attrs.hides(now) {
+            return Ok(None);
+        }
+        if let Some(value) = merge::fetch(io, key, stored) {
+            return Ok(Some(PinnedValue::owned(value?)));
+        }
+        let value: *const [u8] = value;
+        // SAFETY: the value lives in the mini-page or in `self.leaf`, both outlive this call
+        let value = unsafe { &*value };
+
+        match mini_page {
+            Some(index) if cache.slot_contains(index, value.as_ptr()) => {
+                Ok(Some(PinnedValue::in_mini_page(cache, index, value)))
+            }
+            // read from the guard's private copy of the disk leaf, hand that copy over
+            _ => {
+                let leaf = self
+                    .leaf
+                    .take()
+                    .expect("value read from the guard's disk leaf");
+                let offset = value.as_ptr() as usize - leaf.as_bytes().as_ptr() as usize;
+                Ok(Some(PinnedValue::in_disk_leaf(leaf, offset, value.len())))
+            }
+        }
+    }
+
+    /// Describe the record for `key` without copying its value out, `None` if it has expired
+    /// by `now`. A tombstone still in the mini-page is reported, one already flushed is not
+    pub fn entry_info(
+        &mut self,
         cache: &MiniPageBuffer,
         io: &IoEngine,
         key: &[u8],
-    ) -> Result<Option<&'g [u8]>, QSError> {
+        now: u64,
+    ) -> Result<Option<EntryInfo>, QSError> {
+        let page_id = self.page_id();
         let node = match &self.guard_inner {
             GuardWrapper::Write(g) => g.node(),
             GuardWrapper::Read(g) => g.node(),
         };
 
-        let val = match node {
-            NodeRef::Leaf(addr) => {
-                let leaf = ensure_page(io, &mut self.leaf, addr)?;
-                leaf.as_ref().get(key)
-            }
+        let leaf_addr = match node {
+            NodeRef::Leaf(addr) => addr,
             NodeRef::MiniPage(mini_page_index) => {
                 // SAFETY: we have either a read or write lock
                 let node_meta = unsafe { cache.get_meta_ref(mini_page_index) };
                 let prefix = node_meta.get_node_prefix();
                 let key_suffix = &key[prefix.len()..];
-                match node_meta
+                if let Ok(kv) = node_meta
                     .binary_search(key_suffix)
                     .map(|i| node_meta.get_kv_meta(i))
                 {
-                    Ok(kv) => {
-                        let val = match kv.typ().exists() {
-                            true => Some(node_meta.get_val_from_meta(kv)),
-                            false => None,
-                        };
-                        // Value is already cached, so early return
-                        return Ok(val);
-                    }
-                    Err(_) => {}
+                    let value = node_meta.get_val_from_meta(kv);
+                    let info = match kv.typ() {
+                        KVRecordType::Insert | KVRecordType::Cache => {
+                            stored_entry_info(value, true, page_id, now)
+                        }
+                        KVRecordType::Tombstone => Some(EntryInfo {
+                            value_len: 0,
+                            cached: true,
+                            overflow: false,
+                            blob: false,
+                            tombstoned: true,
+                            soft_deleted: false,
+                            merge_pending: false,
+                            page_id,
+                        }),
+                        KVRecordType::Phantom => None,
+                    };
+                    return Ok(info);
                 }
-
-                let leaf_addr = node_meta.leaf();
-                let leaf = ensure_page(io, &mut self.leaf, leaf_addr)?;
-
-                leaf.as_ref().get(key)
+                node_meta.leaf()
             }
         };
 
-        // if rand_for_cache() {
-        //     if let Ok(tmp_write) = self.guard_inner.temp_upgrade() {}
-        // }
+        let leaf = ensure_page(io, &mut self.leaf, leaf_addr)?;
+        Ok(leaf
+            .as_ref()
+            .get(key)
+            .and_then(|value| stored_entry_info(value, false, page_id, now)))
+    }
 
-        // TODO: implement caching
-        // if rand_for_cache() {
-        //     // let write_guard = self.upgrade();
+    pub fn get<'g>(
+        &'g mut self,
+        cache: &MiniPageBuffer,
+        io: &IoEngine,
+        key: &[u8],
+    ) -> Result<Option<&'g [u8]>, QSError> {
+        self.lookup(cache, io, key, true)
+    }
 
-        //     // TODO: add to cache
+    /// Like `get`, but a record read from the disk leaf is never copied into the mini-page,
+    /// so the value stays borrowed from the disk leaf image this guard holds
+    pub fn get_in_place<'g>(
+        &'g mut self,
+        cache: &MiniPageBuffer,
+        io: &IoEngine,
+        key: &[u8],
+    ) -> Result<Option<&'g [u8]>, QSError> {
+        self.lookup(cache, io, key, false)
+    }
 
-        //     // self = write_guard.downgrade()
+    fn lookup<'g>(
+        &'g mut self,
+        cache: &MiniPageBuffer,
+        io: &IoEngine,
+        key: &[u8],
+        admit: bool,
+    ) -> Result<Option<&'g [u8]>, QSError> {
+        let node = match &self.guard_inner {
+            GuardWrapper::Write(g) => g.node(),
+            GuardWrapper::Read(g) => g.node(),
+        };
 
-        //     match &mut self.guard_inner {
-        //         GuardWrapper::Write(wg) => {
-        //             // Does the mini-page (if any) have enough space?
-        //             // If so just insert into that
-        //             // If not allocate a new mini-page
-        //             todo!();
-        //         }
-        //         GuardWrapper::Read(page_read_guard) => todo!(),
-        //     }
-        // };
+        let leaf_addr = match node {
+            NodeRef::Leaf(addr) => addr,
+            NodeRef::MiniPage(index) => {
+                // SAFETY: the page stays locked while `self` is borrowed; rebuilt so the index
+                // does not borrow the guard, which caching a disk record upgrades below
+                let mini_page_index: MiniPageIndex<'g> = unsafe { MiniPageIndex::new(index.index) };
+                // SAFETY: we have either a read or write lock
+                let node_meta = unsafe { cache.get_meta_ref(mini_page_index) };
+                let prefix = node_meta.get_node_prefix();
+                let key_suffix = &key[prefix.len()..];
+                if let Ok(kv) = node_meta
+                    .binary_search(key_suffix)
+                    .map(|i| node_meta.get_kv_meta(i))
+                {
+                    let val = match kv.typ().exists() {
+                        true => Some(node_meta.get_val_from_meta(kv)),
+                        false => None,
+                    };
+                    // Value is already cached, so early return
+                    cache.record_lookup(true);
+                    return Ok(val);
+                }
+
+                node_meta.leaf()
+            }
+        };
+
+        cache.record_lookup(false);
+        let leaf = ensure_page(io, &mut self.leaf, leaf_addr)?;
+        // a hot key is read often enough to win a flip eventually and moves into the cache
+        if admit && leaf.as_ref().get(key).is_some() && rand_for_cache() {
+            self.cache_record(cache, key);
+        }
+        let leaf = self.leaf.as_ref().expect("disk leaf read above");
+        Ok(leaf.as_ref().get(key))
+    }
 
-        Ok(val)
+    /// Copy a record read from the disk leaf into the page's mini-page, giving a leaf with
+    /// none a small one. Best effort: nothing is cached if another reader holds the page,
+    /// the mini-page is out of room or the buffer has no slot free without evicting
+    fn cache_record(&mut self, cache: &MiniPageBuffer, key: &[u8]) {
+        let Some(disk_leaf) = self.leaf.as_ref() else {
+            return;
+        };
+        let disk_leaf = disk_leaf.as_ref();
+        let Some(val) = disk_leaf.get(key) else {
+            return;
+        };
+        let record = size_of::<KVMeta>() + key.len() + val.len();
+        let Some(mut write) = self.guard_inner.try_temp_upgrade() else {
+            return;
+        };
+        let guard = write.as_guard();
+        match guard.node() {
+            NodeRef::MiniPage(mini_page_index) => {
+                // SAFETY: we hold the write lock for this node
+                let node_meta = unsafe { cache.get_meta_mut(mini_page_index) };
+                // a full-size mini-page already holds every record of the leaf
+                if matches!(node_meta.size(), NodeSize::LeafPage)
+                    || node_meta.record_bytes() + record > node_meta.disk_room()
+                {
+                    return;
+                }
+                if node_meta.try_put_cached(key, val).is_ok() {
+                    debug::record_cached_read();
+                }
+            }
+            NodeRef::Leaf(disk_addr) => {
+                if cache.leaf_capacity() < SMALL_MINI_PAGE_MIN_LEAVES
+                    || record > disk_leaf.room_for_records()
+                {
+                    return;
+                }
+                let (lower, upper) = disk_leaf.fence_bounds();
+                let fenced =
+                    size_of::<NodeMeta>() + 2 * size_of::<KVMeta>() + lower.len() + upper.len();
+                let Some(size) = NodeSize::from_byte_num(fenced + record)
+                    .filter(|size| !matches!(size, NodeSize::LeafPage))
+                else {
+                    return;
+                };
+                let Some(cache_index) = cache.alloc(size) else {
+                    return;
+                };
+                // SAFETY: the slot is ours until it is published, and we hold the write lock
+                unsafe {
+                    let mini_index = MiniPageIndex::new(cache_index);
+                    let node_meta = cache.get_meta_mut(mini_index);
+                    node_meta.reset_header(guard.page, size, disk_addr);
+                    node_meta.reset_user_entries_with_fences(&lower, &upper);
+                    node_meta.set_disk_room(disk_leaf.room_for_records());
+                    if node_meta.try_put_cached(key, val).is_err() {
+                        NodeMeta::init_unused(node_meta, size);
+                        cache.dealloc(mini_index);
+                        return;
+                    }
+                    guard.set_mini_page(mini_index);
+                }
+                debug::record_cached_read();
+            }
+        }
     }
 }
 
@@ -298,18 +700,35 @@ impl<'a> WriteGuardWrapper<'a> {
             NodeRef::MiniPage(mini_page_index) => {
                 // SAFETY: we hold the write lock for this node
                 let node_meta = unsafe { cache.get_meta_mut(mini_page_index) };
+                let size = node_meta.size();
+                let full = matches!(size, NodeSize::LeafPage);
+                // a smaller mini-page only takes records its disk leaf has room to merge
+                let record = size_of::<KVMeta>() + key.len() + val.len();
+                if !full && node_meta.record_bytes() + record > node_meta.disk_room() {
+                    return TryPutResult::NeedsGrowth(NodeSize::LeafPage);
+                }
                 match node_meta.try_put(key, val) {
                     Ok(_) => {
                         node_meta.mark_hot();
                         TryPutResult::Success
                     }
-                    Err(_) => TryPutResult::NeedsSplit,
+                    Err(_) if full => TryPutResult::NeedsSplit,
+                    Err(_) => TryPutResult::NeedsGrowth(
+                        size.next().expect("only a full leaf has no next size"),
+                    ),
                 }
             }
         }
     }
 
-    pub fn merge_to_disk(&mut self, buffer: &MiniPageBuffer, io_engine: &IoEngine) {
+    /// Write the mini-page's dirty entries to its disk leaf, after syncing the WAL records
+    /// that describe them
+    pub fn merge_to_disk(
+        &mut self,
+        buffer: &MiniPageBuffer,
+        io_engine: &IoEngine,
+        wal: &WalManager,
+    ) -> Result<(), QSError> {
         let write_guard = self.get_write_guard();
         let node = write_guard.node();
         let index = match node {
@@ -323,7 +742,27 @@ impl<'a> WriteGuardWrapper<'a> {
         // TODO: implement safe method on buffer with page write guard
         let node_meta = unsafe { buffer.get_meta_mut(index) };
 
-        flush_dirty_entries(node_meta, io_engine);
+        wal.sync()
+            .expect("failed to sync WAL before writing a leaf");
+        flush_dirty_entries(node_meta, io_engine, wal.durable_lsn())
+    }
+
+    /// Like `merge_to_disk`, but writes a full-size mini-page even if nothing in it is dirty
+    pub fn write_to_disk(
+        &mut self,
+        buffer: &MiniPageBuffer,
+        io_engine: &IoEngine,
+        wal: &WalManager,
+    ) -> Result<(), QSError> {
+        let index = match self.get_write_guard().node() {
+            NodeRef::Leaf(_) => panic!("should only be called on mini pages"),
+            NodeRef::MiniPage(i) => i,
+        };
+        // SAFETY: we've got a write guard
+        let node_meta = unsafe { buffer.get_meta_mut(index) };
+        wal.sync()
+            .expect("failed to sync WAL before writing a leaf");
+        write_full_image(node_meta, io_engine, wal.durable_lsn())
     }
 }
 
@@ -335,7 +774,7 @@ fn ensure_page<'a>(
     let leaf = match cache {
         Some(l) => l,
         l => {
-            let new_leaf = io.get_page(addr);
+            let new_leaf = io.get_page(addr)?;
             *l = Some(new_leaf);
             l.as_mut().expect("We just set this to Some")
         }
@@ -346,13 +785,15 @@ fn ensure_page<'a>(
 pub enum TryPutResult {
     Success,
     NeedsPromotion(u64),
+    /// The mini-page is out of room, move it into one of this size
+    NeedsGrowth(NodeSize),
     NeedsSplit,
 }
 
 fn owned_entries(meta: &NodeMeta) -> Vec<LeafEntryOwned> {
     let prefix = meta.get_node_prefix();
     meta.entries()
-        .filter(|entry| !entry.meta.fence())
+        .filter(|entry| !entry.meta.fence() && entry.meta.typ().exists())
         .map(|entry| LeafEntryOwned::from_entry(prefix, &entry))
         .collect()
 }
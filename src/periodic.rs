@@ -0,0 +1,100 @@
+//! Background threads that run a pass, wait, and run it again until the store closes.
+//!
+//! Each pass returns how long to wait before the next one. The wait is on a condvar, so
+//! closing the store stops the thread at once however long it was told to wait, and `wake`
+//! cuts a wait short for a pass that should run now, such as after its interval changed.
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Wakes a periodic thread early and tells it to stop
+#[derive(Default)]
+pub(crate) struct Signal {
+    state: Mutex<SignalState>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct SignalState {
+    stopped: bool,
+    woken: bool,
+}
+
+impl Signal {
+    /// Run the next pass at once
+    pub(crate) fn wake(&self) {
+        self.state.lock().expect("signal mutex poisoned").woken = true;
+        self.cond.notify_all();
+    }
+
+    fn stop(&self) {
+        self.state.lock().expect("signal mutex poisoned").stopped = true;
+        self.cond.notify_all();
+    }
+
+    /// Wait up to `timeout` or until woken, false once stopped
+    fn wait(&self, timeout: Duration) -> bool {
+        let state = self.state.lock().expect("signal mutex poisoned");
+        let (mut state, _) = self
+            .cond
+            .wait_timeout_while(state, timeout, |state| !state.stopped && !state.woken)
+            .expect("signal mutex poisoned");
+        state.woken = false;
+        !state.stopped
+    }
+}
+
+/// A thread running a pass after each wait, stopped and joined when dropped
+pub(crate) struct Periodic {
+    signal: Arc<Signal>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Periodic {
+    /// Start a thread named `quickstep-<name>` that runs `pass` once `first_wait` is up,
+    /// then again after each wait it returns
+    pub(crate) fn spawn<F>(name: &str, first_wait: Duration, pass: F) -> Periodic
+    where
+        F: FnMut() -> Duration + Send + 'static,
+    {
+        Periodic::spawn_with(Arc::default(), name, first_wait, pass)
+    }
+
+    /// As `spawn`, woken early through `signal`
+    pub(crate) fn spawn_with<F>(
+        signal: Arc<Signal>,
+        name: &str,
+        first_wait: Duration,
+        mut pass: F,
+    ) -> Periodic
+    where
+        F: FnMut() -> Duration + Send + 'static,
+    {
+        let thread_signal = Arc::clone(&signal);
+        let handle = thread::Builder::new()
+            .name(format!("quickstep-{name}"))
+            .spawn(move || {
+                let mut wait = first_wait;
+                while thread_signal.wait(wait) {
+                    wait = pass();
+                }
+            })
+            .expect("failed to spawn quickstep background thread");
+        Periodic {
+            signal,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Periodic {
+    fn drop(&mut self) {
+        self.signal.stop();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
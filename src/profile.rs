@@ -0,0 +1,93 @@
+//! Preset configurations for common deployments.
+//!
+//! A profile picks the sizes passed to `QuickStepConfig::new` and a set of tunables that
+//! work together: how large the cache is, when commits are synced, how much the WAL may
+//! hold before leaves are checkpointed, and which background work runs. Builder calls made
+//! after `QuickStepConfig::for_profile` override the profile, as do the environment and
+//! command-line overrides applied on open.
+
+use std::{path::PathBuf, time::Duration};
+
+use crate::{checkpoint::CheckpointPolicy, wal::SyncPolicy, QuickStepConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// A library store inside an application: a 16 MiB cache, every commit synced, leaves
+    /// flushed while the application is idle
+    Embedded,
+    /// A long-running service with many concurrent writers: a 256 MiB cache, commits synced
+    /// in shared batches, large checkpoints of the oldest leaves, a bounded WAL and online
+    /// compaction
+    Server,
+    /// A one-off import: a 256 MiB cache, commits never waited on, checkpoints deferred as
+    /// long as possible and no background work. A crash loses the load, so restart it
+    BulkLoad,
+    /// Small devices: a 1 MiB cache, a small read cache and WAL, frequent checkpoints and
+    /// compaction to keep the data file small
+    LowMemory,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [
+        Profile::Embedded,
+        Profile::Server,
+        Profile::BulkLoad,
+        Profile::LowMemory,
+    ];
+
+    /// Inner nodes, leaves and log2 of the cache size passed to `QuickStepConfig::new`
+    pub fn sizes(self) -> (u32, u64, usize) {
+        match self {
+            Profile::Embedded => (1 << 10, 1 << 16, 24),
+            Profile::Server => (1 << 14, 1 << 20, 28),
+            Profile::BulkLoad => (1 << 14, 1 << 20, 28),
+            Profile::LowMemory => (1 << 8, 1 << 14, 20),
+        }
+    }
+
+    /// Set the profile's tunables on `config`, leaving its sizes and path alone
+    pub fn apply(self, config: QuickStepConfig) -> QuickStepConfig {
+        match self {
+            Profile::Embedded => config
+                .with_sync_policy(SyncPolicy::PerCommit)
+                .with_idle_maintenance(Duration::from_secs(1), 64),
+            Profile::Server => config
+                .with_sync_policy(SyncPolicy::PerCommit)
+                .with_wal_thresholds(64, 16 * 1024, 64 << 20)
+                .with_checkpoint_policy(CheckpointPolicy::Oldest)
+                .with_checkpoint_batch(16)
+                .with_leaf_cache_pages(1024)
+                .with_max_wal_size(256 << 20)
+                .with_idle_maintenance(Duration::from_millis(500), 512)
+                .with_auto_compaction(Duration::from_secs(60), 256),
+            Profile::BulkLoad => config
+                .with_sync_policy(SyncPolicy::Never)
+                .with_wal_thresholds(256, 1 << 20, 1 << 30)
+                .with_checkpoint_policy(CheckpointPolicy::DirtyRatio)
+                .with_checkpoint_batch(64)
+                .with_leaf_cache_pages(256)
+                .with_ttl_sweep_interval(Duration::from_secs(10)),
+            Profile::LowMemory => config
+                .with_sync_policy(SyncPolicy::PerCommit)
+                .with_wal_thresholds(16, 256, 128 * 1024)
+                .with_checkpoint_batch(4)
+                .with_leaf_cache_pages(8)
+                .with_max_wal_size(4 << 20)
+                .with_idle_maintenance(Duration::from_millis(200), 32)
+                .with_auto_compaction(Duration::from_secs(10), 16),
+        }
+    }
+}
+
+impl QuickStepConfig {
+    /// Config for a database at `path` set up for `profile`, see `Profile`
+    pub fn for_profile<P: Into<PathBuf>>(path: P, profile: Profile) -> QuickStepConfig {
+        let (inner_nodes, leaves, cache_size_lg) = profile.sizes();
+        profile.apply(QuickStepConfig::new(
+            path,
+            inner_nodes,
+            leaves,
+            cache_size_lg,
+        ))
+    }
+}
@@ -0,0 +1,302 @@
+//! Read repair of leaves the WAL is ahead of.
+//!
+//! Recovery normally rewrites every leaf the WAL holds records for before the database
+//! opens. With read repair configured, it only works out which records each of those leaves
+//! is missing, keyed by the leaf's disk address: the pages with leaf records left in the log
+//! are the ones older than it, less the records their LSN shows they hold. The first read of
+//! such a page applies the records to the copy read from disk, and later reads share the
+//! repaired copy until it is written back, so no reader sees the stale leaf. Writing the
+//! page for any reason, such as a flush or a merge, retires its repair.
+//!
+//! A repaired leaf asks the next write to rewrite it along with a batch of others; a leaf
+//! that is never read is repaired by the checkpoint that flushes it.
+//! The log keeps its records until the leaf is checkpointed, so a crash before then
+//! replays them again.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    apply_wal_op, collect_fence_keys, collect_user_records,
+    error::QSError,
+    io_engine::DiskLeaf,
+    merge::{self, MergeOperator},
+    types::NodeMeta,
+    wal::{WalEntryKind, WalOp, WalRecord},
+};
+
+/// The records a leaf is missing, in the order they apply
+pub(crate) struct LeafRepair {
+    ops: Vec<RepairOp>,
+    /// Fences of the newest record applied, the leaf's own if none were
+    fences: Option<(Vec<u8>, Vec<u8>)>,
+    /// Sequence number the log went on from when it was opened, a leaf stamped at or past
+    /// it was not written against this log and has every record applied
+    next_lsn: u64,
+}
+
+struct RepairOp {
+    key: Vec<u8>,
+    op: WalOp,
+    /// Smallest page LSN of a leaf that already holds the op's effect
+    reflected_at: u64,
+}
+
+/// A leaf's fences and records once its repair is applied
+pub(crate) struct RepairedLeaf {
+    pub lower: Vec<u8>,
+    pub upper: Vec<u8>,
+    pub entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Every record the log held for the leaf when it was opened is applied
+    pub page_lsn: u64,
+}
+
+impl LeafRepair {
+    /// The redo records of committed transactions in `records`, and the undo records of the
+    /// rest. A transaction's records on a leaf are contiguous, it held the leaf's lock from
+    /// its first write to its end. Undo records are applied newest first, so a key written
+    /// twice goes back to its value from before the transaction.
+    ///
+    /// A leaf stamped with a redo record's LSN already holds it. An undo record is held once
+    /// the abort marker is, `aborted_at` giving its LSN: the transaction rolled its writes
+    /// back before logging it. Without one, the leaf may have been written with the
+    /// transaction's changes, so its undo records always apply
+    pub(crate) fn from_records(
+        records: Vec<WalRecord>,
+        committed: impl Fn(u64) -> bool,
+        aborted_at: impl Fn(u64) -> Option<u64>,
+        next_lsn: u64,
+    ) -> LeafRepair {
+        let numbered = |lsn: u64| if lsn == 0 { u64::MAX } else { lsn };
+        let mut ops = Vec::new();
+        let mut fences = None;
+        let mut undo_run: Vec<RepairOp> = Vec::new();
+        let mut undo_txn = None;
+        for record in records {
+            let WalRecord {
+                key,
+                lower_fence,
+                upper_fence,
+                kind,
+                txn_id,
+                op,
+                lsn,
+                ..
+            } = record;
+            if matches!(op, WalOp::TxnMarker(_)) {
+                continue;
+            }
+            if undo_txn.is_some_and(|undo_txn| undo_txn != txn_id) {
+                ops.extend(undo_run.drain(..).rev());
+                undo_txn = None;
+            }
+            match kind {
+                WalEntryKind::Redo if committed(txn_id) => ops.push(RepairOp {
+                    key,
+                    op,
+                    reflected_at: numbered(lsn),
+                }),
+                WalEntryKind::Undo if !committed(txn_id) => {
+                    undo_txn = Some(txn_id);
+                    undo_run.push(RepairOp {
+                        key,
+                        op,
+                        reflected_at: aborted_at(txn_id).map_or(u64::MAX, numbered),
+                    });
+                }
+                _ => continue,
+            }
+            fences = Some((lower_fence, upper_fence));
+        }
+        ops.extend(undo_run.drain(..).rev());
+        LeafRepair {
+            ops,
+            fences,
+            next_lsn,
+        }
+    }
+
+    /// Records the repair applies
+    pub(crate) fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Drop the records `base`, the leaf as it is on disk, is stamped as holding already,
+    /// returning how many
+    pub(crate) fn skip_reflected(&mut self, base: &NodeMeta) -> usize {
+        let page_lsn = base.page_lsn();
+        if page_lsn >= self.next_lsn {
+            return 0;
+        }
+        let before = self.ops.len();
+        self.ops.retain(|op| op.reflected_at > page_lsn);
+        before - self.ops.len()
+    }
+
+    /// Whether `base` already holds every record and has the fences they were logged under,
+    /// so it need not be rewritten
+    pub(crate) fn is_current(&self, base: &NodeMeta) -> bool {
+        self.ops.is_empty()
+            && self
+                .fences
+                .as_ref()
+                .is_none_or(|fences| *fences == collect_fence_keys(base))
+    }
+
+    /// Apply the records to `base`, the leaf as it is on disk, folding the merge operands
+    /// they leave pending through `operator`; `None` if it is left empty
+    pub(crate) fn replay(
+        mut self,
+        base: &NodeMeta,
+        operator: Option<&dyn MergeOperator>,
+    ) -> Option<RepairedLeaf> {
+        self.skip_reflected(base);
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> =
+            collect_user_records(base).into_iter().collect();
+        for RepairOp { key, op, .. } in self.ops {
+            apply_wal_op(&mut entries, key, op);
+        }
+        for (key, stored) in entries.iter_mut() {
+            if let Some(folded) = merge::fold_stored(operator, key, stored) {
+                *stored = folded;
+            }
+        }
+        if entries.is_empty() {
+            return None;
+        }
+        let (lower, upper) = self.fences.unwrap_or_else(|| collect_fence_keys(base));
+        // a leaf split just before a crash may still hold the right half on disk
+        entries.retain(|key, _| {
+            key.as_slice() >= lower.as_slice() && key.as_slice() < upper.as_slice()
+        });
+        Some(RepairedLeaf {
+            lower,
+            upper,
+            entries,
+            page_lsn: self.next_lsn - 1,
+        })
+    }
+}
+
+impl RepairedLeaf {
+    /// Replace the records of `meta` with the repaired ones
+    pub(crate) fn write_into(&self, meta: &mut NodeMeta) {
+        meta.reset_user_entries_with_fences(&self.lower, &self.upper);
+        meta.replay_entries(
+            self.entries
+                .iter()
+                .map(|(key, value)| (key.as_slice(), value.as_slice())),
+        )
+        .expect("leaf should accept WAL replay");
+        // a leaf too full for the stamp replays its records again after another crash
+        let _ = meta.set_page_lsn(self.page_lsn);
+    }
+}
+
+enum RepairState {
+    /// Not read since open, the disk leaf is stale
+    Unread(LeafRepair),
+    /// Read and repaired, waiting to be written back
+    Repaired(DiskLeaf),
+}
+
+/// Leaves waiting for read repair, by disk address
+#[derive(Default)]
+pub struct ReadRepairs {
+    pending: Mutex<HashMap<u64, RepairState>>,
+    /// Entries in `pending`, so reads skip the lock once every leaf is written back
+    outstanding: AtomicUsize,
+    /// Set when a leaf is repaired, the next write rewrites it
+    rewrite_requested: AtomicBool,
+}
+
+impl ReadRepairs {
+    /// Have the leaf at `addr` repaired when it is next read
+    pub(crate) fn stage(&self, addr: u64, repair: LeafRepair) {
+        let mut pending = self.pending.lock().expect("read repair mutex poisoned");
+        if pending.insert(addr, RepairState::Unread(repair)).is_none() {
+            self.outstanding.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// The repaired leaf at `addr`, repairing the copy `read` returns on its first read;
+    /// `None` if the leaf needs no repair
+    pub(crate) fn get(
+        &self,
+        addr: u64,
+        read: impl FnOnce() -> Result<DiskLeaf, QSError>,
+        operator: Option<&dyn MergeOperator>,
+    ) -> Option<Result<DiskLeaf, QSError>> {
+        if self.outstanding.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        let mut pending = self.pending.lock().expect("read repair mutex poisoned");
+        match pending.get(&addr)? {
+            RepairState::Repaired(leaf) => return Some(Ok(leaf.clone())),
+            RepairState::Unread(_) => {}
+        }
+        let mut leaf = match read() {
+            Ok(leaf) => leaf,
+            Err(err) => return Some(Err(err)),
+        };
+        let Some(RepairState::Unread(repair)) = pending.remove(&addr) else {
+            unreachable!("unread leaf checked under the same lock");
+        };
+        if let Some(repaired) = repair.replay(leaf.as_ref(), operator) {
+            repaired.write_into(leaf.as_mut());
+        }
+        pending.insert(addr, RepairState::Repaired(leaf.clone()));
+        self.rewrite_requested.store(true, Ordering::Release);
+        Some(Ok(leaf))
+    }
+
+    /// The leaf at `addr` was written or freed, it no longer needs repair
+    pub(crate) fn retire(&self, addr: u64) {
+        if self.outstanding.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut pending = self.pending.lock().expect("read repair mutex poisoned");
+        if pending.remove(&addr).is_some() {
+            self.outstanding.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Addresses of up to `limit` repaired leaves waiting to be written back
+    pub(crate) fn repaired(&self, limit: usize) -> Vec<u64> {
+        let pending = self.pending.lock().expect("read repair mutex poisoned");
+        pending
+            .iter()
+            .filter(|(_, state)| matches!(state, RepairState::Repaired(_)))
+            .map(|(addr, _)| *addr)
+            .take(limit)
+            .collect()
+    }
+
+    /// The repaired copy of the leaf at `addr`, if it is still waiting to be written back
+    pub(crate) fn repaired_leaf(&self, addr: u64) -> Option<DiskLeaf> {
+        let pending = self.pending.lock().expect("read repair mutex poisoned");
+        match pending.get(&addr)? {
+            RepairState::Repaired(leaf) => Some(leaf.clone()),
+            RepairState::Unread(_) => None,
+        }
+    }
+
+    /// Whether a leaf was repaired since the last call
+    pub(crate) fn take_rewrite_request(&self) -> bool {
+        self.rewrite_requested.swap(false, Ordering::AcqRel)
+    }
+
+    pub(crate) fn request_rewrite(&self) {
+        self.rewrite_requested.store(true, Ordering::Release);
+    }
+
+    /// Leaves the WAL is still ahead of on disk
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Acquire)
+    }
+}
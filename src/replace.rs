@@ -0,0 +1,414 @@
+//! Replacement of the whole dataset.
+//!
+//! `QuickStep::replace_all(entries)` builds a second tree next to the live one on a thread
+//! of its own and returns a `ReplaceHandle` to wait for it: the entries are packed into new
+//! leaves on free disk pages under new page ids, and new inner nodes are built over them,
+//! none of which the live tree reaches. Readers and writers carry on against the old tree
+//! meanwhile. Once it is built, every old leaf and inner node is write-locked and the log is
+//! rewritten in one go to describe the new tree, then the root is pointed at the new inner
+//! nodes and page 0, which recovery starts from, at the new leftmost leaf. A crash sees one
+//! tree or the other, and the old leaves and inner nodes are given back. Closing the store
+//! waits for a replacement in progress.
+//!
+//! Readers only wait for the swap itself. Writes committed while the new tree is built are
+//! replaced with everything else, and the swap fails, leaving the old tree in place, if a
+//! leaf stays locked by a transaction past the lock-wait policy. New leaves are filled to
+//! three quarters, leaving room for the writes that follow. Expiries and soft-delete flags
+//! live in the old records, so no key in the new dataset has either, and the activity log
+//! is emptied with the rest of the reserved keyspace; watchers are not told of the
+//! replacement. Hash-index stores have no tree to replace. Long values get overflow chains
+//! of their own, and the old leaves' chains are freed with them.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+use crate::{
+    attrs::{self, RecordAttrs},
+    buffer::MiniPageIndex,
+    error::QSError,
+    events::StructuralEvent,
+    io_engine::DiskLeaf,
+    iter::{read_leaf_stored, LOWER_SENTINEL, UPPER_SENTINEL},
+    map_table::{PageId, PageWriteGuard},
+    overflow::{self, OverflowPointer},
+    page_op::separator,
+    reserved,
+    types::{NodeRef, NodeSize},
+    wal::WalSmo,
+    Store,
+};
+
+/// What a replacement did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaceStats {
+    /// Entries in the new dataset
+    pub entries: u64,
+    /// Leaves the new tree was built from
+    pub leaves: u64,
+    /// Inner nodes built above them
+    pub inner_nodes: u64,
+    /// Leaves of the old tree given back
+    pub replaced_leaves: u64,
+}
+
+/// A replacement running in the background, see `QuickStep::replace_all`
+#[must_use = "a replacement may fail, its outcome is only known once its handle is waited on"]
+pub struct ReplaceHandle {
+    outcome: Arc<Outcome>,
+}
+
+/// What the replacing thread hands back
+#[derive(Default)]
+struct Outcome {
+    result: Mutex<Option<Result<ReplaceStats, QSError>>>,
+    done: Condvar,
+}
+
+impl ReplaceHandle {
+    /// Whether the new tree is swapped in, or the replacement has failed
+    pub fn is_finished(&self) -> bool {
+        self.outcome
+            .result
+            .lock()
+            .expect("replace outcome mutex poisoned")
+            .is_some()
+    }
+
+    /// Wait until the new tree is swapped in, or the replacement has failed
+    pub fn wait(self) -> Result<ReplaceStats, QSError> {
+        let mut result = self
+            .outcome
+            .result
+            .lock()
+            .expect("replace outcome mutex poisoned");
+        loop {
+            if let Some(result) = result.take() {
+                return result;
+            }
+            result = self
+                .outcome
+                .done
+                .wait(result)
+                .expect("replace outcome mutex poisoned");
+        }
+    }
+}
+
+impl Outcome {
+    fn finish(&self, result: Result<ReplaceStats, QSError>) {
+        *self.result.lock().expect("replace outcome mutex poisoned") = Some(result);
+        self.done.notify_all();
+    }
+}
+
+/// A leaf of the new tree: the pivot it starts at, its page and where it is on disk
+struct NewLeaf {
+    pivot: Vec<u8>,
+    page: PageId,
+    addr: u64,
+}
+
+/// Replace every entry of the store with `entries` on a new thread, which the store joins
+/// when it closes
+pub(crate) fn spawn(db: &Arc<Store>, entries: Vec<(Vec<u8>, Vec<u8>)>) -> ReplaceHandle {
+    let outcome = Arc::new(Outcome::default());
+    let (store, thread_outcome) = (Arc::clone(db), Arc::clone(&outcome));
+    let spawned = thread::Builder::new()
+        .name("quickstep-replace".to_string())
+        .spawn(move || thread_outcome.finish(run(&store, entries)));
+    match spawned {
+        Ok(thread) => {
+            let mut replacements = db.replacements.lock().expect("replacements mutex poisoned");
+            replacements.retain(|thread| !thread.is_finished());
+            replacements.push(thread);
+        }
+        Err(err) => outcome.finish(Err(err.into())),
+    }
+    ReplaceHandle { outcome }
+}
+
+/// Replace every entry of the store with `entries`, a later duplicate key winning
+fn run(db: &Store, entries: Vec<(Vec<u8>, Vec<u8>)>) -> Result<ReplaceStats, QSError> {
+    if db.hash_buckets.is_some() {
+        return Err(QSError::OrderedScanUnsupported);
+    }
+    if db.follower.is_some() {
+        return Err(QSError::FollowerReadOnly);
+    }
+    db.check_writable()?;
+    let mut sorted = BTreeMap::new();
+    for (key, value) in entries {
+        if reserved::is_reserved(&key) {
+            return Err(QSError::ReservedKey { key });
+        }
+        let limits = db.size_limits(&key);
+        limits.check_key(&key)?;
+        limits.check_value(&value)?;
+        sorted.insert(key, value);
+    }
+    let mut sorted: Vec<_> = sorted.into_iter().collect();
+    // long values go to overflow pages, the new leaves only point at them
+    let mut chains = Vec::new();
+    for (_, value) in sorted.iter_mut() {
+        let header_len = attrs::header_len(RecordAttrs::default(), value);
+        if !overflow::needs_chain(value, header_len) {
+            if header_len > 0 {
+                *value = attrs::wrap(RecordAttrs::default(), value).into_owned();
+            }
+            continue;
+        }
+        match db.io_engine.write_overflow(value) {
+            Ok(chain) => {
+                chains.push(chain);
+                *value = chain.encode().to_vec();
+            }
+            Err(err) => {
+                free_leaves(db, &[], &chains);
+                return Err(err);
+            }
+        }
+    }
+
+    let leaves = match write_leaves(db, &sorted) {
+        Ok(leaves) => leaves,
+        Err((leaves, err)) => {
+            free_leaves(db, &leaves, &chains);
+            return Err(err);
+        }
+    };
+    db.io_engine.sync_pages();
+    let pivots: Vec<_> = leaves
+        .iter()
+        .map(|leaf| (leaf.pivot.clone(), leaf.page))
+        .collect();
+    let tree = match db.inner_nodes.build_detached(&pivots) {
+        Ok(tree) => tree,
+        Err(err) => {
+            free_leaves(db, &leaves, &chains);
+            return Err(err);
+        }
+    };
+    let mut stats = ReplaceStats {
+        entries: sorted.len() as u64,
+        leaves: leaves.len() as u64,
+        inner_nodes: tree.inner_nodes() as u64,
+        replaced_leaves: 0,
+    };
+
+    let mut structure = vec![(
+        WalSmo::Relocate {
+            page: 0,
+            addr: leaves[0].addr,
+        },
+        Vec::new(),
+    )];
+    for pair in leaves.windows(2) {
+        let split = WalSmo::Split {
+            left: pair[0].page.as_u64(),
+            right: pair[1].page.as_u64(),
+            right_addr: pair[1].addr,
+        };
+        structure.push((split, pair[1].pivot.clone()));
+    }
+
+    let mut old_leaves = match lock_leaves(db) {
+        Ok(old_leaves) => old_leaves,
+        Err(err) => {
+            db.inner_nodes.discard_detached(tree);
+            free_leaves(db, &leaves, &chains);
+            return Err(err);
+        }
+    };
+    let swapped = db.inner_nodes.swap_root(tree, || {
+        db.wal.append_tree_replacement(&structure)?;
+        Ok(())
+    });
+    if let Err(err) = swapped {
+        free_leaves(db, &leaves, &chains);
+        return Err(err);
+    }
+
+    let mut freed = Vec::with_capacity(old_leaves.len());
+    let mut old_chains = Vec::new();
+    for (page, guard) in old_leaves.iter_mut() {
+        old_chains.extend(leaf_chains(db, guard));
+        let to = (*page == 0).then_some(leaves[0].addr);
+        freed.push(release_leaf(db, guard, to));
+    }
+    drop(old_leaves);
+    stats.replaced_leaves = freed.len() as u64;
+    db.events.emit(|| StructuralEvent::TreeReplaced {
+        leaves: stats.leaves,
+        replaced_leaves: stats.replaced_leaves,
+    });
+    for addr in freed {
+        db.io_engine.free_page(addr);
+    }
+    for chain in old_chains {
+        db.io_engine.free_overflow(chain);
+    }
+
+    // the old records and their headers are gone, the new ones have none
+    db.ttl.clear();
+    if let Some(log) = &db.activity_log {
+        log.clear();
+    }
+    Ok(stats)
+}
+
+/// Pack `entries` into leaves on new disk pages, the first under page 0. On failure the
+/// leaves written so far are handed back to be freed
+fn write_leaves(
+    db: &Store,
+    entries: &[(Vec<u8>, Vec<u8>)],
+) -> Result<Vec<NewLeaf>, (Vec<NewLeaf>, QSError)> {
+    let mut leaves: Vec<NewLeaf> = Vec::new();
+    let mut rest = entries;
+    let mut lower = LOWER_SENTINEL.to_vec();
+    loop {
+        let addr = match db.io_engine.get_new_addr() {
+            Ok(addr) => addr,
+            Err(err) => return Err((leaves, err)),
+        };
+        let page = match leaves.is_empty() {
+            true => PageId(0),
+            false => match db.map_table.create_leaf_entry(addr) {
+                Ok(page) => page,
+                Err(err) => {
+                    db.io_engine.free_page(addr);
+                    return Err((leaves, err));
+                }
+            },
+        };
+        let Some((leaf, taken)) = pack_leaf(page, addr, &lower, rest) else {
+            db.io_engine.free_page(addr);
+            return Err((leaves, QSError::SplitFailed));
+        };
+        db.io_engine.write_page(addr, &leaf);
+        leaves.push(NewLeaf {
+            pivot: lower,
+            page,
+            addr,
+        });
+        rest = &rest[taken..];
+        let Some((next, _)) = rest.first() else {
+            return Ok(leaves);
+        };
+        let last = &entries[entries.len() - rest.len() - 1].0;
+        lower = separator(last, next);
+    }
+}
+
+/// Fill a leaf starting at `lower` with the first entries, up to three quarters of a page;
+/// `None` if not even one fits
+fn pack_leaf(
+    page: PageId,
+    addr: u64,
+    lower: &[u8],
+    entries: &[(Vec<u8>, Vec<u8>)],
+) -> Option<(DiskLeaf, usize)> {
+    let reserve = NodeSize::LeafPage.size_in_bytes() / 4;
+    let mut leaf = DiskLeaf::zeroed();
+    let meta = leaf.as_mut();
+    meta.format_leaf(page, NodeSize::LeafPage, addr);
+    meta.reset_user_entries_with_fences(lower, UPPER_SENTINEL);
+    let mut fits = 0;
+    for (key, value) in entries {
+        if meta.free_bytes() < reserve || meta.try_put(key, value).is_err() {
+            break;
+        }
+        fits += 1;
+    }
+    // the real upper fence may take more room than the sentinel it was packed under
+    for taken in (1..=fits.max(1).min(entries.len())).rev() {
+        let upper = match entries.get(taken) {
+            Some((next, _)) => separator(&entries[taken - 1].0, next),
+            None => UPPER_SENTINEL.to_vec(),
+        };
+        let mut leaf = DiskLeaf::zeroed();
+        let meta = leaf.as_mut();
+        meta.format_leaf(page, NodeSize::LeafPage, addr);
+        meta.reset_user_entries_with_fences(lower, &upper);
+        let replayed = meta.replay_entries(
+            entries[..taken]
+                .iter()
+                .map(|(key, value)| (key.as_slice(), value.as_slice())),
+        );
+        if replayed.is_ok() {
+            return Some((leaf, taken));
+        }
+    }
+    entries.is_empty().then_some((leaf, 0))
+}
+
+/// Write lock every leaf of the live tree in key order, listing the tree again until a pass
+/// finds no leaf a split added behind it. Leaves merged away meanwhile are left out
+fn lock_leaves(db: &Store) -> Result<BTreeMap<u64, PageWriteGuard<'_>>, QSError> {
+    let mut locked = BTreeMap::new();
+    loop {
+        let mut current = Vec::new();
+        let mut added = false;
+        let mut lower = LOWER_SENTINEL.to_vec();
+        loop {
+            let (page, successor) = db.inner_nodes.read_leaf_and_successor(&lower)?;
+            current.push(page.as_u64());
+            if let Entry::Vacant(entry) = locked.entry(page.as_u64()) {
+                entry.insert(db.map_table.write_page_entry(page)?);
+                added = true;
+            }
+            match successor {
+                Some(pivot) => lower = pivot.into_vec(),
+                None => break,
+            }
+        }
+        if !added {
+            locked.retain(|page, _| current.contains(page));
+            return Ok(locked);
+        }
+    }
+}
+
+/// Retire an old leaf's mini-page and point it at `to`, the new leaf of page 0, or else at
+/// its own disk page, which is freed as after a merge. Returns that disk page
+fn release_leaf(db: &Store, guard: &mut PageWriteGuard<'_>, to: Option<u64>) -> u64 {
+    let (addr, slot) = match guard.node() {
+        NodeRef::Leaf(addr) => (addr, None),
+        // SAFETY: we hold the page's write lock
+        NodeRef::MiniPage(index) => (
+            unsafe { db.cache.get_meta_ref(index) }.leaf(),
+            Some(index.index),
+        ),
+    };
+    guard.set_leaf(to.unwrap_or(addr));
+    if let Some(slot) = slot {
+        // SAFETY: the page no longer points at the slot
+        unsafe { db.cache.retire(MiniPageIndex::new(slot)) };
+    }
+    addr
+}
+
+/// Give back the disk pages of leaves that never made it into the tree, and the overflow
+/// chains written for them
+fn free_leaves(db: &Store, leaves: &[NewLeaf], chains: &[OverflowPointer]) {
+    for leaf in leaves {
+        db.io_engine.free_page(leaf.addr);
+    }
+    for chain in chains {
+        db.io_engine.free_overflow(*chain);
+    }
+}
+
+/// The overflow chains an old leaf's records point at, freed along with it. Those of a leaf
+/// that cannot be read are left allocated
+fn leaf_chains(db: &Store, guard: &PageWriteGuard<'_>) -> Vec<OverflowPointer> {
+    let Ok(view) = read_leaf_stored(&db.cache, &db.io_engine, guard.node()) else {
+        return Vec::new();
+    };
+    view.records
+        .values()
+        .filter_map(|value| OverflowPointer::decode(value))
+        .collect()
+}
@@ -0,0 +1,102 @@
+//! Keys the store writes for itself.
+//!
+//! State that has to commit or roll back with a transaction, such as the activity log, is
+//! kept as records in the tree under keys starting with `RESERVED_PREFIX`. The transaction
+//! writes them like any other key, so the WAL logs them with its other writes, and recovery,
+//! rollbacks, two-phase commit and followers treat them the same way. Each user of the
+//! keyspace has a name of its own in it, see `key`.
+//!
+//! A write through the public API to a key in the keyspace fails with
+//! `QSError::ReservedKey`. Scans and samples skip the keyspace, and watchers are not told of
+//! writes to it. The prefix sorts after the empty key and `[0x00]`, which are kept for
+//! the fences of the leftmost leaf, and before every other key.
+
+use crate::{
+    error::QSError,
+    iter::{read_leaf_stored, LeafView},
+    map_table::PageId,
+    overflow, Store, SPIN_RETRIES,
+};
+
+/// Opens every key the store writes for itself
+pub const RESERVED_PREFIX: &[u8] = b"\x00\x00qs\x00";
+
+/// Whether `key` is in the keyspace the store keeps for itself
+pub fn is_reserved(key: &[u8]) -> bool {
+    key.starts_with(RESERVED_PREFIX)
+}
+
+/// The key for `suffix` in the part of the reserved keyspace named `space`
+pub(crate) fn key(space: &[u8], suffix: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(RESERVED_PREFIX.len() + space.len() + suffix.len());
+    key.extend_from_slice(RESERVED_PREFIX);
+    key.extend_from_slice(space);
+    key.extend_from_slice(suffix);
+    key
+}
+
+impl Store {
+    /// The committed records of `space` whose suffix is at least `from`, as suffix and value
+    /// in key order
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn reserved_records(
+        &self,
+        space: &[u8],
+        from: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, QSError> {
+        let lower = key(space, from);
+        let space_prefix = key(space, &[]);
+        let mut records = Vec::new();
+        let mut push = |view: LeafView| -> Result<(), QSError> {
+            for (key, stored) in view.records.range(lower.clone()..) {
+                let Some(suffix) = key.strip_prefix(space_prefix.as_slice()) else {
+                    break;
+                };
+                let value = overflow::resolve(&self.io_engine, stored)?.into_owned();
+                records.push((suffix.to_vec(), value));
+            }
+            Ok(())
+        };
+        match self.hash_buckets {
+            Some(buckets) => {
+                for page in (0..buckets).map(PageId::from_u64) {
+                    let guard = self.map_table.read_page_entry(page)?;
+                    push(read_leaf_stored(
+                        &self.cache,
+                        &self.io_engine,
+                        guard.node(),
+                    )?)?;
+                }
+                records.sort();
+            }
+            None => {
+                let mut resume = lower.clone();
+                loop {
+                    let view = self.read_stored_leaf_covering(&resume)?;
+                    let next = view.upper.clone();
+                    push(view)?;
+                    // the leaf that ends past the space is the last to hold any of it
+                    if !next.starts_with(&space_prefix) {
+                        return Ok(records);
+                    }
+                    resume = next;
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Read the leaf covering `key` as its records hold it, traversing again if a split or
+    /// merge moved it
+    fn read_stored_leaf_covering(&self, key: &[u8]) -> Result<LeafView, QSError> {
+        for _ in 0..SPIN_RETRIES {
+            let page = self.inner_nodes.read_traverse_leaf(key)?.page;
+            let guard = self.map_table.read_page_entry(page)?;
+            let view = read_leaf_stored(&self.cache, &self.io_engine, guard.node())?;
+            if view.covers(key) {
+                return Ok(view);
+            }
+        }
+        Err(QSError::OLCRetriesExceeded)
+    }
+}
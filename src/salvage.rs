@@ -0,0 +1,187 @@
+//! Recovery of what a damaged data file still holds, see `QuickStep::salvage`.
+//!
+//! Every page the data file has handed out is read in turn, without the map table, the inner
+//! nodes or the WAL. A page that fails its checksum, or whose records fail the `invariants`
+//! checks of a single leaf, is listed in the report and skipped; a zeroed page was freed or
+//! never written and is counted as empty. The records of every other page are gathered by
+//! key, and a key found in more than one leaf, as happens when a split was cut short, keeps
+//! the copy from the leaf with the newest page sequence number, see `NodeMeta::page_lsn`.
+//! Tombstones take part like values, so a key deleted in the newest copy stays deleted.
+//!
+//! Overflow pages are counted and passed over; a surviving value stored in them, or in a
+//! blob file beside the data file, is read back through its pointer, and dropped if its
+//! chain or blob file no longer holds it.
+//!
+//! The surviving entries are written, in key order and in batches of `SALVAGE_BATCH`, to a
+//! new store at the output path, created with the damaged store's settings. Only the data
+//! file is read: writes that were still only in the WAL are not carried over, while the
+//! expiries and soft-delete flags in the headers of the records are. Open the store
+//! normally first if its WAL is intact, so recovery writes those records back to their
+//! leaves. The superblock must still be readable, it says whether the pages are compressed.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    blob::BlobLog,
+    error::QSError,
+    invariants,
+    io_engine::IoEngine,
+    overflow, resolve_data_path,
+    types::{KVRecordType, NodeMeta},
+    QuickStep, QuickStepConfig,
+};
+
+/// Entries written to the new store per transaction
+pub const SALVAGE_BATCH: usize = 256;
+
+/// What `QuickStep::salvage` found in the data file and carried over
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// Pages read, the empty and corrupt ones included
+    pub pages_scanned: u64,
+    /// Pages freed or never written
+    pub pages_empty: u64,
+    /// Leaves whose records were gathered
+    pub leaves_salvaged: u64,
+    /// Pages holding parts of long values, see `overflow`
+    pub overflow_pages: u64,
+    /// Pages skipped as unreadable, in address order
+    pub corrupt_pages: Vec<CorruptPage>,
+    /// Copies of a key beyond the first, the newest of them was kept
+    pub duplicate_keys: u64,
+    /// Entries written to the new store, keys whose newest copy is a tombstone aside
+    pub keys_salvaged: u64,
+    /// Keys left out because the overflow chain or blob file holding their value could not
+    /// be read
+    pub overflow_values_lost: u64,
+}
+
+/// A page `QuickStep::salvage` could not use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptPage {
+    pub addr: u64,
+    /// The checksum failure or the broken leaf invariant
+    pub reason: String,
+}
+
+/// The newest copy of a key so far: the page sequence number and address of its leaf, and
+/// its value, `None` for a tombstone
+type Salvaged = ((u64, u64), Option<Vec<u8>>);
+
+pub(crate) fn salvage(
+    mut config: QuickStepConfig,
+    output_path: &Path,
+) -> Result<SalvageReport, QSError> {
+    if config.in_memory {
+        return Err(QSError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "an in-memory store has no data file to salvage",
+        )));
+    }
+    let data_path = resolve_data_path(&config.path);
+    let output_data_path = resolve_data_path(output_path);
+    let output_exists = fs::metadata(&output_data_path).is_ok_and(|meta| meta.len() > 0);
+    if output_data_path == data_path || output_exists {
+        return Err(QSError::Io(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already holds a store", output_data_path.display()),
+        )));
+    }
+
+    let mut report = SalvageReport::default();
+    let mut found: BTreeMap<Vec<u8>, Salvaged> = BTreeMap::new();
+    let io_engine = IoEngine::open_read_only(&data_path, &config.metadata_auth)?
+        .with_blob_log(BlobLog::open_read_only(&data_path)?);
+    for addr in 0..io_engine.pages_used() {
+        report.pages_scanned += 1;
+        let leaf = match io_engine.get_page(addr) {
+            Ok(leaf) => leaf,
+            Err(err) => {
+                let reason = format!("{err:?}");
+                report.corrupt_pages.push(CorruptPage { addr, reason });
+                continue;
+            }
+        };
+        if overflow::is_overflow_page(&leaf) && !io_engine.is_free_page(addr) {
+            report.overflow_pages += 1;
+            continue;
+        }
+        let meta = leaf.as_ref();
+        if meta.record_count() == 0 || io_engine.is_free_page(addr) {
+            report.pages_empty += 1;
+            continue;
+        }
+        if let Err(violation) = invariants::check_node(meta.page_id(), meta) {
+            let reason = format!("{violation:?}");
+            report.corrupt_pages.push(CorruptPage { addr, reason });
+            continue;
+        }
+        report.leaves_salvaged += 1;
+        let version = (meta.page_lsn(), addr);
+        for (key, value) in leaf_records(meta) {
+            match found.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert((version, value));
+                }
+                Entry::Occupied(mut entry) => {
+                    report.duplicate_keys += 1;
+                    if version > entry.get().0 {
+                        entry.insert((version, value));
+                    }
+                }
+            }
+        }
+    }
+    let mut live: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for (key, (_, value)) in found {
+        let Some(value) = value else {
+            continue;
+        };
+        match overflow::fetch_stored(&io_engine, &value) {
+            None => live.push((key, value)),
+            Some(Ok(value)) => live.push((key, value)),
+            Some(Err(_)) => report.overflow_values_lost += 1,
+        }
+    }
+    drop(io_engine);
+
+    config.path = output_path.to_path_buf();
+    let db = QuickStep::open(config)?;
+    for batch in live.chunks(SALVAGE_BATCH) {
+        let mut tx = db.tx();
+        // copied as they were, reserved keys included
+        tx.replica = true;
+        for (key, value) in batch {
+            tx.put_stored(key, value)?;
+        }
+        tx.commit();
+        report.keys_salvaged += batch.len() as u64;
+    }
+    db.close()?;
+    Ok(report)
+}
+
+/// The user records of a leaf with their values, `None` for a tombstone
+fn leaf_records(meta: &NodeMeta) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+    let prefix = meta.get_node_prefix();
+    (0..meta.record_count() as usize)
+        .map(|idx| meta.get_kv_meta(idx))
+        .filter(|kv| !kv.fence())
+        .filter_map(|kv| {
+            let value = match kv.typ() {
+                KVRecordType::Insert | KVRecordType::Cache => {
+                    Some(meta.get_val_from_meta(kv).to_vec())
+                }
+                KVRecordType::Tombstone => None,
+                KVRecordType::Phantom => return None,
+            };
+            let mut key = prefix.to_vec();
+            key.extend_from_slice(meta.get_stored_key_from_meta(kv));
+            Some((key, value))
+        })
+        .collect()
+}
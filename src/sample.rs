@@ -0,0 +1,74 @@
+//! Random samples of the keys in a store, for sketches and for spotting skew.
+//!
+//! `QuickStep::sample_keys` lists the leaves from the inner nodes, which are in memory,
+//! then reads leaves in a random order: as many as keys were asked for, more if those hold
+//! too few keys, and never all of them unless it has to. The keys are drawn uniformly from
+//! the leaves read, so each leaf is as likely to be read as any other and gives keys in
+//! proportion to its record count, and every key has close to the same chance of being
+//! drawn wherever it sits.
+//!
+//! Leaves are read one at a time under their read lock, as a `RangeIter` reads them, so
+//! the sample is not a snapshot. Expired and soft-deleted keys are never drawn.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    error::QSError,
+    iter::{read_leaf, LeafView},
+    ttl::now_millis,
+    Store, SPIN_RETRIES,
+};
+
+impl Store {
+    /// About `n` keys drawn uniformly at random, in key order. Fewer come back when the
+    /// store holds fewer, see `sample`
+    pub fn sample_keys(&self, n: usize) -> Result<Vec<Vec<u8>>, QSError> {
+        if self.hash_buckets.is_some() {
+            return Err(QSError::OrderedScanUnsupported);
+        }
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut leaves = self.leaf_starts()?;
+        fastrand::shuffle(&mut leaves);
+        let now = now_millis();
+        let mut pool = BTreeSet::new();
+        for (read, start) in leaves.iter().enumerate() {
+            if read >= n && pool.len() >= n {
+                break;
+            }
+            let view = self.read_leaf_covering(start, now)?;
+            pool.extend(view.records.into_keys());
+        }
+
+        let mut keys = fastrand::choose_multiple(pool, n);
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// A key in each leaf, the smallest it can hold, read from the inner nodes alone
+    pub(crate) fn leaf_starts(&self) -> Result<Vec<Vec<u8>>, QSError> {
+        let mut starts = vec![Vec::new()];
+        loop {
+            let cursor = starts.last().expect("starts with the empty key");
+            match self.inner_nodes.read_leaf_and_successor(cursor)?.1 {
+                Some(next) => starts.push(next.into_vec()),
+                None => return Ok(starts),
+            }
+        }
+    }
+
+    /// Read the leaf covering `key`, traversing again if a split or merge moved it
+    fn read_leaf_covering(&self, key: &[u8], now: u64) -> Result<LeafView, QSError> {
+        for _ in 0..SPIN_RETRIES {
+            let page = self.inner_nodes.read_traverse_leaf(key)?.page;
+            let guard = self.map_table.read_page_entry(page)?;
+            let view = read_leaf(&self.cache, &self.io_engine, guard.node(), now, false)?;
+            if view.covers(key) {
+                return Ok(view);
+            }
+        }
+        Err(QSError::OLCRetriesExceeded)
+    }
+}
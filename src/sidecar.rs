@@ -0,0 +1,139 @@
+//! Append-only logs kept in files next to the data file.
+//!
+//! The page map of a compressed data file keeps its state in memory and logs each change
+//! to a sidecar file, the last entry for a key winning. Opening reads the entries back in order and cuts off a torn entry a crash left
+//! mid-append. Once overridden entries outnumber live ones the owner rewrites the file with
+//! the live entries alone: they are written and synced to a temporary file, which is
+//! renamed over the log, and the directory is synced so the rename itself survives a crash.
+//!
+//! Appends are not synced as they are made. An owner whose entries have to be on disk by
+//! some point, such as before the WAL records they go with are dropped, calls `sync`.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::storage::Storage;
+
+pub struct SidecarLog {
+    /// `None` for a log kept in memory
+    path: Option<PathBuf>,
+    file: Storage,
+}
+
+impl SidecarLog {
+    /// Open the log at `path`, creating it and its directory if needed
+    pub fn open(path: &Path) -> io::Result<SidecarLog> {
+        Ok(SidecarLog {
+            path: Some(path.to_path_buf()),
+            file: Storage::open(path)?,
+        })
+    }
+
+    /// Keep the log in memory instead of a file, it is lost once the log is dropped
+    pub fn in_memory() -> SidecarLog {
+        SidecarLog {
+            path: None,
+            file: Storage::memory(),
+        }
+    }
+
+    /// A log in memory starting from a copy of the one at `path`, which is left as it is
+    pub fn memory_copy_of(path: &Path) -> io::Result<SidecarLog> {
+        Ok(SidecarLog {
+            path: None,
+            file: Storage::memory_copy_of(path)?,
+        })
+    }
+
+    /// Read the entries back, handing `decode` the log and the offset of each in turn; it
+    /// returns the offset of the next entry, or `None` where the entries run out. What is
+    /// left after the last whole entry is cut off, the log carries on from there
+    pub fn replay<F>(&mut self, mut decode: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8], usize) -> Option<usize>,
+    {
+        let mut bytes = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+        let mut pos = 0;
+        while let Some(next) = decode(&bytes, pos) {
+            pos = next;
+        }
+        if pos < bytes.len() {
+            self.file.set_len(pos as u64)?;
+        }
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    /// Add `entries` to the end of the log
+    pub fn append(&mut self, entries: &[u8]) -> io::Result<()> {
+        self.file.write_all(entries)?;
+        self.file.flush()
+    }
+
+    /// Wait for what has been appended to reach the disk
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Replace the log with `entries`, which are on disk once this returns
+    pub fn rewrite(&mut self, entries: &[u8]) -> io::Result<()> {
+        match &self.path {
+            Some(path) => {
+                // Windows refuses to replace a file that still has an open handle
+                self.file = Storage::memory();
+                let replaced = replace_file(path, entries);
+                self.file = Storage::File(OpenOptions::new().read(true).write(true).open(path)?);
+                replaced?;
+            }
+            None => {
+                self.file.set_len(0)?;
+                self.file.seek(SeekFrom::Start(0))?;
+                self.file.write_all(entries)?;
+            }
+        }
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+/// Atomically replace the file at `path` with `bytes`: a crash leaves either the old file
+/// or the new one, and the new one is on disk once this returns
+pub fn replace_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(bytes)?;
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path)
+}
+
+/// Sync the directory holding `path`, so an entry just created, renamed or removed in it
+/// survives a crash
+pub fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    sync_dir(parent)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Windows cannot open a directory as a file; its renames are journalled with the file
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! Counters and latency histograms kept while the store runs, see `QuickStep::stats`.
+//!
+//! Unlike the `debug` counters, which are process-wide and meant for tests, these belong to
+//! one store and are always on. Gets, puts and deletes are counted as they succeed and
+//! timed into a histogram each; a failed operation is neither counted nor timed. The rest of
+//! the snapshot is read from where it is already counted: the mini-page buffer for cache
+//! hits, the io engine for page reads and the WAL for bytes and checkpoints.
+//!
+//! A histogram keeps `SUB_BUCKETS` buckets per power of two of nanoseconds, so a
+//! percentile it reports is the top of the bucket holding it, at most a quarter above the
+//! true value and never past the slowest operation seen.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{buffer::MiniPageBuffer, io_engine::IoEngine, wal::WalManager};
+
+const SUB_BUCKET_BITS: u32 = 2;
+/// Buckets each power of two is split into
+pub const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = 64 * SUB_BUCKETS;
+
+/// How many operations of a kind succeeded and how long they took
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    pub count: u64,
+    /// Time all of them took together
+    pub total: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// The store's counters at one moment, all counted since open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub gets: OpStats,
+    /// Puts, with or without a TTL
+    pub puts: OpStats,
+    pub deletes: OpStats,
+    /// Gets the leaf's mini-page answered
+    pub cache_hits: u64,
+    /// Gets that went on to the disk leaf, read from the data file or the leaf cache
+    pub cache_misses: u64,
+    /// Page images read from the data file
+    pub disk_reads: u64,
+    /// Bytes written to the WAL, checkpoint rewrites included
+    pub wal_bytes: u64,
+    /// Checkpoints that dropped leaf records from the WAL
+    pub checkpoints: u64,
+    pub splits: u64,
+    pub merges: u64,
+}
+
+impl StatsSnapshot {
+    /// Share of gets the mini-page buffer answered, 0 before any get
+    pub fn cache_hit_ratio(&self) -> f64 {
+        match self.cache_hits + self.cache_misses {
+            0 => 0.0,
+            lookups => self.cache_hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// Operation latencies bucketed by nanoseconds
+pub(crate) struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> OpStats {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let max = self.max_nanos.load(Ordering::Relaxed);
+        // the count is summed from the buckets read, so the percentiles agree with it
+        let count = counts.iter().sum();
+        let percentile = |quantile: f64| {
+            if count == 0 {
+                return Duration::ZERO;
+            }
+            let rank = ((count as f64 * quantile).ceil() as u64).max(1);
+            let mut seen = 0;
+            let index = counts
+                .iter()
+                .position(|bucket| {
+                    seen += bucket;
+                    seen >= rank
+                })
+                .unwrap_or(BUCKETS - 1);
+            Duration::from_nanos(bucket_top(index).min(max))
+        };
+        OpStats {
+            count,
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+            max: Duration::from_nanos(max),
+        }
+    }
+}
+
+/// The bucket `nanos` falls in: values below `SUB_BUCKETS` get one each, after that each
+/// power of two is split `SUB_BUCKETS` ways
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let msb = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (msb - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (msb - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub
+}
+
+/// The largest value that falls in bucket `index`
+fn bucket_top(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let msb = (index / SUB_BUCKETS) as u32 + SUB_BUCKET_BITS - 1;
+    let shift = msb - SUB_BUCKET_BITS;
+    let sub = (index % SUB_BUCKETS) as u64;
+    (1u64 << msb) + (sub << shift) + ((1u64 << shift) - 1)
+}
+
+/// The counters a store keeps itself, the rest of a `StatsSnapshot` is read from its parts
+#[derive(Default)]
+pub(crate) struct Stats {
+    pub(crate) gets: LatencyHistogram,
+    pub(crate) puts: LatencyHistogram,
+    pub(crate) deletes: LatencyHistogram,
+    splits: AtomicU64,
+    merges: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn record_split(&self) {
+        self.splits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_merge(&self) {
+        self.merges.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// These counters with the rest read from the store's buffer, io engine and WAL
+    pub(crate) fn snapshot(
+        &self,
+        cache: &MiniPageBuffer,
+        io_engine: &IoEngine,
+        wal: &WalManager,
+    ) -> StatsSnapshot {
+        let (cache_hits, cache_misses) = cache.lookups();
+        StatsSnapshot {
+            gets: self.gets.snapshot(),
+            puts: self.puts.snapshot(),
+            deletes: self.deletes.snapshot(),
+            cache_hits,
+            cache_misses,
+            disk_reads: io_engine.pages_read(),
+            wal_bytes: wal.bytes_written(),
+            checkpoints: wal.checkpoints(),
+            splits: self.splits.load(Ordering::Relaxed),
+            merges: self.merges.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Byte stores behind the data file, the WAL and the TTL index.
+//!
+//! A store is either a file or, for in-memory databases, a growable buffer that lives as
+//! long as the database. Both offer positional reads and writes through `&self` for page
+//! I/O, and a cursor through `Read`, `Write` and `Seek` for the logs. Syncing a buffer is a
+//! no-op: an in-memory database is gone once it is dropped.
+//!
+//! Either can be wrapped in a `Delayed` store that sleeps before each operation, see
+//! `latency`. With the `failpoints` feature, writes, truncations and syncs are dropped once
+//! a failpoint crash has fired.
+//!
+//! Positional file I/O goes through `FileExt` on Unix and `seek_read`/`seek_write` on
+//! Windows. The Windows calls also move the file cursor, which is harmless as every cursor
+//! user seeks before it reads or writes.
+
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use crate::latency::{IoKind, LatencyInjector};
+
+pub enum Storage {
+    File(File),
+    Memory(MemoryFile),
+    /// Another store whose operations are held up by the injector first
+    Delayed(Box<Storage>, Arc<LatencyInjector>),
+}
+
+/// Why `Storage::try_lock` failed with `WouldBlock`, telling a lock held elsewhere apart
+/// from any other error of that kind
+#[derive(Debug)]
+pub struct FileLocked;
+
+impl FileLocked {
+    /// Whether `err` is a `try_lock` that found the file locked
+    pub fn is(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|inner| inner.is::<FileLocked>())
+    }
+}
+
+impl fmt::Display for FileLocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("file is locked by another handle")
+    }
+}
+
+impl Error for FileLocked {}
+
+#[derive(Default)]
+pub struct MemoryFile {
+    data: RwLock<Vec<u8>>,
+    /// Cursor of the `Read`, `Write` and `Seek` impls
+    pos: u64,
+}
+
+impl Storage {
+    /// Open `path` for reading and writing, creating it and its directory if needed
+    pub fn open(path: &Path) -> io::Result<Storage> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Storage::File(file))
+    }
+
+    /// Open the existing file at `path` for reading only; writes through it fail
+    pub fn open_read_only(path: &Path) -> io::Result<Storage> {
+        Ok(Storage::File(File::open(path)?))
+    }
+
+    pub fn memory() -> Storage {
+        Storage::Memory(MemoryFile::default())
+    }
+
+    /// A buffer holding a copy of the file at `path`, empty if there is none. Writes go to
+    /// the buffer and the file is left as it was
+    pub fn memory_copy_of(path: &Path) -> io::Result<Storage> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Storage::Memory(MemoryFile {
+            data: RwLock::new(data),
+            pos: 0,
+        }))
+    }
+
+    /// Hold up every read, write and sync of this store by `injector`'s latency
+    pub fn delayed(self, injector: Arc<LatencyInjector>) -> Storage {
+        Storage::Delayed(Box::new(self), injector)
+    }
+
+    pub fn is_memory(&self) -> bool {
+        match self {
+            Storage::File(_) => false,
+            Storage::Memory(_) => true,
+            Storage::Delayed(inner, _) => inner.is_memory(),
+        }
+    }
+
+    /// A second handle that syncs the same file, `None` in memory where syncing is a no-op
+    pub fn sync_handle(&self) -> io::Result<Option<Storage>> {
+        match self {
+            Storage::File(file) => Ok(Some(Storage::File(file.try_clone()?))),
+            Storage::Memory(_) => Ok(None),
+            Storage::Delayed(inner, injector) => Ok(inner
+                .sync_handle()?
+                .map(|handle| handle.delayed(Arc::clone(injector)))),
+        }
+    }
+
+    /// Take an exclusive advisory lock on the file, held until every handle to it is closed.
+    /// Fails with `WouldBlock` wrapping `FileLocked` while another open of the file holds
+    /// it, in this process or another; a buffer has nothing to lock
+    pub fn try_lock(&self) -> io::Result<()> {
+        match self {
+            Storage::File(file) => match file.try_lock() {
+                Ok(()) => Ok(()),
+                Err(TryLockError::WouldBlock) => {
+                    Err(io::Error::new(io::ErrorKind::WouldBlock, FileLocked))
+                }
+                Err(TryLockError::Error(err)) => Err(err),
+            },
+            Storage::Memory(_) => Ok(()),
+            Storage::Delayed(inner, _) => inner.try_lock(),
+        }
+    }
+
+    pub fn len(&self) -> io::Result<u64> {
+        match self {
+            Storage::File(file) => Ok(file.metadata()?.len()),
+            Storage::Memory(memory) => Ok(memory.len()),
+            Storage::Delayed(inner, _) => inner.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Truncate or zero-extend to `len` bytes
+    pub fn set_len(&self, len: u64) -> io::Result<()> {
+        if discarding() {
+            return Ok(());
+        }
+        match self {
+            Storage::File(file) => file.set_len(len),
+            Storage::Memory(memory) => {
+                let len = to_usize(len)?;
+                memory
+                    .data
+                    .write()
+                    .expect("memory file poisoned")
+                    .resize(len, 0);
+                Ok(())
+            }
+            Storage::Delayed(inner, injector) => {
+                injector.delay(IoKind::Write);
+                inner.set_len(len)
+            }
+        }
+    }
+
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        match self {
+            Storage::File(file) => platform::read_exact_at(file, buf, offset),
+            Storage::Memory(memory) => {
+                if memory.read_at(buf, offset)? < buf.len() {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+            Storage::Delayed(inner, injector) => {
+                injector.delay(IoKind::Read);
+                inner.read_exact_at(buf, offset)
+            }
+        }
+    }
+
+    pub fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        if discarding() {
+            return Ok(());
+        }
+        match self {
+            Storage::File(file) => platform::write_all_at(file, buf, offset),
+            Storage::Memory(memory) => memory.write_at(buf, offset),
+            Storage::Delayed(inner, injector) => {
+                injector.delay(IoKind::Write);
+                inner.write_all_at(buf, offset)
+            }
+        }
+    }
+
+    pub fn sync_data(&self) -> io::Result<()> {
+        if discarding() {
+            return Ok(());
+        }
+        match self {
+            Storage::File(file) => file.sync_data(),
+            Storage::Memory(_) => Ok(()),
+            Storage::Delayed(inner, injector) => {
+                injector.delay(IoKind::Sync);
+                inner.sync_data()
+            }
+        }
+    }
+}
+
+/// Whether a failpoint crash has fired, after which writes are dropped, see `failpoints`
+fn discarding() -> bool {
+    #[cfg(feature = "failpoints")]
+    return crate::failpoints::crashed();
+    #[cfg(not(feature = "failpoints"))]
+    false
+}
+
+impl MemoryFile {
+    fn len(&self) -> u64 {
+        self.data.read().expect("memory file poisoned").len() as u64
+    }
+
+    /// Copy out what there is of `buf.len()` bytes at `offset`, returns how many
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let data = self.data.read().expect("memory file poisoned");
+        let start = to_usize(offset)?.min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    /// Write `buf` at `offset`, zero filling any gap past the current end
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let mut data = self.data.write().expect("memory file poisoned");
+        let start = to_usize(offset)?;
+        let end = start + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl Read for Storage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Storage::File(file) => file.read(buf),
+            Storage::Memory(memory) => {
+                let len = memory.read_at(buf, memory.pos)?;
+                memory.pos += len as u64;
+                Ok(len)
+            }
+            Storage::Delayed(inner, injector) => {
+                injector.delay(IoKind::Read);
+                inner.read(buf)
+            }
+        }
+    }
+}
+
+impl Write for Storage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if discarding() {
+            return Ok(buf.len());
+        }
+        match self {
+            Storage::File(file) => file.write(buf),
+            Storage::Memory(memory) => {
+                memory.write_at(buf, memory.pos)?;
+                memory.pos += buf.len() as u64;
+                Ok(buf.len())
+            }
+            Storage::Delayed(inner, injector) => {
+                injector.delay(IoKind::Write);
+                inner.write(buf)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.flush(),
+            Storage::Memory(_) => Ok(()),
+            Storage::Delayed(inner, _) => inner.flush(),
+        }
+    }
+}
+
+impl Seek for Storage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Storage::File(file) => file.seek(pos),
+            Storage::Memory(memory) => {
+                let target = match pos {
+                    SeekFrom::Start(offset) => Some(offset),
+                    SeekFrom::End(delta) => memory.len().checked_add_signed(delta),
+                    SeekFrom::Current(delta) => memory.pos.checked_add_signed(delta),
+                };
+                memory.pos = target.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "seek out of range")
+                })?;
+                Ok(memory.pos)
+            }
+            Storage::Delayed(inner, _) => inner.seek(pos),
+        }
+    }
+}
+
+fn to_usize(offset: u64) -> io::Result<usize> {
+    usize::try_from(offset)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "offset out of range"))
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::{fs::File, io, os::unix::fs::FileExt};
+
+    pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        file.read_exact_at(buf, offset)
+    }
+
+    pub fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+        file.write_all_at(buf, offset)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::{fs::File, io, os::windows::fs::FileExt};
+
+    pub fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match file.seek_read(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    buf = &mut buf[read..];
+                    offset += read as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match file.seek_write(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    buf = &buf[written..];
+                    offset += written as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
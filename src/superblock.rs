@@ -0,0 +1,163 @@
+//! The data file's superblock, at the start of its metadata page.
+//!
+//! | magic: "QSSB" | version: u32 | page size: u32 | hash buckets: u64 | flags: u32 |
+//! | next addr: u64 | written by: 3 * u16 | padding: u16 | tag: 16 bytes |
+//!
+//! The tag authenticates the body before it, see `auth`. An all-zero block is a file that
+//! has not been formatted yet. Files from before the hash index read 0 buckets, the ordered
+//! tree, files from before page compression read no flags, and files from before the
+//! allocator was persisted read a next address of 0.
+//!
+//! Version 1 blocks end after the next address and name no crate version; opening one
+//! rewrites it as version 2. A block always records the crate version that last wrote it.
+
+use std::{fmt, io};
+
+use crate::{
+    auth::{MetadataAuth, TAG_LEN},
+    io_engine::PAGE_SIZE,
+};
+
+pub const SUPERBLOCK_MAGIC: [u8; 4] = *b"QSSB";
+pub const SUPERBLOCK_VERSION: u32 = 2;
+/// Blocks written before the crate version was recorded
+pub const SUPERBLOCK_VERSION_V1: u32 = 1;
+/// Bytes covered by the tag
+const BODY_LEN: usize = 40;
+const BODY_LEN_V1: usize = 32;
+pub const SUPERBLOCK_LEN: usize = BODY_LEN + TAG_LEN;
+/// Pages are stored as compressed blocks, see `page_map`
+pub const FLAG_COMPRESSED: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Superblock {
+    pub version: u32,
+    pub page_size: u32,
+    /// Leaf pages of a hash-index store, 0 for the ordered tree
+    pub hash_buckets: u64,
+    pub flags: u32,
+    /// One past the highest page address handed out, the allocator's high-water mark
+    pub next_addr: u64,
+    /// Crate that last wrote the block, `None` for version 1 blocks. Encoding always
+    /// writes a version 2 block naming the running crate
+    pub written_by: Option<CrateVersion>,
+}
+
+/// A quickstep release, as recorded in the superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CrateVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl CrateVersion {
+    /// The version of this build of the crate
+    pub fn current() -> CrateVersion {
+        let part = |value: &str| value.parse().unwrap_or(0);
+        CrateVersion {
+            major: part(env!("CARGO_PKG_VERSION_MAJOR")),
+            minor: part(env!("CARGO_PKG_VERSION_MINOR")),
+            patch: part(env!("CARGO_PKG_VERSION_PATCH")),
+        }
+    }
+}
+
+impl fmt::Display for CrateVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Superblock {
+    pub fn new() -> Superblock {
+        Superblock {
+            version: SUPERBLOCK_VERSION,
+            page_size: PAGE_SIZE as u32,
+            hash_buckets: 0,
+            flags: 0,
+            next_addr: 0,
+            written_by: Some(CrateVersion::current()),
+        }
+    }
+
+    pub fn encode(&self, auth: &MetadataAuth) -> [u8; SUPERBLOCK_LEN] {
+        let mut buf = [0u8; SUPERBLOCK_LEN];
+        buf[0..4].copy_from_slice(&SUPERBLOCK_MAGIC);
+        buf[4..8].copy_from_slice(&SUPERBLOCK_VERSION.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.page_size.to_le_bytes());
+        buf[12..20].copy_from_slice(&self.hash_buckets.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.flags.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.next_addr.to_le_bytes());
+        let written_by = CrateVersion::current();
+        buf[32..34].copy_from_slice(&written_by.major.to_le_bytes());
+        buf[34..36].copy_from_slice(&written_by.minor.to_le_bytes());
+        buf[36..38].copy_from_slice(&written_by.patch.to_le_bytes());
+        let tag = auth.tag(&buf[..BODY_LEN]);
+        buf[BODY_LEN..].copy_from_slice(&tag);
+        buf
+    }
+
+    /// `None` for an unformatted file, `InvalidData` for anything that is not a superblock
+    /// this build can use or whose tag does not check out under `auth`
+    pub fn decode(bytes: &[u8], auth: &MetadataAuth) -> io::Result<Option<Superblock>> {
+        let Some(superblock) = Superblock::decode_unverified(bytes)? else {
+            return Ok(None);
+        };
+        let body_len = match superblock.version {
+            SUPERBLOCK_VERSION => BODY_LEN,
+            SUPERBLOCK_VERSION_V1 => BODY_LEN_V1,
+            _ => return Err(invalid("unsupported superblock version")),
+        };
+        if !auth.verify(&bytes[..body_len], &bytes[body_len..body_len + TAG_LEN]) {
+            return Err(invalid("superblock failed authentication"));
+        }
+        if superblock.page_size as u64 != PAGE_SIZE {
+            return Err(invalid("data file uses a different page size"));
+        }
+        Ok(Some(superblock))
+    }
+
+    /// The fields of the block without checking its tag, version or page size, for
+    /// inspecting a file whose key is not at hand. `None` for an unformatted file,
+    /// `InvalidData` without the magic
+    pub fn decode_unverified(bytes: &[u8]) -> io::Result<Option<Superblock>> {
+        let bytes = &bytes[..SUPERBLOCK_LEN];
+        if bytes.iter().all(|b| *b == 0) {
+            return Ok(None);
+        }
+        if bytes[0..4] != SUPERBLOCK_MAGIC {
+            return Err(invalid("data file has no quickstep superblock"));
+        }
+        let u16_at = |at: usize| u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap());
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        Ok(Some(Superblock {
+            version,
+            page_size: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            hash_buckets: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+            flags: u32::from_le_bytes(bytes[20..24].try_into().unwrap()),
+            next_addr: u64::from_le_bytes(bytes[24..32].try_into().unwrap()),
+            written_by: (version >= SUPERBLOCK_VERSION).then(|| CrateVersion {
+                major: u16_at(32),
+                minor: u16_at(34),
+                patch: u16_at(36),
+            }),
+        }))
+    }
+}
+
+impl Superblock {
+    pub fn compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+}
+
+impl Default for Superblock {
+    fn default() -> Superblock {
+        Superblock::new()
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,128 @@
+//! Per-key expiry.
+//!
+//! A key's expiry is kept in the header of its record, see `attrs`, so it is logged,
+//! replayed and rolled back along with the value. Reads hide keys past their expiry, and
+//! the sweeper deletes them so the space is reclaimed when their leaves are next flushed,
+//! split or merged. The sweeper finds them through `TtlIndex`, which lists the keys with an
+//! expiry in memory: committed writes update it, and opening the store reads every leaf
+//! once to fill it again.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{attrs, error::QSError, iter::read_leaf_stored, map_table::PageId, Store};
+
+/// Milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Default)]
+pub struct TtlIndex {
+    state: Mutex<TtlState>,
+    /// Number of keys with an expiry, lets reads skip the lock when nothing expires
+    tracked: AtomicUsize,
+}
+
+#[derive(Default)]
+struct TtlState {
+    by_key: HashMap<Vec<u8>, u64>,
+    by_expiry: BTreeSet<(u64, Vec<u8>)>,
+}
+
+impl TtlIndex {
+    pub fn new() -> TtlIndex {
+        TtlIndex::default()
+    }
+
+    /// Set when `key` expires, `None` keeps it forever
+    pub fn set(&self, key: &[u8], expires_at: Option<u64>) {
+        let mut state = self.state.lock().expect("ttl mutex poisoned");
+        if let Some(old) = state.by_key.remove(key) {
+            state.by_expiry.remove(&(old, key.to_vec()));
+        }
+        if let Some(expires_at) = expires_at {
+            state.by_expiry.insert((expires_at, key.to_vec()));
+            state.by_key.insert(key.to_vec(), expires_at);
+        }
+        self.tracked.store(state.by_key.len(), Ordering::Release);
+    }
+
+    /// Forget every expiry, the keys they were for are gone
+    pub fn clear(&self) {
+        let mut state = self.state.lock().expect("ttl mutex poisoned");
+        *state = TtlState::default();
+        self.tracked.store(0, Ordering::Release);
+    }
+
+    pub fn expires_at(&self, key: &[u8]) -> Option<u64> {
+        if self.tracked.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        let state = self.state.lock().expect("ttl mutex poisoned");
+        state.by_key.get(key).copied()
+    }
+
+    /// At most `limit` keys past their expiry, soonest expired first
+    pub fn expired(&self, now: u64, limit: usize) -> Vec<Vec<u8>> {
+        let state = self.state.lock().expect("ttl mutex poisoned");
+        state
+            .by_expiry
+            .iter()
+            .take_while(|(at, _)| *at <= now)
+            .take(limit)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    pub fn next_expiry(&self) -> Option<u64> {
+        let state = self.state.lock().expect("ttl mutex poisoned");
+        state.by_expiry.first().map(|(at, _)| *at)
+    }
+
+    /// Number of keys with an expiry
+    pub fn len(&self) -> usize {
+        self.tracked.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Store {
+    /// Fill the expiry index from the records of every leaf, once recovery has replayed the
+    /// WAL and before anything else runs
+    pub(crate) fn index_expiries(&self) -> Result<(), QSError> {
+        let pages: Vec<PageId> = match self.hash_buckets {
+            Some(buckets) => (0..buckets).map(PageId::from_u64).collect(),
+            None => self
+                .leaf_starts()?
+                .iter()
+                .map(|start| Ok(self.inner_nodes.read_traverse_leaf(start)?.page))
+                .collect::<Result<_, QSError>>()?,
+        };
+        for page in pages {
+            let guard = self.map_table.read_page_entry(page)?;
+            // a leaf that cannot be read fails its reads too, `verify` reports it
+            let Ok(view) = read_leaf_stored(&self.cache, &self.io_engine, guard.node()) else {
+                continue;
+            };
+            for (key, stored) in &view.records {
+                if let Some(expires_at) = attrs::split(stored).0.expires_at {
+                    self.ttl.set(key, Some(expires_at));
+                }
+            }
+        }
+        Ok(())
+    }
+}
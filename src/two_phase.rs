@@ -0,0 +1,113 @@
+//! Two-phase commit for transactions coordinated with other stores.
+//!
+//! `QuickStepTx::prepare` logs a prepare marker and syncs it with the transaction's undo
+//! records, then returns a `PreparedTx` that can only be committed or aborted; it keeps its
+//! locks and no longer expires. A crash before the coordinator's decision leaves the
+//! transaction in doubt: recovery takes its writes out of the leaves, as for a transaction
+//! that never committed, but keeps its records in the log and lists it in
+//! `RecoveryReport::txns_in_doubt`. `QuickStep::resolve_in_doubt` then commits it, by
+//! replaying its writes in a new transaction, or aborts it. Its keys are not locked between
+//! the restart and the resolution, a write to them meanwhile is overwritten by a commit.
+
+use std::{collections::BTreeSet, sync::Mutex};
+
+use crate::{
+    error::QSError,
+    follower,
+    overflow::{self, OverflowPointer},
+    wal::{WalEntryKind, WalOp, WalTxnMarker, STRUCTURE_PAGE_ID, TXN_META_PAGE_ID},
+    QuickStepTx, Store,
+};
+
+/// A transaction prepared to commit, waiting for the coordinator's decision. Dropping it
+/// aborts it
+pub struct PreparedTx<'db> {
+    tx: QuickStepTx<'db>,
+}
+
+impl<'db> PreparedTx<'db> {
+    pub(crate) fn new(tx: QuickStepTx<'db>) -> PreparedTx<'db> {
+        PreparedTx { tx }
+    }
+
+    /// The id the prepare marker was logged under, for the coordinator to record
+    pub fn txn_id(&self) -> u64 {
+        self.tx.txn_id
+    }
+
+    pub fn commit(self) {
+        self.tx.commit();
+    }
+
+    pub fn abort(self) {
+        self.tx.abort();
+    }
+}
+
+/// Prepared transactions recovery left in doubt, until they are resolved
+#[derive(Default)]
+pub(crate) struct InDoubt {
+    txns: Mutex<BTreeSet<u64>>,
+}
+
+impl InDoubt {
+    pub(crate) fn new(txns: &[u64]) -> InDoubt {
+        InDoubt {
+            txns: Mutex::new(txns.iter().copied().collect()),
+        }
+    }
+
+    pub(crate) fn txns(&self) -> Vec<u64> {
+        let txns = self.txns.lock().expect("in-doubt mutex poisoned");
+        txns.iter().copied().collect()
+    }
+}
+
+/// Commit or abort the in-doubt transaction `txn_id` of `db`
+pub(crate) fn resolve(db: &Store, txn_id: u64, commit: bool) -> Result<(), QSError> {
+    // one resolution at a time, so a transaction is not replayed twice
+    let mut txns = db.in_doubt.txns.lock().expect("in-doubt mutex poisoned");
+    if !txns.contains(&txn_id) {
+        return Err(QSError::NotInDoubt { txn_id });
+    }
+    let ops: Vec<_> = db
+        .wal
+        .records()
+        .into_iter()
+        .filter(|record| {
+            record.txn_id == txn_id
+                && matches!(record.kind, WalEntryKind::Redo)
+                && record.page_id != STRUCTURE_PAGE_ID
+                && record.page_id != TXN_META_PAGE_ID
+        })
+        .map(|record| (record.key, record.op))
+        .filter(|(_, op)| !matches!(op, WalOp::TxnMarker(_) | WalOp::Smo(_)))
+        .collect();
+    // recovery took the writes out of the leaves, nothing points at their overflow chains
+    let chains: Vec<_> = ops
+        .iter()
+        .filter_map(|(_, op)| match op {
+            WalOp::Put { value } | WalOp::Merge { value, .. } => OverflowPointer::decode(value),
+            _ => None,
+        })
+        .collect();
+    let marker = if commit {
+        let ops = ops
+            .into_iter()
+            .map(|(key, op)| Ok((key, overflow::resolve_op(&db.io_engine, op)?)))
+            .collect::<Result<Vec<_>, QSError>>()?;
+        // replayed first, a crash before the marker leaves it in doubt to resolve again
+        follower::replay_txn(db, &ops)?;
+        WalTxnMarker::Commit
+    } else {
+        WalTxnMarker::Abort
+    };
+    db.wal
+        .append_txn_marker(marker, WalEntryKind::Redo, txn_id)?;
+    db.wal.sync()?;
+    txns.remove(&txn_id);
+    for chain in chains {
+        db.io_engine.free_overflow(chain);
+    }
+    Ok(())
+}
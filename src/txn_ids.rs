@@ -0,0 +1,113 @@
+//! Transaction ids, kept unique across opens by a mark in the data file's metadata page.
+//!
+//! | magic: "QSTX" | mark: u64 | tag: 16 bytes |
+//!
+//! The mark sits at `TXN_IDS_OFFSET`, between the superblock and the free list, and the tag
+//! authenticates the bytes before it, see `auth`. Ids are handed out in blocks of
+//! `TXN_ID_BLOCK`: the end of a block is written and synced before the first id of it is
+//! used, so a store reopened after a crash starts past every id the WAL may hold, at the
+//! cost of a gap. An all-zero region, as in files from before the mark, reads 0, and the ids
+//! are then only carried on past those found in the WAL.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    auth::{MetadataAuth, TAG_LEN},
+    io_engine::IoEngine,
+};
+
+pub const TXN_IDS_MAGIC: [u8; 4] = *b"QSTX";
+/// Where the mark starts in the metadata page
+pub const TXN_IDS_OFFSET: u64 = 256;
+const BODY_LEN: usize = 4 + 8;
+pub const TXN_IDS_LEN: usize = BODY_LEN + TAG_LEN;
+/// Ids reserved by each write of the mark
+pub const TXN_ID_BLOCK: u64 = 1024;
+
+/// The region holding `mark`
+pub fn encode(mark: u64, auth: &MetadataAuth) -> [u8; TXN_IDS_LEN] {
+    let mut buf = [0u8; TXN_IDS_LEN];
+    buf[0..4].copy_from_slice(&TXN_IDS_MAGIC);
+    buf[4..12].copy_from_slice(&mark.to_le_bytes());
+    let tag = auth.tag(&buf[..BODY_LEN]);
+    buf[BODY_LEN..].copy_from_slice(&tag);
+    buf
+}
+
+/// 0 for an all-zero region, `InvalidData` for anything else that is not a mark whose tag
+/// checks out under `auth`
+pub fn decode(bytes: &[u8], auth: &MetadataAuth) -> io::Result<u64> {
+    let bytes = &bytes[..TXN_IDS_LEN];
+    if bytes.iter().all(|b| *b == 0) {
+        return Ok(0);
+    }
+    if bytes[0..4] != TXN_IDS_MAGIC {
+        return Err(invalid("metadata page has no transaction id mark"));
+    }
+    if !auth.verify(&bytes[..BODY_LEN], &bytes[BODY_LEN..]) {
+        return Err(invalid("transaction id mark failed authentication"));
+    }
+    Ok(u64::from_le_bytes(bytes[4..12].try_into().unwrap()))
+}
+
+/// Hands out transaction ids below the mark, moving it on a block at a time
+pub(crate) struct TxnIds {
+    next: AtomicU64,
+    /// The mark last synced, ids from it on are not handed out until it moves
+    mark: AtomicU64,
+    /// Held while the mark is written, so it only ever grows on disk
+    write: Mutex<()>,
+}
+
+impl TxnIds {
+    /// Ids carrying on from the mark read at open
+    pub(crate) fn new(mark: u64) -> TxnIds {
+        TxnIds {
+            next: AtomicU64::new(mark.max(1)),
+            mark: AtomicU64::new(mark),
+            write: Mutex::new(()),
+        }
+    }
+
+    /// Ids carrying on from the mark read at open that never record a new one, for a store
+    /// opened read-only whose ids only reach its in-memory WAL
+    pub(crate) fn unrecorded(mark: u64) -> TxnIds {
+        TxnIds {
+            next: AtomicU64::new(mark.max(1)),
+            mark: AtomicU64::new(u64::MAX),
+            write: Mutex::new(()),
+        }
+    }
+
+    /// A new transaction id, syncing the next block's mark first when this one is used up
+    pub(crate) fn next(&self, io_engine: &IoEngine) -> u64 {
+        let txn_id = self.next.fetch_add(1, Ordering::Relaxed);
+        if txn_id < self.mark.load(Ordering::Acquire) {
+            return txn_id;
+        }
+        let _write = self.write.lock().expect("txn id mutex poisoned");
+        if txn_id >= self.mark.load(Ordering::Acquire) {
+            let mark = txn_id + TXN_ID_BLOCK;
+            io_engine
+                .write_txn_id_mark(mark)
+                .expect("failed to record transaction ids");
+            self.mark.store(mark, Ordering::Release);
+        }
+        txn_id
+    }
+
+    /// Hand out ids after `txn_id` only, one found in the WAL of a file without the mark
+    pub(crate) fn skip_past(&self, txn_id: u64) {
+        self.next.fetch_max(txn_id + 1, Ordering::AcqRel);
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
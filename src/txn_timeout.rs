@@ -0,0 +1,196 @@
+//! Expiry of abandoned transactions.
+//!
+//! A transaction that is never committed, aborted or dropped, because it was leaked or its
+//! thread is stuck, keeps its page locks and its WAL records pinned for as long as the
+//! store is open. With `QuickStepConfig::with_txn_timeout`, each transaction publishes the
+//! pages it locks and its undo log, and stamps the time at the end of every operation. A
+//! background thread watches for transactions idle past the timeout and expires them: their
+//! locks are taken over, their writes rolled back, an Abort marker written and the locks
+//! released.
+//!
+//! An expired transaction that is still around fails every operation with
+//! `QSError::TxnExpired`, and its commit does nothing. Values it read with `get` are
+//! copied out of the leaf rather than borrowed from it, so they stay valid once its locks
+//! are gone.
+
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    error::QSError,
+    lock_manager::{HeldLocks, LockManager},
+    overflow::OverflowPointer,
+    ttl::now_millis,
+    wal::WalEntryKind,
+    QuickStepTx, Store, TxState, UndoAction,
+};
+
+const IDLE: u8 = 0;
+const BUSY: u8 = 1;
+const EXPIRED: u8 = 2;
+
+/// What an expiring transaction shares with the registry
+pub(crate) struct TxnSlot {
+    txn_id: u64,
+    /// `IDLE` between operations, `BUSY` during one, `EXPIRED` once taken over
+    state: AtomicU8,
+    /// End of the last operation, in milliseconds since the unix epoch
+    last_active: AtomicU64,
+    pub(crate) held: Arc<HeldLocks>,
+    pub(crate) undo_log: Mutex<Vec<UndoAction>>,
+    /// Overflow chains the transaction wrote, freed when it is rolled back
+    pub(crate) overflow_written: Mutex<Vec<OverflowPointer>>,
+}
+
+impl TxnSlot {
+    /// Mark the transaction busy until the returned op drops, `None` when it already is;
+    /// fails once it has expired
+    pub(crate) fn enter(self: &Arc<Self>) -> Result<Option<TxnOp>, QSError> {
+        match self
+            .state
+            .compare_exchange(IDLE, BUSY, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => Ok(Some(TxnOp {
+                slot: Arc::clone(self),
+            })),
+            Err(EXPIRED) => Err(QSError::TxnExpired {
+                txn_id: self.txn_id,
+            }),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// An operation in progress on an expiring transaction, it cannot expire until this drops
+pub(crate) struct TxnOp {
+    slot: Arc<TxnSlot>,
+}
+
+impl Drop for TxnOp {
+    fn drop(&mut self) {
+        self.slot.last_active.store(now_millis(), Ordering::Release);
+        self.slot.state.store(IDLE, Ordering::Release);
+    }
+}
+
+/// The open transactions that expire once idle for `timeout`
+pub(crate) struct TxnRegistry {
+    timeout: Duration,
+    slots: Mutex<HashMap<u64, Arc<TxnSlot>>>,
+    expired: AtomicU64,
+}
+
+impl TxnRegistry {
+    pub(crate) fn new(timeout: Duration) -> TxnRegistry {
+        TxnRegistry {
+            timeout,
+            slots: Mutex::new(HashMap::new()),
+            expired: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn register(&self, txn_id: u64) -> Arc<TxnSlot> {
+        let slot = Arc::new(TxnSlot {
+            txn_id,
+            state: AtomicU8::new(IDLE),
+            last_active: AtomicU64::new(now_millis()),
+            held: Arc::new(HeldLocks::default()),
+            undo_log: Mutex::new(Vec::new()),
+            overflow_written: Mutex::new(Vec::new()),
+        });
+        let mut slots = self.slots.lock().expect("txn registry mutex poisoned");
+        slots.insert(txn_id, Arc::clone(&slot));
+        slot
+    }
+
+    /// Forget a transaction that has committed or aborted
+    pub(crate) fn finish(&self, txn_id: u64) {
+        let mut slots = self.slots.lock().expect("txn registry mutex poisoned");
+        slots.remove(&txn_id);
+    }
+
+    fn idle_since(&self, now: u64) -> u64 {
+        now.saturating_sub(self.timeout.as_millis() as u64)
+    }
+
+    /// Whether a transaction has been idle past the timeout
+    pub(crate) fn any_idle(&self, now: u64) -> bool {
+        let since = self.idle_since(now);
+        let slots = self.slots.lock().expect("txn registry mutex poisoned");
+        slots.values().any(|slot| {
+            slot.state.load(Ordering::Acquire) == IDLE
+                && slot.last_active.load(Ordering::Acquire) < since
+        })
+    }
+
+    /// Take the transactions idle past the timeout out of the registry, marking them expired
+    fn claim_idle(&self, now: u64) -> Vec<Arc<TxnSlot>> {
+        let since = self.idle_since(now);
+        let mut slots = self.slots.lock().expect("txn registry mutex poisoned");
+        let claimed: Vec<_> = slots
+            .values()
+            .filter(|slot| {
+                slot.last_active.load(Ordering::Acquire) < since
+                    && slot
+                        .state
+                        .compare_exchange(IDLE, EXPIRED, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+            })
+            .cloned()
+            .collect();
+        for slot in &claimed {
+            slots.remove(&slot.txn_id);
+        }
+        claimed
+    }
+
+    /// Transactions expired since open
+    pub(crate) fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+/// Roll back and release the transactions idle past the timeout, returns how many
+pub(crate) fn expire_idle(db: &Store, registry: &TxnRegistry) -> usize {
+    let claimed = registry.claim_idle(now_millis());
+    for slot in &claimed {
+        let mut lock_manager = LockManager::for_txn(slot.txn_id, &db.lock_owners);
+        // SAFETY: the slot is expired, so the transaction disowns its locks without using
+        // them again, and nothing it returned borrows from a page: `get` copies values out
+        // of the leaf when the transaction can expire
+        unsafe { lock_manager.adopt_locks(&db.map_table, &slot.held) };
+        let undo_log = mem::take(&mut *slot.undo_log.lock().expect("undo log mutex poisoned"));
+        let mut tx = QuickStepTx {
+            db,
+            lock_manager,
+            txn_id: slot.txn_id,
+            wal_entry_kind: WalEntryKind::Redo,
+            undo_log,
+            state: TxState::Active,
+            ttl_changes: Vec::new(),
+            changes: Vec::new(),
+            serializable: false,
+            expiry: None,
+            replica: false,
+            activity_record: None,
+            overflow_written: mem::take(
+                &mut *slot
+                    .overflow_written
+                    .lock()
+                    .expect("overflow chains mutex poisoned"),
+            ),
+            overflow_replaced: Vec::new(),
+            value_buf: Vec::new(),
+        };
+        tx.abort_in_place();
+        registry.expired.fetch_add(1, Ordering::Relaxed);
+    }
+    claimed.len()
+}
@@ -1,12 +1,10 @@
 use std::{
-    error::Error,
     mem::{size_of, transmute},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use crate::{
-    buffer::{MiniPageBuffer, MiniPageIndex},
-    lock_manager::{self, LockManager, WriteGuardWrapper},
-    map_table::{PageId, PageWriteGuard},
+    buffer::MiniPageIndex, error::QSError, lock_manager::WriteGuardWrapper, map_table::PageId,
     QuickStepTx,
 };
 
@@ -46,9 +44,11 @@ impl KVMeta {
     }
 
     #[inline]
-    #[must_use]
     pub fn set_key_size(&mut self, key_size: u16) {
-        todo!()
+        const KEY_SIZE_MASK: u64 = 0x3FFF << 50;
+
+        self.0 &= !KEY_SIZE_MASK;
+        self.0 |= ((key_size as u64) & 0x3FFF) << 50;
     }
 
     #[inline]
@@ -57,9 +57,11 @@ impl KVMeta {
     }
 
     #[inline]
-    #[must_use]
     pub fn set_val_size(&mut self, val_size: u16) {
-        todo!()
+        const VAL_SIZE_MASK: u64 = 0x3FFF << 36;
+
+        self.0 &= !VAL_SIZE_MASK;
+        self.0 |= ((val_size as u64) & 0x3FFF) << 36;
     }
 
     #[inline]
@@ -68,9 +70,8 @@ impl KVMeta {
     }
 
     #[inline]
-    #[must_use]
     pub fn set_offset(&mut self, offset: u16) {
-        const OFFSET_MAST: u64 = { (u16::MAX as u64) << 20 };
+        const OFFSET_MAST: u64 = (u16::MAX as u64) << 20;
 
         // clear offset bits
         self.0 &= !OFFSET_MAST;
@@ -146,7 +147,7 @@ impl KVRecordType {
     pub fn is_dirty(&self) -> bool {
         match self {
             KVRecordType::Insert | KVRecordType::Tombstone => true,
-            KVRecordType::Cache | KVRecordType::Phantom => true,
+            KVRecordType::Cache | KVRecordType::Phantom => false,
         }
     }
 
@@ -154,7 +155,7 @@ impl KVRecordType {
     pub fn exists(&self) -> bool {
         match self {
             KVRecordType::Insert | KVRecordType::Cache => true,
-            KVRecordType::Tombstone | KVRecordType::Phantom => true,
+            KVRecordType::Tombstone | KVRecordType::Phantom => false,
         }
     }
 }
@@ -162,7 +163,7 @@ impl KVRecordType {
 /// represents node size/ type
 /// if not a Leaf, then for discriminent x, 2^x * 8 is the number of words needed
 /// takes 3 bits to store
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum NodeSize {
     N64 = 0,
@@ -175,23 +176,31 @@ pub enum NodeSize {
 }
 
 impl NodeSize {
+    /// Every size, smallest first
+    pub const ALL: [NodeSize; 7] = [
+        NodeSize::N64,
+        NodeSize::N128,
+        NodeSize::N256,
+        NodeSize::N512,
+        NodeSize::N1K,
+        NodeSize::N2K,
+        NodeSize::LeafPage,
+    ];
+
     pub const fn index(&self) -> usize {
         *self as usize
     }
 
+    /// The smallest size with room for `bytes`
     pub fn from_byte_num(bytes: usize) -> Option<NodeSize> {
-        let cand = bytes.next_power_of_two() / 64;
-
-        Some(match cand {
-            0 => NodeSize::N64,
-            1 => NodeSize::N128,
-            2 => NodeSize::N256,
-            3 => NodeSize::N512,
-            4 => NodeSize::N1K,
-            5 => NodeSize::N2K,
-            6 => NodeSize::LeafPage,
-            _ => return None,
-        })
+        NodeSize::ALL
+            .into_iter()
+            .find(|size| size.size_in_bytes() >= bytes)
+    }
+
+    /// The size a mini-page grows into when it runs out of room, `None` for a full leaf
+    pub fn next(&self) -> Option<NodeSize> {
+        NodeSize::ALL.get(self.index() + 1).copied()
     }
 
     pub const fn size_in_words(&self) -> usize {
@@ -211,19 +220,79 @@ impl NodeSize {
 /// | Leaf | size | evicting | free-listed | live | split | record count
 ///   48b  |  3b  |   1b     |      1b     | 1b   |   1b  |      9b
 ///
-/// | NodeId | padding | free on disk
+/// | NodeId | hot | padding | free on disk
+///    48b   | 1b  |   2b    |     13b
 /// Note: each record must take up at least 8 bytes, owing to the metadata, so there can only be 512/page
 ///     this means that 9b is sufficient to encode the record count
+///
+/// The first word is atomic: evictors scan headers without the page lock, so the slot state
+/// bits in it only change by compare-exchange, see `SlotState`
 #[repr(C)]
-// pub struct NodeMeta(AtomicU64, AtomicU64);
-pub struct NodeMeta(u64, u64);
+pub struct NodeMeta(AtomicU64, u64);
 
 const RECORD_COUNT_MASK: u64 = 0x0000_0000_0000_01FF;
 const SPLIT_BIT: u64 = 1 << 9;
 const LIVE_BIT: u64 = 1 << 10;
 const FREELIST_BIT: u64 = 1 << 11;
 const EVICT_BIT: u64 = 1 << 12;
-const HOT_BIT: u64 = 1 << 13;
+const STATE_MASK: u64 = LIVE_BIT | FREELIST_BIT | EVICT_BIT;
+/// Stored in the second word, the size field occupies bits 13..16 of the first
+const HOT_BIT: u64 = 1 << 15;
+const FREE_SPACE_MASK: u64 = 0x1FFF;
+
+/// Where a mini-page slot is in its life, kept in the live, evicting and free-listed bits of
+/// the first header word. Each change is a compare-exchange from the state the caller
+/// expects, so an evictor and a writer cannot both take the same slot:
+///
+/// ```text
+/// Unused -> Live -> Evicting -> Unused -> FreeListed -> Unused
+///             |        |
+///             |        +-> Live (eviction aborted)
+///             +-> Unused (freed by its page's writer)
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotState {
+    /// Neither in use nor on a freelist: being set up, or freed past the head
+    Unused,
+    /// Holds the mini-page of the page in its header
+    Live,
+    /// Claimed by an evictor holding the page's write lock, which flushes and frees it
+    Evicting,
+    /// On its size's freelist, waiting to be handed out again
+    FreeListed,
+}
+
+impl SlotState {
+    fn from_header(word: u64) -> SlotState {
+        match (
+            word & LIVE_BIT != 0,
+            word & EVICT_BIT != 0,
+            word & FREELIST_BIT != 0,
+        ) {
+            (true, true, _) => SlotState::Evicting,
+            (true, false, _) => SlotState::Live,
+            (false, _, true) => SlotState::FreeListed,
+            (false, _, false) => SlotState::Unused,
+        }
+    }
+
+    fn bits(self) -> u64 {
+        match self {
+            SlotState::Unused => 0,
+            SlotState::Live => LIVE_BIT,
+            SlotState::Evicting => LIVE_BIT | EVICT_BIT,
+            SlotState::FreeListed => FREELIST_BIT,
+        }
+    }
+}
+
+/// A mini-page claimed by `NodeMeta::mark_for_eviction`, with the page and size the slot
+/// held, checked again when the claim ends
+#[must_use]
+pub struct EvictionClaim {
+    page: PageId,
+    size: NodeSize,
+}
 
 impl NodeMeta {
     // pub unsafe fn from_repr(repr: u64) -> NodeMeta {
@@ -234,17 +303,27 @@ impl NodeMeta {
     //     self.0
     // }
 
+    /// Format the mini-page at `index` and register it in the mapping table
+    ///
+    /// # Safety
+    /// `index` must be a freshly allocated mini-page of `size` that nothing else references
     pub unsafe fn init<'db>(
         tx: &mut QuickStepTx<'db>,
         index: usize,
         size: NodeSize,
         disk_addr: Option<u64>,
-    ) -> WriteGuardWrapper<'db> {
+    ) -> Result<WriteGuardWrapper<'db>, QSError> {
         let node_ptr = tx.db.cache.get_meta_ptr(index);
-        let disk_addr = disk_addr.unwrap_or_else(|| tx.db.io_engine.get_new_addr());
-        let guard = tx.db.map_table.create_page_entry(MiniPageIndex::new(index));
-
-        let mut w0 = (disk_addr as u64) << 16;
+        let disk_addr = match disk_addr {
+            Some(addr) => addr,
+            None => tx.db.io_engine.get_new_addr()?,
+        };
+        let guard = tx
+            .db
+            .map_table
+            .create_page_entry(MiniPageIndex::new(index))?;
+
+        let mut w0 = disk_addr << 16;
         w0 |= (size as u64) << 13;
         w0 &= !(SPLIT_BIT | FREELIST_BIT | EVICT_BIT);
         w0 |= LIVE_BIT;
@@ -253,82 +332,171 @@ impl NodeMeta {
         let free = 4096 - size_of::<NodeMeta>();
         w1 |= free as u64;
 
-        node_ptr.write(NodeMeta(w0, w1));
+        node_ptr.write(NodeMeta(AtomicU64::new(w0), w1));
+
+        Ok(tx.lock_manager.insert_write_lock(guard))
+    }
 
-        tx.lock_manager.insert_write_lock(guard)
+    /// Give an allocated slot that will not be used a header of its size, so it can be
+    /// deallocated and skipped by eviction scans
+    ///
+    /// # Safety
+    /// `node_ptr` must be a mini-page slot of `size` that nothing else references
+    pub unsafe fn init_unused(node_ptr: *mut NodeMeta, size: NodeSize) {
+        node_ptr.write(NodeMeta(AtomicU64::new((size as u64) << 13), 0));
     }
 }
 
 impl NodeMeta {
+    #[inline]
+    fn header(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Change the first word through `f`, keeping state transitions made meanwhile
+    #[inline]
+    fn update_header(&self, f: impl Fn(u64) -> u64) {
+        let mut word = self.header();
+        loop {
+            match self
+                .0
+                .compare_exchange_weak(word, f(word), Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return,
+                Err(current) => word = current,
+            }
+        }
+    }
+
     #[inline]
     pub fn leaf(&self) -> u64 {
-        self.0 >> 16
+        self.header() >> 16
     }
 
     #[inline]
     pub fn size(&self) -> NodeSize {
-        let size_byte = ((self.0 >> 13) & 0b111) as u8;
+        let size_byte = ((self.header() >> 13) & 0b111) as u8;
         // SAFETY: this was just masked to 3 bits and all 3bit values are valid
         unsafe { transmute(size_byte) }
     }
 
-    fn set_flag(&mut self, mask: u64, val: bool) {
-        if val {
-            self.0 |= mask;
-        } else {
-            self.0 &= !mask;
+    pub fn state(&self) -> SlotState {
+        SlotState::from_header(self.header())
+    }
+
+    /// Move the slot from `from` to `to`, failing with the state it is in otherwise. Other
+    /// header bits changing meanwhile only retry the exchange
+    pub fn transition(&self, from: SlotState, to: SlotState) -> Result<(), SlotState> {
+        let mut word = self.header();
+        loop {
+            let state = SlotState::from_header(word);
+            if state != from {
+                return Err(state);
+            }
+            let next = (word & !STATE_MASK) | to.bits();
+            match self
+                .0
+                .compare_exchange_weak(word, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Ok(()),
+                Err(current) => word = current,
+            }
         }
     }
 
+    /// Whether the slot holds a mini-page, counting one being evicted
     pub fn is_live(&self) -> bool {
-        (self.0 & LIVE_BIT) != 0
+        matches!(self.state(), SlotState::Live | SlotState::Evicting)
     }
 
+    /// Put a slot the caller owns in use, or take it out of use
     pub fn set_live(&mut self, live: bool) {
-        self.set_flag(LIVE_BIT, live);
+        let (from, to) = match live {
+            true => (SlotState::Unused, SlotState::Live),
+            false => (SlotState::Live, SlotState::Unused),
+        };
+        if let Err(state) = self.transition(from, to) {
+            panic!("mini-page set live={live} while {state:?}");
+        }
+    }
+
+    /// Whether the slot sits on its size's freelist, waiting to be handed out again
+    pub fn is_free_listed(&self) -> bool {
+        self.state() == SlotState::FreeListed
+    }
+
+    /// Move an unused slot onto its freelist, or off it
+    pub fn set_free_listed(&self, val: bool) {
+        let (from, to) = match val {
+            true => (SlotState::Unused, SlotState::FreeListed),
+            false => (SlotState::FreeListed, SlotState::Unused),
+        };
+        if let Err(state) = self.transition(from, to) {
+            panic!("mini-page set free-listed={val} while {state:?}");
+        }
     }
 
     pub fn is_being_evicted(&self) -> bool {
-        (self.0 & EVICT_BIT) != 0
+        self.state() == SlotState::Evicting
     }
 
-    pub fn set_being_evicted(&mut self, val: bool) {
-        self.set_flag(EVICT_BIT, val);
+    /// Claim a live page for eviction, `None` if it is dead or already claimed. The caller
+    /// must hold the page's write lock, and end the claim with `finish_eviction` or
+    /// `abort_eviction`
+    pub fn mark_for_eviction(&self) -> Option<EvictionClaim> {
+        self.transition(SlotState::Live, SlotState::Evicting).ok()?;
+        Some(EvictionClaim {
+            page: self.page_id(),
+            size: self.size(),
+        })
     }
 
-    pub fn mark_for_eviction(&mut self) -> Result<(), ()> {
-        if self.is_being_evicted() || !self.is_live() {
-            return Err(());
-        }
-        self.set_being_evicted(true);
-        Ok(())
+    /// Hand a page that could not be flushed back to its page, live
+    pub fn abort_eviction(&self, claim: EvictionClaim) {
+        self.end_eviction(claim, SlotState::Live);
     }
 
-    pub fn clear_eviction(&mut self) {
-        self.set_being_evicted(false);
+    /// Take the flushed page out of use, emptied, so its slot can be freed
+    pub fn finish_eviction(&self, claim: EvictionClaim) {
+        self.end_eviction(claim, SlotState::Unused);
+        self.update_header(|word| word & !RECORD_COUNT_MASK);
+    }
+
+    fn end_eviction(&self, claim: EvictionClaim, to: SlotState) {
+        // the claimed page holds the slot until the claim ends, a different one means the
+        // slot was freed and handed out under the evictor
+        assert!(
+            self.page_id() == claim.page && self.size() == claim.size,
+            "mini-page of page {} changed while it was being evicted",
+            claim.page.0
+        );
+        if let Err(state) = self.transition(SlotState::Evicting, to) {
+            panic!("eviction of page {} ended while {state:?}", claim.page.0);
+        }
     }
 
     pub fn is_hot(&self) -> bool {
-        (self.0 & HOT_BIT) != 0
+        (self.1 & HOT_BIT) != 0
     }
 
     pub fn mark_hot(&mut self) {
-        self.set_flag(HOT_BIT, true);
+        self.1 |= HOT_BIT;
     }
 
     pub fn clear_hot(&mut self) {
-        self.set_flag(HOT_BIT, false);
+        self.1 &= !HOT_BIT;
     }
 
     #[inline]
     pub fn record_count(&self) -> u16 {
-        (self.0 & RECORD_COUNT_MASK) as u16
+        (self.header() & RECORD_COUNT_MASK) as u16
     }
 
     #[inline]
     pub fn set_record_count(&mut self, count: u16) {
-        self.0 &= !RECORD_COUNT_MASK;
-        self.0 |= (count as u64) & RECORD_COUNT_MASK;
+        self.update_header(|word| {
+            (word & !RECORD_COUNT_MASK) | ((count as u64) & RECORD_COUNT_MASK)
+        });
     }
 
     #[inline]
@@ -349,28 +517,39 @@ impl NodeMeta {
         self.set_record_count(next);
     }
 
+    /// For a mini-page smaller than a leaf, the room its disk leaf had for more records when
+    /// the page was cached. The page's records must fit in it, so the two always merge
+    pub fn disk_room(&self) -> usize {
+        (self.1 & FREE_SPACE_MASK) as usize
+    }
+
+    pub fn set_disk_room(&mut self, room: usize) {
+        self.1 = (self.1 & !FREE_SPACE_MASK) | (room as u64 & FREE_SPACE_MASK);
+    }
+
     #[inline]
     pub fn page_id(&self) -> PageId {
         PageId(self.1 >> 16)
     }
 
     pub fn reset_header(&mut self, page_id: PageId, size: NodeSize, disk_addr: u64) {
-        let mut w0 = (disk_addr as u64) << 16;
+        let mut w0 = disk_addr << 16;
         w0 |= (size as u64) << 13;
         w0 &= !(SPLIT_BIT | FREELIST_BIT | EVICT_BIT);
         w0 |= LIVE_BIT;
-        self.0 = w0;
+        // the caller owns the slot, nothing else changes its state meanwhile
+        self.0.store(w0, Ordering::Release);
 
         let free = size.size_in_bytes() - size_of::<NodeMeta>();
         let mut w1 = (page_id.0) << 16;
-        w1 |= (free as u64) & 0xFFFF;
+        w1 |= (free as u64) & FREE_SPACE_MASK;
         self.1 = w1;
         self.set_record_count(0);
     }
 
     pub fn set_disk_addr(&mut self, disk_addr: u64) {
         const LOWER_MASK: u64 = (1u64 << 16) - 1;
-        self.0 = (self.0 & LOWER_MASK) | (disk_addr << 16);
+        self.update_header(|word| (word & LOWER_MASK) | (disk_addr << 16));
     }
 
     pub fn set_page_id_field(&mut self, page_id: PageId) {
@@ -393,6 +572,34 @@ impl NodeMeta {
     }
 }
 
+/// Put the integers of a page image laid out as in memory in their on-disk order. The two
+/// header words and the `KVMeta` of each record are stored little-endian, so a file reads back
+/// the same on any target; the keys and values after them are bytes already. A no-op on
+/// little-endian targets
+pub fn page_to_disk_order(image: &mut [u8; 4096]) {
+    let count = u64::from_ne_bytes(image[..8].try_into().unwrap()) & RECORD_COUNT_MASK;
+    for word in meta_words(image, count) {
+        let value = u64::from_ne_bytes((&*word).try_into().unwrap());
+        word.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Lay out the integers of a page image read from disk as in memory, see
+/// `page_to_disk_order`
+pub fn page_from_disk_order(image: &mut [u8; 4096]) {
+    let count = u64::from_le_bytes(image[..8].try_into().unwrap()) & RECORD_COUNT_MASK;
+    for word in meta_words(image, count) {
+        let value = u64::from_le_bytes((&*word).try_into().unwrap());
+        word.copy_from_slice(&value.to_ne_bytes());
+    }
+}
+
+/// The header words and the `count` record metadata words after them
+fn meta_words(image: &mut [u8; 4096], count: u64) -> impl Iterator<Item = &mut [u8]> {
+    let words = (size_of::<NodeMeta>() / 8 + count as usize).min(image.len() / 8);
+    image[..words * 8].chunks_exact_mut(8)
+}
+
 // Idea: use this layout, and use a macro for match, a la congee
 // | padding | address | type |
 // |   15b   |   48b   |  1b  |
@@ -4,23 +4,56 @@ pub fn store_u48(val: u64) -> [u8; 6] {
     [bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]
 }
 
-pub fn extract_u48(val: *const u8) -> u64 {
-    let slice = unsafe { std::slice::from_raw_parts(val, 6) };
+/// # Safety
+/// `val` must point to at least 6 readable bytes
+pub unsafe fn extract_u48(val: *const u8) -> u64 {
+    let slice = std::slice::from_raw_parts(val, 6);
     let mut buf = [0u8; 8];
     buf[2..].copy_from_slice(slice);
     u64::from_be_bytes(buf)
 }
 
-pub fn store_u32(val: u64) -> [u8; 8] {
-    let masked = (val as u32).to_be_bytes();
-    let mut buf = [0u8; 8];
-    buf[4..].copy_from_slice(&masked);
-    buf
+pub fn store_u32(val: u64) -> [u8; 4] {
+    (val as u32).to_be_bytes()
 }
 
-pub fn extract_u32(val: *const u8) -> u32 {
-    let slice = unsafe { std::slice::from_raw_parts(val, 4) };
+/// # Safety
+/// `val` must point to at least 4 readable bytes
+pub unsafe fn extract_u32(val: *const u8) -> u32 {
+    let slice = std::slice::from_raw_parts(val, 4);
     let mut buf = [0u8; 4];
     buf.copy_from_slice(slice);
     u32::from_be_bytes(buf)
 }
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE), continuing from a previous `crc` so a buffer can be fed in pieces; start
+/// from 0
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
@@ -0,0 +1,176 @@
+use std::{fmt, ops::Deref, ptr::NonNull, slice};
+
+use crate::{buffer::MiniPageBuffer, io_engine::DiskLeaf, map_table::PageId};
+
+/// A value borrowed from the leaf it lives in, either the mini-page or the disk leaf image
+/// held by the transaction's page guard. It is valid as long as the transaction's read
+/// lock on the page, so no copy is made.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ValueRef<'tx> {
+    bytes: &'tx [u8],
+}
+
+impl<'tx> ValueRef<'tx> {
+    pub(crate) fn new(bytes: &'tx [u8]) -> ValueRef<'tx> {
+        ValueRef { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &'tx [u8] {
+        self.bytes
+    }
+}
+
+impl Deref for ValueRef<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+impl AsRef<[u8]> for ValueRef<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+impl PartialEq<[u8]> for ValueRef<'_> {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.bytes == other
+    }
+}
+
+impl fmt::Debug for ValueRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ValueRef").field(&self.bytes).finish()
+    }
+}
+
+/// What a record looks like without reading its value, see `QuickStepTx::entry_info`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Length of the stored value, 0 for a tombstone
+    pub value_len: usize,
+    /// The record is held in the leaf's mini-page, so reading it will not touch the file
+    pub cached: bool,
+    /// The value is stored in overflow pages, reading it reads them from the file whether
+    /// or not the record is cached, see `overflow`
+    pub overflow: bool,
+    /// The value is stored in a blob file, reading it reads the file whether or not the
+    /// record is cached, see `blob`
+    pub blob: bool,
+    /// The record is a delete that has not been flushed to the disk leaf yet
+    pub tombstoned: bool,
+    /// The value is kept but hidden by `QuickStepTx::soft_delete`
+    pub soft_deleted: bool,
+    /// The record holds merge operands not folded yet, reading it runs the merge operator
+    /// and `value_len` is the length of the record holding them, see `merge`
+    pub merge_pending: bool,
+    /// Leaf the key lives in
+    pub page_id: PageId,
+}
+
+/// A value that stays readable after its transaction ends. A value cached in a mini-page
+/// pins the page's slot: writers move the page elsewhere rather than change it, and the
+/// slot is freed when the last pin drops. A value only on disk keeps the leaf image it was
+/// read from, and one stored in overflow pages or a blob file is read out into a buffer of
+/// its own.
+pub struct PinnedValue<'db> {
+    backing: Backing<'db>,
+    len: usize,
+}
+
+enum Backing<'db> {
+    MiniPage {
+        buffer: &'db MiniPageBuffer,
+        index: usize,
+        ptr: NonNull<u8>,
+    },
+    DiskLeaf {
+        leaf: DiskLeaf,
+        offset: usize,
+    },
+    Owned(Vec<u8>),
+}
+
+impl<'db> PinnedValue<'db> {
+    /// Pin the mini-page at `index`, `value` must live inside it
+    pub(crate) fn in_mini_page(
+        buffer: &'db MiniPageBuffer,
+        index: usize,
+        value: &[u8],
+    ) -> PinnedValue<'db> {
+        buffer.pin(index);
+        PinnedValue {
+            backing: Backing::MiniPage {
+                buffer,
+                index,
+                ptr: NonNull::from(value).cast(),
+            },
+            len: value.len(),
+        }
+    }
+
+    /// Keep `leaf` alive for the `len` bytes at `offset` in it
+    pub(crate) fn in_disk_leaf(leaf: DiskLeaf, offset: usize, len: usize) -> PinnedValue<'db> {
+        assert!(offset + len <= leaf.as_bytes().len());
+        PinnedValue {
+            backing: Backing::DiskLeaf { leaf, offset },
+            len,
+        }
+    }
+
+    /// Own `value`, read out of its overflow chain or blob file
+    pub(crate) fn owned(value: Vec<u8>) -> PinnedValue<'db> {
+        PinnedValue {
+            len: value.len(),
+            backing: Backing::Owned(value),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.backing {
+            // SAFETY: the slot is pinned, so it is neither freed nor rewritten while we live
+            Backing::MiniPage { ptr, .. } => unsafe {
+                slice::from_raw_parts(ptr.as_ptr(), self.len)
+            },
+            Backing::DiskLeaf { leaf, offset } => &leaf.as_bytes()[*offset..*offset + self.len],
+            Backing::Owned(value) => value,
+        }
+    }
+
+    /// Whether the value is served from a pinned mini-page rather than a disk leaf image
+    pub fn is_cached(&self) -> bool {
+        matches!(self.backing, Backing::MiniPage { .. })
+    }
+}
+
+impl Drop for PinnedValue<'_> {
+    fn drop(&mut self) {
+        if let Backing::MiniPage { buffer, index, .. } = self.backing {
+            buffer.unpin(index);
+        }
+    }
+}
+
+impl Deref for PinnedValue<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for PinnedValue<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl fmt::Debug for PinnedValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PinnedValue")
+            .field(&self.as_bytes())
+            .finish()
+    }
+}
@@ -0,0 +1,255 @@
+//! A full integrity check of a store, see `QuickStep::verify`.
+//!
+//! The inner nodes are copied out and walked from the root, carrying the range each node is
+//! routed: pivots must increase within a node and lie inside its range, and every child must
+//! be a node or leaf that exists and is reached once. Each leaf reached then goes through the
+//! `invariants` checks, and its fences must match the range its parent routes to it. Last,
+//! the WAL is audited against its file and every leaf it holds records for must be one the
+//! tree reaches; a leaf's records follow it through merges, so a record of any other page
+//! could never be replayed.
+//!
+//! Unlike `invariants::check_all`, which stops at the first broken invariant, every problem
+//! found is listed in the report. Leaves are read one at a time, so run it while no writers
+//! are active.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    btree::{BPNodeId, ChildPointer, InnerSnapshot},
+    error::QSError,
+    hash_index,
+    invariants::{self, InvariantViolation},
+    iter::{LOWER_SENTINEL, UPPER_SENTINEL},
+    map_table::PageId,
+    wal::{WalMismatch, WalStatsLevel},
+    Store,
+};
+
+/// What `QuickStep::verify` walked and the problems it found
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Inner nodes walked
+    pub inner_nodes: usize,
+    /// Leaves reached from the root, or buckets of a hash-index store
+    pub leaves: usize,
+    /// Records in those leaves, tombstones included and counted once per copy: a record
+    /// cached in a mini-page is also counted on disk
+    pub records: usize,
+    /// Leaf records in the WAL
+    pub wal_records: usize,
+    pub problems: Vec<VerifyProblem>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// One broken invariant `QuickStep::verify` ran into
+#[derive(Debug)]
+pub enum VerifyProblem {
+    /// A leaf failed the `invariants` checks, or its fences differ from the range it is
+    /// routed
+    Leaf(InvariantViolation),
+    /// `pivot` does not sort after the pivot before it
+    PivotsOutOfOrder {
+        node: u32,
+        prev: Vec<u8>,
+        pivot: Vec<u8>,
+    },
+    /// `pivot` lies outside the range the parents route to `node`
+    PivotOutsideBounds {
+        node: u32,
+        pivot: Vec<u8>,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+    },
+    /// A child of `node` is an inner node never handed out or a leaf without a map entry
+    DanglingChild { node: u32, child: ChildPointer },
+    /// A child of `node` is also reached from elsewhere in the tree
+    ChildReachedTwice { node: u32, child: ChildPointer },
+    /// The WAL holds `records` records of a leaf the tree does not reach
+    WalPageUnreachable { page_id: PageId, records: usize },
+    /// The WAL file disagrees with what the log counts, see `QuickStep::audit_wal`
+    Wal(WalMismatch),
+}
+
+pub(crate) fn verify(db: &Store) -> Result<VerifyReport, QSError> {
+    let mut report = VerifyReport::default();
+    let reached = match db.hash_index_buckets() {
+        Some(buckets) => verify_buckets(db, buckets, &mut report),
+        None => verify_tree(db, &mut report)?,
+    };
+
+    let logged = db.wal.stats(WalStatsLevel::Pages)?.pages;
+    for (page, usage) in logged {
+        report.wal_records += usage.records;
+        let page_id = PageId::from_u64(page);
+        if !reached.contains(&page_id) {
+            report.problems.push(VerifyProblem::WalPageUnreachable {
+                page_id,
+                records: usage.records,
+            });
+        }
+    }
+    let mismatches = db.wal.audit()?;
+    report
+        .problems
+        .extend(mismatches.into_iter().map(VerifyProblem::Wal));
+    Ok(report)
+}
+
+/// Check every bucket of a hash-index store, returns the buckets
+fn verify_buckets(db: &Store, buckets: u64, report: &mut VerifyReport) -> HashSet<PageId> {
+    let mut reached = HashSet::new();
+    for bucket in 0..buckets {
+        let page_id = PageId::from_u64(bucket);
+        reached.insert(page_id);
+        report.leaves += 1;
+        let leaf = match invariants::read_checked_leaf(db, page_id) {
+            Ok(leaf) => leaf,
+            Err(violation) => {
+                report.problems.push(VerifyProblem::Leaf(violation));
+                continue;
+            }
+        };
+        report.records += leaf.keys.len();
+        for key in leaf.keys {
+            if hash_index::bucket_for(&key, buckets) != page_id {
+                let violation = InvariantViolation::WrongBucket { page_id, key };
+                report.problems.push(VerifyProblem::Leaf(violation));
+            }
+        }
+    }
+    reached
+}
+
+/// Walk the ordered tree from its root and check the leaves it reaches, returns them
+fn verify_tree(db: &Store, report: &mut VerifyReport) -> Result<HashSet<PageId>, QSError> {
+    let snapshot = db.inner_nodes.snapshot_inner()?;
+    let mut walk = TreeWalk {
+        db,
+        nodes: snapshot
+            .nodes
+            .into_iter()
+            .map(|inner| (inner.node, inner))
+            .collect(),
+        visited: HashSet::new(),
+        reached: HashSet::new(),
+        leaves: Vec::new(),
+        problems: Vec::new(),
+    };
+    match snapshot.root {
+        ChildPointer::Leaf(page) => {
+            walk.reached.insert(page);
+            walk.leaves
+                .push((page, LOWER_SENTINEL.to_vec(), UPPER_SENTINEL.to_vec()));
+        }
+        ChildPointer::Inner(root) => {
+            if let Some(root) = walk.nodes.get(&root).cloned() {
+                walk.visited.insert(root.node);
+                walk.walk(&root, LOWER_SENTINEL, UPPER_SENTINEL);
+            }
+        }
+    }
+    report.inner_nodes = walk.visited.len();
+    report.problems.append(&mut walk.problems);
+
+    for (page_id, lower, upper) in &walk.leaves {
+        report.leaves += 1;
+        let checked = invariants::read_checked_leaf(db, *page_id).and_then(|leaf| {
+            invariants::expect_fence(*page_id, lower, &leaf.lower)?;
+            invariants::expect_fence(*page_id, upper, &leaf.upper)?;
+            Ok(leaf)
+        });
+        match checked {
+            Ok(leaf) => report.records += leaf.keys.len(),
+            Err(violation) => report.problems.push(VerifyProblem::Leaf(violation)),
+        }
+    }
+    Ok(walk.reached)
+}
+
+struct TreeWalk<'a> {
+    db: &'a Store,
+    nodes: HashMap<BPNodeId, InnerSnapshot>,
+    visited: HashSet<BPNodeId>,
+    reached: HashSet<PageId>,
+    /// Leaves in key order with the range they are routed
+    leaves: Vec<(PageId, Vec<u8>, Vec<u8>)>,
+    problems: Vec<VerifyProblem>,
+}
+
+impl TreeWalk<'_> {
+    /// Check `inner`, routed keys from `lower` up to `upper`, and walk its children
+    fn walk(&mut self, inner: &InnerSnapshot, lower: &[u8], upper: &[u8]) {
+        let node = inner.node.as_u32();
+        for (idx, pivot) in inner.pivots.iter().enumerate() {
+            if let Some(prev) = idx.checked_sub(1).map(|prev| &inner.pivots[prev]) {
+                if prev >= pivot {
+                    self.problems.push(VerifyProblem::PivotsOutOfOrder {
+                        node,
+                        prev: prev.clone(),
+                        pivot: pivot.clone(),
+                    });
+                }
+            }
+            let above_lower = lower == LOWER_SENTINEL || pivot.as_slice() > lower;
+            let below_upper = upper == UPPER_SENTINEL || pivot.as_slice() < upper;
+            if !(above_lower && below_upper) {
+                self.problems.push(VerifyProblem::PivotOutsideBounds {
+                    node,
+                    pivot: pivot.clone(),
+                    lower: lower.to_vec(),
+                    upper: upper.to_vec(),
+                });
+            }
+        }
+
+        for (idx, child) in inner.children.iter().enumerate() {
+            let child_lower = match idx {
+                0 => lower,
+                _ => inner.pivots[idx - 1].as_slice(),
+            };
+            let child_upper = inner.pivots.get(idx).map_or(upper, Vec::as_slice);
+            match *child {
+                ChildPointer::Leaf(page) => {
+                    let mapped = (page.as_u64() as usize) < self.db.map_table.capacity()
+                        && self.db.map_table.has_entry(page);
+                    if !mapped {
+                        self.problems.push(VerifyProblem::DanglingChild {
+                            node,
+                            child: *child,
+                        });
+                    } else if !self.reached.insert(page) {
+                        self.problems.push(VerifyProblem::ChildReachedTwice {
+                            node,
+                            child: *child,
+                        });
+                    } else {
+                        self.leaves
+                            .push((page, child_lower.to_vec(), child_upper.to_vec()));
+                    }
+                }
+                ChildPointer::Inner(id) => {
+                    if !self.visited.insert(id) {
+                        self.problems.push(VerifyProblem::ChildReachedTwice {
+                            node,
+                            child: *child,
+                        });
+                        continue;
+                    }
+                    let Some(next) = self.nodes.get(&id).cloned() else {
+                        self.problems.push(VerifyProblem::DanglingChild {
+                            node,
+                            child: *child,
+                        });
+                        continue;
+                    };
+                    self.walk(&next, child_lower, child_upper);
+                }
+            }
+        }
+    }
+}
@@ -1,25 +1,57 @@
+use std::convert::TryInto;
 use std::{
-    collections::{BTreeMap, HashMap},
-    fs::{self, File, OpenOptions},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
     io::{self, Read, Seek, SeekFrom, Write},
+    mem,
     path::Path,
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, MutexGuard, RwLock,
+    },
+    time::Duration,
 };
-use std::convert::TryInto;
 
-use crate::map_table::PageId;
+use crate::{
+    auth::{MetadataAuth, TAG_LEN},
+    events::{Events, StructuralEvent},
+    latency::LatencyInjector,
+    map_table::PageId,
+    storage::Storage,
+};
 
 const RECORD_TYPE_PUT: u8 = 0;
 const RECORD_TYPE_TOMBSTONE: u8 = 1;
 const RECORD_TYPE_TXN_BEGIN: u8 = 2;
 const RECORD_TYPE_TXN_COMMIT: u8 = 3;
 const RECORD_TYPE_TXN_ABORT: u8 = 4;
+const RECORD_TYPE_MERGE: u8 = 5;
+const RECORD_TYPE_SMO_SPLIT: u8 = 6;
+const RECORD_TYPE_SMO_MERGE: u8 = 7;
+const RECORD_TYPE_SMO_RELOCATE: u8 = 8;
+const RECORD_TYPE_TXN_PREPARE: u8 = 9;
 pub const TXN_META_PAGE_ID: u64 = u64::MAX;
+/// Page the structure records are logged under, see `WalSmo`. Checkpoints never drop them
+pub const STRUCTURE_PAGE_ID: u64 = u64::MAX - 1;
 const GROUP_MARKER: u8 = 0xAA;
 const GROUP_HEADER_LEN: usize = 1 + 8 + 4;
+/// A group of one transaction's records sharing fences: the transaction id and fences are
+/// written once and the record kinds as a bitmap, see `write_packed_group`
+const PACKED_GROUP_MARKER: u8 = 0xAB;
+/// Marker, page id, record count, transaction id and the two fence lengths
+const PACKED_HEADER_LEN: usize = 1 + 8 + 4 + 8 + 4 + 4;
+/// `| 0xAC | lsn u64 |`, the sequence number of the next record where it does not follow
+/// from the one before. Records count up from the last marker, or from 1 without one, and
+/// records after a marker of 0 are unnumbered
+const LSN_MARKER: u8 = 0xAC;
+const LSN_MARKER_LEN: usize = 1 + 8;
 const MANIFEST_MAGIC: [u8; 4] = *b"WALM";
-const MANIFEST_VERSION: u32 = 1;
-const MANIFEST_LEN: u64 = 32;
+/// Version 2 manifests end in a tag over their first `MANIFEST_BODY_LEN` bytes
+pub const MANIFEST_VERSION: u32 = 2;
+pub const MANIFEST_VERSION_UNTAGGED: u32 = 1;
+const MANIFEST_BODY_LEN: usize = 16;
+pub const MANIFEST_LEN: u64 = 32;
 
 #[derive(Clone, Debug)]
 pub struct WalRecord {
@@ -30,6 +62,33 @@ pub struct WalRecord {
     pub kind: WalEntryKind,
     pub txn_id: u64,
     pub op: WalOp,
+    /// Sequence number the record was appended under, kept across rewrites and opens; 0
+    /// for the structure records a rewrite adds
+    pub lsn: u64,
+}
+
+impl WalRecord {
+    /// A record of `op` on `key` in the leaf `page_id`
+    pub fn leaf(
+        page_id: PageId,
+        key: &[u8],
+        lower_fence: &[u8],
+        upper_fence: &[u8],
+        kind: WalEntryKind,
+        txn_id: u64,
+        op: WalOp,
+    ) -> WalRecord {
+        WalRecord {
+            page_id: page_id.as_u64(),
+            key: key.to_vec(),
+            lower_fence: lower_fence.to_vec(),
+            upper_fence: upper_fence.to_vec(),
+            kind,
+            txn_id,
+            lsn: 0,
+            op,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -38,18 +97,92 @@ pub enum WalEntryKind {
     Undo,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum WalTxnMarker {
     Begin,
     Commit,
     Abort,
+    /// The transaction's records are durable and it waits for a commit or abort from
+    /// outside; replay leaves it in doubt until then
+    Prepare,
 }
 
 #[derive(Clone, Debug)]
 pub enum WalOp {
-    Put { value: Vec<u8> },
+    Put {
+        value: Vec<u8>,
+    },
+    /// A merge operand and the value it resolved to, replay only needs the value
+    Merge {
+        operand: Vec<u8>,
+        value: Vec<u8>,
+    },
     Tombstone,
     TxnMarker(WalTxnMarker),
+    /// A change to the tree, its record's key is the split pivot or a key of the survivor,
+    /// empty for a relocation
+    Smo(WalSmo),
+}
+
+/// A structure modification, logged in order so recovery can rebuild the inner nodes and
+/// the root above the leaves, which are the only part of the tree on disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalSmo {
+    /// `right`, on the disk page `right_addr`, split off `left` at the pivot
+    Split {
+        left: u64,
+        right: u64,
+        right_addr: u64,
+    },
+    /// `removed` merged into its left neighbour `survivor`
+    Merge { survivor: u64, removed: u64 },
+    /// `page` moved to the disk page `addr` by compaction
+    Relocate { page: u64, addr: u64 },
+}
+
+impl WalSmo {
+    fn record_type(self) -> u8 {
+        match self {
+            WalSmo::Split { .. } => RECORD_TYPE_SMO_SPLIT,
+            WalSmo::Merge { .. } => RECORD_TYPE_SMO_MERGE,
+            WalSmo::Relocate { .. } => RECORD_TYPE_SMO_RELOCATE,
+        }
+    }
+
+    fn ids(self) -> Vec<u64> {
+        match self {
+            WalSmo::Split {
+                left,
+                right,
+                right_addr,
+            } => vec![left, right, right_addr],
+            WalSmo::Merge { survivor, removed } => vec![survivor, removed],
+            WalSmo::Relocate { page, addr } => vec![page, addr],
+        }
+    }
+
+    fn id_count(record_type: u8) -> Option<usize> {
+        match record_type {
+            RECORD_TYPE_SMO_SPLIT => Some(3),
+            RECORD_TYPE_SMO_MERGE | RECORD_TYPE_SMO_RELOCATE => Some(2),
+            _ => None,
+        }
+    }
+
+    fn from_ids(record_type: u8, ids: &[u64]) -> Option<WalSmo> {
+        match (record_type, ids) {
+            (RECORD_TYPE_SMO_SPLIT, &[left, right, right_addr]) => Some(WalSmo::Split {
+                left,
+                right,
+                right_addr,
+            }),
+            (RECORD_TYPE_SMO_MERGE, &[survivor, removed]) => {
+                Some(WalSmo::Merge { survivor, removed })
+            }
+            (RECORD_TYPE_SMO_RELOCATE, &[page, addr]) => Some(WalSmo::Relocate { page, addr }),
+            _ => None,
+        }
+    }
 }
 
 impl WalEntryKind {
@@ -74,6 +207,7 @@ impl WalTxnMarker {
             WalTxnMarker::Begin => RECORD_TYPE_TXN_BEGIN,
             WalTxnMarker::Commit => RECORD_TYPE_TXN_COMMIT,
             WalTxnMarker::Abort => RECORD_TYPE_TXN_ABORT,
+            WalTxnMarker::Prepare => RECORD_TYPE_TXN_PREPARE,
         }
     }
 
@@ -82,14 +216,60 @@ impl WalTxnMarker {
             RECORD_TYPE_TXN_BEGIN => Some(WalTxnMarker::Begin),
             RECORD_TYPE_TXN_COMMIT => Some(WalTxnMarker::Commit),
             RECORD_TYPE_TXN_ABORT => Some(WalTxnMarker::Abort),
+            RECORD_TYPE_TXN_PREPARE => Some(WalTxnMarker::Prepare),
             _ => None,
         }
     }
 }
 
-struct LeafWalStats {
-    count: usize,
-    bytes: usize,
+/// Records and the log bytes they take
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalUsage {
+    pub records: usize,
+    pub bytes: usize,
+}
+
+impl WalUsage {
+    fn add(&mut self, bytes: usize) {
+        self.records += 1;
+        self.bytes = self
+            .bytes
+            .checked_add(bytes)
+            .expect("wal byte counter overflow");
+    }
+}
+
+/// How much breakdown `WalManager::stats` copies out. Every level is exact: each record's
+/// bytes, its group's header included, are counted once as it is written or read on open,
+/// and recounted when a checkpoint rewrites the log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalStatsLevel {
+    /// Totals only
+    #[default]
+    Totals,
+    /// Totals and each leaf's share
+    Pages,
+    /// Totals, leaves and each transaction's share
+    Full,
+}
+
+/// What the log holds, past its manifest. `total` is the sum of `pages`, `txn_markers` and
+/// `structure`, and of `txns`; `total.bytes` and `lsn_marker` are the log's length less the
+/// manifest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalStats {
+    pub total: WalUsage,
+    /// Bytes of an LSN marker ending the log, left by a checkpoint to number the next record
+    /// appended; the record written after it counts it
+    pub lsn_marker: usize,
+    /// Transaction markers, kept apart from the leaves
+    pub txn_markers: WalUsage,
+    /// Structure records, see `WalSmo`
+    pub structure: WalUsage,
+    /// Leaf records by page id, empty below `WalStatsLevel::Pages`
+    pub pages: BTreeMap<u64, WalUsage>,
+    /// Records by transaction id, markers included, empty below `WalStatsLevel::Full`
+    pub txns: BTreeMap<u64, WalUsage>,
 }
 
 #[derive(Clone, Copy)]
@@ -105,297 +285,1960 @@ impl WalManifest {
     }
 }
 
-struct WalState {
-    file: File,
-    records: Vec<WalRecord>,
-    leaf_counts: HashMap<u64, LeafWalStats>,
-    total_records: usize,
-    total_bytes: usize,
-    manifest: WalManifest,
-}
-
-pub struct WalManager {
-    state: Mutex<WalState>,
+/// When commits wait for the log to reach disk. Whatever the policy, the log is synced
+/// before a leaf carrying its changes is written to the data file and when a checkpoint
+/// rewrites it, so a crash can lose recent commits but never leaves the data file ahead of
+/// the log
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Every commit waits until it is synced, concurrent commits share a sync
+    #[default]
+    PerCommit,
+    /// Commits return once written; a background thread syncs the log at this interval, so
+    /// a crash loses at most the commits of the last interval
+    Every(Duration),
+    /// Commits return once written and the OS decides when they reach disk
+    Never,
+}
+
+/// Append buffers; each thread sticks to one, so concurrent appenders rarely share a lock
+const APPEND_SHARDS: usize = 16;
+
+/// Records queued before an appender writes them itself, when no commit has
+const APPEND_WRITE_BATCH: u64 = 256;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % APPEND_SHARDS;
+}
+
+fn shard_index() -> usize {
+    SHARD.with(|shard| *shard)
+}
+
+struct WalIo {
+    file: Storage,
+    manifest: WalManifest,
+    /// Sequence number a record written at the end of the file is read back with, one
+    /// appended under another needs an LSN marker first
+    next_lsn: u64,
+}
+
+/// Group commit: one waiter at a time syncs everything written, the rest park until their
+/// record is covered
+struct GroupSync {
+    /// A leader is syncing the file
+    syncing: Mutex<bool>,
+    synced: Condvar,
+    /// Second handle to the log file, so a sync does not hold the file lock that writers
+    /// need. `None` for a log kept in memory
+    file: Option<Storage>,
+}
+
+/// The newest written records, kept for `WalManager::tail` whatever checkpoints drop
+#[derive(Default)]
+struct WalTail {
+    records: VecDeque<WalRecord>,
+    /// Sequence number of the front of `records`
+    first_lsn: u64,
+    /// Records kept, none unless set with `WalManager::with_tail_retention`
+    retention: usize,
+    /// Told the durable sequence number each time it moves
+    subscribers: Vec<Sender<u64>>,
+}
+
+/// `WalManager::tail` was asked for records the log no longer keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalTailGap {
+    /// Oldest sequence number still kept, the stream has to restart from a copy of the
+    /// data taken after it
+    pub oldest_lsn: u64,
+}
+
+/// What the log holds, counted per page and transaction. The records themselves are only
+/// in the file and are read back when replay or a checkpoint needs them
+struct WalIndex {
+    pages: HashMap<u64, WalUsage>,
+    /// Position in the log of each leaf's oldest record
+    first_record: HashMap<u64, usize>,
+    txns: HashMap<u64, WalUsage>,
+    total: WalUsage,
+    /// Leaf records of each transaction begun but not yet committed or aborted, per page.
+    /// Checkpoints keep them, see `checkpoint_page`
+    in_flight: HashMap<u64, HashMap<u64, usize>>,
+    /// The records of `in_flight` summed per page
+    pinned: HashMap<u64, usize>,
+    /// Undo records per page. Recovery only reads those of transactions that never ended,
+    /// and they reach the log whenever a sync does, so they don't count toward a leaf's
+    /// checkpoint threshold
+    undo: HashMap<u64, usize>,
+    /// Bytes of an LSN marker ending the file, counted with the next record written
+    unattributed: usize,
+}
+
+impl WalIndex {
+    /// Index `records`, `sizes` holding the bytes each takes in the log
+    fn new(records: &[WalRecord], sizes: &[usize]) -> WalIndex {
+        let mut index = WalIndex {
+            pages: HashMap::new(),
+            first_record: HashMap::new(),
+            txns: HashMap::new(),
+            total: WalUsage::default(),
+            in_flight: HashMap::new(),
+            pinned: HashMap::new(),
+            undo: HashMap::new(),
+            unattributed: 0,
+        };
+        for (record, size) in records.iter().zip(sizes) {
+            index.push(record, *size);
+        }
+        index
+    }
+
+    fn push(&mut self, record: &WalRecord, bytes: usize) {
+        self.first_record
+            .entry(record.page_id)
+            .or_insert(self.total.records);
+        self.pages.entry(record.page_id).or_default().add(bytes);
+        self.txns.entry(record.txn_id).or_default().add(bytes);
+        self.total.add(bytes);
+        match record.op {
+            WalOp::TxnMarker(WalTxnMarker::Begin) => {
+                self.in_flight.entry(record.txn_id).or_default();
+            }
+            WalOp::TxnMarker(WalTxnMarker::Prepare) => {}
+            WalOp::TxnMarker(WalTxnMarker::Commit | WalTxnMarker::Abort) => {
+                for (page, records) in self.in_flight.remove(&record.txn_id).unwrap_or_default() {
+                    let pinned = self.pinned.get_mut(&page).expect("pinned page counted");
+                    *pinned -= records;
+                    if *pinned == 0 {
+                        self.pinned.remove(&page);
+                    }
+                }
+            }
+            _ if matches!(record.kind, WalEntryKind::Undo) => {
+                *self.undo.entry(record.page_id).or_default() += 1;
+            }
+            _ => {
+                if let Some(pages) = self.in_flight.get_mut(&record.txn_id) {
+                    *pages.entry(record.page_id).or_default() += 1;
+                    *self.pinned.entry(record.page_id).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    fn leaf_usage(&self) -> impl Iterator<Item = (&u64, &WalUsage)> {
+        self.pages
+            .iter()
+            .filter(|(page, _)| **page != TXN_META_PAGE_ID && **page != STRUCTURE_PAGE_ID)
+    }
+}
+
+/// A leaf's share of the log, used to pick checkpoint targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalPageSummary {
+    pub page_id: PageId,
+    pub records: usize,
+    pub bytes: usize,
+    /// Position of the page's oldest record in the log, smaller is older
+    pub first_record: usize,
+}
+
+pub struct WalManager {
+    /// Records appended but not yet written, a thread always appends to the same shard
+    shards: Vec<Mutex<Vec<WalRecord>>>,
+    /// Undo records of running transactions, sharded by transaction id. They only reach the
+    /// log when a leaf may be written with their transaction's writes, see `sync`
+    held_undo: Vec<Mutex<HashMap<u64, Vec<WalRecord>>>>,
+    /// Next log sequence number to hand out, only taken under a shard lock
+    next_lsn: AtomicU64,
+    /// Every record up to this sequence number is written to the file and counted in the
+    /// index, though maybe not synced
+    written_lsn: AtomicU64,
+    /// Every record up to this sequence number is synced
+    durable_lsn: AtomicU64,
+    group_sync: GroupSync,
+    /// Syncs issued to make appended records durable
+    syncs: AtomicU64,
+    /// Records rewrites dropped as replaced by later ones, see `coalesce`
+    coalesced: AtomicU64,
+    /// Checkpoints that dropped leaf records
+    checkpoints: AtomicU64,
+    /// Whether commit markers wait for a sync
+    sync_policy: SyncPolicy,
+    /// Serializes writes to the file; held to flush pending records or rewrite the log
+    io: Mutex<WalIo>,
+    /// Counts of the written records per leaf and transaction, read by the checkpoint
+    /// heuristics
+    index: RwLock<WalIndex>,
+    /// Bytes physically written since open, including checkpoint rewrites and manifests
+    bytes_written: AtomicU64,
+    /// Key the manifest is tagged under
+    auth: MetadataAuth,
+    /// What opening read from the log and cut off
+    open_stats: WalOpenStats,
+    /// Records kept for shipping to replicas and who to tell when more are durable
+    tail: Mutex<WalTail>,
+    /// Told of each checkpoint and each rewrite that shrinks the log, see `events`
+    events: Events,
+}
+
+/// What `WalManager::open` read from an existing log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalOpenStats {
+    /// Length of the file, manifest included
+    pub bytes_scanned: u64,
+    /// Records of whole groups, kept for replay
+    pub records_read: usize,
+    /// Bytes of the torn or corrupt tail cut off after the last whole group
+    pub bytes_discarded: u64,
+    /// Records parsed from a group that was cut off with the tail
+    pub records_discarded: usize,
+    /// Sequence number of the first record appended after opening, every record read is
+    /// numbered below it
+    pub next_lsn: u64,
+}
+
+impl WalManager {
+    pub fn open(path: &Path) -> io::Result<WalManager> {
+        Self::open_with_auth(path, MetadataAuth::default())
+    }
+
+    /// Open the log, failing with `InvalidData` if its manifest does not authenticate under
+    /// `auth`
+    pub fn open_with_auth(path: &Path, auth: MetadataAuth) -> io::Result<WalManager> {
+        Self::with_storage(Storage::open(path)?, auth)
+    }
+
+    /// Keep the log in memory instead of a file, it is lost once the manager is dropped
+    pub fn in_memory(auth: MetadataAuth) -> io::Result<WalManager> {
+        Self::with_storage(Storage::memory(), auth)
+    }
+
+    /// Read the log at `path` into memory and keep appending there, leaving the file as it
+    /// is, for a store opened read-only
+    pub fn memory_copy_of(path: &Path, auth: MetadataAuth) -> io::Result<WalManager> {
+        Self::with_storage(Storage::memory_copy_of(path)?, auth)
+    }
+
+    fn with_storage(mut file: Storage, auth: MetadataAuth) -> io::Result<WalManager> {
+        let file_len = file.len()?;
+        let (manifest, records, sizes, valid_len, records_discarded, numbered) =
+            repair(&mut file, &auth)?;
+        // a marker ending the log numbers the next append, no record counts it yet
+        let mut index = WalIndex::new(&records, &sizes);
+        index.unattributed =
+            (valid_len.max(MANIFEST_LEN) - MANIFEST_LEN) as usize - index.total.bytes;
+        let next_lsn = numbered.max(1);
+        let open_stats = WalOpenStats {
+            bytes_scanned: file_len,
+            records_read: records.len(),
+            bytes_discarded: file_len.saturating_sub(valid_len.max(MANIFEST_LEN)),
+            records_discarded,
+            next_lsn,
+        };
+        let sync_file = file.sync_handle()?;
+
+        Ok(WalManager {
+            shards: (0..APPEND_SHARDS).map(|_| Mutex::new(Vec::new())).collect(),
+            held_undo: (0..APPEND_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            next_lsn: AtomicU64::new(next_lsn),
+            written_lsn: AtomicU64::new(next_lsn - 1),
+            durable_lsn: AtomicU64::new(next_lsn - 1),
+            group_sync: GroupSync {
+                syncing: Mutex::new(false),
+                synced: Condvar::new(),
+                file: sync_file,
+            },
+            syncs: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            checkpoints: AtomicU64::new(0),
+            sync_policy: SyncPolicy::PerCommit,
+            io: Mutex::new(WalIo {
+                file,
+                manifest,
+                next_lsn: numbered,
+            }),
+            index: RwLock::new(index),
+            bytes_written: AtomicU64::new(0),
+            auth,
+            open_stats,
+            tail: Mutex::new(WalTail {
+                first_lsn: next_lsn,
+                ..WalTail::default()
+            }),
+            events: Events::default(),
+        })
+    }
+
+    /// What opening read from the log and cut off, unchanged by later appends
+    pub fn open_stats(&self) -> WalOpenStats {
+        self.open_stats
+    }
+
+    /// Decide when commits wait for a sync, see `SyncPolicy`
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> WalManager {
+        self.sync_policy = policy;
+        self
+    }
+
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    /// Tell `events` of each checkpoint and each rewrite that shrinks the log
+    pub(crate) fn with_events(mut self, events: Events) -> WalManager {
+        self.events = events;
+        self
+    }
+
+    /// Keep the newest `records` written records in memory for `tail`, whether or not a
+    /// checkpoint has dropped them from the log
+    pub fn with_tail_retention(self, records: usize) -> WalManager {
+        self.tail.lock().expect("wal tail poisoned").retention = records;
+        self
+    }
+
+    /// Durable records from the sequence number `from_lsn` on, in the order they were
+    /// appended. Records are numbered without gaps, carrying on from the log's last record
+    /// when it is opened again, so the record after the last one returned is `from_lsn` plus
+    /// the number returned. Fails if records from `from_lsn` have fallen out of the retention
+    /// set with `with_tail_retention` or were appended before the log was opened.
+    ///
+    /// Only appended records are streamed: a whole-tree replacement or `clear` rewrites
+    /// the log without numbering its records
+    pub fn tail(&self, from_lsn: u64) -> Result<impl Iterator<Item = WalRecord>, WalTailGap> {
+        let from_lsn = from_lsn.max(1);
+        let durable = self.durable_lsn.load(Ordering::Acquire);
+        let tail = self.tail.lock().expect("wal tail poisoned");
+        if from_lsn < tail.first_lsn {
+            return Err(WalTailGap {
+                oldest_lsn: tail.first_lsn,
+            });
+        }
+        let end = ((durable + 1).saturating_sub(tail.first_lsn) as usize).min(tail.records.len());
+        let start = ((from_lsn - tail.first_lsn) as usize).min(end);
+        let records: Vec<WalRecord> = tail.records.range(start..end).cloned().collect();
+        Ok(records.into_iter())
+    }
+
+    /// Every record up to this sequence number is durable
+    pub fn durable_lsn(&self) -> u64 {
+        self.durable_lsn.load(Ordering::Acquire)
+    }
+
+    /// Receive the durable sequence number each time more records become durable, so a
+    /// shipper knows when to call `tail` again
+    pub fn subscribe_durable(&self) -> Receiver<u64> {
+        let (sender, receiver) = mpsc::channel();
+        self.tail
+            .lock()
+            .expect("wal tail poisoned")
+            .subscribers
+            .push(sender);
+        receiver
+    }
+
+    /// Tell the subscribers the durable sequence number moved past `before`
+    fn publish_durable(&self, before: u64) {
+        let durable = self.durable_lsn.load(Ordering::Acquire);
+        if durable <= before {
+            return;
+        }
+        let mut tail = self.tail.lock().expect("wal tail poisoned");
+        tail.subscribers
+            .retain(|sender| sender.send(durable).is_ok());
+    }
+
+    /// Hold up the log's reads, writes and syncs by `injector`'s latency, see `latency`
+    pub fn with_io_latency(mut self, injector: Arc<LatencyInjector>) -> WalManager {
+        let io = self.io.get_mut().expect("wal io poisoned");
+        let file = mem::replace(&mut io.file, Storage::memory());
+        io.file = file.delayed(Arc::clone(&injector));
+        self.group_sync.file = self
+            .group_sync
+            .file
+            .take()
+            .map(|file| file.delayed(injector));
+        self
+    }
+
+    /// Every record written, read back from the log. Appends wait for the read
+    pub fn records(&self) -> Vec<WalRecord> {
+        let mut io = self.io.lock().expect("wal io poisoned");
+        self.write_pending(&mut io)
+            .expect("failed to write the WAL");
+        read_written(&mut io.file).expect("failed to read the WAL")
+    }
+
+    /// `records` grouped by the page they belong to, in log order within each page
+    pub fn records_grouped(&self) -> BTreeMap<u64, Vec<WalRecord>> {
+        let mut grouped: BTreeMap<u64, Vec<WalRecord>> = BTreeMap::new();
+        for record in self.records() {
+            grouped.entry(record.page_id).or_default().push(record);
+        }
+        grouped
+    }
+
+    pub fn append_tombstone(
+        &self,
+        page_id: PageId,
+        key: &[u8],
+        lower_fence: &[u8],
+        upper_fence: &[u8],
+        kind: WalEntryKind,
+        txn_id: u64,
+    ) -> io::Result<()> {
+        self.append_record(WalRecord {
+            page_id: page_id.as_u64(),
+            key: key.to_vec(),
+            lower_fence: lower_fence.to_vec(),
+            upper_fence: upper_fence.to_vec(),
+            kind,
+            txn_id,
+            lsn: 0,
+            op: WalOp::Tombstone,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn append_put(
+        &self,
+        page_id: PageId,
+        key: &[u8],
+        value: &[u8],
+        lower_fence: &[u8],
+        upper_fence: &[u8],
+        kind: WalEntryKind,
+        txn_id: u64,
+    ) -> io::Result<()> {
+        self.append_record(WalRecord {
+            page_id: page_id.as_u64(),
+            key: key.to_vec(),
+            lower_fence: lower_fence.to_vec(),
+            upper_fence: upper_fence.to_vec(),
+            kind,
+            txn_id,
+            lsn: 0,
+            op: WalOp::Put {
+                value: value.to_vec(),
+            },
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn append_merge(
+        &self,
+        page_id: PageId,
+        key: &[u8],
+        operand: &[u8],
+        value: &[u8],
+        lower_fence: &[u8],
+        upper_fence: &[u8],
+        kind: WalEntryKind,
+        txn_id: u64,
+    ) -> io::Result<()> {
+        self.append_record(WalRecord {
+            page_id: page_id.as_u64(),
+            key: key.to_vec(),
+            lower_fence: lower_fence.to_vec(),
+            upper_fence: upper_fence.to_vec(),
+            kind,
+            txn_id,
+            lsn: 0,
+            op: WalOp::Merge {
+                operand: operand.to_vec(),
+                value: value.to_vec(),
+            },
+        })
+    }
+
+    pub fn append_txn_marker(
+        &self,
+        marker: WalTxnMarker,
+        kind: WalEntryKind,
+        txn_id: u64,
+    ) -> io::Result<()> {
+        self.append_record(WalRecord {
+            page_id: TXN_META_PAGE_ID,
+            key: Vec::new(),
+            lower_fence: Vec::new(),
+            upper_fence: Vec::new(),
+            kind,
+            txn_id,
+            lsn: 0,
+            op: WalOp::TxnMarker(marker),
+        })
+    }
+
+    /// Log a change to the tree and sync it, so the leaf writes that depend on it may follow
+    pub fn append_smo(&self, smo: WalSmo, key: &[u8]) -> io::Result<()> {
+        self.append_record(WalRecord {
+            page_id: STRUCTURE_PAGE_ID,
+            key: key.to_vec(),
+            lower_fence: Vec::new(),
+            upper_fence: Vec::new(),
+            kind: WalEntryKind::Redo,
+            txn_id: 0,
+            lsn: 0,
+            op: WalOp::Smo(smo),
+        })?;
+        self.sync()
+    }
+
+    /// Log the split of `left` at `pivot`, handing `right` the records of `left` from the
+    /// pivot on and narrowing the fences of both sides. One rewrite does both, so a crash
+    /// sees the split whole or not at all. The right leaf must be on disk first
+    pub fn append_split(
+        &self,
+        left: PageId,
+        right: PageId,
+        right_addr: u64,
+        pivot: &[u8],
+    ) -> io::Result<()> {
+        let split = WalRecord {
+            page_id: STRUCTURE_PAGE_ID,
+            key: pivot.to_vec(),
+            lower_fence: Vec::new(),
+            upper_fence: Vec::new(),
+            kind: WalEntryKind::Redo,
+            txn_id: 0,
+            lsn: 0,
+            op: WalOp::Smo(WalSmo::Split {
+                left: left.as_u64(),
+                right: right.as_u64(),
+                right_addr,
+            }),
+        };
+        self.rewrite_retaining(|records| {
+            let mut rewritten: Vec<WalRecord> = records
+                .iter()
+                .cloned()
+                .map(|mut record| {
+                    if record.page_id == left.as_u64() {
+                        if record.key.as_slice() >= pivot {
+                            record.page_id = right.as_u64();
+                            record.lower_fence = pivot.to_vec();
+                        } else {
+                            record.upper_fence = pivot.to_vec();
+                        }
+                    }
+                    record
+                })
+                .collect();
+            rewritten.push(split);
+            Some(rewritten)
+        })
+    }
+
+    /// Log the merge of `removed` into `survivor`, whose merged leaf spanning `lower` to
+    /// `upper` must be on disk first. The records of both leaves are dropped, except those of
+    /// transactions still running, which move to the survivor with its new fences so replay
+    /// can still take their writes back out. One rewrite does both, as with a split
+    pub fn append_leaf_merge(
+        &self,
+        survivor: PageId,
+        removed: PageId,
+        lower: &[u8],
+        upper: &[u8],
+        merge_key: &[u8],
+    ) -> io::Result<()> {
+        let merge = WalRecord {
+            page_id: STRUCTURE_PAGE_ID,
+            key: merge_key.to_vec(),
+            lower_fence: Vec::new(),
+            upper_fence: Vec::new(),
+            kind: WalEntryKind::Redo,
+            txn_id: 0,
+            lsn: 0,
+            op: WalOp::Smo(WalSmo::Merge {
+                survivor: survivor.as_u64(),
+                removed: removed.as_u64(),
+            }),
+        };
+        let merged = [survivor.as_u64(), removed.as_u64()];
+        self.rewrite_retaining(|records| {
+            let in_flight = in_flight_txns(records);
+            let mut rewritten: Vec<WalRecord> = records
+                .iter()
+                .filter(|record| {
+                    !merged.contains(&record.page_id) || in_flight.contains(&record.txn_id)
+                })
+                .cloned()
+                .map(|mut record| {
+                    if merged.contains(&record.page_id) {
+                        record.page_id = survivor.as_u64();
+                        record.lower_fence = lower.to_vec();
+                        record.upper_fence = upper.to_vec();
+                    }
+                    record
+                })
+                .collect();
+            rewritten.push(merge);
+            Some(rewritten)
+        })
+    }
+
+    /// Append `records` as one batch: they are written together, and one transaction's
+    /// consecutive records on a leaf share a group, so a put's redo and undo records cost
+    /// a single group header, transaction id and copy of the fences
+    pub fn append_batch(&self, records: Vec<WalRecord>) -> io::Result<()> {
+        let commit = self.sync_policy == SyncPolicy::PerCommit
+            && records
+                .iter()
+                .any(|record| matches!(record.op, WalOp::TxnMarker(WalTxnMarker::Commit)));
+        let lsn = self.append_unsynced(records)?;
+        if commit {
+            self.sync_through(lsn)?;
+        }
+        Ok(())
+    }
+
+    /// `append_batch` without the sync a commit marker waits for, returning the sequence
+    /// number of the last record, 0 for none
+    fn append_unsynced(&self, records: Vec<WalRecord>) -> io::Result<u64> {
+        if records.is_empty() {
+            return Ok(0);
+        }
+        #[cfg(feature = "failpoints")]
+        crate::failpoints::hit(crate::failpoints::WAL_APPEND)?;
+        let mut ends_txn = false;
+        for record in &records {
+            if let WalOp::TxnMarker(WalTxnMarker::Commit | WalTxnMarker::Abort) = record.op {
+                // dropped before the marker is numbered, so a `sync` logs them before it or
+                // not at all
+                self.held_undo(record.txn_id).remove(&record.txn_id);
+                ends_txn = true;
+            }
+        }
+        let lsn = {
+            let mut shard = self.shards[shard_index()]
+                .lock()
+                .expect("wal shard poisoned");
+            // taken under the shard lock, so a flush holding every shard sees no gaps
+            let first = self
+                .next_lsn
+                .fetch_add(records.len() as u64, Ordering::Relaxed);
+            let count = records.len() as u64;
+            shard.extend(records.into_iter().zip(first..).map(|(mut record, lsn)| {
+                record.lsn = lsn;
+                record
+            }));
+            first + count - 1
+        };
+        let queued = lsn.saturating_sub(self.written_lsn.load(Ordering::Acquire));
+        if ends_txn || queued >= APPEND_WRITE_BATCH {
+            self.write_through(lsn)?;
+        }
+        Ok(lsn)
+    }
+
+    /// Append a commit marker without waiting for it to be synced, whatever the sync policy.
+    /// Returns its sequence number for `wait_durable`
+    pub fn append_commit_nowait(&self, kind: WalEntryKind, txn_id: u64) -> io::Result<u64> {
+        self.append_unsynced(vec![WalRecord {
+            page_id: TXN_META_PAGE_ID,
+            key: Vec::new(),
+            lower_fence: Vec::new(),
+            upper_fence: Vec::new(),
+            kind,
+            txn_id,
+            lsn: 0,
+            op: WalOp::TxnMarker(WalTxnMarker::Commit),
+        }])
+    }
+
+    /// Wait until the record `lsn` is synced, whatever the sync policy. Waiters share the
+    /// sync covering them as commits do, see `sync_through`
+    pub fn wait_durable(&self, lsn: u64) -> io::Result<()> {
+        self.write_through(lsn)?;
+        self.sync_through(lsn)
+    }
+
+    /// Append a write's `redo` record and hold `undo`, the record taking it back, until a
+    /// leaf may be written with the write in it. Until then the leaf on disk does not hold
+    /// the write, and replay of a transaction that never commits has nothing to take back,
+    /// so a transaction whose leaves are not written before its end logs no undo at all
+    pub fn append_with_undo(&self, redo: WalRecord, undo: Option<WalRecord>) -> io::Result<()> {
+        self.append_batch(vec![redo])?;
+        if let Some(undo) = undo {
+            self.held_undo(undo.txn_id)
+                .entry(undo.txn_id)
+                .or_default()
+                .push(undo);
+        }
+        Ok(())
+    }
+
+    fn held_undo(&self, txn_id: u64) -> MutexGuard<'_, HashMap<u64, Vec<WalRecord>>> {
+        self.held_undo[txn_id as usize % APPEND_SHARDS]
+            .lock()
+            .expect("wal undo poisoned")
+    }
+
+    /// Queue the record on this thread's shard. Only commit and abort markers, or an
+    /// appender finding `APPEND_WRITE_BATCH` records queued, wait until it is written, so
+    /// most appends take no lock but their shard's. Whichever appender takes the file lock
+    /// first writes every queued record in one go, so concurrent appenders share a single
+    /// write. Only commit markers wait for a sync, see `sync_through`, and only under
+    /// `SyncPolicy::PerCommit`; other records become durable with the next commit or `sync`
+    fn append_record(&self, record: WalRecord) -> io::Result<()> {
+        self.append_batch(vec![record])
+    }
+
+    /// Make every record appended so far durable, logging the undo records held for running
+    /// transactions first. Must run before a leaf carrying their changes is written to the
+    /// data file
+    pub fn sync(&self) -> io::Result<()> {
+        self.log_held_undo()?;
+        let lsn = self.appended_lsn();
+        self.write_through(lsn)?;
+        self.sync_through(lsn)
+    }
+
+    /// Queue every held undo record. The shards stay locked until they are numbered, so a
+    /// transaction ending meanwhile logs its marker after them
+    fn log_held_undo(&self) -> io::Result<()> {
+        let mut held: Vec<_> = self
+            .held_undo
+            .iter()
+            .map(|shard| shard.lock().expect("wal undo poisoned"))
+            .collect();
+        let undo: Vec<WalRecord> = held
+            .iter_mut()
+            .flat_map(|shard| shard.drain().flat_map(|(_, records)| records))
+            .collect();
+        self.append_batch(undo)
+    }
+
+    /// The last sequence number handed out, written or still queued
+    fn appended_lsn(&self) -> u64 {
+        self.next_lsn.load(Ordering::Acquire) - 1
+    }
+
+    /// Write the queued records before the log is looked at, so what it reports is counted
+    /// from the file. A failed write stays queued for the next attempt
+    fn write_queued(&self) -> io::Result<()> {
+        self.write_through(self.appended_lsn())
+    }
+
+    /// Syncs issued to make appended records durable, checkpoint rewrites not included
+    pub fn syncs(&self) -> u64 {
+        self.syncs.load(Ordering::Relaxed)
+    }
+
+    /// Checkpoints that dropped leaf records since open, however many leaves each covered;
+    /// those finding nothing to drop are not counted
+    pub fn checkpoints(&self) -> u64 {
+        self.checkpoints.load(Ordering::Relaxed)
+    }
+
+    /// Records checkpoint rewrites dropped since open because later records replace them
+    pub fn records_coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+
+    fn write_through(&self, lsn: u64) -> io::Result<()> {
+        if self.written_lsn.load(Ordering::Acquire) >= lsn {
+            return Ok(());
+        }
+        let mut io = self.io.lock().expect("wal io poisoned");
+        if self.written_lsn.load(Ordering::Acquire) >= lsn {
+            return Ok(());
+        }
+        self.write_pending(&mut io)?;
+        debug_assert!(self.written_lsn.load(Ordering::Acquire) >= lsn);
+        Ok(())
+    }
+
+    /// Wait until the written record `lsn` is synced. The first waiter to find no sync
+    /// running leads one for everything written so far; waiters arriving meanwhile park and
+    /// are covered by it or by the next leader, so concurrent commits share one sync
+    fn sync_through(&self, lsn: u64) -> io::Result<()> {
+        let mut syncing = self.group_sync.syncing.lock().expect("wal sync poisoned");
+        loop {
+            if self.durable_lsn.load(Ordering::Acquire) >= lsn {
+                return Ok(());
+            }
+            if !*syncing {
+                break;
+            }
+            syncing = self
+                .group_sync
+                .synced
+                .wait(syncing)
+                .expect("wal sync poisoned");
+        }
+        *syncing = true;
+        drop(syncing);
+
+        let target = self.written_lsn.load(Ordering::Acquire);
+        let res = match &self.group_sync.file {
+            Some(file) => file.sync_data(),
+            None => Ok(()),
+        };
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+
+        let mut syncing = self.group_sync.syncing.lock().expect("wal sync poisoned");
+        let before = match res {
+            Ok(()) => self.durable_lsn.fetch_max(target, Ordering::AcqRel),
+            Err(_) => target,
+        };
+        *syncing = false;
+        self.group_sync.synced.notify_all();
+        drop(syncing);
+        self.publish_durable(before);
+        res
+    }
+
+    /// Write every queued record in sequence order. On failure the tail is cut back and the
+    /// records are queued again, so a later flush retries them in place
+    fn write_pending(&self, io: &mut WalIo) -> io::Result<()> {
+        #[cfg(feature = "failpoints")]
+        crate::failpoints::hit(crate::failpoints::WAL_WRITE)?;
+        let mut pending = Vec::new();
+        {
+            let mut shards: Vec<_> = self
+                .shards
+                .iter()
+                .map(|shard| shard.lock().expect("wal shard poisoned"))
+                .collect();
+            for shard in shards.iter_mut() {
+                pending.append(shard);
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        pending.sort_unstable_by_key(|record| record.lsn);
+        let records = pending;
+        let last_lsn = records.last().map_or(0, |record| record.lsn);
+
+        let mut buf = Vec::new();
+        let mut next_lsn = io.next_lsn;
+        let mut sizes = write_groups(&mut buf, &records, &mut next_lsn)?;
+        let start = io.file.seek(SeekFrom::End(0))?;
+        if let Err(err) = io.file.write_all(&buf) {
+            let _ = io.file.set_len(start);
+            self.shards[0]
+                .lock()
+                .expect("wal shard poisoned")
+                .extend(records);
+            return Err(err);
+        }
+        io.next_lsn = next_lsn;
+
+        {
+            let mut tail = self.tail.lock().expect("wal tail poisoned");
+            if tail.retention > 0 {
+                tail.records.extend(records.iter().cloned());
+                let excess = tail.records.len().saturating_sub(tail.retention);
+                tail.records.drain(..excess);
+            }
+            // numbered even when nothing is kept, a later `tail` then reports the gap
+            tail.first_lsn = last_lsn + 1 - tail.records.len() as u64;
+        }
+        {
+            let mut index = self.index.write().expect("wal index poisoned");
+            sizes[0] += mem::take(&mut index.unattributed);
+            for (record, size) in records.iter().zip(sizes) {
+                index.push(record, size);
+            }
+        }
+        self.bytes_written
+            .fetch_add(buf.len() as u64, Ordering::Relaxed);
+        // published after the index, so an appender that returns sees its record counted
+        self.written_lsn.store(last_lsn, Ordering::Release);
+        Ok(())
+    }
+
+    /// Everything written is synced after a rewrite or clear, so waiters need not sync again
+    fn mark_durable(&self) {
+        let written = self.written_lsn.load(Ordering::Acquire);
+        let syncing = self.group_sync.syncing.lock().expect("wal sync poisoned");
+        let before = self.durable_lsn.fetch_max(written, Ordering::AcqRel);
+        self.group_sync.synced.notify_all();
+        drop(syncing);
+        self.publish_durable(before);
+    }
+
+    /// Log a tree built to replace the whole current one, whose leaves must be on disk
+    /// first. The leaf and structure records of the old tree are dropped for `structure`, the
+    /// relocation and splits that rebuild the new tree from the root leaf; transaction
+    /// markers are kept
+    pub fn append_tree_replacement(&self, structure: &[(WalSmo, Vec<u8>)]) -> io::Result<()> {
+        self.rewrite_retaining(|records| {
+            let mut rewritten: Vec<WalRecord> = records
+                .iter()
+                .filter(|record| record.page_id == TXN_META_PAGE_ID)
+                .cloned()
+                .collect();
+            rewritten.extend(structure.iter().map(|(smo, key)| WalRecord {
+                page_id: STRUCTURE_PAGE_ID,
+                key: key.clone(),
+                lower_fence: Vec::new(),
+                upper_fence: Vec::new(),
+                kind: WalEntryKind::Redo,
+                txn_id: 0,
+                lsn: 0,
+                op: WalOp::Smo(*smo),
+            }));
+            Some(rewritten)
+        })
+    }
+
+    /// Drop the records of a leaf that has just been written. Records of transactions still
+    /// running are kept: the leaf may hold their writes, and if they never commit, replay
+    /// needs their undo records to take the writes back out
+    pub fn checkpoint_page(&self, page_id: PageId) -> io::Result<()> {
+        self.checkpoint_pages(&[page_id])
+    }
+
+    /// `checkpoint_page` for each of `pages`, rewriting the log once
+    pub fn checkpoint_pages(&self, pages: &[PageId]) -> io::Result<()> {
+        #[cfg(feature = "failpoints")]
+        crate::failpoints::hit(crate::failpoints::WAL_CHECKPOINT)?;
+        let pages: HashSet<u64> = pages.iter().map(|page| page.as_u64()).collect();
+        let mut checkpointed = BTreeSet::new();
+        self.rewrite_retaining(|records| {
+            let in_flight = in_flight_txns(records);
+            let droppable = |record: &WalRecord| {
+                pages.contains(&record.page_id) && !in_flight.contains(&record.txn_id)
+            };
+            checkpointed.extend(records.iter().filter(|r| droppable(r)).map(|r| r.page_id));
+            if checkpointed.is_empty() {
+                return None;
+            }
+            self.checkpoints.fetch_add(1, Ordering::Relaxed);
+            Some(
+                records
+                    .iter()
+                    .filter(|record| !droppable(record))
+                    .cloned()
+                    .collect(),
+            )
+        })?;
+        self.emit_checkpoint(checkpointed);
+        Ok(())
+    }
+
+    fn emit_checkpoint(&self, pages: BTreeSet<u64>) {
+        if !pages.is_empty() {
+            self.events.emit(|| StructuralEvent::LeavesCheckpointed {
+                pages: pages.into_iter().collect(),
+            });
+        }
+    }
+
+    /// Drop the markers of finished transactions none of whose leaf records are left in the
+    /// log; replay has nothing to decide for them. Markers of transactions still running are
+    /// kept
+    pub fn checkpoint_txn_markers(&self) -> io::Result<()> {
+        self.rewrite_retaining(|records| {
+            let mut finished = HashSet::new();
+            let mut referenced = HashSet::new();
+            for record in records {
+                match record.op {
+                    WalOp::TxnMarker(WalTxnMarker::Commit | WalTxnMarker::Abort) => {
+                        finished.insert(record.txn_id);
+                    }
+                    WalOp::TxnMarker(WalTxnMarker::Begin | WalTxnMarker::Prepare) => {}
+                    _ => {
+                        referenced.insert(record.txn_id);
+                    }
+                }
+            }
+            let droppable = |record: &WalRecord| {
+                record.page_id == TXN_META_PAGE_ID
+                    && finished.contains(&record.txn_id)
+                    && !referenced.contains(&record.txn_id)
+            };
+            if !records.iter().any(droppable) {
+                return None;
+            }
+            Some(
+                records
+                    .iter()
+                    .filter(|record| !droppable(record))
+                    .cloned()
+                    .collect(),
+            )
+        })
+    }
+
+    /// Rewrite the log with the records `retain` picks from the synced ones, less those
+    /// `coalesce` finds replaced; `None` leaves it untouched
+    fn rewrite_retaining<F>(&self, retain: F) -> io::Result<()>
+    where
+        F: FnOnce(&[WalRecord]) -> Option<Vec<WalRecord>>,
+    {
+        let mut io = self.io.lock().expect("wal io poisoned");
+        // records appended before the rewrite are rewritten with the rest
+        self.write_pending(&mut io)?;
+        // only flushes, which also hold the file lock, add to the log
+        let Some(retained) = retain(&read_written(&mut io.file)?) else {
+            return Ok(());
+        };
+        let bytes_before = self.index.read().expect("wal index poisoned").total.bytes;
+        let retained_len = retained.len();
+        let snapshot = coalesce(retained);
+        self.coalesced
+            .fetch_add((retained_len - snapshot.len()) as u64, Ordering::Relaxed);
+        // appends carry on numbering from the last record written, whichever records are kept
+        let next_lsn = self.written_lsn.load(Ordering::Acquire) + 1;
+        let (sizes, len) = rewrite_records(&mut io.file, &snapshot, next_lsn)?;
+        let mut rewritten = WalIndex::new(&snapshot, &sizes);
+        rewritten.unattributed = len - sizes.iter().sum::<usize>();
+        let bytes_after = rewritten.total.bytes;
+        *self.index.write().expect("wal index poisoned") = rewritten;
+        io.next_lsn = next_lsn;
+        io.manifest.checkpoint_len = MANIFEST_LEN + len as u64;
+        self.bytes_written
+            .fetch_add(len as u64 + MANIFEST_LEN, Ordering::Relaxed);
+        let manifest = io.manifest;
+        write_manifest(&mut io.file, manifest, &self.auth)?;
+        io.file.sync_data()?;
+        io.file.seek(SeekFrom::End(0))?;
+        self.mark_durable();
+        drop(io);
+        self.emit_truncation(bytes_before, bytes_after);
+        Ok(())
+    }
+
+    fn emit_truncation(&self, bytes_before: usize, bytes_after: usize) {
+        if bytes_after < bytes_before {
+            self.events.emit(|| StructuralEvent::WalTruncated {
+                bytes_before: bytes_before as u64,
+                bytes_after: bytes_after as u64,
+            });
+        }
+    }
+
+    /// Drop every record but the structure records, which still describe the tree once
+    /// the leaves are written, and those of transactions in doubt, which wait to be resolved
+    pub fn clear_leaf_records(&self) -> io::Result<()> {
+        let mut checkpointed = BTreeSet::new();
+        self.rewrite_retaining(|records| {
+            let in_doubt = in_doubt_txns(records);
+            let kept = |record: &WalRecord| {
+                record.page_id == STRUCTURE_PAGE_ID || in_doubt.contains(&record.txn_id)
+            };
+            if records.iter().all(kept) {
+                return None;
+            }
+            self.checkpoints.fetch_add(1, Ordering::Relaxed);
+            checkpointed.extend(
+                records
+                    .iter()
+                    .filter(|record| !kept(record) && record.page_id != TXN_META_PAGE_ID)
+                    .map(|record| record.page_id),
+            );
+            Some(
+                records
+                    .iter()
+                    .filter(|record| kept(record))
+                    .cloned()
+                    .collect(),
+            )
+        })?;
+        self.emit_checkpoint(checkpointed);
+        Ok(())
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        let mut io = self.io.lock().expect("wal io poisoned");
+        // records appended before the clear are cleared with the rest rather than lost
+        // while their appenders still wait on them
+        self.write_pending(&mut io)?;
+        let next_lsn = self.written_lsn.load(Ordering::Acquire) + 1;
+        let bytes_before = self.index.read().expect("wal index poisoned").total.bytes;
+        let (_, len) = rewrite_records(&mut io.file, &[], next_lsn)?;
+        let mut cleared = WalIndex::new(&[], &[]);
+        cleared.unattributed = len;
+        *self.index.write().expect("wal index poisoned") = cleared;
+        io.next_lsn = next_lsn;
+        io.manifest = WalManifest::new();
+        io.manifest.checkpoint_len += len as u64;
+        self.bytes_written
+            .fetch_add(len as u64 + MANIFEST_LEN, Ordering::Relaxed);
+        let manifest = io.manifest;
+        write_manifest(&mut io.file, manifest, &self.auth)?;
+        io.file.sync_data()?;
+        io.file.seek(SeekFrom::End(0))?;
+        self.mark_durable();
+        drop(io);
+        self.emit_truncation(bytes_before, 0);
+        Ok(())
+    }
+
+    /// Whether a checkpoint of the leaf would drop at least `threshold` redo records, records
+    /// of transactions still running are not counted and nor are records still queued
+    pub fn should_checkpoint_page(&self, page_id: PageId, threshold: usize) -> bool {
+        let index = self.index.read().expect("wal index poisoned");
+        let page_key = page_id.as_u64();
+        let pinned = index.pinned.get(&page_key).copied().unwrap_or(0);
+        let undo = index.undo.get(&page_key).copied().unwrap_or(0);
+        index
+            .pages
+            .get(&page_key)
+            .is_some_and(|usage| usage.records - undo - pinned >= threshold)
+    }
+
+    pub fn total_records(&self) -> io::Result<usize> {
+        self.write_queued()?;
+        let index = self.index.read().expect("wal index poisoned");
+        Ok(index.total.records)
+    }
+
+    pub fn total_bytes(&self) -> io::Result<usize> {
+        self.write_queued()?;
+        let index = self.index.read().expect("wal index poisoned");
+        Ok(index.total.bytes)
+    }
+
+    /// Bytes written to the log file since it was opened, counting checkpoint rewrites
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub fn leaf_stats(&self, page_id: PageId) -> io::Result<Option<(usize, usize)>> {
+        self.write_queued()?;
+        let index = self.index.read().expect("wal index poisoned");
+        Ok(index
+            .pages
+            .get(&page_id.as_u64())
+            .map(|usage| (usage.records, usage.bytes)))
+    }
+
+    /// Read the file back and compare it with what the log believes it wrote: the manifest,
+    /// the length of the whole groups and the records counted per page. Opening would
+    /// quietly trim or rewrite whatever disagrees, so this is how a bug or a corrupted file
+    /// shows up while the store is running. Appends wait for the read
+    pub fn audit(&self) -> io::Result<Vec<WalMismatch>> {
+        let mut io = self.io.lock().expect("wal io poisoned");
+        self.write_pending(&mut io)?;
+        let found = manifest_status(&mut io.file, &self.auth)?;
+        let file_len = io.file.len()?;
+        let (records, sizes, valid_len, _, _) = read_records(&mut io.file)?;
+        io.file.seek(SeekFrom::End(0))?;
+        let checkpoint_len = io.manifest.checkpoint_len;
+        let read_back = WalIndex::new(&records, &sizes);
+        // only flushes and rewrites, which hold the file lock, change the index
+        let index = self.index.read().expect("wal index poisoned");
+
+        let mut mismatches = Vec::new();
+        if found != (ManifestStatus::Valid { checkpoint_len }) {
+            mismatches.push(WalMismatch::Manifest {
+                checkpoint_len,
+                found,
+            });
+        }
+        if checkpoint_len > valid_len {
+            mismatches.push(WalMismatch::CheckpointPastEnd {
+                checkpoint_len,
+                valid_len,
+            });
+        }
+        if file_len > valid_len {
+            mismatches.push(WalMismatch::TrailingBytes {
+                bytes: file_len - valid_len,
+            });
+        }
+        if read_back.total != index.total {
+            mismatches.push(WalMismatch::Totals {
+                counted: index.total,
+                found: read_back.total,
+            });
+        }
+        let pages: BTreeSet<u64> = index
+            .pages
+            .keys()
+            .chain(read_back.pages.keys())
+            .copied()
+            .collect();
+        for page_id in pages {
+            let counted = index.pages.get(&page_id).copied().unwrap_or_default();
+            let found = read_back.pages.get(&page_id).copied().unwrap_or_default();
+            if counted != found {
+                mismatches.push(WalMismatch::Page {
+                    page_id,
+                    counted,
+                    found,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// What the log holds, broken down as far as `level` asks
+    pub fn stats(&self, level: WalStatsLevel) -> io::Result<WalStats> {
+        self.write_queued()?;
+        let index = self.index.read().expect("wal index poisoned");
+        let mut stats = WalStats {
+            total: index.total,
+            lsn_marker: index.unattributed,
+            txn_markers: index
+                .pages
+                .get(&TXN_META_PAGE_ID)
+                .copied()
+                .unwrap_or_default(),
+            structure: index
+                .pages
+                .get(&STRUCTURE_PAGE_ID)
+                .copied()
+                .unwrap_or_default(),
+            ..WalStats::default()
+        };
+        if level != WalStatsLevel::Totals {
+            stats.pages = index
+                .leaf_usage()
+                .map(|(page, usage)| (*page, *usage))
+                .collect();
+        }
+        if level == WalStatsLevel::Full {
+            stats.txns = index
+                .txns
+                .iter()
+                .map(|(txn, usage)| (*txn, *usage))
+                .collect();
+        }
+        Ok(stats)
+    }
+
+    /// Whether the log has grown past either global checkpoint threshold
+    pub fn exceeds_global_thresholds(
+        &self,
+        total_record_threshold: usize,
+        total_byte_threshold: usize,
+    ) -> bool {
+        // checked on every write, so queued records are counted without writing them; their
+        // bytes are not known until they are
+        let written = self.written_lsn.load(Ordering::Acquire);
+        let queued = self.appended_lsn().saturating_sub(written) as usize;
+        let index = self.index.read().expect("wal index poisoned");
+        index.total.records + queued >= total_record_threshold
+            || index.total.bytes >= total_byte_threshold
+    }
+
+    /// What each leaf with records in the log contributes to it, ordered by page id
+    pub fn page_summaries(&self) -> io::Result<Vec<WalPageSummary>> {
+        self.write_queued()?;
+        let index = self.index.read().expect("wal index poisoned");
+        let mut summaries: Vec<_> = index
+            .leaf_usage()
+            .map(|(page, usage)| WalPageSummary {
+                page_id: PageId(*page),
+                records: usage.records,
+                bytes: usage.bytes,
+                first_record: index.first_record.get(page).copied().unwrap_or(usize::MAX),
+            })
+            .collect();
+        summaries.sort_by_key(|summary| summary.page_id.0);
+        Ok(summaries)
+    }
+
+    pub fn global_checkpoint_candidate(
+        &self,
+        total_record_threshold: usize,
+        total_byte_threshold: usize,
+    ) -> io::Result<Option<PageId>> {
+        self.write_queued()?;
+        let index = self.index.read().expect("wal index poisoned");
+        if index.total.records < total_record_threshold && index.total.bytes < total_byte_threshold
+        {
+            return Ok(None);
+        }
+        Ok(index
+            .leaf_usage()
+            .max_by_key(|(_, usage)| usage.bytes)
+            .map(|(page, _)| PageId(*page)))
+    }
+}
+
+/// A way the open log disagrees with its file, see `WalManager::audit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalMismatch {
+    /// The manifest on disk is not the one last written
+    Manifest {
+        checkpoint_len: u64,
+        found: ManifestStatus,
+    },
+    /// The manifest points past the last whole group in the file
+    CheckpointPastEnd { checkpoint_len: u64, valid_len: u64 },
+    /// Bytes after the last whole group, which opening would cut off with anything
+    /// appended behind them
+    TrailingBytes { bytes: u64 },
+    /// The file holds other totals than the log counts
+    Totals { counted: WalUsage, found: WalUsage },
+    /// The file holds other records for a page, leaf or not, than the log counts
+    Page {
+        page_id: u64,
+        counted: WalUsage,
+        found: WalUsage,
+    },
+}
+
+/// State of the manifest at the head of a WAL file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestStatus {
+    Valid {
+        checkpoint_len: u64,
+    },
+    /// Written before manifests were tagged, opening upgrades it
+    Untagged {
+        checkpoint_len: u64,
+    },
+    /// The file is shorter than a manifest, opening writes a fresh one
+    Missing,
+    /// Bad magic or version, opening replaces it with a fresh one
+    Unrecognized,
+    /// The tag does not match under the key it was checked with, opening fails
+    Unauthenticated,
+}
+
+impl Drop for WalManager {
+    /// Records still queued are written, as they would have been by the next commit
+    fn drop(&mut self) {
+        if let Ok(mut io) = self.io.lock() {
+            let _ = self.write_pending(&mut io);
+        }
+    }
+}
+
+/// What `verify` found in a WAL file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalHealthReport {
+    pub manifest: ManifestStatus,
+    pub file_len: u64,
+    /// Length up to the end of the last whole record group
+    pub valid_len: u64,
+    pub records: usize,
+    /// Leaves with records in the log
+    pub pages: usize,
+    pub committed_txns: usize,
+    pub aborted_txns: usize,
+    /// Transactions with a begin marker but no commit or abort, replay rolls them back
+    pub unfinished_txns: usize,
+    /// The unfinished transactions that were prepared, replay leaves them in doubt
+    pub prepared_txns: usize,
+}
+
+impl WalHealthReport {
+    /// Bytes past the last whole group that opening cuts off
+    pub fn trailing_bytes(&self) -> u64 {
+        self.file_len.saturating_sub(self.valid_len)
+    }
+
+    /// Whether opening would use the file as it is, without trimming or rewriting it
+    pub fn is_healthy(&self) -> bool {
+        match self.manifest {
+            ManifestStatus::Valid { checkpoint_len } => {
+                self.trailing_bytes() == 0 && checkpoint_len <= self.valid_len
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Check the WAL at `path` without changing it, as `WalManager::open` would read it
+pub fn verify(path: &Path) -> io::Result<WalHealthReport> {
+    verify_with_auth(path, &MetadataAuth::default())
+}
+
+/// Format version in the manifest of the WAL at `path`, without checking its tag. `None`
+/// if the file is shorter than a manifest or lacks the manifest magic
+pub fn manifest_version(path: &Path) -> io::Result<Option<u32>> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() < MANIFEST_LEN {
+        return Ok(None);
+    }
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)?;
+    if header[0..4] != MANIFEST_MAGIC {
+        return Ok(None);
+    }
+    Ok(Some(u32::from_le_bytes(header[4..8].try_into().unwrap())))
+}
+
+pub fn verify_with_auth(path: &Path, auth: &MetadataAuth) -> io::Result<WalHealthReport> {
+    let mut file = Storage::File(File::open(path)?);
+    let manifest = manifest_status(&mut file, auth)?;
+    let file_len = file.len()?;
+    let (records, valid_len) = if file_len < MANIFEST_LEN {
+        (Vec::new(), MANIFEST_LEN)
+    } else {
+        let (records, _, valid_len, _, _) = read_records(&mut file)?;
+        (records, valid_len)
+    };
+
+    let mut finished = HashMap::new();
+    let mut begun = HashSet::new();
+    for record in records.iter() {
+        match record.op {
+            WalOp::TxnMarker(WalTxnMarker::Begin) => {
+                begun.insert(record.txn_id);
+            }
+            WalOp::TxnMarker(WalTxnMarker::Prepare) => {}
+            WalOp::TxnMarker(marker) => {
+                finished.insert(record.txn_id, matches!(marker, WalTxnMarker::Commit));
+            }
+            _ => {}
+        }
+    }
+    let committed_txns = finished.values().filter(|committed| **committed).count();
+    Ok(WalHealthReport {
+        manifest,
+        file_len,
+        valid_len,
+        records: records.len(),
+        pages: records
+            .iter()
+            .map(|record| record.page_id)
+            .filter(|page| *page != TXN_META_PAGE_ID && *page != STRUCTURE_PAGE_ID)
+            .collect::<HashSet<_>>()
+            .len(),
+        committed_txns,
+        aborted_txns: finished.len() - committed_txns,
+        unfinished_txns: begun
+            .iter()
+            .filter(|txn| !finished.contains_key(txn))
+            .count(),
+        prepared_txns: in_doubt_txns(&records).len(),
+    })
+}
+
+/// Cut the WAL at `path` back to its last whole group and fix up its manifest, the way
+/// `WalManager::open` does, then report on the result. A manifest that fails
+/// authentication is left alone and fails with `InvalidData`
+pub fn truncate_to_valid(path: &Path) -> io::Result<WalHealthReport> {
+    truncate_to_valid_with_auth(path, &MetadataAuth::default())
+}
+
+pub fn truncate_to_valid_with_auth(
+    path: &Path,
+    auth: &MetadataAuth,
+) -> io::Result<WalHealthReport> {
+    {
+        let mut file = Storage::File(OpenOptions::new().read(true).write(true).open(path)?);
+        repair(&mut file, auth)?;
+    }
+    verify_with_auth(path, auth)
+}
+
+/// A whole record group in a WAL file, see `inspect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalGroup {
+    /// Offset of the group's header in the file
+    pub offset: u64,
+    pub bytes: u64,
+    pub records: usize,
+    /// Page id in the header, `None` for a packed group, whose records name their own
+    pub page_id: Option<u64>,
+    /// Sequence numbers of the group's first and last record
+    pub first_lsn: u64,
+    pub last_lsn: u64,
+}
+
+impl WalGroup {
+    /// The group of `records` read from `bytes` bytes at `start` past the manifest
+    fn read(start: usize, bytes: usize, packed: bool, records: &[WalRecord]) -> WalGroup {
+        WalGroup {
+            offset: MANIFEST_LEN + start as u64,
+            bytes: bytes as u64,
+            records: records.len(),
+            page_id: match packed {
+                true => None,
+                false => records.first().map(|record| record.page_id),
+            },
+            first_lsn: records.first().map_or(0, |record| record.lsn),
+            last_lsn: records.last().map_or(0, |record| record.lsn),
+        }
+    }
+}
+
+/// A leaf's records in a WAL file, by kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WalPageRecords {
+    pub puts: usize,
+    pub merges: usize,
+    pub tombstones: usize,
+    pub usage: WalUsage,
+}
+
+/// A transaction's records in a WAL file, its markers included
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalTxnRecords {
+    /// Markers in log order
+    pub markers: Vec<WalTxnMarker>,
+    pub usage: WalUsage,
+}
+
+impl WalTxnRecords {
+    /// The commit or abort that finished the transaction, `None` while it is unfinished
+    pub fn outcome(&self) -> Option<WalTxnMarker> {
+        self.markers
+            .iter()
+            .rev()
+            .find(|marker| matches!(marker, WalTxnMarker::Commit | WalTxnMarker::Abort))
+            .copied()
+    }
+}
+
+/// What `inspect` read from a WAL file. Record bytes are counted as `WalStats` counts
+/// them, group headers included and LSN markers with the record after them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalInspection {
+    pub manifest: ManifestStatus,
+    pub file_len: u64,
+    /// Length up to the end of the last whole record group
+    pub valid_len: u64,
+    /// Records parsed from the torn group past `valid_len`, which opening cuts off with it
+    pub discarded_records: usize,
+    /// Sequence number the next record appended would be read back with
+    pub next_lsn: u64,
+    pub groups: Vec<WalGroup>,
+    pub total: WalUsage,
+    pub txn_markers: WalUsage,
+    /// Structure records, see `WalSmo`
+    pub structure: WalUsage,
+    /// Leaf records by page id
+    pub pages: BTreeMap<u64, WalPageRecords>,
+    /// Records by transaction id, markers included
+    pub txns: BTreeMap<u64, WalTxnRecords>,
+}
+
+impl WalInspection {
+    /// Where opening would cut the file, `None` if it ends on a whole group
+    pub fn truncation_point(&self) -> Option<u64> {
+        (self.valid_len < self.file_len).then_some(self.valid_len)
+    }
+}
+
+/// Read the WAL at `path` group by group without changing it or opening a database, for
+/// diagnosing a log that fails to replay
+pub fn inspect(path: &Path) -> io::Result<WalInspection> {
+    inspect_with_auth(path, &MetadataAuth::default())
+}
+
+pub fn inspect_with_auth(path: &Path, auth: &MetadataAuth) -> io::Result<WalInspection> {
+    let mut file = Storage::File(File::open(path)?);
+    let manifest = manifest_status(&mut file, auth)?;
+    let file_len = file.len()?;
+    let mut groups = Vec::new();
+    let (records, sizes, valid_len, discarded_records, next_lsn) = if file_len < MANIFEST_LEN {
+        (Vec::new(), Vec::new(), MANIFEST_LEN, 0, 1)
+    } else {
+        read_groups(&mut file, &mut groups)?
+    };
+
+    let mut inspection = WalInspection {
+        manifest,
+        file_len,
+        valid_len,
+        discarded_records,
+        next_lsn,
+        groups,
+        total: WalUsage::default(),
+        txn_markers: WalUsage::default(),
+        structure: WalUsage::default(),
+        pages: BTreeMap::new(),
+        txns: BTreeMap::new(),
+    };
+    for (record, size) in records.iter().zip(&sizes) {
+        inspection.total.add(*size);
+        let txn = inspection.txns.entry(record.txn_id).or_default();
+        txn.usage.add(*size);
+        match &record.op {
+            WalOp::TxnMarker(marker) => {
+                txn.markers.push(*marker);
+                inspection.txn_markers.add(*size);
+            }
+            WalOp::Smo(_) => inspection.structure.add(*size),
+            op => {
+                let page = inspection.pages.entry(record.page_id).or_default();
+                match op {
+                    WalOp::Put { .. } => page.puts += 1,
+                    WalOp::Merge { .. } => page.merges += 1,
+                    _ => page.tombstones += 1,
+                }
+                page.usage.add(*size);
+            }
+        }
+    }
+    Ok(inspection)
+}
+
+/// Trim `file` to its last whole group and bring the manifest in line with what is left,
+/// returning the manifest, the records kept with their sizes, the length kept, how many
+/// records were cut off and the sequence number the next record appended is read back with
+#[allow(clippy::type_complexity)]
+fn repair(
+    file: &mut Storage,
+    auth: &MetadataAuth,
+) -> io::Result<(WalManifest, Vec<WalRecord>, Vec<usize>, u64, usize, u64)> {
+    let mut manifest = read_manifest(file, auth)?;
+    let (records, sizes, valid_len, discarded, next_lsn) = read_records(file)?;
+    let file_len = file.len()?;
+    if valid_len < file_len {
+        file.set_len(valid_len)?;
+    }
+    if manifest.checkpoint_len > valid_len {
+        manifest.checkpoint_len = valid_len;
+        write_manifest(file, manifest, auth)?;
+    }
+    file.sync_data()?;
+    file.seek(SeekFrom::End(0))?;
+    Ok((manifest, records, sizes, valid_len, discarded, next_lsn))
 }
 
-impl WalManager {
-    pub fn open(path: &Path) -> io::Result<WalManager> {
-        if let Some(parent) = path.parent() {
-            if !parent.as_os_str().is_empty() {
-                fs::create_dir_all(parent)?;
+/// Transactions begun in `records` and neither committed nor aborted
+fn in_flight_txns(records: &[WalRecord]) -> HashSet<u64> {
+    let mut in_flight = HashSet::new();
+    for record in records {
+        match record.op {
+            WalOp::TxnMarker(WalTxnMarker::Begin) => {
+                in_flight.insert(record.txn_id);
             }
+            WalOp::TxnMarker(WalTxnMarker::Commit | WalTxnMarker::Abort) => {
+                in_flight.remove(&record.txn_id);
+            }
+            _ => {}
         }
-
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)?;
-
-        let mut manifest = read_manifest(&mut file)?;
-        let (records, page_bytes, valid_len) = read_records(&mut file)?;
-        let file_len = file.metadata()?.len();
-        if valid_len < file_len {
-            file.set_len(valid_len)?;
-        }
-        if manifest.checkpoint_len > valid_len {
-            manifest.checkpoint_len = valid_len;
-            write_manifest(&mut file, manifest)?;
-            file.sync_data()?;
-        }
-        file.seek(SeekFrom::End(0))?;
-
-        let mut leaf_counts = HashMap::new();
-        let total_bytes = valid_len as usize;
-        for record in records.iter() {
-            let entry = leaf_counts
-                .entry(record.page_id)
-                .or_insert(LeafWalStats { count: 0, bytes: 0 });
-            entry.count += 1;
-        }
-        for (page_id, bytes) in page_bytes.into_iter() {
-            leaf_counts
-                .entry(page_id)
-                .and_modify(|stats| stats.bytes = bytes)
-                .or_insert(LeafWalStats { count: 0, bytes });
-        }
-        let total_records = records.len();
-
-        Ok(WalManager {
-            state: Mutex::new(WalState {
-                file,
-                records,
-                leaf_counts,
-                total_records,
-                total_bytes,
-                manifest,
-            }),
-        })
     }
+    in_flight
+}
 
-    pub fn records(&self) -> Vec<WalRecord> {
-        let state = self.state.lock().expect("wal mutex poisoned");
-        state.records.clone()
+/// Transactions prepared in `records` and neither committed nor aborted
+fn in_doubt_txns(records: &[WalRecord]) -> HashSet<u64> {
+    let mut in_doubt = HashSet::new();
+    for record in records {
+        match record.op {
+            WalOp::TxnMarker(WalTxnMarker::Prepare) => {
+                in_doubt.insert(record.txn_id);
+            }
+            WalOp::TxnMarker(WalTxnMarker::Commit | WalTxnMarker::Abort) => {
+                in_doubt.remove(&record.txn_id);
+            }
+            _ => {}
+        }
     }
+    in_doubt
+}
 
-    pub fn records_grouped(&self) -> BTreeMap<u64, Vec<WalRecord>> {
-        let state = self.state.lock().expect("wal mutex poisoned");
-        let mut grouped: BTreeMap<u64, Vec<WalRecord>> = BTreeMap::new();
-        for record in state.records.iter() {
-            grouped
-                .entry(record.page_id)
-                .or_default()
-                .push(record.clone());
+/// Drop the records replay would end up not using: the undo records of committed
+/// transactions, and a committed write to a key of a leaf that a later committed write to
+/// the same key replaces. The newest write is kept even if it is a tombstone, as the leaf on
+/// disk may still hold the key. Records of transactions running or rolled back are all kept
+fn coalesce(records: Vec<WalRecord>) -> Vec<WalRecord> {
+    let committed: HashSet<u64> = records
+        .iter()
+        .filter(|record| matches!(record.op, WalOp::TxnMarker(WalTxnMarker::Commit)))
+        .map(|record| record.txn_id)
+        .collect();
+    let mut replaced: HashSet<(u64, &[u8])> = HashSet::new();
+    let mut keep = vec![true; records.len()];
+    for (idx, record) in records.iter().enumerate().rev() {
+        if record.page_id == STRUCTURE_PAGE_ID
+            || matches!(record.op, WalOp::TxnMarker(_) | WalOp::Smo(_))
+            || !committed.contains(&record.txn_id)
+        {
+            continue;
         }
-        grouped
+        keep[idx] = match record.kind {
+            WalEntryKind::Undo => false,
+            WalEntryKind::Redo => replaced.insert((record.page_id, record.key.as_slice())),
+        };
     }
+    records
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(record, keep)| keep.then_some(record))
+        .collect()
+}
 
-    pub fn append_tombstone(
-        &self,
-        page_id: PageId,
-        key: &[u8],
-        lower_fence: &[u8],
-        upper_fence: &[u8],
-        kind: WalEntryKind,
-        txn_id: u64,
-    ) -> io::Result<()> {
-        self.append_record(WalRecord {
-            page_id: page_id.as_u64(),
-            key: key.to_vec(),
-            lower_fence: lower_fence.to_vec(),
-            upper_fence: upper_fence.to_vec(),
-            kind,
-            txn_id,
-            op: WalOp::Tombstone,
-        })
+/// Replace the records of the log with `records`, ending it so the next record appended is
+/// read back as `next_lsn`. Returns the bytes each record takes and the bytes written
+fn rewrite_records(
+    file: &mut Storage,
+    records: &[WalRecord],
+    next_lsn: u64,
+) -> io::Result<(Vec<usize>, usize)> {
+    file.set_len(MANIFEST_LEN)?;
+    file.seek(SeekFrom::Start(MANIFEST_LEN))?;
+    let mut buf = Vec::new();
+    let mut numbered = 1;
+    let sizes = write_groups(&mut buf, records, &mut numbered)?;
+    if numbered != next_lsn {
+        // a marker no record follows, only there to number the next append
+        write_lsn_marker(&mut buf, next_lsn)?;
     }
+    file.write_all(&buf)?;
+    file.sync_data()?;
+    Ok((sizes, buf.len()))
+}
 
-    pub fn append_put(
-        &self,
-        page_id: PageId,
-        key: &[u8],
-        value: &[u8],
-        lower_fence: &[u8],
-        upper_fence: &[u8],
-        kind: WalEntryKind,
-        txn_id: u64,
-    ) -> io::Result<()> {
-        self.append_record(WalRecord {
-            page_id: page_id.as_u64(),
-            key: key.to_vec(),
-            lower_fence: lower_fence.to_vec(),
-            upper_fence: upper_fence.to_vec(),
-            kind,
-            txn_id,
-            op: WalOp::Put {
-                value: value.to_vec(),
-            },
-        })
+/// The sequence number the record after one numbered `lsn` is read back with
+fn lsn_after(lsn: u64) -> u64 {
+    match lsn {
+        0 => 0,
+        lsn => lsn + 1,
     }
+}
 
-    pub fn append_txn_marker(
-        &self,
-        marker: WalTxnMarker,
-        kind: WalEntryKind,
-        txn_id: u64,
-    ) -> io::Result<()> {
-        self.append_record(WalRecord {
-            page_id: TXN_META_PAGE_ID,
-            key: Vec::new(),
-            lower_fence: Vec::new(),
-            upper_fence: Vec::new(),
-            kind,
-            txn_id,
-            op: WalOp::TxnMarker(marker),
-        })
-    }
+fn write_lsn_marker<W: Write>(file: &mut W, lsn: u64) -> io::Result<usize> {
+    file.write_all(&[LSN_MARKER])?;
+    file.write_all(&lsn.to_le_bytes())?;
+    Ok(LSN_MARKER_LEN)
+}
 
-    fn append_record(&self, record: WalRecord) -> io::Result<()> {
-        let mut state = self.state.lock().expect("wal mutex poisoned");
-        state.file.seek(SeekFrom::End(0))?;
-        state.records.push(record.clone());
-        state.total_records += 1;
-        state
-            .leaf_counts
-            .entry(record.page_id)
-            .or_insert(LeafWalStats { count: 0, bytes: 0 })
-            .count += 1;
-        let bytes_written = write_group(
-            &mut state.file,
-            record.page_id,
-            std::slice::from_ref(&record),
-        )?;
-        if let Some(entry) = state.leaf_counts.get_mut(&record.page_id) {
-            entry.bytes = entry.bytes.saturating_add(bytes_written);
-        }
-        state.total_bytes = state
-            .total_bytes
-            .checked_add(bytes_written)
-            .expect("wal byte counter overflow");
-        state.file.sync_data()?;
-        Ok(())
+/// Write `records` in order, `next_lsn` being the sequence number the log numbers the next
+/// record with and left at the one after them. Each run of records numbered one after the
+/// other is written as groups, behind an LSN marker if it does not carry on from `next_lsn`.
+/// Returns the bytes each record takes, a marker counted with the record after it
+fn write_groups<W: Write>(
+    file: &mut W,
+    records: &[WalRecord],
+    next_lsn: &mut u64,
+) -> io::Result<Vec<usize>> {
+    let mut sizes = Vec::with_capacity(records.len());
+    let mut start = 0;
+    while start < records.len() {
+        let run = 1 + records[start..]
+            .windows(2)
+            .take_while(|pair| pair[1].lsn == lsn_after(pair[0].lsn))
+            .count();
+        let marker = match records[start].lsn == *next_lsn {
+            true => 0,
+            false => write_lsn_marker(file, records[start].lsn)?,
+        };
+        let first = sizes.len();
+        write_numbered_run(file, &records[start..start + run], &mut sizes)?;
+        sizes[first] += marker;
+        *next_lsn = lsn_after(records[start + run - 1].lsn);
+        start += run;
     }
+    Ok(sizes)
+}
 
-    pub fn checkpoint_page(&self, page_id: PageId) -> io::Result<()> {
-        let page_key = page_id.as_u64();
-        let mut state = self.state.lock().expect("wal mutex poisoned");
-        if state
-            .records
+/// Write `records` as groups, one per run of a page's records, pushing the bytes each
+/// record takes, its group's header counted with the group's first record. Runs of two or
+/// more records of one transaction with the same fences go in packed groups
+fn write_numbered_run<W: Write>(
+    file: &mut W,
+    records: &[WalRecord],
+    sizes: &mut Vec<usize>,
+) -> io::Result<()> {
+    let mut loose = 0;
+    let mut idx = 0;
+    while idx < records.len() {
+        let first = &records[idx];
+        let run = records[idx..]
             .iter()
-            .all(|record| record.page_id != page_key)
-        {
-            return Ok(());
+            .take_while(|record| {
+                record.page_id == first.page_id
+                    && record.txn_id == first.txn_id
+                    && record.lower_fence == first.lower_fence
+                    && record.upper_fence == first.upper_fence
+            })
+            .count();
+        let page_changes =
+            idx + run == records.len() || records[idx + run].page_id != first.page_id;
+        if run >= 2 {
+            write_loose_group(file, &records[loose..idx], sizes)?;
+            let bytes = write_packed_group(file, &records[idx..idx + run])?;
+            push_group_sizes(sizes, &records[idx..idx + run], bytes, packed_record_size);
+            loose = idx + run;
+        } else if page_changes {
+            write_loose_group(file, &records[loose..idx + run], sizes)?;
+            loose = idx + run;
         }
-        state.records.retain(|record| record.page_id != page_key);
-        let snapshot = state.records.clone();
-        let stats = rewrite_records(&mut state.file, &snapshot)?;
-        state.leaf_counts = stats;
-        state.total_records = state.records.len();
-        state.total_bytes = state
-            .leaf_counts
-            .values()
-            .fold(0usize, |acc, entry| acc.saturating_add(entry.bytes));
-        state.manifest.checkpoint_len = MANIFEST_LEN + state.total_bytes as u64;
-        let manifest = state.manifest;
-        write_manifest(&mut state.file, manifest)?;
-        state.file.sync_data()?;
-        state.file.seek(SeekFrom::End(0))?;
-        Ok(())
-    }
-
-    pub fn clear(&self) -> io::Result<()> {
-        let mut state = self.state.lock().expect("wal mutex poisoned");
-        state.records.clear();
-        state.leaf_counts.clear();
-        state.total_records = 0;
-        state.total_bytes = 0;
-        state.manifest = WalManifest::new();
-        let manifest = state.manifest;
-        state.file.set_len(MANIFEST_LEN)?;
-        write_manifest(&mut state.file, manifest)?;
-        state.file.sync_data()?;
-        state.file.seek(SeekFrom::End(0))?;
-        Ok(())
+        idx += run;
     }
+    Ok(())
+}
 
-    pub fn should_checkpoint_page(&self, page_id: PageId, threshold: usize) -> bool {
-        let state = self.state.lock().expect("wal mutex poisoned");
-        state
-            .leaf_counts
-            .get(&page_id.as_u64())
-            .map(|stats| stats.count >= threshold)
-            .unwrap_or(false)
+fn write_loose_group<W: Write>(
+    file: &mut W,
+    records: &[WalRecord],
+    sizes: &mut Vec<usize>,
+) -> io::Result<()> {
+    if let Some(first) = records.first() {
+        let bytes = write_group(file, first.page_id, records)?;
+        push_group_sizes(sizes, records, bytes, record_size);
     }
+    Ok(())
+}
 
-    pub fn total_records(&self) -> usize {
-        let state = self.state.lock().expect("wal mutex poisoned");
-        state.total_records
-    }
+/// Split a group's `bytes` over its records, the header going to the first
+fn push_group_sizes(
+    sizes: &mut Vec<usize>,
+    records: &[WalRecord],
+    bytes: usize,
+    record_size: fn(&WalRecord) -> usize,
+) {
+    let start = sizes.len();
+    sizes.extend(records.iter().map(record_size));
+    let payload: usize = sizes[start..].iter().sum();
+    sizes[start] += bytes - payload;
+}
 
-    pub fn total_bytes(&self) -> usize {
-        let state = self.state.lock().expect("wal mutex poisoned");
-        state.total_bytes
+/// `| marker | page_id: u64 | count: u32 | txn_id: u64 | lower_len: u32 | upper_len: u32 |
+/// kind bitmap | lower | upper |` and then each record as its type byte and the lengths and
+/// bytes of its own fields. Bit `i` of the bitmap is set for an undo record `i`. The
+/// records must share page, transaction and fences
+fn write_packed_group<W: Write>(file: &mut W, records: &[WalRecord]) -> io::Result<usize> {
+    let first = &records[0];
+    let count = u32::try_from(records.len()).expect("record group too large");
+    let mut kinds = vec![0u8; records.len().div_ceil(8)];
+    for (idx, record) in records.iter().enumerate() {
+        if matches!(record.kind, WalEntryKind::Undo) {
+            kinds[idx / 8] |= 1 << (idx % 8);
+        }
     }
-
-    pub fn leaf_stats(&self, page_id: PageId) -> Option<(usize, usize)> {
-        let state = self.state.lock().expect("wal mutex poisoned");
-        state
-            .leaf_counts
-            .get(&page_id.as_u64())
-            .map(|stats| (stats.count, stats.bytes))
+    file.write_all(&[PACKED_GROUP_MARKER])?;
+    file.write_all(&first.page_id.to_le_bytes())?;
+    file.write_all(&count.to_le_bytes())?;
+    file.write_all(&first.txn_id.to_le_bytes())?;
+    file.write_all(&(first.lower_fence.len() as u32).to_le_bytes())?;
+    file.write_all(&(first.upper_fence.len() as u32).to_le_bytes())?;
+    file.write_all(&kinds)?;
+    file.write_all(&first.lower_fence)?;
+    file.write_all(&first.upper_fence)?;
+    let mut payload = 0usize;
+    for record in records {
+        let fields: Vec<&[u8]> = match &record.op {
+            WalOp::Put { value } => {
+                file.write_all(&[RECORD_TYPE_PUT])?;
+                vec![&record.key, value]
+            }
+            WalOp::Merge { operand, value } => {
+                file.write_all(&[RECORD_TYPE_MERGE])?;
+                vec![&record.key, operand, value]
+            }
+            WalOp::Tombstone => {
+                file.write_all(&[RECORD_TYPE_TOMBSTONE])?;
+                vec![&record.key]
+            }
+            WalOp::TxnMarker(marker) => {
+                file.write_all(&[marker.to_record_type()])?;
+                Vec::new()
+            }
+            WalOp::Smo(smo) => {
+                file.write_all(&[smo.record_type()])?;
+                vec![&record.key]
+            }
+        };
+        for field in fields.iter() {
+            file.write_all(&(field.len() as u32).to_le_bytes())?;
+        }
+        for field in fields.iter() {
+            file.write_all(field)?;
+        }
+        if let WalOp::Smo(smo) = &record.op {
+            for id in smo.ids() {
+                file.write_all(&id.to_le_bytes())?;
+            }
+        }
+        payload += packed_record_size(record);
     }
+    Ok(PACKED_HEADER_LEN
+        + kinds.len()
+        + first.lower_fence.len()
+        + first.upper_fence.len()
+        + payload)
+}
 
-    pub fn global_checkpoint_candidate(
-        &self,
-        total_record_threshold: usize,
-        total_byte_threshold: usize,
-    ) -> Option<PageId> {
-        let state = self.state.lock().expect("wal mutex poisoned");
-        if state.total_records < total_record_threshold && state.total_bytes < total_byte_threshold
-        {
-            return None;
-        }
-        state
-            .leaf_counts
-            .iter()
-            .filter(|(page, _)| **page != TXN_META_PAGE_ID)
-            .max_by_key(|(_, stats)| stats.bytes)
-            .map(|(page, _)| PageId(*page))
+/// Bytes a record takes in a packed group, not counting the group's header
+fn packed_record_size(record: &WalRecord) -> usize {
+    1 + match &record.op {
+        WalOp::Put { value } => 4 * 2 + record.key.len() + value.len(),
+        WalOp::Merge { operand, value } => 4 * 3 + record.key.len() + operand.len() + value.len(),
+        WalOp::Tombstone => 4 + record.key.len(),
+        WalOp::TxnMarker(_) => 0,
+        WalOp::Smo(smo) => 4 + record.key.len() + 8 * smo.ids().len(),
     }
 }
 
-fn rewrite_records(
-    file: &mut File,
-    records: &[WalRecord],
-) -> io::Result<HashMap<u64, LeafWalStats>> {
-    file.set_len(MANIFEST_LEN)?;
-    file.seek(SeekFrom::Start(MANIFEST_LEN))?;
-    let mut stats: HashMap<u64, LeafWalStats> = HashMap::new();
-    let mut idx = 0usize;
-    while idx < records.len() {
-        let page_id = records[idx].page_id;
-        let mut end = idx + 1;
-        while end < records.len() && records[end].page_id == page_id {
-            end += 1;
-        }
-        let bytes_written = write_group(file, page_id, &records[idx..end])?;
-        stats
-            .entry(page_id)
-            .and_modify(|entry| {
-                entry.count += end - idx;
-                entry.bytes = entry.bytes.saturating_add(bytes_written);
-            })
-            .or_insert(LeafWalStats {
-                count: end - idx,
-                bytes: bytes_written,
-            });
-        idx = end;
+/// Parse the packed group starting `bytes`, pushing its records onto `records` as they are
+/// read. Returns the group's length, `None` if it is cut off or malformed
+fn read_packed_group(bytes: &[u8], records: &mut Vec<WalRecord>) -> Option<usize> {
+    let mut idx = 1;
+    let mut take = |len: usize| {
+        let field = bytes.get(idx..idx + len)?;
+        idx += len;
+        Some(field)
+    };
+    let page_id = u64::from_le_bytes(take(8)?.try_into().ok()?);
+    let count = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+    let txn_id = u64::from_le_bytes(take(8)?.try_into().ok()?);
+    let lower_len = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+    let upper_len = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+    let kinds = take(count.div_ceil(8))?;
+    let lower_fence = take(lower_len)?;
+    let upper_fence = take(upper_len)?;
+    for record_idx in 0..count {
+        let record_type = take(1)?[0];
+        let field_count = match record_type {
+            RECORD_TYPE_PUT => 2,
+            RECORD_TYPE_MERGE => 3,
+            RECORD_TYPE_TOMBSTONE
+            | RECORD_TYPE_SMO_SPLIT
+            | RECORD_TYPE_SMO_MERGE
+            | RECORD_TYPE_SMO_RELOCATE => 1,
+            _ => 0,
+        };
+        let mut lens = [0usize; 3];
+        for len in lens.iter_mut().take(field_count) {
+            *len = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+        }
+        let mut fields = Vec::with_capacity(field_count);
+        for len in lens.iter().take(field_count) {
+            fields.push(take(*len)?.to_vec());
+        }
+        let mut fields = fields.into_iter();
+        let key = fields.next().unwrap_or_default();
+        let op = match record_type {
+            RECORD_TYPE_PUT => WalOp::Put {
+                value: fields.next()?,
+            },
+            RECORD_TYPE_MERGE => WalOp::Merge {
+                operand: fields.next()?,
+                value: fields.next()?,
+            },
+            RECORD_TYPE_TOMBSTONE => WalOp::Tombstone,
+            RECORD_TYPE_SMO_SPLIT | RECORD_TYPE_SMO_MERGE | RECORD_TYPE_SMO_RELOCATE => {
+                let mut ids = Vec::new();
+                for _ in 0..WalSmo::id_count(record_type)? {
+                    ids.push(u64::from_le_bytes(take(8)?.try_into().ok()?));
+                }
+                WalOp::Smo(WalSmo::from_ids(record_type, &ids)?)
+            }
+            _ => WalOp::TxnMarker(WalTxnMarker::from_record_type(record_type)?),
+        };
+        let kind = if kinds[record_idx / 8] & (1 << (record_idx % 8)) != 0 {
+            WalEntryKind::Undo
+        } else {
+            WalEntryKind::Redo
+        };
+        records.push(WalRecord {
+            page_id,
+            key,
+            lower_fence: lower_fence.to_vec(),
+            upper_fence: upper_fence.to_vec(),
+            kind,
+            txn_id,
+            lsn: 0,
+            op,
+        });
     }
-    file.sync_data()?;
-    Ok(stats)
+    Some(idx)
 }
 
-fn write_group(file: &mut File, page_id: u64, records: &[WalRecord]) -> io::Result<usize> {
+fn write_group<W: Write>(file: &mut W, page_id: u64, records: &[WalRecord]) -> io::Result<usize> {
     if records.is_empty() {
         return Ok(0);
     }
@@ -410,7 +2253,7 @@ fn write_group(file: &mut File, page_id: u64, records: &[WalRecord]) -> io::Resu
     Ok(GROUP_HEADER_LEN + payload)
 }
 
-fn write_record_payload(file: &mut File, record: &WalRecord) -> io::Result<usize> {
+fn write_record_payload<W: Write>(file: &mut W, record: &WalRecord) -> io::Result<usize> {
     match &record.op {
         WalOp::Put { value } => {
             file.write_all(&[RECORD_TYPE_PUT])?;
@@ -440,6 +2283,26 @@ fn write_record_payload(file: &mut File, record: &WalRecord) -> io::Result<usize
                 + record.lower_fence.len()
                 + record.upper_fence.len())
         }
+        WalOp::Merge { operand, value } => {
+            file.write_all(&[RECORD_TYPE_MERGE])?;
+            file.write_all(&[record.kind.as_byte()])?;
+            file.write_all(&record.txn_id.to_le_bytes())?;
+            for len in [
+                record.key.len(),
+                operand.len(),
+                value.len(),
+                record.lower_fence.len(),
+                record.upper_fence.len(),
+            ] {
+                file.write_all(&(len as u32).to_le_bytes())?;
+            }
+            file.write_all(&record.key)?;
+            file.write_all(operand)?;
+            file.write_all(value)?;
+            file.write_all(&record.lower_fence)?;
+            file.write_all(&record.upper_fence)?;
+            Ok(record_size(record))
+        }
         WalOp::Tombstone => {
             file.write_all(&[RECORD_TYPE_TOMBSTONE])?;
             file.write_all(&[record.kind.as_byte()])?;
@@ -470,19 +2333,96 @@ fn write_record_payload(file: &mut File, record: &WalRecord) -> io::Result<usize
             let header_bytes = 1 + 8;
             Ok(header_bytes + 1)
         }
+        WalOp::Smo(smo) => {
+            file.write_all(&[smo.record_type()])?;
+            file.write_all(&[record.kind.as_byte()])?;
+            file.write_all(&record.txn_id.to_le_bytes())?;
+            file.write_all(&(record.key.len() as u32).to_le_bytes())?;
+            file.write_all(&record.key)?;
+            for id in smo.ids() {
+                file.write_all(&id.to_le_bytes())?;
+            }
+            Ok(record_size(record))
+        }
     }
 }
 
-fn read_records(file: &mut File) -> io::Result<(Vec<WalRecord>, HashMap<u64, usize>, u64)> {
+/// The records `read_records` finds, leaving the file positioned for the next append
+fn read_written(file: &mut Storage) -> io::Result<Vec<WalRecord>> {
+    let (records, ..) = read_records(file)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(records)
+}
+
+/// Records of the whole groups, numbered, the bytes each takes as `write_groups` counts
+/// them, the length up to the end of the last whole group, how many records were parsed
+/// from a torn group after it and the sequence number the next record appended is read
+/// back with. A group of no records, which is never written, ends the log
+#[allow(clippy::type_complexity)]
+fn read_records(file: &mut Storage) -> io::Result<(Vec<WalRecord>, Vec<usize>, u64, usize, u64)> {
+    read_groups(file, &mut Vec::new())
+}
+
+/// `read_records`, also pushing each whole group onto `groups`
+#[allow(clippy::type_complexity)]
+fn read_groups(
+    file: &mut Storage,
+    groups: &mut Vec<WalGroup>,
+) -> io::Result<(Vec<WalRecord>, Vec<usize>, u64, usize, u64)> {
     file.seek(SeekFrom::Start(MANIFEST_LEN))?;
     let mut bytes = Vec::new();
     file.read_to_end(&mut bytes)?;
     let mut idx = 0usize;
     let mut records = Vec::new();
-    let mut page_bytes: HashMap<u64, usize> = HashMap::new();
+    let mut sizes = Vec::new();
     let mut valid_idx = 0usize;
+    let mut valid_records = 0usize;
+    let mut next_lsn = 1;
+    // bytes of the LSN markers before the next group, counted with its first record
+    let mut marker_bytes = 0usize;
 
-    'outer: while bytes.len().saturating_sub(idx) >= GROUP_HEADER_LEN {
+    'outer: while idx < bytes.len() {
+        if bytes[idx] == LSN_MARKER {
+            let Some(lsn) = bytes.get(idx + 1..idx + LSN_MARKER_LEN) else {
+                break;
+            };
+            next_lsn = u64::from_le_bytes(lsn.try_into().unwrap());
+            marker_bytes += LSN_MARKER_LEN;
+            idx += LSN_MARKER_LEN;
+            valid_idx = idx;
+            continue;
+        }
+        if bytes.len() - idx < GROUP_HEADER_LEN {
+            break;
+        }
+        let group_start = idx;
+        if bytes[idx] == PACKED_GROUP_MARKER {
+            let Some(group_bytes) = read_packed_group(&bytes[idx..], &mut records) else {
+                break;
+            };
+            if records.len() == valid_records {
+                break;
+            }
+            number_group(&mut records[valid_records..], &mut next_lsn);
+            groups.push(WalGroup::read(
+                group_start,
+                group_bytes,
+                true,
+                &records[valid_records..],
+            ));
+            let first = sizes.len();
+            push_group_sizes(
+                &mut sizes,
+                &records[valid_records..],
+                group_bytes,
+                packed_record_size,
+            );
+            sizes[first] += mem::take(&mut marker_bytes);
+            idx += group_bytes;
+            valid_idx = idx;
+            valid_records = records.len();
+            continue;
+        }
         if bytes[idx] != GROUP_MARKER {
             break;
         }
@@ -537,6 +2477,7 @@ fn read_records(file: &mut File) -> io::Result<(Vec<WalRecord>, HashMap<u64, usi
                         upper_fence: upper,
                         kind: entry_kind,
                         txn_id,
+                        lsn: 0,
                         op: WalOp::Tombstone,
                     };
                     payload_bytes = payload_bytes.saturating_add(record_size(&record));
@@ -576,12 +2517,46 @@ fn read_records(file: &mut File) -> io::Result<(Vec<WalRecord>, HashMap<u64, usi
                         upper_fence: upper,
                         kind: entry_kind,
                         txn_id,
+                        lsn: 0,
                         op: WalOp::Put { value },
                     };
                     payload_bytes = payload_bytes.saturating_add(record_size(&record));
                     records.push(record);
                 }
-                RECORD_TYPE_TXN_BEGIN | RECORD_TYPE_TXN_COMMIT | RECORD_TYPE_TXN_ABORT => {
+                RECORD_TYPE_MERGE => {
+                    if bytes.len() - idx < 20 {
+                        break 'outer;
+                    }
+                    let mut lens = [0usize; 5];
+                    for len in lens.iter_mut() {
+                        *len = u32::from_le_bytes(bytes[idx..idx + 4].try_into().unwrap()) as usize;
+                        idx += 4;
+                    }
+                    if bytes.len() - idx < lens.iter().sum::<usize>() {
+                        break 'outer;
+                    }
+                    let [key, operand, value, lower, upper] = lens.map(|len| {
+                        let field = bytes[idx..idx + len].to_vec();
+                        idx += len;
+                        field
+                    });
+                    let record = WalRecord {
+                        page_id,
+                        key,
+                        lower_fence: lower,
+                        upper_fence: upper,
+                        kind: entry_kind,
+                        txn_id,
+                        lsn: 0,
+                        op: WalOp::Merge { operand, value },
+                    };
+                    payload_bytes = payload_bytes.saturating_add(record_size(&record));
+                    records.push(record);
+                }
+                RECORD_TYPE_TXN_BEGIN
+                | RECORD_TYPE_TXN_COMMIT
+                | RECORD_TYPE_TXN_ABORT
+                | RECORD_TYPE_TXN_PREPARE => {
                     let marker =
                         WalTxnMarker::from_record_type(record_type).expect("invalid txn marker");
                     let record = WalRecord {
@@ -591,11 +2566,44 @@ fn read_records(file: &mut File) -> io::Result<(Vec<WalRecord>, HashMap<u64, usi
                         upper_fence: Vec::new(),
                         kind: entry_kind,
                         txn_id,
+                        lsn: 0,
                         op: WalOp::TxnMarker(marker),
                     };
                     payload_bytes = payload_bytes.saturating_add(record_size(&record));
                     records.push(record);
                 }
+                RECORD_TYPE_SMO_SPLIT | RECORD_TYPE_SMO_MERGE | RECORD_TYPE_SMO_RELOCATE => {
+                    let id_count = WalSmo::id_count(record_type).expect("structure record");
+                    if bytes.len() - idx < 4 {
+                        break 'outer;
+                    }
+                    let key_len =
+                        u32::from_le_bytes(bytes[idx..idx + 4].try_into().unwrap()) as usize;
+                    idx += 4;
+                    if bytes.len() - idx < key_len + 8 * id_count {
+                        break 'outer;
+                    }
+                    let key = bytes[idx..idx + key_len].to_vec();
+                    idx += key_len;
+                    let ids: Vec<u64> = bytes[idx..idx + 8 * id_count]
+                        .chunks_exact(8)
+                        .map(|id| u64::from_le_bytes(id.try_into().unwrap()))
+                        .collect();
+                    idx += 8 * id_count;
+                    let smo = WalSmo::from_ids(record_type, &ids).expect("structure record");
+                    let record = WalRecord {
+                        page_id,
+                        key,
+                        lower_fence: Vec::new(),
+                        upper_fence: Vec::new(),
+                        kind: entry_kind,
+                        txn_id,
+                        lsn: 0,
+                        op: WalOp::Smo(smo),
+                    };
+                    payload_bytes = payload_bytes.saturating_add(record_size(&record));
+                    records.push(record);
+                }
                 _ => {
                     break 'outer;
                 }
@@ -603,16 +2611,41 @@ fn read_records(file: &mut File) -> io::Result<(Vec<WalRecord>, HashMap<u64, usi
             parsed += 1;
         }
 
-        let group_bytes = GROUP_HEADER_LEN + payload_bytes;
-        page_bytes
-            .entry(page_id)
-            .and_modify(|bytes| *bytes = bytes.saturating_add(group_bytes))
-            .or_insert(group_bytes);
+        if record_count == 0 {
+            break;
+        }
+        number_group(&mut records[valid_records..], &mut next_lsn);
+        groups.push(WalGroup::read(
+            group_start,
+            idx - group_start,
+            false,
+            &records[valid_records..],
+        ));
+        let first = sizes.len();
+        push_group_sizes(
+            &mut sizes,
+            &records[valid_records..],
+            GROUP_HEADER_LEN + payload_bytes,
+            record_size,
+        );
+        sizes[first] += mem::take(&mut marker_bytes);
         valid_idx = idx;
+        valid_records = records.len();
     }
+    // records of a torn trailing group are cut off with it
+    let discarded = records.len() - valid_records;
+    records.truncate(valid_records);
 
     let valid_len = MANIFEST_LEN + valid_idx as u64;
-    Ok((records, page_bytes, valid_len))
+    Ok((records, sizes, valid_len, discarded, next_lsn))
+}
+
+/// Number the records of a group just read on from `next_lsn`
+fn number_group(records: &mut [WalRecord], next_lsn: &mut u64) {
+    for record in records {
+        record.lsn = *next_lsn;
+        *next_lsn = lsn_after(*next_lsn);
+    }
 }
 
 fn record_size(record: &WalRecord) -> usize {
@@ -629,6 +2662,16 @@ fn record_size(record: &WalRecord) -> usize {
                 + record.lower_fence.len()
                 + record.upper_fence.len()
         }
+        WalOp::Merge { operand, value } => {
+            1 + 8
+                + 1
+                + 4 * 5
+                + record.key.len()
+                + operand.len()
+                + value.len()
+                + record.lower_fence.len()
+                + record.upper_fence.len()
+        }
         WalOp::Tombstone => {
             1 + 8
                 + 1
@@ -640,37 +2683,78 @@ fn record_size(record: &WalRecord) -> usize {
                 + record.upper_fence.len()
         }
         WalOp::TxnMarker(_) => 1 + 8 + 1,
+        WalOp::Smo(smo) => 1 + 8 + 1 + 4 + record.key.len() + 8 * smo.ids().len(),
+    }
+}
+
+fn read_manifest(file: &mut Storage, auth: &MetadataAuth) -> io::Result<WalManifest> {
+    match manifest_status(file, auth)? {
+        ManifestStatus::Valid { checkpoint_len } => Ok(WalManifest { checkpoint_len }),
+        ManifestStatus::Untagged { checkpoint_len } => {
+            // written before manifests were tagged, upgrade it in place
+            let manifest = WalManifest { checkpoint_len };
+            write_manifest(file, manifest, auth)?;
+            file.sync_data()?;
+            Ok(manifest)
+        }
+        ManifestStatus::Missing => {
+            let manifest = WalManifest::new();
+            file.set_len(MANIFEST_LEN)?;
+            write_manifest(file, manifest, auth)?;
+            file.sync_data()?;
+            Ok(manifest)
+        }
+        ManifestStatus::Unrecognized => {
+            let manifest = WalManifest::new();
+            write_manifest(file, manifest, auth)?;
+            file.sync_data()?;
+            Ok(manifest)
+        }
+        ManifestStatus::Unauthenticated => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "wal manifest failed authentication",
+        )),
     }
 }
 
-fn read_manifest(file: &mut File) -> io::Result<WalManifest> {
-    let mut manifest = WalManifest::new();
-    let len = file.metadata()?.len();
-    if len < MANIFEST_LEN {
-        file.set_len(MANIFEST_LEN)?;
-        write_manifest(file, manifest)?;
-        file.sync_data()?;
-        return Ok(manifest);
+fn manifest_status(file: &mut Storage, auth: &MetadataAuth) -> io::Result<ManifestStatus> {
+    if file.len()? < MANIFEST_LEN {
+        return Ok(ManifestStatus::Missing);
     }
     let mut header = [0u8; MANIFEST_LEN as usize];
     file.seek(SeekFrom::Start(0))?;
     file.read_exact(&mut header)?;
-    if &header[0..4] != MANIFEST_MAGIC || u32::from_le_bytes(header[4..8].try_into().unwrap()) != MANIFEST_VERSION {
-        write_manifest(file, manifest)?;
-        file.sync_data()?;
-        return Ok(manifest);
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if header[0..4] != MANIFEST_MAGIC
+        || (version != MANIFEST_VERSION && version != MANIFEST_VERSION_UNTAGGED)
+    {
+        return Ok(ManifestStatus::Unrecognized);
     }
-    manifest.checkpoint_len =
-        u64::from_le_bytes(header[8..16].try_into().unwrap()).max(MANIFEST_LEN);
-    Ok(manifest)
+    let body = &header[..MANIFEST_BODY_LEN];
+    let tag = &header[MANIFEST_BODY_LEN..MANIFEST_BODY_LEN + TAG_LEN];
+    if version == MANIFEST_VERSION && !auth.verify(body, tag) {
+        return Ok(ManifestStatus::Unauthenticated);
+    }
+    let checkpoint_len = u64::from_le_bytes(header[8..16].try_into().unwrap()).max(MANIFEST_LEN);
+    Ok(if version == MANIFEST_VERSION_UNTAGGED {
+        ManifestStatus::Untagged { checkpoint_len }
+    } else {
+        ManifestStatus::Valid { checkpoint_len }
+    })
 }
 
-fn write_manifest(file: &mut File, manifest: WalManifest) -> io::Result<()> {
+fn write_manifest(
+    file: &mut Storage,
+    manifest: WalManifest,
+    auth: &MetadataAuth,
+) -> io::Result<()> {
     let mut buf = [0u8; MANIFEST_LEN as usize];
     buf[0..4].copy_from_slice(&MANIFEST_MAGIC);
     buf[4..8].copy_from_slice(&MANIFEST_VERSION.to_le_bytes());
     buf[8..16].copy_from_slice(&manifest.checkpoint_len.to_le_bytes());
-    let current = file.seek(SeekFrom::Current(0))?;
+    let tag = auth.tag(&buf[..MANIFEST_BODY_LEN]);
+    buf[MANIFEST_BODY_LEN..MANIFEST_BODY_LEN + TAG_LEN].copy_from_slice(&tag);
+    let current = file.stream_position()?;
     file.seek(SeekFrom::Start(0))?;
     file.write_all(&buf)?;
     file.seek(SeekFrom::Start(current))?;
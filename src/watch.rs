@@ -0,0 +1,85 @@
+//! Change notification for committed writes.
+//!
+//! A transaction buffers the puts and deletes it makes while anyone is subscribed, and
+//! publishes them once its commit marker has been synced to the WAL, or logged for
+//! `QuickStepTx::commit_async`; aborted transactions publish nothing. Subscribers see each
+//! transaction's changes in the order they were made, and transactions in commit order.
+//! Dropping the receiver ends the subscription.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc::{self, Receiver, Sender},
+    Mutex,
+};
+
+/// A committed write to one key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub txn_id: u64,
+    pub key: Vec<u8>,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The key's new value, for merges the resolved one
+    Put(Vec<u8>),
+    Delete,
+}
+
+struct Subscription {
+    prefix: Vec<u8>,
+    sender: Sender<ChangeEvent>,
+}
+
+#[derive(Default)]
+pub struct Watchers {
+    subscriptions: Mutex<Vec<Subscription>>,
+    /// Number of subscriptions, lets writers skip buffering when nobody listens
+    active: AtomicUsize,
+}
+
+impl Watchers {
+    pub fn new() -> Watchers {
+        Watchers::default()
+    }
+
+    /// Receive every committed change to a key starting with `prefix`
+    pub fn subscribe(&self, prefix: &[u8]) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let mut subscriptions = self.subscriptions.lock().expect("watch mutex poisoned");
+        subscriptions.push(Subscription {
+            prefix: prefix.to_vec(),
+            sender,
+        });
+        self.active.store(subscriptions.len(), Ordering::Release);
+        receiver
+    }
+
+    pub fn is_watched(&self) -> bool {
+        self.active.load(Ordering::Acquire) > 0
+    }
+
+    /// Send `events` to the subscriptions they match, dropping those whose receiver is gone
+    pub fn publish(&self, events: &[ChangeEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        let mut subscriptions = self.subscriptions.lock().expect("watch mutex poisoned");
+        subscriptions.retain(|subscription| {
+            events
+                .iter()
+                .filter(|event| event.key.starts_with(&subscription.prefix))
+                .all(|event| subscription.sender.send(event.clone()).is_ok())
+        });
+        self.active.store(subscriptions.len(), Ordering::Release);
+    }
+
+    pub fn len(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
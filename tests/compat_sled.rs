@@ -0,0 +1,95 @@
+use quickstep::{compat, error::QSError};
+use tempfile::TempDir;
+
+fn collect(iter: compat::Iter<'_>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    iter.collect::<Result<_, _>>().expect("iterate tree")
+}
+
+#[test]
+fn trees_are_separate_keyspaces() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = compat::open(temp.path().join("sled.qs")).expect("open db");
+    let users = db.open_tree("users").expect("open users");
+    let orders = db.open_tree("orders").expect("open orders");
+
+    assert_eq!(db.insert(b"k", b"default").expect("insert default"), None);
+    assert_eq!(users.insert(b"k", b"alice").expect("insert user"), None);
+    assert_eq!(
+        users.insert(b"k", b"bob").expect("update user"),
+        Some(b"alice".to_vec())
+    );
+    orders.insert(b"o-1", b"one").expect("insert order");
+    orders.insert(b"o-2", b"two").expect("insert order");
+    orders.insert(b"x-1", b"other").expect("insert order");
+
+    assert_eq!(
+        db.get(b"k").expect("get default"),
+        Some(b"default".to_vec())
+    );
+    assert_eq!(users.get(b"k").expect("get user"), Some(b"bob".to_vec()));
+    assert_eq!(orders.get(b"k").expect("get order"), None);
+    assert_eq!(
+        collect(orders.scan_prefix(b"o-")),
+        vec![
+            (b"o-1".to_vec(), b"one".to_vec()),
+            (b"o-2".to_vec(), b"two".to_vec())
+        ]
+    );
+    assert_eq!(users.len(), 1);
+
+    let mut names = db.tree_names().expect("tree names");
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            b"__sled__default".to_vec(),
+            b"orders".to_vec(),
+            b"users".to_vec()
+        ]
+    );
+    assert!(db.drop_tree("orders").expect("drop orders"));
+    assert!(orders.is_empty());
+    assert_eq!(db.tree_names().expect("tree names").len(), 2);
+    assert_eq!(
+        users.remove(b"k").expect("remove user"),
+        Some(b"bob".to_vec())
+    );
+    assert_eq!(users.remove(b"k").expect("remove again"), None);
+}
+
+#[test]
+fn failed_transaction_writes_nothing() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = compat::open(temp.path().join("tx.qs")).expect("open db");
+    let tree = db.open_tree("accounts").expect("open tree");
+    tree.insert(b"a", b"10").expect("seed");
+
+    let result: Result<(), QSError> = tree.transaction(|tx| {
+        tx.insert(b"a", b"0")?;
+        tx.insert(b"b", b"10")?;
+        Err(QSError::PageLockFail)
+    });
+    assert!(result.is_err());
+    assert_eq!(tree.get(b"a").expect("get a"), Some(b"10".to_vec()));
+    assert_eq!(tree.get(b"b").expect("get b"), None);
+}
+
+#[test]
+fn flushed_trees_survive_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("flush.qs");
+    {
+        let db = compat::open(&path).expect("open db");
+        let tree = db.open_tree("events").expect("open tree");
+        for i in 0..20u8 {
+            tree.insert([i], [i; 8]).expect("insert event");
+        }
+        assert!(tree.flush().expect("flush") > 0);
+        assert_eq!(db.store().debug_wal_stats(None).unwrap().total_records, 0);
+    }
+
+    let db = compat::open(&path).expect("reopen db");
+    let tree = db.open_tree("events").expect("reopen tree");
+    assert_eq!(tree.len(), 20);
+    assert_eq!(tree.get([7u8]).expect("get event"), Some(vec![7u8; 8]));
+}
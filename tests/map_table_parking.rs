@@ -0,0 +1,114 @@
+use quickstep::map_table::{LockWaitPolicy, MapTable};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+fn parking_table() -> Arc<MapTable> {
+    // no spinning, so every contended waiter goes straight to the parking lot
+    Arc::new(MapTable::with_lock_wait(
+        1024,
+        LockWaitPolicy::new(1, Duration::from_secs(10)),
+    ))
+}
+
+fn wait_for_parked(table: &MapTable, count: usize) {
+    let start = Instant::now();
+    while table.parked_waiters() < count {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "waiters never parked"
+        );
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn parked_reader_wakes_when_writer_releases() {
+    let table = parking_table();
+    let page = table.init_leaf_entry(0);
+
+    let writer = table.write_page_entry(page).expect("write lock");
+    let reader = {
+        let table = Arc::clone(&table);
+        thread::spawn(move || {
+            let start = Instant::now();
+            let guard = table.read_page_entry(page).expect("read after release");
+            drop(guard);
+            start.elapsed()
+        })
+    };
+
+    wait_for_parked(&table, 1);
+    drop(writer);
+
+    let waited = reader.join().expect("reader thread");
+    assert!(
+        waited < Duration::from_secs(5),
+        "reader should be woken by the release, not the timeout"
+    );
+    assert_eq!(table.parked_waiters(), 0);
+}
+
+#[test]
+fn waiting_writer_goes_before_new_readers() {
+    let table = parking_table();
+    let page = table.init_leaf_entry(0);
+    let order = Arc::new(AtomicUsize::new(0));
+
+    let first_reader = table.read_page_entry(page).expect("read lock");
+
+    let writer = {
+        let table = Arc::clone(&table);
+        let order = Arc::clone(&order);
+        thread::spawn(move || {
+            let guard = table.write_page_entry(page).expect("write lock");
+            let pos = order.fetch_add(1, Ordering::SeqCst);
+            drop(guard);
+            pos
+        })
+    };
+    wait_for_parked(&table, 1);
+
+    // the writer has claimed the page, so this reader must queue behind it
+    let late_reader = {
+        let table = Arc::clone(&table);
+        let order = Arc::clone(&order);
+        thread::spawn(move || {
+            let guard = table.read_page_entry(page).expect("read lock");
+            let pos = order.fetch_add(1, Ordering::SeqCst);
+            drop(guard);
+            pos
+        })
+    };
+    wait_for_parked(&table, 2);
+
+    drop(first_reader);
+    assert_eq!(writer.join().expect("writer thread"), 0);
+    assert_eq!(late_reader.join().expect("reader thread"), 1);
+}
+
+#[test]
+fn upgrade_waits_for_other_readers() {
+    let table = parking_table();
+    let page = table.init_leaf_entry(0);
+
+    let other_reader = table.read_page_entry(page).expect("read lock");
+    let upgrader = {
+        let table = Arc::clone(&table);
+        thread::spawn(move || {
+            let guard = table.read_page_entry(page).expect("read lock");
+            let guard = guard.upgrade().map_err(|(_, e)| e).expect("upgrade");
+            drop(guard);
+        })
+    };
+    wait_for_parked(&table, 1);
+
+    drop(other_reader);
+    upgrader.join().expect("upgrader thread");
+    table.read_page_entry(page).expect("read after upgrade");
+}
@@ -0,0 +1,109 @@
+use std::{
+    io::ErrorKind,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use quickstep::{
+    auth::{hmac_sha256, sha256, MetadataAuth},
+    io_engine::{IoEngine, MAX_DISK_PAGES},
+    storage::Storage,
+    txn_ids::TXN_IDS_OFFSET,
+    wal::WalManager,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn flip_byte(path: &Path, offset: u64) {
+    let file = Storage::open(path).expect("open");
+    let mut byte = [0u8; 1];
+    file.read_exact_at(&mut byte, offset).expect("read byte");
+    byte[0] ^= 0x01;
+    file.write_all_at(&byte, offset).expect("write byte");
+}
+
+#[test]
+fn digests_match_reference_vectors() {
+    assert_eq!(
+        hex(&sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        hex(&sha256(&[b'a'; 1000])),
+        "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+    );
+    // RFC 4231 test case 2
+    assert_eq!(
+        hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn tampered_or_rekeyed_wal_manifest_fails_open() {
+    let temp = TempDir::new().expect("tempdir");
+    let wal_path = temp.path().join("quickstep.wal");
+    let auth = MetadataAuth::new(b"secret");
+    {
+        let wal = WalManager::open_with_auth(&wal_path, auth.clone()).expect("create wal");
+        wal.clear().expect("write manifest");
+    }
+    WalManager::open_with_auth(&wal_path, auth.clone()).expect("same key opens");
+
+    let err = WalManager::open_with_auth(&wal_path, MetadataAuth::new(b"other"))
+        .err()
+        .expect("wrong key");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // a checkpoint length edited behind the log's back
+    flip_byte(&wal_path, 9);
+    let err = WalManager::open_with_auth(&wal_path, auth)
+        .err()
+        .expect("tampered");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn superblock_is_authenticated_at_open() {
+    let temp = TempDir::new().expect("tempdir");
+    let data_path = temp.path().join("quickstep.db");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14).with_metadata_key(b"k1");
+    {
+        let db = QuickStep::new(config());
+        let mut tx = db.tx();
+        tx.put(b"key", b"value").expect("put");
+        tx.commit();
+    }
+    {
+        let db = QuickStep::new(config());
+        assert_eq!(db.get(b"key").unwrap().as_deref(), Some(&b"value"[..]));
+    }
+
+    let rekeyed = panic::catch_unwind(AssertUnwindSafe(|| {
+        QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14).with_metadata_key(b"k2"))
+    }));
+    assert!(rekeyed.is_err(), "opening under another key must fail");
+
+    flip_byte(&data_path, 8);
+    let err = IoEngine::open_with_auth(&data_path, MAX_DISK_PAGES, &MetadataAuth::new(b"k1"))
+        .err()
+        .expect("tampered superblock");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn transaction_id_mark_is_authenticated_at_open() {
+    let temp = TempDir::new().expect("tempdir");
+    let data_path = temp.path().join("quickstep.db");
+    drop(QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14)).tx());
+    IoEngine::open(&data_path).expect("untouched mark opens");
+
+    // the mark, past its magic
+    flip_byte(&data_path, TXN_IDS_OFFSET + 4);
+    let err = IoEngine::open(&data_path).err().expect("tampered");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
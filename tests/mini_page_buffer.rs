@@ -1,7 +1,10 @@
 use quickstep::{
+    auth::MetadataAuth,
     buffer::{MiniPageBuffer, MiniPageIndex},
-    map_table::PageId,
-    types::NodeSize,
+    io_engine::IoEngine,
+    map_table::{MapTable, PageId},
+    types::{NodeSize, SlotState},
+    wal::WalManager,
 };
 
 fn new_cache() -> MiniPageBuffer {
@@ -32,3 +35,101 @@ fn dealloc_reuses_slot_via_freelist() {
     assert_eq!(reused, idx, "freelist should return the recycled slot");
 }
 
+#[test]
+fn wrapping_carves_the_end_of_the_buffer_into_free_slots() {
+    // 2^13 bytes, two leaf pages
+    let cache = MiniPageBuffer::new(13);
+    let map_table = MapTable::new(16);
+    let auth = MetadataAuth::default();
+    let io_engine = IoEngine::in_memory(16, &auth).expect("io engine");
+    let wal = WalManager::in_memory(auth).expect("wal");
+    let init = |idx: usize, size: NodeSize| unsafe {
+        let meta = cache.get_meta_mut(MiniPageIndex::new(idx));
+        meta.reset_header(PageId::from_u64(idx as u64), size, 0);
+    };
+
+    let first = cache.alloc(NodeSize::LeafPage).expect("first leaf");
+    init(first, NodeSize::LeafPage);
+    let small = cache.alloc(NodeSize::N2K).expect("small page");
+    init(small, NodeSize::N2K);
+    unsafe {
+        cache
+            .get_meta_mut(MiniPageIndex::new(first))
+            .set_live(false);
+        cache.dealloc(MiniPageIndex::new(first));
+    }
+    // the head moves past the freed leaf, which leaves its freelist
+    cache
+        .evict(&map_table, &io_engine, &wal, None)
+        .expect("advance head");
+    assert_eq!(cache.free_listed_slots(), 0);
+
+    // the words after the small page are too few for a leaf
+    let wrapped = cache.alloc(NodeSize::LeafPage).expect("wrap the tail");
+    assert_eq!(wrapped, first);
+    init(wrapped, NodeSize::LeafPage);
+    assert_eq!(cache.free_listed_slots(), 1);
+    let carved = cache.alloc(NodeSize::N2K).expect("carved slot");
+    assert_eq!(carved, small + NodeSize::N2K.size_in_words());
+}
+
+#[test]
+fn a_full_buffer_splits_a_larger_free_slot() {
+    let cache = new_cache();
+    let leaf = cache.alloc(NodeSize::LeafPage).expect("leaf page");
+    unsafe {
+        let meta = cache.get_meta_mut(MiniPageIndex::new(leaf));
+        meta.reset_header(PageId::from_u64(0), NodeSize::LeafPage, 0);
+        meta.set_live(false);
+        cache.dealloc(MiniPageIndex::new(leaf));
+    }
+
+    // the tail has nowhere to go, the freed leaf is halved down to the size asked for
+    let small = cache.alloc(NodeSize::N1K).expect("front of the freed leaf");
+    assert_eq!(small, leaf);
+    assert_eq!(cache.free_listed_slots(), 2);
+    assert_eq!(
+        cache.alloc(NodeSize::N1K),
+        Some(leaf + NodeSize::N1K.size_in_words())
+    );
+    assert_eq!(
+        cache.alloc(NodeSize::N2K),
+        Some(leaf + NodeSize::N2K.size_in_words())
+    );
+    assert_eq!(cache.alloc(NodeSize::N64), None);
+}
+
+#[test]
+fn a_mini_page_has_one_evictor_and_cannot_be_freed_under_it() {
+    let cache = new_cache();
+    let idx = cache.alloc(NodeSize::LeafPage).expect("allocate leaf page");
+    let meta = unsafe { cache.get_meta_mut(MiniPageIndex::new(idx)) };
+    meta.reset_header(PageId::from_u64(3), NodeSize::LeafPage, 0);
+    assert_eq!(meta.state(), SlotState::Live);
+
+    let claim = meta.mark_for_eviction().expect("claim live page");
+    assert!(meta.mark_for_eviction().is_none(), "second evictor");
+    assert!(meta.is_live() && meta.is_being_evicted());
+    assert_eq!(
+        meta.transition(SlotState::Live, SlotState::Unused),
+        Err(SlotState::Evicting)
+    );
+    meta.abort_eviction(claim);
+    assert_eq!(meta.state(), SlotState::Live);
+
+    let claim = meta.mark_for_eviction().expect("claim again");
+    meta.finish_eviction(claim);
+    assert_eq!(meta.state(), SlotState::Unused);
+    assert!(meta.mark_for_eviction().is_none(), "evicted page");
+}
+
+#[test]
+#[should_panic(expected = "deallocated while Evicting")]
+fn dealloc_of_a_page_being_evicted_panics() {
+    let cache = new_cache();
+    let idx = cache.alloc(NodeSize::LeafPage).expect("allocate leaf page");
+    let meta = unsafe { cache.get_meta_mut(MiniPageIndex::new(idx)) };
+    meta.reset_header(PageId::from_u64(3), NodeSize::LeafPage, 0);
+    let _claim = meta.mark_for_eviction().expect("claim live page");
+    unsafe { cache.dealloc(MiniPageIndex::new(idx)) };
+}
@@ -0,0 +1,146 @@
+use quickstep::{
+    activity_log::{ActivityEntry, ActivityOp},
+    error::QSError,
+    reserved::RESERVED_PREFIX,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn config(temp: &TempDir, capacity: usize) -> QuickStepConfig {
+    QuickStepConfig::new(temp.path(), 32, 256, 14).with_activity_log(capacity)
+}
+
+fn ops(entries: &[ActivityEntry]) -> Vec<(Vec<u8>, ActivityOp)> {
+    entries
+        .iter()
+        .map(|entry| (entry.key.clone(), entry.op))
+        .collect()
+}
+
+#[test]
+fn commits_are_logged_in_order_and_aborts_are_not() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(&temp, 100));
+
+    let mut tx = db.tx();
+    let first = tx.txn_id();
+    tx.put(b"a", b"1").expect("put");
+    tx.put(b"b", b"2").expect("put");
+    tx.commit();
+
+    let mut tx = db.tx();
+    tx.put(b"c", b"3").expect("put");
+    tx.abort();
+
+    let mut tx = db.tx();
+    let second = tx.txn_id();
+    assert!(tx.delete(b"a").expect("delete"));
+    tx.commit();
+
+    let entries = db.recent_changes(0).expect("recent changes");
+    assert_eq!(
+        ops(&entries),
+        vec![
+            (b"a".to_vec(), ActivityOp::Put),
+            (b"b".to_vec(), ActivityOp::Put),
+            (b"a".to_vec(), ActivityOp::Delete),
+        ]
+    );
+    let txns: Vec<_> = entries.iter().map(|entry| entry.txn_id).collect();
+    assert_eq!(txns, vec![first, first, second]);
+    assert!(entries
+        .windows(2)
+        .all(|pair| pair[0].committed_at <= pair[1].committed_at));
+
+    // nothing is committed in the future
+    let later = entries.last().unwrap().committed_at + 60_000;
+    assert!(db.recent_changes(later).expect("recent changes").is_empty());
+}
+
+#[test]
+fn log_keeps_the_newest_entries_across_reopens() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(&temp, 10));
+        for idx in 0..35u32 {
+            let mut tx = db.tx();
+            tx.put(&idx.to_be_bytes(), b"value").expect("put");
+            tx.commit();
+        }
+        let keys: Vec<_> = db
+            .recent_changes(0)
+            .expect("recent changes")
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        let expected: Vec<_> = (25..35u32).map(|idx| idx.to_be_bytes().to_vec()).collect();
+        assert_eq!(keys, expected);
+    }
+
+    let db = QuickStep::new(config(&temp, 10));
+    let keys: Vec<_> = db
+        .recent_changes(0)
+        .expect("recent changes")
+        .into_iter()
+        .map(|e| e.key)
+        .collect();
+    let expected: Vec<_> = (25..35u32).map(|idx| idx.to_be_bytes().to_vec()).collect();
+    assert_eq!(keys, expected);
+    // the log stays out of the keys it describes
+    assert_eq!(db.range_iter(b"", b"\xff").count(), 35);
+}
+
+#[test]
+fn nothing_is_logged_without_the_option() {
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 14));
+    let mut tx = db.tx();
+    tx.put(b"key", b"value").expect("put");
+    tx.commit();
+    assert!(db.recent_changes(0).expect("recent changes").is_empty());
+
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 14).with_activity_log(4));
+    let mut tx = db.tx();
+    tx.put(b"key", b"value").expect("put");
+    tx.commit();
+    assert_eq!(
+        ops(&db.recent_changes(0).expect("recent changes")),
+        vec![(b"key".to_vec(), ActivityOp::Put)]
+    );
+}
+
+#[test]
+fn entries_commit_and_recover_with_their_transaction() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(&temp, 100));
+        let mut tx = db.tx();
+        tx.put(b"committed", b"1").expect("put");
+        tx.commit();
+        let mut tx = db.tx();
+        tx.put(b"crashed", b"2").expect("put");
+        std::mem::forget(tx);
+        db.debug_close_without_flush();
+    }
+
+    let db = QuickStep::new(config(&temp, 100));
+    assert_eq!(
+        ops(&db.recent_changes(0).expect("recent changes")),
+        vec![(b"committed".to_vec(), ActivityOp::Put)]
+    );
+}
+
+#[test]
+fn the_reserved_keyspace_refuses_writes() {
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 14).with_activity_log(4));
+    let mut key = RESERVED_PREFIX.to_vec();
+    key.extend_from_slice(b"activity/");
+    let mut tx = db.tx();
+    assert!(matches!(
+        tx.put(&key, b"value"),
+        Err(QSError::ReservedKey { .. })
+    ));
+    assert!(matches!(tx.delete(&key), Err(QSError::ReservedKey { .. })));
+    tx.put(b"key", b"value").expect("put");
+    tx.commit();
+    assert_eq!(db.range_iter(b"", b"\xff").count(), 1);
+}
@@ -0,0 +1,139 @@
+use quickstep::{asynk::AsyncQuickStep, error::QSError, QuickStep, QuickStepConfig};
+use std::{
+    future::Future,
+    pin::pin,
+    sync::{mpsc, Arc},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+use tempfile::TempDir;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn async_writes_are_visible_and_durable() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("async.qs");
+    {
+        let db = AsyncQuickStep::open(QuickStepConfig::new(&path, 32, 256, 14)).expect("open");
+        block_on(db.put(b"alpha", b"one")).expect("put alpha");
+
+        let mut tx = db.tx();
+        tx.put(b"beta", b"two");
+        tx.put(b"gamma", b"three");
+        tx.delete(b"alpha");
+        block_on(tx.commit()).expect("commit tx");
+
+        assert_eq!(block_on(db.get(b"alpha")).expect("get alpha"), None);
+        assert_eq!(
+            block_on(db.get(b"beta")).expect("get beta"),
+            Some(b"two".to_vec())
+        );
+        assert!(block_on(db.delete(b"gamma")).expect("delete gamma"));
+    }
+
+    let db = QuickStep::new(QuickStepConfig::new(&path, 32, 256, 14));
+    assert_eq!(db.get(b"beta").expect("get beta"), Some(b"two".to_vec()));
+    assert_eq!(db.get(b"gamma").expect("get gamma"), None);
+}
+
+#[test]
+fn failed_write_aborts_the_whole_transaction() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = AsyncQuickStep::open(QuickStepConfig::new(
+        temp.path().join("abort.qs"),
+        32,
+        256,
+        14,
+    ))
+    .expect("open");
+
+    let mut tx = db.tx();
+    tx.put(b"kept", b"no");
+    // no merge operator is configured
+    tx.merge(b"counter", b"1");
+    assert!(matches!(
+        block_on(tx.commit()),
+        Err(QSError::NoMergeOperator)
+    ));
+    assert_eq!(block_on(db.get(b"kept")).expect("get kept"), None);
+}
+
+#[test]
+fn jobs_run_concurrently_on_the_pool() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = AsyncQuickStep::open_with_workers(
+        QuickStepConfig::new(temp.path().join("pool.qs"), 32, 256, 14),
+        2,
+    )
+    .expect("open");
+
+    // the first job only finishes once the second has run, which a single worker never would
+    let (sender, receiver) = mpsc::channel();
+    let waiting = db.run(move |_| Ok(receiver.recv_timeout(Duration::from_secs(5)).is_ok()));
+    let signalling = db.run(move |db| {
+        let mut tx = db.tx();
+        tx.put(b"key", b"value")?;
+        tx.commit();
+        sender.send(()).expect("send");
+        Ok(())
+    });
+    block_on(signalling).expect("signalling job");
+    assert!(block_on(waiting).expect("waiting job"));
+    assert_eq!(
+        block_on(db.get(b"key")).expect("get key"),
+        Some(b"value".to_vec())
+    );
+}
+
+#[test]
+fn jobs_fail_once_the_worker_stops() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = AsyncQuickStep::open(QuickStepConfig::new(
+        temp.path().join("stop.qs"),
+        32,
+        256,
+        14,
+    ))
+    .expect("open");
+
+    let crashed = db.run(|_| -> Result<(), QSError> { panic!("job panicked") });
+    assert!(matches!(
+        block_on(crashed),
+        Err(QSError::AsyncWorkerStopped)
+    ));
+    assert!(matches!(
+        block_on(db.get(b"anything")),
+        Err(QSError::AsyncWorkerStopped)
+    ));
+}
+
+#[test]
+fn open_errors_reach_the_caller() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("locked.qs");
+    let _held = QuickStep::new(QuickStepConfig::new(&path, 32, 256, 14));
+    assert!(matches!(
+        AsyncQuickStep::open(QuickStepConfig::new(&path, 32, 256, 14)),
+        Err(QSError::AlreadyLocked { .. })
+    ));
+}
@@ -0,0 +1,88 @@
+use std::{
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 20).with_wal_thresholds(usize::MAX, 256, usize::MAX)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+/// Poll until `done` holds, failing after a few seconds
+fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn an_idle_store_checkpoints_its_leaves_and_trims_the_markers() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        for idx in 0..2000u32 {
+            let mut tx = db.tx();
+            tx.put(&key(idx), b"value").expect("put");
+            tx.commit();
+        }
+        // every transaction left its markers behind
+        assert!(db.debug_wal_record_count().unwrap() > 4000);
+
+        // no foreground write comes along, the checkpoint thread does the work
+        wait_for(|| db.debug_wal_record_count().unwrap() < 256);
+    }
+
+    let db = QuickStep::new(config(temp.path()));
+    assert_eq!(db.last_recovery_report().pages_rebuilt, 0);
+    assert_eq!(db.get(&key(0)).expect("get"), Some(b"value".to_vec()));
+    assert_eq!(db.get(&key(1999)).expect("get"), Some(b"value".to_vec()));
+}
+
+/// Commit `count` single-put transactions
+fn write(db: &QuickStep, count: u32) {
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(&key(idx), b"value").expect("put");
+        tx.commit();
+    }
+}
+
+#[test]
+fn thresholds_lowered_at_runtime_start_checkpoints() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        QuickStepConfig::new(temp.path(), 64, 1024, 20)
+            .with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+            .with_checkpoint_interval(Duration::from_millis(5)),
+    );
+    write(&db, 500);
+    thread::sleep(Duration::from_millis(100));
+    assert!(db.debug_wal_record_count().unwrap() > 1000);
+
+    db.set_wal_thresholds(usize::MAX, 256, usize::MAX);
+    assert_eq!(db.wal_thresholds(), (usize::MAX, 256, usize::MAX));
+    wait_for(|| db.debug_wal_record_count().unwrap() < 256);
+}
+
+#[test]
+fn a_shortened_interval_applies_without_waiting_out_the_old_one() {
+    let temp = TempDir::new().expect("tempdir");
+    let db =
+        QuickStep::new(config(temp.path()).with_checkpoint_interval(Duration::from_secs(3600)));
+    write(&db, 500);
+    thread::sleep(Duration::from_millis(100));
+    assert!(db.debug_wal_record_count().unwrap() > 1000);
+
+    db.set_checkpoint_interval(Duration::from_millis(5));
+    assert_eq!(db.checkpoint_interval(), Duration::from_millis(5));
+    wait_for(|| db.debug_wal_record_count().unwrap() < 256);
+}
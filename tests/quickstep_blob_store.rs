@@ -0,0 +1,223 @@
+use std::{fs, path::Path, time::Duration};
+
+use quickstep::{blob::BlobPointer, overflow::OverflowPointer, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+const THRESHOLD: usize = 8 * 1024;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 16)
+        .with_blob_store(THRESHOLD, 32 * 1024)
+        .with_lock_timeout(Duration::from_millis(50))
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:03}").into_bytes()
+}
+
+fn long_value(idx: usize, len: usize) -> Vec<u8> {
+    (0..len).map(|pos| (pos * 17 + idx) as u8).collect()
+}
+
+fn blob_files(dir: &Path) -> usize {
+    fs::read_dir(dir)
+        .expect("read dir")
+        .filter(|entry| {
+            let name = entry.as_ref().expect("entry").file_name();
+            name.to_string_lossy().contains(".blob-")
+        })
+        .count()
+}
+
+fn put(db: &QuickStep, key: &[u8], value: &[u8]) {
+    let mut tx = db.tx();
+    tx.put(key, value).expect("put");
+    tx.commit();
+}
+
+/// Short values, values kept in overflow pages, values past the threshold, and values that
+/// look like either kind of pointer
+fn values() -> Vec<Vec<u8>> {
+    let mut values: Vec<Vec<u8>> = (0..9)
+        .map(|idx| match idx % 3 {
+            0 => long_value(idx, 40),
+            1 => long_value(idx, THRESHOLD - 1),
+            _ => long_value(idx, THRESHOLD + 5_000 * idx),
+        })
+        .collect();
+    let blob = BlobPointer {
+        file: 1,
+        offset: 12,
+        len: 5,
+        crc: 9,
+    };
+    let chain = OverflowPointer {
+        head: 3,
+        len: 5,
+        crc: 9,
+    };
+    values.push(blob.encode().to_vec());
+    values.push(chain.encode().to_vec());
+    values
+}
+
+fn assert_values(db: &QuickStep, values: &[Vec<u8>]) {
+    let mut tx = db.tx();
+    for (idx, value) in values.iter().enumerate() {
+        assert_eq!(db.get(&key(idx)).expect("get").as_ref(), Some(value));
+        assert_eq!(tx.get(&key(idx)).expect("tx get"), Some(value.as_slice()));
+        let pinned = tx.get_pinned(&key(idx)).expect("pinned").expect("present");
+        assert_eq!(&*pinned, value.as_slice());
+        let info = tx.entry_info(&key(idx)).expect("info").expect("present");
+        assert_eq!(info.value_len, value.len());
+        assert_eq!(info.blob, value.len() >= THRESHOLD, "key {idx}");
+    }
+    tx.commit();
+
+    let scanned: Vec<_> = db
+        .range_iter(&key(0), &key(values.len()))
+        .map(|record| record.expect("scan").1)
+        .collect();
+    assert_eq!(scanned, values);
+}
+
+#[test]
+fn values_past_the_threshold_go_to_blob_files_and_read_back() {
+    let temp = TempDir::new().expect("tempdir");
+    let values = values();
+    {
+        let db = QuickStep::new(config(temp.path()));
+        for (idx, value) in values.iter().enumerate() {
+            put(&db, &key(idx), value);
+        }
+        assert_values(&db, &values);
+    }
+    assert!(blob_files(temp.path()) >= 2);
+
+    // recovered from the log, then with the leaves flushed
+    let db = QuickStep::new(config(temp.path()));
+    assert_values(&db, &values);
+    db.checkpoint().expect("checkpoint");
+    drop(db);
+
+    // a store opened without the blob store still reads the files there
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 16));
+    assert_values(&db, &values);
+    drop(db);
+
+    let db = QuickStep::new(
+        QuickStepConfig::in_memory(64, 1024, 16).with_blob_store(THRESHOLD, 64 * 1024),
+    );
+    for (idx, value) in values.iter().enumerate() {
+        put(&db, &key(idx), value);
+    }
+    assert_values(&db, &values);
+}
+
+#[test]
+fn collection_moves_live_values_and_removes_dead_files() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let mut values: Vec<_> = (0..12).map(|idx| long_value(idx, 20_000)).collect();
+    for (idx, value) in values.iter().enumerate() {
+        put(&db, &key(idx), value);
+    }
+    let mut tx = db.tx();
+    tx.put_with_ttl(&key(1), &values[1], Duration::from_secs(3600))
+        .expect("put with ttl");
+    tx.commit();
+    assert!(db.soft_delete(&key(2)).expect("soft delete"));
+
+    // most of what the first files hold is overwritten or deleted
+    for (idx, value) in values.iter_mut().enumerate().skip(3) {
+        match idx % 2 {
+            0 => assert!(db.delete(&key(idx)).expect("delete")),
+            _ => {
+                *value = long_value(idx + 100, 30_000);
+                put(&db, &key(idx), value);
+            }
+        }
+    }
+    let mut tx = db.tx();
+    tx.put(&key(0), &long_value(0, 50_000)).expect("put");
+    tx.abort();
+    let before = db.blob_bytes();
+
+    let stats = db.gc_blobs(0.5).expect("gc");
+    assert!(stats.files_removed > 0, "{stats:?}");
+    assert_eq!(stats.files_skipped, 0);
+    assert!(stats.values_moved > 0);
+    assert!(db.blob_bytes() < before);
+    assert_eq!(before - db.blob_bytes(), stats.bytes_reclaimed);
+
+    let check = |db: &QuickStep| {
+        for (idx, value) in values.iter().enumerate() {
+            let expected = match idx {
+                2 => None,
+                idx if idx >= 3 && idx % 2 == 0 => None,
+                _ => Some(value.clone()),
+            };
+            assert_eq!(db.get(&key(idx)).expect("get"), expected, "key {idx}");
+        }
+        // moving a value keeps its expiry and soft-delete flag
+        assert!(db.ttl(&key(1)).is_some());
+        assert!(db.is_soft_deleted(&key(2)).unwrap());
+    };
+    check(&db);
+    assert!(db.undelete(&key(2)).expect("undelete"));
+    assert_eq!(db.get(&key(2)).expect("get"), Some(values[2].clone()));
+    assert!(db.soft_delete(&key(2)).expect("soft delete"));
+
+    // nothing left to collect at that ratio, and the moves survive a reopen
+    assert_eq!(db.gc_blobs(0.5).expect("gc").files_removed, 0);
+    drop(db);
+    let db = QuickStep::new(config(temp.path()));
+    check(&db);
+}
+
+#[test]
+fn collection_leaves_files_a_transaction_holds_a_key_of() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    put(&db, b"held", &long_value(1, 20_000));
+    put(&db, b"held", &long_value(2, 20_000));
+
+    let mut tx = db.tx();
+    tx.put(b"held", &long_value(3, 20_000)).expect("put");
+    let stats = db.gc_blobs(0.0).expect("gc");
+    assert_eq!(
+        (stats.files_removed, stats.files_skipped),
+        (0, 2),
+        "{stats:?}"
+    );
+    tx.abort();
+
+    let stats = db.gc_blobs(0.0).expect("gc");
+    assert_eq!(
+        (stats.files_removed, stats.files_skipped),
+        (2, 0),
+        "{stats:?}"
+    );
+    assert_eq!(db.get(b"held").expect("get"), Some(long_value(2, 20_000)));
+}
+
+#[test]
+fn salvage_reads_blob_values_back_from_their_files() {
+    let temp = TempDir::new().expect("tempdir");
+    let source = temp.path().join("source");
+    let values = values();
+    let db = QuickStep::new(config(&source));
+    for (idx, value) in values.iter().enumerate() {
+        put(&db, &key(idx), value);
+    }
+    db.close().expect("close");
+
+    let output = temp.path().join("salvaged");
+    let report = QuickStep::salvage(config(&source), &output).expect("salvage");
+    assert!(report.corrupt_pages.is_empty(), "{report:?}");
+    assert_eq!(report.overflow_values_lost, 0);
+    assert_eq!(report.keys_salvaged, values.len() as u64);
+
+    let salvaged = QuickStep::open(config(&output)).expect("open salvaged");
+    assert_values(&salvaged, &values);
+}
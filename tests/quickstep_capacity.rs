@@ -0,0 +1,71 @@
+use quickstep::{
+    error::{CapacityLimit, QSError},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn fill_until_error(db: &QuickStep) -> (usize, QSError) {
+    let payload = vec![0u8; 1024];
+    let mut tx = db.tx();
+    for i in 0..256 {
+        let key = format!("key-{i:04}");
+        if let Err(err) = tx.put(key.as_bytes(), &payload) {
+            tx.commit();
+            return (i, err);
+        }
+    }
+    panic!("expected a capacity error within 256 inserts");
+}
+
+#[test]
+fn capacity_stats_report_format_and_configured_limits() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14).with_max_db_size(16 * 4096);
+    assert_eq!(config.max_db_size(), 16 * 4096);
+    let db = QuickStep::new(config);
+
+    let stats = db.capacity_stats();
+    assert_eq!(stats.format_max_disk_pages, 1 << 48);
+    assert_eq!(stats.format_max_page_ids, 1 << 48);
+    assert_eq!(stats.format_max_bytes, ((1u64 << 48) + 1) * 4096);
+    assert_eq!(stats.max_disk_pages, 15, "one page of the file is metadata");
+    assert_eq!(stats.max_bytes, 16 * 4096);
+    assert_eq!(stats.max_page_ids, 256);
+    assert_eq!(stats.disk_pages_used, 1, "only the root leaf exists");
+    assert_eq!(stats.page_ids_used, 1);
+}
+
+#[test]
+fn growth_past_max_db_size_is_rejected() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14).with_max_db_size(4 * 4096);
+    let db = QuickStep::new(config);
+
+    let (inserted, err) = fill_until_error(&db);
+    assert!(
+        matches!(err, QSError::CapacityExceeded(CapacityLimit::DiskPages(3))),
+        "expected the disk page limit, got {err:?}"
+    );
+    let stats = db.capacity_stats();
+    assert_eq!(stats.disk_pages_used, stats.max_disk_pages);
+
+    let mut tx = db.tx();
+    for i in 0..inserted {
+        let key = format!("key-{i:04}");
+        assert!(tx.get(key.as_bytes()).unwrap().is_some(), "missing {key}");
+    }
+    tx.commit();
+}
+
+#[test]
+fn growth_past_leaf_upper_bound_is_rejected() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.keep(), 32, 2, 14));
+
+    let (_, err) = fill_until_error(&db);
+    assert!(
+        matches!(err, QSError::CapacityExceeded(CapacityLimit::PageIds(2))),
+        "expected the page id limit, got {err:?}"
+    );
+    assert_eq!(db.capacity_stats().page_ids_used, 2);
+}
@@ -0,0 +1,166 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{
+    checkpoint::{CheckpointCandidate, CheckpointPolicy},
+    map_table::PageId,
+    wal::WalPageSummary,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn candidate(
+    page: u64,
+    bytes: usize,
+    first_record: usize,
+    dirty_ratio: f64,
+) -> CheckpointCandidate {
+    CheckpointCandidate {
+        summary: WalPageSummary {
+            page_id: PageId::from_u64(page),
+            records: bytes / 100,
+            bytes,
+            first_record,
+        },
+        dirty_ratio,
+    }
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+#[test]
+fn policies_rank_candidates_differently() {
+    let candidates = vec![
+        candidate(1, 9000, 40, 0.1),
+        candidate(2, 500, 0, 0.9),
+        candidate(3, 4000, 10, 0.5),
+        candidate(4, 700, 25, 0.2),
+    ];
+    let select = |policy: CheckpointPolicy, last: Option<u64>, batch| {
+        policy
+            .select(candidates.clone(), last.map(PageId::from_u64), batch)
+            .into_iter()
+            .map(|page| page.as_u64())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(select(CheckpointPolicy::Largest, None, 1), vec![1]);
+    assert_eq!(select(CheckpointPolicy::Largest, None, 2), vec![1, 3]);
+    assert_eq!(select(CheckpointPolicy::Oldest, None, 2), vec![2, 3]);
+    assert_eq!(select(CheckpointPolicy::DirtyRatio, None, 3), vec![2, 3, 4]);
+    assert_eq!(select(CheckpointPolicy::RoundRobin, None, 2), vec![1, 2]);
+    assert_eq!(select(CheckpointPolicy::RoundRobin, Some(2), 2), vec![3, 4]);
+    // wraps around past the highest page id
+    assert_eq!(select(CheckpointPolicy::RoundRobin, Some(4), 2), vec![1, 2]);
+    assert_eq!(select(CheckpointPolicy::Largest, None, 10).len(), 4);
+}
+
+fn wal_records_after_load(policy: CheckpointPolicy, batch: usize) -> usize {
+    let temp = TempDir::new().expect("tempdir");
+    // a cache of a fraction of the leaves, so evictions write some back first, and
+    // thresholds the checkpoint thread never reaches
+    let config = QuickStepConfig::new(temp.keep(), 64, 1024, 16)
+        .with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+        .with_checkpoint_policy(policy)
+        .with_checkpoint_batch(batch);
+    assert_eq!(config.checkpoint_policy(), policy);
+    let db = QuickStep::new(config);
+    load(&db);
+    // an evicted leaf is written back and its records dropped, fewer are left than puts made
+    assert!(db.debug_wal_stats(None).unwrap().total_records < 480);
+    let dirty = db
+        .debug_root_leaf_parent()
+        .unwrap()
+        .children
+        .into_iter()
+        .filter(|page| db.debug_wal_stats(Some(*page)).unwrap().leaf_records > Some(0))
+        .count();
+    // only leaves with records are candidates
+    let flushed = db.run_global_checkpoint().expect("checkpoint");
+    assert_eq!(flushed, batch.min(dirty));
+    for idx in (0..480).step_by(7) {
+        assert_eq!(db.get(&key(idx)).expect("get"), Some(vec![3u8; 256]));
+    }
+    db.debug_wal_stats(None).unwrap().total_records
+}
+
+fn load(db: &QuickStep) {
+    let payload = vec![3u8; 256];
+    for round in 0..4 {
+        let mut tx = db.tx();
+        for idx in 0..120 {
+            tx.put(&key(idx * 4 + round), &payload).expect("insert");
+        }
+        tx.commit();
+    }
+}
+
+#[test]
+fn batch_checkpoints_flush_several_leaves() {
+    for policy in [
+        CheckpointPolicy::Largest,
+        CheckpointPolicy::Oldest,
+        CheckpointPolicy::RoundRobin,
+        CheckpointPolicy::DirtyRatio,
+    ] {
+        let single = wal_records_after_load(policy, 1);
+        let batched = wal_records_after_load(policy, 16);
+        assert!(
+            batched < single,
+            "{policy:?}: flushing a batch of leaves should leave fewer records behind ({batched} vs {single})"
+        );
+    }
+}
+
+#[test]
+fn checkpoint_thread_brings_the_wal_under_its_threshold() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        QuickStepConfig::new(temp.path(), 64, 1024, 18).with_wal_thresholds(
+            usize::MAX,
+            400,
+            usize::MAX,
+        ),
+    );
+    load(&db);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while db.debug_wal_stats(None).unwrap().total_records >= 400 {
+        assert!(
+            Instant::now() < deadline,
+            "the WAL stayed over its threshold"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    for idx in (0..480).step_by(7) {
+        assert_eq!(db.get(&key(idx)).expect("get"), Some(vec![3u8; 256]));
+    }
+}
+
+#[test]
+fn checkpoint_skips_a_leaf_a_transaction_holds() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        QuickStepConfig::new(temp.path(), 64, 1024, 18).with_wal_thresholds(
+            usize::MAX,
+            usize::MAX,
+            usize::MAX,
+        ),
+    );
+    let mut writer = db.tx();
+    writer.put(b"held", b"value").expect("put");
+
+    assert_eq!(db.run_global_checkpoint().expect("checkpoint"), 0);
+    writer.commit();
+    assert_eq!(db.run_global_checkpoint().expect("checkpoint"), 1);
+    let leaf_records = db
+        .debug_wal_stats(Some(PageId::from_u64(0)))
+        .unwrap()
+        .leaf_records;
+    assert_eq!(leaf_records.unwrap_or(0), 0);
+    assert_eq!(db.get(b"held").expect("get"), Some(b"value".to_vec()));
+}
@@ -0,0 +1,74 @@
+use std::{mem, path::Path};
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    // leaves are only flushed by eviction and the close
+    QuickStepConfig::new(path, 64, 1024, 20).with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:04}").into_bytes()
+}
+
+fn write_keys(db: &QuickStep, count: u32, value: &[u8]) {
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(&key(idx), value).expect("put");
+        tx.commit();
+    }
+}
+
+#[test]
+fn close_flushes_every_leaf_and_empties_the_wal() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    write_keys(&db, 500, b"value");
+    let mut tx = db.tx();
+    tx.delete(&key(7)).expect("delete");
+    tx.commit();
+    assert!(db.wal_stats(Default::default()).unwrap().total.records > 1000);
+    db.close().expect("close");
+
+    let db = QuickStep::new(config(temp.path()));
+    let report = db.last_recovery_report();
+    assert_eq!((report.records_replayed, report.pages_rebuilt), (0, 0));
+    let stats = db.wal_stats(Default::default()).unwrap();
+    // only the structure records describing the tree are left
+    assert_eq!(stats.total, stats.structure);
+    assert_eq!(db.get(&key(0)).expect("get"), Some(b"value".to_vec()));
+    assert_eq!(db.get(&key(7)).expect("get"), None);
+    assert_eq!(db.get(&key(499)).expect("get"), Some(b"value".to_vec()));
+}
+
+#[test]
+fn dropping_the_store_flushes_as_well() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        write_keys(&db, 200, b"dropped");
+    }
+    let db = QuickStep::new(config(temp.path()));
+    assert_eq!(db.last_recovery_report().records_replayed, 0);
+    assert_eq!(db.get(&key(199)).expect("get"), Some(b"dropped".to_vec()));
+}
+
+#[test]
+fn a_leaked_transaction_keeps_its_records_for_replay() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        write_keys(&db, 50, b"committed");
+        let mut tx = db.tx();
+        tx.put(&key(0), b"in flight").expect("put");
+        mem::forget(tx);
+        db.close().expect("close");
+    }
+
+    let db = QuickStep::new(config(temp.path()));
+    let report = db.last_recovery_report();
+    assert_eq!(report.txns_rolled_back, 1);
+    assert_eq!(db.get(&key(0)).expect("get"), Some(b"committed".to_vec()));
+    assert_eq!(db.get(&key(49)).expect("get"), Some(b"committed".to_vec()));
+}
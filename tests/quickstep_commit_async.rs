@@ -0,0 +1,59 @@
+use std::thread;
+
+use quickstep::{wal::SyncPolicy, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:03}").into_bytes()
+}
+
+#[test]
+fn async_commits_share_the_sync_their_handles_wait_for() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    let before = db.wal_syncs();
+    let handles: Vec<_> = (0..8)
+        .map(|idx| {
+            let mut tx = db.tx();
+            tx.put(&key(idx), b"value").expect("put");
+            tx.commit_async()
+        })
+        .collect();
+    // acknowledged and visible, but nothing synced yet
+    assert_eq!(db.wal_syncs(), before);
+    assert!(handles.iter().all(|handle| !handle.is_durable()));
+    assert!(handles.windows(2).all(|pair| pair[0].lsn() < pair[1].lsn()));
+    assert_eq!(db.get(&key(7)).expect("get"), Some(b"value".to_vec()));
+
+    // the newest handle's sync covers every commit before it
+    let mut handles = handles.into_iter().rev();
+    handles.next().expect("handle").wait().expect("wait");
+    assert_eq!(db.wal_syncs(), before + 1);
+    for handle in handles {
+        assert!(handle.is_durable());
+        handle.wait().expect("wait");
+    }
+    assert_eq!(db.wal_syncs(), before + 1);
+}
+
+#[test]
+fn a_handle_waits_on_another_thread_under_any_sync_policy() {
+    let temp = TempDir::new().expect("tempdir");
+    let config =
+        || QuickStepConfig::new(temp.path(), 32, 256, 14).with_sync_policy(SyncPolicy::Never);
+    {
+        let db = QuickStep::new(config());
+        let mut tx = db.tx();
+        tx.put(b"async", b"value").expect("put");
+        let handle = tx.commit_async();
+        assert!(handle.lsn() > 0);
+        thread::spawn(move || handle.wait())
+            .join()
+            .expect("waiter panicked")
+            .expect("wait");
+        db.debug_close_without_flush();
+    }
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.get(b"async").expect("get"), Some(b"value".to_vec()));
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{invariants, map_table::PageId, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn children(db: &QuickStep) -> usize {
+    db.debug_root_leaf_parent()
+        .map_or(1, |snapshot| snapshot.children.len())
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+fn fill_until(db: &QuickStep, target: usize) -> usize {
+    let payload = vec![3u8; 64];
+    let mut idx = 0;
+    while children(db) < target {
+        let mut tx = db.tx();
+        tx.put(&key(idx), &payload).expect("insert");
+        tx.commit();
+        idx += 1;
+    }
+    idx
+}
+
+/// Split into four leaves laid out in key order on pages 0..4, then merge the third leaf
+/// into the second, freeing page 2 below the last leaf
+fn split_and_merge_middle(db: &QuickStep) -> Vec<PageId> {
+    fill_until(db, 4);
+    let snapshot = db.debug_root_leaf_parent().expect("split");
+    let addrs: Vec<u64> = snapshot
+        .children
+        .iter()
+        .map(|page| db.debug_leaf_snapshot(*page).unwrap().disk_addr)
+        .collect();
+    assert_eq!(addrs, vec![0, 1, 2, 3]);
+    let (left, right) = (snapshot.children[1], snapshot.children[2]);
+    db.debug_truncate_leaf(left, 3, false).expect("shrink left");
+    db.debug_truncate_leaf(right, 2, false)
+        .expect("shrink right");
+    db.debug_merge_leaves(left, right).expect("merge");
+    assert_eq!(db.capacity_stats().disk_pages_free, 1);
+    db.debug_root_leaf_parent().unwrap().children
+}
+
+fn file_len(dir: &Path) -> u64 {
+    fs::metadata(dir.join("quickstep.db")).unwrap().len()
+}
+
+#[test]
+fn compaction_moves_the_last_leaf_down_and_truncates_the_file() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    let leaves = split_and_merge_middle(&db);
+    let last = *leaves.last().unwrap();
+    let expected: Vec<_> = leaves
+        .iter()
+        .map(|page| db.debug_leaf_snapshot(*page).unwrap().keys)
+        .collect();
+    let len_before = file_len(temp.path());
+
+    let stats = db.compact().expect("compact");
+    assert_eq!((stats.pages_moved, stats.pages_trimmed), (1, 1));
+    assert_eq!(stats.bytes_reclaimed, 4096);
+    assert_eq!(file_len(temp.path()), len_before - 4096);
+    let capacity = db.capacity_stats();
+    assert_eq!((capacity.disk_pages_used, capacity.disk_pages_free), (3, 0));
+    assert_eq!(db.debug_leaf_snapshot(last).unwrap().disk_addr, 2);
+    let after: Vec<_> = leaves
+        .iter()
+        .map(|page| db.debug_leaf_snapshot(*page).unwrap().keys)
+        .collect();
+    assert_eq!(after, expected);
+    invariants::check_all(&db).expect("tree intact");
+
+    // nothing left to move
+    assert_eq!(db.compact().unwrap(), Default::default());
+}
+
+#[test]
+fn relocated_leaves_are_found_after_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    let (last, keys) = {
+        let db = QuickStep::new(config());
+        let leaves = split_and_merge_middle(&db);
+        let last = *leaves.last().unwrap();
+        db.compact().expect("compact");
+        let mut tx = db.tx();
+        tx.put(b"key-99999", b"after the move").expect("insert");
+        tx.commit();
+        (last, db.debug_leaf_snapshot(last).unwrap().keys)
+    };
+
+    let db = QuickStep::new(config());
+    // three splits, the merge and the move
+    assert_eq!(db.last_recovery_report().structure_changes, 5);
+    let snapshot = db.debug_leaf_snapshot(last).unwrap();
+    assert_eq!((snapshot.disk_addr, snapshot.keys), (2, keys));
+    assert_eq!(
+        db.get(b"key-99999").unwrap(),
+        Some(b"after the move".to_vec())
+    );
+    assert_eq!(db.capacity_stats().disk_pages_used, 3);
+    invariants::check_all(&db).expect("tree intact");
+}
+
+#[test]
+fn leaves_locked_by_a_transaction_stay_put() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    let leaves = split_and_merge_middle(&db);
+    let last = *leaves.last().unwrap();
+    let last_key = db.debug_leaf_snapshot(last).unwrap().keys[0].clone();
+
+    let mut tx = db.tx();
+    tx.put(&last_key, b"held").expect("update");
+    assert_eq!(db.compact().unwrap().pages_moved, 0);
+    assert_eq!(db.capacity_stats().disk_pages_used, 4);
+    tx.commit();
+
+    assert_eq!(db.compact().unwrap().pages_moved, 1);
+    assert_eq!(db.get(&last_key).unwrap(), Some(b"held".to_vec()));
+}
+
+#[test]
+fn auto_compaction_runs_in_the_background() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    // laid out without the thread, which would move the last leaf as soon as the merge frees
+    // the page below it
+    let last = {
+        let db = QuickStep::new(config());
+        *split_and_merge_middle(&db).last().unwrap()
+    };
+
+    let db = QuickStep::new(config().with_auto_compaction(Duration::from_millis(5), 1));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while db.capacity_stats().disk_pages_used != 3 {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(db.debug_leaf_snapshot(last).unwrap().disk_addr, 2);
+}
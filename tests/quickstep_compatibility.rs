@@ -0,0 +1,113 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use quickstep::{
+    auth::MetadataAuth,
+    compatibility::CompatIssue,
+    superblock::{CrateVersion, SUPERBLOCK_VERSION},
+    wal::MANIFEST_VERSION,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn create(dir: &Path) {
+    let db = QuickStep::new(QuickStepConfig::new(dir, 32, 256, 14));
+    let mut tx = db.tx();
+    tx.put(b"alpha", b"one").expect("insert");
+    tx.commit();
+}
+
+fn overwrite(path: &Path, offset: u64, bytes: &[u8]) {
+    let mut file = OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+#[test]
+fn a_database_written_by_this_build_is_compatible() {
+    let temp = TempDir::new().expect("tempdir");
+    create(temp.path());
+
+    let report = QuickStep::compatibility(temp.path()).expect("inspect");
+    assert_eq!(report.data_format_version, Some(SUPERBLOCK_VERSION));
+    assert_eq!(report.wal_format_version, Some(MANIFEST_VERSION));
+    assert_eq!(report.written_by, Some(CrateVersion::current()));
+    assert!(report.can_open && !report.needs_upgrade);
+    assert!(report.issues.is_empty());
+}
+
+#[test]
+fn a_missing_database_can_be_created() {
+    let temp = TempDir::new().expect("tempdir");
+    let report = QuickStep::compatibility(temp.path().join("absent")).expect("inspect");
+    assert_eq!(
+        (report.data_format_version, report.wal_format_version),
+        (None, None)
+    );
+    assert!(report.can_open && report.issues.is_empty());
+}
+
+#[test]
+fn a_version_1_superblock_is_upgraded_on_open() {
+    let temp = TempDir::new().expect("tempdir");
+    create(temp.path());
+    let data = temp.path().join("quickstep.db");
+    // a version 1 block: the same fields, tagged over the first 32 bytes
+    let mut block = fs::read(&data).unwrap()[..32].to_vec();
+    block[4..8].copy_from_slice(&1u32.to_le_bytes());
+    let tag = MetadataAuth::default().tag(&block);
+    block.extend_from_slice(&tag);
+    block.extend_from_slice(&[0u8; 8]);
+    overwrite(&data, 0, &block);
+
+    let report = QuickStep::compatibility(temp.path()).expect("inspect");
+    assert_eq!(report.data_format_version, Some(1));
+    assert_eq!(report.written_by, None);
+    assert!(report.can_open && report.needs_upgrade);
+    assert_eq!(report.issues, vec![CompatIssue::OldDataFormat(1)]);
+
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    assert_eq!(db.get(b"alpha").unwrap(), Some(b"one".to_vec()));
+    drop(db);
+    let report = QuickStep::compatibility(temp.path()).expect("inspect");
+    assert_eq!(report.data_format_version, Some(SUPERBLOCK_VERSION));
+    assert!(!report.needs_upgrade && report.issues.is_empty());
+}
+
+#[test]
+fn unknown_formats_block_opening() {
+    let temp = TempDir::new().expect("tempdir");
+    create(temp.path());
+    overwrite(&temp.path().join("quickstep.db"), 4, &9u32.to_le_bytes());
+    fs::write(temp.path().join("quickstep.wal"), vec![0xAB; 64]).unwrap();
+
+    let report = QuickStep::compatibility(temp.path()).expect("inspect");
+    assert!(!report.can_open);
+    assert_eq!(
+        report.issues,
+        vec![
+            CompatIssue::UnsupportedDataFormat(9),
+            CompatIssue::UnrecognizedWal
+        ]
+    );
+}
+
+#[test]
+fn a_file_from_a_newer_crate_is_noted_without_blocking() {
+    let temp = TempDir::new().expect("tempdir");
+    create(temp.path());
+    overwrite(
+        &temp.path().join("quickstep.db"),
+        32,
+        &u16::MAX.to_le_bytes(),
+    );
+
+    let report = QuickStep::compatibility(temp.path()).expect("inspect");
+    let newer = report.written_by.expect("recorded");
+    assert_eq!(newer.major, u16::MAX);
+    assert!(report.can_open);
+    assert_eq!(report.issues, vec![CompatIssue::WrittenByNewerCrate(newer)]);
+}
@@ -0,0 +1,159 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use quickstep::{
+    checkpoint::CheckpointPolicy,
+    error::QSError,
+    health::WalLimitPolicy,
+    page_op::{SplitPoint, SplitPolicy},
+    wal::SyncPolicy,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn write_config(temp: &TempDir, name: &str, text: &str) -> PathBuf {
+    let path = temp.path().join(name);
+    fs::write(&path, text).expect("write config");
+    path
+}
+
+#[test]
+fn toml_and_json_files_set_the_same_tunables() {
+    let temp = TempDir::new().expect("tempdir");
+    let toml = write_config(
+        &temp,
+        "quickstep.toml",
+        r#"
+# data lives next to this file
+path = "data"
+inner_node_upper_bound = 32
+leaf_upper_bound = 256
+cache_size_lg = 14
+
+wal_leaf_threshold = 7
+wal_global_record_threshold = 13
+wal_global_byte_threshold = 1_048_576
+wal_limit_policy = "fail"
+max_wal_bytes = 4096
+sync_policy = "every"  # group commits
+sync_interval_ms = 25
+idle_maintenance_after_ms = 500
+idle_maintenance_leaf_writes_per_sec = 40
+checkpoint_policy = "round_robin"
+leaf_cache_pages = 8
+read_repair = true
+split_point = "ratio"
+split_left_percent = 70
+append_split_percent = 0
+"#,
+    );
+    let json = write_config(
+        &temp,
+        "quickstep.json",
+        r#"{
+    "path": "data",
+    "inner_node_upper_bound": 32,
+    "leaf_upper_bound": 256,
+    "cache_size_lg": 14,
+    "wal_leaf_threshold": 7,
+    "wal_global_record_threshold": 13,
+    "wal_global_byte_threshold": 1048576,
+    "wal_limit_policy": "fail",
+    "max_wal_bytes": 4096,
+    "sync_policy": "every",
+    "sync_interval_ms": 25,
+    "idle_maintenance_after_ms": 500,
+    "idle_maintenance_leaf_writes_per_sec": 40,
+    "checkpoint_policy": "round_robin",
+    "leaf_cache_pages": 8,
+    "read_repair": true,
+    "split_point": "ratio",
+    "split_left_percent": 70,
+    "append_split_percent": 0
+}"#,
+    );
+    for path in [toml, json] {
+        let cfg = QuickStepConfig::from_file(&path).expect("load config");
+        assert_eq!(cfg.wal_thresholds(), (7, 13, 1 << 20));
+        assert_eq!(cfg.wal_limit_policy(), WalLimitPolicy::Fail);
+        assert_eq!(cfg.max_wal_size(), Some(4096));
+        assert_eq!(
+            cfg.sync_policy(),
+            SyncPolicy::Every(Duration::from_millis(25))
+        );
+        let idle = cfg.idle_maintenance().expect("idle maintenance");
+        assert_eq!(idle.idle_after, Duration::from_millis(500));
+        assert_eq!(idle.leaf_writes_per_sec, 40);
+        assert_eq!(cfg.checkpoint_policy(), CheckpointPolicy::RoundRobin);
+        assert_eq!(cfg.leaf_cache_pages(), 8);
+        assert!(cfg.read_repair());
+        assert_eq!(
+            cfg.split_policy(),
+            SplitPolicy {
+                point: SplitPoint::Ratio { left_percent: 70 },
+                append_left_percent: None,
+            }
+        );
+        assert!(!cfg.is_in_memory());
+    }
+
+    // the relative path resolves against the config file's directory
+    let cfg = QuickStepConfig::from_file(temp.path().join("quickstep.toml")).expect("load");
+    let db = QuickStep::new(cfg);
+    let mut tx = db.tx();
+    tx.put(b"key", b"value").expect("put");
+    tx.commit();
+    drop(db);
+    assert!(temp.path().join("data").is_dir());
+}
+
+#[test]
+fn mistakes_are_reported_with_their_line() {
+    let temp = TempDir::new().expect("tempdir");
+    let cases = [
+        (
+            "inner_node_upper_bound = 32\nleaf_upper_bound = 256\ncache_size_lg = 14\n\
+             in_memory = true\nwal_leaf_treshold = 7\n",
+            5,
+            "unknown key",
+        ),
+        (
+            "in_memory = true\ninner_node_upper_bound = \"32\"\n",
+            2,
+            "should be a number",
+        ),
+        (
+            "{\"in_memory\": true, \"inner_node_upper_bound\": 32, \"leaf_upper_bound\": 256,\n \
+             \"cache_size_lg\": 14,\n\"sync_policy\": \"sometimes\"}",
+            3,
+            "is not a `sync_policy`",
+        ),
+        (
+            "inner_node_upper_bound = 32\nleaf_upper_bound = 256\ncache_size_lg = 14\n",
+            0,
+            "`path` is required",
+        ),
+        ("in_memory = true\nin_memory = false\n", 2, "more than once"),
+        ("[wal]\n", 1, "tables are not supported"),
+    ];
+    for (idx, (text, line, reason)) in cases.into_iter().enumerate() {
+        let path = write_config(&temp, &format!("case-{idx}.toml"), text);
+        match QuickStepConfig::from_file(&path) {
+            Err(QSError::InvalidConfig {
+                path: err_path,
+                line: err_line,
+                reason: err_reason,
+            }) => {
+                assert_eq!(err_path, path);
+                assert_eq!(err_line, line, "case {idx}: {err_reason}");
+                assert!(err_reason.contains(reason), "case {idx}: {err_reason}");
+            }
+            Err(other) => panic!("case {idx}: unexpected error {other:?}"),
+            Ok(_) => panic!("case {idx}: loaded"),
+        }
+    }
+
+    assert!(matches!(
+        QuickStepConfig::from_file(temp.path().join("missing.toml")),
+        Err(QSError::Io(_))
+    ));
+}
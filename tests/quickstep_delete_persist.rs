@@ -1,3 +1,8 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
 use quickstep::{
     debug,
     map_table::PageId,
@@ -6,8 +11,19 @@ use quickstep::{
 };
 use tempfile::TempDir;
 
+fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
 fn wal_record_count(db: &QuickStep, page_id: Option<PageId>) -> usize {
-    db.debug_wal_stats(page_id).leaf_records.unwrap_or(0)
+    db.debug_wal_stats(page_id)
+        .unwrap()
+        .leaf_records
+        .unwrap_or(0)
 }
 
 #[test]
@@ -158,6 +174,28 @@ fn deletes_persist_after_flush_and_restart() {
     }
 }
 
+#[test]
+fn put_over_a_tombstone_brings_the_key_back() {
+    let temp = TempDir::new().expect("tempdir");
+    let db_path = temp.path().join("db");
+    let db = QuickStep::new(QuickStepConfig::new(db_path.clone(), 32, 256, 14));
+
+    let mut tx = db.tx();
+    tx.put(b"key", b"first").expect("insert");
+    tx.commit();
+    db.debug_flush_root_leaf().expect("flush root leaf");
+    assert!(db.delete(b"key").unwrap());
+    let mut tx = db.tx();
+    tx.put(b"key", b"again").expect("put over the tombstone");
+    tx.commit();
+    assert_eq!(db.get(b"key").unwrap(), Some(b"again".to_vec()));
+    db.debug_flush_root_leaf().expect("flush root leaf");
+    drop(db);
+
+    let reopened = QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14));
+    assert_eq!(reopened.get(b"key").unwrap(), Some(b"again".to_vec()));
+}
+
 #[test]
 fn wal_replays_deletes_without_manual_flush() {
     let temp = TempDir::new().expect("tempdir");
@@ -249,19 +287,24 @@ fn wal_auto_checkpoint_trims_entries() {
 fn wal_byte_threshold_triggers_checkpoint() {
     let temp = TempDir::new().expect("tempdir");
     let db_path = temp.path().join("db");
-    {
-        let db = QuickStep::new(QuickStepConfig::new(db_path.clone(), 32, 256, 14));
-        let payload = vec![0u8; 128 * 1024];
-        let mut tx = db.tx();
-        for idx in 0..8 {
-            let key = format!("key-large-{idx:04}");
-            tx.put(key.as_bytes(), &payload).expect("large insert");
-        }
-        tx.commit();
-        assert!(
-            wal_record_count(&db, Some(PageId::from_u64(0))) < 8,
-            "byte-based threshold should trigger global checkpoint even with redo/undo logging"
-        );
+    // each record logs the pointer to a value's overflow pages, about 55 bytes, so only the
+    // byte threshold is low enough to trip
+    let config = QuickStepConfig::new(db_path, 32, 256, 14)
+        .with_wal_thresholds(usize::MAX, usize::MAX, 256)
+        .with_checkpoint_interval(Duration::from_millis(10));
+    let db = QuickStep::new(config);
+    let payload = vec![0u8; 128 * 1024];
+    let mut tx = db.tx();
+    for idx in 0..8 {
+        let key = format!("key-large-{idx:04}");
+        tx.put(key.as_bytes(), &payload).expect("large insert");
+    }
+    tx.commit();
+    // the checkpoint thread finds the WAL over the byte threshold
+    wait_for(|| wal_record_count(&db, Some(PageId::from_u64(0))) < 8);
+    for idx in 0..8 {
+        let key = format!("key-large-{idx:04}");
+        assert_eq!(db.get(key.as_bytes()).unwrap(), Some(payload.clone()));
     }
 }
 
@@ -345,7 +388,7 @@ fn wal_checkpoint_drops_only_target_page() {
 
     let grouped = wal.records_grouped();
     assert!(
-        grouped.get(&5).is_none(),
+        !grouped.contains_key(&5),
         "page 5 entries should be removed after checkpoint"
     );
     assert_eq!(
@@ -358,7 +401,7 @@ fn wal_checkpoint_drops_only_target_page() {
     let reopened = WalManager::open(&wal_path).expect("reopen wal");
     let grouped = reopened.records_grouped();
     assert!(
-        grouped.get(&5).is_none(),
+        !grouped.contains_key(&5),
         "page 5 entries should stay removed after reopen"
     );
     assert_eq!(
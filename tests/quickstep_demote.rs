@@ -0,0 +1,81 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key-{i:04}").into_bytes()
+}
+
+fn value(i: usize) -> Vec<u8> {
+    vec![(i % 251) as u8; 300]
+}
+
+fn loaded(temp: &TempDir) -> QuickStep {
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 20));
+    let mut tx = db.tx();
+    for i in 0..60 {
+        tx.put(&key(i), &value(i)).expect("insert");
+    }
+    tx.commit();
+    db
+}
+
+#[test]
+fn demoting_a_range_writes_its_leaves_back_and_frees_the_cache() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = loaded(&temp);
+    let pages = db
+        .debug_root_leaf_parent()
+        .expect("the load should split the root")
+        .children;
+    let leaves = pages.len();
+    assert_eq!(db.cached_leaves(), leaves);
+
+    assert_eq!(db.demote_range(b"", b"\xff").expect("demote"), leaves);
+    assert_eq!(db.cached_leaves(), 0);
+    for page in pages {
+        let records = db.debug_wal_stats(Some(page)).unwrap().leaf_records;
+        assert_eq!(records.unwrap_or(0), 0, "leaf {page:?} was checkpointed");
+    }
+    // nothing left to write back
+    assert_eq!(db.demote_range(b"", b"\xff").expect("demote"), 0);
+
+    for i in 0..60 {
+        assert_eq!(db.get(&key(i)).expect("get"), Some(value(i)));
+    }
+    drop(db);
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 20));
+    for i in 0..60 {
+        assert_eq!(db.get(&key(i)).expect("get"), Some(value(i)));
+    }
+}
+
+#[test]
+fn demoting_part_of_the_keys_leaves_the_rest_cached() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = loaded(&temp);
+    let snapshot = db.debug_root_leaf_parent().expect("split root");
+    let first = snapshot.children[0];
+
+    assert!(db.demote(first).expect("demote"));
+    assert!(!db.demote(first).expect("demote again"));
+    assert_eq!(db.cached_leaves(), snapshot.children.len() - 1);
+    assert_eq!(db.get(&key(0)).expect("get"), Some(value(0)));
+}
+
+#[test]
+fn locked_leaves_stay_cached() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = loaded(&temp);
+    let cached = db.cached_leaves();
+
+    let mut tx = db.tx();
+    tx.put(&key(0), b"held").expect("put");
+    let first = db.debug_root_leaf_parent().expect("split root").children[0];
+    assert!(!db.demote(first).expect("demote"));
+    assert_eq!(db.demote_range(b"", &key(1)).expect("demote"), 0);
+    tx.commit();
+
+    assert_eq!(db.cached_leaves(), cached);
+    assert!(db.demote(first).expect("demote"));
+    assert_eq!(db.get(&key(0)).expect("get"), Some(b"held".to_vec()));
+}
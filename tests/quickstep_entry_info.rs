@@ -0,0 +1,58 @@
+use quickstep::{map_table::PageId, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn new_db(db_path: std::path::PathBuf) -> QuickStep {
+    QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14))
+}
+
+#[test]
+fn entry_info_reports_cached_records_and_tombstones() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = new_db(temp.path().join("db"));
+    let mut tx = db.tx();
+    tx.put(b"alpha", b"first value").expect("put alpha");
+    tx.put(b"beta", b"other").expect("put beta");
+    tx.commit();
+
+    let mut tx = db.tx();
+    let info = tx.entry_info(b"alpha").unwrap().expect("alpha present");
+    assert_eq!(info.value_len, b"first value".len());
+    assert!(info.cached);
+    assert!(!info.tombstoned && !info.soft_deleted);
+    assert_eq!(info.page_id, PageId::from_u64(0));
+    assert!(tx.entry_info(b"missing").unwrap().is_none());
+    tx.commit();
+
+    assert!(db.delete(b"beta").unwrap());
+    assert!(db.soft_delete(b"alpha").unwrap());
+    let mut tx = db.tx();
+    let info = tx.entry_info(b"beta").unwrap().expect("tombstone present");
+    assert!(info.tombstoned);
+    assert_eq!(info.value_len, 0);
+    let info = tx.entry_info(b"alpha").unwrap().expect("value kept");
+    assert!(info.soft_deleted);
+    assert_eq!(info.value_len, b"first value".len());
+    tx.commit();
+}
+
+#[test]
+fn entry_info_for_disk_leaf() {
+    let temp = TempDir::new().expect("tempdir");
+    let db_path = temp.path().join("db");
+    {
+        let db = new_db(db_path.clone());
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"on-disk").expect("put alpha");
+        tx.commit();
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+
+    let db = new_db(db_path);
+    let mut tx = db.tx();
+    let info = tx.entry_info(b"alpha").unwrap().expect("alpha present");
+    assert_eq!(info.value_len, b"on-disk".len());
+    assert!(!info.cached);
+    assert!(tx.entry_info(b"missing").unwrap().is_none());
+    assert_eq!(tx.get(b"alpha").unwrap(), Some(&b"on-disk"[..]));
+    tx.commit();
+}
@@ -0,0 +1,155 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use quickstep::{
+    events::{EventRecorder, StructuralEvent},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    // a small buffer, so the run evicts as well as splits
+    QuickStepConfig::new(path, 64, 1024, 16)
+}
+
+fn run_workload(db: &QuickStep) {
+    for idx in 0..1200u32 {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx:05}").as_bytes(), &[7u8; 64])
+            .expect("put");
+        tx.commit();
+    }
+}
+
+#[test]
+fn listener_hears_allocations_splits_and_evictions() {
+    let temp = TempDir::new().expect("tempdir");
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&heard);
+    let listener = move |event: &StructuralEvent| sink.lock().unwrap().push(event.clone());
+    let db = QuickStep::new(config(temp.path()).with_event_listener(Arc::new(listener)));
+    run_workload(&db);
+
+    let heard = heard.lock().unwrap().clone();
+    let splits: Vec<_> = heard
+        .iter()
+        .filter_map(|event| match event {
+            StructuralEvent::LeafSplit { right_addr, .. } => Some(*right_addr),
+            _ => None,
+        })
+        .collect();
+    assert!(!splits.is_empty());
+    // every right half was written to a page allocated before its split was logged
+    for right_addr in &splits {
+        let allocated = heard.iter().position(|event| {
+            matches!(event, StructuralEvent::DiskPageAllocated { addr, .. } if addr == right_addr)
+        });
+        let split = heard.iter().position(|event| {
+            matches!(event, StructuralEvent::LeafSplit { right_addr: addr, .. } if addr == right_addr)
+        });
+        assert!(allocated.expect("allocated") < split.expect("split"));
+    }
+    assert!(heard
+        .iter()
+        .any(|event| matches!(event, StructuralEvent::MiniPageAllocated { .. })));
+    assert!(heard
+        .iter()
+        .any(|event| matches!(event, StructuralEvent::MiniPageEvicted { .. })));
+}
+
+#[test]
+fn recorded_runs_of_the_same_workload_match() {
+    let temp = TempDir::new().expect("tempdir");
+    let mut recordings = Vec::new();
+    for run in 0..2 {
+        let db_path = temp.path().join(format!("run-{run}"));
+        fs::create_dir(&db_path).expect("mkdir");
+        let events_path = temp.path().join(format!("run-{run}.jsonl"));
+        let recorder = Arc::new(EventRecorder::create(&events_path).expect("recorder"));
+        // the checkpoint thread runs on a timer, keep it out of the recording
+        let db = QuickStep::new(
+            config(&db_path)
+                .with_checkpoint_interval(Duration::from_secs(3600))
+                .with_event_listener(recorder.clone()),
+        );
+        run_workload(&db);
+        drop(db);
+        recorder.flush().expect("flush");
+        recordings.push(fs::read_to_string(&events_path).expect("read"));
+    }
+
+    assert_eq!(recordings[0], recordings[1]);
+    let lines: Vec<_> = recordings[0].lines().collect();
+    assert!(lines.len() > 10);
+    assert!(lines.iter().all(|line| line.starts_with(r#"{"event":""#)));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with(r#"{"event":"leaf_split","left":0,"#)));
+}
+
+#[test]
+fn listener_hears_checkpoints_and_wal_truncation() {
+    let temp = TempDir::new().expect("tempdir");
+    let heard = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&heard);
+    let listener = move |event: &StructuralEvent| sink.lock().unwrap().push(event.clone());
+    let db = QuickStep::new(config(temp.path()).with_event_listener(Arc::new(listener)));
+    run_workload(&db);
+    heard.lock().unwrap().clear();
+    db.checkpoint().expect("checkpoint");
+
+    let heard = heard.lock().unwrap().clone();
+    let checkpointed: Vec<u64> = heard
+        .iter()
+        .filter_map(|event| match event {
+            StructuralEvent::LeavesCheckpointed { pages } => Some(pages.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    assert!(!checkpointed.is_empty());
+    assert!(heard.iter().any(|event| matches!(
+        event,
+        StructuralEvent::WalTruncated { bytes_before, bytes_after }
+            if bytes_after < bytes_before
+    )));
+}
+
+#[test]
+fn events_serialize_as_json_lines() {
+    let split = StructuralEvent::LeafSplit {
+        left: 0,
+        right: 3,
+        right_addr: 5,
+        pivot: b"key-1".to_vec(),
+    };
+    assert_eq!(
+        split.to_json(),
+        r#"{"event":"leaf_split","left":0,"right":3,"right_addr":5,"pivot":"6b65792d31"}"#
+    );
+    let allocated = StructuralEvent::DiskPageAllocated {
+        addr: 9,
+        reused: true,
+    };
+    assert_eq!(
+        allocated.to_json(),
+        r#"{"event":"disk_page_allocated","addr":9,"reused":true}"#
+    );
+    let truncated = StructuralEvent::WalTruncated {
+        bytes_before: 4096,
+        bytes_after: 64,
+    };
+    assert_eq!(
+        truncated.to_json(),
+        r#"{"event":"wal_truncated","bytes_before":4096,"bytes_after":64}"#
+    );
+    let checkpointed = StructuralEvent::LeavesCheckpointed { pages: vec![2, 5] };
+    assert_eq!(
+        checkpointed.to_json(),
+        r#"{"event":"leaves_checkpointed","pages":[2,5]}"#
+    );
+}
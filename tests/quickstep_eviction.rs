@@ -3,7 +3,7 @@ use tempfile::TempDir;
 
 fn new_small_cache_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
-    let config = QuickStepConfig::new(temp.into_path(), 32, 256, 13);
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 13);
     QuickStep::new(config)
 }
 
@@ -69,3 +69,33 @@ fn second_chance_clears_hot_pages_before_eviction() {
         "second-chance path should run before eviction"
     );
 }
+
+#[test]
+fn evicting_the_oldest_page_lets_the_buffer_wrap_around() {
+    let temp = TempDir::new().expect("tempdir");
+    // room for four leaves, so the loads below go round the buffer many times
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 14));
+    let value = |i: usize| vec![(i % 251) as u8; 900];
+
+    for round in 0..4 {
+        let mut tx = db.tx();
+        for i in (round..400).step_by(4) {
+            let key = format!("key-{i:04}");
+            tx.put(key.as_bytes(), &value(i)).expect("insert");
+        }
+        tx.commit();
+    }
+    assert!(
+        debug::head_advances() > 0,
+        "evicting the page at the head should move the head"
+    );
+
+    for i in 0..400 {
+        let key = format!("key-{i:04}");
+        assert_eq!(
+            db.get(key.as_bytes()).expect("get"),
+            Some(value(i)),
+            "key {i}"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+#![cfg(feature = "failpoints")]
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+use quickstep::{
+    failpoints::{self, FailAction},
+    health::HealthEvent,
+    wal::WalStatsLevel,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+/// Failpoints are process-wide, so these tests take turns
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn serial() -> std::sync::MutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(|poison| poison.into_inner());
+    failpoints::reset();
+    guard
+}
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 16)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+fn put(db: &QuickStep, idx: u32) {
+    let mut tx = db.tx();
+    tx.put(&key(idx), &[7u8; 64]).expect("put");
+    tx.commit();
+}
+
+#[test]
+fn a_failed_checkpoint_leaves_the_wal_to_the_next_one() {
+    let _serial = serial();
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    for idx in 0..100 {
+        put(&db, idx);
+    }
+
+    failpoints::enable(failpoints::WAL_CHECKPOINT, FailAction::Error);
+    assert!(db.checkpoint().is_err());
+    assert!(failpoints::hits(failpoints::WAL_CHECKPOINT) >= 1);
+    failpoints::disable(failpoints::WAL_CHECKPOINT);
+    db.checkpoint().expect("checkpoint");
+    drop(db);
+
+    let db = QuickStep::new(config(temp.path()));
+    for idx in 0..100 {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(vec![7u8; 64]), "key {idx}");
+    }
+}
+
+#[test]
+fn a_failed_idle_maintenance_pass_is_reported_and_retried() {
+    let _serial = serial();
+    let temp = TempDir::new().expect("tempdir");
+    // the checkpoint thread would flush the same leaves, keep it out of the way
+    let db = QuickStep::new(
+        config(temp.path())
+            .with_idle_maintenance(Duration::from_millis(20), 1000)
+            .with_checkpoint_interval(Duration::from_secs(3600)),
+    );
+    let events = db.health_events();
+    // too few records for a write to checkpoint the leaf itself
+    failpoints::enable(failpoints::WAL_CHECKPOINT, FailAction::Error);
+    for idx in 0..8 {
+        put(&db, idx);
+    }
+
+    let failed = std::iter::from_fn(|| events.recv_timeout(Duration::from_secs(5)).ok())
+        .find(|event| matches!(event, HealthEvent::BackgroundPassFailed { .. }));
+    assert!(matches!(
+        failed,
+        Some(HealthEvent::BackgroundPassFailed {
+            task: "idle-maintenance",
+            ..
+        })
+    ));
+    failpoints::disable(failpoints::WAL_CHECKPOINT);
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while db.debug_wal_stats(None).unwrap().total_records > 0 {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "the WAL was never trimmed"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn a_failed_background_checkpoint_is_reported_and_retried() {
+    let _serial = serial();
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        config(temp.path())
+            .with_wal_thresholds(usize::MAX, 4, usize::MAX)
+            .with_checkpoint_interval(Duration::from_millis(20)),
+    );
+    let events = db.health_events();
+    failpoints::enable(failpoints::WAL_CHECKPOINT, FailAction::Error);
+    for idx in 0..8 {
+        put(&db, idx);
+    }
+
+    let failed = std::iter::from_fn(|| events.recv_timeout(Duration::from_secs(5)).ok())
+        .find(|event| matches!(event, HealthEvent::BackgroundPassFailed { .. }));
+    assert!(matches!(
+        failed,
+        Some(HealthEvent::BackgroundPassFailed {
+            task: "checkpoint",
+            ..
+        })
+    ));
+    failpoints::disable(failpoints::WAL_CHECKPOINT);
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while db.debug_wal_stats(None).unwrap().total_records > 0 {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "the WAL was never trimmed"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn a_failed_wal_write_is_returned_by_reads_of_the_log() {
+    let _serial = serial();
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let mut tx = db.tx();
+    // queued until the commit writes them
+    tx.put(&key(0), &[7u8; 64]).expect("put");
+
+    failpoints::enable(failpoints::WAL_WRITE, FailAction::Error);
+    assert!(db.debug_wal_stats(None).is_err());
+    assert!(db.wal_stats(WalStatsLevel::Totals).is_err());
+    assert!(db.metrics_text().is_err());
+    failpoints::disable(failpoints::WAL_WRITE);
+
+    // the records stayed queued and are written with the commit
+    tx.commit();
+    assert!(db.debug_wal_stats(None).unwrap().total_records > 0);
+    assert_eq!(db.get(&key(0)).unwrap(), Some(vec![7u8; 64]));
+}
+
+#[test]
+fn a_crash_before_a_leaf_is_written_is_replayed_from_the_wal() {
+    let _serial = serial();
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        for idx in 0..100 {
+            put(&db, idx);
+        }
+        failpoints::enable_nth(failpoints::WRITE_PAGE, 1, FailAction::Crash);
+        let crashed = panic::catch_unwind(AssertUnwindSafe(|| db.checkpoint()));
+        assert!(crashed.is_err());
+        assert!(failpoints::crashed());
+    }
+    failpoints::reset();
+
+    let db = QuickStep::new(config(temp.path()));
+    assert!(db.last_recovery_report().records_replayed > 0);
+    for idx in 0..100 {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(vec![7u8; 64]), "key {idx}");
+    }
+}
+
+#[test]
+fn a_crash_mid_split_keeps_every_committed_write() {
+    for point in [failpoints::SPLIT_RIGHT_WRITTEN, failpoints::SPLIT_LOGGED] {
+        let _serial = serial();
+        let temp = TempDir::new().expect("tempdir");
+        let committed = {
+            let db = QuickStep::new(config(temp.path()));
+            failpoints::enable_nth(point, 3, FailAction::Crash);
+            let mut committed = 0;
+            while panic::catch_unwind(AssertUnwindSafe(|| put(&db, committed))).is_ok() {
+                committed += 1;
+            }
+            assert_eq!(failpoints::hits(point), 3);
+            committed
+        };
+        failpoints::reset();
+
+        let db = QuickStep::new(config(temp.path()));
+        for idx in 0..committed {
+            assert_eq!(
+                db.get(&key(idx)).unwrap(),
+                Some(vec![7u8; 64]),
+                "{point}: key {idx}"
+            );
+        }
+        assert_eq!(db.get(&key(committed)).unwrap(), None, "{point}");
+    }
+}
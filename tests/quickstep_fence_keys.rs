@@ -1,16 +1,16 @@
-use quickstep::{debug, map_table::PageId, QuickStep, QuickStepConfig};
+use quickstep::{debug, invariants, map_table::PageId, QuickStep, QuickStepConfig};
 use std::collections::HashSet;
 use tempfile::TempDir;
 
 fn new_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
-    let config = QuickStepConfig::new(temp.into_path(), 32, 256, 14);
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14);
     QuickStep::new(config)
 }
 
 fn new_small_cache_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
-    let config = QuickStepConfig::new(temp.into_path(), 32, 256, 13);
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 13);
     QuickStep::new(config)
 }
 
@@ -56,39 +56,12 @@ fn assert_sentinel_fences(db: &QuickStep, page_id: PageId) {
 }
 
 fn assert_bounds_cover_keys(db: &QuickStep, page_id: PageId) {
-    let fences = db
-        .debug_leaf_fences(page_id)
-        .unwrap_or_else(|_| panic!("missing fences for page {}", page_id.as_u64()));
-    let snapshot = db
-        .debug_leaf_snapshot(page_id)
-        .unwrap_or_else(|_| panic!("missing snapshot for page {}", page_id.as_u64()));
-    if snapshot.keys.is_empty() {
-        return;
+    if let Err(violation) = invariants::check_leaf(db, page_id) {
+        panic!(
+            "page {} breaks leaf invariants: {violation:?}",
+            page_id.as_u64()
+        );
     }
-    let min_key = snapshot
-        .keys
-        .iter()
-        .min()
-        .expect("snapshot already checked non-empty");
-    let max_key = snapshot
-        .keys
-        .iter()
-        .max()
-        .expect("snapshot already checked non-empty");
-    assert!(
-        fences.lower.as_slice() <= min_key.as_slice(),
-        "lower fence {:?} must be <= min key {:?} for page {}",
-        fences.lower,
-        min_key,
-        page_id.as_u64()
-    );
-    assert!(
-        max_key.as_slice() < fences.upper.as_slice(),
-        "upper fence {:?} must be > max key {:?} for page {}",
-        fences.upper,
-        max_key,
-        page_id.as_u64()
-    );
 }
 
 #[test]
@@ -126,6 +99,7 @@ fn split_children_receive_parent_bounds() {
         "right child lower fence should equal pivot"
     );
     assert_bounds_cover_keys(&db, children[1]);
+    assert_eq!(invariants::check_all(&db).expect("tree invariants"), 2);
 }
 
 #[test]
@@ -133,6 +107,9 @@ fn merge_survivor_spans_full_bounds() {
     debug::reset_debug_counters();
     let db = new_db();
     let (children, _, _) = drive_root_split(&db);
+    // a leaf only splits once full, so the halves are shrunk until they fit in one leaf
+    db.debug_truncate_leaf(children[0], 1, false)
+        .expect("shrink left");
 
     db.debug_merge_leaves(children[0], children[1])
         .expect("merge should succeed");
@@ -171,6 +148,7 @@ fn eviction_preserves_fence_monotonicity() {
     for page in pages {
         assert_bounds_cover_keys(&db, PageId::from_u64(page));
     }
+    invariants::check_all(&db).expect("tree invariants");
 }
 
 #[test]
@@ -183,7 +161,8 @@ fn delete_auto_merge_preserves_fence_monotonicity() {
 
     {
         let mut tx = db.tx();
-        for i in inserted..(inserted + 64) {
+        // few enough small values that the right half takes them without splitting again
+        for i in inserted..(inserted + 8) {
             let key = format!("key-{i:04}");
             tx.put(key.as_bytes(), &payload)
                 .expect("insert after split");
@@ -196,7 +175,7 @@ fn delete_auto_merge_preserves_fence_monotonicity() {
         .expect("tree should still have inner root");
     assert_eq!(snapshot.children.len(), 2, "expected exactly two children");
 
-    for i in 0..(inserted + 64) {
+    for i in 0..(inserted + 8) {
         let key = format!("key-{i:04}");
         if key.as_bytes() >= pivot.as_slice() {
             assert!(
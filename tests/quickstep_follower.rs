@@ -0,0 +1,173 @@
+use std::{fs, path::Path, thread, time::Duration};
+
+use quickstep::{error::QSError, wal::WalRecord, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 20).with_wal_tail_retention(100_000)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:04}").into_bytes()
+}
+
+/// A follower opened on a copy of the primary's directory, taken after a checkpoint
+fn seed_follower(primary: &QuickStep, primary_path: &Path, follower_path: &Path) -> QuickStep {
+    primary.checkpoint().expect("checkpoint");
+    for entry in fs::read_dir(primary_path).expect("read dir") {
+        let entry = entry.expect("entry");
+        fs::copy(entry.path(), follower_path.join(entry.file_name())).expect("copy");
+    }
+    QuickStep::open_follower(config(follower_path))
+}
+
+#[test]
+fn a_follower_replays_the_primary_stream() {
+    let (primary_dir, follower_dir) = (
+        TempDir::new().expect("tempdir"),
+        TempDir::new().expect("tempdir"),
+    );
+    let primary = QuickStep::new(config(primary_dir.path()));
+    for idx in 0..100 {
+        let mut tx = primary.tx();
+        tx.put(&key(idx), b"seeded").expect("put");
+        tx.commit();
+    }
+    let from = primary.tail_wal(1).expect("tail").count() as u64 + 1;
+    let follower = seed_follower(&primary, primary_dir.path(), follower_dir.path());
+    assert!(follower.is_follower());
+    assert_eq!(
+        follower.get(&key(99)).expect("get"),
+        Some(b"seeded".to_vec())
+    );
+
+    // enough writes to split leaves, with deletes and a rolled back transaction
+    for idx in 0..600 {
+        let mut tx = primary.tx();
+        tx.put(&key(idx), format!("value-{idx}").as_bytes())
+            .expect("put");
+        if idx % 7 == 0 {
+            tx.delete(&key(idx / 2)).expect("delete");
+        }
+        if idx % 50 == 1 {
+            tx.abort();
+        } else {
+            tx.commit();
+        }
+    }
+
+    let records: Vec<WalRecord> = primary.tail_wal(from).expect("tail").collect();
+    let stats = follower.apply_wal_batch(records).expect("apply");
+    assert_eq!(stats.txns_applied, 588);
+    assert_eq!(stats.txns_discarded, 12);
+    for idx in 0..600 {
+        assert_eq!(
+            follower.get(&key(idx)).expect("get"),
+            primary.get(&key(idx)).expect("get"),
+            "key {idx}"
+        );
+    }
+}
+
+#[test]
+fn a_follower_refuses_local_writes() {
+    let temp = TempDir::new().expect("tempdir");
+    let follower = QuickStep::open_follower(config(temp.path()));
+    let mut tx = follower.tx();
+    assert!(matches!(
+        tx.put(b"key", b"value"),
+        Err(QSError::FollowerReadOnly)
+    ));
+    assert!(matches!(tx.delete(b"key"), Err(QSError::FollowerReadOnly)));
+    tx.abort();
+    assert!(matches!(
+        follower.replace_all([(b"key", b"value")]).wait(),
+        Err(QSError::FollowerReadOnly)
+    ));
+
+    let other = TempDir::new().expect("tempdir");
+    let primary = QuickStep::new(config(other.path()));
+    assert!(matches!(
+        primary.apply_wal_batch(Vec::new()),
+        Err(QSError::NotFollower)
+    ));
+}
+
+#[test]
+fn transactions_wait_for_their_commit_and_batches_can_be_resent() {
+    let (primary_dir, follower_dir) = (
+        TempDir::new().expect("tempdir"),
+        TempDir::new().expect("tempdir"),
+    );
+    let primary = QuickStep::new(config(primary_dir.path()));
+    let follower = seed_follower(&primary, primary_dir.path(), follower_dir.path());
+
+    let mut tx = primary.tx();
+    tx.put(&key(1), b"first").expect("put");
+    tx.put(&key(2), b"first").expect("put");
+    tx.commit();
+    let mut tx = primary.tx();
+    tx.put(&key(1), b"second").expect("put");
+    tx.delete(&key(2)).expect("delete");
+    tx.commit();
+    let records: Vec<WalRecord> = primary.tail_wal(1).expect("tail").collect();
+
+    // a transaction cut off before its commit marker is held back
+    let cut = records.len() - 1;
+    let stats = follower
+        .apply_wal_batch(records[..cut].to_vec())
+        .expect("apply");
+    assert_eq!(stats.txns_applied, 1);
+    assert_eq!(follower.get(&key(1)).expect("get"), Some(b"first".to_vec()));
+    assert_eq!(follower.get(&key(2)).expect("get"), Some(b"first".to_vec()));
+
+    follower
+        .apply_wal_batch(records[cut..].to_vec())
+        .expect("apply");
+    assert_eq!(
+        follower.get(&key(1)).expect("get"),
+        Some(b"second".to_vec())
+    );
+    assert_eq!(follower.get(&key(2)).expect("get"), None);
+
+    // the whole stream again changes nothing
+    follower.apply_wal_batch(records).expect("apply");
+    assert_eq!(
+        follower.get(&key(1)).expect("get"),
+        Some(b"second".to_vec())
+    );
+    assert_eq!(follower.get(&key(2)).expect("get"), None);
+}
+
+#[test]
+fn expiries_and_soft_deletes_reach_the_follower() {
+    let (primary_dir, follower_dir) = (
+        TempDir::new().expect("tempdir"),
+        TempDir::new().expect("tempdir"),
+    );
+    let primary = QuickStep::new(config(primary_dir.path()));
+    for idx in 0..10 {
+        let mut tx = primary.tx();
+        tx.put(&key(idx), b"seeded").expect("put");
+        tx.commit();
+    }
+    let from = primary.tail_wal(1).expect("tail").count() as u64 + 1;
+    let follower = seed_follower(&primary, primary_dir.path(), follower_dir.path());
+
+    let mut tx = primary.tx();
+    tx.put_with_ttl(&key(1), b"brief", Duration::from_millis(20))
+        .expect("put brief");
+    tx.put_with_ttl(&key(2), b"kept", Duration::from_secs(3600))
+        .expect("put kept");
+    assert!(tx.soft_delete(&key(3)).expect("soft delete"));
+    tx.commit();
+
+    let records: Vec<WalRecord> = primary.tail_wal(from).expect("tail").collect();
+    follower.apply_wal_batch(records).expect("apply");
+    assert!(follower.ttl(&key(2)).is_some());
+    assert!(follower.is_soft_deleted(&key(3)).expect("flag"));
+    assert_eq!(follower.get(&key(3)).expect("get"), None);
+    thread::sleep(Duration::from_millis(40));
+    assert_eq!(follower.get(&key(1)).expect("get"), None);
+    assert_eq!(follower.get(&key(2)).expect("get"), Some(b"kept".to_vec()));
+}
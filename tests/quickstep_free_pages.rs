@@ -0,0 +1,101 @@
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Seek, SeekFrom, Write},
+};
+
+use quickstep::{
+    free_list::FREE_LIST_OFFSET,
+    io_engine::{DiskLeaf, IoEngine},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn children(db: &QuickStep) -> usize {
+    db.debug_root_leaf_parent()
+        .map_or(1, |snapshot| snapshot.children.len())
+}
+
+fn fill_until_split(db: &QuickStep, round: usize) {
+    let payload = vec![7u8; 64];
+    let before = children(db);
+    let mut idx = 0;
+    while children(db) == before {
+        let mut tx = db.tx();
+        tx.put(format!("key-{round}-{idx:04}").as_bytes(), &payload)
+            .expect("insert");
+        tx.commit();
+        idx += 1;
+    }
+}
+
+#[test]
+fn merged_leaves_give_their_page_to_the_next_split() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    fill_until_split(&db, 0);
+    assert_eq!(db.capacity_stats().disk_pages_used, 2);
+
+    let snapshot = db.debug_root_leaf_parent().expect("split");
+    let (left, right) = (snapshot.children[0], snapshot.children[1]);
+    db.debug_truncate_leaf(left, 3, false).expect("shrink left");
+    db.debug_truncate_leaf(right, 2, false)
+        .expect("shrink right");
+    db.debug_merge_leaves(left, right).expect("merge");
+    let stats = db.capacity_stats();
+    assert_eq!((stats.disk_pages_used, stats.disk_pages_free), (2, 1));
+
+    fill_until_split(&db, 1);
+    let stats = db.capacity_stats();
+    assert_eq!((stats.disk_pages_used, stats.disk_pages_free), (2, 0));
+    // records gone before the merge stay gone, the new leaf reads back from its reused page
+    db.debug_flush_leaf(db.debug_root_leaf_parent().unwrap().children[1])
+        .expect("flush");
+    assert_eq!(db.get(b"key-0-0010").unwrap(), None);
+    assert_eq!(db.get(b"key-1-0000").unwrap(), Some(vec![7u8; 64]));
+}
+
+#[test]
+fn the_free_list_survives_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("free.db");
+    let freed = {
+        let engine = IoEngine::open(&path).expect("open");
+        let addrs: Vec<u64> = (0..4).map(|_| engine.get_new_addr().unwrap()).collect();
+        for addr in &addrs {
+            engine.write_page(*addr, &DiskLeaf::zeroed());
+        }
+        engine.free_page(addrs[1]);
+        engine.free_page(addrs[2]);
+        assert_eq!(engine.free_pages(), 2);
+        addrs[2]
+    };
+
+    let engine = IoEngine::open(&path).expect("reopen");
+    assert_eq!(engine.free_pages(), 2);
+    let used = engine.pages_used();
+    assert_eq!(engine.get_new_addr().unwrap(), freed);
+    assert_eq!(engine.pages_used(), used);
+    drop(engine);
+
+    let engine = IoEngine::open(&path).expect("reopen");
+    assert_eq!(engine.free_pages(), 1);
+}
+
+#[test]
+fn a_damaged_free_list_fails_the_open() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("free.db");
+    {
+        let engine = IoEngine::open(&path).expect("open");
+        let addr = engine.get_new_addr().unwrap();
+        engine.free_page(addr);
+    }
+
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(FREE_LIST_OFFSET + 8)).unwrap();
+    file.write_all(&[0xFF; 4]).unwrap();
+    drop(file);
+
+    let err = IoEngine::open(&path).err().expect("damaged free list");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,74 @@
+use std::{fs, path::Path, time::Duration};
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    // leaves are only flushed by the checkpoints the tests run
+    QuickStepConfig::new(path, 64, 1024, 20)
+        .with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+        .with_lock_timeout(Duration::from_millis(20))
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:04}").into_bytes()
+}
+
+fn write_keys(db: &QuickStep, count: u32, value: &[u8]) {
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(&key(idx), value).expect("put");
+        tx.commit();
+    }
+}
+
+#[test]
+fn checkpoint_leaves_a_snapshot_that_needs_no_replay() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    write_keys(&db, 500, b"value");
+    let wal_before = db.wal_stats(Default::default()).unwrap().total.bytes;
+
+    let stats = db.checkpoint().expect("checkpoint");
+    assert!(stats.pages_flushed > 1);
+    assert_eq!(stats.pages_skipped, 0);
+    let wal = db.wal_stats(Default::default()).unwrap();
+    assert_eq!(wal.total, wal.structure);
+    assert_eq!(
+        stats.wal_bytes_reclaimed,
+        (wal_before - wal.total.bytes) as u64
+    );
+
+    // copy the files while the store is open, as a filesystem snapshot would
+    let snapshot = TempDir::new().expect("tempdir");
+    for entry in fs::read_dir(temp.path()).expect("read dir") {
+        let entry = entry.expect("entry");
+        fs::copy(entry.path(), snapshot.path().join(entry.file_name())).expect("copy");
+    }
+    let copy = QuickStep::new(config(snapshot.path()));
+    assert_eq!(copy.last_recovery_report().records_replayed, 0);
+    assert_eq!(copy.get(&key(0)).expect("get"), Some(b"value".to_vec()));
+    assert_eq!(copy.get(&key(499)).expect("get"), Some(b"value".to_vec()));
+
+    // a second checkpoint has nothing left to do
+    assert_eq!(db.checkpoint().expect("checkpoint").pages_flushed, 0);
+}
+
+#[test]
+fn a_leaf_held_by_a_transaction_is_skipped_until_it_ends() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    write_keys(&db, 20, b"value");
+    let mut writer = db.tx();
+    writer.put(&key(0), b"held").expect("put");
+
+    let stats = db.checkpoint().expect("checkpoint");
+    assert_eq!((stats.pages_flushed, stats.pages_skipped), (0, 1));
+    writer.commit();
+
+    let stats = db.checkpoint().expect("checkpoint");
+    assert_eq!((stats.pages_flushed, stats.pages_skipped), (1, 0));
+    let wal = db.wal_stats(Default::default()).unwrap();
+    assert_eq!(wal.total, wal.structure);
+    assert_eq!(db.get(&key(0)).expect("get"), Some(b"held".to_vec()));
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashSet, panic};
+
+use quickstep::{error::QSError, hash_index, invariants, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+const BUCKETS: u64 = 8;
+
+fn hash_config(path: &std::path::Path, buckets: u64) -> QuickStepConfig {
+    QuickStepConfig::new(path, 32, 256, 16).with_hash_index(buckets)
+}
+
+#[test]
+fn point_operations_skip_the_tree_and_survive_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("db");
+    {
+        let db = QuickStep::new(hash_config(&path, BUCKETS));
+        assert_eq!(db.hash_index_buckets(), Some(BUCKETS));
+        let mut tx = db.tx();
+        for idx in 0..64 {
+            let key = format!("user-{idx:04}");
+            tx.put(key.as_bytes(), format!("v{idx}").as_bytes())
+                .expect("put");
+        }
+        tx.delete(b"user-0007").expect("delete");
+        tx.commit();
+        assert_eq!(db.debug_root_level(), 0, "no inner nodes are built");
+
+        let buckets: HashSet<u64> = (0..64)
+            .map(|idx| {
+                hash_index::bucket_for(format!("user-{idx:04}").as_bytes(), BUCKETS).as_u64()
+            })
+            .collect();
+        assert!(buckets.len() > 1, "keys should spread over the buckets");
+        assert_eq!(
+            invariants::check_all(&db).expect("bucket invariants"),
+            BUCKETS as usize
+        );
+    }
+
+    let db = QuickStep::new(hash_config(&path, BUCKETS));
+    let mut tx = db.tx();
+    assert_eq!(tx.get(b"user-0003").unwrap(), Some(&b"v3"[..]));
+    assert_eq!(tx.get(b"user-0063").unwrap(), Some(&b"v63"[..]));
+    assert_eq!(tx.get(b"user-0007").unwrap(), None);
+    tx.commit();
+    assert_eq!(db.get(b"user-0042").unwrap(), Some(b"v42".to_vec()));
+
+    let first = db.range_iter(b"", b"\xff").next();
+    assert!(matches!(first, Some(Err(QSError::OrderedScanUnsupported))));
+}
+
+#[test]
+fn full_bucket_fails_the_write_instead_of_splitting() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(hash_config(&temp.path().join("db"), 1));
+    let payload = [3u8; 16];
+
+    let mut tx = db.tx();
+    let mut written = 0;
+    let err = loop {
+        assert!(written < 4096, "a single 4 KiB bucket should fill up");
+        match tx.put(format!("key-{written:05}").as_bytes(), &payload) {
+            Ok(()) => written += 1,
+            Err(err) => break err,
+        }
+    };
+    assert!(
+        matches!(err, QSError::HashBucketFull { bucket: 0 }),
+        "{err:?}"
+    );
+    assert_eq!(tx.get(b"key-00000").unwrap(), Some(&payload[..]));
+    tx.commit();
+}
+
+#[test]
+fn reopening_with_another_layout_panics() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("db");
+    drop(QuickStep::new(hash_config(&path, BUCKETS)));
+
+    let other_count = panic::catch_unwind(|| QuickStep::new(hash_config(&path, BUCKETS * 2)));
+    assert!(other_count.is_err());
+    let tree = panic::catch_unwind(|| QuickStep::new(QuickStepConfig::new(&path, 32, 256, 16)));
+    assert!(tree.is_err());
+}
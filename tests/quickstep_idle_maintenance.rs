@@ -0,0 +1,86 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{map_table::PageId, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn write_and_delete(db: &QuickStep) {
+    let mut tx = db.tx();
+    for idx in 0..8 {
+        tx.put(format!("key-{idx}").as_bytes(), b"value")
+            .expect("put");
+    }
+    tx.commit();
+    for idx in 0..4 {
+        assert!(db.delete(format!("key-{idx}").as_bytes()).unwrap());
+    }
+}
+
+/// Poll without touching the db, which would count as traffic
+fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn idle_maintenance_flushes_leaves_and_trims_the_wal() {
+    let temp = TempDir::new().expect("tempdir");
+    // the checkpoint thread would flush the same leaves, keep it out of the way
+    let config = || {
+        QuickStepConfig::new(temp.path(), 32, 256, 14)
+            .with_idle_maintenance(Duration::from_millis(20), 1000)
+            .with_checkpoint_interval(Duration::from_secs(3600))
+    };
+    {
+        let db = QuickStep::new(config());
+        write_and_delete(&db);
+        assert!(db.debug_wal_stats(None).unwrap().total_records > 0);
+
+        // the pass trims the WAL before it counts its leaf writes, wait for both
+        assert!(wait_for(|| {
+            db.debug_wal_stats(None).unwrap().total_records == 0 && db.idle_leaf_writes() >= 1
+        }));
+        let keys = db.debug_leaf_snapshot(PageId::from_u64(0)).unwrap().keys;
+        assert_eq!(keys.len(), 4, "tombstones were collected: {keys:?}");
+    }
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.get(b"key-0").unwrap(), None);
+    assert_eq!(db.get(b"key-7").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn idle_maintenance_is_bounded_by_its_write_rate() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        QuickStepConfig::new(temp.path(), 32, 256, 14)
+            .with_idle_maintenance(Duration::from_millis(20), 1)
+            .with_checkpoint_interval(Duration::from_secs(3600)),
+    );
+    write_and_delete(&db);
+
+    // the budget starts empty and refills at one leaf a second
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(db.idle_leaf_writes(), 0);
+    assert!(wait_for(|| db.idle_leaf_writes() == 1));
+}
+
+#[test]
+fn no_maintenance_unless_configured() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    write_and_delete(&db);
+    let records = db.debug_wal_stats(None).unwrap().total_records;
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(db.debug_wal_stats(None).unwrap().total_records, records);
+    assert_eq!(db.idle_leaf_writes(), 0);
+}
@@ -0,0 +1,46 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use std::time::Duration;
+
+fn open() -> QuickStep {
+    QuickStep::new(QuickStepConfig::in_memory(64, 256, 20))
+}
+
+#[test]
+fn in_memory_store_reads_back_writes_and_checkpoints() {
+    let db = open();
+    for i in 0..200u32 {
+        let mut tx = db.tx();
+        tx.put(format!("key-{i:04}").as_bytes(), &i.to_le_bytes())
+            .expect("put");
+        tx.commit();
+    }
+    assert!(db.delete(b"key-0007").expect("delete"));
+    db.debug_flush_root_leaf().expect("flush root leaf");
+
+    assert_eq!(
+        db.get(b"key-0199").expect("get"),
+        Some(199u32.to_le_bytes().to_vec())
+    );
+    assert_eq!(db.get(b"key-0007").expect("get deleted"), None);
+    let scanned = db.range_scan(b"key-0000", b"key-0010").expect("scan");
+    assert_eq!(scanned.len(), 9);
+}
+
+#[test]
+fn in_memory_stores_share_nothing() {
+    let first = open();
+    let second = open();
+    let mut tx = first.tx();
+    tx.put_with_ttl(b"alpha", b"one", Duration::from_secs(60))
+        .expect("put alpha");
+    tx.commit();
+
+    assert_eq!(first.get(b"alpha").expect("get"), Some(b"one".to_vec()));
+    assert!(first.ttl(b"alpha").is_some());
+    assert_eq!(second.get(b"alpha").expect("get"), None);
+    assert_eq!(second.expiring_keys(), 0);
+    drop(first);
+
+    // reopening starts from nothing
+    assert_eq!(open().get(b"alpha").expect("get"), None);
+}
@@ -0,0 +1,62 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn grow_until_split(db: &QuickStep, round: usize) {
+    let payload = vec![0u8; 64];
+    let mut i = 0usize;
+    while db.debug_root_leaf_parent().is_none() {
+        let mut tx = db.tx();
+        let key = format!("round-{round:02}-{i:04}");
+        tx.put(key.as_bytes(), &payload).expect("insert");
+        tx.commit();
+        i += 1;
+    }
+}
+
+fn merge_root_children(db: &QuickStep) {
+    let snapshot = db.debug_root_leaf_parent().expect("root should be inner");
+    assert_eq!(snapshot.children.len(), 2);
+    let (left, right) = (snapshot.children[0], snapshot.children[1]);
+    db.debug_truncate_leaf(left, 2, false).expect("shrink left");
+    db.debug_truncate_leaf(right, 2, false)
+        .expect("shrink right");
+    db.debug_merge_leaves(left, right)
+        .expect("merge under root");
+    assert!(db.debug_root_leaf_parent().is_none());
+}
+
+#[test]
+fn a_demoted_root_is_reused_by_the_next_split() {
+    let temp = TempDir::new().expect("tempdir");
+    // room for a single inner node
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 2, 256, 14));
+
+    for round in 0..4 {
+        grow_until_split(&db, round);
+        let stats = db.inner_node_stats();
+        assert_eq!(stats.allocated, 1, "round {round} took a fresh node");
+        assert_eq!(stats.free + stats.retired, 0);
+
+        merge_root_children(&db);
+        let stats = db.inner_node_stats();
+        assert_eq!(stats.retired, 1, "the old root waits to be reclaimed");
+    }
+}
+
+#[test]
+fn retired_nodes_are_counted_until_reused() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 8, 256, 14));
+
+    grow_until_split(&db, 0);
+    merge_root_children(&db);
+    let stats = db.inner_node_stats();
+    assert_eq!(stats.capacity, 7);
+    assert_eq!(stats.allocated, 1);
+    assert_eq!((stats.retired, stats.free), (1, 0));
+
+    grow_until_split(&db, 1);
+    let stats = db.inner_node_stats();
+    assert_eq!(stats.allocated, 1);
+    assert_eq!((stats.retired, stats.free), (0, 0));
+}
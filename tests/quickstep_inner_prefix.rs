@@ -0,0 +1,104 @@
+use quickstep::{invariants, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+const PREFIX: &str = "tenant-0042/region-eu-west/orders/2026/10/";
+
+fn new_db() -> QuickStep {
+    let temp = TempDir::new().expect("tempdir");
+    QuickStep::new(QuickStepConfig::new(temp.keep(), 64, 2048, 16))
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("{PREFIX}{idx:06}").into_bytes()
+}
+
+fn put(db: &QuickStep, key: &[u8]) {
+    let mut tx = db.tx();
+    tx.put(key, &[5u8; 1000]).expect("put");
+    tx.commit();
+}
+
+#[test]
+fn pivots_sharing_a_long_prefix_fit_one_leaf_parent() {
+    let db = new_db();
+    // whole, each pivot would take over 60 bytes and a node would hold at most 60 of them
+    let mut idx = 0;
+    while db
+        .debug_root_leaf_parent()
+        .is_none_or(|root| root.pivots.len() < 120)
+    {
+        put(&db, &key(idx));
+        idx += 1;
+        assert!(idx < 2_000, "root split before it held 120 pivots");
+    }
+    assert_eq!(db.debug_root_level(), 1);
+    assert!(db
+        .debug_root_leaf_parent()
+        .expect("leaf parent")
+        .pivots
+        .iter()
+        .all(|pivot| pivot.starts_with(PREFIX.as_bytes())));
+    invariants::check_all(&db).expect("tree invariants");
+    for idx in 0..idx {
+        assert!(db.get(&key(idx)).expect("get").is_some(), "missing {idx}");
+    }
+}
+
+#[test]
+fn keys_outside_the_prefix_split_full_nodes_and_read_back() {
+    let db = new_db();
+    let mut keys = Vec::new();
+    for idx in 0..600 {
+        keys.push(key(idx));
+        put(&db, keys.last().unwrap());
+    }
+    // pivots below and above every prefixed one shorten the prefix of a full node
+    for idx in 0..200 {
+        for outside in [format!("a/{idx:04}"), format!("z/{idx:04}")] {
+            keys.push(outside.into_bytes());
+            put(&db, keys.last().unwrap());
+        }
+    }
+
+    assert!(db.debug_root_level() >= 2);
+    invariants::check_all(&db).expect("tree invariants");
+    for key in &keys {
+        assert!(db.get(key).expect("get").is_some(), "missing {key:?}");
+    }
+    keys.sort();
+    let scanned: Vec<_> = db
+        .range_iter(b"a", b"zz")
+        .map(|record| record.expect("scan").0)
+        .collect();
+    assert_eq!(scanned, keys);
+}
+
+#[test]
+fn pivots_tied_in_their_first_bytes_route_keys_by_the_rest() {
+    let db = new_db();
+    // zero bytes and short keys pad the stored lookahead to the same bytes as longer keys
+    let mut keys: Vec<Vec<u8>> = (0..800u16)
+        .map(|idx| {
+            let mut key = vec![0u8; usize::from(idx % 5)];
+            key.extend_from_slice(&idx.to_be_bytes());
+            key.extend(std::iter::repeat_n(0u8, usize::from(idx % 3)));
+            key
+        })
+        .collect();
+    for key in &keys {
+        put(&db, key);
+    }
+
+    assert!(db.debug_root_level() >= 1);
+    invariants::check_all(&db).expect("tree invariants");
+    for key in &keys {
+        assert!(db.get(key).expect("get").is_some(), "missing {key:?}");
+    }
+    assert!(db.get(&[0, 0, 0, 0, 0, 0, 0]).expect("get").is_none());
+    keys.sort();
+    let scanned: Vec<_> = db
+        .range_iter(&[], &[0xff])
+        .map(|record| record.expect("scan").0)
+        .collect();
+    assert_eq!(scanned, keys);
+}
@@ -0,0 +1,111 @@
+use quickstep::{
+    error::QSError,
+    health::{HealthEvent, InnerTreeFullPolicy},
+    QuickStep, QuickStepConfig,
+};
+use std::path::Path;
+use tempfile::TempDir;
+
+const VALUE: [u8; 1000] = [3u8; 1000];
+
+fn config(path: &Path, policy: InnerTreeFullPolicy) -> QuickStepConfig {
+    // room for a single inner node: the root over the first leaves
+    QuickStepConfig::new(path, 2, 4096, 22).with_inner_tree_full_policy(policy)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:06}").into_bytes()
+}
+
+/// Put keys in order until one fails, returns how many went in and the error
+fn fill(db: &QuickStep, limit: u32) -> (u32, Option<QSError>) {
+    for idx in 0..limit {
+        let mut tx = db.tx();
+        if let Err(err) = tx.put(&key(idx), &VALUE) {
+            tx.abort();
+            return (idx, Some(err));
+        }
+        tx.commit();
+    }
+    (limit, None)
+}
+
+#[test]
+fn fail_policy_refuses_the_split_and_keeps_the_leaf_intact() {
+    let temp = TempDir::new().expect("tempdir");
+    let written = {
+        let db = QuickStep::new(config(temp.path(), InnerTreeFullPolicy::Fail));
+        let events = db.health_events();
+        let (written, err) = fill(&db, 20_000);
+        assert!(matches!(err, Some(QSError::TreeFull)), "got {err:?}");
+
+        let event = events.try_iter().next().expect("refused split reported");
+        assert_eq!(
+            event,
+            HealthEvent::InnerTreeFull {
+                capacity: 1,
+                needed: 2
+            }
+        );
+        // the full leaf was left as it was, every committed key is still there
+        for idx in 0..written {
+            assert_eq!(db.get(&key(idx)).expect("get"), Some(VALUE.to_vec()));
+        }
+        assert_eq!(db.get(&key(written)).expect("get"), None);
+        let stats = db.inner_node_stats();
+        assert_eq!((stats.allocated, stats.free), (1, 0));
+        written
+    };
+
+    let db = QuickStep::new(config(temp.path(), InnerTreeFullPolicy::Fail));
+    for idx in 0..written {
+        assert_eq!(db.get(&key(idx)).expect("get"), Some(VALUE.to_vec()));
+    }
+}
+
+#[test]
+fn grow_policy_raises_the_capacity_and_reports_it() {
+    let temp = TempDir::new().expect("tempdir");
+    let policy = InnerTreeFullPolicy::Grow {
+        max_inner_nodes: 64,
+    };
+    let db = QuickStep::new(config(temp.path(), policy));
+    let events = db.health_events();
+
+    // enough leaves to fill two leaf parents, prefix compression fits hundreds of pivots in each
+    let (written, err) = fill(&db, 2_000);
+    assert!(err.is_none(), "got {err:?} after {written} keys");
+    let grown: Vec<u32> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            HealthEvent::InnerTreeGrown { capacity } => Some(capacity),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(grown[..2], [3, 7]);
+    let stats = db.inner_node_stats();
+    assert!(stats.allocated > 1);
+    assert_eq!(stats.capacity, *grown.last().expect("grown"));
+    assert_eq!(db.get(&key(1_999)).expect("get"), Some(VALUE.to_vec()));
+}
+
+#[test]
+fn grow_policy_fails_once_the_maximum_is_reached() {
+    let temp = TempDir::new().expect("tempdir");
+    let policy = InnerTreeFullPolicy::Grow { max_inner_nodes: 4 };
+    let db = QuickStep::new(config(temp.path(), policy));
+    let events = db.health_events();
+
+    let (written, err) = fill(&db, 50_000);
+    assert!(matches!(err, Some(QSError::TreeFull)), "got {err:?}");
+    let events: Vec<_> = events.try_iter().collect();
+    assert!(events.contains(&HealthEvent::InnerTreeGrown { capacity: 3 }));
+    assert!(matches!(
+        events.last(),
+        Some(HealthEvent::InnerTreeFull { capacity: 3, .. })
+    ));
+    assert_eq!(
+        db.get(&key(written - 1)).expect("get"),
+        Some(VALUE.to_vec())
+    );
+}
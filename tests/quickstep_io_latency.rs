@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use quickstep::{latency::IoLatency, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn slow_disk() -> IoLatency {
+    IoLatency::new(
+        Duration::from_millis(1),
+        Duration::from_millis(1),
+        Duration::from_millis(5),
+    )
+}
+
+#[test]
+fn commits_wait_on_the_injected_sync_latency() {
+    let temp = TempDir::new().expect("tempdir");
+    let db =
+        QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14).with_io_latency(slow_disk()));
+    let before = db.io_latency_stats().expect("latency configured");
+
+    let started = Instant::now();
+    for idx in 0..4 {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx}").as_bytes(), b"value")
+            .expect("insert");
+        tx.commit();
+    }
+    let elapsed = started.elapsed();
+
+    let stats = db.io_latency_stats().unwrap();
+    assert!(stats.syncs >= before.syncs + 4, "{stats:?}");
+    assert!(stats.writes > before.writes);
+    // every commit synced the log once at least
+    assert!(elapsed >= Duration::from_millis(20), "{elapsed:?}");
+    assert!(stats.injected >= Duration::from_millis(20));
+}
+
+#[test]
+fn delayed_stores_keep_their_data_across_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || {
+        QuickStepConfig::new(temp.path(), 32, 256, 14)
+            .with_io_latency(slow_disk().with_jitter(Duration::from_millis(1)))
+    };
+    {
+        let db = QuickStep::new(config());
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"one").expect("insert");
+        tx.commit();
+    }
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.get(b"alpha").unwrap(), Some(b"one".to_vec()));
+    // reopening read the data file and the WAL through the delays
+    assert!(db.io_latency_stats().unwrap().reads > 0);
+}
+
+#[test]
+fn no_latency_means_no_stats() {
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 14));
+    assert_eq!(db.io_latency_stats(), None);
+
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 14).with_io_latency(slow_disk()));
+    let mut tx = db.tx();
+    tx.put(b"alpha", b"one").expect("insert");
+    tx.commit();
+    assert!(db.io_latency_stats().unwrap().writes > 0);
+}
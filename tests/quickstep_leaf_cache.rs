@@ -0,0 +1,75 @@
+use quickstep::{io_engine::DiskLeaf, leaf_cache::LeafCache, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn reopen_cold(db_path: &std::path::Path, cache_pages: usize) -> QuickStep {
+    {
+        let db = QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14));
+        let mut tx = db.tx();
+        for idx in 0..16 {
+            let key = format!("key-{idx:04}");
+            tx.put(key.as_bytes(), b"value").expect("insert");
+        }
+        tx.commit();
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+    QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14).with_leaf_cache_pages(cache_pages))
+}
+
+#[test]
+fn repeated_cold_reads_hit_leaf_cache() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = reopen_cold(&temp.path().join("db"), 8);
+    let before = db.leaf_cache_stats();
+
+    // distinct keys, a repeated one may be copied into a mini-page and stop reading the leaf
+    for idx in 3..7 {
+        let key = format!("key-{idx:04}");
+        let mut tx = db.tx();
+        assert_eq!(tx.get(key.as_bytes()).unwrap(), Some(&b"value"[..]));
+        tx.commit();
+    }
+
+    let stats = db.leaf_cache_stats();
+    assert_eq!(stats.capacity, 8);
+    assert!(stats.pages >= 1, "root leaf should be cached");
+    assert!(
+        stats.hits >= before.hits + 3,
+        "reads after the first should be served from the cache: {stats:?}"
+    );
+    assert!(stats.hit_rate() > 0.0);
+}
+
+#[test]
+fn disabled_leaf_cache_counts_nothing() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = reopen_cold(&temp.path().join("db"), 0);
+
+    for _ in 0..4 {
+        let mut tx = db.tx();
+        assert!(tx.get(b"key-0003").unwrap().is_some());
+        tx.commit();
+    }
+
+    let stats = db.leaf_cache_stats();
+    assert_eq!(stats.capacity, 0);
+    assert_eq!(stats.pages, 0);
+    assert_eq!(stats.hits + stats.misses, 0);
+    assert_eq!(stats.hit_rate(), 0.0);
+}
+
+#[test]
+fn leaf_cache_evicts_least_recently_used() {
+    let cache = LeafCache::new(2);
+    cache.insert(1, &DiskLeaf::zeroed());
+    cache.insert(2, &DiskLeaf::zeroed());
+    assert!(cache.get(1).is_some());
+
+    cache.insert(3, &DiskLeaf::zeroed());
+    assert!(cache.get(2).is_none(), "page 2 was least recently used");
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(3).is_some());
+
+    let stats = cache.stats();
+    assert_eq!(stats.pages, 2);
+    assert_eq!((stats.hits, stats.misses), (3, 1));
+}
@@ -3,11 +3,11 @@ use tempfile::TempDir;
 
 fn new_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
-    let config = QuickStepConfig::new(temp.into_path(), 32, 256, 14);
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14);
     QuickStep::new(config)
 }
 
-fn fill_until_split(db: &QuickStep, inserts: usize, payload: &[u8]) {
+fn fill_until_split(db: &QuickStep, inserts: usize, payload: &[u8]) -> usize {
     let mut tx = db.tx();
     let mut i = 0usize;
     while debug::split_requests() == 0 && i < inserts {
@@ -16,9 +16,11 @@ fn fill_until_split(db: &QuickStep, inserts: usize, payload: &[u8]) {
         i += 1;
     }
     tx.commit();
+    i
 }
 
 fn fill_until_children(db: &QuickStep, target_children: usize, payload: &[u8]) {
+    let mut round = 0usize;
     while db
         .debug_root_leaf_parent()
         .map(|snap| snap.children.len())
@@ -27,10 +29,11 @@ fn fill_until_children(db: &QuickStep, target_children: usize, payload: &[u8]) {
     {
         let mut tx = db.tx();
         for i in 0..32 {
-            let key = format!("grow-{i:04}-{}", debug::split_requests());
+            let key = format!("grow-{round:04}-{i:04}");
             tx.put(key.as_bytes(), payload).expect("insert");
         }
         tx.commit();
+        round += 1;
     }
 }
 
@@ -145,14 +148,14 @@ fn delete_api_triggers_auto_merge() {
     let db = new_db();
     let payload = vec![0u8; 128];
 
-    fill_until_split(&db, 256, &payload);
+    let inserted = fill_until_split(&db, 256, &payload);
 
     let snapshot = db
         .debug_root_leaf_parent()
         .expect("root should be inner after split");
     let pivot = snapshot.pivots[0].clone();
 
-    for i in 0..256 {
+    for i in 0..inserted {
         let key = format!("key-{i:04}");
         if key.as_bytes() >= pivot.as_slice() {
             assert!(db.delete(key.as_bytes()).expect("delete attempt"));
@@ -204,3 +207,39 @@ fn cascading_merge_reduces_deeper_tree() {
         "expect multiple merges to cascade"
     );
 }
+
+#[test]
+fn merged_away_mini_page_is_free_listed_and_reused() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 18));
+    let payload = vec![0u8; 64];
+
+    fill_until_children(&db, 2, &payload);
+    let snapshot = db
+        .debug_root_leaf_parent()
+        .expect("root should be inner after split");
+    let (left, right) = (snapshot.children[0], snapshot.children[1]);
+    db.debug_truncate_leaf(left, 3, false).expect("shrink left");
+    db.debug_truncate_leaf(right, 2, false)
+        .expect("shrink right");
+
+    let cached = db.cached_leaves();
+    let free_listed = db.free_listed_slots();
+    db.debug_merge_leaves(left, right).expect("merge siblings");
+    assert_eq!(db.cached_leaves(), cached - 1);
+    assert_eq!(db.free_listed_slots(), free_listed + 1);
+
+    // the survivor comes back into a small mini-page and grows into the freed full-size
+    // slot, leaving only its small slot behind; growing from the tail would leave both
+    assert!(db.demote(left).expect("demote survivor"));
+    let back = vec![7u8; 1790];
+    let mut tx = db.tx();
+    tx.put(b"key-0000", &back).expect("put into survivor");
+    tx.commit();
+    let free_listed = db.free_listed_slots();
+    let mut tx = db.tx();
+    tx.put(b"key-0001", &back).expect("grow survivor");
+    tx.commit();
+    assert_eq!(db.free_listed_slots(), free_listed);
+    assert_eq!(db.get(b"key-0000").expect("get"), Some(back));
+}
@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use quickstep::{
+    error::QSError,
+    merge::{SetUnionMerge, U64AddMerge},
+    wal::{WalManager, WalOp},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn counter_db(path: &Path) -> QuickStep {
+    QuickStep::new(
+        QuickStepConfig::new(path, 32, 256, 14)
+            .with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+            .with_merge_operator(U64AddMerge),
+    )
+}
+
+fn counter(db: &QuickStep, key: &[u8]) -> Option<u64> {
+    db.get(key)
+        .unwrap()
+        .map(|value| U64AddMerge::decode(&value))
+}
+
+#[test]
+fn counter_merges_accumulate_and_roll_back() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = counter_db(temp.path());
+    {
+        let mut tx = db.tx();
+        for _ in 0..10 {
+            tx.merge(b"hits", &3u64.to_le_bytes()).expect("merge");
+        }
+        // later merges in the same transaction see the earlier ones
+        assert_eq!(tx.get(b"hits").unwrap(), Some(&30u64.to_le_bytes()[..]));
+        tx.put(b"base", &100u64.to_le_bytes()).expect("put");
+        tx.merge(b"base", &1u64.to_le_bytes())
+            .expect("merge onto put");
+        tx.commit();
+    }
+    assert_eq!(counter(&db, b"hits"), Some(30));
+    assert_eq!(counter(&db, b"base"), Some(101));
+
+    {
+        let mut tx = db.tx();
+        tx.merge(b"hits", &5u64.to_le_bytes()).expect("merge");
+        tx.merge(b"fresh", &5u64.to_le_bytes()).expect("merge");
+        tx.abort();
+    }
+    assert_eq!(counter(&db, b"hits"), Some(30));
+    assert_eq!(counter(&db, b"fresh"), None);
+
+    let plain = QuickStep::new(QuickStepConfig::new(temp.path().join("plain"), 32, 256, 14));
+    let mut tx = plain.tx();
+    assert!(matches!(
+        tx.merge(b"hits", &1u64.to_le_bytes()),
+        Err(QSError::NoMergeOperator)
+    ));
+}
+
+#[test]
+fn merge_records_replay_after_restart() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = counter_db(temp.path());
+        for _ in 0..4 {
+            let mut tx = db.tx();
+            tx.merge(b"total", &7u64.to_le_bytes()).expect("merge");
+            tx.commit();
+        }
+        let mut aborted = db.tx();
+        aborted
+            .merge(b"total", &1000u64.to_le_bytes())
+            .expect("merge");
+        aborted.abort();
+        // close without flushing so the reopen replays the merge records
+        db.debug_close_without_flush();
+    }
+
+    // the records carry the operands with the record they leave, not resolved sums
+    let wal = WalManager::open(&temp.path().join("quickstep.wal")).expect("open wal");
+    let merges: Vec<_> = wal
+        .records()
+        .into_iter()
+        .filter_map(|record| match record.op {
+            WalOp::Merge { operand, value } => Some((U64AddMerge::decode(&operand), value)),
+            _ => None,
+        })
+        .collect();
+    let operands: Vec<_> = merges.iter().map(|(operand, _)| *operand).collect();
+    assert_eq!(operands, vec![7, 7, 7, 7, 1000]);
+    assert!(merges
+        .windows(2)
+        .all(|pair| pair[0].1.len() < pair[1].1.len()));
+    drop(wal);
+
+    let db = counter_db(temp.path());
+    assert_eq!(counter(&db, b"total"), Some(28));
+}
+
+#[test]
+fn pending_operands_fold_on_flush() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = counter_db(temp.path());
+        let mut tx = db.tx();
+        for _ in 0..5 {
+            tx.merge(b"total", &2u64.to_le_bytes()).expect("merge");
+        }
+        let info = tx.entry_info(b"total").unwrap().expect("total present");
+        assert!(info.merge_pending);
+        tx.commit();
+        assert_eq!(counter(&db, b"total"), Some(10));
+        db.checkpoint().expect("checkpoint");
+    }
+
+    let db = counter_db(temp.path());
+    let mut tx = db.tx();
+    let info = tx.entry_info(b"total").unwrap().expect("total present");
+    assert!(!info.merge_pending);
+    assert_eq!(info.value_len, 8);
+    assert_eq!(tx.get(b"total").unwrap(), Some(&10u64.to_le_bytes()[..]));
+}
+
+#[test]
+fn set_union_merges_members() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        QuickStepConfig::new(temp.path(), 32, 256, 14).with_merge_operator(SetUnionMerge),
+    );
+    let mut tx = db.tx();
+    tx.merge(b"tags", &SetUnionMerge::encode([&b"red"[..], b"blue"]))
+        .expect("merge");
+    tx.merge(b"tags", &SetUnionMerge::encode([&b"blue"[..], b"green"]))
+        .expect("merge");
+    tx.commit();
+
+    let members = SetUnionMerge::decode(&db.get(b"tags").unwrap().expect("tags"));
+    assert_eq!(
+        members,
+        vec![b"blue".to_vec(), b"green".to_vec(), b"red".to_vec()]
+    );
+}
@@ -0,0 +1,119 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{
+    metrics::{MetricFamily, MetricKind},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn write(db: &QuickStep, count: u32) {
+    let mut tx = db.tx();
+    for idx in 0..count {
+        tx.put(format!("key-{idx:05}").as_bytes(), b"value")
+            .expect("put");
+    }
+    tx.commit();
+}
+
+/// The value of the sample `line` starts with
+fn sample(text: &str, line: &str) -> f64 {
+    text.lines()
+        .find_map(|l| l.strip_prefix(line)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {line} in\n{text}"))
+        .parse()
+        .expect("sample value")
+}
+
+#[test]
+fn metrics_text_is_prometheus_exposition() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 16));
+    write(&db, 20);
+    db.get(b"key-00003").expect("get");
+
+    let text = db.metrics_text().unwrap();
+    assert!(text.contains("# TYPE quickstep_op_latency_seconds summary\n"));
+    assert!(text.contains("# TYPE quickstep_cache_hits_total counter\n"));
+    assert!(text.contains("# TYPE quickstep_wal_bytes gauge\n"));
+    assert_eq!(
+        sample(&text, r#"quickstep_op_latency_seconds_count{op="put"}"#),
+        20.0
+    );
+    assert_eq!(
+        sample(&text, r#"quickstep_op_latency_seconds_count{op="get"}"#),
+        1.0
+    );
+    let p99 = sample(
+        &text,
+        r#"quickstep_op_latency_seconds{op="put",quantile="0.99"}"#,
+    );
+    assert!(p99 > 0.0 && p99 < 10.0, "{p99}");
+    assert!(sample(&text, "quickstep_wal_records") > 0.0);
+
+    // every sample belongs to the family declared above it
+    let mut family = "";
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("# TYPE ") {
+            family = rest.split(' ').next().expect("family name");
+        } else if !line.starts_with('#') {
+            assert!(line.starts_with(family), "{line} outside {family}");
+        }
+    }
+}
+
+#[test]
+fn the_exporter_is_handed_metrics_at_its_interval() {
+    let temp = TempDir::new().expect("tempdir");
+    let exported: Arc<Mutex<Vec<Vec<MetricFamily>>>> = Arc::default();
+    let sink = Arc::clone(&exported);
+    let config = QuickStepConfig::new(temp.path(), 32, 256, 16).with_metrics_exporter(
+        Duration::from_millis(10),
+        Arc::new(move |metrics: &[MetricFamily]| {
+            sink.lock().expect("sink").push(metrics.to_vec());
+        }),
+    );
+    assert!(config.has_metrics_exporter());
+    let db = QuickStep::new(config);
+    write(&db, 5);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let latest = loop {
+        let latest = exported.lock().expect("sink").last().cloned();
+        let puts = latest.as_ref().and_then(|metrics| {
+            let latency = metrics
+                .iter()
+                .find(|family| family.name == "quickstep_op_latency_seconds")?;
+            assert_eq!(latency.kind, MetricKind::Summary);
+            latency
+                .samples
+                .iter()
+                .find(|s| {
+                    s.name == "quickstep_op_latency_seconds_count"
+                        && s.labels.contains(&("op", "put".to_string()))
+                })
+                .map(|s| s.value)
+        });
+        if puts == Some(5.0) {
+            break latest.expect("exported");
+        }
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(
+        quickstep::metrics::to_text(&latest).lines().next(),
+        db.metrics_text().unwrap().lines().next()
+    );
+
+    drop(db);
+    let exports = exported.lock().expect("sink").len();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(
+        exported.lock().expect("sink").len(),
+        exports,
+        "exported after drop"
+    );
+}
@@ -0,0 +1,123 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(temp: &TempDir) -> QuickStepConfig {
+    QuickStepConfig::new(temp.path(), 32, 256, 14)
+}
+
+fn key(i: usize) -> Vec<u8> {
+    format!("user/{i:04}").into_bytes()
+}
+
+/// Upgrade a `v1:` value to `v2:`, keeping values already upgraded
+fn upgrade(key: &[u8], value: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let rest = value.strip_prefix(b"v1:")?;
+    Some((key.to_vec(), [b"v2:", rest].concat()))
+}
+
+#[test]
+fn migration_moves_keys_and_leaves_the_rest() {
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 14));
+    let mut tx = db.tx();
+    for i in 0..100 {
+        tx.put(format!("old/{i:04}").as_bytes(), format!("{i}").as_bytes())
+            .expect("put");
+    }
+    tx.put(b"other", b"untouched").expect("put");
+    tx.commit();
+
+    let stats = db
+        .migrate_range_in_batches(b"old/", b"old0", 16, |key, value| {
+            let i: usize = std::str::from_utf8(value).unwrap().parse().unwrap();
+            // odd entries keep their encoding
+            i.is_multiple_of(2).then(|| {
+                let new_key = [b"new/", &key[4..]].concat();
+                (new_key, (i as u64).to_be_bytes())
+            })
+        })
+        .expect("migrate");
+    assert_eq!(stats.scanned, 100);
+    assert_eq!(stats.rewritten, 50);
+    assert_eq!(stats.batches, 7);
+    assert!(!stats.resumed);
+
+    for i in 0..100usize {
+        let old = db.get(format!("old/{i:04}").as_bytes()).expect("get");
+        let new = db.get(format!("new/{i:04}").as_bytes()).expect("get");
+        if i.is_multiple_of(2) {
+            assert_eq!(old, None);
+            assert_eq!(new, Some((i as u64).to_be_bytes().to_vec()));
+        } else {
+            assert_eq!(old, Some(format!("{i}").into_bytes()));
+            assert_eq!(new, None);
+        }
+    }
+    assert_eq!(db.get(b"other").expect("get"), Some(b"untouched".to_vec()));
+}
+
+#[test]
+fn interrupted_migration_resumes_after_its_last_batch() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(&temp));
+        let mut tx = db.tx();
+        for i in 0..60 {
+            tx.put(&key(i), format!("v1:{i}").as_bytes()).expect("put");
+        }
+        tx.commit();
+
+        let mut seen = 0;
+        let interrupted = panic::catch_unwind(AssertUnwindSafe(|| {
+            db.migrate_range_in_batches(b"user/", b"user0", 10, |key, value| {
+                seen += 1;
+                assert!(seen <= 25, "interrupted");
+                upgrade(key, value)
+            })
+        }));
+        assert!(interrupted.is_err());
+        // the batch cut short rolled back
+        assert_eq!(db.get(&key(19)).unwrap(), Some(b"v2:19".to_vec()));
+        assert_eq!(db.get(&key(20)).unwrap(), Some(b"v1:20".to_vec()));
+        // the progress is kept in the store itself
+        assert!(!has_sidecar(&temp));
+    }
+
+    let db = QuickStep::new(config(&temp));
+    let mut first = None;
+    let stats = db
+        .migrate_range_in_batches(b"user/", b"user0", 10, |key, value| {
+            first.get_or_insert_with(|| key.to_vec());
+            upgrade(key, value)
+        })
+        .expect("migrate");
+    assert!(stats.resumed);
+    assert_eq!(first, Some(key(20)));
+    assert_eq!(stats.scanned, 40);
+    assert_eq!(stats.rewritten, 40);
+    for i in 0..60 {
+        assert_eq!(
+            db.get(&key(i)).unwrap(),
+            Some(format!("v2:{i}").into_bytes())
+        );
+    }
+
+    // a finished migration leaves no progress behind and starts over when run again
+    let stats = db
+        .migrate_range(b"user/", b"user0", upgrade)
+        .expect("migrate");
+    assert!(!stats.resumed);
+    assert_eq!(stats.scanned, 60);
+    assert_eq!(stats.rewritten, 0);
+}
+
+fn has_sidecar(temp: &TempDir) -> bool {
+    temp.path().read_dir().unwrap().any(|entry| {
+        entry
+            .unwrap()
+            .path()
+            .extension()
+            .is_some_and(|ext| ext == "migrate")
+    })
+}
@@ -0,0 +1,110 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+const FULL_PAGE: usize = 4096;
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key-{i:04}").into_bytes()
+}
+
+fn value(i: usize) -> Vec<u8> {
+    vec![(i % 251) as u8; 300]
+}
+
+/// A store whose leaves are all written back and out of the cache
+fn cold(temp: &TempDir, cache_size_lg: usize) -> QuickStep {
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, cache_size_lg));
+    let mut tx = db.tx();
+    for i in 0..40 {
+        tx.put(&key(i), &value(i)).expect("insert");
+    }
+    tx.commit();
+    db.demote_range(b"", b"\xff").expect("demote");
+    assert_eq!(db.cached_leaves(), 0);
+    db
+}
+
+#[test]
+fn cold_leaf_promotes_small_and_grows_to_a_full_page() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = cold(&temp, 20);
+    let first = db.debug_root_leaf_parent().expect("split root").children[0];
+    let leaves = db.debug_root_leaf_parent().unwrap().children.len();
+
+    let mut tx = db.tx();
+    tx.put(b"key-0000-00", b"hot").expect("put");
+    tx.commit();
+    let mut cached = db
+        .debug_leaf_snapshot(first)
+        .unwrap()
+        .cached_bytes
+        .expect("the put caches the leaf");
+    assert!(cached < FULL_PAGE, "promoted into {cached} bytes");
+    // the records still on disk read through the small mini-page
+    for i in 0..40 {
+        assert_eq!(db.get(&key(i)).expect("get"), Some(value(i)));
+    }
+
+    let mut written = 1;
+    while cached < FULL_PAGE {
+        let mut tx = db.tx();
+        tx.put(format!("key-0000-{written:02}").as_bytes(), &[7u8; 40])
+            .expect("put");
+        tx.commit();
+        written += 1;
+        let grown = db.debug_leaf_snapshot(first).unwrap().cached_bytes.unwrap();
+        assert!(grown >= cached, "mini-page shrank from {cached} to {grown}");
+        cached = grown;
+        // the leaf only splits once it is full-size
+        if cached < FULL_PAGE {
+            assert_eq!(db.debug_root_leaf_parent().unwrap().children.len(), leaves);
+        }
+    }
+
+    assert_eq!(db.get(b"key-0000-00").expect("get"), Some(b"hot".to_vec()));
+    for n in 1..written {
+        let key = format!("key-0000-{n:02}");
+        assert_eq!(db.get(key.as_bytes()).expect("get"), Some(vec![7u8; 40]));
+    }
+    for i in 0..40 {
+        assert_eq!(db.get(&key(i)).expect("get"), Some(value(i)));
+    }
+}
+
+#[test]
+fn small_mini_page_survives_demotion() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = cold(&temp, 20);
+    let first = db.debug_root_leaf_parent().expect("split root").children[0];
+
+    let mut tx = db.tx();
+    tx.put(b"key-0000-00", b"hot").expect("put");
+    tx.put(&key(1), b"updated").expect("update");
+    tx.commit();
+    assert!(db.debug_leaf_snapshot(first).unwrap().cached_bytes.unwrap() < FULL_PAGE);
+
+    assert!(db.demote(first).expect("demote"));
+    assert_eq!(db.get(b"key-0000-00").expect("get"), Some(b"hot".to_vec()));
+    assert_eq!(db.get(&key(1)).expect("get"), Some(b"updated".to_vec()));
+    drop(db);
+
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 20));
+    assert_eq!(db.get(b"key-0000-00").expect("get"), Some(b"hot".to_vec()));
+    assert_eq!(db.get(&key(1)).expect("get"), Some(b"updated".to_vec()));
+    assert_eq!(db.get(&key(0)).expect("get"), Some(value(0)));
+}
+
+#[test]
+fn tiny_buffer_promotes_full_pages() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = cold(&temp, 13);
+    let first = db.debug_root_leaf_parent().expect("split root").children[0];
+
+    let mut tx = db.tx();
+    tx.put(b"key-0000-00", b"hot").expect("put");
+    tx.commit();
+    assert_eq!(
+        db.debug_leaf_snapshot(first).unwrap().cached_bytes,
+        Some(FULL_PAGE)
+    );
+}
@@ -1,5 +1,7 @@
-use quickstep::{QuickStep, QuickStepConfig};
-use std::fs;
+use quickstep::{
+    auth::MetadataAuth, error::QSError, io_engine::IoEngine, QuickStep, QuickStepConfig,
+};
+use std::{fs, io::ErrorKind};
 use tempfile::TempDir;
 
 #[test]
@@ -19,3 +21,114 @@ fn quickstep_new_smoke() {
         "expected quickstep.db to be created"
     );
 }
+
+#[test]
+fn open_reports_why_a_store_cannot_be_opened() {
+    let temp_dir = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp_dir.path(), 32, 128, 12);
+
+    // one handle at a time, the next open succeeds once the first is dropped
+    let first = QuickStep::open(config()).expect("open");
+    assert!(matches!(
+        QuickStep::open(config()),
+        Err(QSError::AlreadyLocked { path }) if path == temp_dir.path().join("quickstep.db")
+    ));
+    drop(first);
+    drop(QuickStep::open(config()).expect("reopen"));
+
+    // a manifest edited behind the log's back
+    let wal_path = temp_dir.path().join("quickstep.wal");
+    let mut wal = fs::read(&wal_path).expect("read wal");
+    wal[9] ^= 0x01;
+    fs::write(&wal_path, wal).expect("write wal");
+    assert!(matches!(
+        QuickStep::open(config()),
+        Err(QSError::WalCorrupt { .. })
+    ));
+
+    let other = TempDir::new().expect("tempdir");
+    fs::write(other.path().join("quickstep.db"), [1u8; 100]).expect("write data file");
+    assert!(matches!(
+        QuickStep::open(QuickStepConfig::new(other.path(), 32, 128, 12)),
+        Err(QSError::DataFileTooSmall {
+            len: 100,
+            min: 4096
+        })
+    ));
+}
+
+#[test]
+fn a_data_file_in_use_is_only_read_through_the_read_only_bypass() {
+    let temp_dir = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp_dir.path(), 32, 128, 12));
+    let mut tx = db.tx();
+    tx.put(b"key", b"value").expect("put");
+    tx.commit();
+    db.debug_flush_root_leaf().expect("flush root leaf");
+
+    let data_path = temp_dir.path().join("quickstep.db");
+    let err = IoEngine::open(&data_path).err().expect("locked");
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    let reader = IoEngine::open_read_only(&data_path, &MetadataAuth::default()).expect("bypass");
+    let leaf = reader.get_page(0).expect("read root leaf");
+    assert_eq!(leaf.as_ref().get(b"key"), Some(&b"value"[..]));
+    assert!(reader.write_txn_id_mark(1 << 20).is_err());
+
+    // the lock goes with the store
+    drop(db);
+    IoEngine::open(&data_path).expect("unlocked");
+}
+
+#[test]
+fn a_store_in_use_opens_read_only_without_writing_its_files() {
+    let temp_dir = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp_dir.path(), 32, 128, 12);
+    let db = QuickStep::new(config());
+    let mut tx = db.tx();
+    tx.put(b"flushed", b"on disk").expect("put");
+    tx.commit();
+    db.debug_flush_root_leaf().expect("flush root leaf");
+    let mut tx = db.tx();
+    tx.put(b"logged", b"in the wal").expect("put");
+    tx.commit();
+
+    let files = || {
+        let mut files: Vec<_> = fs::read_dir(temp_dir.path())
+            .expect("list store files")
+            .map(|entry| {
+                let path = entry.expect("store file").path();
+                (path.clone(), fs::read(path).expect("read store file"))
+            })
+            .collect();
+        files.sort();
+        files
+    };
+    let before = files();
+    let reader = QuickStep::open(config().with_read_only()).expect("open read-only");
+    assert!(reader.is_read_only());
+    let mut tx = reader.tx();
+    assert_eq!(tx.get(b"flushed").expect("get"), Some(&b"on disk"[..]));
+    assert_eq!(tx.get(b"logged").expect("get"), Some(&b"in the wal"[..]));
+    assert!(matches!(tx.put(b"new", b"value"), Err(QSError::ReadOnly)));
+    assert!(matches!(tx.delete(b"flushed"), Err(QSError::ReadOnly)));
+    tx.commit();
+    assert!(matches!(reader.checkpoint(), Err(QSError::ReadOnly)));
+    assert!(matches!(reader.compact(), Err(QSError::ReadOnly)));
+    reader.close().expect("close");
+    assert!(files() == before, "a read-only open wrote to the store");
+
+    // the writer keeps its lock and carries on
+    let mut tx = db.tx();
+    tx.put(b"new", b"value").expect("put");
+    tx.commit();
+}
+
+#[test]
+fn a_read_only_open_needs_a_store_on_disk() {
+    let temp_dir = TempDir::new().expect("tempdir");
+    let missing =
+        QuickStep::open(QuickStepConfig::new(temp_dir.path(), 32, 128, 12).with_read_only());
+    assert!(matches!(missing, Err(QSError::Io(err)) if err.kind() == ErrorKind::NotFound));
+    let in_memory = QuickStep::open(QuickStepConfig::in_memory(32, 128, 12).with_read_only());
+    assert!(matches!(in_memory, Err(QSError::Io(err)) if err.kind() == ErrorKind::InvalidInput));
+}
@@ -0,0 +1,267 @@
+use std::{
+    fs, mem,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{
+    limits::MAX_INLINE_VALUE_LEN,
+    merge::MergeOperator,
+    overflow::{OverflowPointer, PAGE_PAYLOAD},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 16)
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:03}").into_bytes()
+}
+
+fn long_value(idx: usize, len: usize) -> Vec<u8> {
+    (0..len).map(|pos| (pos * 31 + idx) as u8).collect()
+}
+
+struct Append;
+
+impl MergeOperator for Append {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+        let mut value = existing.unwrap_or_default().to_vec();
+        value.extend_from_slice(operand);
+        value
+    }
+}
+
+/// Values of every shape: short, just past the inline limit, several pages long, and one
+/// that looks like an overflow pointer
+fn values() -> Vec<Vec<u8>> {
+    let mut values: Vec<Vec<u8>> = (0..12)
+        .map(|idx| match idx % 4 {
+            0 => long_value(idx, 16),
+            1 => long_value(idx, MAX_INLINE_VALUE_LEN + 1),
+            2 => long_value(idx, 3 * PAGE_PAYLOAD + 17),
+            _ => long_value(idx, 20_000 + idx),
+        })
+        .collect();
+    let lookalike = OverflowPointer {
+        head: 2,
+        len: 9,
+        crc: 7,
+    };
+    values.push(lookalike.encode().to_vec());
+    values
+}
+
+fn assert_values(db: &QuickStep, values: &[Vec<u8>]) {
+    let mut tx = db.tx();
+    for (idx, value) in values.iter().enumerate() {
+        assert_eq!(db.get(&key(idx)).expect("get").as_ref(), Some(value));
+        assert_eq!(tx.get(&key(idx)).expect("tx get"), Some(value.as_slice()));
+        let pinned = tx.get_pinned(&key(idx)).expect("pinned").expect("present");
+        assert_eq!(&*pinned, value.as_slice());
+        let info = tx.entry_info(&key(idx)).expect("info").expect("present");
+        assert_eq!(info.value_len, value.len());
+        assert_eq!(
+            info.overflow,
+            value.len() > MAX_INLINE_VALUE_LEN || idx == 12
+        );
+    }
+    tx.commit();
+
+    let scanned: Vec<_> = db
+        .range_iter(&key(0), &key(values.len()))
+        .map(|record| record.expect("scan").1)
+        .collect();
+    assert_eq!(scanned, values);
+}
+
+#[test]
+fn long_values_read_back_whole_before_and_after_reopening() {
+    let temp = TempDir::new().expect("tempdir");
+    let values = values();
+    {
+        let db = QuickStep::new(config(temp.path()));
+        for (idx, value) in values.iter().enumerate() {
+            let mut tx = db.tx();
+            tx.put(&key(idx), value).expect("put");
+            tx.commit();
+        }
+        assert_values(&db, &values);
+    }
+
+    // recovered from the log, then with the leaves flushed
+    let db = QuickStep::new(config(temp.path()));
+    assert_values(&db, &values);
+    db.checkpoint().expect("checkpoint");
+    drop(db);
+    let db = QuickStep::new(config(temp.path()));
+    assert_values(&db, &values);
+}
+
+#[test]
+fn replaced_deleted_and_aborted_values_give_their_pages_back() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let (first, second) = (
+        long_value(1, 2 * PAGE_PAYLOAD + 1),
+        long_value(2, 3 * PAGE_PAYLOAD),
+    );
+    let used = db.capacity_stats().disk_pages_used;
+
+    let mut tx = db.tx();
+    tx.put(b"key", &first).expect("put");
+    tx.commit();
+    let stats = db.capacity_stats();
+    assert_eq!(
+        (stats.disk_pages_used, stats.disk_pages_free),
+        (used + 3, 0)
+    );
+
+    // the aborted write's chain is freed, the committed one kept
+    let mut tx = db.tx();
+    tx.put(b"key", &second).expect("put");
+    tx.abort();
+    assert_eq!(db.get(b"key").expect("get"), Some(first));
+    let stats = db.capacity_stats();
+    assert_eq!(
+        (stats.disk_pages_used, stats.disk_pages_free),
+        (used + 6, 3)
+    );
+
+    // overwriting reuses those pages and frees the old chain once committed
+    let mut tx = db.tx();
+    tx.put(b"key", &second).expect("put");
+    tx.commit();
+    assert_eq!(db.get(b"key").expect("get"), Some(second));
+    let stats = db.capacity_stats();
+    assert_eq!(
+        (stats.disk_pages_used, stats.disk_pages_free),
+        (used + 6, 3)
+    );
+
+    assert!(db.delete(b"key").expect("delete"));
+    assert_eq!(db.get(b"key").expect("get"), None);
+    assert_eq!(db.capacity_stats().disk_pages_free, 6);
+}
+
+#[test]
+fn chains_of_crashed_and_expired_transactions_are_freed() {
+    let temp = TempDir::new().expect("tempdir");
+    let value = long_value(1, 2 * PAGE_PAYLOAD + 1);
+    let used = {
+        let db = QuickStep::new(config(temp.path()));
+        let used = db.capacity_stats().disk_pages_used;
+        let mut tx = db.tx();
+        tx.put(b"key", &value).expect("put");
+        mem::forget(tx);
+        db.debug_close_without_flush();
+        used
+    };
+
+    // the put is undone on open and nothing points at its chain
+    let db = QuickStep::new(config(temp.path()));
+    assert_eq!(db.last_recovery_report().overflow_chains_freed, 1);
+    assert_eq!(db.get(b"key").expect("get"), None);
+    let stats = db.capacity_stats();
+    assert_eq!(
+        (stats.disk_pages_used, stats.disk_pages_free),
+        (used + 3, 3)
+    );
+    drop(db);
+
+    // an expired transaction's chain is freed with its rollback
+    let db = QuickStep::new(config(temp.path()).with_txn_timeout(Duration::from_millis(50)));
+    assert_eq!(db.last_recovery_report().overflow_chains_freed, 0);
+    let mut tx = db.tx();
+    tx.put(b"key", &value).expect("put");
+    mem::forget(tx);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while db.expired_txns() == 0 {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(db.get(b"key").expect("get"), None);
+    let stats = db.capacity_stats();
+    assert_eq!(
+        (stats.disk_pages_used, stats.disk_pages_free),
+        (used + 3, 3)
+    );
+}
+
+#[test]
+fn merged_and_shipped_long_values_arrive_whole() {
+    let (primary_dir, follower_dir) = (
+        TempDir::new().expect("tempdir"),
+        TempDir::new().expect("tempdir"),
+    );
+    let config = |path: &Path| {
+        config(path)
+            .with_wal_tail_retention(100_000)
+            .with_merge_operator(Append)
+    };
+    let primary = QuickStep::new(config(primary_dir.path()));
+    let mut tx = primary.tx();
+    tx.put(b"seed", b"seeded").expect("put");
+    tx.commit();
+    let from = primary.tail_wal(1).expect("tail").count() as u64 + 1;
+    primary.checkpoint().expect("checkpoint");
+    for entry in fs::read_dir(primary_dir.path()).expect("read dir") {
+        let entry = entry.expect("entry");
+        fs::copy(entry.path(), follower_dir.path().join(entry.file_name())).expect("copy");
+    }
+    let follower = QuickStep::open_follower(config(follower_dir.path()));
+
+    // the operands add up to a value past the inline limit
+    let mut expected = Vec::new();
+    for idx in 0..8 {
+        let operand = long_value(idx, MAX_INLINE_VALUE_LEN / 3);
+        expected.extend_from_slice(&operand);
+        let mut tx = primary.tx();
+        tx.merge(b"merged", &operand).expect("merge");
+        tx.commit();
+    }
+    let mut tx = primary.tx();
+    tx.put(b"replaced", &long_value(1, 3 * PAGE_PAYLOAD))
+        .expect("put");
+    tx.commit();
+    let mut tx = primary.tx();
+    tx.put(b"replaced", &long_value(2, 2 * PAGE_PAYLOAD))
+        .expect("put");
+    tx.commit();
+    assert_eq!(primary.get(b"merged").expect("get"), Some(expected.clone()));
+
+    let records: Vec<_> = primary.tail_wal(from).expect("tail").collect();
+    follower.apply_wal_batch(records).expect("apply");
+    assert_eq!(follower.get(b"merged").expect("get"), Some(expected));
+    assert_eq!(
+        follower.get(b"replaced").expect("get"),
+        Some(long_value(2, 2 * PAGE_PAYLOAD))
+    );
+}
+
+#[test]
+fn salvage_reads_long_values_back_through_their_chains() {
+    let temp = TempDir::new().expect("tempdir");
+    let source = temp.path().join("source");
+    let values = values();
+    let db = QuickStep::new(config(&source));
+    for (idx, value) in values.iter().enumerate() {
+        let mut tx = db.tx();
+        tx.put(&key(idx), value).expect("put");
+        tx.commit();
+    }
+    db.close().expect("close");
+
+    let output = temp.path().join("salvaged");
+    let report = QuickStep::salvage(config(&source), &output).expect("salvage");
+    assert!(report.corrupt_pages.is_empty(), "{report:?}");
+    assert!(report.overflow_pages > 0);
+    assert_eq!(report.overflow_values_lost, 0);
+    assert_eq!(report.keys_salvaged, values.len() as u64);
+
+    let salvaged = QuickStep::open(config(&output)).expect("open salvaged");
+    assert_values(&salvaged, &values);
+}
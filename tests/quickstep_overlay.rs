@@ -0,0 +1,104 @@
+use quickstep::{error::QSError, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn seeded(temp: &TempDir) -> QuickStep {
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14).with_max_value_len(64));
+    let mut tx = db.tx();
+    for key in [b"a", b"c", b"e"] {
+        tx.put(key, b"base").expect("seed");
+    }
+    tx.commit();
+    db
+}
+
+fn pairs(
+    iter: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), QSError>>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    iter.collect::<Result<_, _>>().expect("scan")
+}
+
+#[test]
+fn reads_see_the_layer_over_the_store() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = seeded(&temp);
+    let mut overlay = db.overlay();
+    overlay.put(b"b", b"layer").unwrap();
+    overlay.put(b"c", b"layer").unwrap();
+    assert!(overlay.delete(b"e").unwrap());
+    assert!(!overlay.delete(b"z").unwrap());
+
+    assert_eq!(overlay.get(b"a").unwrap(), Some(b"base".to_vec()));
+    assert_eq!(overlay.get(b"c").unwrap(), Some(b"layer".to_vec()));
+    assert_eq!(overlay.get(b"e").unwrap(), None);
+    assert_eq!(
+        pairs(overlay.range_iter(b"a", b"z")),
+        vec![
+            (b"a".to_vec(), b"base".to_vec()),
+            (b"b".to_vec(), b"layer".to_vec()),
+            (b"c".to_vec(), b"layer".to_vec()),
+        ]
+    );
+
+    // the store is untouched
+    assert_eq!(db.get(b"b").unwrap(), None);
+    assert_eq!(db.get(b"e").unwrap(), Some(b"base".to_vec()));
+}
+
+#[test]
+fn a_discarded_layer_leaves_the_store_as_it_was() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = seeded(&temp);
+    let before = pairs(db.range_iter(b"a", b"z"));
+    let mut overlay = db.overlay();
+    overlay.put(b"a", b"changed").unwrap();
+    overlay.delete(b"c").unwrap();
+    overlay.discard();
+    assert_eq!(pairs(db.range_iter(b"a", b"z")), before);
+}
+
+#[test]
+fn merging_down_writes_the_whole_layer() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = seeded(&temp);
+    let mut overlay = db.overlay();
+    overlay.put(b"b", b"layer").unwrap();
+    overlay.delete(b"c").unwrap();
+    assert_eq!(overlay.len(), 2);
+    overlay.merge_down().expect("merge down");
+    assert!(overlay.is_empty());
+
+    assert_eq!(
+        pairs(db.range_iter(b"a", b"z")),
+        vec![
+            (b"a".to_vec(), b"base".to_vec()),
+            (b"b".to_vec(), b"layer".to_vec()),
+            (b"e".to_vec(), b"base".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn a_failed_merge_writes_nothing_and_keeps_the_layer() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = seeded(&temp);
+    let mut overlay = db.overlay();
+    overlay.put(b"b", b"layer").unwrap();
+    // the store's limits are checked up front
+    assert!(matches!(
+        overlay.put(b"big", &[0u8; 65]),
+        Err(QSError::ValueTooLong { .. })
+    ));
+
+    // a transaction holding a key the layer writes makes the merge fail
+    overlay.delete(b"e").unwrap();
+    let mut holder = db.tx();
+    holder.put(b"e", b"held").unwrap();
+    assert!(overlay.merge_down().is_err());
+    holder.commit();
+
+    assert_eq!(overlay.len(), 2);
+    assert_eq!(db.get(b"b").unwrap(), None);
+    overlay.merge_down().expect("merge down");
+    assert_eq!(db.get(b"b").unwrap(), Some(b"layer".to_vec()));
+    assert_eq!(db.get(b"e").unwrap(), None);
+}
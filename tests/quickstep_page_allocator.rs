@@ -0,0 +1,90 @@
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Seek, SeekFrom, Write},
+};
+
+use quickstep::{
+    io_engine::{DiskLeaf, IoEngine},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+#[test]
+fn addresses_handed_out_before_a_reopen_are_not_handed_out_again() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("alloc.db");
+    let handed_out = {
+        let engine = IoEngine::open(&path).expect("open");
+        // none of them is written, the file does not grow
+        (0..5)
+            .map(|_| engine.get_new_addr().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(handed_out, vec![1, 2, 3, 4, 5]);
+
+    let engine = IoEngine::open(&path).expect("reopen");
+    assert_eq!(engine.pages_used(), 6);
+    assert_eq!(engine.get_new_addr().unwrap(), 6);
+}
+
+#[test]
+fn the_mark_and_the_free_list_come_back_together() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("alloc.db");
+    {
+        let engine = IoEngine::open(&path).expect("open");
+        let addrs: Vec<u64> = (0..3).map(|_| engine.get_new_addr().unwrap()).collect();
+        engine.write_page(addrs[0], &DiskLeaf::zeroed());
+        engine.free_page(addrs[0]);
+    }
+
+    let engine = IoEngine::open(&path).expect("reopen");
+    assert_eq!((engine.pages_used(), engine.free_pages()), (4, 1));
+    assert_eq!(engine.get_new_addr().unwrap(), 1);
+    assert_eq!(engine.get_new_addr().unwrap(), 4);
+}
+
+#[test]
+fn a_tampered_mark_fails_the_open() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("alloc.db");
+    {
+        let engine = IoEngine::open(&path).expect("open");
+        engine.get_new_addr().unwrap();
+    }
+
+    // the mark sits in the superblock, under its tag
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(24)).unwrap();
+    file.write_all(&1u64.to_le_bytes()).unwrap();
+    drop(file);
+
+    let err = IoEngine::open(&path).err().expect("tampered mark");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn split_leaves_keep_their_pages_across_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    let used = {
+        let db = QuickStep::new(config());
+        let payload = vec![5u8; 64];
+        let mut idx = 0;
+        while db
+            .debug_root_leaf_parent()
+            .map_or(1, |snapshot| snapshot.children.len())
+            < 3
+        {
+            let mut tx = db.tx();
+            tx.put(format!("key-{idx:05}").as_bytes(), &payload)
+                .expect("insert");
+            tx.commit();
+            idx += 1;
+        }
+        db.capacity_stats().disk_pages_used
+    };
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.capacity_stats().disk_pages_used, used);
+}
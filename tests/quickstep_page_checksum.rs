@@ -0,0 +1,78 @@
+use std::path::Path;
+
+use quickstep::{
+    error::QSError, io_engine::IoEngine, storage::Storage, utils::crc32, QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn flushed_root(dir: &Path) -> std::path::PathBuf {
+    let db = QuickStep::new(QuickStepConfig::new(dir, 32, 256, 14));
+    let mut tx = db.tx();
+    for idx in 0..16 {
+        let key = format!("key-{idx:04}");
+        tx.put(key.as_bytes(), b"value").expect("insert");
+    }
+    tx.commit();
+    db.debug_flush_root_leaf().expect("flush root leaf");
+    dir.join("quickstep.db")
+}
+
+#[test]
+fn crc32_matches_reference_vector() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn sealed_leaf_reads_back_with_its_address() {
+    let temp = TempDir::new().expect("tempdir");
+    let data_path = flushed_root(temp.path());
+
+    let io = IoEngine::open(&data_path).expect("open data file");
+    let leaf = io.get_page(0).expect("valid checksum");
+    assert_eq!(leaf.as_ref().leaf(), 0);
+    assert_eq!(leaf.as_ref().get(b"key-0007"), Some(&b"value"[..]));
+}
+
+#[test]
+fn flipped_byte_fails_with_page_corrupt() {
+    let temp = TempDir::new().expect("tempdir");
+    let data_path = flushed_root(temp.path());
+
+    {
+        // page 0 sits after the superblock page
+        let file = Storage::open(&data_path).expect("open");
+        let mut byte = [0u8; 1];
+        file.read_exact_at(&mut byte, 4096 + 2048)
+            .expect("read byte");
+        byte[0] ^= 0x01;
+        file.write_all_at(&byte, 4096 + 2048).expect("write byte");
+    }
+
+    let io = IoEngine::open(&data_path).expect("open data file");
+    assert!(matches!(
+        io.get_page(0),
+        Err(QSError::PageCorrupt { addr: 0 })
+    ));
+}
+
+#[test]
+fn page_metadata_is_stored_little_endian() {
+    let temp = TempDir::new().expect("tempdir");
+    let data_path = flushed_root(temp.path());
+
+    let io = IoEngine::open(&data_path).expect("open data file");
+    let leaf = io.get_page(0).expect("valid checksum");
+    let meta = leaf.as_ref();
+    drop(io);
+
+    let file = Storage::open(&data_path).expect("open");
+    let mut image = [0u8; 4096];
+    file.read_exact_at(&mut image, 4096).expect("read page");
+    let word = |idx: usize| u64::from_le_bytes(image[idx * 8..idx * 8 + 8].try_into().unwrap());
+    assert_eq!(word(0) & 0x1FF, u64::from(meta.record_count()));
+    assert_eq!(word(1) >> 16, meta.page_id().as_u64());
+    for idx in 0..meta.record_count() as usize {
+        assert_eq!(word(2 + idx), meta.get_kv_meta(idx).0);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{panic, path::Path};
+
+use quickstep::{
+    compress::{compress, decompress},
+    error::QSError,
+    io_engine::IoEngine,
+    storage::Storage,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn compressed_config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 32, 256, 16).with_page_compression()
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("tenant/0042/orders/2026/10/{idx:06}").into_bytes()
+}
+
+fn value(idx: usize) -> Vec<u8> {
+    let mut value = format!("n={idx};").into_bytes();
+    value.resize(200, b's');
+    value
+}
+
+/// Keys with a long common prefix, enough to split into several leaves
+fn fill(db: &QuickStep, count: usize) {
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(&key(idx), &value(idx)).expect("insert");
+        tx.commit();
+    }
+}
+
+#[test]
+fn compression_round_trips_page_images() {
+    let mut page = [0u8; 4096];
+    for (idx, chunk) in page[..2048].chunks_mut(32).enumerate() {
+        chunk[..8].copy_from_slice(&(idx as u64).to_le_bytes());
+        chunk[8..].fill(b'p');
+    }
+    let block = compress(&page);
+    assert!(block.len() < 1024, "compressed to {}", block.len());
+    let mut out = [0u8; 4096];
+    decompress(&block, &mut out).expect("decompress");
+    assert_eq!(out, page);
+
+    // a truncated block or a wrong output size is rejected
+    assert!(decompress(&block[..block.len() - 1], &mut out).is_none());
+    assert!(decompress(&block, &mut [0u8; 4095]).is_none());
+}
+
+#[test]
+fn compressed_leaves_read_back() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(compressed_config(temp.path()));
+    fill(&db, 200);
+    let stats = db.page_compression_stats().expect("compressed file");
+    assert!(stats.pages > 1);
+    assert!(stats.ratio() < 0.5, "{stats:?}");
+    for idx in 0..200 {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(value(idx)));
+    }
+    let data_len = std::fs::metadata(temp.path().join("quickstep.db"))
+        .unwrap()
+        .len();
+    assert!(
+        data_len < (stats.pages + 1) * 4096,
+        "{data_len} bytes for {stats:?}"
+    );
+}
+
+#[test]
+fn compressed_root_survives_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(compressed_config(temp.path()));
+        fill(&db, 10);
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+
+    let db = QuickStep::new(compressed_config(temp.path()));
+    for idx in 0..10 {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(value(idx)));
+    }
+    assert_eq!(db.page_compression_stats().unwrap().pages, 1);
+}
+
+#[test]
+fn in_memory_store_compresses_too() {
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 16).with_page_compression());
+    fill(&db, 200);
+    assert!(db.page_compression_stats().unwrap().stored_bytes > 0);
+    assert_eq!(db.get(&key(150)).unwrap(), Some(value(150)));
+    assert!(QuickStep::new(QuickStepConfig::in_memory(32, 256, 16))
+        .page_compression_stats()
+        .is_none());
+}
+
+#[test]
+fn a_file_keeps_its_compression_setting() {
+    let temp = TempDir::new().expect("tempdir");
+    drop(QuickStep::new(compressed_config(temp.path())));
+    let plain =
+        panic::catch_unwind(|| QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 16)));
+    assert!(plain.is_err());
+
+    let other = TempDir::new().expect("tempdir");
+    drop(QuickStep::new(QuickStepConfig::new(
+        other.path(),
+        32,
+        256,
+        16,
+    )));
+    let compressed = panic::catch_unwind(|| QuickStep::new(compressed_config(other.path())));
+    assert!(compressed.is_err());
+}
+
+#[test]
+fn corrupt_block_fails_with_page_corrupt() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(compressed_config(temp.path()));
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"one").expect("insert");
+        tx.commit();
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+
+    let data_path = temp.path().join("quickstep.db");
+    let io = IoEngine::open(&data_path).expect("open data file");
+    assert!(io.is_compressed());
+    let leaf = io.get_page(0).expect("valid block");
+    assert_eq!(leaf.as_ref().get(b"alpha"), Some(&b"one"[..]));
+    drop(io);
+
+    {
+        // blocks start after the superblock page
+        let file = Storage::open(&data_path).expect("open");
+        let len = file.len().expect("len");
+        file.write_all_at(&vec![0x5A; (len - 4096) as usize], 4096)
+            .expect("overwrite blocks");
+    }
+    let io = IoEngine::open(&data_path).expect("open data file");
+    assert!(matches!(
+        io.get_page(0),
+        Err(QSError::PageCorrupt { addr: 0 })
+    ));
+}
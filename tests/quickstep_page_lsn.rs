@@ -0,0 +1,119 @@
+use std::{mem, path::Path};
+
+use quickstep::{
+    map_table::PageId,
+    wal::{WalEntryKind, WalManager, WalOp, WalRecord, WalStatsLevel},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn put(page: u64, key: &[u8]) -> WalRecord {
+    WalRecord::leaf(
+        PageId::from_u64(page),
+        key,
+        b"",
+        b"\xff",
+        WalEntryKind::Redo,
+        1,
+        WalOp::Put {
+            value: key.to_vec(),
+        },
+    )
+}
+
+fn lsns(wal: &WalManager) -> Vec<u64> {
+    wal.records().iter().map(|record| record.lsn).collect()
+}
+
+#[test]
+fn records_keep_their_sequence_numbers_across_checkpoints_and_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("lsn.wal");
+    {
+        let wal = WalManager::open(&path).expect("open wal");
+        assert_eq!(wal.open_stats().next_lsn, 1);
+        for idx in 0..6u8 {
+            wal.append_batch(vec![put(u64::from(idx % 2), &[idx])])
+                .expect("append");
+        }
+        wal.sync().expect("sync");
+        assert_eq!(lsns(&wal), [1, 2, 3, 4, 5, 6]);
+
+        // the records left behind keep their numbers, new ones carry on after the dropped
+        wal.checkpoint_page(PageId::from_u64(1))
+            .expect("checkpoint");
+        assert_eq!(lsns(&wal), [1, 3, 5]);
+        wal.append_batch(vec![put(1, b"after")]).expect("append");
+        wal.sync().expect("sync");
+        assert_eq!(lsns(&wal), [1, 3, 5, 7]);
+        assert!(wal.audit().expect("audit").is_empty());
+    }
+    {
+        let wal = WalManager::open(&path).expect("reopen wal");
+        assert_eq!(wal.open_stats().next_lsn, 8);
+        assert_eq!(lsns(&wal), [1, 3, 5, 7]);
+        assert_eq!(wal.durable_lsn(), 7);
+
+        // a log checkpointed down to nothing still numbers on from where it was
+        wal.clear().expect("clear");
+        let stats = wal.stats(WalStatsLevel::Totals).unwrap();
+        assert_eq!(stats.total.records, 0);
+        assert!(stats.lsn_marker > 0);
+    }
+    let wal = WalManager::open(&path).expect("reopen wal");
+    assert_eq!(wal.open_stats().next_lsn, 8);
+    wal.append_batch(vec![put(0, b"next")]).expect("append");
+    wal.sync().expect("sync");
+    assert_eq!(lsns(&wal), [8]);
+    assert_eq!(wal.stats(WalStatsLevel::Totals).unwrap().lsn_marker, 0);
+    assert!(wal.audit().expect("audit").is_empty());
+}
+
+fn config(path: &Path) -> QuickStepConfig {
+    // leaves are only flushed by the checkpoints the tests run
+    QuickStepConfig::new(path, 64, 1024, 20).with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:04}").into_bytes()
+}
+
+#[test]
+fn recovery_skips_records_a_written_leaf_already_holds() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        for idx in 0..400 {
+            let mut tx = db.tx();
+            tx.put(&key(idx), b"committed").expect("put");
+            tx.commit();
+        }
+        let mut tx = db.tx();
+        tx.put(&key(0), b"in flight").expect("put");
+        mem::forget(tx);
+        db.debug_close_without_flush();
+    }
+    {
+        // read repair writes the repaired leaves back, the log keeps their records until
+        // each is checkpointed
+        let db = QuickStep::new(config(temp.path()).with_read_repair());
+        let replayed = db.last_recovery_report().records_replayed;
+        assert!(replayed >= 400);
+        for idx in 0..400 {
+            assert_eq!(db.get(&key(idx)).expect("get"), Some(b"committed".to_vec()));
+        }
+        db.flush_read_repairs().expect("flush");
+        assert!(db.debug_wal_record_count().unwrap() >= 400);
+        db.debug_close_without_flush();
+    }
+
+    let db = QuickStep::new(config(temp.path()));
+    let report = db.last_recovery_report();
+    assert!(report.records_skipped >= 400, "{report:?}");
+    // only the rolled back transaction's undo record is left to apply
+    assert_eq!(report.records_replayed, 1);
+    assert_eq!(report.pages_rebuilt, 1);
+    for idx in 0..400 {
+        assert_eq!(db.get(&key(idx)).expect("get"), Some(b"committed".to_vec()));
+    }
+}
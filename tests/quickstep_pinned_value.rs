@@ -0,0 +1,72 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn new_db(db_path: std::path::PathBuf) -> QuickStep {
+    QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14))
+}
+
+#[test]
+fn pinned_value_outlives_transaction_and_writes() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = new_db(temp.path().join("db"));
+    {
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"first").expect("put alpha");
+        tx.put(b"beta", b"other").expect("put beta");
+        tx.commit();
+    }
+
+    let pinned = {
+        let mut tx = db.tx();
+        let pinned = tx.get_pinned(b"alpha").unwrap().expect("value present");
+        tx.commit();
+        pinned
+    };
+    assert!(pinned.is_cached());
+    assert_eq!(db.debug_pinned_slots(), 1);
+
+    // writers move the pinned mini-page instead of changing it
+    {
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"second").expect("overwrite alpha");
+        tx.put(b"gamma", b"new").expect("put gamma");
+        tx.commit();
+    }
+    assert!(db.delete(b"beta").unwrap());
+    assert_eq!(&*pinned, b"first");
+
+    let mut tx = db.tx();
+    assert_eq!(tx.get(b"alpha").unwrap(), Some(&b"second"[..]));
+    assert_eq!(tx.get(b"gamma").unwrap(), Some(&b"new"[..]));
+    tx.commit();
+
+    drop(pinned);
+    assert_eq!(db.debug_pinned_slots(), 0);
+}
+
+#[test]
+fn pinned_value_from_disk_leaf() {
+    let temp = TempDir::new().expect("tempdir");
+    let db_path = temp.path().join("db");
+    {
+        let db = new_db(db_path.clone());
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"on-disk").expect("put alpha");
+        tx.commit();
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+
+    let db = new_db(db_path);
+    let pinned = {
+        let mut tx = db.tx();
+        let pinned = tx.get_pinned(b"alpha").unwrap().expect("value present");
+        assert!(tx.get_pinned(b"missing").unwrap().is_none());
+        tx.commit();
+        pinned
+    };
+    assert!(!pinned.is_cached());
+    assert_eq!(db.debug_pinned_slots(), 0);
+
+    assert!(db.delete(b"alpha").unwrap());
+    assert_eq!(pinned.as_bytes(), b"on-disk");
+}
@@ -0,0 +1,109 @@
+use std::{thread, time::Duration};
+
+use quickstep::{io_engine::DiskLeaf, leaf_cache::LeafCache, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn reopen_cold(db_path: &std::path::Path, cache_pages: usize) -> QuickStep {
+    {
+        let db = QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14));
+        let mut tx = db.tx();
+        for idx in 0..16 {
+            let key = format!("key-{idx:04}");
+            tx.put(key.as_bytes(), b"value").expect("insert");
+        }
+        tx.commit();
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+    QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14).with_leaf_cache_pages(cache_pages))
+}
+
+/// A tree grown until the mini-page buffer has evicted leaves to disk, in a one page leaf
+/// cache
+fn evicted_leaves(db_path: &std::path::Path) -> QuickStep {
+    let db = QuickStep::new(QuickStepConfig::new(db_path, 32, 256, 14).with_leaf_cache_pages(1));
+    let payload = vec![7u8; 64];
+    for round in 0..64 {
+        let mut tx = db.tx();
+        for idx in 0..8 {
+            let key = format!("key-{round:04}-{idx:04}");
+            tx.put(key.as_bytes(), &payload).expect("insert");
+        }
+        tx.commit();
+    }
+    db
+}
+
+fn keys() -> impl Iterator<Item = String> {
+    (0..64).flat_map(|round| (0..8).map(move |idx| format!("key-{round:04}-{idx:04}")))
+}
+
+#[test]
+fn prefetched_leaf_is_served_from_the_cache() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = evicted_leaves(&temp.path().join("db"));
+
+    let tx = db.tx();
+    let key = keys()
+        .find(|key| tx.prefetch([key]).unwrap() == 1)
+        .expect("some leaf should be on disk and uncached");
+    // the read happens on the prefetch thread, once done the leaf is no longer queued
+    let mut cached = false;
+    for _ in 0..200 {
+        if tx.prefetch([&key]).unwrap() == 0 {
+            cached = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    tx.commit();
+    assert!(cached, "prefetch of {key} never landed");
+
+    let before = db.leaf_cache_stats();
+    let mut tx = db.tx();
+    assert_eq!(tx.get(key.as_bytes()).unwrap(), Some(&[7u8; 64][..]));
+    tx.commit();
+    let after = db.leaf_cache_stats();
+    assert_eq!(after.misses, before.misses, "{after:?}");
+    assert!(after.hits > before.hits);
+}
+
+#[test]
+fn prefetch_range_walks_the_leaves_of_the_range() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = evicted_leaves(&temp.path().join("db"));
+    let leaves = db.debug_root_leaf_parent().unwrap().children.len();
+
+    let tx = db.tx();
+    let queued = tx.prefetch_range(b"", b"\xff").unwrap();
+    assert!(queued >= 1 && queued <= leaves, "{queued} of {leaves}");
+    assert_eq!(tx.prefetch_range(b"key-", b"key-").unwrap(), 0);
+    tx.commit();
+}
+
+#[test]
+fn prefetch_without_leaf_cache_queues_nothing() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = reopen_cold(&temp.path().join("db"), 0);
+
+    let tx = db.tx();
+    assert_eq!(tx.prefetch([b"key-0003"]).unwrap(), 0);
+    assert_eq!(tx.prefetch_range(b"", b"\xff").unwrap(), 0);
+    tx.commit();
+    assert_eq!(db.leaf_cache_stats().pages, 0);
+}
+
+#[test]
+fn fill_skips_images_a_write_may_have_overtaken() {
+    let cache = LeafCache::new(4);
+    let stamp = cache.write_stamp();
+    cache.write(1, &DiskLeaf::zeroed());
+    assert!(
+        !cache.fill(2, &DiskLeaf::zeroed(), stamp),
+        "a write raced the read"
+    );
+
+    let stamp = cache.write_stamp();
+    assert!(cache.fill(2, &DiskLeaf::zeroed(), stamp));
+    assert!(!cache.fill(2, &DiskLeaf::zeroed(), stamp), "already cached");
+    assert_eq!(cache.stats().pages, 2);
+}
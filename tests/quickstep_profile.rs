@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use quickstep::{profile::Profile, wal::SyncPolicy, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+#[test]
+fn every_profile_opens_and_keeps_its_writes_across_reopen() {
+    for profile in Profile::ALL {
+        let temp = TempDir::new().expect("tempdir");
+        {
+            let db = QuickStep::new(QuickStepConfig::for_profile(temp.path(), profile));
+            let mut tx = db.tx();
+            for idx in 0..200 {
+                tx.put(format!("key-{idx:04}").as_bytes(), &[7u8; 48])
+                    .expect("insert");
+            }
+            tx.commit();
+        }
+
+        let db = QuickStep::new(QuickStepConfig::for_profile(temp.path(), profile));
+        for idx in (0..200).step_by(17) {
+            assert_eq!(
+                db.get(format!("key-{idx:04}").as_bytes()).unwrap(),
+                Some(vec![7u8; 48]),
+                "{profile:?} key {idx}"
+            );
+        }
+    }
+}
+
+#[test]
+fn profiles_trade_durability_and_memory_as_documented() {
+    let config = |profile| QuickStepConfig::for_profile("/tmp/unused", profile);
+
+    let bulk = config(Profile::BulkLoad);
+    assert_eq!(bulk.sync_policy(), SyncPolicy::Never);
+    assert!(bulk.idle_maintenance().is_none() && bulk.auto_compaction().is_none());
+
+    let server = config(Profile::Server);
+    assert_eq!(server.sync_policy(), SyncPolicy::PerCommit);
+    assert!(server.max_wal_size().is_some() && server.auto_compaction().is_some());
+
+    let embedded = config(Profile::Embedded);
+    assert!(embedded.idle_maintenance().is_some());
+
+    let low = config(Profile::LowMemory);
+    assert!(low.leaf_cache_pages() < embedded.leaf_cache_pages());
+    assert!(low.wal_thresholds().2 < embedded.wal_thresholds().2);
+    assert!(Profile::LowMemory.sizes().2 < Profile::Embedded.sizes().2);
+    assert!(Profile::Embedded.sizes().2 < Profile::Server.sizes().2);
+}
+
+#[test]
+fn later_builder_calls_override_the_profile() {
+    let config = QuickStepConfig::for_profile("/tmp/unused", Profile::BulkLoad)
+        .with_sync_policy(SyncPolicy::Every(Duration::from_millis(5)))
+        .with_leaf_cache_pages(3);
+    assert_eq!(
+        config.sync_policy(),
+        SyncPolicy::Every(Duration::from_millis(5))
+    );
+    assert_eq!(config.leaf_cache_pages(), 3);
+    assert_eq!(config.wal_thresholds(), (256, 1 << 20, 1 << 30));
+}
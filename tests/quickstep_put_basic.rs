@@ -3,7 +3,7 @@ use tempfile::TempDir;
 
 fn new_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
-    let config = QuickStepConfig::new(temp.into_path(), 32, 128, 12);
+    let config = QuickStepConfig::new(temp.keep(), 32, 128, 12);
     QuickStep::new(config)
 }
 
@@ -35,3 +35,78 @@ fn insert_and_read_back() {
         "happy-path insert should not request a split"
     );
 }
+
+#[test]
+fn get_without_transaction() {
+    let temp = TempDir::new().expect("tempdir");
+    let db_path = temp.path().join("db");
+    {
+        let db = QuickStep::new(QuickStepConfig::new(db_path.clone(), 32, 128, 12));
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"one").expect("put alpha");
+        tx.put(b"beta", b"two").expect("put beta");
+        tx.commit();
+
+        assert_eq!(db.get(b"alpha").unwrap(), Some(b"one".to_vec()));
+        assert_eq!(db.get(b"delta").unwrap(), None);
+
+        assert!(db.delete(b"beta").unwrap());
+        assert_eq!(db.get(b"beta").unwrap(), None);
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+
+    // served from the on-disk leaf after a restart
+    let db = QuickStep::new(QuickStepConfig::new(db_path, 32, 128, 12));
+    assert_eq!(db.get(b"alpha").unwrap(), Some(b"one".to_vec()));
+    assert_eq!(db.get(b"beta").unwrap(), None);
+}
+
+#[test]
+fn get_ref_borrows_value_in_place() {
+    let temp = TempDir::new().expect("tempdir");
+    let db_path = temp.path().join("db");
+    let large = vec![7u8; 1024];
+    {
+        let db = QuickStep::new(QuickStepConfig::new(db_path.clone(), 32, 128, 12));
+        let mut tx = db.tx();
+        tx.put(b"large", &large).expect("put large");
+        tx.commit();
+
+        let mut tx = db.tx();
+        let first = tx.get_ref(b"large").unwrap().expect("value present");
+        assert_eq!(first.as_bytes(), large.as_slice());
+        let first_ptr = first.as_ptr();
+        let second_ptr = tx
+            .get_ref(b"large")
+            .unwrap()
+            .expect("value present")
+            .as_ptr();
+        assert_eq!(
+            first_ptr, second_ptr,
+            "value should not be copied per lookup"
+        );
+        assert!(tx.get_ref(b"missing").unwrap().is_none());
+        tx.commit();
+        db.debug_flush_root_leaf().expect("flush root leaf");
+    }
+
+    // a disk leaf read stays borrowed from the leaf image, where `get` would eventually
+    // copy the record into a mini-page and read it from there
+    let db = QuickStep::new(QuickStepConfig::new(db_path, 32, 128, 16));
+    let mut tx = db.tx();
+    let first_ptr = tx
+        .get_ref(b"large")
+        .unwrap()
+        .expect("value present")
+        .as_ptr();
+    for _ in 0..64 {
+        let value = tx.get_ref(b"large").unwrap().expect("value present");
+        assert_eq!(value.as_bytes(), large.as_slice());
+        assert_eq!(
+            value.as_ptr(),
+            first_ptr,
+            "disk leaf value should not be copied"
+        );
+    }
+    tx.commit();
+}
@@ -3,7 +3,7 @@ use tempfile::TempDir;
 
 fn new_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
-    QuickStep::new(QuickStepConfig::new(temp.into_path(), 32, 256, 14))
+    QuickStep::new(QuickStepConfig::new(temp.keep(), 32, 256, 14))
 }
 
 #[test]
@@ -40,11 +40,8 @@ fn range_scan_across_split_leaves() {
         tx.commit();
     }
 
-    let results = db
-        .range_scan(b"key-0050", b"key-0100")
-        .expect("range scan");
+    let results = db.range_scan(b"key-0050", b"key-0100").expect("range scan");
     assert_eq!(results.len(), 50);
     assert_eq!(results.first().unwrap().0, b"key-0050");
     assert_eq!(results.last().unwrap().0, b"key-0099");
 }
-
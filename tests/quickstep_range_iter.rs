@@ -0,0 +1,191 @@
+use quickstep::{debug, error::QSError, QuickStep, QuickStepConfig};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+use tempfile::TempDir;
+
+fn new_db() -> QuickStep {
+    let temp = TempDir::new().expect("tempdir");
+    QuickStep::new(QuickStepConfig::new(temp.keep(), 64, 1024, 16))
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+/// xorshift, enough to vary where the interleaved writes land
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+#[test]
+fn range_iter_returns_range_in_order() {
+    let db = new_db();
+    let payload = vec![1u8; 512];
+    {
+        let mut tx = db.tx();
+        for idx in 0..300 {
+            tx.put(&key(idx), &payload).expect("insert");
+        }
+        tx.commit();
+    }
+
+    let expected: Vec<_> = (40..260).map(|idx| (key(idx), payload.clone())).collect();
+    let iterated: Vec<_> = db
+        .range_iter(&key(40), &key(260))
+        .collect::<Result<_, _>>()
+        .expect("range iter");
+    assert_eq!(iterated, expected);
+    assert_eq!(
+        db.range_scan(&key(40), &key(260)).expect("range scan"),
+        expected
+    );
+    assert!(db.range_iter(&key(5), &key(5)).next().is_none());
+}
+
+#[test]
+fn range_iter_survives_interleaved_splits_and_merges() {
+    debug::reset_debug_counters();
+    let db = new_db();
+    let payload = vec![2u8; 256];
+    const KEYS: usize = 800;
+
+    // every fourth key is present for the whole scan, the others come and go under it
+    {
+        let mut tx = db.tx();
+        for idx in (0..KEYS).step_by(4) {
+            tx.put(&key(idx), &payload).expect("seed");
+        }
+        tx.commit();
+    }
+
+    for seed in 1..=2u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let mut seen = Vec::new();
+        for record in db.range_iter(&key(0), &key(KEYS)) {
+            let (k, _) = record.expect("iterator step");
+            seen.push(k);
+
+            let mut tx = db.tx();
+            for _ in 0..2 {
+                let idx = rng.below(KEYS);
+                if idx.is_multiple_of(4) {
+                    continue;
+                }
+                match rng.below(3) {
+                    0 => {
+                        tx.delete(&key(idx)).expect("delete");
+                    }
+                    _ => tx.put(&key(idx), &payload).expect("insert"),
+                }
+            }
+            tx.commit();
+        }
+
+        assert!(
+            seen.windows(2).all(|pair| pair[0] < pair[1]),
+            "keys must come out strictly increasing without duplicates"
+        );
+        for idx in (0..KEYS).step_by(4) {
+            assert!(
+                seen.binary_search(&key(idx)).is_ok(),
+                "stable key {idx} skipped (seed {seed})"
+            );
+        }
+    }
+
+    assert!(
+        debug::split_requests() > 0,
+        "interleaved inserts should split leaves under the cursor"
+    );
+}
+
+#[test]
+fn concurrent_splits_and_merges_do_not_skip_or_repeat_keys() {
+    let db = new_db();
+    let payload = vec![3u8; 256];
+    const KEYS: usize = 1024;
+    const STABLE: usize = 16;
+    const WRITERS: usize = 2;
+
+    // every sixteenth key is present for the whole run, so emptying a band between two of
+    // them leaves a leaf sparse enough to merge
+    {
+        let mut tx = db.tx();
+        for idx in (0..KEYS).step_by(STABLE) {
+            tx.put(&key(idx), &payload).expect("seed");
+        }
+        tx.commit();
+    }
+
+    let done = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for writer in 0..WRITERS {
+            let (db, payload, done) = (&db, &payload, &done);
+            scope.spawn(move || {
+                let mut rng = Rng((writer as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                for _ in 0..40 {
+                    // each writer fills and then empties bands of its own, splitting leaves on
+                    // the way up and merging them on the way down
+                    let band = rng.below(KEYS / (STABLE * WRITERS)) * WRITERS + writer;
+                    let keys = (band * STABLE + 1)..((band + 1) * STABLE);
+                    for delete in [false, true] {
+                        loop {
+                            let mut tx = db.tx();
+                            let res = keys.clone().try_for_each(|idx| match delete {
+                                true => tx.delete(&key(idx)).map(drop),
+                                false => tx.put(&key(idx), payload),
+                            });
+                            match res {
+                                Ok(()) => {
+                                    tx.commit();
+                                    break;
+                                }
+                                // a leaf the other writer holds, back off and retry
+                                Err(_) => tx.abort(),
+                            }
+                        }
+                    }
+                }
+                done.fetch_add(1, Ordering::Release);
+            });
+        }
+
+        let mut scans = 0;
+        while done.load(Ordering::Acquire) < WRITERS || scans == 0 {
+            let seen: Vec<_> = match db
+                .range_iter(&key(0), &key(KEYS))
+                .map(|record| record.map(|(k, _)| k))
+                .collect()
+            {
+                Ok(seen) => seen,
+                // a writer kept a leaf locked past the lock timeout, scan again
+                Err(QSError::PageLockFail) => continue,
+                Err(err) => panic!("iterator step: {err:?}"),
+            };
+            assert!(
+                seen.windows(2).all(|pair| pair[0] < pair[1]),
+                "keys must come out strictly increasing without duplicates (scan {scans})"
+            );
+            for idx in (0..KEYS).step_by(STABLE) {
+                assert!(
+                    seen.binary_search(&key(idx)).is_ok(),
+                    "stable key {idx} skipped (scan {scans})"
+                );
+            }
+            scans += 1;
+        }
+    });
+
+    let stats = db.stats();
+    assert!(stats.splits > 0, "filling bands should split leaves");
+    assert!(stats.merges > 0, "emptying bands should merge leaves");
+}
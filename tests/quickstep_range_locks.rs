@@ -0,0 +1,107 @@
+use quickstep::{error::QSError, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn new_db() -> QuickStep {
+    let temp = TempDir::new().expect("tempdir");
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14);
+    QuickStep::new(config)
+}
+
+/// Fill two leaves, returns the pivot between them
+fn seed_two_leaves(db: &QuickStep) -> Vec<u8> {
+    let payload = vec![7u8; 512];
+    let mut tx = db.tx();
+    for idx in 0..10 {
+        tx.put(format!("key-{idx:02}").as_bytes(), &payload)
+            .expect("insert");
+    }
+    tx.commit();
+    let parent = db.debug_root_leaf_parent().expect("root should have split");
+    assert_eq!(parent.children.len(), 2);
+    parent.pivots[0].clone()
+}
+
+fn expect_conflict(db: &QuickStep, key: &[u8], holder: u64) {
+    let mut writer = db.tx();
+    match writer.put(key, b"phantom") {
+        Err(QSError::WriteConflict { key: conflict }) => assert_eq!(conflict, key),
+        other => panic!("expected a write conflict, got {other:?}"),
+    }
+    assert_eq!(writer.conflicting_txns(), &[holder]);
+    writer.abort();
+}
+
+#[test]
+fn serializable_scan_locks_its_leaf_range() {
+    let db = new_db();
+    let pivot = seed_two_leaves(&db);
+    assert!(pivot.as_slice() > b"key-03".as_slice());
+
+    let mut scanner = db.serializable_tx();
+    let keys: Vec<_> = scanner
+        .range_scan(b"key-01", b"key-03")
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(keys, vec![b"key-01".to_vec(), b"key-02".to_vec()]);
+
+    // inside the range, and in the gap out to the leaf's fences
+    expect_conflict(&db, b"key-02a", scanner.txn_id());
+    expect_conflict(&db, b"key-00a", scanner.txn_id());
+    let mut delete = db.tx();
+    assert!(matches!(
+        delete.delete(b"key-01"),
+        Err(QSError::WriteConflict { .. })
+    ));
+    delete.abort();
+
+    // the next leaf is not locked
+    let mut other_leaf = db.tx();
+    other_leaf
+        .put(b"key-99", b"fine")
+        .expect("write past the fences");
+    other_leaf.commit();
+
+    // the scanner can still write its own range
+    scanner.put(b"key-01a", b"mine").expect("own write");
+    scanner.commit();
+
+    let mut writer = db.tx();
+    writer
+        .put(b"key-02a", b"later")
+        .expect("write after scanner ended");
+    writer.commit();
+}
+
+#[test]
+fn transaction_scan_sees_its_own_writes() {
+    let db = new_db();
+    let mut tx = db.tx();
+    tx.put(b"a", b"1").unwrap();
+    tx.put(b"b", b"2").unwrap();
+    tx.put(b"c", b"3").unwrap();
+    tx.commit();
+    assert!(db.soft_delete(b"c").unwrap());
+
+    let mut tx = db.tx();
+    tx.put(b"bb", b"22").unwrap();
+    assert!(tx.delete(b"a").unwrap());
+    let records = tx.range_scan(b"a", b"z").unwrap();
+    assert_eq!(
+        records,
+        vec![
+            (b"b".to_vec(), b"2".to_vec()),
+            (b"bb".to_vec(), b"22".to_vec()),
+        ]
+    );
+    tx.commit();
+
+    // a plain transaction does not lock the range
+    let mut scanner = db.tx();
+    assert_eq!(scanner.range_scan(b"b", b"c").unwrap().len(), 2);
+    drop(scanner);
+    let mut writer = db.tx();
+    writer.put(b"ba", b"ok").expect("write after a plain scan");
+    writer.commit();
+}
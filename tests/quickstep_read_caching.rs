@@ -0,0 +1,87 @@
+use quickstep::{debug, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+const FULL_PAGE: usize = 4096;
+/// Enough reads that a 20% coin never misses
+const READS: usize = 200;
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key-{i:04}").into_bytes()
+}
+
+fn value(i: usize) -> Vec<u8> {
+    vec![(i % 251) as u8; 300]
+}
+
+/// A store whose leaves are all written back and out of the cache
+fn cold(temp: &TempDir, cache_size_lg: usize) -> QuickStep {
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, cache_size_lg));
+    let mut tx = db.tx();
+    for i in 0..40 {
+        tx.put(&key(i), &value(i)).expect("insert");
+    }
+    tx.commit();
+    db.demote_range(b"", b"\xff").expect("demote");
+    assert_eq!(db.cached_leaves(), 0);
+    db
+}
+
+#[test]
+fn hot_disk_record_moves_into_a_small_mini_page() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = cold(&temp, 20);
+    let first = db.debug_root_leaf_parent().expect("split root").children[0];
+    let cached_reads = debug::cached_reads();
+
+    for _ in 0..READS {
+        assert_eq!(db.get(&key(3)).expect("get"), Some(value(3)));
+    }
+    assert!(debug::cached_reads() > cached_reads);
+    let cached = db.debug_leaf_snapshot(first).unwrap().cached_bytes;
+    assert!(
+        cached.is_some_and(|bytes| bytes < FULL_PAGE),
+        "cached in {cached:?}"
+    );
+    let mut tx = db.tx();
+    let info = tx
+        .entry_info(&key(3))
+        .expect("entry info")
+        .expect("present");
+    assert!(info.cached);
+    tx.commit();
+
+    // the rest of the leaf still reads from disk
+    for i in 0..40 {
+        assert_eq!(db.get(&key(i)).expect("get"), Some(value(i)));
+    }
+}
+
+#[test]
+fn write_over_a_cached_copy_is_written_back() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = cold(&temp, 20);
+        for _ in 0..READS {
+            db.get(&key(3)).expect("get");
+        }
+        let mut tx = db.tx();
+        tx.put(&key(3), b"updated").expect("put");
+        tx.commit();
+        db.demote_range(b"", b"\xff").expect("demote");
+        assert_eq!(db.get(&key(3)).expect("get"), Some(b"updated".to_vec()));
+    }
+
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 20));
+    assert_eq!(db.get(&key(3)).expect("get"), Some(b"updated".to_vec()));
+    assert_eq!(db.get(&key(4)).expect("get"), Some(value(4)));
+}
+
+#[test]
+fn tiny_buffer_reads_leave_the_cache_alone() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = cold(&temp, 13);
+    for _ in 0..READS {
+        assert_eq!(db.get(&key(3)).expect("get"), Some(value(3)));
+    }
+    assert_eq!(db.cached_leaves(), 0);
+}
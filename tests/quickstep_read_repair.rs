@@ -0,0 +1,114 @@
+use std::{
+    mem,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    // leaves are only flushed by the checkpoints the tests run
+    QuickStepConfig::new(path, 64, 1024, 20).with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:04}").into_bytes()
+}
+
+/// Commit `count` keys, leave a transaction overwriting the first one unfinished and drop
+/// the store without flushing its leaves
+fn crash_with_unflushed_leaves(path: &Path, count: u32) {
+    let db = QuickStep::new(config(path));
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(&key(idx), b"committed").expect("put");
+        tx.commit();
+    }
+    let mut tx = db.tx();
+    tx.put(&key(0), b"in flight").expect("put");
+    mem::forget(tx);
+    db.debug_close_without_flush();
+}
+
+#[test]
+fn reads_see_repaired_leaves_and_writes_rewrite_them() {
+    let temp = TempDir::new().expect("tempdir");
+    crash_with_unflushed_leaves(temp.path(), 400);
+
+    let db = QuickStep::new(config(temp.path()).with_read_repair());
+    let report = db.last_recovery_report();
+    assert_eq!(report.pages_rebuilt, 0);
+    assert!(report.pages_deferred > 1);
+    assert_eq!(report.txns_rolled_back, 1);
+    assert_eq!(db.pending_read_repairs(), report.pages_deferred);
+
+    // the first read of each leaf repairs it from the log
+    for idx in 0..400 {
+        assert_eq!(db.get(&key(idx)).expect("get"), Some(b"committed".to_vec()));
+    }
+    assert_eq!(db.pending_read_repairs(), report.pages_deferred);
+    let mut tx = db.tx();
+    tx.put(b"other", b"value").expect("put");
+    tx.commit();
+    assert!(db.pending_read_repairs() < report.pages_deferred);
+    db.flush_read_repairs().expect("flush");
+    assert_eq!(db.pending_read_repairs(), 0);
+    drop(db);
+
+    let db = QuickStep::new(config(temp.path()));
+    assert_eq!(db.get(&key(0)).expect("get"), Some(b"committed".to_vec()));
+    assert_eq!(db.get(&key(399)).expect("get"), Some(b"committed".to_vec()));
+    assert_eq!(db.get(b"other").expect("get"), Some(b"value".to_vec()));
+}
+
+#[test]
+fn a_crash_before_the_rewrite_replays_the_log_again() {
+    let temp = TempDir::new().expect("tempdir");
+    crash_with_unflushed_leaves(temp.path(), 50);
+    {
+        let db = QuickStep::new(config(temp.path()).with_read_repair());
+        assert_eq!(db.last_recovery_report().pages_deferred, 1);
+        assert_eq!(db.get(&key(0)).expect("get"), Some(b"committed".to_vec()));
+        // writes on top of the repaired leaf, numbered past the unfinished transaction
+        let mut tx = db.tx();
+        tx.put(&key(1), b"after").expect("put");
+        tx.delete(&key(2)).expect("delete");
+        tx.commit();
+        let mut tx = db.tx();
+        tx.put(&key(3), b"in flight again").expect("put");
+        mem::forget(tx);
+    }
+
+    let db = QuickStep::new(config(temp.path()));
+    assert_eq!(db.last_recovery_report().pages_rebuilt, 1);
+    assert_eq!(db.get(&key(0)).expect("get"), Some(b"committed".to_vec()));
+    assert_eq!(db.get(&key(1)).expect("get"), Some(b"after".to_vec()));
+    assert_eq!(db.get(&key(2)).expect("get"), None);
+    assert_eq!(db.get(&key(3)).expect("get"), Some(b"committed".to_vec()));
+    assert_eq!(db.get(&key(49)).expect("get"), Some(b"committed".to_vec()));
+}
+
+#[test]
+fn the_checkpoint_thread_repairs_leaves_that_were_never_read() {
+    let temp = TempDir::new().expect("tempdir");
+    crash_with_unflushed_leaves(temp.path(), 400);
+    {
+        let config = config(temp.path()).with_wal_thresholds(usize::MAX, 64, usize::MAX);
+        let db = QuickStep::new(config.with_read_repair());
+        assert!(db.last_recovery_report().pages_deferred > 1);
+        // nothing reads or writes, the background checkpoint writes every leaf back
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while db.pending_read_repairs() > 0 || db.debug_wal_record_count().unwrap() >= 64 {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    let db = QuickStep::new(config(temp.path()));
+    assert_eq!(db.last_recovery_report().pages_rebuilt, 0);
+    for idx in 0..400 {
+        assert_eq!(db.get(&key(idx)).expect("get"), Some(b"committed".to_vec()));
+    }
+}
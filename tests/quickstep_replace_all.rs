@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(temp: &TempDir) -> QuickStepConfig {
+    QuickStepConfig::new(temp.path(), 64, 1024, 16)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+fn scan(db: &QuickStep) -> Vec<(Vec<u8>, Vec<u8>)> {
+    db.range_iter(b"", b"\xff")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("scan")
+}
+
+#[test]
+fn replace_all_swaps_in_the_new_dataset_and_survives_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let new_entries: Vec<_> = (0..2000u32)
+        .map(|idx| (key(idx * 2), format!("new-{idx}").into_bytes()))
+        .collect();
+    {
+        let db = QuickStep::new(config(&temp));
+        for idx in 0..1500u32 {
+            let mut tx = db.tx();
+            tx.put(&key(idx * 3 + 1), b"old").expect("put");
+            tx.commit();
+        }
+
+        let stats = db.replace_all(new_entries.clone()).wait().expect("replace");
+        assert_eq!(stats.entries, 2000);
+        assert!(stats.leaves > 1);
+        assert!(stats.inner_nodes >= 1);
+        assert!(stats.replaced_leaves >= 1);
+        assert_eq!(scan(&db), new_entries);
+
+        // the new tree takes writes and splits like any other
+        for idx in 0..500u32 {
+            let mut tx = db.tx();
+            tx.put(&key(idx * 2 + 1), b"after").expect("put");
+            tx.commit();
+        }
+        assert_eq!(scan(&db).len(), 2500);
+    }
+
+    let db = QuickStep::new(config(&temp));
+    let entries = scan(&db);
+    assert_eq!(entries.len(), 2500);
+    assert_eq!(db.get(&key(10)).expect("get"), Some(b"new-5".to_vec()));
+    assert_eq!(db.get(&key(11)).expect("get"), Some(b"after".to_vec()));
+    assert_eq!(db.get(&key(1003)).expect("get"), None);
+}
+
+#[test]
+fn replace_all_with_nothing_empties_the_store() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(&temp));
+        for idx in 0..300u32 {
+            let mut tx = db.tx();
+            tx.put(&key(idx), b"value").expect("put");
+            tx.commit();
+        }
+        let stats = db
+            .replace_all(Vec::<(Vec<u8>, Vec<u8>)>::new())
+            .wait()
+            .expect("replace");
+        assert_eq!((stats.entries, stats.leaves, stats.inner_nodes), (0, 1, 0));
+        assert!(scan(&db).is_empty());
+    }
+    let db = QuickStep::new(config(&temp));
+    assert!(scan(&db).is_empty());
+}
+
+#[test]
+fn a_locked_leaf_fails_the_swap_and_keeps_the_old_tree() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(&temp).with_lock_timeout(Duration::from_millis(20)));
+    for idx in 0..1000u32 {
+        let mut tx = db.tx();
+        tx.put(&key(idx), b"old").expect("put");
+        tx.commit();
+    }
+    let old = scan(&db);
+    let live_pages = |db: &QuickStep| {
+        let stats = db.capacity_stats();
+        stats.disk_pages_used - stats.disk_pages_free
+    };
+    let live_before = live_pages(&db);
+
+    let mut writer = db.tx();
+    writer.put(&key(500), b"held").expect("put");
+    let entries = (0..1000u32).map(|idx| (key(idx), b"new".to_vec()));
+    assert!(db.replace_all(entries.clone()).wait().is_err());
+    writer.abort();
+    assert_eq!(scan(&db), old);
+    // the leaves built for the failed swap went back to the free list
+    assert_eq!(live_pages(&db), live_before);
+
+    db.replace_all(entries).wait().expect("replace");
+    assert!(scan(&db).iter().all(|(_, value)| value == b"new"));
+    assert_eq!(scan(&db).len(), 1000);
+}
+
+#[test]
+fn the_caller_reads_on_while_the_tree_builds_and_close_waits_for_it() {
+    let temp = TempDir::new().expect("tempdir");
+    let new_entries: Vec<_> = (0..5000u32)
+        .map(|idx| (key(idx), b"new".to_vec()))
+        .collect();
+    {
+        let db = QuickStep::new(config(&temp));
+        for idx in 0..1000u32 {
+            let mut tx = db.tx();
+            tx.put(&key(idx), b"old").expect("put");
+            tx.commit();
+        }
+
+        let handle = db.replace_all(new_entries.clone());
+        while !handle.is_finished() {
+            let value = db.get(&key(7)).expect("get");
+            assert!(matches!(value.as_deref(), Some(b"old" | b"new")));
+        }
+        // dropped unwaited, the store still finishes the swap before it closes
+        drop(handle);
+        let handle = db.replace_all(new_entries.clone());
+        drop(handle);
+    }
+
+    let db = QuickStep::new(config(&temp));
+    assert_eq!(scan(&db), new_entries);
+}
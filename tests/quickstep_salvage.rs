@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use quickstep::{error::QSError, storage::Storage, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 16)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+fn value(idx: u32) -> Vec<u8> {
+    format!("value-{idx}").into_bytes()
+}
+
+#[test]
+fn salvage_copies_intact_leaves_and_reports_corrupt_ones() {
+    let temp = TempDir::new().expect("tempdir");
+    let source = temp.path().join("source");
+    let db = QuickStep::new(config(&source));
+    for idx in 0..1500 {
+        let mut tx = db.tx();
+        tx.put(&key(idx), &value(idx)).expect("put");
+        tx.commit();
+    }
+    for idx in (0..1500).step_by(7) {
+        assert!(db.delete(&key(idx)).expect("delete"));
+    }
+    db.close().expect("close");
+
+    {
+        // leaf 1 sits after the superblock page and leaf 0
+        let file = Storage::open(&source.join("quickstep.db")).expect("open");
+        let mut byte = [0u8; 1];
+        file.read_exact_at(&mut byte, 2 * 4096 + 2048)
+            .expect("read byte");
+        byte[0] ^= 0x01;
+        file.write_all_at(&byte, 2 * 4096 + 2048)
+            .expect("write byte");
+    }
+
+    let output = temp.path().join("salvaged");
+    let report = QuickStep::salvage(config(&source), &output).expect("salvage");
+    assert_eq!(report.corrupt_pages.len(), 1);
+    assert_eq!(report.corrupt_pages[0].addr, 1);
+    assert!(report.corrupt_pages[0].reason.contains("PageCorrupt"));
+    assert!(report.leaves_salvaged > 1);
+    assert_eq!(report.duplicate_keys, 0);
+
+    let salvaged = QuickStep::open(config(&output)).expect("open salvaged");
+    let (mut kept, mut lost) = (0, 0);
+    let mut tx = salvaged.tx();
+    for idx in 0..1500 {
+        match tx.get(&key(idx)).expect("get") {
+            Some(found) => {
+                assert_ne!(idx % 7, 0, "deleted key {idx} came back");
+                assert_eq!(found, value(idx));
+                kept += 1;
+            }
+            None if idx % 7 != 0 => lost += 1,
+            None => {}
+        }
+    }
+    tx.commit();
+    assert_eq!(kept, report.keys_salvaged);
+    assert!(lost > 0, "the corrupt leaf held live keys");
+    assert!(salvaged.verify().expect("verify").is_ok());
+    drop(salvaged);
+
+    assert!(matches!(
+        QuickStep::salvage(config(&source), &output),
+        Err(QSError::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists
+    ));
+}
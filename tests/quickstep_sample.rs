@@ -0,0 +1,76 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key-{i:05}").into_bytes()
+}
+
+fn loaded(temp: &TempDir, count: usize) -> QuickStep {
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 20));
+    let mut tx = db.tx();
+    for i in 0..count {
+        tx.put(&key(i), &[7u8; 200]).expect("insert");
+    }
+    tx.commit();
+    db
+}
+
+#[test]
+fn samples_are_distinct_stored_keys_in_order() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = loaded(&temp, 2000);
+    assert!(
+        db.debug_root_level() > 0,
+        "the load should span many leaves"
+    );
+
+    let sample = db.sample_keys(100).expect("sample");
+    assert_eq!(sample.len(), 100);
+    assert!(sample.windows(2).all(|pair| pair[0] < pair[1]));
+    for key in &sample {
+        assert!(db.get(key).expect("get").is_some());
+    }
+}
+
+#[test]
+fn samples_spread_over_the_key_space() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = loaded(&temp, 2000);
+
+    // over several draws both halves of the keys should turn up roughly evenly
+    let mut low = 0;
+    let mut total = 0;
+    for _ in 0..10 {
+        for sampled in db.sample_keys(50).expect("sample") {
+            total += 1;
+            if sampled < key(1000) {
+                low += 1;
+            }
+        }
+    }
+    assert_eq!(total, 500);
+    assert!(
+        (150..=350).contains(&low),
+        "{low} of {total} below the median"
+    );
+}
+
+#[test]
+fn asking_for_more_than_stored_returns_every_live_key() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = loaded(&temp, 40);
+    db.delete(&key(3)).expect("delete");
+    db.soft_delete(&key(4)).expect("soft delete");
+
+    let sample = db.sample_keys(1000).expect("sample");
+    let expected: Vec<_> = (0..40).filter(|i| ![3, 4].contains(i)).map(key).collect();
+    assert_eq!(sample, expected);
+    assert!(db.sample_keys(0).expect("sample").is_empty());
+}
+
+#[test]
+fn an_empty_store_samples_nothing() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 20));
+    assert!(db.sample_keys(10).expect("sample").is_empty());
+}
@@ -0,0 +1,60 @@
+use quickstep::sidecar::SidecarLog;
+use std::fs;
+use tempfile::TempDir;
+
+/// Entries of one length byte followed by that many bytes
+fn entries(log: &mut SidecarLog) -> Vec<Vec<u8>> {
+    let mut entries = Vec::new();
+    log.replay(|bytes, pos| {
+        let len = *bytes.get(pos)? as usize;
+        entries.push(bytes.get(pos + 1..pos + 1 + len)?.to_vec());
+        Some(pos + 1 + len)
+    })
+    .expect("replay");
+    entries
+}
+
+#[test]
+fn a_sidecar_log_cuts_a_torn_entry_and_carries_on_after_it() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("quickstep.side");
+    let mut log = SidecarLog::open(&path).expect("open");
+    log.append(b"\x03one\x03two").expect("append");
+    log.sync().expect("sync");
+    drop(log);
+
+    // a crash part way through the third entry
+    let mut bytes = fs::read(&path).expect("read log");
+    bytes.extend_from_slice(b"\x05thr");
+    fs::write(&path, bytes).expect("write log");
+
+    let mut log = SidecarLog::open(&path).expect("reopen");
+    assert_eq!(entries(&mut log), [b"one".to_vec(), b"two".to_vec()]);
+    log.append(b"\x04four").expect("append");
+    drop(log);
+    let mut log = SidecarLog::open(&path).expect("reopen");
+    assert_eq!(entries(&mut log).len(), 3);
+}
+
+#[test]
+fn a_rewritten_sidecar_log_replaces_the_file_in_one_step() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("quickstep.side");
+    let mut log = SidecarLog::open(&path).expect("open");
+    log.append(b"\x03old\x03old").expect("append");
+    log.rewrite(b"\x03new").expect("rewrite");
+    log.append(b"\x05after").expect("append after rewrite");
+    drop(log);
+
+    let names: Vec<_> = fs::read_dir(temp.path())
+        .expect("list dir")
+        .map(|entry| entry.expect("entry").file_name())
+        .collect();
+    assert_eq!(
+        names,
+        ["quickstep.side"],
+        "the temporary file is left behind"
+    );
+    let mut log = SidecarLog::open(&path).expect("reopen");
+    assert_eq!(entries(&mut log), [b"new".to_vec(), b"after".to_vec()]);
+}
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use quickstep::{
+    error::QSError,
+    health::WalLimitPolicy,
+    limits::{SizeLimits, MAX_INLINE_VALUE_LEN, MAX_KEY_LEN, MAX_VALUE_LEN},
+    merge::MergeOperator,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+struct Append;
+
+impl MergeOperator for Append {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Vec<u8> {
+        let mut value = existing.unwrap_or_default().to_vec();
+        value.extend_from_slice(operand);
+        value
+    }
+}
+
+#[test]
+fn format_limits_are_the_default_and_fit_a_leaf() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    assert_eq!(
+        db.size_limits(b"any"),
+        SizeLimits {
+            max_key_len: MAX_KEY_LEN,
+            max_value_len: MAX_VALUE_LEN,
+        }
+    );
+
+    // enough of the largest records kept in a leaf to split it several times
+    let value = vec![5u8; MAX_INLINE_VALUE_LEN];
+    for idx in 0..8u8 {
+        let mut key = vec![b'k'; MAX_KEY_LEN];
+        key[MAX_KEY_LEN - 1] = idx;
+        let mut tx = db.tx();
+        tx.put(&key, &value).expect("largest record fits");
+        tx.commit();
+        assert_eq!(db.get(&key).unwrap(), Some(value.clone()));
+    }
+
+    let mut tx = db.tx();
+    assert!(matches!(
+        tx.put(&[b'k'; MAX_KEY_LEN + 1], b"v"),
+        Err(QSError::KeyTooLong { len, max }) if len == MAX_KEY_LEN + 1 && max == MAX_KEY_LEN
+    ));
+    assert!(matches!(
+        tx.put(b"key", &vec![0u8; MAX_VALUE_LEN + 1]),
+        Err(QSError::ValueTooLong { max, .. }) if max == MAX_VALUE_LEN
+    ));
+    tx.abort();
+}
+
+#[test]
+fn configured_limits_are_clamped_and_overridden_per_keyspace() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = QuickStepConfig::new(temp.path(), 32, 256, 14)
+        .with_max_key_len(16)
+        .with_max_value_len(usize::MAX)
+        .with_keyspace_limits(b"small/", SizeLimits::new(32, 4))
+        .with_keyspace_limits(b"small/big/", SizeLimits::new(32, 64))
+        .with_merge_operator(Append);
+    assert_eq!(config.size_limits(b"other").max_value_len, MAX_VALUE_LEN);
+    let db = QuickStep::new(config);
+
+    assert_eq!(db.size_limits(b"plain"), SizeLimits::new(16, MAX_VALUE_LEN));
+    assert_eq!(db.size_limits(b"small/x"), SizeLimits::new(32, 4));
+    assert_eq!(db.size_limits(b"small/big/x"), SizeLimits::new(32, 64));
+
+    let mut tx = db.tx();
+    assert!(matches!(
+        tx.put(b"a-key-of-17-bytes", b"v"),
+        Err(QSError::KeyTooLong { len: 17, max: 16 })
+    ));
+    tx.put(b"small/a-longer-key", b"1234")
+        .expect("keyspace allows longer keys");
+    assert!(matches!(
+        tx.put(b"small/x", b"12345"),
+        Err(QSError::ValueTooLong { len: 5, max: 4 })
+    ));
+    tx.put(b"small/big/x", b"12345")
+        .expect("longest prefix decides");
+
+    // a merge is held to the limit on its result
+    tx.merge(b"small/y", b"12").expect("first operand");
+    tx.merge(b"small/y", b"34").expect("still within the limit");
+    assert!(matches!(
+        tx.merge(b"small/y", b"5"),
+        Err(QSError::ValueTooLong { len: 5, max: 4 })
+    ));
+    tx.commit();
+    assert_eq!(db.get(b"small/y").unwrap(), Some(b"1234".to_vec()));
+}
+
+#[test]
+fn oversized_writes_are_reported_before_the_wal_limit() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        QuickStepConfig::new(temp.path(), 64, 256, 20)
+            .with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+            .with_max_wal_size(4 * 1024)
+            .with_wal_limit_policy(WalLimitPolicy::Fail)
+            .with_max_value_len(64)
+            .with_merge_operator(Append),
+    );
+    // the holder's leaf stays locked, so the log cannot be checkpointed below its limit
+    let mut holder = db.tx();
+    let mut idx = 0u32;
+    while db.debug_wal_stats(None).unwrap().total_bytes < 4 * 1024 {
+        holder
+            .put(format!("held-{idx:04}").as_bytes(), &[1u8; 16])
+            .expect("holder put");
+        idx += 1;
+    }
+
+    let mut tx = db.tx();
+    assert!(matches!(
+        tx.put(b"other", b"value"),
+        Err(QSError::WalFull { .. })
+    ));
+    assert!(matches!(
+        tx.put(b"other", &[0u8; 65]),
+        Err(QSError::ValueTooLong { len: 65, max: 64 })
+    ));
+    assert!(matches!(
+        tx.put_with_ttl(&[b'k'; MAX_KEY_LEN + 1], b"v", Duration::from_secs(1)),
+        Err(QSError::KeyTooLong { .. })
+    ));
+    assert!(matches!(
+        tx.merge(&[b'k'; MAX_KEY_LEN + 1], b"v"),
+        Err(QSError::KeyTooLong { .. })
+    ));
+    tx.abort();
+    holder.commit();
+}
@@ -0,0 +1,120 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn open(path: &std::path::Path) -> QuickStep {
+    QuickStep::new(QuickStepConfig::new(path, 32, 256, 14))
+}
+
+fn seed(db: &QuickStep) {
+    let mut tx = db.tx();
+    for idx in 0..4 {
+        let key = format!("key-{idx}");
+        tx.put(key.as_bytes(), format!("value-{idx}").as_bytes())
+            .expect("insert");
+    }
+    tx.commit();
+}
+
+#[test]
+fn soft_deleted_keys_are_hidden_until_undeleted() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = open(temp.path());
+    seed(&db);
+
+    assert!(db.soft_delete(b"key-1").unwrap());
+    assert!(!db.soft_delete(b"key-1").unwrap(), "already hidden");
+    assert!(!db.soft_delete(b"missing").unwrap());
+    assert!(db.is_soft_deleted(b"key-1").unwrap());
+    assert_eq!(db.get(b"key-1").unwrap(), None);
+    let mut tx = db.tx();
+    assert_eq!(tx.get(b"key-1").unwrap(), None);
+    tx.commit();
+
+    let visible: Vec<_> = db
+        .range_scan(b"key-", b"key-~")
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(
+        visible,
+        vec![b"key-0".to_vec(), b"key-2".to_vec(), b"key-3".to_vec()]
+    );
+    let all = db
+        .range_iter(b"key-", b"key-~")
+        .include_soft_deleted()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[1], (b"key-1".to_vec(), b"value-1".to_vec()));
+
+    assert!(db.undelete(b"key-1").unwrap());
+    assert!(!db.undelete(b"key-1").unwrap());
+    assert_eq!(db.get(b"key-1").unwrap(), Some(b"value-1".to_vec()));
+}
+
+#[test]
+fn soft_deletes_survive_reopen_and_writes_clear_them() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = open(temp.path());
+        seed(&db);
+        assert!(db.soft_delete(b"key-0").unwrap());
+        assert!(db.soft_delete(b"key-2").unwrap());
+    }
+
+    let db = open(temp.path());
+    assert!(db.is_soft_deleted(b"key-0").unwrap());
+    assert_eq!(db.get(b"key-2").unwrap(), None);
+
+    let mut tx = db.tx();
+    tx.put(b"key-0", b"fresh").unwrap();
+    // a hard delete of a hidden key reports it absent
+    assert!(!tx.delete(b"key-2").unwrap());
+    tx.commit();
+
+    assert!(!db.is_soft_deleted(b"key-0").unwrap());
+    assert_eq!(db.get(b"key-0").unwrap(), Some(b"fresh".to_vec()));
+    assert!(!db.is_soft_deleted(b"key-2").unwrap());
+    assert!(!db.undelete(b"key-2").unwrap());
+    assert_eq!(db.get(b"key-2").unwrap(), None);
+}
+
+#[test]
+fn aborted_soft_delete_changes_nothing() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = open(temp.path());
+    seed(&db);
+
+    let mut tx = db.tx();
+    assert!(tx.soft_delete(b"key-3").unwrap());
+    assert_eq!(
+        tx.get(b"key-3").unwrap(),
+        None,
+        "visible to its own transaction"
+    );
+    tx.abort();
+
+    assert!(!db.is_soft_deleted(b"key-3").unwrap());
+    assert_eq!(db.get(b"key-3").unwrap(), Some(b"value-3".to_vec()));
+}
+
+#[test]
+fn soft_deletes_are_replayed_from_the_wal_after_a_crash() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = open(temp.path());
+        seed(&db);
+        assert!(db.soft_delete(b"key-1").unwrap());
+        assert!(db.soft_delete(b"key-2").unwrap());
+        assert!(db.undelete(b"key-2").unwrap());
+        db.debug_close_without_flush();
+    }
+
+    let db = open(temp.path());
+    assert!(db.is_soft_deleted(b"key-1").unwrap());
+    assert_eq!(db.get(b"key-1").unwrap(), None);
+    assert!(db.undelete(b"key-1").unwrap());
+    assert_eq!(db.get(b"key-1").unwrap(), Some(b"value-1".to_vec()));
+    assert_eq!(db.get(b"key-2").unwrap(), Some(b"value-2".to_vec()));
+}
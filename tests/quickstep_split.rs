@@ -1,11 +1,11 @@
-use quickstep::{debug, QuickStep, QuickStepConfig};
+use quickstep::{debug, invariants, page_op::SplitPoint, QuickStep, QuickStepConfig};
 use std::collections::HashSet;
 use tempfile::TempDir;
 
 fn new_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
     // keep the same parameters as other integration tests
-    let config = QuickStepConfig::new(temp.into_path(), 32, 256, 14);
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14);
     QuickStep::new(config)
 }
 
@@ -116,11 +116,13 @@ fn post_split_inserts_route_to_expected_children() {
     let payload = vec![0u8; 1024];
     let mut inserted = 0usize;
 
+    // descending keys split the root evenly, leaving room on both sides; ascending ones
+    // would leave the left child full
     {
         let mut tx = db.tx();
         while debug::split_requests() == 0 {
             assert!(inserted < 128, "expected a root split within 128 inserts");
-            let key = format!("key-{inserted:04}");
+            let key = format!("key-{:04}", 127 - inserted);
             tx.put(key.as_bytes(), &payload)
                 .expect("insert before split");
             inserted += 1;
@@ -307,10 +309,14 @@ fn second_split_under_root_adds_third_child() {
         left_snapshot.keys.len(),
         "first split left_count should match left snapshot"
     );
-    assert_eq!(
-        events[0].right_count,
-        middle_snapshot.keys.len(),
-        "first split right_count should match middle snapshot"
+    // ascending keys keep the split leaf nearly full and fill the new one until it splits
+    assert!(
+        events[0].left_count > events[0].right_count,
+        "an append should keep most of the leaf on the left"
+    );
+    assert!(
+        events[0].right_count <= middle_snapshot.keys.len(),
+        "the middle child only gains keys after the first split"
     );
     assert_eq!(
         events[1].left_count,
@@ -358,7 +364,9 @@ fn second_split_under_root_adds_third_child() {
 #[test]
 fn root_parent_splits_and_promotes_new_inner_level() {
     debug::reset_debug_counters();
-    let db = new_db();
+    // prefix-compressed pivots fill a leaf parent only after hundreds of leaves
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.keep(), 32, 4096, 14));
     let payload = vec![0u8; 512];
     let mut inserted = 0usize;
 
@@ -385,6 +393,32 @@ fn root_parent_splits_and_promotes_new_inner_level() {
     );
 }
 
+#[test]
+fn split_halves_partition_the_leaf_and_keep_their_pages() {
+    let db = new_db();
+    let payload = vec![4u8; 512];
+    let mut inserted = Vec::new();
+    while db.debug_root_leaf_parent().is_none() {
+        let key = format!("key-{:04}", inserted.len()).into_bytes();
+        let mut tx = db.tx();
+        tx.put(&key, &payload).expect("insert");
+        tx.commit();
+        inserted.push(key);
+    }
+
+    let snapshot = db.debug_root_leaf_parent().unwrap();
+    let left = db.debug_leaf_snapshot(snapshot.children[0]).unwrap();
+    let right = db.debug_leaf_snapshot(snapshot.children[1]).unwrap();
+    assert_eq!((left.page_id, left.disk_addr), (snapshot.children[0], 0));
+    assert_eq!(right.page_id, snapshot.children[1]);
+    assert_ne!(right.disk_addr, left.disk_addr);
+    assert!(left.keys.iter().all(|key| key < &snapshot.pivots[0]));
+    assert!(right.keys.iter().all(|key| key >= &snapshot.pivots[0]));
+    let mut keys = left.keys;
+    keys.extend(right.keys);
+    assert_eq!(keys, inserted);
+}
+
 fn parse_key_index(key: &[u8]) -> u32 {
     let key_str = std::str::from_utf8(key).expect("utf8 key");
     let digits = key_str
@@ -393,3 +427,110 @@ fn parse_key_index(key: &[u8]) -> u32 {
     let numeric = &digits[..4];
     numeric.parse().expect("parse key digits")
 }
+
+#[test]
+fn ascending_inserts_split_less_often_than_descending_ones() {
+    let payload = vec![6u8; 200];
+    let splits = |keys: Vec<usize>| {
+        let db = new_db();
+        for idx in keys {
+            let mut tx = db.tx();
+            tx.put(format!("key-{idx:04}").as_bytes(), &payload)
+                .expect("insert");
+            tx.commit();
+        }
+        db.stats().splits
+    };
+
+    let ascending = splits((0..400).collect());
+    let descending = splits((0..400).rev().collect());
+    assert!(
+        ascending * 3 < descending * 2,
+        "appends should fill leaves: {ascending} splits ascending, {descending} descending"
+    );
+}
+
+/// Leaf sizes after the first split of a store using `point` and `append`, filled with
+/// `keys` in order and the value `value(idx)` for each
+fn first_split(
+    point: SplitPoint,
+    append: Option<u8>,
+    keys: impl Iterator<Item = usize>,
+    value: impl Fn(usize) -> usize,
+) -> (usize, usize) {
+    let temp = TempDir::new().expect("tempdir");
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14)
+        .with_split_point(point)
+        .with_append_split(append);
+    let db = QuickStep::new(config);
+    for idx in keys {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx:04}").as_bytes(), &vec![1u8; value(idx)])
+            .expect("insert");
+        tx.commit();
+        if let Some(root) = db.debug_root_leaf_parent() {
+            let len = |child| db.debug_leaf_snapshot(child).expect("snapshot").keys.len();
+            return (len(root.children[0]), len(root.children[1]));
+        }
+    }
+    panic!("no split");
+}
+
+#[test]
+fn configured_split_points_divide_the_leaf() {
+    let descending = || (0..200).rev();
+    let (left, right) = first_split(SplitPoint::EvenCount, None, descending(), |_| 200);
+    assert!(left.abs_diff(right) <= 1, "{left} / {right}");
+    let (left, right) = first_split(
+        SplitPoint::Ratio { left_percent: 75 },
+        None,
+        descending(),
+        |_| 200,
+    );
+    assert!(left > 2 * right, "{left} / {right}");
+
+    // the few long values at the high end weigh as much as the many short ones
+    let value = |idx| match idx {
+        197.. => 900,
+        _ => 60,
+    };
+    let (left, right) = first_split(SplitPoint::EvenBytes, None, descending(), value);
+    assert!(right * 4 < left, "{left} / {right}");
+    let (left, right) = first_split(SplitPoint::EvenCount, None, descending(), value);
+    assert!(left.abs_diff(right) <= 1, "{left} / {right}");
+
+    // appends keep their own share unless it is turned off
+    let (left, right) = first_split(SplitPoint::EvenCount, Some(80), 0..200, |_| 200);
+    assert!(left > 3 * right, "{left} / {right}");
+    let (left, right) = first_split(SplitPoint::EvenCount, None, 0..200, |_| 200);
+    assert!(left.abs_diff(right) <= 1, "{left} / {right}");
+}
+
+#[test]
+fn pivots_keep_only_the_bytes_that_separate_the_halves() {
+    let db = new_db();
+    let tail = "/".repeat(56);
+    let key = |idx: usize| format!("{idx:04}{tail}").into_bytes();
+    for idx in (0..120).rev() {
+        let mut tx = db.tx();
+        tx.put(&key(idx), &[3u8; 64]).expect("insert");
+        tx.commit();
+    }
+
+    let root = db.debug_root_leaf_parent().expect("split root");
+    assert!(root.pivots.len() >= 2);
+    for (pivot, children) in root.pivots.iter().zip(root.children.windows(2)) {
+        assert!(pivot.len() <= 4, "pivot {pivot:?} carries the shared tail");
+        let left = db.debug_leaf_snapshot(children[0]).expect("left");
+        let right = db.debug_leaf_snapshot(children[1]).expect("right");
+        assert!(left.keys.iter().all(|key| key < pivot));
+        assert!(right.keys.iter().all(|key| key >= pivot));
+    }
+    invariants::check_all(&db).expect("tree invariants");
+
+    let mut tx = db.tx();
+    for idx in 0..120 {
+        assert!(tx.get(&key(idx)).expect("get").is_some(), "missing {idx}");
+    }
+    tx.commit();
+}
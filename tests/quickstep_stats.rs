@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 20).with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+}
+
+fn key(idx: u32) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+#[test]
+fn operations_are_counted_and_timed() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    assert_eq!(db.stats().gets.count, 0);
+
+    let value = vec![7u8; 200];
+    let mut tx = db.tx();
+    for idx in 0..400 {
+        tx.put(&key(idx), &value).expect("put");
+    }
+    for idx in 0..10 {
+        tx.delete(&key(idx)).expect("delete");
+    }
+    tx.commit();
+    for idx in 0..100 {
+        db.get(&key(idx)).expect("get");
+    }
+
+    let stats = db.stats();
+    assert_eq!(stats.puts.count, 400);
+    assert_eq!(stats.deletes.count, 10);
+    assert_eq!(stats.gets.count, 100);
+    for op in [stats.gets, stats.puts, stats.deletes] {
+        assert!(op.p50 <= op.p99 && op.p99 <= op.max, "{op:?}");
+        assert!(!op.max.is_zero());
+    }
+    // 80KB of values do not fit one leaf
+    assert!(stats.splits > 0);
+    assert!(stats.wal_bytes > 0);
+    // freshly written keys sit in their mini-pages
+    assert!(stats.cache_hits > 0);
+    assert_eq!(stats.checkpoints, 0);
+
+    db.checkpoint().expect("checkpoint");
+    assert!(db.stats().checkpoints > 0);
+}
+
+#[test]
+fn reads_of_cold_leaves_go_to_disk() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        let mut tx = db.tx();
+        for idx in 0..100 {
+            tx.put(&key(idx), b"value").expect("put");
+        }
+        tx.commit();
+    }
+
+    // without the leaf cache every disk leaf read reaches the file
+    let db = QuickStep::new(config(temp.path()).with_leaf_cache_pages(0));
+    let before = db.stats();
+    assert_eq!(db.get(&key(50)).expect("get"), Some(b"value".to_vec()));
+    let after = db.stats();
+    assert_eq!(after.cache_misses, before.cache_misses + 1);
+    assert!(after.disk_reads > before.disk_reads);
+    assert!(after.cache_hit_ratio() < 1.0);
+}
@@ -0,0 +1,258 @@
+use std::fs;
+
+use quickstep::{
+    map_table::PageId,
+    wal::{WalEntryKind, WalManager, WalOp, WalSmo, WalStatsLevel, STRUCTURE_PAGE_ID},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn children(db: &QuickStep) -> usize {
+    db.debug_root_leaf_parent()
+        .map_or(1, |snapshot| snapshot.children.len())
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+/// Insert keys from `start` until the root has `target` children, returning the next index
+fn fill_until(db: &QuickStep, start: usize, target: usize) -> usize {
+    let payload = vec![9u8; 64];
+    let mut idx = start;
+    while children(db) < target {
+        let mut tx = db.tx();
+        tx.put(&key(idx), &payload).expect("insert");
+        tx.commit();
+        idx += 1;
+    }
+    idx
+}
+
+#[test]
+fn a_root_split_survives_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    let (inserted, before) = {
+        let db = QuickStep::new(config());
+        let inserted = fill_until(&db, 0, 3);
+        (inserted, db.debug_root_leaf_parent().expect("root split"))
+    };
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.last_recovery_report().structure_changes, 2);
+    let after = db.debug_root_leaf_parent().expect("root split restored");
+    assert_eq!(after.children, before.children);
+    assert_eq!(after.pivots, before.pivots);
+    for idx in 0..inserted {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(vec![9u8; 64]), "key {idx}");
+    }
+
+    // new splits take page ids past the restored leaves
+    fill_until(&db, inserted, 4);
+    let newest = before
+        .children
+        .iter()
+        .map(|page| page.as_u64())
+        .max()
+        .unwrap();
+    let grown = db.debug_root_leaf_parent().unwrap();
+    assert!(grown
+        .children
+        .iter()
+        .all(|page| before.children.contains(page) || page.as_u64() > newest));
+    for idx in (0..inserted).step_by(5) {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(vec![9u8; 64]), "key {idx}");
+    }
+}
+
+#[test]
+fn a_root_demotion_survives_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    {
+        let db = QuickStep::new(config());
+        fill_until(&db, 0, 2);
+        let snapshot = db.debug_root_leaf_parent().expect("split");
+        let (left, right) = (snapshot.children[0], snapshot.children[1]);
+        db.debug_truncate_leaf(left, 3, false).expect("shrink left");
+        db.debug_truncate_leaf(right, 2, false)
+            .expect("shrink right");
+        db.debug_merge_leaves(left, right).expect("merge");
+        assert!(db.debug_root_leaf_parent().is_none());
+    }
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.last_recovery_report().structure_changes, 2);
+    assert!(db.debug_root_leaf_parent().is_none());
+    assert_eq!(db.get(&key(0)).unwrap(), Some(vec![9u8; 64]));
+    assert_eq!(db.get(&key(10)).unwrap(), None);
+}
+
+#[test]
+fn writes_after_a_split_replay_into_the_right_leaf() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    let inserted = {
+        let db = QuickStep::new(config());
+        let inserted = fill_until(&db, 0, 2);
+        let mut tx = db.tx();
+        tx.put(&key(inserted + 100), b"late").expect("insert");
+        tx.put(&key(1), b"updated").expect("update");
+        tx.commit();
+        assert!(db.debug_wal_stats(None).unwrap().total_records > 0);
+        db.debug_close_without_flush();
+        inserted
+    };
+
+    let db = QuickStep::new(config());
+    assert!(db.last_recovery_report().records_replayed >= 2);
+    assert_eq!(children(&db), 2);
+    assert_eq!(
+        db.get(&key(inserted + 100)).unwrap(),
+        Some(b"late".to_vec())
+    );
+    assert_eq!(db.get(&key(1)).unwrap(), Some(b"updated".to_vec()));
+    assert_eq!(db.get(&key(inserted - 1)).unwrap(), Some(vec![9u8; 64]));
+}
+
+#[test]
+fn structure_records_outlive_checkpoints() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("structure.wal");
+    {
+        let wal = WalManager::open(&path).expect("open wal");
+        wal.append_put(
+            PageId::from_u64(0),
+            b"b",
+            b"value",
+            b"",
+            b"",
+            WalEntryKind::Redo,
+            1,
+        )
+        .expect("put");
+        wal.append_put(
+            PageId::from_u64(0),
+            b"m",
+            b"value",
+            b"",
+            b"",
+            WalEntryKind::Redo,
+            1,
+        )
+        .expect("put");
+        wal.append_split(PageId::from_u64(0), PageId::from_u64(1), 7, b"k")
+            .expect("split");
+        wal.append_smo(
+            WalSmo::Merge {
+                survivor: 0,
+                removed: 1,
+            },
+            b"b",
+        )
+        .expect("merge");
+
+        let records = wal.records();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.page_id, record.upper_fence.clone()))
+                .take(2)
+                .collect::<Vec<_>>(),
+            vec![(0, b"k".to_vec()), (1, Vec::new())]
+        );
+        assert_eq!(records[1].lower_fence, b"k");
+        wal.checkpoint_page(PageId::from_u64(0))
+            .expect("checkpoint");
+        wal.clear_leaf_records().expect("clear");
+    }
+
+    let wal = WalManager::open(&path).expect("reopen wal");
+    let smos: Vec<_> = wal
+        .records()
+        .into_iter()
+        .map(|record| {
+            assert_eq!(record.page_id, STRUCTURE_PAGE_ID);
+            match record.op {
+                WalOp::Smo(smo) => (smo, record.key),
+                op => panic!("unexpected {op:?}"),
+            }
+        })
+        .collect();
+    assert_eq!(
+        smos,
+        vec![
+            (
+                WalSmo::Split {
+                    left: 0,
+                    right: 1,
+                    right_addr: 7,
+                },
+                b"k".to_vec(),
+            ),
+            (
+                WalSmo::Merge {
+                    survivor: 0,
+                    removed: 1,
+                },
+                b"b".to_vec(),
+            ),
+        ]
+    );
+    let stats = wal.stats(WalStatsLevel::Pages).unwrap();
+    assert_eq!(stats.structure, stats.total);
+    assert!(stats.pages.is_empty());
+}
+
+#[test]
+fn a_lost_log_rebuilds_the_tree_from_the_leaves_on_disk() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    let (inserted, before) = {
+        let db = QuickStep::new(config());
+        let inserted = fill_until(&db, 0, 4);
+        (inserted, db.debug_root_leaf_parent().expect("split"))
+    };
+    // a reopen replays the leaf records onto the disk, leaving only structure records
+    drop(QuickStep::new(config()));
+    fs::remove_file(temp.path().join("quickstep.wal")).expect("remove wal");
+
+    let db = QuickStep::new(config());
+    let report = db.last_recovery_report();
+    assert_eq!((report.leaves_scanned, report.structure_changes), (4, 3));
+    let after = db.debug_root_leaf_parent().expect("tree rebuilt");
+    assert_eq!(after.children, before.children);
+    assert_eq!(after.pivots, before.pivots);
+    for idx in 0..inserted {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(vec![9u8; 64]), "key {idx}");
+    }
+    drop(db);
+
+    // the scan was logged, the next open replays it
+    let db = QuickStep::new(config());
+    let report = db.last_recovery_report();
+    assert_eq!((report.leaves_scanned, report.structure_changes), (0, 3));
+    assert_eq!(
+        db.debug_root_leaf_parent().unwrap().children,
+        before.children
+    );
+}
+
+#[test]
+fn a_single_leaf_file_needs_no_scan() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    {
+        let db = QuickStep::new(config());
+        let mut tx = db.tx();
+        tx.put(b"only", b"leaf").expect("insert");
+        tx.commit();
+    }
+    drop(QuickStep::new(config()));
+    fs::remove_file(temp.path().join("quickstep.wal")).expect("remove wal");
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.last_recovery_report().leaves_scanned, 0);
+    assert_eq!(db.get(b"only").unwrap(), Some(b"leaf".to_vec()));
+}
@@ -0,0 +1,63 @@
+use std::{thread, time::Duration};
+
+use quickstep::{wal::SyncPolicy, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn commit_puts(db: &QuickStep, count: usize) {
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx:03}").as_bytes(), b"value")
+            .expect("put");
+        tx.commit();
+    }
+}
+
+#[test]
+fn per_commit_syncs_every_commit() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    let before = db.wal_syncs();
+    commit_puts(&db, 8);
+    assert_eq!(db.wal_syncs() - before, 8);
+}
+
+#[test]
+fn never_leaves_commits_to_the_os_but_keeps_them_on_close() {
+    let temp = TempDir::new().expect("tempdir");
+    let config =
+        || QuickStepConfig::new(temp.path(), 32, 256, 14).with_sync_policy(SyncPolicy::Never);
+    {
+        let db = QuickStep::new(config());
+        let before = db.wal_syncs();
+        commit_puts(&db, 8);
+        assert_eq!(db.wal_syncs(), before, "commits do not wait for a sync");
+    }
+
+    let db = QuickStep::new(config());
+    let mut tx = db.tx();
+    assert_eq!(tx.get(b"key-007").unwrap(), Some(&b"value"[..]));
+    tx.commit();
+}
+
+#[test]
+fn interval_policy_syncs_in_the_background() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(
+        QuickStepConfig::new(temp.path(), 32, 256, 14)
+            .with_sync_policy(SyncPolicy::Every(Duration::from_millis(20))),
+    );
+    let before = db.wal_syncs();
+    commit_puts(&db, 8);
+    assert!(
+        db.wal_syncs() - before < 8,
+        "commits share the interval syncs"
+    );
+
+    let synced = db.wal_syncs();
+    commit_puts(&db, 1);
+    thread::sleep(Duration::from_millis(100));
+    assert!(
+        db.wal_syncs() > synced,
+        "the background thread syncs pending commits"
+    );
+}
@@ -0,0 +1,64 @@
+use quickstep::{
+    debug::{self, DumpChild},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+#[test]
+fn dump_follows_the_tree_from_root_to_leaves() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 64, 1024, 16));
+    let dump = debug::dump_tree(&db);
+    assert_eq!(dump.root, Some(DumpChild::Leaf(0)));
+    assert!(dump.inner_nodes.is_empty());
+    assert_eq!(dump.leaves.len(), 1);
+    assert_eq!(dump.leaves[0].records, 0);
+
+    for idx in 0..1500u32 {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx:05}").as_bytes(), &[7u8; 64])
+            .expect("put");
+        tx.commit();
+    }
+    db.checkpoint().expect("checkpoint");
+
+    let dump = debug::dump_tree(&db);
+    assert!(matches!(dump.root, Some(DumpChild::Inner(_))));
+    assert!(!dump.inner_nodes.is_empty());
+    let leaf_children: usize = dump
+        .inner_nodes
+        .iter()
+        .filter(|node| node.level == 1)
+        .map(|node| node.children.len())
+        .sum();
+    assert_eq!(leaf_children, dump.leaves.len());
+    assert_eq!(
+        dump.leaves.iter().map(|leaf| leaf.records).sum::<usize>(),
+        1500
+    );
+    assert_eq!(dump.leaves[0].lower_fence, [0x00]);
+    assert_eq!(dump.leaves.last().unwrap().upper_fence, [0xFF]);
+    for pair in dump.leaves.windows(2) {
+        assert_eq!(pair[0].upper_fence, pair[1].lower_fence);
+    }
+    for leaf in &dump.leaves {
+        assert!(leaf.error.is_none());
+        assert!(leaf.occupancy() > 0.0 && leaf.occupancy() <= 1.0);
+    }
+
+    let json = dump.to_json();
+    assert!(json.starts_with(r#"{"root":{"inner":"#));
+    assert_eq!(json.matches(r#""page_id":"#).count(), dump.leaves.len());
+    // pivots are hex, "key-" is 6b65792d
+    assert!(json.contains(r#""pivots":["6b65792d"#));
+
+    let dot = debug::dump_tree(&db).to_dot();
+    assert!(dot.starts_with("digraph quickstep {"));
+    let edges: usize = dump
+        .inner_nodes
+        .iter()
+        .map(|node| node.children.len())
+        .sum();
+    assert_eq!(dot.matches(" -> ").count(), edges);
+    assert!(dot.contains("key-"));
+}
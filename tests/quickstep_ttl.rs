@@ -0,0 +1,147 @@
+use std::{
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn open(path: &Path, sweep: Duration) -> QuickStep {
+    QuickStep::new(QuickStepConfig::new(path, 32, 256, 14).with_ttl_sweep_interval(sweep))
+}
+
+/// Poll until `done` holds, failing after a few seconds
+fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn expired_keys_are_hidden_and_purged() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = open(temp.path(), Duration::from_secs(60));
+    {
+        let mut tx = db.tx();
+        tx.put_with_ttl(b"short", b"gone soon", Duration::from_millis(30))
+            .expect("put short");
+        tx.put_with_ttl(b"renewed", b"first", Duration::from_millis(30))
+            .expect("put renewed");
+        tx.put_with_ttl(b"long", b"stays", Duration::from_secs(3600))
+            .expect("put long");
+        tx.put(b"plain", b"forever").expect("put plain");
+        tx.commit();
+    }
+    assert_eq!(db.expiring_keys(), 3);
+    assert_eq!(
+        db.get(b"short").unwrap().as_deref(),
+        Some(&b"gone soon"[..])
+    );
+    assert!(db.ttl(b"plain").is_none());
+
+    {
+        // a plain put clears the expiry
+        let mut tx = db.tx();
+        tx.put(b"renewed", b"second").expect("renew");
+        tx.commit();
+    }
+    thread::sleep(Duration::from_millis(60));
+
+    assert_eq!(db.get(b"short").unwrap(), None);
+    {
+        let mut tx = db.tx();
+        assert_eq!(tx.get(b"short").unwrap(), None);
+        assert_eq!(tx.get(b"renewed").unwrap(), Some(&b"second"[..]));
+        tx.commit();
+    }
+    let keys: Vec<_> = db
+        .range_scan(b"a", b"z")
+        .expect("scan")
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(
+        keys,
+        vec![b"long".to_vec(), b"plain".to_vec(), b"renewed".to_vec()]
+    );
+
+    assert_eq!(db.purge_expired().expect("purge"), 1);
+    assert_eq!(db.expiring_keys(), 1);
+    assert!(!db.delete(b"short").unwrap());
+}
+
+#[test]
+fn expiries_survive_reopen_and_abort_discards_them() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = open(temp.path(), Duration::from_secs(60));
+        let mut tx = db.tx();
+        tx.put_with_ttl(b"kept", b"value", Duration::from_secs(3600))
+            .expect("put kept");
+        tx.put_with_ttl(b"brief", b"value", Duration::from_millis(20))
+            .expect("put brief");
+        tx.commit();
+
+        let mut aborted = db.tx();
+        aborted
+            .put_with_ttl(b"aborted", b"value", Duration::from_millis(1))
+            .expect("put aborted");
+        aborted.abort();
+        assert!(db.ttl(b"aborted").is_none());
+    }
+    thread::sleep(Duration::from_millis(40));
+
+    let db = open(temp.path(), Duration::from_secs(60));
+    let left = db.ttl(b"kept").expect("expiry persisted");
+    assert!(left > Duration::from_secs(3500) && left <= Duration::from_secs(3600));
+    assert_eq!(db.get(b"kept").unwrap().as_deref(), Some(&b"value"[..]));
+    assert_eq!(db.get(b"brief").unwrap(), None);
+    assert_eq!(db.expiring_keys(), 2);
+}
+
+#[test]
+fn sweeper_reclaims_expired_keys_without_writes() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = open(temp.path(), Duration::from_millis(5));
+    {
+        let mut tx = db.tx();
+        for idx in 0..100u32 {
+            let key = format!("ttl-{idx:03}");
+            tx.put_with_ttl(key.as_bytes(), b"v", Duration::from_millis(10))
+                .expect("put");
+        }
+        tx.commit();
+    }
+    // more than a batch, the sweeper carries on until none are left
+    wait_for(|| db.expiring_keys() == 0);
+    assert_eq!(db.range_scan(b"ttl-", b"ttl.").unwrap(), Vec::new());
+}
+
+#[test]
+fn expiries_are_replayed_from_the_wal_after_a_crash() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = open(temp.path(), Duration::from_secs(60));
+        let mut tx = db.tx();
+        tx.put_with_ttl(b"kept", b"value", Duration::from_secs(3600))
+            .expect("put kept");
+        tx.put_with_ttl(b"brief", b"value", Duration::from_millis(20))
+            .expect("put brief");
+        tx.put_with_ttl(b"long", &[7u8; 3000], Duration::from_secs(3600))
+            .expect("put long");
+        tx.commit();
+        db.debug_close_without_flush();
+    }
+    thread::sleep(Duration::from_millis(40));
+
+    let db = open(temp.path(), Duration::from_secs(60));
+    assert!(db.ttl(b"kept").is_some());
+    assert!(db.ttl(b"long").is_some());
+    assert_eq!(db.get(b"long").unwrap(), Some(vec![7u8; 3000]));
+    assert_eq!(db.get(b"brief").unwrap(), None);
+    assert_eq!(db.purge_expired().expect("purge"), 1);
+    assert_eq!(db.expiring_keys(), 2);
+}
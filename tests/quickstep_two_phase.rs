@@ -0,0 +1,90 @@
+use quickstep::{error::QSError, wal::SyncPolicy, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &std::path::Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 32, 256, 14).with_sync_policy(SyncPolicy::Never)
+}
+
+#[test]
+fn a_prepared_transaction_commits_or_aborts_as_decided() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let syncs = db.wal_syncs();
+    let mut tx = db.tx();
+    tx.put(b"committed", b"value").expect("put");
+    let prepared = tx.prepare().expect("prepare");
+    // synced under any sync policy, the coordinator may rely on it
+    assert_eq!(db.wal_syncs(), syncs + 1);
+    assert!(prepared.txn_id() > 0);
+    prepared.commit();
+    assert_eq!(db.get(b"committed").expect("get"), Some(b"value".to_vec()));
+
+    let mut tx = db.tx();
+    tx.put(b"aborted", b"value").expect("put");
+    tx.prepare().expect("prepare").abort();
+    assert_eq!(db.get(b"aborted").expect("get"), None);
+    assert!(db.in_doubt_txns().is_empty());
+}
+
+/// Prepare a write of `value` to `keys` and crash before the decision
+fn crash_after_prepare(temp: &TempDir, keys: &[&[u8]], value: &[u8]) -> u64 {
+    let db = QuickStep::new(config(temp.path()));
+    let mut tx = db.tx();
+    for key in keys {
+        tx.put(key, value).expect("put");
+    }
+    let prepared = tx.prepare().expect("prepare");
+    let txn_id = prepared.txn_id();
+    std::mem::forget(prepared);
+    db.debug_close_without_flush();
+    txn_id
+}
+
+#[test]
+fn a_crash_after_prepare_leaves_the_transaction_to_resolve() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        let mut tx = db.tx();
+        tx.put(b"base", b"before").expect("put");
+        tx.commit();
+    }
+    let txn_id = crash_after_prepare(&temp, &[b"base", b"other"], b"prepared");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        let report = db.last_recovery_report();
+        assert_eq!(report.txns_in_doubt, [txn_id]);
+        assert_eq!(report.txns_rolled_back, 0);
+        // out of the leaves until resolved
+        assert_eq!(db.get(b"base").expect("get"), Some(b"before".to_vec()));
+        assert_eq!(db.get(b"other").expect("get"), None);
+        db.debug_close_without_flush();
+    }
+    {
+        // still in doubt after another restart
+        let db = QuickStep::new(config(temp.path()));
+        assert_eq!(db.in_doubt_txns(), [txn_id]);
+        db.resolve_in_doubt(txn_id, true).expect("commit");
+        assert!(matches!(
+            db.resolve_in_doubt(txn_id, true),
+            Err(QSError::NotInDoubt { txn_id: id }) if id == txn_id
+        ));
+        assert!(db.in_doubt_txns().is_empty());
+        assert_eq!(db.get(b"base").expect("get"), Some(b"prepared".to_vec()));
+        assert_eq!(db.get(b"other").expect("get"), Some(b"prepared".to_vec()));
+        db.debug_close_without_flush();
+    }
+
+    let txn_id = crash_after_prepare(&temp, &[b"base"], b"aborted");
+    {
+        let db = QuickStep::new(config(temp.path()));
+        assert_eq!(db.in_doubt_txns(), [txn_id]);
+        db.resolve_in_doubt(txn_id, false).expect("abort");
+        assert_eq!(db.get(b"base").expect("get"), Some(b"prepared".to_vec()));
+        db.debug_close_without_flush();
+    }
+    let db = QuickStep::new(config(temp.path()));
+    assert!(db.last_recovery_report().txns_in_doubt.is_empty());
+    assert_eq!(db.get(b"base").expect("get"), Some(b"prepared".to_vec()));
+    assert_eq!(db.get(b"other").expect("get"), Some(b"prepared".to_vec()));
+}
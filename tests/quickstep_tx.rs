@@ -1,9 +1,10 @@
-use quickstep::{QuickStep, QuickStepConfig};
+use quickstep::{error::QSError, map_table::LockWaitPolicy, QuickStep, QuickStepConfig};
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
 fn new_db() -> QuickStep {
     let temp = TempDir::new().expect("tempdir");
-    let config = QuickStepConfig::new(temp.into_path(), 32, 256, 14);
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14);
     QuickStep::new(config)
 }
 
@@ -37,3 +38,57 @@ fn drop_without_commit_auto_aborts() {
     verify.commit();
 }
 
+#[test]
+fn concurrent_write_reports_conflict() {
+    let db = new_db();
+
+    let mut holder = db.tx();
+    holder.put(b"gamma", b"three").expect("put gamma");
+
+    let mut contender = db.tx();
+    match contender.put(b"gamma", b"other") {
+        Err(QSError::WriteConflict { key }) => assert_eq!(key, b"gamma"),
+        other => panic!("expected a write conflict, got {other:?}"),
+    }
+    assert_eq!(contender.conflicting_txns(), &[holder.txn_id()]);
+    contender.abort();
+    holder.commit();
+
+    let mut retry = db.tx();
+    retry
+        .put(b"gamma", b"other")
+        .expect("retry after holder committed");
+    retry.commit();
+
+    let mut verify = db.tx();
+    assert_eq!(verify.get(b"gamma").unwrap(), Some(&b"other"[..]));
+    verify.commit();
+}
+
+#[test]
+fn contended_write_waits_for_lock_timeout() {
+    let temp = TempDir::new().expect("tempdir");
+    let timeout = Duration::from_millis(40);
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14)
+        .with_lock_spins(16)
+        .with_lock_timeout(timeout);
+    assert_eq!(config.lock_wait(), LockWaitPolicy::new(16, timeout));
+    let db = QuickStep::new(config);
+
+    let mut holder = db.tx();
+    holder.put(b"delta", b"four").expect("put delta");
+
+    let mut contender = db.tx();
+    let started = Instant::now();
+    let res = contender.put(b"delta", b"other");
+    assert!(
+        matches!(res, Err(QSError::WriteConflict { .. })),
+        "lock should still be held after the timeout"
+    );
+    assert!(
+        started.elapsed() >= timeout,
+        "contended lock should park for the configured timeout before failing"
+    );
+    contender.abort();
+    holder.commit();
+}
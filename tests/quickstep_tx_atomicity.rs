@@ -0,0 +1,93 @@
+use std::mem;
+
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+/// Commit keys one at a time until the root leaf splits, returning how many went in
+fn fill_two_leaves(db: &QuickStep) -> usize {
+    let payload = vec![1u8; 64];
+    let mut idx = 0;
+    while db.debug_root_leaf_parent().is_none() {
+        let mut tx = db.tx();
+        tx.put(&key(idx), &payload).expect("insert");
+        tx.commit();
+        idx += 1;
+    }
+    idx
+}
+
+#[test]
+fn a_crashed_transaction_over_two_leaves_is_rolled_back_on_both() {
+    let temp = TempDir::new().expect("tempdir");
+    // checkpoint leaves after a handful of records, so the crashed writes reach the disk
+    let config =
+        || QuickStepConfig::new(temp.path(), 32, 256, 14).with_wal_thresholds(4, 100_000, 1 << 30);
+    let inserted = {
+        let db = QuickStep::new(config());
+        let inserted = fill_two_leaves(&db);
+        let mut tx = db.tx();
+        for idx in (0..6).chain([inserted - 1]) {
+            tx.put(&key(idx), b"partial").expect("update");
+        }
+        tx.put(b"key-99999", b"partial").expect("insert");
+        mem::forget(tx);
+        inserted
+    };
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.last_recovery_report().txns_rolled_back, 1);
+    for idx in (0..6).chain([inserted - 1]) {
+        assert_eq!(db.get(&key(idx)).unwrap(), Some(vec![1u8; 64]), "key {idx}");
+    }
+    assert_eq!(db.get(b"key-99999").unwrap(), None);
+}
+
+#[test]
+fn a_key_written_twice_rolls_back_to_its_first_value() {
+    let temp = TempDir::new().expect("tempdir");
+    let config =
+        || QuickStepConfig::new(temp.path(), 32, 256, 14).with_wal_thresholds(2, 100_000, 1 << 30);
+    {
+        let db = QuickStep::new(config());
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"original").expect("insert");
+        tx.commit();
+
+        let mut tx = db.tx();
+        tx.put(b"alpha", b"first").expect("update");
+        tx.put(b"alpha", b"second").expect("update");
+        tx.put(b"beta", b"new").expect("insert");
+        mem::forget(tx);
+    }
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.get(b"alpha").unwrap(), Some(b"original".to_vec()));
+    assert_eq!(db.get(b"beta").unwrap(), None);
+}
+
+#[test]
+fn readers_see_none_of_a_running_transaction_across_leaves() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    let inserted = fill_two_leaves(&db);
+    let (first, last) = (key(0), key(inserted - 1));
+
+    let mut tx = db.tx();
+    tx.put(&first, b"pending").expect("update");
+    tx.put(&last, b"pending").expect("update");
+    for key in [&first, &last] {
+        // either still the committed value or turned away by the lock, never the new one
+        if let Ok(value) = db.get(key) {
+            assert_eq!(value, Some(vec![1u8; 64]));
+        }
+    }
+    tx.commit();
+
+    for key in [&first, &last] {
+        assert_eq!(db.get(key).unwrap(), Some(b"pending".to_vec()));
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::Path;
+
+use quickstep::{txn_ids::TXN_ID_BLOCK, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 32, 256, 14)
+}
+
+/// Commit `count` transactions, returning the id of the last
+fn commit(db: &QuickStep, count: usize) -> u64 {
+    let mut last = 0;
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx}").as_bytes(), b"value")
+            .expect("put");
+        last = tx.txn_id();
+        tx.commit();
+    }
+    last
+}
+
+#[test]
+fn ids_carry_on_past_a_checkpointed_log() {
+    let temp = TempDir::new().expect("tempdir");
+    let first_run = {
+        let db = QuickStep::new(config(temp.path()));
+        let last = commit(&db, 5);
+        // the log keeps no record of the ids handed out
+        db.checkpoint().expect("checkpoint");
+        assert_eq!(db.debug_wal_record_count().unwrap(), 0);
+        last
+    };
+    let second_run = {
+        let db = QuickStep::new(config(temp.path()));
+        let next = db.tx().txn_id();
+        assert!(next > first_run, "{next} reused after {first_run}");
+        // a crash loses the rest of the block, never an id handed out
+        let last = commit(&db, 3);
+        db.debug_close_without_flush();
+        last
+    };
+    let db = QuickStep::new(config(temp.path()));
+    assert!(db.tx().txn_id() > second_run);
+}
+
+#[test]
+fn ids_past_a_block_reserve_the_next_one() {
+    let temp = TempDir::new().expect("tempdir");
+    let last = {
+        let db = QuickStep::new(config(temp.path()));
+        commit(&db, TXN_ID_BLOCK as usize + 2)
+    };
+    assert!(last > TXN_ID_BLOCK);
+    let db = QuickStep::new(config(temp.path()));
+    assert!(db.tx().txn_id() > last);
+}
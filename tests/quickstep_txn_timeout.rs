@@ -0,0 +1,123 @@
+use std::{
+    mem, thread,
+    time::{Duration, Instant},
+};
+
+use quickstep::{error::QSError, QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+const TIMEOUT: Duration = Duration::from_millis(50);
+
+fn config(temp: &TempDir) -> QuickStepConfig {
+    QuickStepConfig::new(temp.path(), 32, 256, 14).with_txn_timeout(TIMEOUT)
+}
+
+fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn leaked_txn_is_rolled_back_and_releases_its_locks() {
+    let temp = TempDir::new().expect("tempdir");
+    {
+        let db = QuickStep::new(config(&temp));
+        let mut tx = db.tx();
+        tx.put(b"kept", b"committed").expect("put");
+        tx.commit();
+
+        let mut tx = db.tx();
+        tx.put(b"kept", b"leaked").expect("put");
+        tx.put(b"added", b"leaked").expect("put");
+        assert_eq!(tx.get(b"kept").unwrap(), Some(&b"leaked"[..]));
+        mem::forget(tx);
+
+        // the background thread expires the leaked one
+        wait_for(|| db.expired_txns() == 1);
+        let mut tx = db.tx();
+        tx.put(b"kept", b"after")
+            .expect("the leaked locks are released");
+        tx.commit();
+        assert_eq!(db.get(b"added").unwrap(), None);
+        assert_eq!(db.get(b"kept").unwrap(), Some(b"after".to_vec()));
+    }
+
+    let db = QuickStep::new(config(&temp));
+    assert_eq!(db.get(b"kept").unwrap(), Some(b"after".to_vec()));
+    assert_eq!(db.get(b"added").unwrap(), None);
+}
+
+#[test]
+fn idle_txn_fails_once_expired() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(&temp));
+    let mut tx = db.tx();
+    let txn_id = tx.txn_id();
+    tx.put(b"key", b"idle").expect("put");
+
+    wait_for(|| db.expired_txns() == 1);
+    assert!(matches!(
+        tx.put(b"key", b"late"),
+        Err(QSError::TxnExpired { txn_id: id }) if id == txn_id
+    ));
+    assert!(matches!(tx.get(b"key"), Err(QSError::TxnExpired { .. })));
+    // committing an expired transaction does nothing
+    tx.commit();
+    assert_eq!(db.get(b"key").unwrap(), None);
+
+    let mut tx = db.tx();
+    tx.put(b"key", b"fresh").expect("put");
+    tx.commit();
+    assert_eq!(db.get(b"key").unwrap(), Some(b"fresh".to_vec()));
+}
+
+#[test]
+fn values_read_survive_expiry() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(&temp));
+    let mut tx = db.tx();
+    tx.put(b"key", b"committed").expect("put");
+    tx.commit();
+
+    let mut tx = db.tx();
+    let value = tx.get(b"key").unwrap().expect("present");
+    wait_for(|| db.expired_txns() == 1);
+    // the read lock was taken over and released, so the leaf can change under the value
+    let mut writer = db.tx();
+    writer.put(b"key", b"overwritten").expect("put");
+    writer.commit();
+    assert_eq!(value, b"committed");
+    drop(tx);
+    assert_eq!(db.get(b"key").unwrap(), Some(b"overwritten".to_vec()));
+}
+
+#[test]
+fn busy_txns_do_not_expire() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(&temp));
+    let mut tx = db.tx();
+    for idx in 0..10u32 {
+        tx.put(&idx.to_be_bytes(), b"value").expect("put");
+        thread::sleep(TIMEOUT / 5);
+        assert_eq!(db.expire_idle_txns(), 0);
+    }
+    tx.commit();
+    thread::sleep(TIMEOUT * 2);
+    // committed transactions are no longer tracked
+    assert_eq!(db.expire_idle_txns(), 0);
+    assert_eq!(db.expired_txns(), 0);
+    assert_eq!(db.range_iter(b"", b"\xff").count(), 10);
+
+    let db = QuickStep::new(QuickStepConfig::in_memory(32, 256, 14));
+    let tx = db.tx();
+    mem::forget(tx);
+    thread::sleep(TIMEOUT * 2);
+    assert_eq!(
+        db.expire_idle_txns(),
+        0,
+        "transactions never expire without a timeout"
+    );
+}
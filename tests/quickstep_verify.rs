@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use quickstep::{
+    error::QSError, invariants::InvariantViolation, storage::Storage, verify::VerifyProblem,
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 16)
+}
+
+fn write(db: &QuickStep, range: std::ops::Range<u32>) {
+    for idx in range {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx:05}").as_bytes(), &[7u8; 64])
+            .expect("put");
+        tx.commit();
+    }
+}
+
+#[test]
+fn healthy_tree_verifies_through_splits_merges_and_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let report = db.verify().expect("verify");
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!((report.inner_nodes, report.leaves), (0, 1));
+
+    write(&db, 0..1500);
+    let report = db.verify().expect("verify");
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert!(report.inner_nodes >= 1);
+    assert!(report.leaves > 2);
+    assert!(report.records >= 1500);
+    assert!(report.wal_records > 0);
+
+    for idx in (0..1000u32).filter(|idx| idx % 50 != 0) {
+        db.delete(format!("key-{idx:05}").as_bytes())
+            .expect("delete");
+    }
+    assert!(db.stats().merges > 0);
+    db.checkpoint().expect("checkpoint");
+    let report = db.verify().expect("verify");
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.wal_records, 0);
+    drop(db);
+
+    let db = QuickStep::open(config(temp.path())).expect("reopen");
+    let report = db.verify().expect("verify");
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert!(report.leaves > 1);
+}
+
+#[test]
+fn corrupted_leaf_is_reported_without_stopping_the_walk() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    write(&db, 0..1500);
+    db.checkpoint().expect("checkpoint");
+    let leaves = db.verify().expect("verify").leaves;
+    drop(db);
+
+    {
+        // leaf 1 sits after the superblock page and leaf 0
+        let file = Storage::open(&temp.path().join("quickstep.db")).expect("open");
+        let mut byte = [0u8; 1];
+        file.read_exact_at(&mut byte, 2 * 4096 + 2048)
+            .expect("read byte");
+        byte[0] ^= 0x01;
+        file.write_all_at(&byte, 2 * 4096 + 2048)
+            .expect("write byte");
+    }
+
+    let db = QuickStep::open(config(temp.path()).with_leaf_cache_pages(0)).expect("reopen");
+    let report = db.verify().expect("verify");
+    assert_eq!(report.leaves, leaves);
+    assert!(matches!(
+        report.problems.as_slice(),
+        [VerifyProblem::Leaf(InvariantViolation::Read {
+            err: QSError::PageCorrupt { addr: 1 },
+            ..
+        })]
+    ));
+}
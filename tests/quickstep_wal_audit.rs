@@ -0,0 +1,118 @@
+use std::{
+    fs::OpenOptions, io::Write, os::unix::fs::FileExt, path::Path, sync::mpsc::Receiver,
+    time::Duration,
+};
+
+use quickstep::{
+    health::HealthEvent,
+    wal::{ManifestStatus, WalMismatch},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn config(path: &Path) -> QuickStepConfig {
+    // leaves are only flushed by the checkpoints the tests run
+    QuickStepConfig::new(path, 64, 1024, 20)
+        .with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+        .with_wal_audit(Duration::from_millis(20))
+}
+
+fn write_keys(db: &QuickStep, count: u32) {
+    for idx in 0..count {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx:04}").as_bytes(), b"value")
+            .expect("put");
+        tx.commit();
+    }
+}
+
+fn next_mismatch(events: &Receiver<HealthEvent>) -> WalMismatch {
+    loop {
+        match events.recv_timeout(Duration::from_secs(5)).expect("event") {
+            HealthEvent::WalMismatch(mismatch) => return mismatch,
+            _ => continue,
+        }
+    }
+}
+
+#[test]
+fn a_healthy_log_audits_clean() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    assert!(db.audit_wal().expect("audit").is_empty());
+    write_keys(&db, 200);
+    assert!(db.audit_wal().expect("audit").is_empty());
+    db.run_global_checkpoint().expect("checkpoint");
+    write_keys(&db, 20);
+    assert!(db.audit_wal().expect("audit").is_empty());
+}
+
+#[test]
+fn bytes_appended_behind_the_log_are_reported_once() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let events = db.health_events();
+    write_keys(&db, 50);
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(temp.path().join("quickstep.wal"))
+        .expect("open wal");
+    file.write_all(&[0xEE; 7]).expect("append");
+    assert_eq!(
+        next_mismatch(&events),
+        WalMismatch::TrailingBytes { bytes: 7 }
+    );
+    // the next audits find the same bytes and stay quiet
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(events.try_iter().next().is_none());
+}
+
+#[test]
+fn a_cut_off_log_reports_the_records_it_lost() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    write_keys(&db, 50);
+    let wal = OpenOptions::new()
+        .write(true)
+        .open(temp.path().join("quickstep.wal"))
+        .expect("open wal");
+    let len = wal.metadata().expect("metadata").len();
+    wal.set_len(len - 3).expect("truncate");
+
+    let mismatches = db.audit_wal().expect("audit");
+    assert!(mismatches
+        .iter()
+        .any(|mismatch| matches!(mismatch, WalMismatch::TrailingBytes { .. })));
+    let Some(WalMismatch::Totals { counted, found }) = mismatches
+        .iter()
+        .find(|mismatch| matches!(mismatch, WalMismatch::Totals { .. }))
+    else {
+        panic!("totals disagree: {mismatches:?}");
+    };
+    assert!(found.records < counted.records);
+    assert!(mismatches
+        .iter()
+        .any(|mismatch| matches!(mismatch, WalMismatch::Page { .. })));
+}
+
+#[test]
+fn an_overwritten_manifest_is_reported() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let events = db.health_events();
+    write_keys(&db, 10);
+    let wal = OpenOptions::new()
+        .write(true)
+        .open(temp.path().join("quickstep.wal"))
+        .expect("open wal");
+    wal.write_all_at(b"XXXX", 0).expect("overwrite");
+
+    assert!(matches!(
+        next_mismatch(&events),
+        WalMismatch::Manifest {
+            found: ManifestStatus::Unrecognized,
+            ..
+        }
+    ));
+}
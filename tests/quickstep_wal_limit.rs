@@ -0,0 +1,87 @@
+use quickstep::{
+    error::QSError,
+    health::{HealthEvent, WalLimitPolicy, WalPressure},
+    QuickStep, QuickStepConfig,
+};
+use std::path::Path;
+use tempfile::TempDir;
+
+const MAX_WAL_BYTES: usize = 4 * 1024;
+
+fn open(path: &Path, policy: WalLimitPolicy) -> QuickStep {
+    // other checkpoint triggers stay out of the way, only the limit bounds the log
+    QuickStep::new(
+        QuickStepConfig::new(path, 64, 256, 20)
+            .with_wal_thresholds(usize::MAX, usize::MAX, usize::MAX)
+            .with_max_wal_size(MAX_WAL_BYTES)
+            .with_wal_limit_policy(policy),
+    )
+}
+
+fn wal_bytes(db: &QuickStep) -> usize {
+    db.debug_wal_stats(None).unwrap().total_bytes
+}
+
+#[test]
+fn throttled_writes_keep_the_wal_near_its_limit() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = open(&temp.path().join("throttle.qs"), WalLimitPolicy::Throttle);
+    let events = db.health_events();
+
+    let value = [7u8; 16];
+    for i in 0..60u32 {
+        let mut tx = db.tx();
+        tx.put(format!("key-{i:04}").as_bytes(), &value)
+            .expect("throttled put");
+        tx.commit();
+        // one write's records may land past the limit before the next write checks it
+        assert!(wal_bytes(&db) < MAX_WAL_BYTES + 256);
+    }
+
+    let pressures: Vec<WalPressure> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            HealthEvent::WalPressure { pressure, .. } => Some(pressure),
+            _ => None,
+        })
+        .collect();
+    assert!(pressures.contains(&WalPressure::Elevated));
+    assert!(pressures.contains(&WalPressure::AtLimit));
+    assert_eq!(
+        db.get(b"key-0000").expect("get first key"),
+        Some(value.to_vec())
+    );
+}
+
+#[test]
+fn fail_policy_rejects_writes_that_cannot_be_checkpointed() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = open(&temp.path().join("fail.qs"), WalLimitPolicy::Fail);
+
+    // the holder's own puts may cross the limit, its leaf stays locked until it commits
+    let mut holder = db.tx();
+    let mut i = 0u32;
+    while wal_bytes(&db) < MAX_WAL_BYTES {
+        holder
+            .put(format!("held-{i:04}").as_bytes(), &[1u8; 16])
+            .expect("holder put");
+        i += 1;
+    }
+
+    let mut blocked = db.tx();
+    assert!(matches!(
+        blocked.put(b"other", b"value"),
+        Err(QSError::WalFull {
+            max_wal_bytes: MAX_WAL_BYTES,
+            ..
+        })
+    ));
+    blocked.abort();
+
+    holder.commit();
+    let mut tx = db.tx();
+    tx.put(b"other", b"value")
+        .expect("put after holder commits");
+    tx.commit();
+    assert!(wal_bytes(&db) < MAX_WAL_BYTES);
+}
@@ -0,0 +1,92 @@
+use std::sync::mpsc::TryRecvError;
+
+use quickstep::{
+    merge::U64AddMerge,
+    watch::{ChangeEvent, ChangeKind},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn new_db() -> QuickStep {
+    let temp = TempDir::new().expect("tempdir");
+    QuickStep::new(QuickStepConfig::new(temp.keep(), 32, 256, 14).with_merge_operator(U64AddMerge))
+}
+
+#[test]
+fn subscribers_see_committed_changes_under_their_prefix() {
+    let db = new_db();
+    {
+        let mut tx = db.tx();
+        tx.put(b"user/2", b"old").expect("seed");
+        tx.commit();
+    }
+
+    let users = db.subscribe(b"user/");
+    let everything = db.subscribe(b"");
+
+    let mut tx = db.tx();
+    let txn_id = tx.txn_id();
+    tx.put(b"user/1", b"alice").expect("put");
+    tx.put(b"order/9", b"widget").expect("put other prefix");
+    assert!(tx.delete(b"user/2").expect("delete"));
+    assert!(!tx.delete(b"user/missing").expect("delete missing"));
+    assert_eq!(
+        users.try_recv(),
+        Err(TryRecvError::Empty),
+        "nothing before commit"
+    );
+    tx.commit();
+
+    let received: Vec<_> = users.try_iter().collect();
+    assert_eq!(
+        received,
+        vec![
+            ChangeEvent {
+                txn_id,
+                key: b"user/1".to_vec(),
+                kind: ChangeKind::Put(b"alice".to_vec()),
+            },
+            ChangeEvent {
+                txn_id,
+                key: b"user/2".to_vec(),
+                kind: ChangeKind::Delete,
+            },
+        ]
+    );
+    let keys: Vec<_> = everything.try_iter().map(|event| event.key).collect();
+    assert_eq!(
+        keys,
+        vec![b"user/1".to_vec(), b"order/9".to_vec(), b"user/2".to_vec()]
+    );
+}
+
+#[test]
+fn aborted_transactions_publish_nothing_and_merges_report_resolved_values() {
+    let db = new_db();
+    let events = db.subscribe(b"count");
+
+    let mut aborted = db.tx();
+    aborted.put(b"count", b"ignored").expect("put");
+    aborted.abort();
+    assert_eq!(events.try_recv(), Err(TryRecvError::Empty));
+
+    for _ in 0..2 {
+        let mut tx = db.tx();
+        tx.merge(b"count", &5u64.to_le_bytes()).expect("merge");
+        tx.commit();
+    }
+    let values: Vec<_> = events
+        .try_iter()
+        .map(|event| match event.kind {
+            ChangeKind::Put(value) => U64AddMerge::decode(&value),
+            ChangeKind::Delete => panic!("unexpected delete"),
+        })
+        .collect();
+    assert_eq!(values, vec![5, 10]);
+
+    // a dropped receiver just stops the subscription
+    drop(events);
+    let mut tx = db.tx();
+    tx.put(b"count", b"after").expect("put");
+    tx.commit();
+}
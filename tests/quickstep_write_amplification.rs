@@ -0,0 +1,72 @@
+use quickstep::{QuickStep, QuickStepConfig};
+use tempfile::TempDir;
+
+fn new_db(leaf_checkpoint: usize) -> QuickStep {
+    let temp = TempDir::new().expect("tempdir");
+    let config = QuickStepConfig::new(temp.keep(), 32, 256, 14).with_wal_thresholds(
+        leaf_checkpoint,
+        usize::MAX,
+        usize::MAX,
+    );
+    QuickStep::new(config)
+}
+
+fn write_keys(db: &QuickStep, count: usize) -> u64 {
+    let mut logical = 0;
+    let mut tx = db.tx();
+    for idx in 0..count {
+        let key = format!("key-{idx:04}");
+        let value = [idx as u8; 24];
+        tx.put(key.as_bytes(), &value).expect("insert");
+        logical += (key.len() + value.len()) as u64;
+    }
+    tx.commit();
+    logical
+}
+
+#[test]
+fn counts_logical_wal_and_data_bytes() {
+    let db = new_db(usize::MAX);
+    let fresh = db.write_amplification();
+    assert_eq!(fresh.logical_bytes, 0);
+    assert_eq!(fresh.ratio(), 0.0);
+
+    let mut logical = write_keys(&db, 20);
+    assert!(db.delete(b"key-0003").unwrap());
+    logical += "key-0003".len() as u64;
+
+    let stats = db.write_amplification();
+    assert_eq!(stats.logical_bytes, logical);
+    assert!(
+        stats.wal_bytes > stats.logical_bytes,
+        "redo and undo records should cost more than the user bytes"
+    );
+    // opening writes the empty root leaf, nothing else is flushed yet
+    assert_eq!(stats.data_bytes, fresh.data_bytes);
+
+    db.debug_flush_root_leaf().expect("flush root leaf");
+    let flushed = db.write_amplification();
+    assert!(flushed.data_bytes >= stats.data_bytes + 4096);
+    assert!(flushed.wal_bytes >= stats.wal_bytes);
+    assert!(flushed.ratio() > flushed.wal_ratio());
+    assert_eq!(
+        flushed.physical_bytes(),
+        flushed.wal_bytes + flushed.data_bytes
+    );
+}
+
+#[test]
+fn eager_leaf_checkpoints_raise_data_amplification() {
+    let eager = new_db(2);
+    let lazy = new_db(usize::MAX);
+    write_keys(&eager, 40);
+    write_keys(&lazy, 40);
+
+    let eager = eager.write_amplification();
+    let lazy = lazy.write_amplification();
+    assert_eq!(eager.logical_bytes, lazy.logical_bytes);
+    assert!(
+        eager.data_ratio() > lazy.data_ratio(),
+        "checkpointing every couple of records should write the leaf more often: {eager:?} vs {lazy:?}"
+    );
+}
@@ -0,0 +1,220 @@
+use std::fs;
+
+use quickstep::{
+    map_table::PageId,
+    wal::{WalEntryKind, WalManager, WalOp, WalRecord, WalTxnMarker},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+const LOWER: &[u8] = b"lower-fence";
+const UPPER: &[u8] = b"upper-fence";
+
+fn put_pair(page: u64, key: &[u8], txn_id: u64) -> Vec<WalRecord> {
+    let record =
+        |kind, op| WalRecord::leaf(PageId::from_u64(page), key, LOWER, UPPER, kind, txn_id, op);
+    vec![
+        record(
+            WalEntryKind::Redo,
+            WalOp::Put {
+                value: b"new-value".to_vec(),
+            },
+        ),
+        record(WalEntryKind::Undo, WalOp::Tombstone),
+    ]
+}
+
+/// Append and write each record on its own, appends alone would share a write
+fn append_separately(wal: &WalManager, records: Vec<WalRecord>) {
+    for record in records {
+        let page_id = PageId::from_u64(record.page_id);
+        match record.op {
+            WalOp::Put { value } => wal.append_put(
+                page_id,
+                &record.key,
+                &value,
+                &record.lower_fence,
+                &record.upper_fence,
+                record.kind,
+                record.txn_id,
+            ),
+            WalOp::Tombstone => wal.append_tombstone(
+                page_id,
+                &record.key,
+                &record.lower_fence,
+                &record.upper_fence,
+                record.kind,
+                record.txn_id,
+            ),
+            _ => unreachable!(),
+        }
+        .expect("append");
+        wal.sync().expect("sync");
+    }
+}
+
+#[test]
+fn batched_pairs_reopen_with_their_kinds_and_fences() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("batch.wal");
+    {
+        let wal = WalManager::open(&path).expect("open wal");
+        wal.append_batch(put_pair(4, b"alpha", 7)).expect("batch");
+        wal.append_batch(put_pair(5, b"beta", 8)).expect("batch");
+    }
+
+    let wal = WalManager::open(&path).expect("reopen wal");
+    assert_eq!(wal.open_stats().records_discarded, 0);
+    let records = wal.records();
+    assert_eq!(records.len(), 4);
+    for (record, (page, key, txn)) in records
+        .chunks(2)
+        .zip([(4, b"alpha".as_slice(), 7), (5, b"beta", 8)])
+    {
+        assert!(matches!(record[0].kind, WalEntryKind::Redo));
+        assert!(matches!(&record[0].op, WalOp::Put { value } if value == b"new-value"));
+        assert!(matches!(record[1].kind, WalEntryKind::Undo));
+        assert!(matches!(record[1].op, WalOp::Tombstone));
+        for record in record {
+            assert_eq!(record.page_id, page);
+            assert_eq!(record.key, key);
+            assert_eq!(record.txn_id, txn);
+            assert_eq!(record.lower_fence, LOWER);
+            assert_eq!(record.upper_fence, UPPER);
+        }
+    }
+    // the log bytes past its 32 byte manifest
+    assert_eq!(
+        wal.total_bytes().unwrap() as u64 + 32,
+        fs::metadata(&path).unwrap().len()
+    );
+}
+
+#[test]
+fn batched_pairs_take_less_log_than_separate_records() {
+    let batched = WalManager::in_memory(Default::default()).expect("wal");
+    let separate = WalManager::in_memory(Default::default()).expect("wal");
+    for idx in 0..32u64 {
+        let key = format!("key-{idx:04}");
+        batched
+            .append_batch(put_pair(idx % 4, key.as_bytes(), idx))
+            .expect("batch");
+        append_separately(&separate, put_pair(idx % 4, key.as_bytes(), idx));
+    }
+    assert_eq!(
+        batched.total_records().unwrap(),
+        separate.total_records().unwrap()
+    );
+    assert!(
+        batched.total_bytes().unwrap() * 4 <= separate.total_bytes().unwrap() * 3,
+        "batched {} separate {}",
+        batched.total_bytes().unwrap(),
+        separate.total_bytes().unwrap()
+    );
+}
+
+#[test]
+fn a_torn_batch_is_cut_off_whole() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("torn.wal");
+    let clean_len = {
+        let wal = WalManager::open(&path).expect("open wal");
+        wal.append_batch(put_pair(1, b"kept", 1)).expect("batch");
+        wal.sync().expect("sync");
+        let clean_len = fs::metadata(&path).unwrap().len();
+        wal.append_batch(put_pair(1, b"torn", 2)).expect("batch");
+        clean_len
+    };
+    let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(fs::metadata(&path).unwrap().len() - 3)
+        .unwrap();
+    drop(file);
+
+    let wal = WalManager::open(&path).expect("reopen wal");
+    let records = wal.records();
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|record| record.key == b"kept"));
+    assert_eq!(wal.open_stats().records_discarded, 1);
+    assert_eq!(fs::metadata(&path).unwrap().len(), clean_len);
+}
+
+#[test]
+fn checkpoints_keep_the_batches_of_other_leaves() {
+    let wal = WalManager::in_memory(Default::default()).expect("wal");
+    wal.append_batch(put_pair(1, b"one", 1)).expect("batch");
+    wal.append_batch(put_pair(2, b"two", 2)).expect("batch");
+    wal.append_batch(put_pair(1, b"three", 3)).expect("batch");
+    wal.checkpoint_page(PageId::from_u64(2))
+        .expect("checkpoint");
+
+    let records = wal.records();
+    assert_eq!(records.len(), 4);
+    assert!(records.iter().all(|record| record.page_id == 1));
+    assert_eq!(
+        wal.leaf_stats(PageId::from_u64(1))
+            .unwrap()
+            .map(|(count, _)| count),
+        Some(4)
+    );
+}
+
+#[test]
+fn transactional_puts_replay_from_batched_records() {
+    let temp = TempDir::new().expect("tempdir");
+    let config = || QuickStepConfig::new(temp.path(), 32, 256, 14);
+    {
+        let db = QuickStep::new(config());
+        let mut tx = db.tx();
+        tx.put(b"committed", b"value").expect("put");
+        tx.put(b"replaced", b"old").expect("put");
+        tx.commit();
+        let mut tx = db.tx();
+        tx.put(b"replaced", b"new").expect("put");
+        tx.commit();
+        assert!(db.delete(b"committed").unwrap());
+
+        let records = db.debug_wal_stats(None).unwrap().total_records;
+        assert!(records >= 8, "{records} records");
+    }
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.get(b"committed").unwrap(), None);
+    assert_eq!(db.get(b"replaced").unwrap(), Some(b"new".to_vec()));
+}
+
+#[test]
+fn undo_records_are_logged_only_when_a_leaf_may_be_written() {
+    let wal = WalManager::in_memory(Default::default()).expect("wal");
+    let undo_of = |txn_id| {
+        let [redo, undo]: [WalRecord; 2] = put_pair(1, b"key", txn_id).try_into().unwrap();
+        wal.append_with_undo(redo, Some(undo)).expect("append");
+    };
+    let undo_records = |records: &[WalRecord]| {
+        records
+            .iter()
+            .filter(|record| matches!(record.kind, WalEntryKind::Undo))
+            .map(|record| record.txn_id)
+            .collect::<Vec<_>>()
+    };
+
+    // a transaction that ends before any leaf is written logs only its redo records
+    undo_of(1);
+    wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, 1)
+        .expect("commit");
+    undo_of(2);
+    wal.append_txn_marker(WalTxnMarker::Abort, WalEntryKind::Redo, 2)
+        .expect("abort");
+    undo_of(3);
+    assert_eq!(wal.total_records().unwrap(), 5);
+    assert!(undo_records(&wal.records()).is_empty());
+
+    // the sync ahead of a leaf write logs the undo records of transactions still running
+    wal.sync().expect("sync");
+    let records = wal.records();
+    assert_eq!(undo_records(&records), [3]);
+    assert_eq!(records.last().map(|record| record.lsn), Some(6));
+    wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, 3)
+        .expect("commit");
+    wal.sync().expect("sync");
+    assert_eq!(undo_records(&wal.records()), [3]);
+}
@@ -0,0 +1,117 @@
+use std::mem;
+
+use quickstep::{
+    map_table::PageId,
+    wal::{WalEntryKind, WalManager, WalOp, WalRecord, WalTxnMarker},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn write(page: u64, key: &[u8], op: WalOp, txn_id: u64) -> Vec<WalRecord> {
+    let record =
+        |kind, op| WalRecord::leaf(PageId::from_u64(page), key, b"", b"", kind, txn_id, op);
+    vec![
+        record(WalEntryKind::Redo, op),
+        record(WalEntryKind::Undo, WalOp::Tombstone),
+    ]
+}
+
+fn put(value: &[u8]) -> WalOp {
+    WalOp::Put {
+        value: value.to_vec(),
+    }
+}
+
+fn marker(wal: &WalManager, marker: WalTxnMarker, txn_id: u64) {
+    wal.append_txn_marker(marker, WalEntryKind::Redo, txn_id)
+        .expect("marker");
+}
+
+#[test]
+fn rewrites_keep_only_the_newest_committed_write_of_each_key() {
+    let wal = WalManager::in_memory(Default::default()).expect("wal");
+    for (txn_id, value) in [(1, b"a"), (2, b"b")] {
+        marker(&wal, WalTxnMarker::Begin, txn_id);
+        wal.append_batch(write(1, b"key", put(value), txn_id))
+            .expect("append");
+        marker(&wal, WalTxnMarker::Commit, txn_id);
+    }
+    marker(&wal, WalTxnMarker::Begin, 3);
+    wal.append_batch(write(1, b"gone", put(b"x"), 3))
+        .expect("append");
+    wal.append_batch(write(1, b"gone", WalOp::Tombstone, 3))
+        .expect("append");
+    marker(&wal, WalTxnMarker::Commit, 3);
+    // still running, replay may need every record of it
+    marker(&wal, WalTxnMarker::Begin, 4);
+    wal.append_batch(write(1, b"key", put(b"c"), 4))
+        .expect("append");
+    wal.append_batch(write(2, b"other", put(b"y"), 5))
+        .expect("append");
+    marker(&wal, WalTxnMarker::Commit, 5);
+
+    wal.checkpoint_page(PageId::from_u64(2))
+        .expect("checkpoint");
+    let kept: Vec<_> = wal
+        .records()
+        .into_iter()
+        .filter(|record| record.page_id == 1)
+        .map(|record| {
+            (
+                record.txn_id,
+                record.key,
+                matches!(record.kind, WalEntryKind::Undo),
+            )
+        })
+        .collect();
+    assert_eq!(
+        kept,
+        [
+            (2, b"key".to_vec(), false),
+            (3, b"gone".to_vec(), false),
+            (4, b"key".to_vec(), false),
+            (4, b"key".to_vec(), true),
+        ]
+    );
+    assert_eq!(wal.records_coalesced(), 6);
+    assert!(wal.audit().expect("audit").is_empty());
+}
+
+fn key(idx: usize) -> Vec<u8> {
+    format!("key-{idx:05}").into_bytes()
+}
+
+#[test]
+fn an_update_heavy_store_recovers_from_a_coalesced_log() {
+    let temp = TempDir::new().expect("tempdir");
+    let config =
+        || QuickStepConfig::new(temp.path(), 32, 256, 14).with_wal_thresholds(50, 100_000, 1 << 30);
+    let (first, last) = {
+        let db = QuickStep::new(config());
+        let payload = vec![1u8; 64];
+        let mut inserted = 0;
+        while db.debug_root_leaf_parent().is_none() {
+            let mut tx = db.tx();
+            tx.put(&key(inserted), &payload).expect("insert");
+            tx.commit();
+            inserted += 1;
+        }
+        let (first, last) = (key(0), key(inserted - 1));
+        for round in 0..400u32 {
+            let mut tx = db.tx();
+            let hot = if round % 2 == 0 { &first } else { &last };
+            tx.put(hot, &round.to_le_bytes()).expect("update");
+            tx.commit();
+        }
+        // a crash in the middle of another update rolls it back over the coalesced records
+        let mut tx = db.tx();
+        tx.put(&first, b"partial").expect("update");
+        mem::forget(tx);
+        db.debug_close_without_flush();
+        (first, last)
+    };
+
+    let db = QuickStep::new(config());
+    assert_eq!(db.get(&first).unwrap(), Some(398u32.to_le_bytes().to_vec()));
+    assert_eq!(db.get(&last).unwrap(), Some(399u32.to_le_bytes().to_vec()));
+}
@@ -0,0 +1,180 @@
+use quickstep::{
+    map_table::PageId,
+    wal::{WalEntryKind, WalManager, WalOp, WalTxnMarker},
+};
+use std::{collections::HashMap, fs, sync::Arc, thread};
+use tempfile::TempDir;
+
+const THREADS: u64 = 8;
+const PER_THREAD: u64 = 200;
+
+fn append_from_threads(wal: &Arc<WalManager>) {
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread_id| {
+            let wal = Arc::clone(wal);
+            thread::spawn(move || {
+                for seq in 0..PER_THREAD {
+                    wal.append_put(
+                        PageId::from_u64(thread_id),
+                        &seq.to_be_bytes(),
+                        &thread_id.to_be_bytes(),
+                        b"",
+                        b"",
+                        WalEntryKind::Redo,
+                        thread_id,
+                    )
+                    .expect("append put");
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("appender panicked");
+    }
+}
+
+/// Each thread's records, in the order they appear in the log
+fn sequences(wal: &WalManager) -> HashMap<u64, Vec<u64>> {
+    let mut by_thread: HashMap<u64, Vec<u64>> = HashMap::new();
+    for record in wal.records() {
+        let seq = u64::from_be_bytes(record.key.as_slice().try_into().unwrap());
+        assert!(matches!(record.op, WalOp::Put { .. }));
+        by_thread.entry(record.txn_id).or_default().push(seq);
+    }
+    by_thread
+}
+
+#[test]
+fn concurrent_appends_are_all_logged_in_thread_order() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("concurrent.wal");
+    let wal = Arc::new(WalManager::open(&path).expect("open wal"));
+
+    append_from_threads(&wal);
+
+    assert_eq!(wal.total_records().unwrap() as u64, THREADS * PER_THREAD);
+    let expected: Vec<u64> = (0..PER_THREAD).collect();
+    for (thread_id, seqs) in sequences(&wal) {
+        assert_eq!(seqs, expected, "thread {thread_id} out of order");
+        let (count, _) = wal
+            .leaf_stats(PageId::from_u64(thread_id))
+            .unwrap()
+            .expect("leaf stats");
+        assert_eq!(count as u64, PER_THREAD);
+    }
+    let total_bytes = wal.total_bytes().unwrap();
+    drop(wal);
+
+    let reopened = WalManager::open(&path).expect("reopen wal");
+    assert_eq!(
+        reopened.total_records().unwrap() as u64,
+        THREADS * PER_THREAD
+    );
+    assert_eq!(reopened.total_bytes().unwrap(), total_bytes);
+    assert_eq!(sequences(&reopened).len() as u64, THREADS);
+}
+
+#[test]
+fn checkpoint_runs_alongside_appends() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("checkpoint.wal");
+    let wal = Arc::new(WalManager::open(&path).expect("open wal"));
+
+    let checkpointer = {
+        let wal = Arc::clone(&wal);
+        thread::spawn(move || {
+            for round in 0..50u64 {
+                wal.checkpoint_page(PageId::from_u64(round % THREADS))
+                    .expect("checkpoint page");
+            }
+        })
+    };
+    append_from_threads(&wal);
+    checkpointer.join().expect("checkpointer panicked");
+
+    // whatever survived the checkpoints is still in per-thread order and replays as is
+    let logged = sequences(&wal);
+    for seqs in logged.values() {
+        assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+    let total_records = wal.total_records().unwrap();
+    drop(wal);
+
+    let reopened = WalManager::open(&path).expect("reopen wal");
+    assert_eq!(reopened.total_records().unwrap(), total_records);
+    assert_eq!(sequences(&reopened), logged);
+}
+
+#[test]
+fn only_commits_wait_for_a_sync_and_share_it() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("group.wal");
+    let wal = Arc::new(WalManager::open(&path).expect("open wal"));
+
+    append_from_threads(&wal);
+    assert_eq!(wal.syncs(), 0, "records outside a commit are not synced");
+    wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, 0)
+        .expect("commit marker");
+    assert_eq!(
+        wal.syncs(),
+        1,
+        "one sync covers every record before the commit"
+    );
+    wal.sync().expect("sync");
+    assert_eq!(wal.syncs(), 1, "nothing new to sync");
+
+    let committers: Vec<_> = (0..THREADS)
+        .map(|thread_id| {
+            let wal = Arc::clone(&wal);
+            thread::spawn(move || {
+                for _ in 0..PER_THREAD / 4 {
+                    wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, thread_id)
+                        .expect("commit marker");
+                }
+            })
+        })
+        .collect();
+    for handle in committers {
+        handle.join().expect("committer panicked");
+    }
+    let commits = THREADS * PER_THREAD / 4;
+    assert!(wal.syncs() <= 1 + commits);
+    assert_eq!(
+        wal.total_records().unwrap() as u64,
+        THREADS * PER_THREAD + 1 + commits
+    );
+}
+
+#[test]
+fn appends_stay_queued_until_a_commit_writes_them() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("queued.wal");
+    let wal = WalManager::open(&path).expect("open wal");
+    let manifest_len = fs::metadata(&path).expect("metadata").len();
+
+    for seq in 0..10u64 {
+        wal.append_put(
+            PageId::from_u64(1),
+            &seq.to_be_bytes(),
+            b"value",
+            b"",
+            b"",
+            WalEntryKind::Redo,
+            1,
+        )
+        .expect("append put");
+    }
+    assert_eq!(wal.bytes_written(), 0);
+    assert_eq!(fs::metadata(&path).expect("metadata").len(), manifest_len);
+
+    // the commit writes everything queued before it in one go
+    wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, 1)
+        .expect("commit marker");
+    let written = wal.bytes_written();
+    assert_eq!(
+        fs::metadata(&path).expect("metadata").len(),
+        manifest_len + written
+    );
+    assert_eq!(wal.total_records().unwrap(), 11);
+    assert_eq!(wal.durable_lsn(), 11);
+}
@@ -0,0 +1,132 @@
+use std::{fs, io::Write};
+
+use quickstep::{
+    map_table::PageId,
+    wal::{self, ManifestStatus, WalEntryKind, WalManager, WalStatsLevel, WalTxnMarker},
+};
+use tempfile::TempDir;
+
+/// Bytes in front of the first group
+const MANIFEST_LEN: u64 = 32;
+
+#[test]
+fn inspect_breaks_the_log_down_by_group_page_and_transaction() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("inspect.wal");
+    let stats = {
+        let wal = WalManager::open(&path).expect("open wal");
+        wal.append_txn_marker(WalTxnMarker::Begin, WalEntryKind::Redo, 1)
+            .expect("begin 1");
+        wal.append_put(
+            PageId::from_u64(0),
+            b"a",
+            b"1",
+            b"",
+            b"",
+            WalEntryKind::Redo,
+            1,
+        )
+        .expect("put a");
+        wal.append_merge(
+            PageId::from_u64(0),
+            b"a",
+            b"+1",
+            b"2",
+            b"",
+            b"",
+            WalEntryKind::Redo,
+            1,
+        )
+        .expect("merge a");
+        wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, 1)
+            .expect("commit 1");
+        wal.append_txn_marker(WalTxnMarker::Begin, WalEntryKind::Redo, 2)
+            .expect("begin 2");
+        wal.append_tombstone(PageId::from_u64(3), b"b", b"", b"", WalEntryKind::Redo, 2)
+            .expect("delete b");
+        wal.sync().expect("sync");
+        wal.stats(WalStatsLevel::Full).unwrap()
+    };
+
+    let inspection = wal::inspect(&path).expect("inspect");
+    assert!(matches!(inspection.manifest, ManifestStatus::Valid { .. }));
+    assert_eq!(inspection.truncation_point(), None);
+    assert_eq!(inspection.discarded_records, 0);
+    assert_eq!(inspection.total, stats.total);
+    assert_eq!(inspection.txn_markers, stats.txn_markers);
+    assert_eq!(inspection.next_lsn, 7);
+
+    assert_eq!(
+        inspection.pages.keys().copied().collect::<Vec<_>>(),
+        vec![0, 3]
+    );
+    let page = inspection.pages[&0];
+    assert_eq!((page.puts, page.merges, page.tombstones), (1, 1, 0));
+    assert_eq!(page.usage, stats.pages[&0]);
+    assert_eq!(inspection.pages[&3].tombstones, 1);
+
+    let committed = &inspection.txns[&1];
+    assert_eq!(
+        committed.markers,
+        vec![WalTxnMarker::Begin, WalTxnMarker::Commit]
+    );
+    assert_eq!(committed.outcome(), Some(WalTxnMarker::Commit));
+    assert_eq!(committed.usage, stats.txns[&1]);
+    assert_eq!(inspection.txns[&2].outcome(), None);
+
+    // the groups tile the file after the manifest
+    let mut offset = MANIFEST_LEN;
+    for group in &inspection.groups {
+        assert_eq!(group.offset, offset);
+        assert!(group.first_lsn <= group.last_lsn);
+        offset += group.bytes;
+    }
+    assert_eq!(offset, inspection.valid_len);
+    assert_eq!(
+        inspection
+            .groups
+            .iter()
+            .map(|group| group.records)
+            .sum::<usize>(),
+        6
+    );
+    assert_eq!(inspection.groups.last().unwrap().last_lsn, 6);
+}
+
+#[test]
+fn inspect_reports_a_torn_tail_without_cutting_it() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("torn.wal");
+    {
+        let wal = WalManager::open(&path).expect("open wal");
+        wal.append_put(
+            PageId::from_u64(1),
+            b"k",
+            b"v",
+            b"",
+            b"",
+            WalEntryKind::Redo,
+            4,
+        )
+        .expect("put");
+        wal.sync().expect("sync");
+    }
+    let clean_len = fs::metadata(&path).expect("metadata").len();
+
+    // half a group header, as a crash mid-append leaves behind
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .expect("open for append");
+    file.write_all(&[0xAA, 1, 2, 3, 4])
+        .expect("append torn bytes");
+    drop(file);
+
+    let inspection = wal::inspect(&path).expect("inspect");
+    assert_eq!(inspection.truncation_point(), Some(clean_len));
+    assert_eq!(inspection.file_len, clean_len + 5);
+    assert_eq!(inspection.groups.len(), 1);
+    assert_eq!(inspection.groups[0].page_id, Some(1));
+    assert_eq!(inspection.pages[&1].puts, 1);
+    assert_eq!(fs::metadata(&path).expect("metadata").len(), clean_len + 5);
+}
@@ -85,4 +85,3 @@ fn wal_replay_discards_uncommitted_transactions() {
     );
     tx.commit();
 }
-
@@ -0,0 +1,196 @@
+use std::{fs, path::Path};
+
+use quickstep::{
+    map_table::PageId,
+    wal::{WalEntryKind, WalManager, WalOp, WalRecord, WalStats, WalStatsLevel, WalTxnMarker},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+/// Bytes in front of the first group
+const MANIFEST_LEN: u64 = 32;
+
+fn write_log(wal: &WalManager) {
+    for txn_id in 1..=3u64 {
+        wal.append_txn_marker(WalTxnMarker::Begin, WalEntryKind::Redo, txn_id)
+            .expect("begin");
+        for page in 0..txn_id {
+            let key = format!("key-{txn_id}-{page}");
+            let record = |kind, op| {
+                WalRecord::leaf(
+                    PageId::from_u64(page),
+                    key.as_bytes(),
+                    b"",
+                    b"",
+                    kind,
+                    txn_id,
+                    op,
+                )
+            };
+            wal.append_batch(vec![
+                record(
+                    WalEntryKind::Redo,
+                    WalOp::Put {
+                        value: b"value".to_vec(),
+                    },
+                ),
+                record(WalEntryKind::Undo, WalOp::Tombstone),
+            ])
+            .expect("batch");
+        }
+        wal.append_tombstone(
+            PageId::from_u64(0),
+            b"solo",
+            b"",
+            b"",
+            WalEntryKind::Redo,
+            txn_id,
+        )
+        .expect("tombstone");
+        if txn_id != 3 {
+            wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, txn_id)
+                .expect("commit");
+        }
+    }
+}
+
+fn assert_consistent(stats: &WalStats, path: &Path) {
+    let file_len = fs::metadata(path).expect("wal metadata").len();
+    assert_eq!(
+        (stats.total.bytes + stats.lsn_marker) as u64,
+        file_len - MANIFEST_LEN
+    );
+    let pages =
+        stats
+            .pages
+            .values()
+            .chain([&stats.structure])
+            .fold(stats.txn_markers, |mut acc, usage| {
+                acc.records += usage.records;
+                acc.bytes += usage.bytes;
+                acc
+            });
+    assert_eq!(pages, stats.total);
+    let (records, bytes) = stats.txns.values().fold((0, 0), |(records, bytes), usage| {
+        (records + usage.records, bytes + usage.bytes)
+    });
+    assert_eq!((records, bytes), (stats.total.records, stats.total.bytes));
+}
+
+#[test]
+fn levels_break_the_same_totals_down_further() {
+    let wal = WalManager::in_memory(Default::default()).expect("wal");
+    write_log(&wal);
+
+    let totals = wal.stats(WalStatsLevel::Totals).unwrap();
+    assert_eq!(totals.total.records, 20);
+    assert_eq!(totals.txn_markers.records, 5);
+    assert!(totals.pages.is_empty() && totals.txns.is_empty());
+
+    let pages = wal.stats(WalStatsLevel::Pages).unwrap();
+    assert_eq!(pages.total, totals.total);
+    assert_eq!(
+        pages.pages.keys().copied().collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+    assert_eq!(pages.pages[&0].records, 9);
+    assert!(pages.txns.is_empty());
+
+    let full = wal.stats(WalStatsLevel::Full).unwrap();
+    assert_eq!(full.pages, pages.pages);
+    assert_eq!(full.txns[&1].records, 5);
+    assert_eq!(full.txns[&3].records, 8);
+    assert_eq!(wal.total_bytes().unwrap(), full.total.bytes);
+}
+
+#[test]
+fn stats_match_the_file_across_appends_checkpoints_and_reopen() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("stats.wal");
+    let before = {
+        let wal = WalManager::open(&path).expect("open wal");
+        write_log(&wal);
+        let stats = wal.stats(WalStatsLevel::Full).unwrap();
+        assert_consistent(&stats, &path);
+
+        wal.checkpoint_page(PageId::from_u64(1))
+            .expect("checkpoint");
+        let stats = wal.stats(WalStatsLevel::Full).unwrap();
+        assert_consistent(&stats, &path);
+        // transaction 3 never committed, its records stay
+        assert_eq!(stats.pages[&1].records, 2);
+        stats
+    };
+
+    let wal = WalManager::open(&path).expect("reopen wal");
+    let after = wal.stats(WalStatsLevel::Full).unwrap();
+    assert_eq!(after, before);
+
+    wal.clear().expect("clear");
+    let cleared = wal.stats(WalStatsLevel::Full).unwrap();
+    assert_consistent(&cleared, &path);
+    // only the marker numbering the next append is left
+    assert_eq!(
+        cleared,
+        WalStats {
+            lsn_marker: 9,
+            ..WalStats::default()
+        }
+    );
+}
+
+#[test]
+fn db_stats_agree_with_the_debug_view() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(QuickStepConfig::new(temp.path(), 32, 256, 14));
+    let mut tx = db.tx();
+    for idx in 0..6 {
+        tx.put(format!("key-{idx}").as_bytes(), b"value")
+            .expect("put");
+    }
+    tx.commit();
+
+    let stats = db.wal_stats(WalStatsLevel::Full).unwrap();
+    assert_consistent(&stats, &temp.path().join("quickstep.wal"));
+    let debug = db.debug_wal_stats(Some(PageId::from_u64(0))).unwrap();
+    assert_eq!(debug.total_records, stats.total.records);
+    assert_eq!(debug.total_bytes, stats.total.bytes);
+    assert_eq!(debug.leaf_records, Some(stats.pages[&0].records));
+    assert_eq!(debug.leaf_bytes, Some(stats.pages[&0].bytes));
+    // the redo records alone, a transaction whose leaf is not written logs no undo
+    assert_eq!(stats.pages[&0].records, 6);
+    assert_eq!(stats.txns.len(), 1);
+}
+
+#[test]
+fn summaries_locate_records_read_back_from_the_file() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("summaries.wal");
+    let wal = WalManager::open(&path).expect("open wal");
+    write_log(&wal);
+    wal.checkpoint_page(PageId::from_u64(1))
+        .expect("checkpoint");
+
+    let records = wal.records();
+    let summaries = wal.page_summaries().unwrap();
+    assert!(!summaries.is_empty());
+    for summary in &summaries {
+        let first = records
+            .iter()
+            .position(|record| record.page_id == summary.page_id.as_u64())
+            .expect("page has records");
+        assert_eq!(summary.first_record, first);
+        let count = records
+            .iter()
+            .filter(|record| record.page_id == summary.page_id.as_u64())
+            .count();
+        assert_eq!(summary.records, count);
+    }
+
+    // the log read back after reopening is the one the running manager reads
+    drop(wal);
+    let reopened = WalManager::open(&path).expect("reopen wal");
+    let lsns = |records: &[WalRecord]| records.iter().map(|record| record.lsn).collect::<Vec<_>>();
+    assert_eq!(lsns(&reopened.records()), lsns(&records));
+    assert_eq!(reopened.open_stats().records_read, records.len());
+}
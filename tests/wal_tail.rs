@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use quickstep::{
+    map_table::PageId,
+    wal::{WalEntryKind, WalManager, WalOp, WalRecord, WalTailGap, WalTxnMarker},
+    QuickStep, QuickStepConfig,
+};
+use tempfile::TempDir;
+
+fn put(page: u64, key: &[u8], txn_id: u64) -> WalRecord {
+    WalRecord::leaf(
+        PageId::from_u64(page),
+        key,
+        b"",
+        b"\xff",
+        WalEntryKind::Redo,
+        txn_id,
+        WalOp::Put {
+            value: key.to_vec(),
+        },
+    )
+}
+
+fn keys(records: impl Iterator<Item = WalRecord>) -> Vec<Vec<u8>> {
+    records.map(|record| record.key).collect()
+}
+
+#[test]
+fn tail_streams_durable_records_in_order_past_checkpoints() {
+    let temp = TempDir::new().expect("tempdir");
+    let wal = WalManager::open(&temp.path().join("tail.wal"))
+        .expect("open wal")
+        .with_tail_retention(16);
+    let durable = wal.subscribe_durable();
+
+    wal.append_batch(vec![put(4, b"a", 1), put(5, b"b", 1)])
+        .expect("append");
+    // written but not synced, nothing to ship yet
+    assert_eq!(wal.tail(1).expect("tail").count(), 0);
+    wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, 1)
+        .expect("commit");
+    assert_eq!(durable.recv_timeout(Duration::from_secs(1)), Ok(3));
+    assert_eq!(wal.durable_lsn(), 3);
+    assert_eq!(
+        keys(wal.tail(1).expect("tail")),
+        [b"a".to_vec(), b"b".to_vec(), Vec::new()]
+    );
+    assert_eq!(
+        keys(wal.tail(2).expect("tail")),
+        [b"b".to_vec(), Vec::new()]
+    );
+    assert_eq!(wal.tail(4).expect("tail").count(), 0);
+
+    // a checkpoint drops the records from the log but not from the stream
+    wal.checkpoint_page(PageId::from_u64(4))
+        .expect("checkpoint");
+    wal.append_batch(vec![put(4, b"c", 2)]).expect("append");
+    wal.sync().expect("sync");
+    assert_eq!(durable.recv_timeout(Duration::from_secs(1)), Ok(4));
+    assert_eq!(keys(wal.tail(1).expect("tail")).len(), 4);
+    assert_eq!(keys(wal.tail(4).expect("tail")), [b"c".to_vec()]);
+}
+
+#[test]
+fn records_past_the_retention_are_reported_as_a_gap() {
+    let temp = TempDir::new().expect("tempdir");
+    let wal = WalManager::open(&temp.path().join("tail.wal"))
+        .expect("open wal")
+        .with_tail_retention(2);
+    for idx in 0..5u8 {
+        wal.append_batch(vec![put(4, &[idx], 1)]).expect("append");
+    }
+    wal.sync().expect("sync");
+
+    assert_eq!(wal.tail(3).err(), Some(WalTailGap { oldest_lsn: 4 }));
+    assert_eq!(keys(wal.tail(4).expect("tail")), [vec![3], vec![4]]);
+
+    let unretained = WalManager::open(&temp.path().join("none.wal")).expect("open wal");
+    unretained
+        .append_batch(vec![put(4, b"a", 1)])
+        .expect("append");
+    unretained.sync().expect("sync");
+    assert_eq!(unretained.tail(1).err(), Some(WalTailGap { oldest_lsn: 2 }));
+}
+
+fn config(path: &Path) -> QuickStepConfig {
+    QuickStepConfig::new(path, 64, 1024, 20).with_wal_tail_retention(100_000)
+}
+
+#[test]
+fn a_replica_applying_the_stream_sees_every_commit() {
+    let temp = TempDir::new().expect("tempdir");
+    let db = QuickStep::new(config(temp.path()));
+    let durable = db.wal_durable_events();
+    for idx in 0..300u32 {
+        let mut tx = db.tx();
+        tx.put(format!("key-{idx:04}").as_bytes(), &idx.to_le_bytes())
+            .expect("put");
+        if idx % 10 == 3 {
+            tx.abort();
+        } else {
+            tx.commit();
+        }
+    }
+    let shipped = durable.try_iter().last().expect("durable notified");
+
+    // apply the redo records of committed transactions, as replay does
+    let records: Vec<WalRecord> = db.tail_wal(1).expect("tail").collect();
+    assert_eq!(records.len() as u64, shipped);
+    let committed: Vec<u64> = records
+        .iter()
+        .filter(|record| matches!(record.op, WalOp::TxnMarker(WalTxnMarker::Commit)))
+        .map(|record| record.txn_id)
+        .collect();
+    let mut replica = BTreeMap::new();
+    for record in records {
+        if let (WalEntryKind::Redo, WalOp::Put { value }) = (record.kind, record.op) {
+            if committed.contains(&record.txn_id) {
+                replica.insert(record.key, value);
+            }
+        }
+    }
+    assert_eq!(replica.len(), 270);
+    for (key, value) in &replica {
+        assert_eq!(db.get(key).expect("get").as_ref(), Some(value));
+    }
+    assert_eq!(db.tail_wal(shipped + 1).expect("tail").count(), 0);
+}
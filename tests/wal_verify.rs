@@ -0,0 +1,87 @@
+use quickstep::{
+    auth::MetadataAuth,
+    map_table::PageId,
+    wal::{self, ManifestStatus, WalEntryKind, WalManager, WalTxnMarker},
+};
+use std::{fs, io::Write, path::Path};
+use tempfile::TempDir;
+
+fn write_log(path: &Path, auth: MetadataAuth) {
+    let wal = WalManager::open_with_auth(path, auth).expect("open wal");
+    wal.append_txn_marker(WalTxnMarker::Begin, WalEntryKind::Redo, 1)
+        .expect("begin 1");
+    wal.append_put(
+        PageId::from_u64(0),
+        b"a",
+        b"1",
+        b"",
+        b"",
+        WalEntryKind::Redo,
+        1,
+    )
+    .expect("put a");
+    wal.append_txn_marker(WalTxnMarker::Commit, WalEntryKind::Redo, 1)
+        .expect("commit 1");
+    wal.append_txn_marker(WalTxnMarker::Begin, WalEntryKind::Redo, 2)
+        .expect("begin 2");
+    wal.append_tombstone(PageId::from_u64(3), b"b", b"", b"", WalEntryKind::Redo, 2)
+        .expect("delete b");
+}
+
+#[test]
+fn verify_reports_a_torn_tail_that_truncate_removes() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("torn.wal");
+    write_log(&path, MetadataAuth::default());
+
+    let report = wal::verify(&path).expect("verify clean log");
+    assert!(report.is_healthy(), "{report:?}");
+    assert_eq!(report.records, 5);
+    assert_eq!(report.pages, 2);
+    assert_eq!(report.committed_txns, 1);
+    assert_eq!(report.unfinished_txns, 1);
+    let clean_len = report.file_len;
+
+    // half a group header, as a crash mid-append leaves behind
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .expect("open for append");
+    file.write_all(&[0xAA, 1, 2, 3, 4])
+        .expect("append torn bytes");
+    drop(file);
+
+    let report = wal::verify(&path).expect("verify torn log");
+    assert!(!report.is_healthy());
+    assert_eq!(report.trailing_bytes(), 5);
+    assert_eq!(report.records, 5);
+    assert_eq!(
+        fs::metadata(&path).expect("metadata").len(),
+        clean_len + 5,
+        "verify must not modify the file"
+    );
+
+    let repaired = wal::truncate_to_valid(&path).expect("truncate");
+    assert!(repaired.is_healthy(), "{repaired:?}");
+    assert_eq!(repaired.file_len, clean_len);
+    assert_eq!(repaired.records, 5);
+}
+
+#[test]
+fn unauthenticated_manifest_is_reported_and_left_alone() {
+    let temp = TempDir::new().expect("tempdir");
+    let path = temp.path().join("keyed.wal");
+    let auth = MetadataAuth::new(b"operator key");
+    write_log(&path, auth.clone());
+    let before = fs::read(&path).expect("read wal");
+
+    let report = wal::verify(&path).expect("verify under wrong key");
+    assert_eq!(report.manifest, ManifestStatus::Unauthenticated);
+    assert!(!report.is_healthy());
+    let err = wal::truncate_to_valid(&path).expect_err("repair under wrong key");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(fs::read(&path).expect("read wal"), before);
+
+    let report = wal::verify_with_auth(&path, &auth).expect("verify under key");
+    assert!(report.is_healthy(), "{report:?}");
+}